@@ -25,7 +25,7 @@ fn txn_over_fraction_of_trie(size: u64, denominator: u64) -> Snapshot<u64> {
         txn.insert(&key_hash, k).unwrap();
     }
 
-    let root = txn.commit(hasher).unwrap();
+    let root = txn.commit(hasher).unwrap().root;
 
     let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
 
@@ -0,0 +1,174 @@
+//! Replays the operation traces in `fixtures/witness_traces.json` against a `SnapshotBuilder`,
+//! and reports the size of each witness.
+//!
+//! Each trace records the largest witness it may produce, as node counts and bincode bytes.
+//! A trace whose witness grows past its limit fails the run, so a change to the node structure
+//! cannot silently regress proof sizes. Lower the limits when a change shrinks the witnesses.
+
+use std::{fs, path::Path, rc::Rc};
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+        Idx,
+    },
+    walk, Branch, DigestHasher, KeyHash, Leaf, NodeHash, PortableHash, PortableHasher, Transaction,
+    TrieError, TrieRoot, VisitControl, Visitor,
+};
+use serde::Deserialize;
+use sha2::Sha256;
+
+#[derive(Deserialize)]
+struct Fixture {
+    traces: Vec<Trace>,
+}
+
+#[derive(Deserialize)]
+struct Trace {
+    name: String,
+    keys: KeyDerivation,
+    /// Keys `0..initial_keys` are in the trie before the trace, each mapped to its own index.
+    initial_keys: u64,
+    ops: Vec<TraceOp>,
+    max_witness: WitnessSize,
+}
+
+/// How a trace's `u64` keys become `KeyHash`es.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum KeyDerivation {
+    /// The SHA-256 of the key's `PortableHash`, like most applications.
+    Hashed,
+    /// `KeyHash::from_u64`, so consecutive keys share long prefixes.
+    Raw,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TraceOp {
+    Get(u64),
+    Insert(u64, u64),
+    Remove(u64),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+struct WitnessSize {
+    branches: usize,
+    leaves: usize,
+    unvisited: usize,
+    bytes: usize,
+}
+
+impl WitnessSize {
+    fn exceeds(&self, max: &WitnessSize) -> bool {
+        self.branches > max.branches
+            || self.leaves > max.leaves
+            || self.unvisited > max.unvisited
+            || self.bytes > max.bytes
+    }
+}
+
+impl Visitor<u64> for WitnessSize {
+    fn pre_branch(&mut self, _: Idx, _: &Branch<Idx>) -> Result<VisitControl, TrieError> {
+        self.branches += 1;
+        Ok(VisitControl::Continue)
+    }
+
+    fn leaf(&mut self, _: Idx, _: &Leaf<u64>) -> Result<(), TrieError> {
+        self.leaves += 1;
+        Ok(())
+    }
+
+    fn unvisited(&mut self, _: Idx, _: &NodeHash) -> Result<(), TrieError> {
+        self.unvisited += 1;
+        Ok(())
+    }
+}
+
+fn key_hash(derivation: KeyDerivation, key: u64, hasher: &mut DigestHasher<Sha256>) -> KeyHash {
+    match derivation {
+        KeyDerivation::Hashed => {
+            key.portable_hash(hasher);
+            KeyHash::from_bytes(&hasher.finalize_reset())
+        }
+        KeyDerivation::Raw => KeyHash::from_u64(key),
+    }
+}
+
+/// Build the trie the trace starts from, replay the trace, and measure the witness.
+fn replay(trace: &Trace) -> WitnessSize {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in 0..trace.initial_keys {
+        txn.insert(&key_hash(trace.keys, key, hasher), key).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for op in &trace.ops {
+        match *op {
+            TraceOp::Get(key) => {
+                txn.get(&key_hash(trace.keys, key, hasher)).unwrap();
+            }
+            TraceOp::Insert(key, value) => {
+                txn.insert(&key_hash(trace.keys, key, hasher), value)
+                    .unwrap();
+            }
+            TraceOp::Remove(key) => {
+                txn.remove(&key_hash(trace.keys, key, hasher)).unwrap();
+            }
+        }
+    }
+
+    let snapshot: Snapshot<u64> = txn.build_initial_snapshot();
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+
+    let mut size = WitnessSize {
+        bytes: bincode::serialized_size(&snapshot).unwrap() as usize,
+        ..WitnessSize::default()
+    };
+    walk(&snapshot, snapshot.root_node_idx().unwrap(), &mut size).unwrap();
+    size
+}
+
+fn main() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/fixtures/witness_traces.json");
+    let fixture: Fixture = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+    println!(
+        "{:<20} {:>6} {:>9} {:>7} {:>10} {:>9} {:>9}",
+        "trace", "ops", "branches", "leaves", "unvisited", "bytes", "max bytes"
+    );
+
+    let mut regressions = Vec::new();
+    for trace in &fixture.traces {
+        let size = replay(trace);
+        println!(
+            "{:<20} {:>6} {:>9} {:>7} {:>10} {:>9} {:>9}",
+            trace.name,
+            trace.ops.len(),
+            size.branches,
+            size.leaves,
+            size.unvisited,
+            size.bytes,
+            trace.max_witness.bytes
+        );
+
+        if size.exceeds(&trace.max_witness) {
+            regressions.push(format!(
+                "{}: witness {size:?} exceeds {:?}",
+                trace.name, trace.max_witness
+            ));
+        }
+    }
+
+    assert!(
+        regressions.is_empty(),
+        "Witness size regressions:\n{}",
+        regressions.join("\n")
+    );
+}
@@ -1,19 +1,15 @@
 use std::rc::Rc;
 
-use kairos_trie::{
-    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
-    DigestHasher, KeyHash, PortableHash, PortableHasher, Transaction, TrieRoot,
-};
+use kairos_trie::prelude::*;
 use sha2::Sha256;
 
 fn main() {
     // Bring your own key-value database, rocksdb, sled, etc.
-    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut trie = Trie::open(Rc::new(MemoryDb::<u64>::empty()), TrieRoot::Empty);
 
     // On server
     let _snapshot = {
-        let mut txn =
-            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        let mut txn = trie.transaction();
 
         let hasher = &mut DigestHasher::<Sha256>::default();
 
@@ -26,7 +22,11 @@ fn main() {
             txn.insert(&key_hash, value).unwrap();
         }
 
-        let _root = txn.commit(hasher).unwrap();
-        txn.build_initial_snapshot()
+        // Writes the new nodes, moves `trie` to the new root,
+        // and returns the witness of the old trie for the verifier.
+        let (_receipt, snapshot) = trie.commit(&txn, hasher).unwrap();
+        snapshot
     };
+
+    let _root = trie.root();
 }
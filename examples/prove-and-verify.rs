@@ -53,7 +53,7 @@ fn prover(
 
     // Commit the new merkle root to the database.
     // The old merkle trie can still be accessed through the old merkle root.
-    let merkle_root = txn.commit(hasher).unwrap();
+    let merkle_root = txn.commit(hasher).unwrap().root;
 
     // Build a Snapshot containing the minimal portion of the old merkle tree needed to replay the transaction.
     let snapshot = txn.build_initial_snapshot();
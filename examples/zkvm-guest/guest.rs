@@ -0,0 +1,46 @@
+use kairos_trie::{
+    cycle_probe::{measure, CycleProbe, Phase},
+    snapshot,
+    stored::merkle::Snapshot,
+    verify_batch_probed, DigestHasher, NodeHash, Op, TrieRoot,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// The largest snapshot the guest decodes, in estimated bytes of memory.
+pub const MAX_SNAPSHOT_MEMORY: u64 = 64 << 20;
+
+/// What the host writes to the guest's input.
+#[derive(Serialize, Deserialize)]
+pub struct Input {
+    pub old_root: TrieRoot<NodeHash>,
+    pub new_root: TrieRoot<NodeHash>,
+    pub ops: Vec<Op<u64>>,
+    /// The snapshot, framed by `snapshot::encode` so its size is checked before it is decoded.
+    pub snapshot: Vec<u8>,
+}
+
+/// Verify the batch in `input`, returning the new root the guest commits to.
+pub fn verify(input: &[u8], probe: &mut impl CycleProbe) -> TrieRoot<NodeHash> {
+    let (input, snapshot) = measure(probe, Phase::Decode, || {
+        let input: Input = bincode::deserialize(input).expect("invalid input");
+        let snapshot = snapshot::decode(&input.snapshot, MAX_SNAPSHOT_MEMORY, |body| {
+            bincode::deserialize::<Snapshot<u64>>(body)
+        })
+        .expect("invalid snapshot");
+        (input, snapshot)
+    });
+
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    verify_batch_probed(
+        input.old_root,
+        input.new_root,
+        &snapshot,
+        &input.ops,
+        hasher,
+        probe,
+    )
+    .expect("the batch does not verify");
+
+    input.new_root
+}
@@ -0,0 +1,48 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    snapshot,
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Op, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+use crate::guest::Input;
+
+/// Commit a trie of `trie_size` entries, then run a batch of `batch_size` reads and writes against it,
+/// returning the guest's input for the batch.
+pub fn build_input(trie_size: u64, batch_size: u64) -> Vec<u8> {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..trie_size {
+        txn.insert(&KeyHash::from_u64(i * 2), i).unwrap();
+    }
+    let old_root = txn.commit(hasher).unwrap().root;
+
+    // Half the batch reads existing entries, half writes new ones.
+    let ops: Vec<_> = (0..batch_size)
+        .map(|i| match i % 2 {
+            0 => Op::Get(KeyHash::from_u64((i * 7919 % trie_size) * 2)),
+            _ => Op::Insert(KeyHash::from_u64(i * 2 + 1), i),
+        })
+        .collect();
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, old_root));
+    for op in &ops {
+        op.apply(&mut txn).unwrap();
+    }
+    let new_root = txn.commit(hasher).unwrap().root;
+
+    let snapshot = txn.build_initial_snapshot();
+    let body = bincode::serialize(&snapshot).unwrap();
+    let input = Input {
+        old_root,
+        new_root,
+        ops,
+        snapshot: snapshot::encode(&snapshot, &body),
+    };
+    bincode::serialize(&input).unwrap()
+}
@@ -0,0 +1,38 @@
+//! A zkVM guest verifying a batch, instrumented with `cycle_probe`.
+//!
+//! The example is laid out as a guest would be split across crates:
+//!
+//! - `guest.rs` is the guest program. It only depends on `kairos-trie`, `bincode` and a hasher,
+//!   copy it into a risc0 or SP1 guest crate and call `guest::verify` from the guest's `main`,
+//!   passing a probe over the zkVM's cycle counter, see `cycle_probe`.
+//! - `host.rs` runs the batch against a database and builds the witness the guest reads.
+//!
+//! Run it with `cargo run --release --example zkvm-guest --features serde -- <batch size>`.
+//! Outside a zkVM it stands in nanoseconds for cycles,
+//! so the split between phases is indicative, not the guest's cycle count.
+
+mod guest;
+mod host;
+
+use std::time::Instant;
+
+use kairos_trie::cycle_probe::PhaseCycles;
+
+fn main() {
+    let batch_size = std::env::args()
+        .nth(1)
+        .map(|arg| arg.parse().expect("the batch size must be a number"))
+        .unwrap_or(100);
+
+    let input = host::build_input(10_000, batch_size);
+    println!("witness: {} bytes", input.len());
+
+    let start = Instant::now();
+    let mut probe = PhaseCycles::new(|| start.elapsed().as_nanos() as u64);
+    let new_root = guest::verify(&input, &mut probe);
+    println!("verified, new root {new_root:?}");
+
+    for (phase, nanos) in probe.totals() {
+        println!("{:<20} {nanos:>12} ns", phase.label());
+    }
+}
@@ -0,0 +1,132 @@
+//! `#[derive(PortableHash)]` for `kairos-trie`, re-exported by `kairos_trie` with the `derive` feature.
+//!
+//! The derived hash is part of every root hash computed over the type,
+//! so it must not change between builds, and the server and guest must agree on it.
+//! It follows these rules, which are stable:
+//!
+//! - A struct hashes each field with `PortableHash`, in declaration order.
+//!   Field names are not hashed, so renaming a field keeps the hash, reordering fields changes it.
+//! - An enum hashes the index of the variant, counting from 0 in declaration order,
+//!   as a little endian `u32`, then the variant's fields like a struct.
+//!   Explicit discriminants are ignored, so reordering or inserting variants changes the hash,
+//!   appending a variant does not change the hash of the others.
+//! - Unit structs and fieldless variants hash nothing besides the tag.
+//! - Fields of std types use the implementations in `kairos_trie`, which follow the same rules,
+//!   so an `Option<T>` field hashes like a derived `enum Option { None, Some(T) }`.
+//! - Collection fields are length prefixed. A `Vec<T>`, slice, `BTreeMap` or `BTreeSet` field hashes
+//!   its number of items as a little endian `u64`, then each item with `PortableHash::portable_hash_delimited`.
+//! - Byte strings and sequences of primitives are not. `Vec<u8>`, `[u8]`, `String` and `str` fields hash their bytes only,
+//!   and `Vec`s and slices of the other primitive integers, `bool` and `char` hash their items only,
+//!   so a struct with fields `(vec![1], vec![2, 3])` hashes the same as one with `(vec![1, 2], vec![3])`.
+//!   Give such types a fixed size, or hash their length in a field before them.
+//! - Inside a collection, `Option` or `Result`, a derived type is hashed with `portable_hash_delimited`,
+//!   which hashes each field with `portable_hash_delimited`, so byte string fields hash their length first there.
+//!
+//! Each type parameter of the deriving type must implement `PortableHash`. Unions are not supported.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Ident, Index};
+
+#[proc_macro_derive(PortableHash)]
+pub fn derive_portable_hash(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::kairos_trie::PortableHash));
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let name = &input.ident;
+
+    match &input.data {
+        Data::Enum(data) if data.variants.len() > u32::MAX as usize => {
+            return syn::Error::new_spanned(name, "PortableHash tags variants with a `u32`")
+                .to_compile_error()
+                .into();
+        }
+        Data::Union(_) => {
+            return syn::Error::new_spanned(name, "PortableHash cannot be derived for unions")
+                .to_compile_error()
+                .into();
+        }
+        _ => {}
+    }
+
+    let body = hash_body(&input.data, &format_ident!("portable_hash"));
+    let delimited_body = hash_body(&input.data, &format_ident!("portable_hash_delimited"));
+
+    quote! {
+        impl #impl_generics ::kairos_trie::PortableHash for #name #ty_generics #where_clause {
+            #[inline]
+            fn portable_hash<H: ::kairos_trie::PortableUpdate>(&self, hasher: &mut H) {
+                #body
+            }
+
+            #[inline]
+            fn portable_hash_delimited<H: ::kairos_trie::PortableUpdate>(&self, hasher: &mut H) {
+                #delimited_body
+            }
+        }
+    }
+    .into()
+}
+
+/// The statements hashing `self`, hashing each field with the `PortableHash` method `hash_field`.
+fn hash_body(data: &Data, hash_field: &Ident) -> TokenStream2 {
+    match data {
+        Data::Struct(data) => {
+            let (pattern, hash_fields) = destructure(&data.fields, hash_field);
+            quote! {
+                let Self #pattern = self;
+                #hash_fields
+            }
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(tag, variant)| {
+                let tag = tag as u32;
+                let variant_name = &variant.ident;
+                let (pattern, hash_fields) = destructure(&variant.fields, hash_field);
+                quote! {
+                    Self::#variant_name #pattern => {
+                        ::kairos_trie::PortableUpdate::portable_update(
+                            hasher,
+                            #tag.to_le_bytes(),
+                        );
+                        #hash_fields
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => unreachable!("unions are rejected before hashing"),
+    }
+}
+
+/// A pattern binding each field to `field_<n>`, and the statements hashing the bindings in order.
+fn destructure(fields: &Fields, hash_field: &Ident) -> (TokenStream2, TokenStream2) {
+    let bindings: Vec<_> = (0..fields.len())
+        .map(|i| format_ident!("field_{}", i))
+        .collect();
+    let hash_fields = quote! {
+        #(::kairos_trie::PortableHash::#hash_field(#bindings, hasher);)*
+    };
+
+    let pattern = match fields {
+        Fields::Named(fields) => {
+            let names = fields.named.iter().map(|field| &field.ident);
+            quote!({ #(#names: #bindings),* })
+        }
+        Fields::Unnamed(_) => {
+            let indexes = (0..fields.len()).map(Index::from);
+            quote!({ #(#indexes: #bindings),* })
+        }
+        Fields::Unit => quote!(),
+    };
+
+    (pattern, hash_fields)
+}
@@ -0,0 +1,100 @@
+//! An allocator that counts heap allocations, for tests pinning how often the verifier allocates.
+//!
+//! A zkVM guest pays cycles for every allocation, so the number of allocations verification makes
+//! is part of its cost as much as the number of hashes.
+//! Install a `CountingAlloc` as the `#[global_allocator]` of a test binary,
+//! and measure a computation with `CountingAlloc::count`.
+//!
+//! The counters are shared by every thread, so measure in a binary that runs one test at a time,
+//! or with no other threads allocating.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The allocations made during a computation measured by `CountingAlloc::count`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct AllocCounts {
+    /// Calls to `alloc`, `alloc_zeroed` and `realloc`.
+    pub allocations: usize,
+    /// Bytes requested by those calls, the new size for `realloc`.
+    pub bytes: usize,
+}
+
+/// A `GlobalAlloc` counting the allocations made through `inner`.
+#[derive(Debug)]
+pub struct CountingAlloc<A> {
+    inner: A,
+    allocations: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl<A> CountingAlloc<A> {
+    #[inline]
+    pub const fn new(inner: A) -> Self {
+        CountingAlloc {
+            inner,
+            allocations: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// The allocations made since this allocator was created.
+    #[inline]
+    pub fn counts(&self) -> AllocCounts {
+        AllocCounts {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Run `f`, returning its result and the allocations made while it ran.
+    ///
+    /// Allocations of other threads running at the same time are counted too.
+    #[inline]
+    pub fn count<R>(&self, f: impl FnOnce() -> R) -> (R, AllocCounts) {
+        let before = self.counts();
+        let result = f();
+        let after = self.counts();
+        (
+            result,
+            AllocCounts {
+                allocations: after.allocations.wrapping_sub(before.allocations),
+                bytes: after.bytes.wrapping_sub(before.bytes),
+            },
+        )
+    }
+
+    #[inline(always)]
+    fn record(&self, size: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+    }
+}
+
+// Safety: every call is forwarded to `inner` unchanged, counting has no effect on the memory returned.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAlloc<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.record(layout.size());
+        self.inner.alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.record(layout.size());
+        self.inner.alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.record(new_size);
+        self.inner.realloc(ptr, layout, new_size)
+    }
+}
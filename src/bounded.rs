@@ -0,0 +1,241 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::{spec::DEFAULT_MAX_VALUE_BYTES, DecodeError, PortableHash, PortableUpdate};
+
+/// A value that can be decoded from its `PortableHash` encoding, the bytes a leaf hashes for it.
+///
+/// Decoding reports malformed bytes as a `DecodeError` and never panics,
+/// so values decoded from an untrusted witness cannot crash the verifier.
+/// `PortableHash` encodings are not length prefixed:
+/// fixed size types take their size from the front of the bytes,
+/// and a type with no fixed size, such as `Vec<u8>`, takes all of the bytes left.
+pub trait BoundedDecode: PortableHash + Sized {
+    /// Decode a value from the front of `bytes`, advancing `bytes` past it.
+    fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// Decode a `V` from all of `bytes`, its `PortableHash` encoding.
+///
+/// Rejects more than `max_bytes` bytes before decoding anything,
+/// pass `NodeLimits::max_value_bytes` to apply the verifier's limit.
+#[inline]
+pub fn decode_value<V: BoundedDecode>(bytes: &[u8], max_bytes: usize) -> Result<V, DecodeError> {
+    if bytes.len() > max_bytes {
+        return Err(DecodeError::TooLong {
+            bytes: bytes.len(),
+            max: max_bytes,
+        });
+    }
+
+    let mut rest = bytes;
+    let value = V::decode_from(&mut rest)?;
+    if !rest.is_empty() {
+        return Err(DecodeError::TrailingBytes(rest.len()));
+    }
+    Ok(value)
+}
+
+#[inline]
+fn take<'b>(bytes: &mut &'b [u8], len: usize) -> Result<&'b [u8], DecodeError> {
+    if bytes.len() < len {
+        return Err(DecodeError::Truncated {
+            needed: len,
+            remaining: bytes.len(),
+        });
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+#[inline]
+fn take_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+    let mut array = [0; N];
+    array.copy_from_slice(take(bytes, N)?);
+    Ok(array)
+}
+
+impl BoundedDecode for () {
+    #[inline]
+    fn decode_from(_: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(())
+    }
+}
+
+impl BoundedDecode for bool {
+    #[inline]
+    fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        match take_array::<1>(bytes)? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(DecodeError::Invalid("a bool must be 0 or 1")),
+        }
+    }
+}
+
+impl BoundedDecode for char {
+    #[inline]
+    fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        char::from_u32(u32::decode_from(bytes)?).ok_or(DecodeError::Invalid(
+            "a char must be a Unicode scalar value",
+        ))
+    }
+}
+
+macro_rules! impl_bounded_decode {
+    ($($t:ty),+) => {
+        $(
+            impl BoundedDecode for $t {
+                #[inline]
+                fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+                    take_array(bytes).map(<$t>::from_le_bytes)
+                }
+            }
+        )+
+    };
+}
+
+impl_bounded_decode!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl<const N: usize> BoundedDecode for [u8; N] {
+    #[inline]
+    fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        take_array(bytes)
+    }
+}
+
+impl BoundedDecode for Vec<u8> {
+    #[inline]
+    fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take(bytes, bytes.len())?.to_vec())
+    }
+}
+
+impl BoundedDecode for String {
+    #[inline]
+    fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        let utf8 = take(bytes, bytes.len())?;
+        core::str::from_utf8(utf8)
+            .map(String::from)
+            .map_err(|_| DecodeError::Invalid("a String must be UTF-8"))
+    }
+}
+
+impl<T: BoundedDecode> BoundedDecode for Option<T> {
+    #[inline]
+    fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u32::decode_from(bytes)? {
+            0 => Ok(None),
+            1 => T::decode_from(bytes).map(Some),
+            _ => Err(DecodeError::Invalid("an Option's tag must be 0 or 1")),
+        }
+    }
+}
+
+macro_rules! impl_bounded_decode_tuple {
+    ($($t:ident),+) => {
+        impl<$($t: BoundedDecode),+> BoundedDecode for ($($t,)+) {
+            #[inline]
+            fn decode_from(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+                Ok(($($t::decode_from(bytes)?,)+))
+            }
+        }
+    };
+}
+
+impl_bounded_decode_tuple!(A, B);
+impl_bounded_decode_tuple!(A, B, C);
+impl_bounded_decode_tuple!(A, B, C, D);
+impl_bounded_decode_tuple!(A, B, C, D, E);
+impl_bounded_decode_tuple!(A, B, C, D, E, F);
+impl_bounded_decode_tuple!(A, B, C, D, E, F, G);
+
+/// A leaf value serialized as its `PortableHash` encoding, and deserialized with `BoundedDecode`.
+///
+/// Deserializing a `Snapshot<V>` trusts `V`'s `Deserialize`,
+/// which may allocate whatever length a malformed witness claims, or panic on bad input.
+/// `Bounded` reads each value as a byte string, rejects one longer than `MAX` bytes,
+/// and decodes the rest with `decode_value`.
+///
+/// A deserializer borrowing from a slice hands over the bytes without copying them,
+/// so an oversized value is rejected before anything is allocated for it.
+/// A deserializer reading from an `io::Read`, such as `bincode::deserialize_from`,
+/// allocates the claimed length before `Bounded` sees the bytes,
+/// so it needs its own size limit, such as bincode's `with_limit` or the header checked by `snapshot::peek_header`.
+/// It hashes the same as `V`, so a `Snapshot<Bounded<V>>` has the same root as the `Snapshot<V>` it was built from.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Bounded<V, const MAX: usize = DEFAULT_MAX_VALUE_BYTES>(pub V);
+
+impl<V, const MAX: usize> Bounded<V, MAX> {
+    #[inline]
+    pub fn into_inner(self) -> V {
+        self.0
+    }
+}
+
+impl<V: fmt::Debug, const MAX: usize> fmt::Debug for Bounded<V, MAX> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<V: PortableHash, const MAX: usize> PortableHash for Bounded<V, MAX> {
+    #[inline]
+    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
+        self.0.portable_hash(hasher);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<V: PortableHash, const MAX: usize> serde::Serialize for Bounded<V, MAX> {
+    #[inline]
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut encoding = crate::spec::Preimage(Vec::new());
+        self.0.portable_hash(&mut encoding);
+        serializer.serialize_bytes(&encoding.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, V: BoundedDecode, const MAX: usize> serde::Deserialize<'de> for Bounded<V, MAX> {
+    #[inline]
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use core::marker::PhantomData;
+        use serde::de::{Error, SeqAccess, Visitor};
+
+        struct BytesVisitor<V, const MAX: usize>(PhantomData<fn() -> V>);
+
+        impl<'de, V: BoundedDecode, const MAX: usize> Visitor<'de> for BytesVisitor<V, MAX> {
+            type Value = Bounded<V, MAX>;
+
+            #[inline]
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a value encoded in at most {MAX} bytes")
+            }
+
+            #[inline]
+            fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+                decode_value(bytes, MAX).map(Bounded).map_err(E::custom)
+            }
+
+            #[inline]
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(MAX));
+                while let Some(byte) = seq.next_element()? {
+                    if bytes.len() == MAX {
+                        return Err(A::Error::custom(DecodeError::TooLong {
+                            bytes: MAX + 1,
+                            max: MAX,
+                        }));
+                    }
+                    bytes.push(byte);
+                }
+                self.visit_bytes(&bytes)
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor(PhantomData))
+    }
+}
@@ -0,0 +1,768 @@
+use alloc::{
+    collections::BinaryHeap,
+    format,
+    vec::{self, Vec},
+};
+use core::{
+    cmp::Ordering, convert::Infallible, fmt::Display, marker::PhantomData, mem, num::NonZeroUsize,
+};
+#[cfg(feature = "std")]
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering as AtomicOrdering},
+};
+
+#[cfg(feature = "std")]
+use crate::{decode_value, BoundedDecode, PortableUpdate};
+use crate::{
+    errors::error_context,
+    stored::{DatabaseGet, DatabaseSet},
+    transaction::nodes::{hash_branch, Branch, BranchMask, Leaf, Node, TrieRoot},
+    KeyHash, NodeHash, PortableHash, PortableHasher, TrieError, TrieKey,
+};
+
+/// Where a `TrieBuilder` keeps the sorted runs that do not fit in memory.
+///
+/// A deployment importing more entries than fit in memory implements this over temporary files.
+pub trait RunStorage<V, K = KeyHash> {
+    type Error: Display;
+    type Run: Iterator<Item = Result<(K, V), Self::Error>>;
+
+    /// Store a run, sorted in `TrieKey::cmp_trie_order` without duplicate keys,
+    /// returning an iterator that reads it back in the same order.
+    fn spill(&mut self, run: Vec<(K, V)>) -> Result<Self::Run, Self::Error>;
+}
+
+/// Keeps every run in memory, for tests and imports that fit in memory.
+///
+/// Memory grows with the number of entries pushed, use `FileRuns` to bound it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MemoryRuns;
+
+/// A run kept in memory by `MemoryRuns`.
+#[derive(Debug)]
+pub struct MemoryRun<V, K = KeyHash>(vec::IntoIter<(K, V)>);
+
+impl<V, K> Iterator for MemoryRun<V, K> {
+    type Item = Result<(K, V), Infallible>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(Ok)
+    }
+}
+
+impl<V, K> RunStorage<V, K> for MemoryRuns {
+    type Error = Infallible;
+    type Run = MemoryRun<V, K>;
+
+    #[inline]
+    fn spill(&mut self, run: Vec<(K, V)>) -> Result<Self::Run, Self::Error> {
+        Ok(MemoryRun(run.into_iter()))
+    }
+}
+
+/// Spills each run to a temporary file, so a `TrieBuilder` holds one run of entries in memory however many it imports.
+///
+/// Each entry is written as `KeyHash::to_bytes`, the length of the value's `PortableHash` encoding as a little endian `u32`,
+/// then the encoding, which is read back with `BoundedDecode`.
+/// A value whose encoding is longer than `max_value_bytes` is an error, when spilled or read back.
+/// Each file is deleted when its run is dropped.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct FileRuns {
+    dir: PathBuf,
+    max_value_bytes: usize,
+}
+
+/// Numbers the files of every `FileRuns` in the process, so builders can share a directory.
+#[cfg(feature = "std")]
+static NEXT_RUN_FILE: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "std")]
+impl FileRuns {
+    /// Spill runs to new files in `dir`, which must exist.
+    #[inline]
+    pub fn new(dir: impl Into<PathBuf>, max_value_bytes: usize) -> Self {
+        FileRuns {
+            dir: dir.into(),
+            max_value_bytes,
+        }
+    }
+
+    #[inline]
+    fn write_run<V: PortableHash>(
+        &self,
+        file: File,
+        run: &[(KeyHash, V)],
+    ) -> Result<(), TrieError> {
+        let mut writer = BufWriter::new(file);
+        let mut encoded = Encoded(Vec::new());
+        for (key, value) in run {
+            encoded.0.clear();
+            value.portable_hash(&mut encoded);
+            let len = u32::try_from(encoded.0.len())
+                .ok()
+                .filter(|&len| len as usize <= self.max_value_bytes)
+                .ok_or_else(|| {
+                    format!(
+                        "Value of {} bytes exceeds the run limit of {} bytes",
+                        encoded.0.len(),
+                        self.max_value_bytes
+                    )
+                })?;
+
+            writer.write_all(&key.to_bytes()).map_err(io_error)?;
+            writer.write_all(&len.to_le_bytes()).map_err(io_error)?;
+            writer.write_all(&encoded.0).map_err(io_error)?;
+        }
+        writer.flush().map_err(io_error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<V: BoundedDecode> RunStorage<V> for FileRuns {
+    type Error = TrieError;
+    type Run = FileRun<V>;
+
+    #[inline]
+    fn spill(&mut self, run: Vec<(KeyHash, V)>) -> Result<Self::Run, TrieError> {
+        let path = self.dir.join(format!(
+            "kairos-trie-run-{}-{}",
+            std::process::id(),
+            NEXT_RUN_FILE.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| {
+                error_context(
+                    io_error(e),
+                    format_args!("Error creating {}", path.display()),
+                )
+            })?;
+        let reader = self
+            .write_run(file, &run)
+            .and_then(|()| File::open(&path).map_err(io_error));
+        let file = match reader {
+            Ok(file) => file,
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                return Err(error_context(
+                    e,
+                    format_args!("Error writing {}", path.display()),
+                ));
+            }
+        };
+        Ok(FileRun {
+            reader: BufReader::new(file),
+            path,
+            remaining: run.len(),
+            max_value_bytes: self.max_value_bytes,
+            buf: Vec::new(),
+            _values: PhantomData,
+        })
+    }
+}
+
+/// A run spilled to a file by `FileRuns`, deleted when dropped.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct FileRun<V> {
+    reader: BufReader<File>,
+    path: PathBuf,
+    remaining: usize,
+    max_value_bytes: usize,
+    buf: Vec<u8>,
+    _values: PhantomData<fn() -> V>,
+}
+
+#[cfg(feature = "std")]
+impl<V: BoundedDecode> FileRun<V> {
+    #[inline]
+    fn read_entry(&mut self) -> Result<(KeyHash, V), TrieError> {
+        let mut key = [0; 32];
+        self.reader.read_exact(&mut key).map_err(io_error)?;
+        let mut len = [0; 4];
+        self.reader.read_exact(&mut len).map_err(io_error)?;
+
+        let len = u32::from_le_bytes(len) as usize;
+        if len > self.max_value_bytes {
+            return Err(format!(
+                "Value of {len} bytes exceeds the run limit of {} bytes",
+                self.max_value_bytes
+            )
+            .into());
+        }
+        self.buf.resize(len, 0);
+        self.reader.read_exact(&mut self.buf).map_err(io_error)?;
+
+        let value = decode_value(&self.buf, self.max_value_bytes)?;
+        Ok((KeyHash::from_bytes(&key), value))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<V: BoundedDecode> Iterator for FileRun<V> {
+    type Item = Result<(KeyHash, V), TrieError>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        let entry = self.read_entry();
+        if entry.is_err() {
+            self.remaining = 0;
+        }
+        Some(
+            entry.map_err(|e| {
+                error_context(e, format_args!("Error reading {}", self.path.display()))
+            }),
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl<V> Drop for FileRun<V> {
+    #[inline]
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Collects a value's `PortableHash` encoding.
+#[cfg(feature = "std")]
+struct Encoded(Vec<u8>);
+
+#[cfg(feature = "std")]
+impl PortableUpdate for Encoded {
+    #[inline]
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.extend_from_slice(data.as_ref());
+    }
+}
+
+#[cfg(feature = "std")]
+#[inline]
+fn io_error(e: io::Error) -> TrieError {
+    e.to_string().into()
+}
+
+/// Builds a trie from unsorted entries in one pass, for imports too large to go through `Transaction::insert`.
+///
+/// Entries are buffered up to `run_len` at a time.
+/// Each full buffer is sorted and handed to the `RunStorage` as a run,
+/// and `build` merges the runs, building the trie bottom-up and writing each node to a `DatabaseSet` as it completes.
+/// Besides the buffer, `build` holds one entry per run and one node per level of the trie,
+/// so with `FileRuns` memory is bounded by `run_len`, not the number of entries.
+///
+/// As with `Transaction::insert`, the last value pushed for a key wins.
+pub struct TrieBuilder<R: RunStorage<V, K>, V, K = KeyHash> {
+    storage: R,
+    run_len: NonZeroUsize,
+    buf: Vec<(K, V)>,
+    runs: Vec<R::Run>,
+}
+
+impl<R: RunStorage<V, K>, V, K: TrieKey> TrieBuilder<R, V, K> {
+    /// A builder that spills a run to `storage` every `run_len` entries.
+    #[inline]
+    pub fn new(storage: R, run_len: NonZeroUsize) -> Self {
+        Self {
+            storage,
+            run_len,
+            buf: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// The number of runs spilled so far.
+    #[inline]
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    #[inline]
+    pub fn push(&mut self, key: K, value: V) -> Result<(), TrieError> {
+        if self.buf.len() >= self.run_len.get() {
+            let run = sort_run(mem::take(&mut self.buf));
+            let run = self
+                .storage
+                .spill(run)
+                .map_err(|e| format!("Error spilling run {}: {e}", self.runs.len()))?;
+            self.runs.push(run);
+        }
+
+        if self.buf.capacity() == 0 {
+            self.buf.reserve_exact(self.run_len.get());
+        }
+        self.buf.push((key, value));
+        Ok(())
+    }
+
+    #[inline]
+    pub fn extend(&mut self, entries: impl IntoIterator<Item = (K, V)>) -> Result<(), TrieError> {
+        entries
+            .into_iter()
+            .try_for_each(|(key, value)| self.push(key, value))
+    }
+}
+
+impl<R: RunStorage<V, K>, V: PortableHash, K: TrieKey> TrieBuilder<R, V, K> {
+    /// Build the trie, writing every node to `db`, and return its root hash.
+    ///
+    /// The root equals that of a `Transaction` holding the same entries.
+    /// A run the `RunStorage` reads back out of trie order is an error, not a wrong root.
+    /// Nothing is rolled back on error, the nodes already written are harmless but the trie is incomplete.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn build<Db: DatabaseSet<V, K>>(
+        self,
+        hasher: &mut impl PortableHasher<32>,
+        db: &Db,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        let mut sources = Merge::<R, V, K>::new(self.runs, sort_run(self.buf))?;
+        let mut spine = Spine::<K>::default();
+
+        while let Some((key, value)) = sources.next()? {
+            // The merge only yields keys in trie order if every run does.
+            if let Some(last) = &spine.last_key {
+                if last.cmp_trie_order(&key) != Ordering::Less {
+                    return Err(format!(
+                        "TrieBuilder runs must be read back in trie order: {key:?} after {last:?}"
+                    )
+                    .into());
+                }
+            }
+            spine.push_leaf(
+                hasher,
+                db,
+                Leaf {
+                    key_hash: key,
+                    value,
+                },
+            )?;
+        }
+
+        spine.finish(hasher, db)
+    }
+}
+
+/// Builds a trie from entries arriving in trie order, keeping the root of the entries pushed so far.
+///
+/// Nothing is buffered: each node is written to the database as soon as its subtree is complete,
+/// and only the right spine of the trie, one node per level, is held in memory, whatever the number of entries.
+/// `root` hashes that spine without writing it, so an ingest pipeline can publish a rolling root as it goes.
+///
+/// Keys must be pushed in `TrieKey::cmp_trie_order`, a key equal to the last one replaces its value.
+/// Use a `TrieBuilder` for entries in any other order.
+pub struct TrieAccumulator<Db, V, K = KeyHash> {
+    db: Db,
+    spine: Spine<K>,
+    len: u64,
+    _values: PhantomData<fn(V)>,
+}
+
+impl<Db, V, K: TrieKey> TrieAccumulator<Db, V, K> {
+    /// An accumulator of the empty trie, writing nodes to `db`.
+    #[inline]
+    pub fn new(db: Db) -> Self {
+        TrieAccumulator {
+            db,
+            spine: Spine::default(),
+            len: 0,
+            _values: PhantomData,
+        }
+    }
+
+    /// The number of keys pushed.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The last key pushed.
+    #[inline]
+    pub fn last_key(&self) -> Option<&K> {
+        self.spine.last_key.as_ref()
+    }
+
+    #[inline]
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+}
+
+impl<Db: DatabaseSet<V, K>, V: PortableHash, K: TrieKey> TrieAccumulator<Db, V, K> {
+    /// Add `value` at `key`, writing the leaf and every subtree it completes.
+    ///
+    /// Fails without changing the trie if `key` comes before the last key in trie order.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn push(
+        &mut self,
+        hasher: &mut impl PortableHasher<32>,
+        key: K,
+        value: V,
+    ) -> Result<(), TrieError> {
+        match self.spine.last_key.map(|last| last.cmp_trie_order(&key)) {
+            Some(Ordering::Greater) => {
+                return Err(format!(
+                    "TrieAccumulator keys must be pushed in trie order: {key:?} after {:?}",
+                    self.spine.last_key
+                )
+                .into());
+            }
+            Some(Ordering::Equal) => {}
+            Some(Ordering::Less) | None => self.len += 1,
+        }
+
+        self.spine.push_leaf(
+            hasher,
+            &self.db,
+            Leaf {
+                key_hash: key,
+                value,
+            },
+        )
+    }
+
+    /// Push each entry in order, stopping at the first error.
+    #[inline]
+    pub fn extend(
+        &mut self,
+        hasher: &mut impl PortableHasher<32>,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<(), TrieError> {
+        entries
+            .into_iter()
+            .try_for_each(|(key, value)| self.push(hasher, key, value))
+    }
+
+    /// The root of the trie of the entries pushed so far.
+    ///
+    /// The root equals that of a `Transaction` holding the same entries.
+    /// It costs a hash per level of the right spine, and writes nothing.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn root(&self, hasher: &mut impl PortableHasher<32>) -> TrieRoot<NodeHash> {
+        match self.spine.clone().finish::<V, _>(hasher, &Discard) {
+            Ok(root) => root,
+            Err(_) => unreachable!("Discard never fails to write"),
+        }
+    }
+
+    /// Write the right spine, completing the trie in the database, and return its root.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn finish(
+        self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        self.spine.finish(hasher, &self.db)
+    }
+}
+
+/// A database that discards every write, for hashing a spine without writing it.
+struct Discard;
+
+impl<V, K> DatabaseGet<V, K> for Discard {
+    type GetError = TrieError;
+
+    #[inline]
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V, K>>, TrieError> {
+        Err(format!("Discard does not store {hash}").into())
+    }
+}
+
+impl<V, K> DatabaseSet<V, K> for Discard {
+    type SetError = TrieError;
+
+    #[inline]
+    fn set(&self, _: NodeHash, _: Node<Branch<NodeHash>, Leaf<V, K>>) -> Result<(), TrieError> {
+        Ok(())
+    }
+}
+
+/// Sort `run` in trie order, keeping the last value pushed for each key.
+fn sort_run<V, K: TrieKey>(mut run: Vec<(K, V)>) -> Vec<(K, V)> {
+    // Reversed, a stable sort puts the last value pushed for a key first.
+    run.reverse();
+    run.sort_by(|(a, _), (b, _)| a.cmp_trie_order(b));
+    run.dedup_by(|(a, _), (b, _)| a == b);
+    run
+}
+
+/// The next key of a source, ordered so the `BinaryHeap` pops the smallest key first,
+/// and of equal keys the one from the latest source.
+struct Head<K> {
+    key: K,
+    source: usize,
+}
+
+impl<K: TrieKey> Ord for Head<K> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .cmp_trie_order(&self.key)
+            .then(self.source.cmp(&other.source))
+    }
+}
+
+impl<K: TrieKey> PartialOrd for Head<K> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: TrieKey> PartialEq for Head<K> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: TrieKey> Eq for Head<K> {}
+
+/// Merges the spilled runs and the final in-memory run, keeping the latest value for each key.
+struct Merge<R: RunStorage<V, K>, V, K> {
+    runs: Vec<R::Run>,
+    last: vec::IntoIter<(K, V)>,
+    heads: BinaryHeap<Head<K>>,
+    /// The value of each source's head, indexed by source.
+    values: Vec<Option<V>>,
+}
+
+impl<R: RunStorage<V, K>, V, K: TrieKey> Merge<R, V, K> {
+    fn new(runs: Vec<R::Run>, last: Vec<(K, V)>) -> Result<Self, TrieError> {
+        let sources = runs.len() + 1;
+        let mut merge = Merge {
+            runs,
+            last: last.into_iter(),
+            heads: BinaryHeap::with_capacity(sources),
+            values: (0..sources).map(|_| None).collect(),
+        };
+
+        for source in 0..sources {
+            merge.advance(source)?;
+        }
+        Ok(merge)
+    }
+
+    /// Read the next entry of `source` into the heap.
+    fn advance(&mut self, source: usize) -> Result<(), TrieError> {
+        let next = match self.runs.get_mut(source) {
+            Some(run) => run
+                .next()
+                .transpose()
+                .map_err(|e| format!("Error reading run {source}: {e}"))?,
+            None => self.last.next(),
+        };
+
+        if let Some((key, value)) = next {
+            self.heads.push(Head { key, source });
+            self.values[source] = Some(value);
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<(K, V)>, TrieError> {
+        let Some(Head { key, source }) = self.heads.pop() else {
+            return Ok(None);
+        };
+        let value = self.values[source].take().expect("a head has a value");
+        self.advance(source)?;
+
+        // Older sources holding the same key are superseded.
+        while let Some(head) = self.heads.peek().filter(|head| head.key == key) {
+            let stale = head.source;
+            self.heads.pop();
+            self.values[stale] = None;
+            self.advance(stale)?;
+        }
+
+        Ok(Some((key, value)))
+    }
+}
+
+/// A branch whose hash waits on the word of its parent's discriminant bit, which decides its prefix.
+#[derive(Clone)]
+struct OpenBranch {
+    mask: BranchMask,
+    left: NodeHash,
+    right: NodeHash,
+}
+
+/// The right spine of the trie built so far, from leaves pushed in trie order.
+///
+/// Each level holds the hash of a finished left subtree and the bit of the branch it waits on for a right sibling.
+/// The bits strictly increase towards the top of the stack,
+/// so the stack is never deeper than the number of bits in a key.
+#[derive(Clone)]
+struct Spine<K> {
+    levels: Vec<(NodeHash, u32)>,
+    /// The subtree holding the last leaf, not yet a child of any branch on `levels`.
+    current: Option<Node<OpenBranch, NodeHash>>,
+    last_key: Option<K>,
+}
+
+impl<K> Default for Spine<K> {
+    #[inline]
+    fn default() -> Self {
+        Spine {
+            levels: Vec::new(),
+            current: None,
+            last_key: None,
+        }
+    }
+}
+
+impl<K: TrieKey> Spine<K> {
+    fn push_leaf<V: PortableHash, Db: DatabaseSet<V, K>>(
+        &mut self,
+        hasher: &mut impl PortableHasher<32>,
+        db: &Db,
+        leaf: Leaf<V, K>,
+    ) -> Result<(), TrieError> {
+        // A leaf at the last key replaces the current subtree, which is the last leaf.
+        if let Some(last_key) = self.last_key.filter(|last| *last != leaf.key_hash) {
+            let bit = split_bit(&last_key, &leaf.key_hash);
+
+            // Every open branch deeper than the new split is complete.
+            while let Some(&(left, branch_bit)) = self.levels.last() {
+                if branch_bit < bit {
+                    break;
+                }
+                debug_assert_ne!(branch_bit, bit);
+                self.levels.pop();
+                self.close_under(hasher, db, left, branch_bit)?;
+            }
+
+            let left = self.finish_current(hasher, db, (bit / 32) as usize)?;
+            self.levels.push((left, bit));
+        }
+
+        let hash = leaf.hash_leaf(hasher);
+        self.last_key = Some(leaf.key_hash);
+        db.set(hash, Node::Leaf(leaf))
+            .map_err(|e| error_context(e, format_args!("Error writing leaf {hash} to database")))?;
+        self.current = Some(Node::Leaf(hash));
+        Ok(())
+    }
+
+    /// Make the current subtree the right child of a branch on `bit` with `left` as its left child.
+    fn close_under<V, Db: DatabaseSet<V, K>>(
+        &mut self,
+        hasher: &mut impl PortableHasher<32>,
+        db: &Db,
+        left: NodeHash,
+        bit: u32,
+    ) -> Result<(), TrieError> {
+        let word_idx = (bit / 32) as usize;
+        let right = self.finish_current(hasher, db, word_idx)?;
+
+        let key = self.last_key.expect("a closed branch has leaves");
+        let word = key.words()[word_idx];
+        self.current = Some(Node::Branch(OpenBranch {
+            mask: BranchMask::new(word_idx as u32, word, word ^ (1 << (bit % 32))),
+            left,
+            right,
+        }));
+        Ok(())
+    }
+
+    /// Hash and write the current subtree, now that its parent's word is known.
+    fn finish_current<V, Db: DatabaseSet<V, K>>(
+        &mut self,
+        hasher: &mut impl PortableHasher<32>,
+        db: &Db,
+        parent_word_idx: usize,
+    ) -> Result<NodeHash, TrieError> {
+        let branch = match self
+            .current
+            .take()
+            .expect("the spine has a current subtree")
+        {
+            Node::Leaf(hash) => return Ok(hash),
+            Node::Branch(branch) => branch,
+        };
+
+        // The current subtree holds the last leaf, so its key supplies the words the branch covers.
+        let key = self.last_key.expect("a branch has leaves");
+        let words = key.words();
+        let word_idx = branch.mask.word_idx();
+        let prior_word = if word_idx == 0 {
+            0
+        } else {
+            words[word_idx - 1]
+        };
+        let prefix = if parent_word_idx + 1 < word_idx {
+            &words[parent_word_idx..word_idx - 1]
+        } else {
+            &[]
+        };
+
+        let hash = hash_branch(
+            hasher,
+            &branch.mask,
+            prior_word,
+            prefix,
+            &branch.left,
+            &branch.right,
+        );
+        db.set(
+            hash,
+            Node::Branch(Branch {
+                left: branch.left,
+                right: branch.right,
+                mask: branch.mask,
+                prior_word,
+                prefix: prefix.into(),
+            }),
+        )
+        .map_err(|e| error_context(e, format_args!("Error writing branch {hash} to database")))?;
+        Ok(hash)
+    }
+
+    fn finish<V, Db: DatabaseSet<V, K>>(
+        mut self,
+        hasher: &mut impl PortableHasher<32>,
+        db: &Db,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        if self.current.is_none() {
+            return Ok(TrieRoot::Empty);
+        }
+
+        while let Some((left, bit)) = self.levels.pop() {
+            self.close_under(hasher, db, left, bit)?;
+        }
+        Ok(TrieRoot::Node(self.finish_current(hasher, db, 0)?))
+    }
+}
+
+/// The first bit, in trie order, at which two distinct keys differ.
+fn split_bit<K: TrieKey>(a: &K, b: &K) -> u32 {
+    let (word_idx, diff) = a
+        .words()
+        .iter()
+        .zip(b.words())
+        .map(|(a, b)| a ^ b)
+        .enumerate()
+        .find(|(_, diff)| *diff != 0)
+        .expect("merged keys are distinct");
+    word_idx as u32 * 32 + diff.trailing_zeros()
+}
@@ -0,0 +1,177 @@
+//! Glue for using the trie as a Casper global state store.
+//!
+//! The trie does not depend on `casper-types`.
+//! Instead, an integrator describes how its `Key` and `StoredValue` serialize with a `StateCodec`,
+//! usually a unit struct whose methods call `bytesrepr::ToBytes::to_bytes` and `FromBytes::from_bytes`.
+//! Values are stored in the trie as their serialized bytes, and keys are located by the hash of theirs.
+//!
+//! On the host, `execute_deploys` runs each deploy against the database and produces one `DeployWitness` per deploy.
+//! In the guest, `DeployWitness::replay` runs the same deploy against the witness's snapshot.
+
+use alloc::{format, vec::Vec};
+use core::{fmt::Display, marker::PhantomData};
+
+use crate::{
+    stored::{
+        merkle::{Snapshot, SnapshotBuilder},
+        DatabaseSet, Store,
+    },
+    verify, KeyHash, NodeHash, PortableHasher, Transaction, TrieError, TrieRoot, VerifyError,
+};
+
+/// How a global state's keys and values are serialized.
+pub trait StateCodec {
+    type Key;
+    type Value;
+    type Error: Display;
+
+    fn key_to_bytes(key: &Self::Key) -> Result<Vec<u8>, Self::Error>;
+
+    fn value_to_bytes(value: &Self::Value) -> Result<Vec<u8>, Self::Error>;
+
+    fn value_from_bytes(bytes: &[u8]) -> Result<Self::Value, Self::Error>;
+}
+
+/// A typed view of a `Transaction` over serialized values, in terms of a `StateCodec`'s keys and values.
+pub struct GlobalState<'t, S, C, H> {
+    txn: &'t mut Transaction<S, Vec<u8>>,
+    /// Hashes keys, separate from the hasher used for the trie itself.
+    hasher: H,
+    _codec: PhantomData<C>,
+}
+
+impl<'t, S: Store<Vec<u8>>, C: StateCodec, H: PortableHasher<32>> GlobalState<'t, S, C, H> {
+    #[inline]
+    pub fn new(txn: &'t mut Transaction<S, Vec<u8>>) -> Self {
+        GlobalState {
+            txn,
+            hasher: H::default(),
+            _codec: PhantomData,
+        }
+    }
+
+    /// The `KeyHash` of `key`, the hash of its serialized bytes.
+    #[inline]
+    pub fn key_hash(&mut self, key: &C::Key) -> Result<KeyHash, TrieError> {
+        let bytes = C::key_to_bytes(key).map_err(|e| format!("Error serializing key: {e}"))?;
+        self.hasher.portable_update(&bytes);
+        Ok(KeyHash::from_bytes(&self.hasher.finalize_reset()))
+    }
+
+    #[inline]
+    pub fn read(&mut self, key: &C::Key) -> Result<Option<C::Value>, TrieError> {
+        let key_hash = self.key_hash(key)?;
+        self.txn
+            .get(&key_hash)?
+            .map(|bytes| {
+                C::value_from_bytes(bytes)
+                    .map_err(|e| format!("Error deserializing value at {key_hash:?}: {e}").into())
+            })
+            .transpose()
+    }
+
+    #[inline]
+    pub fn write(&mut self, key: &C::Key, value: &C::Value) -> Result<(), TrieError> {
+        let key_hash = self.key_hash(key)?;
+        let bytes =
+            C::value_to_bytes(value).map_err(|e| format!("Error serializing value: {e}"))?;
+        self.txn.insert(&key_hash, bytes)
+    }
+
+    /// Remove the value at `key`, returning it.
+    #[inline]
+    pub fn prune(&mut self, key: &C::Key) -> Result<Option<C::Value>, TrieError> {
+        let key_hash = self.key_hash(key)?;
+        self.txn
+            .remove(&key_hash)?
+            .map(|bytes| {
+                C::value_from_bytes(&bytes)
+                    .map_err(|e| format!("Error deserializing value at {key_hash:?}: {e}").into())
+            })
+            .transpose()
+    }
+
+    #[inline]
+    pub fn transaction(&mut self) -> &mut Transaction<S, Vec<u8>> {
+        self.txn
+    }
+}
+
+/// Everything a guest needs to check one deploy's effect on global state.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DeployWitness {
+    pub pre_state_root: TrieRoot<NodeHash>,
+    pub post_state_root: TrieRoot<NodeHash>,
+    /// The global state the deploy touched, as of `pre_state_root`.
+    pub snapshot: Snapshot<Vec<u8>>,
+}
+
+impl DeployWitness {
+    /// Check the snapshot is of `pre_state_root`, run `deploy` against it,
+    /// and check the result is `post_state_root`.
+    ///
+    /// `deploy` must perform the same reads and writes it performed in `execute_deploys`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn replay<C: StateCodec, H: PortableHasher<32>>(
+        &self,
+        hasher: &mut H,
+        deploy: impl FnOnce(&mut GlobalState<&Snapshot<Vec<u8>>, C, H>) -> Result<(), TrieError>,
+    ) -> Result<(), VerifyError> {
+        verify::replay_snapshot(
+            self.pre_state_root,
+            self.post_state_root,
+            &self.snapshot,
+            hasher,
+            &mut (),
+            |txn| Ok(deploy(&mut GlobalState::new(txn))?),
+        )
+    }
+}
+
+/// Run each of `deploys` in turn against the global state at `pre_state_root`,
+/// committing after each one, and return a witness per deploy.
+///
+/// Each witness starts from the previous deploy's post state root,
+/// so a guest can check the deploys one by one, or in parallel.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn execute_deploys<Db, C, H, D>(
+    db: Db,
+    pre_state_root: TrieRoot<NodeHash>,
+    hasher: &mut H,
+    deploys: impl IntoIterator<Item = D>,
+    mut execute: impl FnMut(
+        &mut GlobalState<SnapshotBuilder<Db, Vec<u8>>, C, H>,
+        D,
+    ) -> Result<(), TrieError>,
+) -> Result<Vec<DeployWitness>, TrieError>
+where
+    Db: 'static + DatabaseSet<Vec<u8>>,
+    C: StateCodec,
+    H: PortableHasher<32>,
+{
+    let mut builder = SnapshotBuilder::new(db, pre_state_root);
+    let mut pre_state_root = pre_state_root;
+    let mut witnesses = Vec::new();
+
+    for deploy in deploys {
+        let mut txn = Transaction::from_snapshot_builder(builder);
+        execute(&mut GlobalState::new(&mut txn), deploy)?;
+
+        let post_state_root = txn.commit(hasher)?.root;
+        witnesses.push(DeployWitness {
+            pre_state_root,
+            post_state_root,
+            snapshot: txn.build_initial_snapshot(),
+        });
+
+        builder = txn.data_store.reset_to_root(post_state_root);
+        pre_state_root = post_state_root;
+    }
+
+    Ok(witnesses)
+}
@@ -0,0 +1,399 @@
+use alloc::{boxed::Box, collections::BTreeMap, format, vec, vec::Vec};
+use core::{fmt::Display, num::NonZeroUsize};
+
+use crate::{
+    stored::Store, KeyHash, NodeHash, PortableHash, PortableHasher, PortableUpdate, Transaction,
+    TrieError,
+};
+
+/// Size limits for values encoded with `ChunkConfig::encode`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChunkConfig {
+    /// Values up to this many bytes are stored directly in the leaf.
+    pub max_inline_len: usize,
+    /// Larger values are split into chunks of this many bytes, the last chunk may be shorter.
+    pub chunk_len: usize,
+    /// Values longer than this are rejected.
+    pub max_value_len: usize,
+}
+
+impl Default for ChunkConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            max_inline_len: 256,
+            chunk_len: 1024,
+            max_value_len: 1 << 24,
+        }
+    }
+}
+
+impl ChunkConfig {
+    /// Encode `bytes` as a value that can be inserted into the trie.
+    ///
+    /// Values longer than `max_inline_len` are split into chunks.
+    /// The leaf only commits to the Merkle root of the chunks,
+    /// so the returned `Chunks` must be stored alongside the trie to serve `Transaction::get_chunk`.
+    #[inline]
+    pub fn encode(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        bytes: &[u8],
+    ) -> Result<(ChunkedValue, Option<Chunks>), TrieError> {
+        let Some(chunk_len) = NonZeroUsize::new(self.chunk_len) else {
+            return Err("Invalid ChunkConfig: chunk_len must be greater than 0".into());
+        };
+
+        if bytes.len() > self.max_value_len {
+            return Err(format!(
+                "Value of {} bytes exceeds the maximum value length of {} bytes",
+                bytes.len(),
+                self.max_value_len
+            )
+            .into());
+        }
+
+        if bytes.len() <= self.max_inline_len {
+            return Ok((ChunkedValue::Inline(bytes.to_vec()), None));
+        }
+
+        let chunks = Chunks::new(hasher, bytes, chunk_len);
+        let value = ChunkedValue::Chunked {
+            len: bytes.len() as u64,
+            chunk_count: chunks.chunk_count(),
+            root: chunks.root(),
+        };
+
+        Ok((value, Some(chunks)))
+    }
+}
+
+/// A value stored either inline in the leaf or as a Merkle list of chunks.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ChunkedValue {
+    Inline(Vec<u8>),
+    Chunked {
+        /// The length of the whole value in bytes.
+        len: u64,
+        chunk_count: u32,
+        /// The Merkle root of the chunks.
+        root: NodeHash,
+    },
+}
+
+impl ChunkedValue {
+    /// The length of the whole value in bytes.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        match self {
+            ChunkedValue::Inline(bytes) => bytes.len() as u64,
+            ChunkedValue::Chunked { len, .. } => *len,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// An inline value is a single chunk.
+    #[inline]
+    pub fn chunk_count(&self) -> u32 {
+        match self {
+            ChunkedValue::Inline(_) => 1,
+            ChunkedValue::Chunked { chunk_count, .. } => *chunk_count,
+        }
+    }
+
+    /// Check that `chunk` is chunk `idx` of this value.
+    ///
+    /// Run this in the guest on the chunk and proof returned by `Transaction::get_chunk`.
+    /// Caller must ensure that the hasher is reset before calling this function.
+    #[inline]
+    pub fn verify_chunk(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        idx: u32,
+        chunk: &[u8],
+        proof: &ChunkProof,
+    ) -> Result<(), TrieError> {
+        match self {
+            ChunkedValue::Inline(bytes) => {
+                if idx == 0 && proof.siblings.is_empty() && bytes.as_slice() == chunk {
+                    Ok(())
+                } else {
+                    Err(format!("Invalid chunk proof: chunk {idx} of an inline value").into())
+                }
+            }
+            ChunkedValue::Chunked {
+                chunk_count, root, ..
+            } => {
+                if idx >= *chunk_count {
+                    return Err(format!(
+                        "Chunk index {idx} out of bounds for a value with {chunk_count} chunks"
+                    )
+                    .into());
+                }
+
+                let computed = proof.calc_root(hasher, idx, *chunk_count, chunk)?;
+                if computed.verify_eq(root) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "Invalid chunk proof: chunk {idx} hashes to root {computed}, expected {root}"
+                    )
+                    .into())
+                }
+            }
+        }
+    }
+}
+
+impl PortableHash for ChunkedValue {
+    #[inline]
+    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
+        match self {
+            ChunkedValue::Inline(bytes) => {
+                hasher.portable_update([0]);
+                hasher.portable_update((bytes.len() as u64).to_le_bytes());
+                hasher.portable_update(bytes);
+            }
+            ChunkedValue::Chunked {
+                len,
+                chunk_count,
+                root,
+            } => {
+                hasher.portable_update([1]);
+                hasher.portable_update(len.to_le_bytes());
+                hasher.portable_update(chunk_count.to_le_bytes());
+                hasher.portable_update(root);
+            }
+        }
+    }
+}
+
+/// The sibling hashes from a chunk up to the Merkle root of its value.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ChunkProof {
+    pub siblings: Box<[NodeHash]>,
+}
+
+impl ChunkProof {
+    fn calc_root(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        mut idx: u32,
+        mut width: u32,
+        chunk: &[u8],
+    ) -> Result<NodeHash, TrieError> {
+        let mut hash = hash_chunk(hasher, chunk);
+        let mut siblings = self.siblings.iter();
+
+        while width > 1 {
+            // The last node of an odd level has no sibling and is carried up as is.
+            if !(idx.is_multiple_of(2) && idx + 1 == width) {
+                let sibling = siblings
+                    .next()
+                    .ok_or("Invalid chunk proof: too few sibling hashes")?;
+
+                hash = if idx.is_multiple_of(2) {
+                    hash_chunk_pair(hasher, &hash, sibling)
+                } else {
+                    hash_chunk_pair(hasher, sibling, &hash)
+                };
+            }
+
+            idx /= 2;
+            width = width.div_ceil(2);
+        }
+
+        if siblings.next().is_some() {
+            return Err("Invalid chunk proof: too many sibling hashes".into());
+        }
+
+        Ok(hash)
+    }
+}
+
+/// The chunks of a large value and the levels of their Merkle tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Chunks {
+    chunks: Box<[Box<[u8]>]>,
+    /// `levels[0]` holds the chunk hashes, the last level holds only the root.
+    levels: Box<[Box<[NodeHash]>]>,
+}
+
+impl Chunks {
+    /// Split `bytes` into chunks of `chunk_len` bytes and build their Merkle tree.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this function.
+    #[inline]
+    pub fn new(
+        hasher: &mut impl PortableHasher<32>,
+        bytes: &[u8],
+        chunk_len: NonZeroUsize,
+    ) -> Self {
+        let chunks: Box<[Box<[u8]>]> = if bytes.is_empty() {
+            Box::new([Box::default()])
+        } else {
+            bytes.chunks(chunk_len.get()).map(Box::from).collect()
+        };
+
+        let mut levels: Vec<Box<[NodeHash]>> =
+            vec![chunks.iter().map(|c| hash_chunk(hasher, c)).collect()];
+
+        while let Some(level) = levels.last().filter(|l| l.len() > 1) {
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_chunk_pair(hasher, left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self {
+            chunks,
+            levels: levels.into_boxed_slice(),
+        }
+    }
+
+    #[inline]
+    pub fn root(&self) -> NodeHash {
+        self.levels[self.levels.len() - 1][0]
+    }
+
+    #[inline]
+    pub fn chunk_count(&self) -> u32 {
+        self.chunks.len() as u32
+    }
+
+    #[inline]
+    pub fn chunk(&self, idx: u32) -> Option<&[u8]> {
+        self.chunks.get(idx as usize).map(|c| &**c)
+    }
+
+    /// The chunk at `idx` and the proof that it belongs under `root`.
+    #[inline]
+    pub fn prove(&self, idx: u32) -> Option<(&[u8], ChunkProof)> {
+        let chunk = self.chunk(idx)?;
+
+        let mut idx = idx as usize;
+        let mut siblings = Vec::with_capacity(self.levels.len());
+        for level in self.levels[..self.levels.len() - 1].iter() {
+            if let Some(sibling) = level.get(idx ^ 1) {
+                siblings.push(*sibling);
+            }
+            idx /= 2;
+        }
+
+        Some((
+            chunk,
+            ChunkProof {
+                siblings: siblings.into_boxed_slice(),
+            },
+        ))
+    }
+}
+
+/// A source of the chunks of values stored with `ChunkedValue::Chunked`.
+pub trait ChunkGet {
+    type GetError: Display;
+
+    /// Get chunk `idx` of the value with Merkle root `root`, and its proof.
+    fn get_chunk(
+        &self,
+        root: &NodeHash,
+        idx: u32,
+    ) -> Result<(Box<[u8]>, ChunkProof), Self::GetError>;
+}
+
+impl<C: ChunkGet> ChunkGet for &C {
+    type GetError = C::GetError;
+
+    #[inline]
+    fn get_chunk(
+        &self,
+        root: &NodeHash,
+        idx: u32,
+    ) -> Result<(Box<[u8]>, ChunkProof), Self::GetError> {
+        (**self).get_chunk(root, idx)
+    }
+}
+
+impl ChunkGet for BTreeMap<NodeHash, Chunks> {
+    type GetError = TrieError;
+
+    #[inline]
+    fn get_chunk(
+        &self,
+        root: &NodeHash,
+        idx: u32,
+    ) -> Result<(Box<[u8]>, ChunkProof), Self::GetError> {
+        let chunks = self
+            .get(root)
+            .ok_or_else(|| format!("Chunks with root `{root}` not found"))?;
+
+        chunks
+            .prove(idx)
+            .map(|(chunk, proof)| (chunk.into(), proof))
+            .ok_or_else(|| {
+                format!(
+                    "Chunk index {idx} out of bounds for a value with {} chunks",
+                    chunks.chunk_count()
+                )
+                .into()
+            })
+    }
+}
+
+impl<S: Store<ChunkedValue>> Transaction<S, ChunkedValue> {
+    /// Get chunk `idx` of the value at `key_hash`, along with its proof.
+    ///
+    /// Only the leaf is recorded in the witness, not the rest of the value.
+    /// The chunk is not checked against the leaf, the guest must call `ChunkedValue::verify_chunk`.
+    #[inline]
+    pub fn get_chunk(
+        &self,
+        key_hash: &KeyHash,
+        idx: u32,
+        chunks: &impl ChunkGet,
+    ) -> Result<Option<(Box<[u8]>, ChunkProof)>, TrieError> {
+        match self.get(key_hash)? {
+            None => Ok(None),
+            Some(ChunkedValue::Inline(bytes)) => {
+                if idx == 0 {
+                    Ok(Some((bytes.as_slice().into(), ChunkProof::default())))
+                } else {
+                    Err(format!("Chunk index {idx} out of bounds for an inline value").into())
+                }
+            }
+            Some(ChunkedValue::Chunked { root, .. }) => chunks
+                .get_chunk(root, idx)
+                .map(Some)
+                .map_err(|e| format!("Error getting chunk {idx} of {root}: `{e}`").into()),
+        }
+    }
+}
+
+fn hash_chunk(hasher: &mut impl PortableHasher<32>, chunk: &[u8]) -> NodeHash {
+    hasher.portable_update([0]);
+    hasher.portable_update(chunk);
+    NodeHash::new(hasher.finalize_reset())
+}
+
+fn hash_chunk_pair(
+    hasher: &mut impl PortableHasher<32>,
+    left: &NodeHash,
+    right: &NodeHash,
+) -> NodeHash {
+    hasher.portable_update([1]);
+    hasher.portable_update(left);
+    hasher.portable_update(right);
+    NodeHash::new(hasher.finalize_reset())
+}
@@ -0,0 +1,241 @@
+//! A compact byte encoding of single-key inclusion proofs, sized for calldata.
+//!
+//! Every branch on the path to a key is fixed by the key itself and the branch's discriminant bit,
+//! its prefix words and `prior_word` are words of the key, and its `left_prefix` the key bits below the discriminant.
+//! So the encoding stores the key once, and per branch only the discriminant bit and the sibling's hash:
+//!
+//! | bytes        | field                                                              |
+//! |--------------|--------------------------------------------------------------------|
+//! | 32           | the key, as `KeyHash::to_bytes`                                    |
+//! | varint       | the length of the value's bytes                                    |
+//! | the length   | the value, as fed to the hasher by `PortableHash`                  |
+//! | varint       | the number of branches on the path, at most `MAX_COMPACT_DEPTH`    |
+//! | per branch   | a varint `BranchMask::bit_idx`, then the 32 byte sibling hash      |
+//! | the rest     | zeros, padding from `InclusionProof::encode_fixed`                 |
+//!
+//! Branches are listed from the leaf's parent up to the root, so `verify_from_bytes` hashes them as it reads them.
+//! Varints are unsigned LEB128.
+
+use alloc::{format, vec::Vec};
+
+use crate::{
+    spec::Preimage,
+    transaction::nodes::{hash_branch, hash_leaf},
+    BranchMask, EncodeError, InclusionProof, KeyHash, NodeHash, PortableHash, PortableHasher,
+    TrieError, TrieRoot, VerifyError,
+};
+
+/// The most branches a compact proof can hold.
+///
+/// A trie of hashed keys is about `log2(n)` branches deep, so this covers any realistic trie
+/// while bounding a proof to a little over 2 KiB.
+pub const MAX_COMPACT_DEPTH: usize = 64;
+
+impl<V: PortableHash> InclusionProof<V> {
+    /// Encode the proof in the compact layout described in the `compact` module.
+    #[inline]
+    pub fn encode_compact(&self) -> Result<Vec<u8>, EncodeError> {
+        let depth = self.path.len();
+        if depth > MAX_COMPACT_DEPTH {
+            return Err(EncodeError::TooDeep {
+                depth,
+                max: MAX_COMPACT_DEPTH,
+            });
+        }
+
+        let key = &self.leaf.key_hash;
+        let mut value = Preimage(Vec::new());
+        self.leaf.value.portable_hash(&mut value);
+
+        let mut bytes = Vec::with_capacity(32 + 5 + value.0.len() + 1 + depth * 34);
+        bytes.extend_from_slice(&key.to_bytes());
+        push_varint(&mut bytes, value.0.len() as u32);
+        bytes.extend_from_slice(&value.0);
+        push_varint(&mut bytes, depth as u32);
+
+        for (idx, branch) in self.path.iter().enumerate().rev() {
+            let parent_word_idx = idx
+                .checked_sub(1)
+                .map_or(0, |i| self.path[i].mask.word_idx());
+            let bit_idx = branch.mask.bit_idx();
+
+            let (mask, prior_word, prefix) = branch_from_key(key, bit_idx, parent_word_idx);
+            if branch.mask != mask || branch.prior_word != prior_word || *branch.prefix != *prefix {
+                return Err(EncodeError::NonCanonical { depth: idx });
+            }
+
+            push_varint(&mut bytes, bit_idx);
+            let sibling = if key.bit(bit_idx) {
+                &branch.left
+            } else {
+                &branch.right
+            };
+            bytes.extend_from_slice(&sibling.bytes);
+        }
+
+        Ok(bytes)
+    }
+
+    /// Encode the proof with `encode_compact`, padded with zeros to `N` bytes.
+    #[inline]
+    pub fn encode_fixed<const N: usize>(&self) -> Result<[u8; N], EncodeError> {
+        let bytes = self.encode_compact()?;
+        if bytes.len() > N {
+            return Err(EncodeError::TooLong {
+                len: bytes.len(),
+                capacity: N,
+            });
+        }
+
+        let mut fixed = [0; N];
+        fixed[..bytes.len()].copy_from_slice(&bytes);
+        Ok(fixed)
+    }
+}
+
+/// Check a proof from `InclusionProof::encode_compact` or `encode_fixed` against `root`,
+/// returning the proven key and the bytes of its value.
+///
+/// The value bytes are those its `PortableHash` feeds the hasher, decoding them is left to the caller.
+/// Verifying allocates nothing, unless the proof is malformed.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn verify_from_bytes<'a>(
+    hasher: &mut impl PortableHasher<32>,
+    bytes: &'a [u8],
+    root: TrieRoot<NodeHash>,
+) -> Result<(KeyHash, &'a [u8]), VerifyError> {
+    let mut reader = Reader(bytes);
+
+    let key = KeyHash::from_bytes(reader.array()?);
+    let value_len = reader.varint()? as usize;
+    let value = reader.take(value_len)?;
+    let depth = reader.varint()? as usize;
+    if depth > MAX_COMPACT_DEPTH {
+        return Err(malformed("the path is deeper than `MAX_COMPACT_DEPTH`"));
+    }
+
+    let mut hash = hash_leaf(hasher, &key, &value);
+    let mut remaining = depth;
+    let mut next = reader.next_branch(&mut remaining)?;
+
+    while let Some((bit_idx, sibling)) = next {
+        next = reader.next_branch(&mut remaining)?;
+
+        let parent_word_idx = match next {
+            Some((parent_bit_idx, _)) if parent_bit_idx >= bit_idx => {
+                return Err(malformed("the branches are not in path order"));
+            }
+            Some((parent_bit_idx, _)) => parent_bit_idx as usize / 32,
+            None => 0,
+        };
+
+        let (mask, prior_word, prefix) = branch_from_key(&key, bit_idx, parent_word_idx);
+        hash = if key.bit(bit_idx) {
+            hash_branch(hasher, &mask, prior_word, prefix, &sibling, &hash)
+        } else {
+            hash_branch(hasher, &mask, prior_word, prefix, &hash, &sibling)
+        };
+    }
+
+    if reader.0.iter().any(|&b| b != 0) {
+        return Err(malformed("nonzero bytes after the path"));
+    }
+
+    let actual = TrieRoot::Node(hash);
+    if !actual.verify_eq(&root) {
+        return Err(VerifyError::OldRootMismatch {
+            expected: root,
+            actual,
+        });
+    }
+    Ok((key, value))
+}
+
+/// The mask, `prior_word` and prefix of the branch on the path to `key` that discriminates on `bit_idx`,
+/// below a branch discriminating on a bit of word `parent_word_idx`, or 0 at the root.
+fn branch_from_key(
+    key: &KeyHash,
+    bit_idx: u32,
+    parent_word_idx: usize,
+) -> (BranchMask, u32, &[u32]) {
+    let (word_idx, bit) = KeyHash::word_and_bit(bit_idx);
+    let word = key.0[word_idx];
+    let mask = BranchMask::new(word_idx as u32, word, word ^ (1 << bit));
+
+    let prior_word = word_idx.checked_sub(1).map_or(0, |i| key.0[i]);
+    let prefix = if parent_word_idx + 1 < word_idx {
+        &key.0[parent_word_idx..word_idx - 1]
+    } else {
+        &[]
+    };
+    (mask, prior_word, prefix)
+}
+
+fn push_varint(bytes: &mut Vec<u8>, mut n: u32) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+fn malformed(reason: &str) -> VerifyError {
+    TrieError::from(format!("Invalid compact proof: {reason}")).into()
+}
+
+/// Reads the fields of a compact proof from the front of a byte slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], VerifyError> {
+        if len > self.0.len() {
+            return Err(malformed("the proof ends early"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<&'a [u8; N], VerifyError> {
+        Ok(self.take(N)?.try_into().expect("`take` returns N bytes"))
+    }
+
+    fn varint(&mut self) -> Result<u32, VerifyError> {
+        let mut n = 0u32;
+        for shift in (0..35).step_by(7) {
+            let [byte] = *self.array::<1>()?;
+            let bits = u32::from(byte & 0x7f);
+            if shift == 28 && bits > 0xf {
+                return Err(malformed("a varint overflows a u32"));
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(malformed("a varint overflows a u32"))
+    }
+
+    /// The next branch's discriminant bit and sibling hash, if `remaining` is not 0.
+    fn next_branch(
+        &mut self,
+        remaining: &mut usize,
+    ) -> Result<Option<(u32, NodeHash)>, VerifyError> {
+        if *remaining == 0 {
+            return Ok(None);
+        }
+        *remaining -= 1;
+        self.branch().map(Some)
+    }
+
+    /// A branch's discriminant bit and sibling hash.
+    fn branch(&mut self) -> Result<(u32, NodeHash), VerifyError> {
+        let bit_idx = self.varint()?;
+        if bit_idx >= 256 {
+            return Err(malformed("a discriminant bit is past the end of the key"));
+        }
+        Ok((bit_idx, NodeHash::new(*self.array::<32>()?)))
+    }
+}
@@ -0,0 +1,178 @@
+//! Counted tries, whose node hashes carry the number of leaves under them.
+//!
+//! With `TrieParams::COUNTED`, the last 4 bytes of every node hash are replaced by the number of leaves in its subtree,
+//! a little endian `u32`, saturating at `u32::MAX`.
+//! A leaf counts 1, and a branch the sum of its children's counts.
+//! The first 28 bytes are the hasher's digest as usual.
+//!
+//! A branch's preimage includes its children's full hashes, counts and all,
+//! so the root commits to the size of the trie and of every subtree.
+//! `root_leaf_count` reads the size of a trie from its root alone,
+//! and a `PrefixCountProof` proves the number of leaves starting with a key prefix.
+//!
+//! Counted mode changes every hash in the trie, and leaves 224 bits of digest.
+
+use alloc::boxed::Box;
+
+use crate::{
+    transaction::nodes::{KeyPosition, KeySide},
+    Branch, KeyHash, Leaf, NodeHash, PortableHash, PortableHasher, TrieError, TrieRoot,
+    VerifyError,
+};
+
+/// The number of leaves under a node, from its hash.
+///
+/// Only meaningful for hashes computed in counted mode.
+#[inline]
+pub fn leaf_count(hash: &NodeHash) -> u32 {
+    let [.., a, b, c, d] = hash.bytes;
+    u32::from_le_bytes([a, b, c, d])
+}
+
+/// `hash` with its last 4 bytes replaced by `leaves`.
+#[inline]
+pub fn with_leaf_count(hash: NodeHash, leaves: u32) -> NodeHash {
+    let mut bytes = hash.bytes;
+    bytes[28..].copy_from_slice(&leaves.to_le_bytes());
+    NodeHash::new(bytes)
+}
+
+/// The number of leaves in the trie at `root`, computed in counted mode.
+#[inline]
+pub fn root_leaf_count(root: TrieRoot<NodeHash>) -> u32 {
+    match root {
+        TrieRoot::Empty => 0,
+        TrieRoot::Node(hash) => leaf_count(&hash),
+    }
+}
+
+/// Fails unless `H` hashes in counted mode.
+#[inline]
+pub(crate) fn check_counted<H: PortableHasher<32>>() -> Result<(), TrieError> {
+    if H::COUNTED {
+        Ok(())
+    } else {
+        Err("Leaf counts need a hasher in counted mode, see `TrieParams::COUNTED`".into())
+    }
+}
+
+/// The node a `PrefixCountProof` ends at.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PrefixCountEnd<V> {
+    /// The trie is empty.
+    Empty,
+    /// The first branch discriminating on a bit after the prefix, or a branch whose keys leave the prefix.
+    Branch(Branch<NodeHash>),
+    /// The only leaf the prefix could reach.
+    Leaf(Leaf<V>),
+}
+
+/// Evidence of the number of leaves whose keys start with a prefix, produced by `Transaction::prove_prefix_count`.
+///
+/// The path follows the prefix down from the root through the branches discriminating on bits of the prefix.
+/// Below the last of them every key either starts with the prefix, or none does,
+/// so the count is the end node's leaf count or 0.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PrefixCountProof<V> {
+    /// The branches from the root down to the end's parent, with both children's hashes.
+    pub path: Box<[Branch<NodeHash>]>,
+    pub end: PrefixCountEnd<V>,
+}
+
+impl<V: PortableHash> PrefixCountProof<V> {
+    /// Check the proof against `root`, returning the number of leaves whose keys start with
+    /// the first `len_bits` bits of `prefix_bits`, numbered as in `Transaction::keys_with_prefix`.
+    ///
+    /// `H` must hash in counted mode.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify<H: PortableHasher<32>>(
+        &self,
+        hasher: &mut H,
+        root: TrieRoot<NodeHash>,
+        prefix_bits: &[u8],
+        len_bits: usize,
+    ) -> Result<u32, VerifyError> {
+        check_counted::<H>()?;
+        let (min, max) = KeyHash::prefix_bounds(prefix_bits, len_bits)?;
+
+        let (mut hash, count) = match &self.end {
+            PrefixCountEnd::Empty => {
+                if !self.path.is_empty() {
+                    return Err(TrieError::from(
+                        "Invalid prefix count proof: the path of an empty trie must be empty",
+                    )
+                    .into());
+                }
+                if !TrieRoot::Empty.verify_eq(&root) {
+                    return Err(VerifyError::OldRootMismatch {
+                        expected: root,
+                        actual: TrieRoot::Empty,
+                    });
+                }
+                return Ok(0);
+            }
+            PrefixCountEnd::Leaf(leaf) => {
+                let in_prefix = leaf.key_hash.matches_prefix(&min, len_bits as u32);
+                (leaf.hash_leaf(hasher), u32::from(in_prefix))
+            }
+            PrefixCountEnd::Branch(branch) => {
+                branch.check_invariants()?;
+                let in_prefix = if (branch.mask.bit_idx() as usize) < len_bits {
+                    if !matches!(branch.key_position(&min), KeyPosition::Adjacent(_)) {
+                        return Err(TrieError::from(
+                            "Invalid prefix count proof: the proof ends at a branch the prefix descends through",
+                        )
+                        .into());
+                    }
+                    false
+                } else {
+                    // The keys under the branch agree on every bit of the prefix, so they are all in it or all outside it.
+                    branch.key_side(&min) != KeySide::After
+                        && branch.key_side(&max) != KeySide::Before
+                };
+                let hash = branch.hash_branch(hasher, &branch.left, &branch.right);
+                (hash, if in_prefix { leaf_count(&hash) } else { 0 })
+            }
+        };
+
+        for branch in self.path.iter().rev() {
+            branch.check_invariants()?;
+            if branch.mask.bit_idx() as usize >= len_bits {
+                return Err(TrieError::from(
+                    "Invalid prefix count proof: a branch on the path discriminates on a bit after the prefix",
+                )
+                .into());
+            }
+            let child = match branch.key_position(&min) {
+                KeyPosition::Left => &branch.left,
+                KeyPosition::Right => &branch.right,
+                KeyPosition::Adjacent(_) => {
+                    return Err(TrieError::from(
+                        "Invalid prefix count proof: the prefix leaves the path before its end",
+                    )
+                    .into())
+                }
+            };
+            if *child != hash {
+                return Err(TrieError::from(
+                    "Invalid prefix count proof: a branch does not commit to the node below it",
+                )
+                .into());
+            }
+            hash = branch.hash_branch(hasher, &branch.left, &branch.right);
+        }
+
+        let actual = TrieRoot::Node(hash);
+        if !actual.verify_eq(&root) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: root,
+                actual,
+            });
+        }
+        Ok(count)
+    }
+}
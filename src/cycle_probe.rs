@@ -0,0 +1,130 @@
+//! Annotations of the phases of verification, for measuring where a zkVM guest spends its cycles.
+//!
+//! The verifier reports each phase it enters and leaves to a `CycleProbe`.
+//! `()` is the default probe and does nothing, so outside a zkVM nothing is measured or paid for.
+//! In a guest, implement `CycleProbe` over the zkVM's own counter:
+//!
+//! - risc0: `PhaseCycles::new(risc0_zkvm::guest::env::cycle_count)`.
+//! - SP1: print `cycle-tracker-start: {label}` and `cycle-tracker-end: {label}` with `Phase::label`,
+//!   and the prover's cycle tracker reports each phase.
+//!
+//! See `examples/zkvm-guest` for a guest laid out this way.
+
+/// A phase of verifying a batch.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Phase {
+    /// Decoding the witness into a `Snapshot`.
+    Decode,
+    /// Checking the snapshot against the old root, or hashing the replayed trie to its new root.
+    Hash,
+    /// Replaying the operations against the snapshot.
+    Replay,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 3] = [Phase::Decode, Phase::Hash, Phase::Replay];
+
+    /// A name for the phase, for cycle trackers that key on labels.
+    #[inline]
+    pub fn label(self) -> &'static str {
+        match self {
+            Phase::Decode => "kairos-trie:decode",
+            Phase::Hash => "kairos-trie:hash",
+            Phase::Replay => "kairos-trie:replay",
+        }
+    }
+}
+
+/// Told when verification enters and leaves each `Phase`.
+///
+/// A phase may be entered more than once, `Phase::Hash` is entered for the old root and again for the new one.
+/// Phases do not nest.
+pub trait CycleProbe {
+    fn start(&mut self, phase: Phase);
+
+    fn end(&mut self, phase: Phase);
+}
+
+/// The probe that does nothing.
+impl CycleProbe for () {
+    #[inline(always)]
+    fn start(&mut self, _phase: Phase) {}
+
+    #[inline(always)]
+    fn end(&mut self, _phase: Phase) {}
+}
+
+impl<P: CycleProbe + ?Sized> CycleProbe for &mut P {
+    #[inline(always)]
+    fn start(&mut self, phase: Phase) {
+        (**self).start(phase)
+    }
+
+    #[inline(always)]
+    fn end(&mut self, phase: Phase) {
+        (**self).end(phase)
+    }
+}
+
+/// Run `f` as `phase`.
+#[inline]
+pub fn measure<T>(probe: &mut impl CycleProbe, phase: Phase, f: impl FnOnce() -> T) -> T {
+    probe.start(phase);
+    let result = f();
+    probe.end(phase);
+    result
+}
+
+/// A probe totalling the cycles spent in each phase, read from a zkVM's cycle counter.
+#[derive(Clone, Debug)]
+pub struct PhaseCycles<C> {
+    counter: C,
+    started: Option<(Phase, u64)>,
+    totals: [u64; 3],
+}
+
+impl<C: FnMut() -> u64> PhaseCycles<C> {
+    /// `counter` returns the cycles the guest has run so far.
+    #[inline]
+    pub fn new(counter: C) -> Self {
+        PhaseCycles {
+            counter,
+            started: None,
+            totals: [0; 3],
+        }
+    }
+
+    /// The cycles spent in `phase` so far.
+    #[inline]
+    pub fn cycles(&self, phase: Phase) -> u64 {
+        self.totals[phase as usize]
+    }
+
+    /// The cycles spent in every phase so far, in the order of `Phase::ALL`.
+    #[inline]
+    pub fn totals(&self) -> [(Phase, u64); 3] {
+        Phase::ALL.map(|phase| (phase, self.cycles(phase)))
+    }
+}
+
+impl<C: FnMut() -> u64> CycleProbe for PhaseCycles<C> {
+    #[inline]
+    fn start(&mut self, phase: Phase) {
+        debug_assert!(
+            self.started.is_none(),
+            "{phase:?} started inside another phase"
+        );
+        self.started = Some((phase, (self.counter)()));
+    }
+
+    #[inline]
+    fn end(&mut self, phase: Phase) {
+        let now = (self.counter)();
+        match self.started.take() {
+            Some((started, at)) if started == phase => {
+                self.totals[phase as usize] += now.saturating_sub(at);
+            }
+            _ => debug_assert!(false, "{phase:?} ended without starting"),
+        }
+    }
+}
@@ -1,57 +1,518 @@
 use alloc::{
     boxed::Box,
+    format,
     string::{String, ToString},
+    sync::Arc,
+};
+use core::{
+    cmp::Ordering,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    hash::{Hash, Hasher},
+    iter,
+};
+
+use crate::{
+    stored::{Idx, MAX_NODES},
+    NodeHash, RootParams, TrieRoot,
 };
-use core::fmt::{self, Display, Formatter};
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct TrieError(Box<str>);
+/// An error from the trie, or from a database it read.
+///
+/// Errors compare by message only.
+/// A database error converted with `TrieError::from_source` is kept as the `Error::source`,
+/// so callers can recover it with `TrieError::downcast_source`, for example to retry transient failures.
+#[derive(Debug, Clone)]
+pub struct TrieError {
+    message: Box<str>,
+    source: Option<Arc<dyn Error + Send + Sync>>,
+}
 
 impl TrieError {
     #[inline]
     pub fn display(&self) -> &str {
-        &self.0
+        &self.message
+    }
+
+    /// An error with `source` as its cause, and the same message.
+    #[inline]
+    pub fn from_source(source: impl Error + Send + Sync + 'static) -> Self {
+        TrieError {
+            message: source.to_string().into_boxed_str(),
+            source: Some(Arc::new(source)),
+        }
+    }
+
+    /// Prefix the message with `context`, keeping the source.
+    #[inline]
+    pub fn context(self, context: impl Display) -> Self {
+        TrieError {
+            message: format!("{context}: {}", self.message).into_boxed_str(),
+            source: self.source,
+        }
+    }
+
+    /// The first error in the chain of sources of this error that is an `E`.
+    #[inline]
+    pub fn downcast_source<E: Error + 'static>(&self) -> Option<&E> {
+        let source: &(dyn Error + 'static) = self.source.as_deref()?;
+        iter::successors(Some(source), |&e| e.source()).find_map(|e| e.downcast_ref())
+    }
+
+    /// Replace the source of this error, keeping the message.
+    #[inline]
+    pub(crate) fn with_source(self, source: impl Error + Send + Sync + 'static) -> Self {
+        TrieError {
+            message: self.message,
+            source: Some(Arc::new(source)),
+        }
     }
 }
 
 impl Display for TrieError {
     #[inline]
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for TrieError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+impl PartialEq for TrieError {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message
+    }
+}
+
+impl Eq for TrieError {}
+
+impl PartialOrd for TrieError {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
+impl Ord for TrieError {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.message.cmp(&other.message)
+    }
+}
+
+impl Hash for TrieError {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.message.hash(state);
+    }
+}
+
+/// Convert a database or store error to a `TrieError`, prefixing its message with `context`.
+#[inline]
+pub(crate) fn error_context(error: impl Into<TrieError>, context: impl Display) -> TrieError {
+    error.into().context(context)
+}
+
 impl From<&str> for TrieError {
     #[inline]
     fn from(s: &str) -> Self {
-        Self(s.into())
+        Self::from(String::from(s))
     }
 }
 
 impl From<String> for TrieError {
     #[inline]
     fn from(s: String) -> Self {
-        Self(s.into_boxed_str())
+        TrieError {
+            message: s.into_boxed_str(),
+            source: None,
+        }
     }
 }
 
 impl From<&String> for TrieError {
     #[inline]
     fn from(s: &String) -> Self {
-        Self(s.clone().into_boxed_str())
+        Self::from(s.clone())
     }
 }
 
 impl From<&TrieError> for String {
     #[inline]
     fn from(e: &TrieError) -> Self {
-        e.0.to_string()
+        e.message.to_string()
     }
 }
 
 impl From<TrieError> for String {
     #[inline]
     fn from(e: TrieError) -> Self {
-        e.0.to_string()
+        e.message.into_string()
+    }
+}
+
+/// The reason a batch failed verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The snapshot does not hash to the root the verifier expected before the batch.
+    OldRootMismatch {
+        expected: TrieRoot<NodeHash>,
+        actual: TrieRoot<NodeHash>,
+    },
+    /// Replaying the batch produced a different root than the one claimed.
+    NewRootMismatch {
+        expected: TrieRoot<NodeHash>,
+        actual: TrieRoot<NodeHash>,
+    },
+    /// Replaying the operation at `op_idx` returned a different value than the original run.
+    ResultMismatch { op_idx: usize },
+    /// The batch at `batch_idx` of a `SnapshotChain` does not start from the root the previous batch produced.
+    ChainBroken {
+        batch_idx: usize,
+        expected: TrieRoot<NodeHash>,
+        actual: TrieRoot<NodeHash>,
+    },
+    /// Undoing a batch with `AuditedBatch::revert` did not restore the root before it.
+    RevertMismatch {
+        expected: TrieRoot<NodeHash>,
+        actual: TrieRoot<NodeHash>,
+    },
+    /// The snapshot's root does not match the truncated commitment the verifier expected before the batch.
+    OldTruncatedRootMismatch {
+        expected: TrieRoot<Box<[u8]>>,
+        actual: TrieRoot<NodeHash>,
+    },
+    /// Replaying the batch produced a root that does not match the truncated commitment claimed.
+    NewTruncatedRootMismatch {
+        expected: TrieRoot<Box<[u8]>>,
+        actual: TrieRoot<NodeHash>,
+    },
+    /// A root was computed under different `RootParams` than the verifier expects.
+    ParamsMismatch {
+        expected: RootParams,
+        actual: RootParams,
+    },
+    /// The snapshot could not be read, or did not contain a node the batch needed.
+    Trie(TrieError),
+}
+
+impl Display for VerifyError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            VerifyError::OldRootMismatch { expected, actual } => write!(
+                f,
+                "Snapshot root mismatch: expected {expected:?}, snapshot hashes to {actual:?}"
+            ),
+            VerifyError::NewRootMismatch { expected, actual } => write!(
+                f,
+                "New root mismatch: expected {expected:?}, replay produced {actual:?}"
+            ),
+            VerifyError::ResultMismatch { op_idx } => write!(
+                f,
+                "Operation {op_idx} returned a different value when replayed against the snapshot"
+            ),
+            VerifyError::ChainBroken {
+                batch_idx,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Batch {batch_idx} starts from {actual:?}, but the previous batch produced {expected:?}"
+            ),
+            VerifyError::RevertMismatch { expected, actual } => write!(
+                f,
+                "Revert mismatch: expected {expected:?}, undoing the batch produced {actual:?}"
+            ),
+            VerifyError::OldTruncatedRootMismatch { expected, actual } => write!(
+                f,
+                "Snapshot root mismatch: expected a root starting with {expected:?}, snapshot hashes to {actual:?}"
+            ),
+            VerifyError::NewTruncatedRootMismatch { expected, actual } => write!(
+                f,
+                "New root mismatch: expected a root starting with {expected:?}, replay produced {actual:?}"
+            ),
+            VerifyError::ParamsMismatch { expected, actual } => write!(
+                f,
+                "Trie parameters mismatch: expected {expected:?}, root was computed under {actual:?}"
+            ),
+            VerifyError::Trie(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<TrieError> for VerifyError {
+    #[inline]
+    fn from(e: TrieError) -> Self {
+        VerifyError::Trie(e)
+    }
+}
+
+impl From<VerifyError> for TrieError {
+    #[inline]
+    fn from(e: VerifyError) -> Self {
+        match e {
+            VerifyError::Trie(e) => e,
+            e => TrieError::from(e.to_string()),
+        }
+    }
+}
+
+/// The reason a `FlatSnapshot` could not be read.
+///
+/// Unlike `TrieError`, it never allocates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlatError {
+    /// The arrays do not describe a trie with a root.
+    InvalidShape {
+        branches: usize,
+        leaves: usize,
+        unvisited_nodes: usize,
+    },
+    /// A branch refers to a node index past the end of the snapshot.
+    NodeNotFound(Idx),
+    /// The branch at this index has a prefix range past the end of `prefixes`.
+    PrefixOutOfRange(Idx),
+    /// The branch at this index has more than `spec::MAX_BRANCH_PREFIX_WORDS` prefix words,
+    /// or more than the words before its discriminant word.
+    PrefixTooLong(Idx),
+    /// Reading a key reached a node the snapshot only knows by hash.
+    Unvisited(Idx),
+    /// The branch at this index does not discriminate on a later bit than its parent.
+    BranchOutOfOrder(Idx),
+    /// The node at this index is reachable through more than one branch.
+    SharedNode(Idx),
+    /// The leaves of a `FixedFlatSnapshot` are not a whole number of leaves.
+    MisalignedLeaves { bytes: usize, stride: usize },
+    /// The snapshot has more nodes than `stored::MAX_NODES`, so `Idx` cannot index them all on this target.
+    TooManyNodes(usize),
+}
+
+impl Display for FlatError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            FlatError::InvalidShape {
+                branches,
+                leaves,
+                unvisited_nodes,
+            } => write!(
+                f,
+                "Invalid snapshot: \n\
+                a tree with no branches can only have one leaf.\n\
+                a tree with no branches or leaves can only have one unvisited node.\n\
+                Found {branches} branches, {leaves} leaves, and {unvisited_nodes} unvisited nodes"
+            ),
+            FlatError::NodeNotFound(idx) => write!(f, "Invalid snapshot: node {idx} not found"),
+            FlatError::PrefixTooLong(idx) => write!(
+                f,
+                "Invalid snapshot: the prefix of branch {idx} is longer than any canonical branch's"
+            ),
+            FlatError::PrefixOutOfRange(idx) => write!(
+                f,
+                "Invalid snapshot: the prefix of branch {idx} is out of range"
+            ),
+            FlatError::Unvisited(idx) => write!(
+                f,
+                "Invalid snapshot: node {idx} was not visited, the snapshot cannot answer this read"
+            ),
+            FlatError::BranchOutOfOrder(idx) => write!(
+                f,
+                "Invalid snapshot: branch {idx} does not discriminate on a later bit than its parent"
+            ),
+            FlatError::SharedNode(idx) => write!(
+                f,
+                "Invalid snapshot: node {idx} is reachable through more than one branch"
+            ),
+            FlatError::MisalignedLeaves { bytes, stride } => write!(
+                f,
+                "Invalid snapshot: {bytes} bytes of leaves is not a multiple of the {stride} byte leaf stride"
+            ),
+            FlatError::TooManyNodes(nodes) => write!(
+                f,
+                "Invalid snapshot: {nodes} nodes is more than the {MAX_NODES} an `Idx` can index on this target"
+            ),
+        }
+    }
+}
+
+impl From<FlatError> for TrieError {
+    #[inline]
+    fn from(e: FlatError) -> Self {
+        TrieError::from(e.to_string())
+    }
+}
+
+/// A node larger than the canonical encoding or the verifier's `NodeLimits` allow.
+///
+/// Converting it to a `TrieError` keeps it as the source, see `TrieError::downcast_source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeSizeError {
+    /// A branch has `words` prefix words, more than `max`.
+    PrefixTooLong { words: usize, max: usize },
+    /// A leaf value's `PortableHash` encoding is `bytes` long, more than `max`.
+    ValueTooLarge { bytes: usize, max: usize },
+}
+
+impl Display for NodeSizeError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            NodeSizeError::PrefixTooLong { words, max } => write!(
+                f,
+                "Branch prefix of {words} words is longer than the limit of {max} words"
+            ),
+            NodeSizeError::ValueTooLarge { bytes, max } => write!(
+                f,
+                "Leaf value of {bytes} bytes is larger than the limit of {max} bytes"
+            ),
+        }
+    }
+}
+
+impl Error for NodeSizeError {}
+
+impl From<NodeSizeError> for TrieError {
+    #[inline]
+    fn from(e: NodeSizeError) -> Self {
+        TrieError::from_source(e)
+    }
+}
+
+/// A `NodeRef::temp_null_stored` placeholder left reachable from the root of a `Transaction`.
+///
+/// Placeholders only exist while an operation moves a node, so a leak is a bug in the trie.
+/// Converting it to a `TrieError` keeps it as the source, see `TrieError::downcast_source`.
+#[derive(Debug, Clone)]
+pub struct LeakedPlaceholder {
+    /// The words of the key whose operation left the placeholder on its path, or beside it.
+    /// `None` when `calc_root_hash` found the placeholder.
+    pub key: Option<Box<[u32]>>,
+    /// The number of branches above the placeholder, when `key` is known.
+    pub depth: Option<usize>,
+    /// The error of the operation that left the placeholder, if it failed.
+    pub source: Option<TrieError>,
+}
+
+impl Display for LeakedPlaceholder {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match (&self.key, self.depth) {
+            (Some(key), Some(depth)) => write!(
+                f,
+                "Invalid transaction: a placeholder node was left at depth {depth} on the path to {key:?}"
+            )?,
+            _ => write!(
+                f,
+                "Invalid transaction: a placeholder node is reachable from the root, refusing to hash it"
+            )?,
+        }
+        if let Some(source) = &self.source {
+            write!(f, ": {source}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for LeakedPlaceholder {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+impl From<LeakedPlaceholder> for TrieError {
+    #[inline]
+    fn from(e: LeakedPlaceholder) -> Self {
+        TrieError::from_source(e)
+    }
+}
+
+/// The reason an `InclusionProof` could not be encoded with `InclusionProof::encode_compact`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The path has more branches than `MAX_COMPACT_DEPTH`.
+    TooDeep { depth: usize, max: usize },
+    /// The branch at `depth` on the path, counting from the root, is not the one the proven key implies.
+    NonCanonical { depth: usize },
+    /// The encoding is longer than the buffer of `InclusionProof::encode_fixed`.
+    TooLong { len: usize, capacity: usize },
+}
+
+impl Display for EncodeError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            EncodeError::TooDeep { depth, max } => write!(
+                f,
+                "Cannot encode a proof of {depth} branches, the compact encoding holds at most {max}"
+            ),
+            EncodeError::NonCanonical { depth } => write!(
+                f,
+                "Cannot encode the proof: branch {depth} of the path does not follow from the proven key"
+            ),
+            EncodeError::TooLong { len, capacity } => write!(
+                f,
+                "The proof encodes to {len} bytes, more than the {capacity} byte buffer"
+            ),
+        }
+    }
+}
+
+impl From<EncodeError> for TrieError {
+    #[inline]
+    fn from(e: EncodeError) -> Self {
+        TrieError::from(e.to_string())
+    }
+}
+
+/// The reason a leaf value could not be decoded with `BoundedDecode`.
+///
+/// Converting it to a `TrieError` keeps it as the source, see `TrieError::downcast_source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The encoding is `bytes` long, more than the limit of `max`.
+    /// When the encoding was not read to its end, `bytes` is the first length past the limit.
+    TooLong { bytes: usize, max: usize },
+    /// A field needed `needed` more bytes, but only `remaining` were left.
+    Truncated { needed: usize, remaining: usize },
+    /// The value was decoded with this many bytes left over.
+    TrailingBytes(usize),
+    /// The bytes are not an encoding of the type.
+    Invalid(&'static str),
+}
+
+impl Display for DecodeError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DecodeError::TooLong { bytes, max } => write!(
+                f,
+                "Leaf value of {bytes} bytes is longer than the limit of {max} bytes"
+            ),
+            DecodeError::Truncated { needed, remaining } => write!(
+                f,
+                "Leaf value is truncated: needed {needed} bytes, {remaining} were left"
+            ),
+            DecodeError::TrailingBytes(len) => {
+                write!(f, "Leaf value has {len} bytes left over after decoding")
+            }
+            DecodeError::Invalid(reason) => write!(f, "Invalid leaf value: {reason}"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+impl From<DecodeError> for TrieError {
+    #[inline]
+    fn from(e: DecodeError) -> Self {
+        TrieError::from_source(e)
     }
 }
@@ -0,0 +1,61 @@
+//! Keys and hashes written as hex and decoded at compile time, for golden roots and fixed keys in tests and contracts.
+//!
+//! ```
+//! use kairos_trie::{key_hash, node_hash, KeyHash, NodeHash, TrieRoot};
+//!
+//! const ROOT: TrieRoot<NodeHash> = TrieRoot::Node(node_hash!(
+//!     "58cf94c20c1577df44120ccda03b18f5ce630fefc69077b8654376f8dfca0857"
+//! ));
+//! const KEY: KeyHash = key_hash!("0x0100000000000000000000000000000000000000000000000000000000000000");
+//! assert_eq!(KEY, KeyHash::from_u64(1));
+//! ```
+//!
+//! Hex is read in byte order, as `NodeHash::bytes` and `KeyHash::to_bytes`.
+//! Malformed hex fails to compile.
+
+/// The 32 bytes written as 64 hex digits in `hex`, with an optional `0x` prefix.
+///
+/// Panics if `hex` is not 64 hex digits, at compile time when evaluated in a constant.
+#[inline]
+pub const fn hex32(hex: &str) -> [u8; 32] {
+    let mut digits = hex.as_bytes();
+    if let [b'0', b'x' | b'X', rest @ ..] = digits {
+        digits = rest;
+    }
+    assert!(digits.len() == 64, "Expected 64 hex digits");
+
+    let mut bytes = [0; 32];
+    let mut i = 0;
+    while i < bytes.len() {
+        bytes[i] = hex_digit(digits[2 * i]) << 4 | hex_digit(digits[2 * i + 1]);
+        i += 1;
+    }
+    bytes
+}
+
+const fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        b'A'..=b'F' => digit - b'A' + 10,
+        _ => panic!("Invalid hex digit"),
+    }
+}
+
+/// A `NodeHash` written as 64 hex digits, decoded at compile time, see `fixture`.
+#[macro_export]
+macro_rules! node_hash {
+    ($hex:expr) => {{
+        const BYTES: [u8; 32] = $crate::fixture::hex32($hex);
+        $crate::NodeHash::new(BYTES)
+    }};
+}
+
+/// A `KeyHash` written as the 64 hex digits of its `to_bytes`, decoded at compile time, see `fixture`.
+#[macro_export]
+macro_rules! key_hash {
+    ($hex:expr) => {{
+        const BYTES: [u8; 32] = $crate::fixture::hex32($hex);
+        $crate::KeyHash::from_bytes(&BYTES)
+    }};
+}
@@ -1,11 +1,125 @@
-use alloc::{boxed::Box, rc::Rc, string::String, sync::Arc, vec::Vec};
+use alloc::{
+    borrow::{Cow, ToOwned},
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    rc::Rc,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{fmt, marker::PhantomData};
 
 pub trait PortableHasher<const LEN: usize>: PortableUpdate + Default {
+    /// Hashed before the fields of every leaf. Empty by default, see `TrieParams`.
+    const LEAF_TAG: &'static [u8] = &[];
+    /// Hashed before the fields of every branch. Empty by default, see `TrieParams`.
+    const BRANCH_TAG: &'static [u8] = &[];
+    /// Whether node hashes carry the number of leaves under them, see `counted`. Off by default.
+    const COUNTED: bool = false;
+
     fn finalize_reset(&mut self) -> [u8; LEN];
 }
 
 pub trait PortableUpdate {
     fn portable_update(&mut self, data: impl AsRef<[u8]>);
+
+    /// Update the hasher with the little endian bytes of each word.
+    ///
+    /// The default implementation copies the words into a stack buffer,
+    /// so the hasher sees a few large updates instead of one per word.
+    /// Hashers that can absorb words directly should override this,
+    /// but must produce the same result as hashing `to_le_bytes` of each word in order.
+    #[inline]
+    fn portable_update_u32_slice(&mut self, words: &[u32]) {
+        const CHUNK_WORDS: usize = 16;
+        let mut buf = [0; CHUNK_WORDS * 4];
+
+        for chunk in words.chunks(CHUNK_WORDS) {
+            for (bytes, word) in buf.as_chunks_mut::<4>().0.iter_mut().zip(chunk) {
+                *bytes = word.to_le_bytes();
+            }
+            self.portable_update(&buf[..chunk.len() * 4]);
+        }
+    }
+}
+
+/// Domain separation tags for the nodes of a trie, applied by hashing with a `TaggedHasher`.
+///
+/// Applications sharing infrastructure should pick distinct tags,
+/// so a node of one application's trie never hashes the same as a node of another's.
+/// Distinct leaf and branch tags also keep a leaf from hashing the same as a branch.
+///
+/// The tags are constants of the hasher's type,
+/// so every root, snapshot and proof computed or checked with `TaggedHasher<H, Self>` is bound to them,
+/// and a guest cannot be handed a different configuration at runtime.
+/// Changing the tags changes every hash in the trie.
+pub trait TrieParams {
+    const LEAF_TAG: &'static [u8];
+    const BRANCH_TAG: &'static [u8];
+    /// Fold leaf counts into node hashes, see `counted`.
+    const COUNTED: bool = false;
+}
+
+/// Wraps a hasher, hashing the tags of `P` before each leaf and branch.
+pub struct TaggedHasher<H, P> {
+    pub hasher: H,
+    params: PhantomData<fn() -> P>,
+}
+
+impl<H, P> TaggedHasher<H, P> {
+    #[inline(always)]
+    pub fn new(hasher: H) -> Self {
+        Self {
+            hasher,
+            params: PhantomData,
+        }
+    }
+}
+
+impl<H: Default, P> Default for TaggedHasher<H, P> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(H::default())
+    }
+}
+
+impl<H: Clone, P> Clone for TaggedHasher<H, P> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::new(self.hasher.clone())
+    }
+}
+
+impl<H: fmt::Debug, P> fmt::Debug for TaggedHasher<H, P> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TaggedHasher").field(&self.hasher).finish()
+    }
+}
+
+impl<const LEN: usize, H: PortableHasher<LEN>, P: TrieParams> PortableHasher<LEN>
+    for TaggedHasher<H, P>
+{
+    const LEAF_TAG: &'static [u8] = P::LEAF_TAG;
+    const BRANCH_TAG: &'static [u8] = P::BRANCH_TAG;
+    const COUNTED: bool = P::COUNTED;
+
+    #[inline(always)]
+    fn finalize_reset(&mut self) -> [u8; LEN] {
+        self.hasher.finalize_reset()
+    }
+}
+
+impl<H: PortableUpdate, P> PortableUpdate for TaggedHasher<H, P> {
+    #[inline(always)]
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.hasher.portable_update(data);
+    }
+
+    #[inline(always)]
+    fn portable_update_u32_slice(&mut self, words: &[u32]) {
+        self.hasher.portable_update_u32_slice(words);
+    }
 }
 
 /// A wrapper around a `digest::Digest` that implements `PortableHasher`.
@@ -43,6 +157,73 @@ impl<H: digest::Digest> PortableUpdate for DigestHasher<H> {
     }
 }
 
+/// A fast, NON-CRYPTOGRAPHIC `PortableHasher`, built on XXH3.
+///
+/// Finding two tries with the same root is easy, so never use this for a root that leaves the process,
+/// is committed to on chain, or is checked by a verifier.
+/// It is meant for local caches and indexes over trusted data,
+/// which want the trie's API and structural sharing without paying for a cryptographic hash.
+///
+/// The 32 byte output is two 128 bit XXH3 hashes of the input with different seeds.
+/// The output is stable across platforms and versions of this crate.
+#[cfg(feature = "fast-hash")]
+#[derive(Clone)]
+pub struct FastHash {
+    low: xxhash_rust::xxh3::Xxh3,
+    high: xxhash_rust::xxh3::Xxh3,
+}
+
+#[cfg(feature = "fast-hash")]
+impl FastHash {
+    const HIGH_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    #[inline]
+    pub fn new() -> Self {
+        FastHash {
+            low: xxhash_rust::xxh3::Xxh3::new(),
+            high: xxhash_rust::xxh3::Xxh3::with_seed(Self::HIGH_SEED),
+        }
+    }
+}
+
+#[cfg(feature = "fast-hash")]
+impl Default for FastHash {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "fast-hash")]
+impl fmt::Debug for FastHash {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FastHash").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "fast-hash")]
+impl PortableHasher<32> for FastHash {
+    #[inline]
+    fn finalize_reset(&mut self) -> [u8; 32] {
+        let mut out = [0; 32];
+        out[..16].copy_from_slice(&self.low.digest128().to_le_bytes());
+        out[16..].copy_from_slice(&self.high.digest128().to_le_bytes());
+        self.low.reset();
+        self.high.reset();
+        out
+    }
+}
+
+#[cfg(feature = "fast-hash")]
+impl PortableUpdate for FastHash {
+    #[inline]
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.low.update(data.as_ref());
+        self.high.update(data.as_ref());
+    }
+}
+
 /// `std::portable_hash::portable_Hash` is not portable across platforms.
 /// Implement this trait for a type that can be hashed in a portable way.
 ///
@@ -52,174 +233,245 @@ impl<H: digest::Digest> PortableUpdate for DigestHasher<H> {
 /// - Always use `to_le_bytes` or `to_be_bytes`.
 ///
 /// All supported primitive types use `to_le_bytes`.
+///
+/// The implementations here follow the same rules as `#[derive(PortableHash)]`, so values hash the same on every team:
+/// - `Option<T>` and `Result<T, E>` hash like a derived enum,
+///   the index of the variant as a little endian `u32`, `None` and `Ok` are 0, `Some` and `Err` are 1,
+///   then the variant's value.
+/// - Slices and `Vec`s hash their number of items as a little endian `u64`, then each item in order.
+///   Arrays have a fixed number of items, so they hash only the items.
+/// - `BTreeMap<K, V>` hashes its number of entries as a little endian `u64`,
+///   then each key and its value in ascending order. `BTreeSet<T>` hashes its number of items, then each item.
+/// - Items of those collections, and the values of `Option` and `Result`, are hashed with `portable_hash_delimited`,
+///   so a byte string inside them hashes its length first.
+/// - Tuples hash each field in order.
+/// - References and smart pointers hash the same as the value they point to.
+///
+/// The byte string impls, `[u8]`, `Vec<u8>`, `str` and `String`, and the slices and `Vec`s of the other
+/// primitive integers, `bool` and `char`, predate these rules and hash only their items, so existing roots keep their hashes.
+/// A byte string followed by another field of a tuple or derived type is therefore ambiguous.
+/// Give such types a fixed size, or hash their length in a field before them.
 pub trait PortableHash {
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H);
+
+    /// Hash `self` as an item of a collection, or the value of an `Option` or `Result`,
+    /// where the bytes hashed must end where the value ends.
+    ///
+    /// Defaults to `portable_hash`, which already delimits fixed size types and the length prefixed collections.
+    /// Types whose `portable_hash` is not delimited, like the byte strings, override this to hash their length first.
+    #[inline]
+    fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+        self.portable_hash(hasher);
+    }
+
+    /// Hash each item of `data` in order, with `portable_hash_delimited`.
+    ///
+    /// Override this to hash a slice of `Self` in fewer updates, producing the same result.
+    #[inline]
+    fn portable_hash_slice<H: PortableUpdate>(data: &[Self], hasher: &mut H)
+    where
+        Self: Sized,
+    {
+        for item in data {
+            item.portable_hash_delimited(hasher);
+        }
+    }
+
+    /// Hash `data` as a variable length sequence, as `[Self]` and `Vec<Self>` do.
+    ///
+    /// Hashes the number of items as a little endian `u64`, then the items with `portable_hash_slice`.
+    /// The primitive integers, `bool` and `char` hash only the items, like the sequence impls that predate the length prefix.
+    #[inline]
+    fn portable_hash_seq<H: PortableUpdate>(data: &[Self], hasher: &mut H)
+    where
+        Self: Sized,
+    {
+        hash_len(data.len(), hasher);
+        Self::portable_hash_slice(data, hasher);
+    }
 }
 
-impl PortableHash for () {
-    #[inline(always)]
-    fn portable_hash<H: PortableUpdate>(&self, _: &mut H) {}
+/// Hash the length of a collection as a fixed width little endian `u64`.
+#[inline(always)]
+fn hash_len<H: PortableUpdate>(len: usize, hasher: &mut H) {
+    hasher.portable_update((len as u64).to_le_bytes());
 }
 
-impl PortableHash for u8 {
+impl<T: PortableHash + ?Sized> PortableHash for &T {
     #[inline(always)]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update([*self]);
+        (**self).portable_hash(hasher);
     }
-}
-impl PortableHash for &u8 {
+
     #[inline(always)]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update([**self]);
+    fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+        (**self).portable_hash_delimited(hasher);
     }
 }
 
-impl<const N: usize> PortableHash for [u8; N] {
-    #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self);
-    }
+impl PortableHash for () {
+    #[inline(always)]
+    fn portable_hash<H: PortableUpdate>(&self, _: &mut H) {}
 }
-impl<const N: usize> PortableHash for &[u8; N] {
-    #[inline]
+
+impl PortableHash for u8 {
+    #[inline(always)]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(*self);
+        hasher.portable_update([*self]);
     }
-}
 
-impl PortableHash for [u8] {
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self);
+    fn portable_hash_slice<H: PortableUpdate>(data: &[Self], hasher: &mut H) {
+        hasher.portable_update(data);
     }
-}
-impl PortableHash for &[u8] {
+
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self);
+    fn portable_hash_seq<H: PortableUpdate>(data: &[Self], hasher: &mut H) {
+        Self::portable_hash_slice(data, hasher);
     }
 }
 
-impl PortableHash for Vec<u8> {
+impl PortableHash for bool {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self);
+        hasher.portable_update([*self as u8]);
     }
-}
-impl PortableHash for &Vec<u8> {
+
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self);
+    fn portable_hash_slice<H: PortableUpdate>(data: &[Self], hasher: &mut H) {
+        const CHUNK_LEN: usize = 64;
+        let mut buf = [0; CHUNK_LEN];
+
+        for chunk in data.chunks(CHUNK_LEN) {
+            for (byte, item) in buf.iter_mut().zip(chunk) {
+                *byte = *item as u8;
+            }
+            hasher.portable_update(&buf[..chunk.len()]);
+        }
     }
-}
 
-impl PortableHash for bool {
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update([*self as u8]);
+    fn portable_hash_seq<H: PortableUpdate>(data: &[Self], hasher: &mut H) {
+        Self::portable_hash_slice(data, hasher);
     }
 }
-impl PortableHash for &bool {
+
+impl PortableHash for char {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update([**self as u8]);
+        hasher.portable_update((*self as u32).to_le_bytes());
     }
-}
-impl<const N: usize> PortableHash for [bool; N] {
+
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        (&self).portable_hash(hasher);
+    fn portable_hash_seq<H: PortableUpdate>(data: &[Self], hasher: &mut H) {
+        Self::portable_hash_slice(data, hasher);
     }
 }
-impl<const N: usize> PortableHash for &[bool; N] {
+
+impl PortableHash for str {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        let mut bytes = [0; N];
-        for (i, item) in self.iter().enumerate() {
-            bytes[i] = *item as u8;
-        }
-        hasher.portable_update(bytes);
+        hasher.portable_update(self.as_bytes());
     }
-}
-impl PortableHash for &[bool] {
+
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        for item in *self {
-            item.portable_hash(hasher);
-        }
+    fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+        hash_len(self.len(), hasher);
+        hasher.portable_update(self.as_bytes());
     }
 }
-impl PortableHash for [bool] {
+
+impl PortableHash for String {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        (&self).portable_hash(hasher);
+        self.as_str().portable_hash(hasher);
     }
-}
-impl PortableHash for Vec<bool> {
+
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        for item in self {
-            item.portable_hash(hasher);
-        }
+    fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+        self.as_str().portable_hash_delimited(hasher);
     }
 }
-impl PortableHash for &Vec<bool> {
+
+impl<T: PortableHash> PortableHash for [T] {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        (*self).portable_hash(hasher);
+        T::portable_hash_seq(self, hasher);
     }
-}
 
-impl PortableHash for char {
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update((*self as u32).to_le_bytes());
+    fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+        hash_len(self.len(), hasher);
+        T::portable_hash_slice(self, hasher);
     }
 }
-impl PortableHash for &char {
+
+impl<T: PortableHash, const N: usize> PortableHash for [T; N] {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update((**self as u32).to_le_bytes());
+        T::portable_hash_slice(self, hasher);
     }
 }
-impl PortableHash for Vec<char> {
+
+impl<T: PortableHash> PortableHash for Vec<T> {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        for item in self {
-            item.portable_hash(hasher);
-        }
+        self.as_slice().portable_hash(hasher);
     }
-}
-impl PortableHash for &Vec<char> {
+
     #[inline]
-    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        (*self).portable_hash(hasher);
+    fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+        self.as_slice().portable_hash_delimited(hasher);
     }
 }
 
-impl PortableHash for str {
+impl<T: PortableHash> PortableHash for Option<T> {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self.as_bytes());
+        match self {
+            None => hasher.portable_update(0u32.to_le_bytes()),
+            Some(value) => {
+                hasher.portable_update(1u32.to_le_bytes());
+                value.portable_hash_delimited(hasher);
+            }
+        }
     }
 }
-impl PortableHash for &str {
+
+impl<T: PortableHash, E: PortableHash> PortableHash for Result<T, E> {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self.as_bytes());
+        match self {
+            Ok(value) => {
+                hasher.portable_update(0u32.to_le_bytes());
+                value.portable_hash_delimited(hasher);
+            }
+            Err(err) => {
+                hasher.portable_update(1u32.to_le_bytes());
+                err.portable_hash_delimited(hasher);
+            }
+        }
     }
 }
 
-impl PortableHash for String {
+impl<K: PortableHash, V: PortableHash> PortableHash for BTreeMap<K, V> {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self.as_bytes());
+        hash_len(self.len(), hasher);
+        for (key, value) in self {
+            key.portable_hash_delimited(hasher);
+            value.portable_hash_delimited(hasher);
+        }
     }
 }
-impl PortableHash for &String {
+
+impl<T: PortableHash> PortableHash for BTreeSet<T> {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self.as_bytes());
+        hash_len(self.len(), hasher);
+        for item in self {
+            item.portable_hash_delimited(hasher);
+        }
     }
 }
 
@@ -231,59 +483,10 @@ macro_rules! impl_portable_hash {
                 fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
                     hasher.portable_update(&self.to_le_bytes());
                 }
-            }
-
-            impl<const N: usize> PortableHash for [$t; N] {
-                #[inline]
-                fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-                    for item in self {
-                        item.portable_hash(hasher);
-                    }
-                }
-            }
-
-            impl<const N: usize> PortableHash for &[$t; N] {
-                #[inline]
-                fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-                    for item in *self {
-                        item.portable_hash(hasher);
-                    }
-                }
-            }
-
-            impl PortableHash for [$t] {
-                #[inline]
-                fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-                    for item in self {
-                        item.portable_hash(hasher);
-                    }
-                }
-            }
-
-            impl PortableHash for &[$t] {
-                #[inline]
-                fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-                    for item in *self {
-                        item.portable_hash(hasher);
-                    }
-                }
-            }
-
-            impl PortableHash for Vec<$t> {
-                #[inline]
-                fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-                    for item in self {
-                        item.portable_hash(hasher);
-                    }
-                }
-            }
 
-            impl PortableHash for &Vec<$t> {
                 #[inline]
-                fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-                    for item in *self {
-                        item.portable_hash(hasher);
-                    }
+                fn portable_hash_seq<H: PortableUpdate>(data: &[Self], hasher: &mut H) {
+                    Self::portable_hash_slice(data, hasher);
                 }
             }
         )+
@@ -300,6 +503,11 @@ macro_rules! impl_portable_hash_smart_ptr {
                 fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
                     self.as_ref().portable_hash(hasher);
                 }
+
+                #[inline]
+                fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+                    self.as_ref().portable_hash_delimited(hasher);
+                }
             }
         )+
     };
@@ -307,6 +515,19 @@ macro_rules! impl_portable_hash_smart_ptr {
 
 impl_portable_hash_smart_ptr!(Box<T>, Rc<T>, Arc<T>);
 
+/// Hashes the same as the borrowed value, so `Cow<[u8]>` values hash like `Vec<u8>` values.
+impl<T: PortableHash + ToOwned + ?Sized> PortableHash for Cow<'_, T> {
+    #[inline]
+    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
+        (**self).portable_hash(hasher);
+    }
+
+    #[inline]
+    fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+        (**self).portable_hash_delimited(hasher);
+    }
+}
+
 macro_rules! impl_portable_hash_tuple {
     ($($t:ident),+) => {
         impl<$($t: PortableHash),+> PortableHash for ($($t,)+) {
@@ -316,6 +537,13 @@ macro_rules! impl_portable_hash_tuple {
                 let ($($t,)+) = self;
                 $($t.portable_hash(hasher);)+
             }
+
+            #[inline]
+            fn portable_hash_delimited<H: PortableUpdate>(&self, hasher: &mut H) {
+                #[allow(non_snake_case)]
+                let ($($t,)+) = self;
+                $($t.portable_hash_delimited(hasher);)+
+            }
         }
     };
 }
@@ -0,0 +1,301 @@
+use alloc::{format, vec::Vec};
+
+use crate::{
+    stored::{
+        merkle::{Snapshot, SnapshotBuilder},
+        DatabaseSet, Store,
+    },
+    verify::check_snapshot,
+    KeyHash, NodeHash, Op, PortableHash, PortableHasher, PortableUpdate, Transaction, TrieError,
+    TrieRoot, VerifyError,
+};
+
+/// The value of a leaf in a secondary index: a primary key and the attribute it is indexed under.
+///
+/// The index key keeps only the first 128 bits of each,
+/// so the leaf holds both in full to tell apart entries whose index keys collide.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct IndexEntry {
+    pub attribute: KeyHash,
+    pub key: KeyHash,
+}
+
+impl PortableHash for IndexEntry {
+    #[inline]
+    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
+        self.attribute.portable_hash(hasher);
+        self.key.portable_hash(hasher);
+    }
+}
+
+impl IndexEntry {
+    /// The key of this entry in the index trie,
+    /// the first 128 bits of `attribute` followed by the first 128 bits of `key`.
+    ///
+    /// The entries of an attribute share its first 128 bits, so they are contiguous in trie order.
+    #[inline]
+    pub fn index_key(&self) -> KeyHash {
+        let mut words = [0; 8];
+        words[..4].copy_from_slice(&self.attribute.0[..4]);
+        words[4..].copy_from_slice(&self.key.0[..4]);
+        KeyHash(words)
+    }
+
+    /// The first and last index keys an entry of `attribute` can have.
+    #[inline]
+    fn attribute_bounds(attribute: &KeyHash) -> (KeyHash, KeyHash) {
+        let mut min = [0; 8];
+        min[..4].copy_from_slice(&attribute.0[..4]);
+        let mut max = [u32::MAX; 8];
+        max[..4].copy_from_slice(&attribute.0[..4]);
+        (KeyHash(min), KeyHash(max))
+    }
+}
+
+/// The roots of a primary trie and its secondary index, committed together.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IndexedRoots {
+    pub primary: TrieRoot<NodeHash>,
+    pub index: TrieRoot<NodeHash>,
+}
+
+/// A primary trie and an inverted index trie from an attribute of each value to the keys holding it,
+/// kept in lockstep.
+///
+/// `attribute` maps a value to the `KeyHash` of the attribute it is indexed under, or `None` to leave it out of the index.
+/// It must be deterministic, since the verifier runs it again.
+///
+/// Every write reads the old value from the primary trie first,
+/// then removes the old index entry, then inserts the new one.
+/// Keeping to that order is what makes the index writes, and so the index root,
+/// a function of the primary trie's writes alone.
+/// Against `SnapshotBuilder`s, both snapshots record what `verify_indexed_batch` needs to replay them.
+pub struct SecondaryIndex<P, I, V, F> {
+    primary: Transaction<P, V>,
+    index: Transaction<I, IndexEntry>,
+    attribute: F,
+}
+
+impl<P, I, V, F> SecondaryIndex<P, I, V, F> {
+    /// The index must hold exactly the entries of the primary trie's values under `attribute`.
+    #[inline]
+    pub fn new(
+        primary: Transaction<P, V>,
+        index: Transaction<I, IndexEntry>,
+        attribute: F,
+    ) -> Self {
+        SecondaryIndex {
+            primary,
+            index,
+            attribute,
+        }
+    }
+
+    #[inline]
+    pub fn primary(&self) -> &Transaction<P, V> {
+        &self.primary
+    }
+
+    #[inline]
+    pub fn index(&self) -> &Transaction<I, IndexEntry> {
+        &self.index
+    }
+
+    #[inline]
+    pub fn into_parts(self) -> (Transaction<P, V>, Transaction<I, IndexEntry>) {
+        (self.primary, self.index)
+    }
+}
+
+impl<P, I, V, F> SecondaryIndex<P, I, V, F>
+where
+    P: Store<V>,
+    I: Store<IndexEntry>,
+    V: PortableHash + Clone,
+    F: Fn(&V) -> Option<KeyHash>,
+{
+    #[inline]
+    pub fn get(&self, key_hash: &KeyHash) -> Result<Option<&V>, TrieError> {
+        self.primary.get(key_hash)
+    }
+
+    /// Insert `value` at `key_hash`, moving the key to the index entry of the new value's attribute.
+    #[inline]
+    pub fn insert(&mut self, key_hash: &KeyHash, value: V) -> Result<(), TrieError> {
+        let old = self.primary.get(key_hash)?.and_then(&self.attribute);
+        let new = (self.attribute)(&value);
+        self.primary.insert(key_hash, value)?;
+        self.update_index(key_hash, old, new)
+    }
+
+    /// Remove the value at `key_hash` and its index entry, returning the value.
+    #[inline]
+    pub fn remove(&mut self, key_hash: &KeyHash) -> Result<Option<V>, TrieError> {
+        let Some(value) = self.primary.remove(key_hash)? else {
+            return Ok(None);
+        };
+        self.update_index(key_hash, (self.attribute)(&value), None)?;
+        Ok(Some(value))
+    }
+
+    /// The keys whose values have `attribute`, ordered by `KeyHash::cmp_trie_order` of their index keys.
+    ///
+    /// Against a `SnapshotBuilder` this records the range of the index holding the attribute,
+    /// so the index snapshot proves no key was omitted.
+    #[inline]
+    pub fn keys_with_attribute(&self, attribute: &KeyHash) -> Result<Vec<KeyHash>, TrieError> {
+        let (start, end) = IndexEntry::attribute_bounds(attribute);
+        Ok(self
+            .index
+            .range_get(start..=end)?
+            .into_iter()
+            .filter(|(_, entry)| entry.attribute == *attribute)
+            .map(|(_, entry)| entry.key)
+            .collect())
+    }
+
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn calc_root_hashes(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<IndexedRoots, TrieError> {
+        Ok(IndexedRoots {
+            primary: self.primary.calc_root_hash(hasher)?,
+            index: self.index.calc_root_hash(hasher)?,
+        })
+    }
+
+    /// Remove the index entry of `key_hash` under `old`, then add one under `new`.
+    #[inline]
+    fn update_index(
+        &mut self,
+        key_hash: &KeyHash,
+        old: Option<KeyHash>,
+        new: Option<KeyHash>,
+    ) -> Result<(), TrieError> {
+        if old == new {
+            return Ok(());
+        }
+
+        if let Some(attribute) = old {
+            let entry = IndexEntry {
+                attribute,
+                key: *key_hash,
+            };
+            match self.index.remove(&entry.index_key())? {
+                Some(removed) if removed == entry => {}
+                removed => {
+                    return Err(format!(
+                        "Index out of sync: expected {entry:?} at {:?}, found {removed:?}",
+                        entry.index_key()
+                    )
+                    .into())
+                }
+            }
+        }
+
+        if let Some(attribute) = new {
+            let entry = IndexEntry {
+                attribute,
+                key: *key_hash,
+            };
+            let index_key = entry.index_key();
+            if let Some(existing) = self.index.get(&index_key)? {
+                return Err(format!(
+                    "Index key collision at {index_key:?}: it holds {existing:?}, not {entry:?}"
+                )
+                .into());
+            }
+            self.index.insert(&index_key, entry)?;
+        }
+        Ok(())
+    }
+}
+
+impl<Pd, Id, V, F> SecondaryIndex<SnapshotBuilder<Pd, V>, SnapshotBuilder<Id, IndexEntry>, V, F>
+where
+    Pd: DatabaseSet<V>,
+    Id: DatabaseSet<IndexEntry>,
+    V: PortableHash + Clone,
+    F: Fn(&V) -> Option<KeyHash>,
+{
+    /// Write the modified nodes of both tries to their databases, see `Transaction::commit`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit(&self, hasher: &mut impl PortableHasher<32>) -> Result<IndexedRoots, TrieError> {
+        Ok(IndexedRoots {
+            primary: self.primary.commit(hasher)?.root,
+            index: self.index.commit(hasher)?.root,
+        })
+    }
+
+    /// The witnesses of both tries, for `verify_indexed_batch`.
+    #[inline]
+    pub fn build_initial_snapshots(&self) -> (Snapshot<V>, Snapshot<IndexEntry>) {
+        (
+            self.primary.build_initial_snapshot(),
+            self.index.build_initial_snapshot(),
+        )
+    }
+}
+
+/// Verify that applying `ops` through a `SecondaryIndex` takes both tries from `old_roots` to `new_roots`.
+///
+/// Like `verify_batch`, each snapshot must hash to its old root and be canonical.
+/// The index is never written directly, so a matching index root proves the index followed the primary trie's writes.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn verify_indexed_batch<V: PortableHash + Clone>(
+    old_roots: IndexedRoots,
+    new_roots: IndexedRoots,
+    primary: &Snapshot<V>,
+    index: &Snapshot<IndexEntry>,
+    ops: &[Op<V>],
+    attribute: impl Fn(&V) -> Option<KeyHash>,
+    hasher: &mut impl PortableHasher<32>,
+) -> Result<(), VerifyError> {
+    let check_root = |expected: TrieRoot<NodeHash>| {
+        move |actual: TrieRoot<NodeHash>| {
+            if actual.verify_eq(&expected) {
+                Ok(())
+            } else {
+                Err(VerifyError::OldRootMismatch { expected, actual })
+            }
+        }
+    };
+    check_snapshot(primary, hasher, check_root(old_roots.primary))?;
+    check_snapshot(index, hasher, check_root(old_roots.index))?;
+
+    let mut txn = SecondaryIndex::new(
+        Transaction::from_snapshot(primary)?,
+        Transaction::from_snapshot(index)?,
+        attribute,
+    );
+    for op in ops {
+        match op {
+            Op::Get(key_hash) => {
+                txn.get(key_hash)?;
+            }
+            Op::Insert(key_hash, value) => txn.insert(key_hash, value.clone())?,
+            Op::Remove(key_hash) => {
+                txn.remove(key_hash)?;
+            }
+        }
+    }
+
+    let actual = txn.calc_root_hashes(hasher)?;
+    for (expected, actual) in [
+        (new_roots.primary, actual.primary),
+        (new_roots.index, actual.index),
+    ] {
+        if !actual.verify_eq(&expected) {
+            return Err(VerifyError::NewRootMismatch { expected, actual });
+        }
+    }
+    Ok(())
+}
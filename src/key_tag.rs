@@ -0,0 +1,77 @@
+use alloc::format;
+
+use crate::{stored::Store, PortableHash, PortableUpdate, Transaction, TrieError, TrieKey};
+
+/// A short tag of the key a `KeyHash` was hashed from, such as the first bytes of its preimage.
+///
+/// Tags let a trie shared by several key namespaces notice when two different keys hash to the same `KeyHash`.
+/// They are not collision resistant, they only need to differ between the namespaces.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct KeyTag(pub [u8; 8]);
+
+impl KeyTag {
+    /// The first 8 bytes of `preimage`, padded with zeros if it is shorter.
+    #[inline]
+    pub fn from_preimage(preimage: &[u8]) -> Self {
+        let mut tag = [0; 8];
+        let len = preimage.len().min(8);
+        tag[..len].copy_from_slice(&preimage[..len]);
+        KeyTag(tag)
+    }
+}
+
+/// A value stored with the `KeyTag` of its key, see `Transaction::insert_tagged`.
+///
+/// The tag is part of the leaf, so it is hashed before the value and costs 8 bytes per leaf.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeyTagged<V> {
+    pub tag: KeyTag,
+    pub value: V,
+}
+
+impl<V: PortableHash> PortableHash for KeyTagged<V> {
+    #[inline]
+    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
+        self.tag.0.portable_hash(hasher);
+        self.value.portable_hash(hasher);
+    }
+}
+
+impl<S: Store<KeyTagged<V>, K>, V, K: TrieKey> Transaction<S, KeyTagged<V>, K> {
+    /// Insert `value` at `key_hash`, failing if the key already holds a value with a different tag.
+    ///
+    /// A different tag means two keys hashed to the same `KeyHash`,
+    /// which `insert` would silently resolve by overwriting the other key's value.
+    #[inline]
+    pub fn insert_tagged(&mut self, key_hash: &K, tag: KeyTag, value: V) -> Result<(), TrieError> {
+        if let Some(existing) = self.get(key_hash)? {
+            check_tag(key_hash, existing.tag, tag)?;
+        }
+        self.insert(key_hash, KeyTagged { tag, value })
+    }
+
+    /// Get the value at `key_hash`, failing if it was inserted with a different tag.
+    #[inline]
+    pub fn get_tagged(&self, key_hash: &K, tag: KeyTag) -> Result<Option<&V>, TrieError> {
+        match self.get(key_hash)? {
+            Some(existing) => {
+                check_tag(key_hash, existing.tag, tag)?;
+                Ok(Some(&existing.value))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn check_tag<K: TrieKey>(key_hash: &K, existing: KeyTag, tag: KeyTag) -> Result<(), TrieError> {
+    if existing == tag {
+        Ok(())
+    } else {
+        Err(format!(
+            "Key hash collision at {key_hash:?}: it holds a value tagged {existing:?}, not {tag:?}"
+        )
+        .into())
+    }
+}
@@ -0,0 +1,140 @@
+//! Reports on how the keys of a stored trie spread over its branches, for comparing key derivation schemes.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    errors::error_context, stored::DatabaseGet, Node, NodeHash, TrieError, TrieKey, TrieRoot,
+};
+
+/// The nodes at one depth of a trie, see `LayoutReport::depths`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DepthLayout {
+    /// The number of branches at this depth.
+    pub branches: u64,
+    /// The number of leaves at this depth, each proven by this many sibling hashes.
+    pub leaves: u64,
+    /// The key bits the branches at this depth skip, bits every key below a branch agrees on
+    /// between its parent's discriminant bit and its own.
+    ///
+    /// Evenly spread keys skip few bits near the root, a key scheme with fixed or correlated bits skips many.
+    pub skipped_bits: u64,
+}
+
+/// How the keys of a stored trie spread over its branches, from `layout_report`.
+///
+/// With `n` evenly spread keys, as hashed keys are, the branches near the root discriminate on
+/// the first bits of the key, and a leaf is about `log2(n)` branches deep.
+/// Keys that agree on many bits, or cluster in part of the key space, make deeper tries,
+/// and so longer proofs and more hashing per read.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LayoutReport {
+    /// `depths[d]` counts the nodes with `d` branches above them.
+    pub depths: Vec<DepthLayout>,
+    /// `bit_branches[b]` is the number of branches discriminating on key bit `b`, numbered as in `KeyHash::bit`.
+    pub bit_branches: Vec<u64>,
+}
+
+impl Default for LayoutReport {
+    #[inline]
+    fn default() -> Self {
+        LayoutReport {
+            depths: Vec::new(),
+            bit_branches: vec![0; 256],
+        }
+    }
+}
+
+impl LayoutReport {
+    #[inline]
+    pub fn leaf_count(&self) -> u64 {
+        self.depths.iter().map(|depth| depth.leaves).sum()
+    }
+
+    #[inline]
+    pub fn branch_count(&self) -> u64 {
+        self.depths.iter().map(|depth| depth.branches).sum()
+    }
+
+    /// The distribution of proof lengths, as pairs of a number of sibling hashes and how many leaves need that many.
+    #[inline]
+    pub fn proof_lengths(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.depths
+            .iter()
+            .enumerate()
+            .filter(|(_, depth)| depth.leaves != 0)
+            .map(|(len, depth)| (len, depth.leaves))
+    }
+
+    /// The mean number of sibling hashes in a proof of a leaf, or `None` for the empty trie.
+    #[inline]
+    pub fn mean_proof_len(&self) -> Option<f64> {
+        let leaves = self.leaf_count();
+        let total: u64 = self
+            .proof_lengths()
+            .map(|(len, leaves)| len as u64 * leaves)
+            .sum();
+        (leaves != 0).then(|| total as f64 / leaves as f64)
+    }
+
+    /// The most sibling hashes in a proof of a leaf, or `None` for the empty trie.
+    #[inline]
+    pub fn max_proof_len(&self) -> Option<usize> {
+        self.proof_lengths().map(|(len, _)| len).last()
+    }
+
+    /// The most sibling hashes a proof needs in a perfectly balanced trie of the same number of leaves,
+    /// `ceil(log2(leaf_count))`, to compare `max_proof_len` and `mean_proof_len` against.
+    #[inline]
+    pub fn balanced_proof_len(&self) -> u32 {
+        self.leaf_count().next_power_of_two().trailing_zeros()
+    }
+}
+
+/// Walk the trie at `root` in `db`, and report how its keys spread over its branches.
+///
+/// Run it over a trie of production keys, or of keys from a candidate derivation scheme,
+/// to see what proofs of them would cost.
+/// Reads every node of the trie, so it belongs in tooling, not on a hot path.
+#[inline]
+pub fn layout_report<V, K: TrieKey>(
+    db: &impl DatabaseGet<V, K>,
+    root: TrieRoot<NodeHash>,
+) -> Result<LayoutReport, TrieError> {
+    let mut report = LayoutReport::default();
+    // The hash of each node to visit, its depth, and the discriminant bit of its parent.
+    let mut stack = match root {
+        TrieRoot::Node(hash) => vec![(hash, 0, None)],
+        TrieRoot::Empty => Vec::new(),
+    };
+
+    while let Some((hash, depth, parent_bit_idx)) = stack.pop() {
+        let node = db.get(&hash).map_err(|e| {
+            error_context(e, format_args!("Error in `layout_report` getting {hash}"))
+        })?;
+
+        if report.depths.len() <= depth {
+            report.depths.resize(depth + 1, DepthLayout::default());
+        }
+        let layout = &mut report.depths[depth];
+
+        match node {
+            Node::Branch(branch) => {
+                let bit_idx = branch.mask.bit_idx();
+                let first_free_bit =
+                    parent_bit_idx.map_or(0, |parent_bit_idx: u32| parent_bit_idx + 1);
+
+                layout.branches += 1;
+                layout.skipped_bits += bit_idx.saturating_sub(first_free_bit) as u64;
+                if let Some(count) = report.bit_branches.get_mut(bit_idx as usize) {
+                    *count += 1;
+                }
+
+                stack.push((branch.right, depth + 1, Some(bit_idx)));
+                stack.push((branch.left, depth + 1, Some(bit_idx)));
+            }
+            Node::Leaf(_) => layout.leaves += 1,
+        }
+    }
+
+    Ok(report)
+}
@@ -4,52 +4,293 @@
 
 extern crate alloc;
 
-use core::fmt::{Debug, Display};
+use core::{
+    cmp::Ordering,
+    fmt::{Debug, Display},
+};
 
+#[cfg(feature = "test-utils")]
+pub mod alloc_count;
+mod bounded;
+mod builder;
+pub mod casper;
+mod chunked;
+mod compact;
+pub mod counted;
+pub mod cycle_probe;
 mod errors;
+#[cfg(feature = "std")]
+pub mod export;
+pub mod fixture;
 mod hash;
+mod index;
+mod key_tag;
+mod layout;
+mod log;
+mod merge;
+#[cfg(feature = "test-utils")]
+pub mod naive;
+pub mod prelude;
+mod proof;
+mod range_proof;
+pub mod raw;
+mod root_params;
+pub mod self_test;
+mod set;
+pub mod snapshot;
+pub mod spec;
 pub mod stored;
+pub mod stream;
+#[cfg(all(feature = "test-utils", feature = "std"))]
+pub mod testing;
+mod timestamped;
 mod transaction;
+mod transform;
+mod trie;
+mod verify;
+mod walk;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-pub use errors::TrieError;
-pub use hash::{DigestHasher, PortableHash, PortableHasher, PortableUpdate};
+pub use bounded::{decode_value, Bounded, BoundedDecode};
+#[cfg(feature = "std")]
+pub use builder::{FileRun, FileRuns};
+pub use builder::{MemoryRun, MemoryRuns, RunStorage, TrieAccumulator, TrieBuilder};
+pub use chunked::{ChunkConfig, ChunkGet, ChunkProof, ChunkedValue, Chunks};
+pub use compact::{verify_from_bytes, MAX_COMPACT_DEPTH};
+pub use errors::{
+    DecodeError, EncodeError, FlatError, LeakedPlaceholder, NodeSizeError, TrieError, VerifyError,
+};
+#[cfg(feature = "fast-hash")]
+pub use hash::FastHash;
+pub use hash::{
+    DigestHasher, PortableHash, PortableHasher, PortableUpdate, TaggedHasher, TrieParams,
+};
+pub use index::{verify_indexed_batch, IndexEntry, IndexedRoots, SecondaryIndex};
+#[cfg(feature = "derive")]
+pub use kairos_trie_derive::PortableHash;
+pub use key_tag::{KeyTag, KeyTagged};
+pub use layout::{layout_report, DepthLayout, LayoutReport};
+pub use log::{LogLenProof, TrieLog};
+pub use merge::merge_disjoint;
+pub use proof::{DeletionProof, InclusionProof, LeafPath, PrefixRemovalProof, RemovedSubtree};
+pub use range_proof::{prove_range, RangeProof, RangeProofNode};
+pub use root_params::{ParamsRoot, RootParams};
+pub use set::TrieSet;
+pub use timestamped::Timestamped;
 pub use transaction::{
-    nodes::{Branch, Leaf, Node, TrieRoot},
-    Entry, OccupiedEntry, Transaction, VacantEntry, VacantEntryEmptyTrie,
+    nodes::{Branch, BranchMask, Leaf, Node, NodeRef, TrieRoot},
+    overlay::Overlay,
+    CommitEvent, CommitReceipt, Entry, HashCounts, ModifiedShape, OccupiedEntry, ReplicaFailure,
+    ReplicatedCommit, ReplicationMode, SeekNode, SeekResult, StorageUsage, Transaction,
+    VacantEntry, VacantEntryEmptyTrie,
+};
+pub use transform::{Transformed, ValueTransform};
+pub use trie::Trie;
+pub use verify::{
+    verify_batch, verify_batch_probed, verify_batch_truncated, verify_batch_with_params,
+    AuditedBatch, Journal, Op, SnapshotChain,
 };
+pub use walk::{walk, walk_nodes, VisitControl, Visitor};
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct KeyHash(pub [u32; 8]);
 
 impl KeyHash {
+    /// The key with these words, usable in constants, see `key_hash!` for keys written in hex.
     #[inline]
-    pub fn from_bytes(hash_key: &[u8; 32]) -> Self {
+    pub const fn from_words(words: [u32; 8]) -> Self {
+        Self(words)
+    }
+
+    #[inline]
+    pub const fn from_bytes(hash_key: &[u8; 32]) -> Self {
         let mut r = [0; 8];
 
-        hash_key
-            .chunks_exact(4)
-            .enumerate()
-            .for_each(|(i, chunk)| r[i] = u32::from_le_bytes(chunk.try_into().unwrap()));
+        let mut i = 0;
+        while i < r.len() {
+            let offset = i * 4;
+            r[i] = u32::from_le_bytes([
+                hash_key[offset],
+                hash_key[offset + 1],
+                hash_key[offset + 2],
+                hash_key[offset + 3],
+            ]);
+            i += 1;
+        }
 
         Self(r)
     }
 
     #[inline]
-    pub fn to_bytes(&self) -> [u8; 32] {
+    pub const fn to_bytes(&self) -> [u8; 32] {
         let mut r = [0; 32];
 
-        self.0.iter().enumerate().for_each(|(i, &word)| {
-            let [a, b, c, d] = word.to_le_bytes();
+        let mut i = 0;
+        while i < self.0.len() {
+            let [a, b, c, d] = self.0[i].to_le_bytes();
             let offset = i * 4;
             r[offset] = a;
             r[offset + 1] = b;
             r[offset + 2] = c;
             r[offset + 3] = d;
-        });
+            i += 1;
+        }
 
         r
     }
+
+    /// A key that is the little endian expansion of `index`, for trusted key spaces that need no hashing.
+    ///
+    /// Hashing keys spreads them evenly over the trie, which bounds its depth no matter who picks the keys.
+    /// When keys are already unique and fixed-width, and picked by a trusted party such as sequential account indices,
+    /// using them directly saves a hash per key in the guest.
+    ///
+    /// The depth of the trie stays bounded.
+    /// Only the first two words vary, so a path has at most one branch per bit, 64 in all.
+    /// The trie branches on the least significant bit first,
+    /// so a dense range `0..n` makes a balanced trie no deeper than `ceil(log2(n))`.
+    ///
+    /// Never use this for keys an adversary picks.
+    /// Keys sharing long runs of low bits, like `0` and `1 << i` for every `i`, make a path 64 branches deep.
+    #[inline]
+    pub const fn from_u64(index: u64) -> Self {
+        Self([index as u32, (index >> 32) as u32, 0, 0, 0, 0, 0, 0])
+    }
+
+    /// The index of a key made by `from_u64`, or `None` if the key does not fit in a `u64`.
+    #[inline]
+    pub const fn to_u64(&self) -> Option<u64> {
+        match self.0 {
+            [low, high, 0, 0, 0, 0, 0, 0] => Some(low as u64 | (high as u64) << 32),
+            _ => None,
+        }
+    }
+
+    /// The key of entry `index` of a `TrieLog`.
+    ///
+    /// The trie branches on the least significant bit first,
+    /// so the index is stored bit reversed, most significant bit first,
+    /// and trie order is the order entries were appended.
+    /// The newest entry is always the last leaf of the trie.
+    #[inline]
+    pub const fn from_log_index(index: u64) -> Self {
+        let reversed = index.reverse_bits();
+        Self([reversed as u32, (reversed >> 32) as u32, 0, 0, 0, 0, 0, 0])
+    }
+
+    /// The index of a key made by `from_log_index`, or `None` if the key is not a log key.
+    #[inline]
+    pub const fn to_log_index(&self) -> Option<u64> {
+        match self.to_u64() {
+            Some(reversed) => Some(reversed.reverse_bits()),
+            None => None,
+        }
+    }
+
+    /// The index of the word holding bit `bit_idx` of a key, and the index of the bit within that word.
+    ///
+    /// Bits are numbered as `BranchMask::bit_idx` numbers them, word by word,
+    /// and within a word from the least significant bit up.
+    #[inline]
+    pub const fn word_and_bit(bit_idx: u32) -> (usize, u32) {
+        ((bit_idx / 32) as usize, bit_idx % 32)
+    }
+
+    /// Bit `bit_idx` of the key, numbered as in `word_and_bit`.
+    ///
+    /// Below a branch discriminating on `bit_idx`, keys with a 0 bit go left and keys with a 1 bit go right.
+    #[inline]
+    pub const fn bit(&self, bit_idx: u32) -> bool {
+        let (word_idx, bit) = Self::word_and_bit(bit_idx);
+        (self.0[word_idx] >> bit) & 1 == 1
+    }
+
+    /// The 256 bits of the key in the order the trie branches on them, see `bit`.
+    #[inline]
+    pub fn bits(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..256).map(|bit_idx| self.bit(bit_idx))
+    }
+
+    /// Compare two keys in the order the trie stores them.
+    ///
+    /// The trie branches on key bits word by word, and within a word from the least significant bit up.
+    /// Walking the trie from left to right visits keys in this order, which differs from `Ord for KeyHash`.
+    #[inline]
+    pub fn cmp_trie_order(&self, other: &Self) -> Ordering {
+        TrieKey::cmp_trie_order(self, other)
+    }
+}
+
+/// A key the trie can be built over, as a fixed number of 32 bit words.
+///
+/// The trie branches on the bits of a key's words, word by word,
+/// and within a word from the least significant bit up.
+/// A leaf hashes its key as the little endian bytes of its words.
+///
+/// `KeyHash` is the usual key, a 256 bit hash that spreads keys evenly over the trie.
+/// Other encodings, such as 20 byte addresses in 5 words, can be used directly, without hashing or padding.
+///
+/// Every key of a type must have the same number of words, so no key is a prefix of another,
+/// and no more than 8, the 256 bits a `BranchMask` can index.
+pub trait TrieKey: Copy + Eq + Debug {
+    /// The words of the key, in the order the trie branches on them.
+    fn words(&self) -> &[u32];
+
+    /// Compare two keys in the order the trie stores them, see `KeyHash::cmp_trie_order`.
+    #[inline]
+    fn cmp_trie_order(&self, other: &Self) -> Ordering {
+        match self.words().iter().zip(other.words()).find(|(a, b)| a != b) {
+            Some((a, b)) => {
+                let first_diff_bit = (a ^ b).trailing_zeros();
+
+                if (a >> first_diff_bit) & 1 == 0 {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            None => Ordering::Equal,
+        }
+    }
+}
+
+impl TrieKey for KeyHash {
+    #[inline(always)]
+    fn words(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl KeyHash {
+    /// The first and last keys in trie order that start with the first `len_bits` bits of `prefix_bits`.
+    ///
+    /// Bits are numbered in trie order, bit `j` of `prefix_bits[i]` is bit `8 * i + j` of the key,
+    /// where bit 0 is the least significant.
+    /// This matches the byte layout of `KeyHash::from_bytes`.
+    pub(crate) fn prefix_bounds(
+        prefix_bits: &[u8],
+        len_bits: usize,
+    ) -> Result<(Self, Self), TrieError> {
+        if len_bits > 256 || len_bits > prefix_bits.len() * 8 {
+            return Err(alloc::format!(
+                "Invalid key prefix: {len_bits} bits requested from a {} byte prefix",
+                prefix_bits.len()
+            )
+            .into());
+        }
+
+        let mut bytes = [0; 32];
+        let len_bytes = len_bits.div_ceil(8);
+        bytes[..len_bytes].copy_from_slice(&prefix_bits[..len_bytes]);
+        let prefix = Self::from_bytes(&bytes);
+
+        Ok((
+            prefix.prefix_min(len_bits as u32),
+            prefix.prefix_max(len_bits as u32),
+        ))
+    }
 }
 
 impl From<&[u8; 32]> for KeyHash {
@@ -69,7 +310,7 @@ impl From<&KeyHash> for [u8; 32] {
 impl PortableHash for KeyHash {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        self.0.portable_hash(hasher);
+        hasher.portable_update_u32_slice(&self.0);
     }
 }
 
@@ -80,10 +321,37 @@ pub struct NodeHash {
 }
 
 impl NodeHash {
+    /// Usable in constants, see `node_hash!` for hashes written in hex.
     #[inline]
-    pub fn new(bytes: [u8; 32]) -> Self {
+    pub const fn new(bytes: [u8; 32]) -> Self {
         Self { bytes }
     }
+
+    /// Compare two hashes in constant time.
+    #[cfg(feature = "subtle")]
+    #[inline]
+    pub fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        subtle::ConstantTimeEq::ct_eq(&self.bytes[..], &other.bytes[..])
+    }
+
+    /// The equality used by verification helpers.
+    /// Constant time when the `subtle` feature is enabled.
+    #[inline]
+    pub(crate) fn verify_eq(&self, other: &Self) -> bool {
+        #[cfg(feature = "subtle")]
+        return self.ct_eq(other).into();
+
+        #[cfg(not(feature = "subtle"))]
+        return self == other;
+    }
+}
+
+#[cfg(feature = "subtle")]
+impl subtle::ConstantTimeEq for NodeHash {
+    #[inline]
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        NodeHash::ct_eq(self, other)
+    }
 }
 
 impl AsRef<[u8]> for NodeHash {
@@ -97,7 +365,7 @@ impl Display for NodeHash {
     #[inline]
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         // TODO hex
-        write!(f, "NodeHash({:?})", &self.bytes)
+        write!(f, "NodeHash({:?})", self.bytes)
     }
 }
 
@@ -0,0 +1,194 @@
+use alloc::format;
+
+use crate::{
+    stored::{merkle::SnapshotBuilder, DatabaseSet, Store},
+    transaction::nodes::KeyPosition,
+    InclusionProof, KeyHash, NodeHash, PortableHash, PortableHasher, Transaction, TrieError,
+    TrieRoot, VerifyError,
+};
+
+/// A provable append-only log, a `Transaction` whose entries are keyed by `KeyHash::from_log_index`.
+///
+/// Entry `i` is at key `KeyHash::from_log_index(i)`, so the entries are in trie order,
+/// and each append only rewrites the path to the new last leaf.
+/// A path is at most `ceil(log2(len))` branches long,
+/// and the entries after the last power of two are shallower.
+///
+/// The length is not stored, it is one past the index of the last leaf.
+/// Reading it against a `SnapshotBuilder` records the path to the last leaf,
+/// and `prove_len` proves it with that path alone.
+/// Use `transaction` for anything else, such as building a snapshot.
+pub struct TrieLog<S, V> {
+    txn: Transaction<S, V>,
+}
+
+impl<S, V> TrieLog<S, V> {
+    /// The trie must only hold keys from `KeyHash::from_log_index`, with no gaps.
+    #[inline]
+    pub fn new(txn: Transaction<S, V>) -> Self {
+        TrieLog { txn }
+    }
+
+    #[inline]
+    pub fn transaction(&self) -> &Transaction<S, V> {
+        &self.txn
+    }
+
+    #[inline]
+    pub fn into_transaction(self) -> Transaction<S, V> {
+        self.txn
+    }
+}
+
+impl<S, V> From<Transaction<S, V>> for TrieLog<S, V> {
+    #[inline]
+    fn from(txn: Transaction<S, V>) -> Self {
+        Self::new(txn)
+    }
+}
+
+impl<S: Store<V>, V: PortableHash + Clone> TrieLog<S, V> {
+    /// The number of entries, one past the index of the last leaf.
+    #[inline]
+    pub fn len(&self) -> Result<u64, TrieError> {
+        match self.txn.prev_key_before(&KeyHash([u32::MAX; 8]))? {
+            Some((key_hash, _)) => len_through(&key_hash),
+            None => Ok(0),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> Result<bool, TrieError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Add `value` after the last entry, returning its key.
+    #[inline]
+    pub fn append(&mut self, value: V) -> Result<KeyHash, TrieError> {
+        let index = self.len()?;
+        if index == u64::MAX {
+            return Err("Log is full: it holds u64::MAX entries".into());
+        }
+
+        let key_hash = KeyHash::from_log_index(index);
+        self.txn.insert(&key_hash, value)?;
+        Ok(key_hash)
+    }
+
+    #[inline]
+    pub fn get(&self, index: u64) -> Result<Option<&V>, TrieError> {
+        self.txn.get(&KeyHash::from_log_index(index))
+    }
+
+    /// Prove entry `index` is in the log at its current root, see `Transaction::prove_inclusion`.
+    ///
+    /// The proof's leaf is at `KeyHash::from_log_index(index)`, check it before trusting the value.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn prove_get(
+        &self,
+        index: u64,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<Option<InclusionProof<V>>, TrieError> {
+        self.txn
+            .prove_inclusion(&KeyHash::from_log_index(index), hasher)
+    }
+
+    /// Prove the length of the log at its current root.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn prove_len(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<LogLenProof<V>, TrieError> {
+        let last = match self.len()? {
+            0 => None,
+            len => Some(self.prove_get(len - 1, hasher)?.ok_or_else(|| {
+                TrieError::from(format!("Invalid log: entry {} is missing", len - 1))
+            })?),
+        };
+        Ok(LogLenProof { last })
+    }
+
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn calc_root_hash(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        self.txn.calc_root_hash(hasher)
+    }
+}
+
+impl<Db: DatabaseSet<V>, V: PortableHash + Clone> TrieLog<SnapshotBuilder<Db, V>, V> {
+    /// Write the modified nodes to the database, see `Transaction::commit`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        Ok(self.txn.commit(hasher)?.root)
+    }
+}
+
+/// Evidence of the length of a `TrieLog` at some root, produced by `TrieLog::prove_len`.
+///
+/// It is an `InclusionProof` of the last entry.
+/// The path to the last leaf turns right at every branch, so no entry can follow it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LogLenProof<V> {
+    /// The last entry, or `None` for an empty log.
+    pub last: Option<InclusionProof<V>>,
+}
+
+impl<V: PortableHash> LogLenProof<V> {
+    /// Check the proof against `root`, returning the length of the log.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        root: TrieRoot<NodeHash>,
+    ) -> Result<u64, VerifyError> {
+        let Some(last) = &self.last else {
+            if !root.verify_eq(&TrieRoot::Empty) {
+                return Err(VerifyError::OldRootMismatch {
+                    expected: root,
+                    actual: TrieRoot::Empty,
+                });
+            }
+            return Ok(0);
+        };
+
+        last.verify(hasher, root)?;
+        let key_hash = &last.leaf.key_hash;
+        if last
+            .path
+            .iter()
+            .any(|branch| branch.key_position(key_hash) != KeyPosition::Right)
+        {
+            return Err(TrieError::from(
+                "Invalid log length proof: the proven entry is not the last leaf",
+            )
+            .into());
+        }
+        Ok(len_through(key_hash)?)
+    }
+}
+
+/// The length of a log whose last entry is at `key_hash`.
+#[inline]
+fn len_through(key_hash: &KeyHash) -> Result<u64, TrieError> {
+    key_hash
+        .to_log_index()
+        .and_then(|index| index.checked_add(1))
+        .ok_or_else(|| {
+            format!("Invalid log: {key_hash:?} is not the key of an appended entry").into()
+        })
+}
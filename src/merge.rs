@@ -0,0 +1,316 @@
+//! Merging tries whose key sets do not overlap, such as shards consolidated back into one global trie.
+
+use alloc::{boxed::Box, format, vec::Vec};
+use core::{marker::PhantomData, ops::ControlFlow};
+
+use crate::{
+    errors::error_context,
+    stored::{DatabaseGet, DatabaseSet},
+    transaction::{first_diff_bit, nodes::hash_branch},
+    walk_nodes, Branch, BranchMask, Leaf, Node, NodeHash, PortableHasher, TrieError, TrieKey,
+    TrieRoot,
+};
+
+/// Merge the trie at `root_a` in `db_a` with the trie at `root_b` in `db_b`,
+/// writing the merged trie to `db_out` and returning its root.
+///
+/// The two tries must not share a key, sharing one is an error.
+/// The merged trie is the trie a transaction inserting every key of both tries would commit.
+///
+/// Only the branches on the paths where the two tries interleave are rebuilt and rehashed.
+/// A subtree holding keys of only one trie is reused whole, so merging shards that each own
+/// a range of the key space costs a few branches, however many keys they hold.
+/// Every node of the merged trie is written to `db_out`, reused subtrees are copied node by node,
+/// so `db_out` can be a fresh database, or the database both shards already live in.
+#[inline]
+pub fn merge_disjoint<V: Clone, K: TrieKey>(
+    db_a: &impl DatabaseGet<V, K>,
+    root_a: TrieRoot<NodeHash>,
+    db_b: &impl DatabaseGet<V, K>,
+    root_b: TrieRoot<NodeHash>,
+    db_out: &impl DatabaseSet<V, K>,
+    hasher: &mut impl PortableHasher<32>,
+) -> Result<TrieRoot<NodeHash>, TrieError> {
+    let mut merger = Merger {
+        db_a,
+        db_b,
+        db_out,
+        hasher,
+        _marker: PhantomData,
+    };
+
+    match (root_a, root_b) {
+        (TrieRoot::Empty, TrieRoot::Empty) => Ok(TrieRoot::Empty),
+        (TrieRoot::Node(hash), TrieRoot::Empty) => {
+            merger.copy(Side::A, hash)?;
+            Ok(root_a)
+        }
+        (TrieRoot::Empty, TrieRoot::Node(hash)) => {
+            merger.copy(Side::B, hash)?;
+            Ok(root_b)
+        }
+        (TrieRoot::Node(a), TrieRoot::Node(b)) => {
+            let a = merger.load(Side::A, a, &[])?;
+            let b = merger.load(Side::B, b, &[])?;
+            merger.merge(a, b, 0).map(TrieRoot::Node)
+        }
+    }
+}
+
+/// Which of the two tries a node was read from.
+#[derive(Clone, Copy)]
+enum Side {
+    A,
+    B,
+}
+
+/// A node of one of the merged tries, with the key bits shared by every leaf under it.
+struct Subtree<V, K> {
+    side: Side,
+    hash: NodeHash,
+    node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    /// The words of the shared key prefix, only the first `bits` bits are meaningful.
+    words: Vec<u32>,
+    /// How many bits, in trie order, every key under the node shares.
+    /// For a branch this is its discriminant bit, for a leaf the whole key.
+    bits: u32,
+}
+
+struct Merger<'a, A, B, O, H, V, K> {
+    db_a: &'a A,
+    db_b: &'a B,
+    db_out: &'a O,
+    hasher: &'a mut H,
+    _marker: PhantomData<fn() -> (V, K)>,
+}
+
+impl<V, K, A, B, O, H> Merger<'_, A, B, O, H, V, K>
+where
+    V: Clone,
+    K: TrieKey,
+    A: DatabaseGet<V, K>,
+    B: DatabaseGet<V, K>,
+    O: DatabaseSet<V, K>,
+    H: PortableHasher<32>,
+{
+    /// Read a node below a parent whose shared key prefix is `parent_words`.
+    fn load(
+        &self,
+        side: Side,
+        hash: NodeHash,
+        parent_words: &[u32],
+    ) -> Result<Subtree<V, K>, TrieError> {
+        let node = match side {
+            Side::A => self.db_a.get(&hash).map_err(|e| {
+                error_context(e, format_args!("Error getting {hash} from the first trie"))
+            })?,
+            Side::B => self.db_b.get(&hash).map_err(|e| {
+                error_context(e, format_args!("Error getting {hash} from the second trie"))
+            })?,
+        };
+
+        let (words, bits) = match &node {
+            Node::Branch(branch) => {
+                let mut words = parent_words.to_vec();
+                // The left child's prefix is the branch's own, plus its 0 discriminant bit.
+                let bits = branch.child_key_prefix(false, &mut words) - 1;
+                (words, bits)
+            }
+            Node::Leaf(leaf) => {
+                let words = leaf.key_hash.words().to_vec();
+                let bits = words.len() as u32 * 32;
+                (words, bits)
+            }
+        };
+
+        Ok(Subtree {
+            side,
+            hash,
+            node,
+            words,
+            bits,
+        })
+    }
+
+    /// Merge two subtrees into one below a branch on word `parent_word_idx`, and return its hash.
+    fn merge(
+        &mut self,
+        a: Subtree<V, K>,
+        b: Subtree<V, K>,
+        parent_word_idx: usize,
+    ) -> Result<NodeHash, TrieError> {
+        let shared_bits = a.bits.min(b.bits);
+        let diff_bit = first_diff_bit(&a.words, &b.words);
+
+        // The key sets split before either subtree branches, a new branch holds both whole.
+        if diff_bit < shared_bits {
+            let word_idx = (diff_bit / 32) as usize;
+            let mask = BranchMask::new(word_idx as u32, a.words[word_idx], b.words[word_idx]);
+            let (left, right) = if (a.words[word_idx] >> (diff_bit % 32)) & 1 == 0 {
+                (a, b)
+            } else {
+                (b, a)
+            };
+
+            let words = left.words.clone();
+            let left = self.reparent(left, word_idx)?;
+            let right = self.reparent(right, word_idx)?;
+            return self.write_branch(mask, &words, parent_word_idx, left, right);
+        }
+
+        if a.bits == b.bits {
+            let (Node::Branch(branch_a), Node::Branch(branch_b)) = (&a.node, &b.node) else {
+                let Node::Leaf(leaf) = &a.node else {
+                    unreachable!("a branch never shares every bit of a leaf's key")
+                };
+                return Err(format!(
+                    "Cannot merge tries that both contain the key {:?}",
+                    leaf.key_hash
+                )
+                .into());
+            };
+
+            let word_idx = branch_a.mask.word_idx();
+            let left = {
+                let left_a = self.load(a.side, branch_a.left, &a.words)?;
+                let left_b = self.load(b.side, branch_b.left, &b.words)?;
+                self.merge(left_a, left_b, word_idx)?
+            };
+            let right = {
+                let right_a = self.load(a.side, branch_a.right, &a.words)?;
+                let right_b = self.load(b.side, branch_b.right, &b.words)?;
+                self.merge(right_a, right_b, word_idx)?
+            };
+            return self.write_branch(branch_a.mask, &a.words, parent_word_idx, left, right);
+        }
+
+        let (outer, inner) = if a.bits < b.bits { (a, b) } else { (b, a) };
+        self.merge_into(outer, inner, parent_word_idx)
+    }
+
+    /// Merge `inner` into the child of the branch `outer` its keys belong under,
+    /// reusing the other child as it is.
+    fn merge_into(
+        &mut self,
+        outer: Subtree<V, K>,
+        inner: Subtree<V, K>,
+        parent_word_idx: usize,
+    ) -> Result<NodeHash, TrieError> {
+        let Node::Branch(branch) = &outer.node else {
+            unreachable!("only a branch has fewer shared bits than another node")
+        };
+        let word_idx = branch.mask.word_idx();
+        let goes_right = (inner.words[word_idx] >> (branch.mask.bit_idx() % 32)) & 1 == 1;
+
+        let (left, right) = if goes_right {
+            self.copy(outer.side, branch.left)?;
+            let right = self.load(outer.side, branch.right, &outer.words)?;
+            (branch.left, self.merge(right, inner, word_idx)?)
+        } else {
+            self.copy(outer.side, branch.right)?;
+            let left = self.load(outer.side, branch.left, &outer.words)?;
+            (self.merge(left, inner, word_idx)?, branch.right)
+        };
+
+        self.write_branch(branch.mask, &outer.words, parent_word_idx, left, right)
+    }
+
+    /// Place a whole subtree below a new branch on word `parent_word_idx`, and return its hash.
+    ///
+    /// A branch's prefix runs from its parent's word, so only the top node may change.
+    fn reparent(
+        &mut self,
+        subtree: Subtree<V, K>,
+        parent_word_idx: usize,
+    ) -> Result<NodeHash, TrieError> {
+        let branch = match &subtree.node {
+            Node::Branch(branch)
+                if *branch.prefix != *prefix_of(&subtree.words, branch.mask, parent_word_idx) =>
+            {
+                branch
+            }
+            _ => {
+                self.copy(subtree.side, subtree.hash)?;
+                return Ok(subtree.hash);
+            }
+        };
+
+        self.copy(subtree.side, branch.left)?;
+        self.copy(subtree.side, branch.right)?;
+        self.write_branch(
+            branch.mask,
+            &subtree.words,
+            parent_word_idx,
+            branch.left,
+            branch.right,
+        )
+    }
+
+    /// Hash and store a branch whose shared key prefix is `words`.
+    fn write_branch(
+        &mut self,
+        mask: BranchMask,
+        words: &[u32],
+        parent_word_idx: usize,
+        left: NodeHash,
+        right: NodeHash,
+    ) -> Result<NodeHash, TrieError> {
+        let word_idx = mask.word_idx();
+        let branch = Branch {
+            left,
+            right,
+            mask,
+            prior_word: if word_idx == 0 {
+                0
+            } else {
+                words[word_idx - 1]
+            },
+            prefix: prefix_of(words, mask, parent_word_idx),
+        };
+
+        let hash = hash_branch(
+            self.hasher,
+            &branch.mask,
+            branch.prior_word,
+            &branch.prefix,
+            &left,
+            &right,
+        );
+        self.db_out
+            .set(hash, Node::Branch(branch))
+            .map_err(|e| error_context(e, format_args!("Error writing merged branch {hash}")))?;
+        Ok(hash)
+    }
+
+    /// Copy the subtree at `hash` to the output database unchanged.
+    fn copy(&self, side: Side, hash: NodeHash) -> Result<(), TrieError> {
+        let mut result = Ok(());
+        let mut sink = |hash: NodeHash, node: &Node<Branch<NodeHash>, Leaf<V, K>>| match self
+            .db_out
+            .set(hash, node.clone())
+        {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                result = Err(error_context(e, format_args!("Error copying {hash}")));
+                ControlFlow::Break(())
+            }
+        };
+
+        // The walk only breaks on a write error, which is in `result`.
+        let _: ControlFlow<()> = match side {
+            Side::A => walk_nodes(self.db_a, TrieRoot::Node(hash), &mut sink)?,
+            Side::B => walk_nodes(self.db_b, TrieRoot::Node(hash), &mut sink)?,
+        };
+        result
+    }
+}
+
+/// The prefix words of a branch on `mask` below a branch on word `parent_word_idx`.
+fn prefix_of(words: &[u32], mask: BranchMask, parent_word_idx: usize) -> Box<[u32]> {
+    let word_idx = mask.word_idx();
+    if parent_word_idx + 1 < word_idx {
+        words[parent_word_idx..word_idx - 1].into()
+    } else {
+        Box::default()
+    }
+}
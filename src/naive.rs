@@ -0,0 +1,179 @@
+//! A deliberately simple Merkle map computing the same root hash as the trie, for differential testing.
+//!
+//! `NaiveMerkleMap` keeps its entries in a sorted `Vec` and rebuilds the root hash from scratch on every call,
+//! spelling out each byte that goes into a node's hash.
+//! It shares no hashing or branching code with the trie,
+//! so a test comparing the two checks the trie against an independent statement of its hash definition.
+
+use alloc::vec::Vec;
+
+use crate::{KeyHash, NodeHash, PortableHash, PortableHasher, TrieKey, TrieRoot};
+
+/// A map from keys to values whose `root_hash` equals the root of a trie holding the same entries.
+///
+/// Every operation is linear in the number of entries, use it as an oracle for tests only.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NaiveMerkleMap<V, K = KeyHash> {
+    /// Sorted by `TrieKey::cmp_trie_order`, without duplicate keys.
+    entries: Vec<(K, V)>,
+}
+
+impl<V, K> Default for NaiveMerkleMap<V, K> {
+    #[inline]
+    fn default() -> Self {
+        NaiveMerkleMap {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<V, K: TrieKey> NaiveMerkleMap<V, K> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    fn search(&self, key: &K) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|(k, _)| k.cmp_trie_order(key))
+    }
+
+    #[inline]
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.search(key).ok().map(|idx| &self.entries[idx].1)
+    }
+
+    /// Insert `value` at `key`, returning the value it replaces.
+    #[inline]
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.search(&key) {
+            Ok(idx) => Some(core::mem::replace(&mut self.entries[idx].1, value)),
+            Err(idx) => {
+                self.entries.insert(idx, (key, value));
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let idx = self.search(key).ok()?;
+        Some(self.entries.remove(idx).1)
+    }
+
+    /// The entries in trie order.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+}
+
+impl<V: PortableHash, K: TrieKey> NaiveMerkleMap<V, K> {
+    /// The root hash of a trie holding exactly these entries.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn root_hash<H: PortableHasher<32>>(&self, hasher: &mut H) -> TrieRoot<NodeHash> {
+        if self.entries.is_empty() {
+            TrieRoot::Empty
+        } else {
+            TrieRoot::Node(hash_subtree(hasher, &self.entries, 0))
+        }
+    }
+}
+
+impl<V, K: TrieKey> FromIterator<(K, V)> for NaiveMerkleMap<V, K> {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+/// Hash the subtree holding `entries`, which must be non-empty and sorted in trie order.
+///
+/// `parent_word_idx` is the word of the parent branch's discriminant bit, or 0 at the root.
+fn hash_subtree<V: PortableHash, K: TrieKey, H: PortableHasher<32>>(
+    hasher: &mut H,
+    entries: &[(K, V)],
+    parent_word_idx: usize,
+) -> NodeHash {
+    // A leaf hashes its key's words, little endian, then its value.
+    let [first, .., last] = entries else {
+        let (key, value) = &entries[0];
+        if !H::LEAF_TAG.is_empty() {
+            hasher.portable_update(H::LEAF_TAG);
+        }
+        for word in key.words() {
+            hasher.portable_update(word.to_le_bytes());
+        }
+        value.portable_hash(hasher);
+        return finish(hasher, 1);
+    };
+
+    // Sorted in trie order, the first and last keys first differ at the first bit any two keys differ at.
+    // Bits are numbered word by word, least significant bit first.
+    let (first, last) = (first.0.words(), last.0.words());
+    let word_idx = (0..first.len())
+        .find(|&i| first[i] != last[i])
+        .expect("NaiveMerkleMap keys are unique");
+    let bit = (first[word_idx] ^ last[word_idx]).trailing_zeros();
+
+    let bit_idx = word_idx as u32 * 32 + bit;
+    // The bits of the discriminant word below the discriminant bit, which every key shares.
+    let left_prefix = first[word_idx] & ((1 << bit) - 1);
+    let prior_word = if word_idx == 0 {
+        0
+    } else {
+        first[word_idx - 1]
+    };
+    // The whole words between the parent's discriminant word, inclusive, and `prior_word`, exclusive.
+    let prefix = if parent_word_idx + 1 < word_idx {
+        &first[parent_word_idx..word_idx - 1]
+    } else {
+        &[]
+    };
+
+    // Keys with a 0 discriminant bit go left, and sort before those with a 1.
+    let split = entries.partition_point(|(key, _)| (key.words()[word_idx] >> bit) & 1 == 0);
+    let left = hash_subtree(hasher, &entries[..split], word_idx);
+    let right = hash_subtree(hasher, &entries[split..], word_idx);
+
+    // A branch hashes its children's hashes, then its discriminant, then the key bits it covers.
+    if !H::BRANCH_TAG.is_empty() {
+        hasher.portable_update(H::BRANCH_TAG);
+    }
+    hasher.portable_update(left.bytes);
+    hasher.portable_update(right.bytes);
+    hasher.portable_update(bit_idx.to_le_bytes());
+    hasher.portable_update(left_prefix.to_le_bytes());
+    hasher.portable_update(prior_word.to_le_bytes());
+    for word in prefix {
+        hasher.portable_update(word.to_le_bytes());
+    }
+    finish(hasher, entries.len())
+}
+
+/// The digest, with its last 4 bytes replaced by the number of leaves, little endian, in counted mode.
+fn finish<H: PortableHasher<32>>(hasher: &mut H, leaves: usize) -> NodeHash {
+    let mut hash = hasher.finalize_reset();
+    if H::COUNTED {
+        let leaves = u32::try_from(leaves).unwrap_or(u32::MAX);
+        hash[28..].copy_from_slice(&leaves.to_le_bytes());
+    }
+    NodeHash::new(hash)
+}
@@ -0,0 +1,15 @@
+//! The types and traits most users need, for a glob import.
+//!
+//! ```
+//! use kairos_trie::prelude::*;
+//! ```
+
+pub use crate::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+        DatabaseGet, DatabaseSet, Store,
+    },
+    DigestHasher, KeyHash, NodeHash, PortableHash, PortableHasher, Transaction, Trie, TrieError,
+    TrieRoot,
+};
@@ -0,0 +1,324 @@
+use alloc::{boxed::Box, format, vec::Vec};
+
+use crate::{
+    transaction::nodes::{Branch, KeyPosition, Leaf, PrefixSide, TrieRoot},
+    KeyHash, NodeHash, PortableHash, PortableHasher, TrieError, TrieKey, VerifyError,
+};
+
+/// Evidence that a leaf was removed between two roots, produced by `Transaction::remove_with_proof`.
+///
+/// It proves the leaf was included under the old root,
+/// and that removing it and promoting its sibling yields the new root.
+/// Verifying it requires nothing but the two roots.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DeletionProof<V, K = KeyHash> {
+    /// The branches from the root down to the removed leaf's parent, under the old root.
+    pub path: Box<[Branch<NodeHash>]>,
+    /// The removed leaf.
+    pub leaf: Leaf<V, K>,
+    /// The removed leaf's sibling, if it is a branch that needs a new prefix to replace its parent.
+    pub sibling: Option<Branch<NodeHash>>,
+}
+
+impl<V: PortableHash, K: TrieKey> DeletionProof<V, K> {
+    /// Check that `leaf` is in the trie at `old_root`, and that removing it yields `new_root`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        old_root: TrieRoot<NodeHash>,
+        new_root: TrieRoot<NodeHash>,
+    ) -> Result<(), VerifyError> {
+        let (hash, go_right) = leaf_path_root(hasher, &self.path, &self.leaf, "deletion")?;
+
+        let actual = TrieRoot::Node(hash);
+        if !actual.verify_eq(&old_root) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: old_root,
+                actual,
+            });
+        }
+
+        // The new root has the leaf's sibling in place of its parent.
+        let actual = root_without_child(
+            hasher,
+            &self.path,
+            &go_right,
+            self.sibling.as_ref(),
+            "deletion",
+        )?;
+
+        if !actual.verify_eq(&new_root) {
+            return Err(VerifyError::NewRootMismatch {
+                expected: new_root,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The subtree removed by `Transaction::remove_prefix_with_proof`, the root node of every key under the prefix.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RemovedSubtree<V> {
+    /// A branch discriminating on a bit after the prefix, with its children's hashes.
+    Branch(Branch<NodeHash>),
+    /// The only leaf starting with the prefix.
+    Leaf(Leaf<V>),
+}
+
+/// Evidence that every key starting with a prefix was removed between two roots, and nothing else changed,
+/// produced by `Transaction::remove_prefix_with_proof`.
+///
+/// The keys under a prefix are one subtree, so the proof is the path down to it and its root node,
+/// whatever the number of keys removed.
+/// Verifying it requires nothing but the two roots.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PrefixRemovalProof<V> {
+    /// The branches from the root down to the removed subtree's parent, under the old root.
+    pub path: Box<[Branch<NodeHash>]>,
+    pub removed: RemovedSubtree<V>,
+    /// The removed subtree's sibling, if it is a branch that needs a new prefix to replace its parent.
+    pub sibling: Option<Branch<NodeHash>>,
+}
+
+impl<V: PortableHash> PrefixRemovalProof<V> {
+    /// Check that `removed` holds exactly the keys at `old_root` starting with the first `len_bits` bits of `prefix_bits`,
+    /// numbered as in `Transaction::keys_with_prefix`, and that removing it yields `new_root`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        old_root: TrieRoot<NodeHash>,
+        new_root: TrieRoot<NodeHash>,
+        prefix_bits: &[u8],
+        len_bits: usize,
+    ) -> Result<(), VerifyError> {
+        let (min, max) = KeyHash::prefix_bounds(prefix_bits, len_bits)?;
+
+        let mut hash = match &self.removed {
+            RemovedSubtree::Leaf(leaf) => {
+                if !leaf.key_hash.matches_prefix(&min, len_bits as u32) {
+                    return Err(TrieError::from(
+                        "Invalid prefix removal proof: the removed leaf does not start with the prefix",
+                    )
+                    .into());
+                }
+                leaf.hash_leaf(hasher)
+            }
+            RemovedSubtree::Branch(branch) => {
+                branch.check_invariants()?;
+                if branch.prefix_side(&min, &max, len_bits) != PrefixSide::Inside {
+                    return Err(TrieError::from(
+                        "Invalid prefix removal proof: the removed branch holds keys outside the prefix",
+                    )
+                    .into());
+                }
+                branch.hash_branch(hasher, &branch.left, &branch.right)
+            }
+        };
+
+        // Every branch on the path discriminates on a bit of the prefix, so the removed subtree holds all its keys.
+        let mut go_right = Vec::with_capacity(self.path.len());
+        for branch in self.path.iter() {
+            branch.check_invariants()?;
+            match branch.prefix_side(&min, &max, len_bits) {
+                PrefixSide::Descend { go_right: right } => go_right.push(right),
+                PrefixSide::Inside | PrefixSide::Outside => {
+                    return Err(TrieError::from(
+                        "Invalid prefix removal proof: the path does not follow the prefix",
+                    )
+                    .into())
+                }
+            }
+        }
+
+        for (branch, &go_right) in self.path.iter().zip(go_right.iter()).rev() {
+            let child = if go_right {
+                &branch.right
+            } else {
+                &branch.left
+            };
+            if *child != hash {
+                return Err(TrieError::from(
+                    "Invalid prefix removal proof: a branch does not commit to its child on the path",
+                )
+                .into());
+            }
+            hash = branch.hash_branch(hasher, &branch.left, &branch.right);
+        }
+
+        let actual = TrieRoot::Node(hash);
+        if !actual.verify_eq(&old_root) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: old_root,
+                actual,
+            });
+        }
+
+        let actual = root_without_child(
+            hasher,
+            &self.path,
+            &go_right,
+            self.sibling.as_ref(),
+            "prefix removal",
+        )?;
+        if !actual.verify_eq(&new_root) {
+            return Err(VerifyError::NewRootMismatch {
+                expected: new_root,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Evidence that a leaf is in the trie at some root, produced by `RootArchive`.
+///
+/// Verifying it requires nothing but the root.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InclusionProof<V, K = KeyHash> {
+    /// The branches from the root down to the leaf's parent.
+    pub path: Box<[Branch<NodeHash>]>,
+    /// The included leaf.
+    pub leaf: Leaf<V, K>,
+}
+
+impl<V: PortableHash, K: TrieKey> InclusionProof<V, K> {
+    /// Check that `leaf` is in the trie at `root`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        root: TrieRoot<NodeHash>,
+    ) -> Result<(), VerifyError> {
+        let (hash, _) = leaf_path_root(hasher, &self.path, &self.leaf, "inclusion")?;
+
+        let actual = TrieRoot::Node(hash);
+        if !actual.verify_eq(&root) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: root,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The turns from the root to a leaf of a transaction, from `Entry::or_insert_with_proof`.
+///
+/// `Transaction::prove_leaf_path` follows it to prove the leaf without searching for its key again.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LeafPath<K = KeyHash> {
+    /// The key of the leaf at the end of the path.
+    pub key_hash: K,
+    /// Whether the path turns right at each branch, from the root down.
+    pub turns: Vec<bool>,
+}
+
+/// Hash `leaf` up through `path`, checking each branch leads to the leaf's key and commits to the child below it.
+///
+/// Returns the hash of the first branch, or of the leaf if the path is empty,
+/// along with whether the path turns right at each branch.
+#[inline]
+fn leaf_path_root<V: PortableHash, K: TrieKey>(
+    hasher: &mut impl PortableHasher<32>,
+    path: &[Branch<NodeHash>],
+    leaf: &Leaf<V, K>,
+    proof_kind: &str,
+) -> Result<(NodeHash, Vec<bool>), TrieError> {
+    let mut go_right = Vec::with_capacity(path.len());
+    for branch in path.iter() {
+        match branch.key_position(&leaf.key_hash) {
+            KeyPosition::Left => go_right.push(false),
+            KeyPosition::Right => go_right.push(true),
+            KeyPosition::Adjacent(_) => {
+                return Err(format!(
+                    "Invalid {proof_kind} proof: the path does not lead to the proven key"
+                )
+                .into())
+            }
+        }
+    }
+
+    let mut hash = leaf.hash_leaf(hasher);
+    for (branch, &go_right) in path.iter().zip(go_right.iter()).rev() {
+        let child = if go_right {
+            &branch.right
+        } else {
+            &branch.left
+        };
+        if *child != hash {
+            return Err(format!(
+                "Invalid {proof_kind} proof: a branch does not commit to its child on the path"
+            )
+            .into());
+        }
+
+        hash = branch.hash_branch(hasher, &branch.left, &branch.right);
+    }
+
+    Ok((hash, go_right))
+}
+
+/// The root after removing the child at the end of `path`, promoting its sibling in place of its parent.
+///
+/// `sibling` is the sibling with its children's hashes, if it is a branch that needs its parent's prefix.
+#[inline]
+fn root_without_child(
+    hasher: &mut impl PortableHasher<32>,
+    path: &[Branch<NodeHash>],
+    go_right: &[bool],
+    sibling: Option<&Branch<NodeHash>>,
+    proof_kind: &str,
+) -> Result<TrieRoot<NodeHash>, TrieError> {
+    let Some((parent, ancestors)) = path.split_last() else {
+        return Ok(TrieRoot::Empty);
+    };
+
+    let grandparent_word_idx = ancestors.last().map_or(0, |b| b.mask.word_idx());
+    let sibling_hash = if go_right[ancestors.len()] {
+        parent.left
+    } else {
+        parent.right
+    };
+
+    let mut hash = match sibling {
+        Some(sibling) => {
+            if sibling.hash_branch(hasher, &sibling.left, &sibling.right) != sibling_hash {
+                return Err(format!(
+                    "Invalid {proof_kind} proof: the sibling does not match its parent"
+                )
+                .into());
+            }
+
+            let mut sibling = sibling.clone();
+            sibling.absorb_parent_prefix(parent, grandparent_word_idx);
+            sibling.hash_branch(hasher, &sibling.left, &sibling.right)
+        }
+        None => sibling_hash,
+    };
+
+    for (branch, &go_right) in ancestors.iter().zip(go_right.iter()).rev() {
+        hash = if go_right {
+            branch.hash_branch(hasher, &branch.left, &hash)
+        } else {
+            branch.hash_branch(hasher, &hash, &branch.right)
+        };
+    }
+
+    Ok(TrieRoot::Node(hash))
+}
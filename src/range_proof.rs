@@ -0,0 +1,215 @@
+use alloc::{boxed::Box, format, vec::Vec};
+use core::ops::RangeInclusive;
+
+use crate::{
+    errors::error_context,
+    stored::{Idx, Store},
+    transaction::nodes::{key_bits, KeyRange, Node},
+    Branch, KeyHash, Leaf, NodeHash, PortableHash, PortableHasher, TrieError, TrieKey, TrieRoot,
+    VerifyError,
+};
+
+/// A node of a `RangeProof`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RangeProofNode<V, K = KeyHash> {
+    /// A branch the range reaches, followed by its left subtree, then its right subtree.
+    Branch(Branch<()>),
+    /// A leaf the range reaches, in the range or bounding it.
+    Leaf(Leaf<V, K>),
+    /// A subtree with no key in the range, by hash.
+    Pruned(NodeHash),
+}
+
+/// Evidence that a set of leaves is every leaf with a key in a range, produced by `prove_range`.
+///
+/// The nodes are the subtree the range reaches, in depth first order, with the subtrees outside it pruned.
+/// Each subtree the range reaches must be expanded down to its leaves,
+/// so a leaf in the range cannot be left out without changing the root.
+/// The leaves bounding the range, just outside it, show where the range ends.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RangeProof<V, K = KeyHash> {
+    pub nodes: Box<[RangeProofNode<V, K>]>,
+}
+
+/// Prove which leaves of the trie at `root` in `store` have a key in `range`.
+///
+/// The bounds of `range` are compared with `TrieKey::cmp_trie_order`, not `Ord`.
+/// The range's subtree must be in `store`, an unvisited node of a `Snapshot` in it is an error.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn prove_range<V: PortableHash + Clone, K: TrieKey, S: Store<V, K>>(
+    store: &S,
+    root: TrieRoot<Idx>,
+    range: RangeInclusive<K>,
+    hasher: &mut impl PortableHasher<32>,
+) -> Result<RangeProof<V, K>, TrieError> {
+    let mut nodes = Vec::new();
+    if let TrieRoot::Node(idx) = root {
+        let key_bits = key_bits(range.start());
+        prove_node(
+            store,
+            idx,
+            root_range(&range),
+            (None, key_bits),
+            hasher,
+            &mut nodes,
+        )?;
+    }
+    Ok(RangeProof {
+        nodes: nodes.into_boxed_slice(),
+    })
+}
+
+/// The range to search from the root, or `None` if it is empty.
+#[inline]
+fn root_range<K: TrieKey>(range: &RangeInclusive<K>) -> Option<KeyRange<'_, K>> {
+    range
+        .start()
+        .cmp_trie_order(range.end())
+        .is_le()
+        .then_some(KeyRange {
+            start: Some(range.start()),
+            end: Some(range.end()),
+        })
+}
+
+/// Push the proof of the subtree at `idx`, whose parent discriminates on `parent_bit_idx`, to `nodes`.
+///
+/// Stored branches must discriminate on a later bit than their parent, below `key_bits`,
+/// so it recurses at most `key_bits` branches deep.
+#[inline]
+fn prove_node<V: PortableHash + Clone, K: TrieKey, S: Store<V, K>>(
+    store: &S,
+    idx: Idx,
+    range: Option<KeyRange<K>>,
+    (parent_bit_idx, key_bits): (Option<u32>, u32),
+    hasher: &mut impl PortableHasher<32>,
+    nodes: &mut Vec<RangeProofNode<V, K>>,
+) -> Result<(), TrieError> {
+    let Some(range) = range else {
+        let hash = store
+            .calc_subtree_hash(hasher, idx)
+            .map_err(|e| error_context(e, "Error in `prove_range`"))?;
+        nodes.push(RangeProofNode::Pruned(hash));
+        return Ok(());
+    };
+
+    match store
+        .get_node(idx)
+        .map_err(|e| error_context(e, "Error in `prove_range`"))?
+    {
+        Node::Branch(branch) => {
+            branch
+                .check_bit_order(parent_bit_idx, key_bits)
+                .map_err(|e| {
+                    error_context(e, format_args!("Error in `prove_range` at branch {idx}"))
+                })?;
+            nodes.push(RangeProofNode::Branch(branch.with_children((), ())));
+            let (left, right) = range.split(branch);
+            let (left_idx, right_idx) = (branch.left, branch.right);
+            let bits = (Some(branch.mask.bit_idx()), key_bits);
+            prove_node(store, left_idx, left, bits, hasher, nodes)?;
+            prove_node(store, right_idx, right, bits, hasher, nodes)
+        }
+        Node::Leaf(leaf) => {
+            nodes.push(RangeProofNode::Leaf(leaf.clone()));
+            Ok(())
+        }
+    }
+}
+
+impl<V: PortableHash, K: TrieKey> RangeProof<V, K> {
+    /// Check the proof against `root`, returning every entry with a key in `range`,
+    /// ordered by `TrieKey::cmp_trie_order`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        root: TrieRoot<NodeHash>,
+        range: RangeInclusive<K>,
+    ) -> Result<Vec<(K, &V)>, VerifyError> {
+        let mut entries = Vec::new();
+        let actual = if self.nodes.is_empty() {
+            TrieRoot::Empty
+        } else {
+            let mut pos = 0;
+            let hash =
+                self.verify_node(&mut pos, root_range(&range), None, hasher, &mut entries)?;
+            if pos != self.nodes.len() {
+                return Err(TrieError::from(format!(
+                    "Invalid range proof: {} nodes after the root's subtree",
+                    self.nodes.len() - pos
+                ))
+                .into());
+            }
+            TrieRoot::Node(hash)
+        };
+
+        if !actual.verify_eq(&root) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: root,
+                actual,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Hash the subtree starting at `nodes[*pos]`, whose parent discriminates on `parent_bit_idx`.
+    ///
+    /// Discriminant bits must grow down the proof, so it recurses at most 256 branches deep.
+    #[inline]
+    fn verify_node<'p>(
+        &'p self,
+        pos: &mut usize,
+        range: Option<KeyRange<K>>,
+        parent_bit_idx: Option<u32>,
+        hasher: &mut impl PortableHasher<32>,
+        entries: &mut Vec<(K, &'p V)>,
+    ) -> Result<NodeHash, TrieError> {
+        let Some(node) = self.nodes.get(*pos) else {
+            return Err("Invalid range proof: the proof ends inside a branch".into());
+        };
+        *pos += 1;
+
+        match (node, range) {
+            (RangeProofNode::Pruned(hash), None) => Ok(*hash),
+            (RangeProofNode::Pruned(_), Some(_)) => Err(format!(
+                "Invalid range proof: node {} is pruned, but the range reaches it",
+                *pos - 1
+            )
+            .into()),
+            (_, None) => Err(format!(
+                "Invalid range proof: node {} is outside the range, but not pruned",
+                *pos - 1
+            )
+            .into()),
+            (RangeProofNode::Leaf(leaf), Some(range)) => {
+                if range.contains(&leaf.key_hash) {
+                    entries.push((leaf.key_hash, &leaf.value));
+                }
+                Ok(leaf.hash_leaf(hasher))
+            }
+            (RangeProofNode::Branch(branch), Some(range)) => {
+                branch.check_invariants()?;
+                let bit_idx = branch.mask.bit_idx();
+                if parent_bit_idx.is_some_and(|parent_bit_idx| bit_idx <= parent_bit_idx) {
+                    return Err(format!(
+                        "Invalid range proof: branch {} does not discriminate on a bit after its parent's",
+                        *pos - 1
+                    )
+                    .into());
+                }
+
+                let (left_range, right_range) = range.split(branch);
+                let left = self.verify_node(pos, left_range, Some(bit_idx), hasher, entries)?;
+                let right = self.verify_node(pos, right_range, Some(bit_idx), hasher, entries)?;
+                Ok(branch.hash_branch(hasher, &left, &right))
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Read-only access to the arrays of a `Snapshot`, for provers writing their own circuit verifiers.
+//!
+//! A snapshot is three arrays: branches, leaves, and the hashes of unvisited nodes,
+//! the subtrees the transaction never read.
+//! Nodes are addressed by a single `Idx` counting the branches, then the leaves, then the unvisited nodes:
+//!
+//! | `Idx`                                              | node                                       |
+//! |----------------------------------------------------|--------------------------------------------|
+//! | `0..branches`                                      | `branches[idx]`                            |
+//! | `branches..branches + leaves`                      | `leaves[idx - branches]`                   |
+//! | `branches + leaves..branches + leaves + unvisited` | `unvisited_nodes[idx - branches - leaves]` |
+//!
+//! A branch's `left` and `right` are `Idx`s in this space.
+//! The root is the last branch.
+//! A snapshot without branches has at most one node, its root at `Idx` 0, and a snapshot with no nodes is of the empty trie.
+//!
+//! `SnapshotBuilder` writes the nodes in post-order, children before their parents and left subtrees before right ones,
+//! so the leaves and unvisited nodes are in trie order, and a branch comes after the branches below it.
+//! A snapshot from an untrusted prover need not be ordered, only the references between nodes are meaningful.
+//! `Snapshot::check_canonical` and `Snapshot::check_limits` are the checks a verifier runs on the nodes themselves.
+//!
+//! A node's hash is specified in `spec`, an unvisited node's hash is taken as given.
+
+use crate::{
+    stored::{
+        merkle::{NodeIdx, Snapshot},
+        Idx,
+    },
+    Branch, KeyHash, Leaf, NodeHash, TrieError, TrieRoot,
+};
+
+/// A node of a `RawSnapshot`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RawNode<'s, V, K = KeyHash> {
+    Branch(&'s Branch<Idx>),
+    Leaf(&'s Leaf<V, K>),
+    Unvisited(&'s NodeHash),
+}
+
+/// The arrays of a `Snapshot`, see the module documentation for how they refer to each other.
+#[derive(Debug)]
+pub struct RawSnapshot<'s, V, K = KeyHash> {
+    snapshot: &'s Snapshot<V, K>,
+}
+
+impl<V, K> Clone for RawSnapshot<'_, V, K> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<V, K> Copy for RawSnapshot<'_, V, K> {}
+
+impl<'s, V, K> RawSnapshot<'s, V, K> {
+    #[inline]
+    pub fn new(snapshot: &'s Snapshot<V, K>) -> Self {
+        RawSnapshot { snapshot }
+    }
+
+    #[inline]
+    pub fn branches(&self) -> &'s [Branch<Idx>] {
+        self.snapshot.arrays().0
+    }
+
+    #[inline]
+    pub fn leaves(&self) -> &'s [Leaf<V, K>] {
+        self.snapshot.arrays().1
+    }
+
+    #[inline]
+    pub fn unvisited_nodes(&self) -> &'s [NodeHash] {
+        self.snapshot.arrays().2
+    }
+
+    /// The `Idx` of the first leaf, the number of branches.
+    #[inline]
+    pub fn leaf_offset(&self) -> Idx {
+        self.branches().len() as Idx
+    }
+
+    /// The `Idx` of the first unvisited node, the number of branches and leaves.
+    #[inline]
+    pub fn unvisited_offset(&self) -> Idx {
+        self.leaf_offset() + self.leaves().len() as Idx
+    }
+
+    /// The `Idx` of the root, failing if the arrays have no root, see the module documentation.
+    #[inline]
+    pub fn root(&self) -> Result<TrieRoot<Idx>, TrieError> {
+        self.snapshot.root_node_idx()
+    }
+
+    /// The node at `idx`, or `None` if `idx` is past the last unvisited node.
+    #[inline]
+    pub fn node(&self, idx: Idx) -> Option<RawNode<'s, V, K>> {
+        let (branches, leaves, unvisited_nodes) = self.snapshot.arrays();
+        Some(match self.snapshot.node_idx(idx).ok()? {
+            NodeIdx::Branch(idx) => RawNode::Branch(&branches[idx.0 as usize]),
+            NodeIdx::Leaf(idx) => RawNode::Leaf(&leaves[idx.0 as usize]),
+            NodeIdx::Unvisited(idx) => RawNode::Unvisited(&unvisited_nodes[idx.0 as usize]),
+        })
+    }
+}
+
+impl<'s, V, K> From<&'s Snapshot<V, K>> for RawSnapshot<'s, V, K> {
+    #[inline]
+    fn from(snapshot: &'s Snapshot<V, K>) -> Self {
+        Self::new(snapshot)
+    }
+}
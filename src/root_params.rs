@@ -0,0 +1,122 @@
+use alloc::format;
+
+use crate::{
+    NodeHash, PortableHash, PortableHasher, PortableUpdate, TrieError, TrieRoot, VerifyError,
+};
+
+/// The parameters a root was computed under, stored alongside it in a `ParamsRoot`.
+///
+/// A root on its own does not say which hash function or version of the hashing rules produced it.
+/// A verifier built for other parameters would reject an honest witness with a confusing root mismatch,
+/// or worse, if two schemes ever agreed on some hashes, accept a witness built for a different trie.
+/// Checking the parameters first turns both into a `VerifyError::ParamsMismatch`.
+///
+/// Domain separation tags and counted mode are not recorded here, they are part of the hasher's type, see `TrieParams`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct RootParams {
+    /// Identifies the hash function, chosen by the application.
+    pub hasher_id: u32,
+    /// The version of the node hashing rules, see `spec`.
+    pub version: u32,
+    /// The children per branch.
+    pub arity: u32,
+    /// The bits in a key.
+    pub key_bits: u32,
+}
+
+impl RootParams {
+    /// The version of the node hashing rules this crate implements.
+    pub const VERSION: u32 = 1;
+    /// Branches of this trie always have two children.
+    pub const ARITY: u32 = 2;
+
+    /// The parameters of a trie built by this version of the crate, with keys of `key_bits` bits.
+    #[inline]
+    pub const fn new(hasher_id: u32, key_bits: u32) -> Self {
+        RootParams {
+            hasher_id,
+            version: Self::VERSION,
+            arity: Self::ARITY,
+            key_bits,
+        }
+    }
+
+    /// Check that this crate can build and verify tries with these parameters.
+    #[inline]
+    pub fn check_supported(&self) -> Result<(), TrieError> {
+        if self.version != Self::VERSION || self.arity != Self::ARITY {
+            return Err(format!(
+                "Unsupported trie parameters: version {} with arity {}, expected version {} with arity {}",
+                self.version,
+                self.arity,
+                Self::VERSION,
+                Self::ARITY
+            )
+            .into());
+        }
+        if self.key_bits == 0 || self.key_bits > 256 || !self.key_bits.is_multiple_of(32) {
+            return Err(format!(
+                "Unsupported trie parameters: keys of {} bits, expected a multiple of 32 up to 256",
+                self.key_bits
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl PortableHash for RootParams {
+    #[inline]
+    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
+        hasher.portable_update_u32_slice(&[
+            self.hasher_id,
+            self.version,
+            self.arity,
+            self.key_bits,
+        ]);
+    }
+}
+
+/// A root and the parameters it was computed under.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ParamsRoot {
+    pub params: RootParams,
+    pub root: TrieRoot<NodeHash>,
+}
+
+impl ParamsRoot {
+    #[inline]
+    pub fn new(params: RootParams, root: TrieRoot<NodeHash>) -> Self {
+        ParamsRoot { params, root }
+    }
+
+    /// Return the root if it was computed under `expected`.
+    #[inline]
+    pub fn check(&self, expected: &RootParams) -> Result<TrieRoot<NodeHash>, VerifyError> {
+        if self.params != *expected {
+            return Err(VerifyError::ParamsMismatch {
+                expected: *expected,
+                actual: self.params,
+            });
+        }
+        Ok(self.root)
+    }
+
+    /// A single hash committing to both the root and its parameters, for publishing in place of the root.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commitment(&self, hasher: &mut impl PortableHasher<32>) -> NodeHash {
+        self.params.portable_hash(hasher);
+        match self.root {
+            TrieRoot::Empty => hasher.portable_update([0]),
+            TrieRoot::Node(root) => {
+                hasher.portable_update([1]);
+                hasher.portable_update(root.bytes);
+            }
+        }
+        NodeHash::new(hasher.finalize_reset())
+    }
+}
@@ -0,0 +1,123 @@
+//! A known-answer check that hashing does not depend on the target it runs on.
+//!
+//! A guest can only verify a witness if it hashes exactly as the host that built it.
+//! Hashing is meant to be portable: words are fed little endian, and `PortableHash` is not implemented for `usize`,
+//! whose width differs between a 64 bit host and a 32 bit guest.
+//! A value type, hasher or target that breaks this fails every proof with nothing but a root mismatch.
+//!
+//! `portable_hash_consistency` runs a fixed set of computations through the crate's hashing,
+//! primitive values, key encodings, and the roots of a small trie before and after removals,
+//! and returns one digest of their results.
+//! Run it with the same hasher on the host and in the guest, and compare the digests before trusting proofs.
+
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    vec::Vec,
+};
+
+use crate::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    KeyHash, NodeHash, PortableHash, PortableHasher, Transaction, TrieError, TrieRoot,
+};
+
+/// The digest of the canonical computations hashed with `H`, see the module documentation.
+///
+/// The digest depends on `H`, including its tags and counted mode,
+/// so compare digests computed with the same hasher type.
+#[inline]
+pub fn portable_hash_consistency<H: PortableHasher<32>>() -> Result<NodeHash, TrieError> {
+    let hasher = &mut H::default();
+    let mut transcript = H::default();
+
+    transcript.portable_update(primitives(hasher).bytes);
+    transcript.portable_update(containers(hasher).bytes);
+    transcript.portable_update(keys(hasher).bytes);
+    for root in trie_roots(hasher)? {
+        match root {
+            TrieRoot::Empty => transcript.portable_update([0]),
+            TrieRoot::Node(hash) => {
+                transcript.portable_update([1]);
+                transcript.portable_update(hash.bytes);
+            }
+        }
+    }
+
+    Ok(NodeHash::new(transcript.finalize_reset()))
+}
+
+/// Every primitive `PortableHash` implementation, at values whose byte order matters.
+fn primitives<H: PortableHasher<32>>(hasher: &mut H) -> NodeHash {
+    (0xa5u8, true, false, 'é', "kairos", String::from("trie")).portable_hash(hasher);
+    (0x0123u16, 0x0123_4567u32, 0x0123_4567_89ab_cdefu64).portable_hash(hasher);
+    0x0123_4567_89ab_cdef_fedc_ba98_7654_3210u128.portable_hash(hasher);
+    (-2i8, i16::MIN, -0x0123_4567i32, i64::MIN + 1, -3i128).portable_hash(hasher);
+    (u32::MAX, u64::MAX, i32::MAX, ()).portable_hash(hasher);
+    NodeHash::new(hasher.finalize_reset())
+}
+
+/// Slices, options and collections, with slices long enough to be hashed in more than one chunk.
+fn containers<H: PortableHasher<32>>(hasher: &mut H) -> NodeHash {
+    let bools: Vec<bool> = (0..100u32).map(|i| i % 3 == 0).collect();
+    bools.portable_hash(hasher);
+    let bytes: Vec<u8> = (0..=255).collect();
+    bytes.portable_hash(hasher);
+    let words: Vec<u32> = (0..40).map(|i| 0x0101_0101 * i).collect();
+    words.portable_hash(hasher);
+    hasher.portable_update_u32_slice(&words);
+
+    (Some(7u32), None::<u64>, Ok::<u8, u16>(1), Err::<u8, u16>(2)).portable_hash(hasher);
+    (Box::new(5u64), Cow::Borrowed(&[1u8, 2, 3][..]), [-1i16; 3]).portable_hash(hasher);
+    BTreeMap::from([(3u32, "c"), (1, "a"), (2, "b")]).portable_hash(hasher);
+    BTreeSet::from([9u64, 8, 7]).portable_hash(hasher);
+    NodeHash::new(hasher.finalize_reset())
+}
+
+/// The words of keys built from bytes and from integers.
+fn keys<H: PortableHasher<32>>(hasher: &mut H) -> NodeHash {
+    let bytes: [u8; 32] = core::array::from_fn(|i| i as u8 * 7);
+    for key in [
+        KeyHash::from_bytes(&bytes),
+        KeyHash::from_u64(0x0123_4567_89ab_cdef),
+    ] {
+        hasher.portable_update_u32_slice(&key.0);
+        hasher.portable_update(key.to_bytes());
+    }
+    NodeHash::new(hasher.finalize_reset())
+}
+
+/// Keys branching on bits at the edges of words, where masks and prefixes go wrong,
+/// and keys sharing several whole words, which need prefix words.
+fn canonical_keys() -> impl Iterator<Item = KeyHash> {
+    let edges = (0..8).flat_map(|word| {
+        [0, 1, 30, 31].map(|bit| {
+            let mut key = [0x5555_5555; 8];
+            key[word] ^= 1 << bit;
+            KeyHash(key)
+        })
+    });
+    let shared_words = (0..8u32).map(|i| KeyHash([7, 7, 7, 7, 7, i, i * 3, 0]));
+    (0..32)
+        .map(KeyHash::from_u64)
+        .chain(edges)
+        .chain(shared_words)
+}
+
+/// The roots of a trie of `canonical_keys`, then of what is left after removing every third key.
+fn trie_roots<H: PortableHasher<32>>(hasher: &mut H) -> Result<[TrieRoot<NodeHash>; 2], TrieError> {
+    let db = MemoryDb::<u64>::empty();
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::empty(&db));
+    for (key, value) in canonical_keys().zip(0x0100_0000_0000_0001u64..) {
+        txn.insert(&key, value)?;
+    }
+    let full = txn.calc_root_hash(hasher)?;
+
+    for key in canonical_keys().step_by(3) {
+        txn.remove(&key)?;
+    }
+    let removed = txn.calc_root_hash(hasher)?;
+
+    Ok([full, removed])
+}
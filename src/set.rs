@@ -0,0 +1,109 @@
+use crate::{
+    stored::{merkle::SnapshotBuilder, DatabaseSet, Store},
+    DeletionProof, InclusionProof, KeyHash, NodeHash, PortableHasher, Transaction, TrieError,
+    TrieKey, TrieRoot,
+};
+
+/// A provable set of keys, a `Transaction` whose values are all `()`.
+///
+/// A leaf of `()` hashes only its key, so members cost no value bytes in the database or a snapshot.
+/// Proofs are the usual `InclusionProof` and `DeletionProof`, with a `()` value.
+/// Use `transaction` for anything else, such as building a snapshot.
+pub struct TrieSet<S, K = KeyHash> {
+    txn: Transaction<S, (), K>,
+}
+
+impl<S, K> TrieSet<S, K> {
+    #[inline]
+    pub fn new(txn: Transaction<S, (), K>) -> Self {
+        TrieSet { txn }
+    }
+
+    #[inline]
+    pub fn transaction(&self) -> &Transaction<S, (), K> {
+        &self.txn
+    }
+
+    #[inline]
+    pub fn into_transaction(self) -> Transaction<S, (), K> {
+        self.txn
+    }
+}
+
+impl<S, K> From<Transaction<S, (), K>> for TrieSet<S, K> {
+    #[inline]
+    fn from(txn: Transaction<S, (), K>) -> Self {
+        Self::new(txn)
+    }
+}
+
+impl<S: Store<(), K>, K: TrieKey> TrieSet<S, K> {
+    #[inline]
+    pub fn contains(&self, key_hash: &K) -> Result<bool, TrieError> {
+        Ok(self.txn.get(key_hash)?.is_some())
+    }
+
+    /// Add `key_hash` to the set, returning whether it was absent.
+    ///
+    /// Inserting a member leaves the trie unmodified.
+    #[inline]
+    pub fn insert(&mut self, key_hash: &K) -> Result<bool, TrieError> {
+        if self.contains(key_hash)? {
+            return Ok(false);
+        }
+        self.txn.insert(key_hash, ())?;
+        Ok(true)
+    }
+
+    /// Remove `key_hash` from the set, returning whether it was present.
+    #[inline]
+    pub fn remove(&mut self, key_hash: &K) -> Result<bool, TrieError> {
+        Ok(self.txn.remove(key_hash)?.is_some())
+    }
+
+    /// Like `remove`, but also returns a `DeletionProof` of the removal, see `Transaction::remove_with_proof`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn remove_with_proof(
+        &mut self,
+        key_hash: &K,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<Option<DeletionProof<(), K>>, TrieError> {
+        self.txn.remove_with_proof(key_hash, hasher)
+    }
+
+    /// Prove `key_hash` is a member of the set at its current root, see `Transaction::prove_inclusion`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn prove_contains(
+        &self,
+        key_hash: &K,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<Option<InclusionProof<(), K>>, TrieError> {
+        self.txn.prove_inclusion(key_hash, hasher)
+    }
+
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn calc_root_hash(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        self.txn.calc_root_hash(hasher)
+    }
+}
+
+impl<Db: DatabaseSet<(), K>, K: TrieKey> TrieSet<SnapshotBuilder<Db, (), K>, K> {
+    /// Write the modified nodes to the database, see `Transaction::commit`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        Ok(self.txn.commit(hasher)?.root)
+    }
+}
@@ -0,0 +1,302 @@
+//! A framing for serialized snapshots, with a fixed size header a guest can check before decoding the body.
+//!
+//! A framed snapshot is:
+//!
+//! | bytes | field                                                        |
+//! |-------|--------------------------------------------------------------|
+//! | 4     | `MAGIC`                                                      |
+//! | 4     | the format version, `VERSION`, little endian                 |
+//! | 8     | the number of branches, little endian                        |
+//! | 8     | the number of leaves, little endian                          |
+//! | 8     | the number of unvisited nodes, little endian                 |
+//! | 8     | the total number of branch prefix words, little endian       |
+//! | 8     | the length of the body, little endian                        |
+//! | 8     | the size of a key in bytes, little endian                    |
+//! | the rest | the body, the snapshot in the caller's serialization      |
+//!
+//! `peek_header` reads the header alone, so a witness that is too large,
+//! or of a version the guest does not read, is rejected before anything is allocated for it.
+//! `decode` checks the decoded body against the header, so the header cannot understate the snapshot.
+//!
+//! This crate reads frames of `MIN_VERSION` as well as `VERSION`,
+//! so a verifier can be upgraded before or after its prover, and witnesses queued before an upgrade still verify.
+//! Version 1 has no key size, its header ends at the body length, and its keys are read as `KeyHash`es.
+//! The body is the caller's serialization and does not change between versions.
+//! `upgrade` rewrites an older frame as one of `VERSION`.
+
+use alloc::{format, vec::Vec};
+use core::{fmt::Display, mem::size_of};
+
+use crate::{
+    spec::MAX_BRANCH_PREFIX_WORDS,
+    stored::{merkle::Snapshot, Idx},
+    Branch, KeyHash, NodeHash, TrieError,
+};
+
+/// The first bytes of a framed snapshot.
+pub const MAGIC: [u8; 4] = *b"KTSN";
+
+/// The version of the framing this crate writes.
+pub const VERSION: u32 = 2;
+
+/// The oldest version of the framing this crate reads.
+pub const MIN_VERSION: u32 = VERSION - 1;
+
+/// The length of the header before the body.
+pub const HEADER_LEN: usize = 56;
+
+/// The length of a version 1 header, which ends before the key size.
+pub const HEADER_LEN_V1: usize = 48;
+
+/// The length of the header of `version`.
+#[inline]
+const fn header_len(version: u32) -> usize {
+    if version == 1 {
+        HEADER_LEN_V1
+    } else {
+        HEADER_LEN
+    }
+}
+
+/// The lengths of a snapshot's arrays.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SnapshotCounts {
+    pub branches: u64,
+    pub leaves: u64,
+    pub unvisited_nodes: u64,
+}
+
+/// The header of a framed snapshot, see `peek_header`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SnapshotHeader {
+    pub version: u32,
+    pub counts: SnapshotCounts,
+    /// The prefix words of all branches together.
+    pub prefix_words: u64,
+    /// The length of the body in bytes.
+    pub body_len: u64,
+    /// The size of a key in bytes, the size of `KeyHash` for version 1.
+    pub key_bytes: u64,
+    /// An estimate of the bytes the decoded snapshot takes in memory.
+    ///
+    /// The arrays are counted at their in-memory size,
+    /// with a leaf counted as its key, and the whole body added as a bound on what the values take.
+    pub estimated_memory: u64,
+}
+
+/// The counts and prefix words of `snapshot`, as its header records them.
+#[inline]
+fn describe<V, K>(snapshot: &Snapshot<V, K>) -> (SnapshotCounts, u64) {
+    let (branches, leaves, unvisited_nodes) = snapshot.arrays();
+    let counts = SnapshotCounts {
+        branches: branches.len() as u64,
+        leaves: leaves.len() as u64,
+        unvisited_nodes: unvisited_nodes.len() as u64,
+    };
+    let prefix_words = branches.iter().map(|b| b.prefix.len() as u64).sum();
+    (counts, prefix_words)
+}
+
+/// Frame `body`, the caller's serialization of `snapshot`, with a header describing it.
+#[inline]
+pub fn encode<V, K>(snapshot: &Snapshot<V, K>, body: &[u8]) -> Vec<u8> {
+    let (counts, prefix_words) = describe(snapshot);
+    frame(counts, prefix_words, size_of::<K>() as u64, body)
+}
+
+/// Write a header of `VERSION` followed by `body`.
+#[inline]
+fn frame(counts: SnapshotCounts, prefix_words: u64, key_bytes: u64, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    for field in [
+        counts.branches,
+        counts.leaves,
+        counts.unvisited_nodes,
+        prefix_words,
+        body.len() as u64,
+        key_bytes,
+    ] {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+    debug_assert_eq!(bytes.len(), HEADER_LEN);
+
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// Rewrite a framed snapshot of keys `K` as a frame of `VERSION`, with the same body.
+///
+/// Fails if `bytes` is not a framed snapshot of a version this crate reads,
+/// or if it records keys of a size other than `K`'s.
+#[inline]
+pub fn upgrade<K>(bytes: &[u8]) -> Result<Vec<u8>, TrieError> {
+    let header = peek_header(bytes)?;
+    check_key_size::<K>(&header)?;
+    let body = &bytes[header_len(header.version)..];
+    Ok(frame(
+        header.counts,
+        header.prefix_words,
+        size_of::<K>() as u64,
+        body,
+    ))
+}
+
+/// Fails if `header` records keys of a size other than `K`'s. Version 1 does not record it.
+#[inline]
+fn check_key_size<K>(header: &SnapshotHeader) -> Result<(), TrieError> {
+    if header.version > 1 && header.key_bytes != size_of::<K>() as u64 {
+        return Err(format!(
+            "Invalid snapshot header: keys of {} bytes, decoding keys of {} bytes",
+            header.key_bytes,
+            size_of::<K>()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Read and check the header of a framed snapshot, without reading the body.
+///
+/// Fails if `bytes` is not a framed snapshot of a version from `MIN_VERSION` to `VERSION`,
+/// if the body is not exactly the rest of `bytes`,
+/// or if the counts could not describe a snapshot.
+#[inline]
+pub fn peek_header(bytes: &[u8]) -> Result<SnapshotHeader, TrieError> {
+    let Some(header) = bytes.first_chunk::<HEADER_LEN_V1>() else {
+        return Err(format!(
+            "Invalid snapshot header: {} bytes is shorter than the {HEADER_LEN_V1} byte header",
+            bytes.len()
+        )
+        .into());
+    };
+
+    if header[..4] != MAGIC {
+        return Err("Invalid snapshot header: the bytes are not a framed snapshot".into());
+    }
+    let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if !(MIN_VERSION..=VERSION).contains(&version) {
+        return Err(format!(
+            "Unsupported snapshot version {version}, this crate reads versions {MIN_VERSION} to {VERSION}"
+        )
+        .into());
+    }
+
+    let Some((header, body)) = bytes.split_at_checked(header_len(version)) else {
+        return Err(format!(
+            "Invalid snapshot header: {} bytes is shorter than the {} byte header of version {version}",
+            bytes.len(),
+            header_len(version)
+        )
+        .into());
+    };
+
+    let field = |i: usize| {
+        let start = 8 + 8 * i;
+        let mut word = [0; 8];
+        word.copy_from_slice(&header[start..start + 8]);
+        u64::from_le_bytes(word)
+    };
+    let counts = SnapshotCounts {
+        branches: field(0),
+        leaves: field(1),
+        unvisited_nodes: field(2),
+    };
+    let prefix_words = field(3);
+    let body_len = field(4);
+    let key_bytes = if version == 1 {
+        size_of::<KeyHash>() as u64
+    } else {
+        field(5)
+    };
+
+    if body_len != body.len() as u64 {
+        return Err(format!(
+            "Invalid snapshot header: the body is {} bytes, the header says {body_len}",
+            body.len()
+        )
+        .into());
+    }
+
+    let nodes = counts
+        .branches
+        .checked_add(counts.leaves)
+        .and_then(|n| n.checked_add(counts.unvisited_nodes))
+        .and_then(|n| Idx::try_from(n).ok());
+    if nodes.is_none() {
+        return Err(format!(
+            "Invalid snapshot header: {counts:?} is more nodes than an `Idx` can address"
+        )
+        .into());
+    }
+    let max_prefix_words = counts.branches.checked_mul(MAX_BRANCH_PREFIX_WORDS as u64);
+    if max_prefix_words.is_none_or(|max| prefix_words > max) {
+        return Err(format!(
+            "Invalid snapshot header: {prefix_words} prefix words is more than {} branches can hold",
+            counts.branches
+        )
+        .into());
+    }
+
+    let estimated_memory = [
+        counts
+            .branches
+            .saturating_mul(size_of::<Branch<Idx>>() as u64),
+        prefix_words.saturating_mul(size_of::<u32>() as u64),
+        counts.leaves.saturating_mul(key_bytes),
+        counts
+            .unvisited_nodes
+            .saturating_mul(size_of::<NodeHash>() as u64),
+        body_len,
+    ]
+    .into_iter()
+    .fold(0u64, u64::saturating_add);
+
+    Ok(SnapshotHeader {
+        version,
+        counts,
+        prefix_words,
+        body_len,
+        key_bytes,
+        estimated_memory,
+    })
+}
+
+/// Check the header of a framed snapshot, then decode its body with `decode_body`.
+///
+/// Reads every version from `MIN_VERSION` to `VERSION`, the body is decoded the same way for each.
+/// Fails before calling `decode_body` if the header estimates more than `max_memory` bytes,
+/// or records keys of a size other than `K`'s,
+/// and after it if the decoded snapshot does not match the header's counts.
+#[inline]
+pub fn decode<V, K, E: Display>(
+    bytes: &[u8],
+    max_memory: u64,
+    decode_body: impl FnOnce(&[u8]) -> Result<Snapshot<V, K>, E>,
+) -> Result<Snapshot<V, K>, TrieError> {
+    let header = peek_header(bytes)?;
+    if header.estimated_memory > max_memory {
+        return Err(format!(
+            "Snapshot too large: an estimated {} bytes in memory, the limit is {max_memory}",
+            header.estimated_memory
+        )
+        .into());
+    }
+
+    check_key_size::<K>(&header)?;
+
+    let snapshot = decode_body(&bytes[header_len(header.version)..])
+        .map_err(|e| TrieError::from(format!("Invalid snapshot body: {e}")))?;
+
+    let (counts, prefix_words) = describe(&snapshot);
+    if counts != header.counts || prefix_words != header.prefix_words {
+        return Err(format!(
+            "Invalid snapshot: the body has {counts:?} and {prefix_words} prefix words, \
+            the header says {:?} and {}",
+            header.counts, header.prefix_words
+        )
+        .into());
+    }
+    Ok(snapshot)
+}
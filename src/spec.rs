@@ -0,0 +1,215 @@
+//! The bytes hashed for each node, in order, as an executable specification.
+//!
+//! A node's hash is the hasher's digest of its preimage, fed in a single stream.
+//! The trie feeds the same bytes in fewer, larger updates, so hashing a preimage with any streaming hasher
+//! gives the same `NodeHash` as `Leaf::hash_leaf` and `Branch::hash_branch`.
+//!
+//! A leaf hashes:
+//!
+//! | bytes            | field                                                |
+//! |------------------|------------------------------------------------------|
+//! | `LEAF_TAG`       | `PortableHasher::LEAF_TAG`, empty by default         |
+//! | `4 * key words`  | each word of the key, little endian                  |
+//! | the rest         | the value, as fed to the hasher by `PortableHash`    |
+//!
+//! The value is not hashed separately first, its `PortableHash` encoding is part of the preimage.
+//!
+//! A branch hashes, after `PortableHasher::BRANCH_TAG`, empty by default,
+//! the fields at the `BRANCH_*` offsets below, followed by its prefix words.
+//!
+//! In counted mode, see `counted`, the last 4 bytes of the digest are replaced by the number of leaves under the node,
+//! which `hash_preimage` leaves to `counted::with_leaf_count`.
+//!
+//! A canonical branch has at most `MAX_BRANCH_PREFIX_WORDS` prefix words,
+//! and a verifier bounds leaf values with `NodeLimits`,
+//! so the memory a node of an untrusted witness takes is bounded before it is hashed.
+
+use alloc::vec::Vec;
+
+use crate::{
+    errors::NodeSizeError, Branch, NodeHash, PortableHash, PortableHasher, PortableUpdate, TrieKey,
+};
+
+/// The offset of the left child's hash in a branch preimage, after the tag.
+pub const BRANCH_LEFT: usize = 0;
+/// The offset of the right child's hash in a branch preimage, after the tag.
+pub const BRANCH_RIGHT: usize = 32;
+/// The offset of `BranchMask::bit_idx`, a little endian `u32`, in a branch preimage, after the tag.
+pub const BRANCH_BIT_IDX: usize = 64;
+/// The offset of `BranchMask::left_prefix`, a little endian `u32`, in a branch preimage, after the tag.
+pub const BRANCH_LEFT_PREFIX: usize = 68;
+/// The offset of `Branch::prior_word`, a little endian `u32`, in a branch preimage, after the tag.
+pub const BRANCH_PRIOR_WORD: usize = 72;
+/// The offset of `Branch::prefix`, little endian `u32`s to the end of the preimage, in a branch preimage, after the tag.
+pub const BRANCH_PREFIX: usize = 76;
+
+/// The most prefix words a branch can have.
+///
+/// A key has at most 256 bits, 8 words,
+/// and a branch's prefix holds neither its own word nor `prior_word`.
+pub const MAX_BRANCH_PREFIX_WORDS: usize = 6;
+
+/// The default `NodeLimits::max_value_bytes`, 1 MiB.
+///
+/// Larger values belong in a `ChunkedValue`.
+pub const DEFAULT_MAX_VALUE_BYTES: usize = 1 << 20;
+
+/// Bounds on the size of a node, checked by `Snapshot::check_limits` and `verify_batch`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NodeLimits {
+    /// The longest `PortableHash` encoding of a leaf value, in bytes.
+    pub max_value_bytes: usize,
+    /// The most prefix words of a branch, at most `MAX_BRANCH_PREFIX_WORDS`.
+    pub max_prefix_words: usize,
+}
+
+impl Default for NodeLimits {
+    #[inline]
+    fn default() -> Self {
+        NodeLimits {
+            max_value_bytes: DEFAULT_MAX_VALUE_BYTES,
+            max_prefix_words: MAX_BRANCH_PREFIX_WORDS,
+        }
+    }
+}
+
+impl NodeLimits {
+    /// Check the prefix of `branch` is within the limit.
+    #[inline]
+    pub fn check_branch<NR>(&self, branch: &Branch<NR>) -> Result<(), NodeSizeError> {
+        let max = self.max_prefix_words.min(MAX_BRANCH_PREFIX_WORDS);
+        if branch.prefix.len() > max {
+            return Err(NodeSizeError::PrefixTooLong {
+                words: branch.prefix.len(),
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the `PortableHash` encoding of `value` is within the limit.
+    #[inline]
+    pub fn check_value(&self, value: &impl PortableHash) -> Result<(), NodeSizeError> {
+        let bytes = value_len(value);
+        if bytes > self.max_value_bytes {
+            return Err(NodeSizeError::ValueTooLarge {
+                bytes,
+                max: self.max_value_bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The length of the `PortableHash` encoding of `value`, the bytes it adds to a leaf preimage.
+#[inline]
+pub fn value_len(value: &impl PortableHash) -> usize {
+    let mut len = ByteCount(0);
+    value.portable_hash(&mut len);
+    len.0
+}
+
+/// Counts the bytes fed to a hasher.
+struct ByteCount(usize);
+
+impl PortableUpdate for ByteCount {
+    #[inline]
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.0 = self.0.saturating_add(data.as_ref().len());
+    }
+}
+
+/// Deserialize a branch prefix, rejecting one longer than `MAX_BRANCH_PREFIX_WORDS`
+/// before allocating it.
+#[cfg(feature = "serde")]
+#[inline]
+pub(crate) fn deserialize_prefix<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<alloc::boxed::Box<[u32]>, D::Error> {
+    use alloc::boxed::Box;
+    use serde::de::{Error, SeqAccess, Visitor};
+
+    struct PrefixVisitor;
+
+    impl<'de> Visitor<'de> for PrefixVisitor {
+        type Value = Box<[u32]>;
+
+        #[inline]
+        fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "at most {MAX_BRANCH_PREFIX_WORDS} prefix words")
+        }
+
+        #[inline]
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut words = [0; MAX_BRANCH_PREFIX_WORDS];
+            let mut len = 0;
+            while let Some(word) = seq.next_element()? {
+                let Some(slot) = words.get_mut(len) else {
+                    return Err(A::Error::invalid_length(len + 1, &self));
+                };
+                *slot = word;
+                len += 1;
+            }
+            Ok(words[..len].into())
+        }
+    }
+
+    deserializer.deserialize_seq(PrefixVisitor)
+}
+
+/// Collects the bytes fed to a hasher.
+pub(crate) struct Preimage(pub(crate) Vec<u8>);
+
+impl PortableUpdate for Preimage {
+    #[inline]
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.extend_from_slice(data.as_ref());
+    }
+}
+
+/// The bytes `Leaf::hash_leaf` hashes for a leaf holding `value` at `key`, with the tags of `H`.
+#[inline]
+pub fn leaf_preimage<H: PortableHasher<32>, K: TrieKey>(
+    key: &K,
+    value: &impl PortableHash,
+) -> Vec<u8> {
+    let mut preimage = Preimage(H::LEAF_TAG.to_vec());
+    for word in key.words() {
+        preimage.portable_update(word.to_le_bytes());
+    }
+    value.portable_hash(&mut preimage);
+    preimage.0
+}
+
+/// The bytes `Branch::hash_branch` hashes for `branch` with children `left` and `right`, with the tags of `H`.
+#[inline]
+pub fn branch_preimage<H: PortableHasher<32>, NR>(
+    branch: &Branch<NR>,
+    left: &NodeHash,
+    right: &NodeHash,
+) -> Vec<u8> {
+    let tag = H::BRANCH_TAG;
+    let mut preimage = Vec::with_capacity(tag.len() + BRANCH_PREFIX + branch.prefix.len() * 4);
+    preimage.extend_from_slice(tag);
+
+    preimage.extend_from_slice(&left.bytes);
+    preimage.extend_from_slice(&right.bytes);
+    preimage.extend_from_slice(&branch.mask.bit_idx().to_le_bytes());
+    preimage.extend_from_slice(&branch.mask.left_prefix().to_le_bytes());
+    preimage.extend_from_slice(&branch.prior_word.to_le_bytes());
+    debug_assert_eq!(preimage.len(), tag.len() + BRANCH_PREFIX);
+
+    for word in branch.prefix.iter() {
+        preimage.extend_from_slice(&word.to_le_bytes());
+    }
+    preimage
+}
+
+/// Hash a preimage from `leaf_preimage` or `branch_preimage` into the node's hash.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn hash_preimage<H: PortableHasher<32>>(hasher: &mut H, preimage: &[u8]) -> NodeHash {
+    hasher.portable_update(preimage);
+    NodeHash::new(hasher.finalize_reset())
+}
@@ -1,5 +1,18 @@
+pub mod archive;
+pub(crate) mod arena;
+#[cfg(feature = "test-utils")]
+pub mod conformance;
+pub mod delta;
+#[cfg(feature = "test-utils")]
+pub mod faulty;
+pub mod flat;
 pub mod memory_db;
 pub mod merkle;
+#[cfg(feature = "std")]
+pub mod node_cache;
+#[cfg(feature = "std")]
+pub mod prefetch;
+pub mod refcount;
 
 use core::fmt::Display;
 
@@ -7,13 +20,62 @@ use alloc::{rc::Rc, sync::Arc};
 
 use crate::{
     transaction::nodes::{Branch, Leaf, Node},
-    NodeHash, PortableHasher,
+    KeyHash, NodeHash, PortableHasher, TrieError,
 };
 
+/// The index of a node in a `Store`.
+///
+/// `u32` by default, which bounds a store to `u32::MAX` nodes.
+/// The `idx-u64` feature widens it to `u64`, see `MAX_NODES` for the limit on 32-bit targets.
+#[cfg(not(feature = "idx-u64"))]
 pub type Idx = u32;
+#[cfg(feature = "idx-u64")]
+pub type Idx = u64;
+
+/// The most nodes a `Store` can index on this target, the smaller of `Idx::MAX` and `usize::MAX`.
+///
+/// On 64-bit targets this is `Idx::MAX`.
+/// On 32-bit targets, such as wasm32 and riscv32 zkVM guests, with the `idx-u64` feature it is `usize::MAX`,
+/// since an index past it could not address a slice.
+/// Every index below it converts between `Idx` and `usize` without truncating.
+pub const MAX_NODES: usize = if (Idx::MAX as u128) < (usize::MAX as u128) {
+    Idx::MAX as usize
+} else {
+    usize::MAX
+};
+
+// Lengths and offsets read as `u32` from a witness are converted to `usize` with `as`.
+const _: () = assert!(
+    usize::BITS >= 32,
+    "kairos-trie needs at least a 32-bit `usize`"
+);
+
+/// Whether `nodes` nodes can each be given an `Idx` on this target, that is at most `MAX_NODES`.
+#[inline]
+#[allow(clippy::absurd_extreme_comparisons)] // With `idx-u64`, `MAX_NODES` is `usize::MAX`.
+pub(crate) fn indexable(nodes: usize) -> bool {
+    nodes <= MAX_NODES
+}
+
+/// `idx` as a slice index, or `None` if it does not fit in a `usize` on this target.
+///
+/// A witness can hold any `Idx`, so it must not be cast with `as`,
+/// which would wrap a 64-bit index onto a valid node on a 32-bit target.
+#[inline]
+pub(crate) fn idx_to_usize(idx: Idx) -> Option<usize> {
+    usize::try_from(idx).ok()
+}
+
+/// A cell written once through a shared reference.
+///
+/// With `std` this is a `OnceLock`, so the types holding one can be shared between threads.
+#[cfg(feature = "std")]
+pub(crate) type OnceCell<T> = std::sync::OnceLock<T>;
+#[cfg(not(feature = "std"))]
+pub(crate) type OnceCell<T> = core::cell::OnceCell<T>;
 
-pub trait Store<V> {
-    type Error: Display;
+pub trait Store<V, K = KeyHash> {
+    type Error: Display + Into<TrieError>;
 
     fn calc_subtree_hash(
         &self,
@@ -21,10 +83,17 @@ pub trait Store<V> {
         hash_idx: Idx,
     ) -> Result<NodeHash, Self::Error>;
 
-    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V>>, Self::Error>;
+    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V, K>>, Self::Error>;
+
+    /// Returns the hash of a node the store only knows by hash, such as an unvisited node of a `Snapshot`.
+    /// Returns `None` if the node can be loaded with `get_node`.
+    #[inline]
+    fn get_unvisited_hash(&self, _hash_idx: Idx) -> Result<Option<NodeHash>, Self::Error> {
+        Ok(None)
+    }
 }
 
-impl<V, S: Store<V>> Store<V> for &S {
+impl<V, K, S: Store<V, K>> Store<V, K> for &S {
     type Error = S::Error;
 
     #[inline(always)]
@@ -38,12 +107,17 @@ impl<V, S: Store<V>> Store<V> for &S {
     }
 
     #[inline(always)]
-    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V>>, Self::Error> {
+    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V, K>>, Self::Error> {
         (**self).get_node(hash_idx)
     }
+
+    #[inline(always)]
+    fn get_unvisited_hash(&self, hash_idx: Idx) -> Result<Option<NodeHash>, Self::Error> {
+        (**self).get_unvisited_hash(hash_idx)
+    }
 }
 
-impl<V, S: Store<V>> Store<V> for Rc<S> {
+impl<V, K, S: Store<V, K>> Store<V, K> for Rc<S> {
     type Error = S::Error;
 
     #[inline(always)]
@@ -56,12 +130,17 @@ impl<V, S: Store<V>> Store<V> for Rc<S> {
     }
 
     #[inline(always)]
-    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V>>, Self::Error> {
+    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V, K>>, Self::Error> {
         (**self).get_node(hash_idx)
     }
+
+    #[inline(always)]
+    fn get_unvisited_hash(&self, hash_idx: Idx) -> Result<Option<NodeHash>, Self::Error> {
+        (**self).get_unvisited_hash(hash_idx)
+    }
 }
 
-impl<V, S: Store<V>> Store<V> for Arc<S> {
+impl<V, K, S: Store<V, K>> Store<V, K> for Arc<S> {
     type Error = S::Error;
 
     #[inline(always)]
@@ -74,89 +153,244 @@ impl<V, S: Store<V>> Store<V> for Arc<S> {
     }
 
     #[inline(always)]
-    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V>>, Self::Error> {
+    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V, K>>, Self::Error> {
         (**self).get_node(hash_idx)
     }
+
+    #[inline(always)]
+    fn get_unvisited_hash(&self, hash_idx: Idx) -> Result<Option<NodeHash>, Self::Error> {
+        (**self).get_unvisited_hash(hash_idx)
+    }
 }
 
-pub trait DatabaseGet<V> {
-    type GetError: Display;
+/// Reads nodes from a database by hash.
+///
+/// `GetError` converts into a `TrieError` when a read fails.
+/// Convert an error type implementing `core::error::Error` with `TrieError::from_source`,
+/// so callers can recover it from the `TrieError` with `TrieError::downcast_source`.
+pub trait DatabaseGet<V, K = KeyHash> {
+    type GetError: Display + Into<TrieError>;
 
-    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V>>, Self::GetError>;
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V, K>>, Self::GetError>;
 }
 
-impl<V, D: DatabaseGet<V>> DatabaseGet<V> for &D {
+impl<V, K, D: DatabaseGet<V, K>> DatabaseGet<V, K> for &D {
     type GetError = D::GetError;
 
     #[inline]
-    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V>>, Self::GetError> {
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V, K>>, Self::GetError> {
         (**self).get(hash)
     }
 }
 
-pub trait DatabaseSet<V>: DatabaseGet<V> {
-    type SetError: Display;
+/// Writes nodes to a database by hash.
+///
+/// `Transaction::commit` writes a commit's nodes between a call to `begin_batch` and one to `end_batch`,
+/// so a database with native write batches can buffer the writes, and sync once per commit rather than once per node.
+pub trait DatabaseSet<V, K = KeyHash>: DatabaseGet<V, K> {
+    type SetError: Display + Into<TrieError>;
 
     fn set(
         &self,
         hash: NodeHash,
-        node: Node<Branch<NodeHash>, Leaf<V>>,
-    ) -> Result<(), Self::GetError>;
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    ) -> Result<(), Self::SetError>;
+
+    /// Called before the nodes of a commit are written. Does nothing by default.
+    #[inline]
+    fn begin_batch(&self) -> Result<(), Self::SetError> {
+        Ok(())
+    }
+
+    /// Called after the nodes of a commit are written, or once a write has failed, with `completed` false.
+    ///
+    /// Write and sync the batch when `completed`, and discard it otherwise.
+    /// An error fails the commit. Does nothing by default.
+    #[inline]
+    fn end_batch(&self, completed: bool) -> Result<(), Self::SetError> {
+        let _ = completed;
+        Ok(())
+    }
 }
 
-impl<V, D: DatabaseSet<V>> DatabaseSet<V> for &D {
+impl<V, K, D: DatabaseSet<V, K>> DatabaseSet<V, K> for &D {
     type SetError = D::SetError;
 
     #[inline]
     fn set(
         &self,
         hash: NodeHash,
-        node: Node<Branch<NodeHash>, Leaf<V>>,
-    ) -> Result<(), Self::GetError> {
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    ) -> Result<(), Self::SetError> {
         (**self).set(hash, node)
     }
+
+    #[inline]
+    fn begin_batch(&self) -> Result<(), Self::SetError> {
+        (**self).begin_batch()
+    }
+
+    #[inline]
+    fn end_batch(&self, completed: bool) -> Result<(), Self::SetError> {
+        (**self).end_batch(completed)
+    }
 }
 
-impl<V, D: DatabaseGet<V>> DatabaseGet<V> for Rc<D> {
+impl<V, K, D: DatabaseGet<V, K>> DatabaseGet<V, K> for Rc<D> {
     type GetError = D::GetError;
 
     #[inline]
-    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V>>, Self::GetError> {
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V, K>>, Self::GetError> {
         (**self).get(hash)
     }
 }
 
-impl<V, D: DatabaseSet<V>> DatabaseSet<V> for Rc<D> {
+impl<V, K, D: DatabaseSet<V, K>> DatabaseSet<V, K> for Rc<D> {
     type SetError = D::SetError;
 
     #[inline]
     fn set(
         &self,
         hash: NodeHash,
-        node: Node<Branch<NodeHash>, Leaf<V>>,
-    ) -> Result<(), Self::GetError> {
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    ) -> Result<(), Self::SetError> {
         (**self).set(hash, node)
     }
+
+    #[inline]
+    fn begin_batch(&self) -> Result<(), Self::SetError> {
+        (**self).begin_batch()
+    }
+
+    #[inline]
+    fn end_batch(&self, completed: bool) -> Result<(), Self::SetError> {
+        (**self).end_batch(completed)
+    }
 }
 
-impl<V, D: DatabaseGet<V>> DatabaseGet<V> for Arc<D> {
+impl<V, K, D: DatabaseGet<V, K>> DatabaseGet<V, K> for Arc<D> {
     type GetError = D::GetError;
 
     #[inline]
-    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V>>, Self::GetError> {
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V, K>>, Self::GetError> {
         (**self).get(hash)
     }
 }
 
-impl<V, D: DatabaseSet<V>> DatabaseSet<V> for Arc<D> {
+impl<V, K, D: DatabaseSet<V, K>> DatabaseSet<V, K> for Arc<D> {
     type SetError = D::SetError;
 
     #[inline]
     fn set(
         &self,
         hash: NodeHash,
-        node: Node<Branch<NodeHash>, Leaf<V>>,
-    ) -> Result<(), Self::GetError> {
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    ) -> Result<(), Self::SetError> {
         (**self).set(hash, node)
     }
+
+    #[inline]
+    fn begin_batch(&self) -> Result<(), Self::SetError> {
+        (**self).begin_batch()
+    }
+
+    #[inline]
+    fn end_batch(&self, completed: bool) -> Result<(), Self::SetError> {
+        (**self).end_batch(completed)
+    }
+}
+
+/// Roots whose nodes a garbage collector must keep, whatever the reference counts say.
+///
+/// A prover pins the root it is building a proof against, and unpins it when the proof is done,
+/// so committing a newer root cannot collect nodes the proof still reads.
+/// Pins are counted, a root pinned twice stays pinned until it is unpinned twice.
+pub trait DatabasePin {
+    type PinError: Display;
+
+    fn pin_root(&self, root: NodeHash) -> Result<(), Self::PinError>;
+
+    fn unpin_root(&self, root: NodeHash) -> Result<(), Self::PinError>;
+
+    fn is_pinned(&self, root: &NodeHash) -> Result<bool, Self::PinError>;
+}
+
+impl<D: DatabasePin> DatabasePin for &D {
+    type PinError = D::PinError;
+
+    #[inline]
+    fn pin_root(&self, root: NodeHash) -> Result<(), Self::PinError> {
+        (**self).pin_root(root)
+    }
+
+    #[inline]
+    fn unpin_root(&self, root: NodeHash) -> Result<(), Self::PinError> {
+        (**self).unpin_root(root)
+    }
+
+    #[inline]
+    fn is_pinned(&self, root: &NodeHash) -> Result<bool, Self::PinError> {
+        (**self).is_pinned(root)
+    }
+}
+
+impl<D: DatabasePin> DatabasePin for Rc<D> {
+    type PinError = D::PinError;
+
+    #[inline]
+    fn pin_root(&self, root: NodeHash) -> Result<(), Self::PinError> {
+        (**self).pin_root(root)
+    }
+
+    #[inline]
+    fn unpin_root(&self, root: NodeHash) -> Result<(), Self::PinError> {
+        (**self).unpin_root(root)
+    }
+
+    #[inline]
+    fn is_pinned(&self, root: &NodeHash) -> Result<bool, Self::PinError> {
+        (**self).is_pinned(root)
+    }
+}
+
+impl<D: DatabasePin> DatabasePin for Arc<D> {
+    type PinError = D::PinError;
+
+    #[inline]
+    fn pin_root(&self, root: NodeHash) -> Result<(), Self::PinError> {
+        (**self).pin_root(root)
+    }
+
+    #[inline]
+    fn unpin_root(&self, root: NodeHash) -> Result<(), Self::PinError> {
+        (**self).unpin_root(root)
+    }
+
+    #[inline]
+    fn is_pinned(&self, root: &NodeHash) -> Result<bool, Self::PinError> {
+        (**self).is_pinned(root)
+    }
+}
+
+/// A `DatabaseSet` with its error type erased,
+/// so stores of different types can be passed together, as to `Transaction::commit_replicated`.
+///
+/// Implemented for every `DatabaseSet`.
+pub trait DynDatabaseSet<V, K = KeyHash> {
+    fn set_dyn(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    ) -> Result<(), TrieError>;
+}
+
+impl<V, K, D: DatabaseSet<V, K>> DynDatabaseSet<V, K> for D {
+    #[inline]
+    fn set_dyn(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    ) -> Result<(), TrieError> {
+        self.set(hash, node).map_err(Into::into)
+    }
 }
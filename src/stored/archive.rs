@@ -0,0 +1,105 @@
+//! Proofs about past roots, read from a database that keeps every node it was ever given.
+//!
+//! Nodes are keyed by their hash, so a database that never deletes a node can still walk the trie at any past root.
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use crate::{
+    errors::error_context,
+    stored::{DatabaseGet, Node, NodeHash},
+    transaction::nodes::KeyPosition,
+    InclusionProof, KeyHash, TrieError, TrieKey, TrieRoot,
+};
+
+/// Looks keys up in past roots of a trie, producing an `InclusionProof` for each leaf found.
+///
+/// The database must retain the nodes of every root queried,
+/// so it must not prune nodes orphaned by later commits.
+#[derive(Debug)]
+pub struct RootArchive<Db, V, K = KeyHash> {
+    db: Db,
+    _marker: PhantomData<fn() -> (V, K)>,
+}
+
+impl<Db, V, K> RootArchive<Db, V, K> {
+    #[inline]
+    pub fn new(db: Db) -> Self {
+        RootArchive {
+            db,
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+}
+
+impl<Db: DatabaseGet<V, K>, V, K: TrieKey> RootArchive<Db, V, K> {
+    /// Prove the leaf at `key_hash` in the trie at `root`, or return `None` if the key is absent.
+    ///
+    /// Reads one node per level of the trie.
+    #[inline]
+    pub fn prove(
+        &self,
+        root: TrieRoot<NodeHash>,
+        key_hash: &K,
+    ) -> Result<Option<InclusionProof<V, K>>, TrieError> {
+        let TrieRoot::Node(mut hash) = root else {
+            return Ok(None);
+        };
+
+        let mut path = Vec::new();
+        loop {
+            let node = self.db.get(&hash).map_err(|e| {
+                error_context(e, format_args!("Error getting {hash} from the archive"))
+            })?;
+
+            match node {
+                Node::Branch(branch) => {
+                    hash = match branch.key_position(key_hash) {
+                        KeyPosition::Left => branch.left,
+                        KeyPosition::Right => branch.right,
+                        KeyPosition::Adjacent(_) => return Ok(None),
+                    };
+                    path.push(branch);
+                }
+                Node::Leaf(leaf) if leaf.key_hash == *key_hash => {
+                    return Ok(Some(InclusionProof {
+                        path: path.into_boxed_slice(),
+                        leaf,
+                    }));
+                }
+                Node::Leaf(_) => return Ok(None),
+            }
+        }
+    }
+
+    /// Find the roots whose trie maps `key_hash` to `value`,
+    /// returning the index of each in `roots` along with its proof.
+    ///
+    /// Roots are checked independently, so `roots` need not be consecutive or ordered.
+    #[inline]
+    pub fn prove_entry_in_roots(
+        &self,
+        roots: &[TrieRoot<NodeHash>],
+        key_hash: &K,
+        value: &V,
+    ) -> Result<Vec<(usize, InclusionProof<V, K>)>, TrieError>
+    where
+        V: PartialEq,
+    {
+        let mut proofs = Vec::new();
+        for (idx, root) in roots.iter().enumerate() {
+            if let Some(proof) = self.prove(*root, key_hash)? {
+                if proof.leaf.value == *value {
+                    proofs.push((idx, proof));
+                }
+            }
+        }
+
+        Ok(proofs)
+    }
+}
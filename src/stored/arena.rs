@@ -0,0 +1,101 @@
+//! An append-only list that can grow while references into it are held.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::cell::{Cell, OnceCell};
+
+/// `log2` of the number of items in the first chunk.
+const FIRST_CHUNK_BITS: u32 = 6;
+const FIRST_CHUNK: usize = 1 << FIRST_CHUNK_BITS;
+/// Enough chunks to hold `usize::MAX` items.
+const CHUNKS: usize = (usize::BITS - FIRST_CHUNK_BITS) as usize;
+
+/// Items are stored in chunks that are never reallocated,
+/// so pushing through a shared reference never moves an item already in the arena.
+///
+/// Chunk `i` holds `FIRST_CHUNK << i` items, so an index maps to its chunk in constant time.
+pub(crate) struct AppendOnly<T> {
+    chunks: [OnceCell<Box<[OnceCell<T>]>>; CHUNKS],
+    len: Cell<usize>,
+}
+
+impl<T> AppendOnly<T> {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        AppendOnly {
+            chunks: [const { OnceCell::new() }; CHUNKS],
+            len: Cell::new(0),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append `item`, returning its index.
+    #[inline]
+    pub(crate) fn push(&self, item: T) -> usize {
+        let idx = self.len.get();
+        let (chunk, offset) = locate(idx);
+        let chunk = self.chunks[chunk].get_or_init(|| {
+            (0..FIRST_CHUNK << chunk)
+                .map(|_| OnceCell::new())
+                .collect::<Vec<_>>()
+                .into_boxed_slice()
+        });
+
+        if chunk[offset].set(item).is_err() {
+            unreachable!("AppendOnly slot {idx} is past the end but already set");
+        }
+        self.len.set(idx + 1);
+        idx
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, idx: usize) -> Option<&T> {
+        if idx >= self.len.get() {
+            return None;
+        }
+        let (chunk, offset) = locate(idx);
+        self.chunks[chunk].get()?[offset].get()
+    }
+
+    #[inline]
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len()).filter_map(|idx| self.get(idx))
+    }
+
+    /// Drop every item, keeping the chunks allocated for reuse.
+    #[inline]
+    pub(crate) fn clear(&mut self) {
+        for idx in 0..self.len() {
+            let (chunk, offset) = locate(idx);
+            if let Some(chunk) = self.chunks[chunk].get_mut() {
+                chunk[offset].take();
+            }
+        }
+        self.len.set(0);
+    }
+}
+
+impl<T> Default for AppendOnly<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The chunk holding `idx`, and its offset within that chunk.
+#[inline]
+fn locate(idx: usize) -> (usize, usize) {
+    // Chunk `i` starts at `(FIRST_CHUNK << i) - FIRST_CHUNK`.
+    // `idx + FIRST_CHUNK` does not overflow, since the arena can't hold more items than fit in memory.
+    let shifted = idx + FIRST_CHUNK;
+    let chunk = (usize::BITS - 1 - shifted.leading_zeros() - FIRST_CHUNK_BITS) as usize;
+    (chunk, shifted - (FIRST_CHUNK << chunk))
+}
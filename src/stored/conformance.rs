@@ -0,0 +1,236 @@
+//! A conformance suite for `DatabaseGet` and `DatabaseSet` implementations.
+//!
+//! `check_database` drives a database through randomized rounds of commits,
+//! and after each one reloads the trie from the database and compares it against a `NaiveMerkleMap`.
+//! Rounds are generated from `ConformanceConfig::seed`, so a reported `Violation` replays exactly.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Display};
+
+use crate::{
+    naive::NaiveMerkleMap,
+    stored::{merkle::SnapshotBuilder, DatabaseGet, DatabaseSet, NodeHash},
+    KeyHash, PortableHasher, Transaction, TrieRoot,
+};
+
+/// How many rounds `check_database` runs, and what each round does.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ConformanceConfig {
+    pub seed: u64,
+    /// Each round commits one transaction and reloads the trie it wrote.
+    pub rounds: u32,
+    /// Inserts and removes per transaction.
+    pub ops_per_round: u32,
+}
+
+impl Default for ConformanceConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            rounds: 32,
+            ops_per_round: 64,
+        }
+    }
+}
+
+/// What a successful `check_database` run covered.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ConformanceReport {
+    pub rounds: u32,
+    /// The entries in the trie after the last round.
+    pub entries: usize,
+    pub root: TrieRoot<NodeHash>,
+}
+
+/// The first behavior of a database that a trie cannot rely on.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Violation {
+    pub seed: u64,
+    /// The round that found the violation, counting from 0.
+    pub round: u32,
+    pub message: String,
+}
+
+impl Display for Violation {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Database conformance violation in round {} of seed {}: {}",
+            self.round, self.seed, self.message
+        )
+    }
+}
+
+/// Run the conformance suite against `db`, which should start empty.
+///
+/// Each round:
+/// 1. Applies random inserts, overwrites and removes of `Vec<u8>` values to the last committed root.
+/// 2. Commits, and checks the new root against a `NaiveMerkleMap` holding the same entries.
+/// 3. Reloads the new root and the previous one, checking every entry, some absent keys,
+///    and that the nodes read hash back to the root.
+///    Committing must not make the nodes of earlier roots unreadable.
+/// 4. Checks that getting a node that was never set returns an error.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn check_database<Db: DatabaseSet<Vec<u8>>>(
+    db: &Db,
+    hasher: &mut impl PortableHasher<32>,
+    config: ConformanceConfig,
+) -> Result<ConformanceReport, Violation> {
+    let mut rng = SplitMix64(config.seed);
+    let mut root = TrieRoot::Empty;
+    let mut entries = NaiveMerkleMap::new();
+
+    for round in 0..config.rounds {
+        let violation = |message: String| Violation {
+            seed: config.seed,
+            round,
+            message,
+        };
+
+        let prev_root = root;
+        let prev_entries = entries.clone();
+
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for _ in 0..config.ops_per_round {
+            let key = rng.key(&entries);
+            let result = if rng.below(6) == 0 {
+                entries.remove(&key);
+                txn.remove(&key).map(drop)
+            } else {
+                let value = rng.value();
+                entries.insert(key, value.clone());
+                txn.insert(&key, value)
+            };
+            result.map_err(|e| violation(format!("Reading the trie at {root:?} failed: {e}")))?;
+        }
+
+        root = txn
+            .commit(hasher)
+            .map_err(|e| violation(format!("Commit failed: {e}")))?
+            .root;
+        let expected = entries.root_hash(hasher);
+        if root != expected {
+            return Err(violation(format!(
+                "Committed root {root:?} does not match the expected root {expected:?}"
+            )));
+        }
+
+        check_root(db, hasher, &mut rng, root, &entries).map_err(violation)?;
+        check_root(db, hasher, &mut rng, prev_root, &prev_entries)
+            .map_err(|e| violation(format!("Previous root {prev_root:?}: {e}")))?;
+
+        let missing = NodeHash::new(rng.bytes());
+        if db.get(&missing).is_ok() {
+            return Err(violation(format!(
+                "Getting {missing:?}, which was never set, did not return an error"
+            )));
+        }
+    }
+
+    Ok(ConformanceReport {
+        rounds: config.rounds,
+        entries: entries.len(),
+        root,
+    })
+}
+
+/// Reload the trie at `root` from `db`, and check it holds exactly `entries`.
+fn check_root<Db: DatabaseGet<Vec<u8>>>(
+    db: &Db,
+    hasher: &mut impl PortableHasher<32>,
+    rng: &mut SplitMix64,
+    root: TrieRoot<NodeHash>,
+    entries: &NaiveMerkleMap<Vec<u8>>,
+) -> Result<(), String> {
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+
+    for (key, value) in entries.iter() {
+        match txn.get(key) {
+            Ok(Some(got)) if got == value => {}
+            Ok(got) => {
+                return Err(format!(
+                    "Reloaded {key:?} as {got:?}, but {value:?} was committed"
+                ))
+            }
+            Err(e) => return Err(format!("Reloading {key:?} failed: {e}")),
+        }
+    }
+
+    for _ in 0..8 {
+        let key = KeyHash(rng.words());
+        if entries.get(&key).is_some() {
+            continue;
+        }
+        match txn.get(&key) {
+            Ok(None) => {}
+            Ok(Some(got)) => {
+                return Err(format!(
+                    "Reloaded {key:?} as {got:?}, but it was never inserted"
+                ))
+            }
+            Err(e) => return Err(format!("Reloading absent {key:?} failed: {e}")),
+        }
+    }
+
+    // `get` trusts the database, only hashing the nodes it returned shows they are the nodes that were set.
+    let actual = txn
+        .build_initial_snapshot()
+        .calc_root_hash(hasher)
+        .map_err(|e| e.to_string())?;
+    if actual != root {
+        return Err(format!(
+            "The nodes read hash to {actual:?}, they are not the nodes committed"
+        ));
+    }
+    Ok(())
+}
+
+/// A small deterministic generator, so the suite needs no `rand` dependency.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn words(&mut self) -> [u32; 8] {
+        core::array::from_fn(|_| self.next() as u32)
+    }
+
+    fn bytes(&mut self) -> [u8; 32] {
+        KeyHash(self.words()).to_bytes()
+    }
+
+    fn value(&mut self) -> Vec<u8> {
+        let len = self.below(48) as usize;
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    /// An existing key, a small index sharing long prefixes with other indices, or a random key.
+    fn key(&mut self, entries: &NaiveMerkleMap<Vec<u8>>) -> KeyHash {
+        match self.below(4) {
+            0 if !entries.is_empty() => {
+                let idx = self.below(entries.len() as u64) as usize;
+                *entries.iter().nth(idx).unwrap().0
+            }
+            1 => KeyHash::from_u64(self.below(256)),
+            _ => KeyHash(self.words()),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Witnesses for blocks that only change the values of existing keys.
+
+use alloc::{boxed::Box, format, vec::Vec};
+
+use crate::{
+    transaction::nodes::{hash_leaf, TrieRoot},
+    Branch, KeyHash, NodeHash, PortableHash, PortableHasher, TrieError, TrieKey, VerifyError,
+};
+
+use super::{
+    merkle::{BranchIdx, Layout, LeafIdx, NodeIdx, Snapshot, UnvisitedIdx},
+    Idx,
+};
+
+type Result<T, E = TrieError> = core::result::Result<T, E>;
+
+/// A leaf of a `DeltaSnapshot`, with its value before and after the block.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeltaLeaf<V, K = KeyHash> {
+    pub key_hash: K,
+    pub old_value: V,
+    /// `None` if the block reads the leaf without changing it.
+    pub new_value: Option<V>,
+}
+
+/// A snapshot of the leaves a block updates, carrying each new value beside the old one.
+///
+/// A block that only overwrites existing keys leaves the shape of the trie unchanged,
+/// so the guest does not need a `Transaction` to replay it.
+/// `verify` hashes the snapshot once, computing the old and new hash of each node together,
+/// and the new values travel in the witness instead of a separate batch that repeats every key.
+///
+/// The old values are still needed.
+/// A leaf hashes its key and value bytes together, not a hash of the value,
+/// so without the old value nothing would tie a leaf's key to the old root,
+/// and a prover could replace one key with another on the same path.
+///
+/// Shares the layout of `Snapshot`: branches refer to their children by an `Idx`
+/// counting the branches, then the leaves, then the unvisited nodes.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeltaSnapshot<V, K = KeyHash> {
+    /// The last branch is the root of the trie if it exists.
+    branches: Box<[Branch<Idx>]>,
+    leaves: Box<[DeltaLeaf<V, K>]>,
+    unvisited_nodes: Box<[NodeHash]>,
+}
+
+impl<V, K: TrieKey> DeltaSnapshot<V, K> {
+    /// Pair each leaf of `snapshot` with its new value from `updates`.
+    ///
+    /// `snapshot` is usually built by a `SnapshotBuilder` over the keys the block touches.
+    /// Each update must be to a key with a leaf in the snapshot, the last update to a key wins.
+    #[inline]
+    pub fn new(
+        snapshot: Snapshot<V, K>,
+        updates: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self> {
+        let (branches, leaves, unvisited_nodes) = snapshot.into_parts();
+
+        let mut by_key: Vec<usize> = (0..leaves.len()).collect();
+        by_key.sort_by(|&a, &b| leaves[a].key_hash.cmp_trie_order(&leaves[b].key_hash));
+
+        let mut new_values: Vec<Option<V>> = (0..leaves.len()).map(|_| None).collect();
+        for (key, value) in updates {
+            let idx = by_key
+                .binary_search_by(|&idx| leaves[idx].key_hash.cmp_trie_order(&key))
+                .map_err(|_| format!("Cannot update {key:?}: the snapshot has no leaf for it"))?;
+            new_values[by_key[idx]] = Some(value);
+        }
+
+        let leaves = Vec::from(leaves)
+            .into_iter()
+            .zip(new_values)
+            .map(|(leaf, new_value)| DeltaLeaf {
+                key_hash: leaf.key_hash,
+                old_value: leaf.value,
+                new_value,
+            })
+            .collect();
+
+        Ok(DeltaSnapshot {
+            branches,
+            leaves,
+            unvisited_nodes,
+        })
+    }
+}
+
+impl<V, K> DeltaSnapshot<V, K> {
+    #[inline]
+    fn layout(&self) -> Layout {
+        Layout {
+            branches: self.branches.len() as Idx,
+            leaves: self.leaves.len() as Idx,
+            unvisited_nodes: self.unvisited_nodes.len() as Idx,
+        }
+    }
+
+    /// The leaves of the snapshot, in no particular order.
+    #[inline]
+    pub fn leaves(&self) -> &[DeltaLeaf<V, K>] {
+        &self.leaves
+    }
+
+    /// The leaves the block changes, with their old and new values.
+    #[inline]
+    pub fn changes(&self) -> impl Iterator<Item = (&K, &V, &V)> {
+        self.leaves.iter().filter_map(|leaf| {
+            let new_value = leaf.new_value.as_ref()?;
+            Some((&leaf.key_hash, &leaf.old_value, new_value))
+        })
+    }
+}
+
+impl<V: PortableHash, K: TrieKey> DeltaSnapshot<V, K> {
+    /// Check the snapshot is of the trie at `old_root`, and return the root with the new values applied.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        old_root: TrieRoot<NodeHash>,
+    ) -> Result<TrieRoot<NodeHash>, VerifyError> {
+        let layout = Layout::checked(
+            self.branches.len(),
+            self.leaves.len(),
+            self.unvisited_nodes.len(),
+        )?;
+        let (actual, new_root) = match layout.root()? {
+            TrieRoot::Node(root) => {
+                let (old, new) = self.hash_subtree(hasher, root, 0)?;
+                (TrieRoot::Node(old), TrieRoot::Node(new))
+            }
+            TrieRoot::Empty => (TrieRoot::Empty, TrieRoot::Empty),
+        };
+
+        if !actual.verify_eq(&old_root) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: old_root,
+                actual,
+            });
+        }
+        Ok(new_root)
+    }
+
+    /// The old and new hash of the subtree at `idx`.
+    fn hash_subtree(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        idx: Idx,
+        depth: usize,
+    ) -> Result<(NodeHash, NodeHash)> {
+        // No valid trie is deeper than its keys have bits, this also stops a cycle.
+        if depth > 256 {
+            return Err("Invalid delta snapshot: the trie is deeper than 256 branches".into());
+        }
+
+        match self.layout().decode(idx) {
+            Some(NodeIdx::Branch(BranchIdx(i))) => {
+                let branch = &self.branches[i as usize];
+                let (old_left, new_left) = self.hash_subtree(hasher, branch.left, depth + 1)?;
+                let (old_right, new_right) = self.hash_subtree(hasher, branch.right, depth + 1)?;
+
+                let old = branch.hash_branch(hasher, &old_left, &old_right);
+                let new = if (old_left, old_right) == (new_left, new_right) {
+                    old
+                } else {
+                    branch.hash_branch(hasher, &new_left, &new_right)
+                };
+                Ok((old, new))
+            }
+            Some(NodeIdx::Leaf(LeafIdx(i))) => {
+                let leaf = &self.leaves[i as usize];
+                let old = hash_leaf(hasher, &leaf.key_hash, &leaf.old_value);
+                let new = match &leaf.new_value {
+                    Some(value) => hash_leaf(hasher, &leaf.key_hash, value),
+                    None => old,
+                };
+                Ok((old, new))
+            }
+            Some(NodeIdx::Unvisited(UnvisitedIdx(i))) => {
+                let hash = self.unvisited_nodes[i as usize];
+                Ok((hash, hash))
+            }
+            None => Err(format!("Invalid delta snapshot: node {idx} not found").into()),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! A database that fails on purpose, for testing how callers handle a misbehaving store.
+//!
+//! Faults follow a deterministic schedule keyed by the index of the database call,
+//! so a failing test case replays exactly.
+
+use alloc::collections::BTreeMap;
+use core::{
+    error::Error,
+    fmt::{self, Display},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    stored::{merkle::Snapshot, DatabaseGet, DatabaseSet, Node, NodeHash},
+    Branch, Leaf, PortableHash, TrieError,
+};
+
+/// What a `FaultyDb` does instead of a database call.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fault {
+    /// Return an error.
+    Error,
+    /// Return a node that does not hash to the requested hash.
+    /// On `set`, this is the same as `Fault::Error`.
+    WrongNode,
+}
+
+/// The error of a call that a `FaultyDb` failed on purpose.
+///
+/// It is the source of the `TrieError` the call returns,
+/// so tests of retry policies can tell injected faults from real ones with `TrieError::downcast_source`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InjectedFault {
+    pub call_idx: u64,
+    pub hash: NodeHash,
+}
+
+impl Display for InjectedFault {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Injected fault: call {} failed for {}",
+            self.call_idx, self.hash
+        )
+    }
+}
+
+impl Error for InjectedFault {}
+
+/// Wraps a database, injecting a `Fault` into the calls picked by its schedule.
+///
+/// Calls to `get` and `set` share one counter, starting at 0.
+/// The counter is atomic, so a `FaultyDb` over a `Sync` database can be shared between threads,
+/// but which thread's call gets a fault then depends on scheduling.
+#[derive(Debug)]
+pub struct FaultyDb<D> {
+    db: D,
+    schedule: BTreeMap<u64, Fault>,
+    calls: AtomicU64,
+}
+
+impl<D> FaultyDb<D> {
+    /// Inject each `(call_idx, fault)` of `schedule` into the `call_idx`th database call.
+    #[inline]
+    pub fn new(db: D, schedule: impl IntoIterator<Item = (u64, Fault)>) -> Self {
+        FaultyDb {
+            db,
+            schedule: schedule.into_iter().collect(),
+            calls: AtomicU64::new(0),
+        }
+    }
+
+    /// Inject `fault` into every `period`th call, starting with call `offset`.
+    ///
+    /// Faults are scheduled for the first `calls` calls only.
+    #[inline]
+    pub fn every(db: D, period: u64, offset: u64, calls: u64, fault: Fault) -> Self {
+        Self::new(
+            db,
+            (offset..calls)
+                .step_by(period.max(1) as usize)
+                .map(|call_idx| (call_idx, fault)),
+        )
+    }
+
+    /// The number of database calls made so far.
+    #[inline]
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    #[inline]
+    fn next_fault(&self) -> (u64, Option<Fault>) {
+        let call_idx = self.calls.fetch_add(1, Ordering::Relaxed);
+        (call_idx, self.schedule.get(&call_idx).copied())
+    }
+}
+
+impl<V, D: DatabaseGet<V>> DatabaseGet<V> for FaultyDb<D> {
+    type GetError = TrieError;
+
+    #[inline]
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V>>, Self::GetError> {
+        let (call_idx, fault) = self.next_fault();
+        if fault == Some(Fault::Error) {
+            return Err(TrieError::from_source(InjectedFault {
+                call_idx,
+                hash: *hash,
+            }));
+        }
+
+        let node = self.db.get(hash).map_err(Into::into)?;
+        if fault != Some(Fault::WrongNode) {
+            return Ok(node);
+        }
+
+        Ok(match node {
+            Node::Branch(branch) => Node::Branch(Branch {
+                left: branch.right,
+                right: branch.left,
+                ..branch
+            }),
+            Node::Leaf(mut leaf) => {
+                leaf.key_hash.0[7] ^= 1;
+                Node::Leaf(leaf)
+            }
+        })
+    }
+}
+
+impl<V, D: DatabaseSet<V>> DatabaseSet<V> for FaultyDb<D> {
+    type SetError = TrieError;
+
+    #[inline]
+    fn set(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<V>>,
+    ) -> Result<(), Self::SetError> {
+        let (call_idx, fault) = self.next_fault();
+        if fault.is_some() {
+            return Err(TrieError::from_source(InjectedFault { call_idx, hash }));
+        }
+
+        self.db.set(hash, node).map_err(Into::into)
+    }
+
+    #[inline]
+    fn begin_batch(&self) -> Result<(), Self::SetError> {
+        self.db.begin_batch().map_err(Into::into)
+    }
+
+    #[inline]
+    fn end_batch(&self, completed: bool) -> Result<(), Self::SetError> {
+        self.db.end_batch(completed).map_err(Into::into)
+    }
+}
+
+/// A copy of `snapshot` with the last `branches`, `leaves` and `unvisited_nodes` entries of its arrays cut off,
+/// as if it had been truncated in transit.
+#[inline]
+pub fn truncate_snapshot<V: PortableHash + Clone>(
+    snapshot: &Snapshot<V>,
+    branches: usize,
+    leaves: usize,
+    unvisited_nodes: usize,
+) -> Snapshot<V> {
+    snapshot.truncated(branches, leaves, unvisited_nodes)
+}
@@ -0,0 +1,457 @@
+//! A `Snapshot` laid out over borrowed arrays, for guests that cannot afford heap allocations.
+//!
+//! `FlatSnapshot` never allocates, not even for errors.
+//! With a value type that hashes without allocating, such as `[u8; N]`,
+//! computing the root hash of a snapshot and reading values from it run entirely over the flat arrays.
+//! A minimal guest can therefore verify snapshots under an allocator that panics on use,
+//! or that never frees.
+//!
+//! The host produces the arrays with `Snapshot::to_flat`.
+//!
+//! For `[u8; N]` values, `FixedFlatSnapshot` packs the leaves into a single byte array instead,
+//! produced with `Snapshot::to_fixed_flat`.
+
+use alloc::boxed::Box;
+
+use crate::{
+    spec::MAX_BRANCH_PREFIX_WORDS,
+    transaction::nodes::{self, finalize_node, BranchMask, KeyPosition, Leaf, TrieRoot},
+    FlatError, KeyHash, NodeHash, PortableHash, PortableHasher,
+};
+
+use super::{idx_to_usize, indexable, Idx};
+
+type Result<T, E = FlatError> = core::result::Result<T, E>;
+
+/// A `Branch<Idx>` whose prefix is stored out of line, in `FlatSnapshot::prefixes`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FlatBranch {
+    pub left: Idx,
+    pub right: Idx,
+    pub mask: BranchMask,
+    pub prior_word: u32,
+    /// The branch's prefix is `prefixes[prefix_start..prefix_start + prefix_len]`.
+    pub prefix_start: Idx,
+    pub prefix_len: u32,
+}
+
+/// A `Snapshot` over borrowed arrays.
+///
+/// Nodes are indexed as in a `Snapshot`: branches first, then leaves, then unvisited nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FlatSnapshot<'a, V> {
+    arrays: FlatArrays<'a, &'a [Leaf<V>]>,
+}
+
+/// A `FlatSnapshot` of `[u8; N]` values, with the leaves packed into a single byte array.
+///
+/// Each leaf takes `32 + N` bytes, the `KeyHash::to_bytes` of its key followed by its value.
+/// That is the leaf's hash preimage, so hashing a leaf is a single hasher update,
+/// and finding one is pointer arithmetic.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FixedFlatSnapshot<'a, const N: usize> {
+    arrays: FlatArrays<'a, FixedLeaves<'a, N>>,
+}
+
+/// The arrays of a flat snapshot, generic over how its leaves are laid out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct FlatArrays<'a, L> {
+    branches: &'a [FlatBranch],
+    prefixes: &'a [u32],
+    leaves: L,
+    unvisited_nodes: &'a [NodeHash],
+}
+
+/// The leaves of a flat snapshot, indexed from 0.
+trait FlatLeaves<'a>: Copy {
+    type Value: 'a;
+
+    fn len(self) -> usize;
+
+    fn key_hash(self, leaf_idx: usize) -> KeyHash;
+
+    fn value(self, leaf_idx: usize) -> &'a Self::Value;
+}
+
+/// Flat leaves that can be hashed without allocating.
+trait HashFlatLeaves<'a>: FlatLeaves<'a> {
+    /// Caller must ensure that the hasher is reset before calling this function.
+    fn hash_leaf<H: PortableHasher<32>>(self, leaf_idx: usize, hasher: &mut H) -> NodeHash;
+}
+
+impl<'a, V> FlatLeaves<'a> for &'a [Leaf<V>] {
+    type Value = V;
+
+    #[inline]
+    fn len(self) -> usize {
+        <[Leaf<V>]>::len(self)
+    }
+
+    #[inline]
+    fn key_hash(self, leaf_idx: usize) -> KeyHash {
+        self[leaf_idx].key_hash
+    }
+
+    #[inline]
+    fn value(self, leaf_idx: usize) -> &'a V {
+        &self[leaf_idx].value
+    }
+}
+
+impl<'a, V: PortableHash> HashFlatLeaves<'a> for &'a [Leaf<V>] {
+    #[inline]
+    fn hash_leaf<H: PortableHasher<32>>(self, leaf_idx: usize, hasher: &mut H) -> NodeHash {
+        self[leaf_idx].hash_leaf(hasher)
+    }
+}
+
+/// Leaves packed `32 + N` bytes apart.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct FixedLeaves<'a, const N: usize>(&'a [u8]);
+
+impl<'a, const N: usize> FixedLeaves<'a, N> {
+    const STRIDE: usize = 32 + N;
+
+    #[inline]
+    fn leaf(self, leaf_idx: usize) -> (&'a [u8; 32], &'a [u8; N]) {
+        let start = leaf_idx * Self::STRIDE;
+        let (key, value) = self.0[start..start + Self::STRIDE].split_at(32);
+        (
+            key.try_into().expect("split at 32"),
+            value.try_into().expect("the rest of the stride is N bytes"),
+        )
+    }
+}
+
+impl<'a, const N: usize> FlatLeaves<'a> for FixedLeaves<'a, N> {
+    type Value = [u8; N];
+
+    #[inline]
+    fn len(self) -> usize {
+        self.0.len() / Self::STRIDE
+    }
+
+    #[inline]
+    fn key_hash(self, leaf_idx: usize) -> KeyHash {
+        KeyHash::from_bytes(self.leaf(leaf_idx).0)
+    }
+
+    #[inline]
+    fn value(self, leaf_idx: usize) -> &'a [u8; N] {
+        self.leaf(leaf_idx).1
+    }
+}
+
+impl<'a, const N: usize> HashFlatLeaves<'a> for FixedLeaves<'a, N> {
+    #[inline]
+    fn hash_leaf<H: PortableHasher<32>>(self, leaf_idx: usize, hasher: &mut H) -> NodeHash {
+        if !H::LEAF_TAG.is_empty() {
+            hasher.portable_update(H::LEAF_TAG);
+        }
+        let start = leaf_idx * Self::STRIDE;
+        hasher.portable_update(&self.0[start..start + Self::STRIDE]);
+        finalize_node(hasher, || 1)
+    }
+}
+
+enum FlatNode<'a> {
+    Branch(&'a FlatBranch, &'a [u32]),
+    Leaf(usize),
+    Unvisited(&'a NodeHash),
+}
+
+impl<'a, L: FlatLeaves<'a>> FlatArrays<'a, L> {
+    #[inline]
+    fn root_node_idx(&self) -> Result<TrieRoot<Idx>> {
+        let nodes = self.branches.len() + self.leaves.len() + self.unvisited_nodes.len();
+        if !indexable(nodes) {
+            return Err(FlatError::TooManyNodes(nodes));
+        }
+        match (
+            self.branches.len(),
+            self.leaves.len(),
+            self.unvisited_nodes.len(),
+        ) {
+            (0, 0, 0) => Ok(TrieRoot::Empty),
+            (1, 0, 0) | (0, 1, 0) | (0, 0, 1) => Ok(TrieRoot::Node(0)),
+            (branches, _, _) if branches != 0 => Ok(TrieRoot::Node(branches as Idx - 1)),
+            (branches, leaves, unvisited_nodes) => Err(FlatError::InvalidShape {
+                branches,
+                leaves,
+                unvisited_nodes,
+            }),
+        }
+    }
+
+    #[inline]
+    fn node(&self, idx: Idx) -> Result<FlatNode<'a>> {
+        let i = idx_to_usize(idx).ok_or(FlatError::NodeNotFound(idx))?;
+        let leaf_offset = self.branches.len();
+        let unvisited_offset = leaf_offset + self.leaves.len();
+
+        if let Some(branch) = self.branches.get(i) {
+            let prefix_len = branch.prefix_len as usize;
+            if prefix_len > MAX_BRANCH_PREFIX_WORDS || prefix_len > branch.mask.word_idx() {
+                return Err(FlatError::PrefixTooLong(idx));
+            }
+            let start =
+                idx_to_usize(branch.prefix_start).ok_or(FlatError::PrefixOutOfRange(idx))?;
+            let prefix = start
+                .checked_add(prefix_len)
+                .and_then(|end| self.prefixes.get(start..end))
+                .ok_or(FlatError::PrefixOutOfRange(idx))?;
+            Ok(FlatNode::Branch(branch, prefix))
+        } else if i < unvisited_offset {
+            Ok(FlatNode::Leaf(i - leaf_offset))
+        } else if let Some(hash) = self.unvisited_nodes.get(i - unvisited_offset) {
+            Ok(FlatNode::Unvisited(hash))
+        } else {
+            Err(FlatError::NodeNotFound(idx))
+        }
+    }
+
+    /// Check the branch at `idx` discriminates on a later bit than its parent's `parent_bit_idx`,
+    /// so a corrupt snapshot cannot make a traversal loop, or recurse deeper than 256 branches.
+    #[inline]
+    fn check_bit_order(
+        branch: &FlatBranch,
+        idx: Idx,
+        parent_bit_idx: Option<u32>,
+    ) -> Result<Option<u32>> {
+        let bit_idx = branch.mask.bit_idx();
+        if bit_idx >= 256 || parent_bit_idx.is_some_and(|parent_bit_idx| bit_idx <= parent_bit_idx)
+        {
+            return Err(FlatError::BranchOutOfOrder(idx));
+        }
+        Ok(Some(bit_idx))
+    }
+
+    #[inline]
+    fn get(&self, key_hash: &KeyHash) -> Result<Option<&'a L::Value>> {
+        let TrieRoot::Node(mut idx) = self.root_node_idx()? else {
+            return Ok(None);
+        };
+        let mut parent_bit_idx = None;
+
+        loop {
+            match self.node(idx)? {
+                FlatNode::Branch(branch, prefix) => {
+                    parent_bit_idx = Self::check_bit_order(branch, idx, parent_bit_idx)?;
+                    match nodes::key_position(&branch.mask, branch.prior_word, prefix, key_hash) {
+                        KeyPosition::Left => idx = branch.left,
+                        KeyPosition::Right => idx = branch.right,
+                        KeyPosition::Adjacent(_) => return Ok(None),
+                    }
+                }
+                FlatNode::Leaf(leaf_idx) => {
+                    return Ok((self.leaves.key_hash(leaf_idx) == *key_hash)
+                        .then(|| self.leaves.value(leaf_idx)));
+                }
+                FlatNode::Unvisited(_) => return Err(FlatError::Unvisited(idx)),
+            }
+        }
+    }
+}
+
+impl<'a, L: HashFlatLeaves<'a>> FlatArrays<'a, L> {
+    #[inline]
+    fn calc_root_hash(&self, hasher: &mut impl PortableHasher<32>) -> Result<TrieRoot<NodeHash>> {
+        let TrieRoot::Node(idx) = self.root_node_idx()? else {
+            return Ok(TrieRoot::Empty);
+        };
+
+        let mut budget = self.branches.len() + self.leaves.len() + self.unvisited_nodes.len();
+        Ok(TrieRoot::Node(self.calc_subtree_hash(
+            hasher,
+            idx,
+            None,
+            &mut budget,
+        )?))
+    }
+
+    // A well formed trie is at most 257 nodes deep, so recursing needs no heap allocated stack.
+    // Discriminant bits must grow along a path, which keeps a corrupt snapshot to that depth too,
+    // and each node visited takes one from `budget`, so shared subtrees cannot blow up the work.
+    #[inline]
+    fn calc_subtree_hash(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        idx: Idx,
+        parent_bit_idx: Option<u32>,
+        budget: &mut usize,
+    ) -> Result<NodeHash> {
+        let node = self.node(idx)?;
+        *budget = budget.checked_sub(1).ok_or(FlatError::SharedNode(idx))?;
+
+        match node {
+            FlatNode::Branch(branch, prefix) => {
+                let bit_idx = Self::check_bit_order(branch, idx, parent_bit_idx)?;
+                let left = self.calc_subtree_hash(hasher, branch.left, bit_idx, budget)?;
+                let right = self.calc_subtree_hash(hasher, branch.right, bit_idx, budget)?;
+
+                Ok(nodes::hash_branch(
+                    hasher,
+                    &branch.mask,
+                    branch.prior_word,
+                    prefix,
+                    &left,
+                    &right,
+                ))
+            }
+            FlatNode::Leaf(leaf_idx) => Ok(self.leaves.hash_leaf(leaf_idx, hasher)),
+            FlatNode::Unvisited(hash) => Ok(*hash),
+        }
+    }
+}
+
+impl<'a, V> FlatSnapshot<'a, V> {
+    /// The arrays are not checked until they are read.
+    #[inline]
+    pub const fn new(
+        branches: &'a [FlatBranch],
+        prefixes: &'a [u32],
+        leaves: &'a [Leaf<V>],
+        unvisited_nodes: &'a [NodeHash],
+    ) -> Self {
+        FlatSnapshot {
+            arrays: FlatArrays {
+                branches,
+                prefixes,
+                leaves,
+                unvisited_nodes,
+            },
+        }
+    }
+
+    #[inline]
+    pub fn root_node_idx(&self) -> Result<TrieRoot<Idx>> {
+        self.arrays.root_node_idx()
+    }
+
+    /// Returns the value at `key_hash`, or `None` if the snapshot proves the key is absent.
+    ///
+    /// Returns `FlatError::Unvisited` if the snapshot does not contain the path to `key_hash`.
+    #[inline]
+    pub fn get(&self, key_hash: &KeyHash) -> Result<Option<&'a V>> {
+        self.arrays.get(key_hash)
+    }
+}
+
+impl<V: PortableHash> FlatSnapshot<'_, V> {
+    /// Calculate the merkle root hash of the snapshot, like `Snapshot::calc_root_hash`.
+    ///
+    /// Always check that the snapshot is of the merkle tree you expect.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn calc_root_hash(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>> {
+        self.arrays.calc_root_hash(hasher)
+    }
+}
+
+impl<'a, const N: usize> FixedFlatSnapshot<'a, N> {
+    /// `leaves` holds `32 + N` bytes per leaf.
+    /// The arrays are not checked until they are read.
+    #[inline]
+    pub const fn new(
+        branches: &'a [FlatBranch],
+        prefixes: &'a [u32],
+        leaves: &'a [u8],
+        unvisited_nodes: &'a [NodeHash],
+    ) -> Self {
+        FixedFlatSnapshot {
+            arrays: FlatArrays {
+                branches,
+                prefixes,
+                leaves: FixedLeaves(leaves),
+                unvisited_nodes,
+            },
+        }
+    }
+
+    #[inline]
+    pub fn root_node_idx(&self) -> Result<TrieRoot<Idx>> {
+        let bytes = self.arrays.leaves.0.len();
+        let stride = FixedLeaves::<N>::STRIDE;
+        if !bytes.is_multiple_of(stride) {
+            return Err(FlatError::MisalignedLeaves { bytes, stride });
+        }
+
+        self.arrays.root_node_idx()
+    }
+
+    /// Returns the value at `key_hash`, or `None` if the snapshot proves the key is absent.
+    ///
+    /// Returns `FlatError::Unvisited` if the snapshot does not contain the path to `key_hash`.
+    #[inline]
+    pub fn get(&self, key_hash: &KeyHash) -> Result<Option<&'a [u8; N]>> {
+        self.root_node_idx()?;
+        self.arrays.get(key_hash)
+    }
+
+    /// Calculate the merkle root hash of the snapshot, like `Snapshot::calc_root_hash`.
+    ///
+    /// Always check that the snapshot is of the merkle tree you expect.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn calc_root_hash(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>> {
+        self.root_node_idx()?;
+        self.arrays.calc_root_hash(hasher)
+    }
+}
+
+/// The arrays of a `FlatSnapshot`, owned by the host that builds them.
+///
+/// Built by `Snapshot::to_flat`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FlatSnapshotBuf<V> {
+    pub branches: Box<[FlatBranch]>,
+    pub prefixes: Box<[u32]>,
+    pub leaves: Box<[Leaf<V>]>,
+    pub unvisited_nodes: Box<[NodeHash]>,
+}
+
+impl<V> FlatSnapshotBuf<V> {
+    #[inline]
+    pub fn as_flat(&self) -> FlatSnapshot<'_, V> {
+        FlatSnapshot::new(
+            &self.branches,
+            &self.prefixes,
+            &self.leaves,
+            &self.unvisited_nodes,
+        )
+    }
+}
+
+/// The arrays of a `FixedFlatSnapshot`, owned by the host that builds them.
+///
+/// Built by `Snapshot::to_fixed_flat`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FixedFlatSnapshotBuf<const N: usize> {
+    pub branches: Box<[FlatBranch]>,
+    pub prefixes: Box<[u32]>,
+    pub leaves: Box<[u8]>,
+    pub unvisited_nodes: Box<[NodeHash]>,
+}
+
+impl<const N: usize> FixedFlatSnapshotBuf<N> {
+    #[inline]
+    pub fn as_flat(&self) -> FixedFlatSnapshot<'_, N> {
+        FixedFlatSnapshot::new(
+            &self.branches,
+            &self.prefixes,
+            &self.leaves,
+            &self.unvisited_nodes,
+        )
+    }
+}
@@ -1,17 +1,49 @@
-use alloc::{collections::BTreeMap, format, string::String};
-use core::cell::RefCell;
+use alloc::collections::BTreeMap;
+use core::{
+    cell::RefCell,
+    error::Error,
+    fmt::{self, Display},
+};
 
 use crate::{
     stored::{DatabaseGet, DatabaseSet, Node, NodeHash},
-    Branch, Leaf,
+    Branch, KeyHash, Leaf, TrieError,
 };
 
+/// The reason a `MemoryDb` or `SyncMemoryDb` call failed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemoryDbError {
+    /// No node with this hash was set.
+    NotFound(NodeHash),
+    /// A thread panicked while holding the lock of a `SyncMemoryDb`.
+    Poisoned,
+}
+
+impl Display for MemoryDbError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryDbError::NotFound(hash) => write!(f, "Hash: `{hash}` not found"),
+            MemoryDbError::Poisoned => write!(f, "SyncMemoryDb lock poisoned"),
+        }
+    }
+}
+
+impl Error for MemoryDbError {}
+
+impl From<MemoryDbError> for TrieError {
+    #[inline]
+    fn from(e: MemoryDbError) -> Self {
+        TrieError::from_source(e)
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
-pub struct MemoryDb<V> {
-    leaves: RefCell<BTreeMap<NodeHash, Node<Branch<NodeHash>, Leaf<V>>>>,
+pub struct MemoryDb<V, K = KeyHash> {
+    leaves: RefCell<BTreeMap<NodeHash, Node<Branch<NodeHash>, Leaf<V, K>>>>,
 }
 
-impl<V> MemoryDb<V> {
+impl<V, K> MemoryDb<V, K> {
     #[inline]
     pub fn empty() -> Self {
         Self {
@@ -20,29 +52,89 @@ impl<V> MemoryDb<V> {
     }
 }
 
-impl<V: Clone> DatabaseGet<V> for MemoryDb<V> {
-    type GetError = String;
+impl<V: Clone, K: Clone> DatabaseGet<V, K> for MemoryDb<V, K> {
+    type GetError = MemoryDbError;
 
     #[inline]
-    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V>>, Self::GetError> {
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V, K>>, Self::GetError> {
         self.leaves
             .borrow()
             .get(hash)
             .cloned()
-            .ok_or_else(|| format!("Hash: `{}` not found", hash))
+            .ok_or(MemoryDbError::NotFound(*hash))
     }
 }
 
-impl<V: Clone> DatabaseSet<V> for MemoryDb<V> {
-    type SetError = String;
+impl<V: Clone, K: Clone> DatabaseSet<V, K> for MemoryDb<V, K> {
+    type SetError = MemoryDbError;
 
     #[inline]
     fn set(
         &self,
         hash: NodeHash,
-        node: Node<Branch<NodeHash>, Leaf<V>>,
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
     ) -> Result<(), Self::SetError> {
         self.leaves.borrow_mut().insert(hash, node);
         Ok(())
     }
 }
+
+/// A `MemoryDb` behind a `RwLock`, so one store can be shared between threads.
+///
+/// Reads only take the lock in shared mode, so concurrent readers do not block each other.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct SyncMemoryDb<V, K = KeyHash> {
+    leaves: std::sync::RwLock<BTreeMap<NodeHash, Node<Branch<NodeHash>, Leaf<V, K>>>>,
+}
+
+#[cfg(feature = "std")]
+impl<V, K> SyncMemoryDb<V, K> {
+    #[inline]
+    pub fn empty() -> Self {
+        Self {
+            leaves: std::sync::RwLock::default(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<V, K> Default for SyncMemoryDb<V, K> {
+    #[inline]
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<V: Clone, K: Clone> DatabaseGet<V, K> for SyncMemoryDb<V, K> {
+    type GetError = MemoryDbError;
+
+    #[inline]
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V, K>>, Self::GetError> {
+        self.leaves
+            .read()
+            .map_err(|_| MemoryDbError::Poisoned)?
+            .get(hash)
+            .cloned()
+            .ok_or(MemoryDbError::NotFound(*hash))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<V: Clone, K: Clone> DatabaseSet<V, K> for SyncMemoryDb<V, K> {
+    type SetError = MemoryDbError;
+
+    #[inline]
+    fn set(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    ) -> Result<(), Self::SetError> {
+        self.leaves
+            .write()
+            .map_err(|_| MemoryDbError::Poisoned)?
+            .insert(hash, node);
+        Ok(())
+    }
+}
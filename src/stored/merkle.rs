@@ -1,94 +1,1410 @@
-use core::{cell::RefCell, ops::Deref};
+use core::{fmt, ops::RangeInclusive};
 
-use alloc::{boxed::Box, format, vec::Vec};
-use bumpalo::Bump;
-use ouroboros::self_referencing;
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    format, vec,
+    vec::Vec,
+};
 
 use crate::{
-    transaction::nodes::{NodeRef, TrieRoot},
-    Branch, Leaf, PortableHash, PortableHasher, TrieError,
+    errors::error_context,
+    spec::NodeLimits,
+    transaction::nodes::{key_bits, KeyPosition, NodeRef, TrieRoot, MAX_KEY_BITS},
+    walk, Branch, BranchMask, KeyHash, Leaf, PortableHash, PortableHasher, TrieError, TrieKey,
+    VerifyError, VisitControl, Visitor,
+};
+
+use super::{
+    arena::AppendOnly,
+    flat::{FixedFlatSnapshotBuf, FlatBranch, FlatSnapshotBuf},
+    idx_to_usize, indexable,
+    memory_db::MemoryDb,
+    DatabaseGet, DatabaseSet, Idx, Node, NodeHash, OnceCell, Store, MAX_NODES,
 };
 
-use super::{DatabaseGet, Idx, Node, NodeHash, Store};
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+
+#[cfg(feature = "std")]
+use super::prefetch::Prefetcher;
+
+type Result<T, E = TrieError> = core::result::Result<T, E>;
+
+/// A snapshot of the merkle trie
+///
+/// Contains visited nodes and unvisited nodes
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<V, K = KeyHash> {
+    /// The last branch is the root of the trie if it exists.
+    branches: Box<[Branch<Idx>]>,
+    /// A Snapshot containing only
+    leaves: Box<[Leaf<V, K>]>,
+
+    // we only store the hashes of the nodes that have not been visited.
+    unvisited_nodes: Box<[NodeHash]>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    subtree_hashes: SubtreeHashCache,
+}
+
+/// The index of a branch among a `Snapshot`'s branches.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BranchIdx(pub Idx);
+
+/// The index of a leaf among a `Snapshot`'s leaves.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct LeafIdx(pub Idx);
+
+/// The index of a hash among a `Snapshot`'s unvisited nodes.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct UnvisitedIdx(pub Idx);
+
+/// A node of a `Snapshot`, tagged with the array it is in.
+///
+/// Internally, and in `Branch<Idx>` children, a snapshot addresses its nodes with a single `Idx`
+/// counting the branches, then the leaves, then the unvisited nodes.
+/// `Snapshot::node_idx` and `Snapshot::encode_idx` convert between the two.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum NodeIdx {
+    Branch(BranchIdx),
+    Leaf(LeafIdx),
+    Unvisited(UnvisitedIdx),
+}
+
+/// The lengths of a snapshot's arrays, which is all it takes to map between an `Idx` and a `NodeIdx`.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Layout {
+    pub(super) branches: Idx,
+    pub(super) leaves: Idx,
+    pub(super) unvisited_nodes: Idx,
+}
+
+impl Layout {
+    /// The layout of arrays of these lengths.
+    ///
+    /// Errors if they total more than `MAX_NODES`, so that no index or sum of lengths truncates or overflows an `Idx`.
+    #[inline]
+    pub(super) fn checked(branches: usize, leaves: usize, unvisited_nodes: usize) -> Result<Self> {
+        let fits = branches
+            .checked_add(leaves)
+            .and_then(|nodes| nodes.checked_add(unvisited_nodes))
+            .is_some_and(indexable);
+        if !fits {
+            return Err(format!(
+                "Invalid snapshot: {branches} branches, {leaves} leaves, and {unvisited_nodes} unvisited nodes \
+                are more than the {MAX_NODES} nodes an `Idx` can index on this target"
+            )
+            .into());
+        }
+        Ok(Layout {
+            branches: branches as Idx,
+            leaves: leaves as Idx,
+            unvisited_nodes: unvisited_nodes as Idx,
+        })
+    }
+
+    #[inline]
+    pub(super) fn encode(self, idx: NodeIdx) -> Idx {
+        match idx {
+            NodeIdx::Branch(BranchIdx(idx)) => idx,
+            NodeIdx::Leaf(LeafIdx(idx)) => self.branches + idx,
+            NodeIdx::Unvisited(UnvisitedIdx(idx)) => self.branches + self.leaves + idx,
+        }
+    }
+
+    /// Returns `None` if `idx` is past the last unvisited node.
+    #[inline]
+    pub(super) fn decode(self, idx: Idx) -> Option<NodeIdx> {
+        if idx < self.branches {
+            return Some(NodeIdx::Branch(BranchIdx(idx)));
+        }
+        let idx = idx - self.branches;
+        if idx < self.leaves {
+            return Some(NodeIdx::Leaf(LeafIdx(idx)));
+        }
+        let idx = idx - self.leaves;
+        (idx < self.unvisited_nodes).then_some(NodeIdx::Unvisited(UnvisitedIdx(idx)))
+    }
+
+    /// The index of the root: the last branch, or the only node of a trie without branches.
+    #[inline]
+    pub(super) fn root(self) -> Result<TrieRoot<Idx>> {
+        match (self.branches, self.leaves, self.unvisited_nodes) {
+            // A empty tree
+            (0, 0, 0) => Ok(TrieRoot::Empty),
+            // A tree with only one node
+            (1, 0, 0) | (0, 1, 0) | (0, 0, 1) => Ok(TrieRoot::Node(0)),
+            (branches, _, _) if branches != 0 => Ok(TrieRoot::Node(branches - 1)),
+            _ => Err(format!(
+                "Invalid snapshot: \n\
+                a tree with no branches can only have one leaf.\n\
+                a tree with no branches or leaves can only have one unvisited node.\n\
+                Found {} branches, {} leaves, and {} unvisited nodes",
+                self.branches, self.leaves, self.unvisited_nodes
+            )
+            .into()),
+        }
+    }
+}
+
+impl<V, K> Snapshot<V, K> {
+    #[inline]
+    fn layout(&self) -> Layout {
+        Layout {
+            branches: self.branches.len() as Idx,
+            leaves: self.leaves.len() as Idx,
+            unvisited_nodes: self.unvisited_nodes.len() as Idx,
+        }
+    }
+
+    /// The snapshot's arrays: branches, leaves and unvisited nodes.
+    #[inline]
+    pub(crate) fn arrays(&self) -> (&[Branch<Idx>], &[Leaf<V, K>], &[NodeHash]) {
+        (&self.branches, &self.leaves, &self.unvisited_nodes)
+    }
+
+    /// The snapshot's arrays, for converting it into another form.
+    #[inline]
+    pub(super) fn into_parts(self) -> (Box<[Branch<Idx>]>, Box<[Leaf<V, K>]>, Box<[NodeHash]>) {
+        (self.branches, self.leaves, self.unvisited_nodes)
+    }
+
+    /// Which array the node at `idx` is in, and its index there.
+    #[inline]
+    pub fn node_idx(&self, idx: Idx) -> Result<NodeIdx> {
+        self.layout().decode(idx).ok_or_else(|| {
+            format!(
+                "Invalid snapshot: node {} not found\n\
+                Snapshot has {} branches, {} leaves, and {} unvisited nodes",
+                idx,
+                self.branches.len(),
+                self.leaves.len(),
+                self.unvisited_nodes.len(),
+            )
+            .into()
+        })
+    }
+
+    /// The `Idx` a `Branch<Idx>` of this snapshot uses to refer to `idx`.
+    ///
+    /// This does not check that `idx` is in bounds.
+    #[inline]
+    pub fn encode_idx(&self, idx: NodeIdx) -> Idx {
+        self.layout().encode(idx)
+    }
+
+    /// The root of the trie, tagged with the array it is in.
+    #[inline]
+    pub fn root_node(&self) -> Result<TrieRoot<NodeIdx>> {
+        match self.root_node_idx()? {
+            TrieRoot::Node(idx) => Ok(TrieRoot::Node(self.node_idx(idx)?)),
+            TrieRoot::Empty => Ok(TrieRoot::Empty),
+        }
+    }
+
+    #[inline]
+    pub fn branch(&self, idx: BranchIdx) -> Option<&Branch<Idx>> {
+        self.branches.get(idx.0 as usize)
+    }
+
+    #[inline]
+    pub fn leaf(&self, idx: LeafIdx) -> Option<&Leaf<V, K>> {
+        self.leaves.get(idx.0 as usize)
+    }
+
+    #[inline]
+    pub fn unvisited_node(&self, idx: UnvisitedIdx) -> Option<&NodeHash> {
+        self.unvisited_nodes.get(idx.0 as usize)
+    }
+
+    /// The index of the root node.
+    ///
+    /// Errors if the snapshot has more than `MAX_NODES` nodes, which `Idx` could not index on this target.
+    #[inline]
+    pub fn root_node_idx(&self) -> Result<TrieRoot<Idx>> {
+        Layout::checked(
+            self.branches.len(),
+            self.leaves.len(),
+            self.unvisited_nodes.len(),
+        )?
+        .root()
+    }
+
+    /// The number of branches, leaves and unvisited nodes.
+    #[inline]
+    fn node_count(&self) -> usize {
+        self.branches.len() + self.leaves.len() + self.unvisited_nodes.len()
+    }
+
+    /// The branch at `idx`, checked with `Branch::check_bit_order` against its parent,
+    /// which discriminates on `parent_bit_idx`, or `None` at the top of a traversal.
+    ///
+    /// The branches of a snapshot refer to each other by index, so a corrupt snapshot can contain cycles,
+    /// or chains of branches deep enough to overflow the stack of a recursive traversal.
+    /// Requiring the discriminant bit to grow along every path, within the `key_bits` bits of the key,
+    /// rules out both.
+    #[inline]
+    fn ordered_branch(
+        &self,
+        idx: BranchIdx,
+        parent_bit_idx: Option<u32>,
+        key_bits: u32,
+    ) -> Result<&Branch<Idx>> {
+        let branch = &self.branches[idx.0 as usize];
+        branch
+            .check_bit_order(parent_bit_idx, key_bits)
+            .map_err(|e| error_context(e, format_args!("Invalid snapshot: branch {}", idx.0)))?;
+        Ok(branch)
+    }
+}
+
+/// The hashes of a `Snapshot`'s visited nodes, filled in by `Snapshot::calc_root_hash`.
+///
+/// Verification hashes the snapshot once to check the old root,
+/// then again to compute the new root after replaying the transaction.
+/// With the cache, the second pass only rehashes the modified nodes,
+/// untouched stored subtrees cost a lookup.
+///
+/// The cache is derived from the snapshot, so it is ignored by comparisons and serialization.
+/// It assumes every hasher used with a snapshot computes the same hash function.
+///
+/// With `std` the cache is a `OnceLock`, so a `Snapshot` can be shared between threads.
+#[derive(Clone, Default)]
+struct SubtreeHashCache(OnceCell<Box<[Option<NodeHash>]>>);
+
+impl PartialEq for SubtreeHashCache {
+    #[inline]
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SubtreeHashCache {}
+
+impl fmt::Debug for SubtreeHashCache {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SubtreeHashCache")
+            .field(&self.0.get().is_some())
+            .finish()
+    }
+}
+
+impl<V: PortableHash, K: TrieKey> Snapshot<V, K> {
+    #[inline]
+    pub fn trie_root(&self) -> Result<TrieRoot<NodeRef<V, K>>> {
+        match self.root_node_idx()? {
+            TrieRoot::Node(idx) => Ok(TrieRoot::Node(NodeRef::Stored(idx))),
+            TrieRoot::Empty => Ok(TrieRoot::Empty),
+        }
+    }
+
+    /// Calculate the merkle root hash of the snapshot.
+    /// This computation can be thought of as verifying a Snapshot has a particular Merkle root hash.
+    /// However, in reality, it is calculating the root hash of the snapshot
+    /// by visiting all nodes touched by the transaction.
+    ///
+    /// Always check that the snapshot is of the merkle tree you expect.
+    ///
+    /// The hash of every visited node is cached in the snapshot,
+    /// so a transaction over the snapshot does not rehash the subtrees it left unmodified.
+    #[inline]
+    pub fn calc_root_hash(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>> {
+        let TrieRoot::Node(idx) = self.root_node_idx()? else {
+            return Ok(TrieRoot::Empty);
+        };
+
+        if self.subtree_hashes.0.get().is_none() {
+            let mut hashes = vec![None; self.branches.len() + self.leaves.len()];
+            let root_hash = self.hash_subtree(hasher, idx, HashCache::Filling(&mut hashes))?;
+            // Only a reentrant call could have filled the cache, and it would have computed the same hashes.
+            let _ = self.subtree_hashes.0.set(hashes.into_boxed_slice());
+            return Ok(TrieRoot::Node(root_hash));
+        }
+
+        Ok(TrieRoot::Node(self.calc_subtree_hash(hasher, idx)?))
+    }
+
+    /// Returns the value at `key_hash`, or `None` if the snapshot proves the key is absent.
+    ///
+    /// Reads the snapshot in place, without building a `Transaction`.
+    /// Returns an error if the snapshot does not contain the path to `key_hash`.
+    #[inline]
+    pub fn get(&self, key_hash: &K) -> Result<Option<&V>> {
+        let TrieRoot::Node(mut idx) = self.root_node_idx()? else {
+            return Ok(None);
+        };
+        let key_bits = key_bits(key_hash);
+        let mut parent_bit_idx = None;
+
+        loop {
+            match self.node_idx(idx)? {
+                NodeIdx::Branch(branch_idx) => {
+                    let branch = self.ordered_branch(branch_idx, parent_bit_idx, key_bits)?;
+                    parent_bit_idx = Some(branch.mask.bit_idx());
+                    match branch.key_position(key_hash) {
+                        KeyPosition::Left => idx = branch.left,
+                        KeyPosition::Right => idx = branch.right,
+                        KeyPosition::Adjacent(_) => return Ok(None),
+                    }
+                }
+                NodeIdx::Leaf(leaf_idx) => {
+                    let leaf = &self.leaves[leaf_idx.0 as usize];
+                    return Ok((leaf.key_hash == *key_hash).then_some(&leaf.value));
+                }
+                NodeIdx::Unvisited(_) => {
+                    return Err(format!(
+                        "Cannot read {key_hash:?}, node {idx} is only stored as its hash"
+                    )
+                    .into())
+                }
+            }
+        }
+    }
+
+    /// Check the snapshot is of the trie at `root`, then read the value of each key in `keys`.
+    ///
+    /// This is verification for a guest that only consumes state.
+    /// Unlike replaying reads in a `Transaction`, nothing but the returned `Vec` is allocated,
+    /// the root is hashed without filling the subtree hash cache, and no modified nodes are built.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn replay_reads<'k>(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        root: TrieRoot<NodeHash>,
+        keys: impl IntoIterator<Item = &'k K>,
+    ) -> Result<Vec<Option<&V>>, VerifyError>
+    where
+        K: 'k,
+    {
+        let actual = match self.root_node_idx()? {
+            TrieRoot::Node(idx) => {
+                TrieRoot::Node(self.hash_subtree(hasher, idx, self.cached_hashes())?)
+            }
+            TrieRoot::Empty => TrieRoot::Empty,
+        };
+        if !actual.verify_eq(&root) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: root,
+                actual,
+            });
+        }
+
+        keys.into_iter()
+            .map(|key_hash| self.get(key_hash).map_err(VerifyError::from))
+            .collect()
+    }
+
+    /// The hashes cached by `calc_root_hash`, for `hash_subtree` to reuse.
+    #[inline]
+    fn cached_hashes(&self) -> HashCache<'_> {
+        HashCache::Cached(self.subtree_hashes.0.get().map(|hashes| &**hashes))
+    }
+
+    /// Hash the subtree at `node` by walking it, reusing or filling the hashes in `cache`.
+    fn hash_subtree<H: PortableHasher<32>>(
+        &self,
+        hasher: &mut H,
+        node: Idx,
+        cache: HashCache<'_>,
+    ) -> Result<NodeHash> {
+        let mut visitor = SubtreeHashes {
+            hasher,
+            cache,
+            budget: self.node_count(),
+            stack: [NodeHash::new([0; 32]); MAX_KEY_BITS as usize + 1],
+            stack_len: 0,
+            skipped: None,
+        };
+        walk(self, TrieRoot::Node(node), &mut visitor)?;
+        visitor
+            .pop()
+            .ok_or_else(|| "Invalid snapshot: the walk hashed no node".into())
+    }
+
+    /// The branches of the snapshot, with their prefixes moved into a single array.
+    fn flat_branches(&self) -> (Box<[FlatBranch]>, Box<[u32]>) {
+        let mut prefixes = Vec::with_capacity(self.branches.iter().map(|b| b.prefix.len()).sum());
+        let branches = self
+            .branches
+            .iter()
+            .map(|branch| {
+                let prefix_start = prefixes.len() as Idx;
+                prefixes.extend_from_slice(&branch.prefix);
+                FlatBranch {
+                    left: branch.left,
+                    right: branch.right,
+                    mask: branch.mask,
+                    prior_word: branch.prior_word,
+                    prefix_start,
+                    prefix_len: branch.prefix.len() as u32,
+                }
+            })
+            .collect();
+
+        (branches, prefixes.into_boxed_slice())
+    }
+
+    #[cfg(feature = "test-utils")]
+    pub(crate) fn truncated(&self, branches: usize, leaves: usize, unvisited_nodes: usize) -> Self
+    where
+        V: Clone,
+        K: Clone,
+    {
+        fn cut<T: Clone>(items: &[T], n: usize) -> Box<[T]> {
+            items[..items.len().saturating_sub(n)].into()
+        }
+
+        Snapshot {
+            branches: cut(&self.branches, branches),
+            leaves: cut(&self.leaves, leaves),
+            unvisited_nodes: cut(&self.unvisited_nodes, unvisited_nodes),
+            subtree_hashes: SubtreeHashCache::default(),
+        }
+    }
+
+    /// Returns true if `other` is a snapshot of the same trie that visits every node this snapshot visits.
+    ///
+    /// Nodes are compared by hash, so the order of the internal arrays does not matter.
+    /// A merged witness, for example, should contain each of its parts.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this function.
+    #[inline]
+    pub fn is_sub_snapshot_of(
+        &self,
+        other: &Self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<bool> {
+        let (root, visited) = self.visited_node_hashes(hasher)?;
+        let (other_root, other_visited) = other.visited_node_hashes(hasher)?;
+
+        Ok(root == other_root && visited.is_subset(&other_visited))
+    }
+
+    /// Returns true if both snapshots are of the same trie and visit the same nodes.
+    ///
+    /// Unlike `==`, this ignores the order of the internal arrays,
+    /// which is only canonical for snapshots built by a `SnapshotBuilder` or `Snapshot::canonicalize`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this function.
+    #[inline]
+    pub fn is_equivalent_to(
+        &self,
+        other: &Self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<bool> {
+        let (root, visited) = self.visited_node_hashes(hasher)?;
+        let (other_root, other_visited) = other.visited_node_hashes(hasher)?;
+
+        Ok(root == other_root && visited == other_visited)
+    }
+
+    /// The nodes `a` visits that `b` does not, and the nodes `b` visits that `a` does not, by hash.
+    ///
+    /// Meant for two witnesses of the same root, such as two provers' witnesses for one block:
+    /// the difference shows which reads one of them made that the other did not,
+    /// and storing one witness whole and only the other's `SnapshotDifference::only_in_b`
+    /// stores each shared node once.
+    /// A node one snapshot only knows by hash is not visited, so it is in the other's side if the other visits it.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this function.
+    #[inline]
+    pub fn difference(
+        a: &Self,
+        b: &Self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<SnapshotDifference<V, K>>
+    where
+        V: Clone,
+        K: Clone,
+    {
+        let mut only_in_a = a.visited_nodes(hasher)?;
+        let mut only_in_b = b.visited_nodes(hasher)?;
+
+        let total = only_in_a.len();
+        only_in_a.retain(|hash, _| only_in_b.remove(hash).is_none());
+
+        Ok(SnapshotDifference {
+            shared: total - only_in_a.len(),
+            only_in_a,
+            only_in_b,
+        })
+    }
+
+    /// The subtrees the snapshot only knows by hash, in trie order,
+    /// with the bits every key in each of them starts with.
+    ///
+    /// These are the parts of the trie a transaction over the snapshot cannot read or modify,
+    /// so a prover chaining programs knows which regions the next witness must add.
+    #[inline]
+    pub fn unvisited(&self) -> Result<Vec<UnvisitedNode>> {
+        let mut unvisited = Vec::with_capacity(self.unvisited_nodes.len());
+        if let TrieRoot::Node(root) = self.root_node_idx()? {
+            self.collect_unvisited(root, &mut Vec::new(), 0, &mut unvisited)?;
+        }
+        Ok(unvisited)
+    }
+
+    fn collect_unvisited(
+        &self,
+        idx: Idx,
+        words: &mut Vec<u32>,
+        len_bits: u32,
+        unvisited: &mut Vec<UnvisitedNode>,
+    ) -> Result<()> {
+        if let Some(hash) = self.get_unvisited_hash(idx)? {
+            words.truncate(len_bits.div_ceil(32) as usize);
+            unvisited.push(UnvisitedNode {
+                idx,
+                hash,
+                prefix: words.as_slice().into(),
+                len_bits,
+            });
+            return Ok(());
+        }
+
+        let Node::Branch(branch) = self.get_node(idx)? else {
+            return Ok(());
+        };
+        branch.check_invariants()?;
+        for (child, right) in [(branch.left, false), (branch.right, true)] {
+            let child_len_bits = branch.child_key_prefix(right, words);
+            // Discriminant bits grow down a valid trie, which also rules out cycles.
+            if child_len_bits <= len_bits {
+                return Err(format!(
+                    "Invalid snapshot: branch {idx} does not discriminate on a bit after its parent's"
+                )
+                .into());
+            }
+            self.collect_unvisited(child, words, child_len_bits, unvisited)?;
+        }
+        Ok(())
+    }
+
+    /// Check every branch and leaf, reachable or not, is within `limits`.
+    ///
+    /// `verify_batch` runs this check with `NodeLimits::default()` before hashing the snapshot,
+    /// so an oversized node is rejected before any work is spent on it.
+    /// The error's source is the `NodeSizeError`.
+    #[inline]
+    pub fn check_limits(&self, limits: &NodeLimits) -> Result<()> {
+        for (i, branch) in self.branches.iter().enumerate() {
+            limits.check_branch(branch).map_err(|e| {
+                let idx = self.encode_idx(NodeIdx::Branch(BranchIdx(i as Idx)));
+                error_context(e, format_args!("Invalid snapshot: branch {idx}"))
+            })?;
+        }
+        for (i, leaf) in self.leaves.iter().enumerate() {
+            limits.check_value(&leaf.value).map_err(|e| {
+                let idx = self.encode_idx(NodeIdx::Leaf(LeafIdx(i as Idx)));
+                error_context(e, format_args!("Invalid snapshot: leaf {idx}"))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check that every visited branch and leaf agrees with the key bits fixed by the branches above it.
+    ///
+    /// The keys of a trie decide its shape, so a set of keys has exactly one root.
+    /// A snapshot can still describe other shapes, such as a branch whose prefix covers the wrong words,
+    /// or restates bits its parent already fixed with different values.
+    /// Such a branch hashes differently from the canonical one,
+    /// so accepting it would let a witness commit to a second root for the same keys.
+    ///
+    /// `verify_batch` runs this check on every snapshot it replays.
+    #[inline]
+    pub fn check_canonical(&self) -> Result<()> {
+        if let TrieRoot::Node(root) = self.root_node_idx()? {
+            self.check_canonical_node(root, None, &mut Vec::new(), 0)?;
+        }
+        Ok(())
+    }
+
+    /// Check the subtree at `idx`, whose keys all start with the first `len_bits` bits of `words`.
+    fn check_canonical_node(
+        &self,
+        idx: Idx,
+        parent: Option<&BranchMask>,
+        words: &mut Vec<u32>,
+        len_bits: u32,
+    ) -> Result<()> {
+        if self.get_unvisited_hash(idx)?.is_some() {
+            return Ok(());
+        }
+
+        let branch = match self.get_node(idx)? {
+            Node::Leaf(leaf) => {
+                return if bits_agree(leaf.key_hash.words(), words, len_bits) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "Invalid snapshot: leaf {:?} does not match the key bits of the branches above it",
+                        leaf.key_hash
+                    )
+                    .into())
+                };
+            }
+            Node::Branch(branch) => branch,
+        };
+
+        branch.check_invariants()?;
+        let word_idx = branch.mask.word_idx();
+        let canonical_shape = match parent {
+            // The only branch above the root's prefix is the root itself.
+            None => branch.prefix.len() == word_idx.saturating_sub(1),
+            Some(parent) => {
+                branch
+                    .mask
+                    .is_valid_child_of(parent, branch.prior_word, &branch.prefix)
+            }
+        };
+        if !canonical_shape {
+            return Err(format!(
+                "Invalid snapshot: branch {idx} has a prefix of {} words, which does not start at its parent's word",
+                branch.prefix.len()
+            )
+            .into());
+        }
+
+        let known = words.clone();
+        for right in [false, true] {
+            words.clone_from(&known);
+            let child_len_bits = branch.child_key_prefix(right, words);
+            if !bits_agree(words, &known, len_bits) {
+                return Err(format!(
+                    "Invalid snapshot: branch {idx} does not match the key bits of the branches above it"
+                )
+                .into());
+            }
+
+            let child = if right { branch.right } else { branch.left };
+            self.check_canonical_node(child, Some(&branch.mask), words, child_len_bits)?;
+        }
+        Ok(())
+    }
+
+    /// Reorder the internal arrays into the order `SnapshotBuilder::build_initial_snapshot` produces,
+    /// dropping nodes that are not reachable from the root.
+    ///
+    /// Nodes are laid out depth first, left before right, each branch after its children.
+    /// This depends only on which nodes the snapshot visits,
+    /// so two canonical snapshots visiting the same nodes are equal and serialize to the same bytes,
+    /// and can be cached or deduplicated by their encoding.
+    /// A builder's output is already canonical, whatever order the transaction touched the nodes in,
+    /// but a deserialized or hand-built snapshot may not be.
+    ///
+    /// Returns an error if the snapshot is not a tree, such as when a node is reachable twice.
+    #[inline]
+    pub fn canonicalize(&self) -> Result<Self>
+    where
+        V: Clone,
+        K: Clone,
+    {
+        let root = self.root_node_idx()?;
+        let node_count = self.branches.len() + self.leaves.len() + self.unvisited_nodes.len();
+
+        let mut counts = CountReachable {
+            seen: vec![false; node_count],
+            branches: 0,
+            leaves: 0,
+            unvisited_nodes: 0,
+        };
+        walk(self, root, &mut counts)?;
+
+        let mut fold = CanonicalFold {
+            layout: Layout {
+                branches: counts.branches,
+                leaves: counts.leaves,
+                unvisited_nodes: counts.unvisited_nodes,
+            },
+            branches: Vec::with_capacity(counts.branches as usize),
+            leaves: Vec::with_capacity(counts.leaves as usize),
+            unvisited_nodes: Vec::with_capacity(counts.unvisited_nodes as usize),
+            stack: Vec::new(),
+        };
+        walk(self, root, &mut fold)?;
+
+        Ok(Snapshot {
+            branches: fold.branches.into_boxed_slice(),
+            leaves: fold.leaves.into_boxed_slice(),
+            unvisited_nodes: fold.unvisited_nodes.into_boxed_slice(),
+            subtree_hashes: SubtreeHashCache::default(),
+        })
+    }
+
+    /// Returns the root hash and the hashes of every visited branch and leaf.
+    fn visited_node_hashes(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<(TrieRoot<NodeHash>, BTreeSet<NodeHash>)> {
+        let mut visitor = VisitedNodeHashes {
+            hasher,
+            stack: Vec::new(),
+            visited: BTreeSet::new(),
+        };
+        walk(self, self.root_node_idx()?, &mut visitor)?;
+
+        Ok((visitor.stack.pop().into(), visitor.visited))
+    }
+
+    fn visited_nodes(&self, hasher: &mut impl PortableHasher<32>) -> Result<DbNodes<V, K>>
+    where
+        V: Clone,
+        K: Clone,
+    {
+        let mut visitor = VisitedNodes {
+            hasher,
+            stack: Vec::new(),
+            nodes: BTreeMap::new(),
+        };
+        walk(self, self.root_node_idx()?, &mut visitor)?;
+
+        Ok(visitor.nodes)
+    }
+
+    /// Hash every visited node of the snapshot into a new `MemoryDb`, keyed by hash,
+    /// and return it with the snapshot's root hash.
+    ///
+    /// A `SnapshotBuilder` over the database and root replays and extends the witness with every `Transaction` feature,
+    /// for debugging a failing guest locally.
+    /// Unvisited nodes are only known by hash, so they are not in the database,
+    /// and reading a key under one fails with a `MissingNode` error.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn to_memory_db(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<(MemoryDb<V, K>, TrieRoot<NodeHash>)>
+    where
+        V: Clone,
+        K: Clone,
+    {
+        let mut visitor = MemoryDbFold {
+            hasher,
+            stack: Vec::new(),
+            db: MemoryDb::empty(),
+        };
+        walk(self, self.root_node_idx()?, &mut visitor)?;
+
+        Ok((visitor.db, visitor.stack.pop().into()))
+    }
+
+    /// Find where the snapshot diverges from the trie at `expected_root` in `db`.
+    ///
+    /// Meant for the host, to debug a snapshot that failed verification in the guest.
+    /// Starting at the root, this descends into the child whose hash differs from the database's,
+    /// the left one if both do, and reports the deepest node whose hash does not match.
+    /// Returns `None` if the snapshot hashes to `expected_root`.
+    ///
+    /// `db` must contain the branches and leaves under `expected_root` on the mismatching path.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this function.
+    #[inline]
+    pub fn locate_mismatch(
+        &self,
+        db: &impl DatabaseGet<V, K>,
+        expected_root: TrieRoot<NodeHash>,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<Option<HashMismatch>> {
+        // Fills the subtree hash cache, so the hashes below are lookups.
+        let actual_root = self.calc_root_hash(hasher)?;
+        let (TrieRoot::Node(mut idx), TrieRoot::Node(mut expected)) =
+            (self.root_node_idx()?, expected_root)
+        else {
+            return if actual_root == expected_root {
+                Ok(None)
+            } else {
+                Err(format!(
+                    "Cannot locate a mismatch between the snapshot root {actual_root:?} and {expected_root:?}, \
+                    only one of them is empty"
+                )
+                .into())
+            };
+        };
+
+        let mut path = Vec::new();
+        let mut actual = self.calc_subtree_hash(hasher, idx)?;
+        if actual == expected {
+            return Ok(None);
+        }
+
+        loop {
+            let mismatch = |path: Vec<bool>| {
+                Ok(Some(HashMismatch {
+                    idx,
+                    path: path.into_boxed_slice(),
+                    expected,
+                    actual,
+                }))
+            };
+
+            if self.get_unvisited_hash(idx)?.is_some() {
+                return mismatch(path);
+            }
+            let Node::Branch(branch) = self.get_node(idx)? else {
+                return mismatch(path);
+            };
+            let Node::Branch(expected_branch) = db.get(&expected).map_err(|e| {
+                error_context(e, format_args!("Error getting {expected} from database"))
+            })?
+            else {
+                return mismatch(path);
+            };
+            if branch.mask != expected_branch.mask
+                || branch.prior_word != expected_branch.prior_word
+                || branch.prefix != expected_branch.prefix
+            {
+                return mismatch(path);
+            }
+
+            let left = self.calc_subtree_hash(hasher, branch.left)?;
+            let right = self.calc_subtree_hash(hasher, branch.right)?;
+            (idx, expected, actual) = if left != expected_branch.left {
+                path.push(false);
+                (branch.left, expected_branch.left, left)
+            } else if right != expected_branch.right {
+                path.push(true);
+                (branch.right, expected_branch.right, right)
+            } else {
+                // Same fields and children, so the hashes only differ if the hasher does.
+                return mismatch(path);
+            };
+        }
+    }
+}
+
+/// Whether `a` and `b` agree on their first `len_bits` bits, words missing from either being 0.
+#[inline]
+fn bits_agree(a: &[u32], b: &[u32], len_bits: u32) -> bool {
+    (0..len_bits.div_ceil(32) as usize).all(|i| {
+        let known_bits = (len_bits - 32 * i as u32).min(32);
+        let mask = u32::MAX.checked_shl(known_bits).map_or(u32::MAX, |m| !m);
+        let (a, b) = (a.get(i).unwrap_or(&0), b.get(i).unwrap_or(&0));
+        (a ^ b) & mask == 0
+    })
+}
+
+/// The deepest node at which a `Snapshot` diverges from the expected trie, from `Snapshot::locate_mismatch`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HashMismatch {
+    /// The index of the node in the snapshot.
+    pub idx: Idx,
+    /// The side taken at each branch from the root to the node, `true` for right.
+    pub path: Box<[bool]>,
+    /// The hash of the node in the expected trie.
+    pub expected: NodeHash,
+    /// The hash of the node in the snapshot.
+    pub actual: NodeHash,
+}
+
+/// A subtree a `Snapshot` only knows by hash, from `Snapshot::unvisited`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnvisitedNode {
+    /// The index of the node in the snapshot.
+    pub idx: Idx,
+    pub hash: NodeHash,
+    /// The words of the bits every key in the subtree starts with,
+    /// bit `j` of `prefix[i]` being bit `32 * i + j` of the key in trie order.
+    /// Bits from `len_bits` on are 0.
+    pub prefix: Box<[u32]>,
+    /// The number of bits every key in the subtree shares, 0 if the subtree is the whole trie.
+    pub len_bits: u32,
+}
+
+impl UnvisitedNode {
+    /// The first and last keys the subtree can contain, in trie order.
+    ///
+    /// This can be passed to `Transaction::range_get`, against a database, to extend a witness over the subtree.
+    #[inline]
+    pub fn key_range(&self) -> RangeInclusive<KeyHash> {
+        let mut min = [0; 8];
+        let mut max = [u32::MAX; 8];
+        for (i, word) in self.prefix.iter().enumerate() {
+            let known_bits = self.len_bits.saturating_sub(32 * i as u32).min(32);
+            let known_mask = u32::MAX.checked_shl(known_bits).map_or(u32::MAX, |m| !m);
+            min[i] = word & known_mask;
+            max[i] = word | !known_mask;
+        }
+        KeyHash(min)..=KeyHash(max)
+    }
+}
+
+impl<'s, K> From<Snapshot<&'s [u8], K>> for Snapshot<Cow<'s, [u8]>, K> {
+    /// Wrap each value in `Cow::Borrowed`, copying no value bytes.
+    ///
+    /// Serde always deserializes a `Cow` as owned,
+    /// but deserializing a `Snapshot<&[u8]>` borrows each value from the witness buffer.
+    /// Converting that gives a guest a snapshot that reads values in place,
+    /// while a transaction over it can still insert owned values.
+    /// With `bincode`, a `Snapshot<Vec<u8>>` or `Snapshot<Cow<[u8]>>` can be deserialized as a `Snapshot<&[u8]>`.
+    #[inline]
+    fn from(snapshot: Snapshot<&'s [u8], K>) -> Self {
+        Snapshot {
+            branches: snapshot.branches,
+            leaves: Vec::from(snapshot.leaves)
+                .into_iter()
+                .map(|leaf| Leaf {
+                    key_hash: leaf.key_hash,
+                    value: Cow::Borrowed(leaf.value),
+                })
+                .collect(),
+            unvisited_nodes: snapshot.unvisited_nodes,
+            // The values hash the same either way.
+            subtree_hashes: snapshot.subtree_hashes,
+        }
+    }
+}
+
+impl<V: PortableHash> Snapshot<V> {
+    /// Copy the snapshot into the flat arrays of a `FlatSnapshot`,
+    /// which a guest can verify without heap allocations.
+    #[inline]
+    pub fn to_flat(&self) -> FlatSnapshotBuf<V>
+    where
+        V: Clone,
+    {
+        let (branches, prefixes) = self.flat_branches();
+
+        FlatSnapshotBuf {
+            branches,
+            prefixes,
+            leaves: self.leaves.clone(),
+            unvisited_nodes: self.unvisited_nodes.clone(),
+        }
+    }
+}
+
+impl<const N: usize> Snapshot<[u8; N]> {
+    /// Copy the snapshot into the arrays of a `FixedFlatSnapshot`,
+    /// packing each leaf's key and value into `32 + N` bytes.
+    #[inline]
+    pub fn to_fixed_flat(&self) -> FixedFlatSnapshotBuf<N> {
+        let (branches, prefixes) = self.flat_branches();
+        let leaves = self
+            .leaves
+            .iter()
+            .flat_map(|leaf| leaf.key_hash.to_bytes().into_iter().chain(leaf.value))
+            .collect();
+
+        FixedFlatSnapshotBuf {
+            branches,
+            prefixes,
+            leaves,
+            unvisited_nodes: self.unvisited_nodes.clone(),
+        }
+    }
+}
 
-type Result<T, E = TrieError> = core::result::Result<T, E>;
+type DbNodes<V, K> = BTreeMap<NodeHash, Node<Branch<NodeHash>, Leaf<V, K>>>;
 
-/// A snapshot of the merkle trie
+/// The nodes visited by only one of two snapshots, see `Snapshot::difference`.
 ///
-/// Contains visited nodes and unvisited nodes
-#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Nodes are in the form a database stores them, with their children by hash.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Snapshot<V> {
-    /// The last branch is the root of the trie if it exists.
-    branches: Box<[Branch<Idx>]>,
-    /// A Snapshot containing only
-    leaves: Box<[Leaf<V>]>,
+pub struct SnapshotDifference<V, K = KeyHash> {
+    /// The nodes only the first snapshot visits.
+    pub only_in_a: DbNodes<V, K>,
+    /// The nodes only the second snapshot visits.
+    pub only_in_b: DbNodes<V, K>,
+    /// The number of nodes both snapshots visit.
+    pub shared: usize,
+}
 
-    // we only store the hashes of the nodes that have not been visited.
-    unvisited_nodes: Box<[NodeHash]>,
+impl<V, K> SnapshotDifference<V, K> {
+    /// Returns true if both snapshots visit the same nodes.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty() && self.only_in_b.is_empty()
+    }
 }
 
-impl<V: PortableHash> Snapshot<V> {
+/// Evidence that a witness was checked to be the witness of the empty trie, made by `EmptyTrieAttestation::verify`.
+///
+/// A snapshot with no nodes hashes to `TrieRoot::Empty`, whether it was built from the empty trie,
+/// or is a default a host sent without loading the root it meant to.
+/// An attestation can only be made by checking the snapshot against the root the guest trusts, never deserialized,
+/// so code handling genesis can take one to require that the check was done.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EmptyTrieAttestation(());
+
+impl EmptyTrieAttestation {
+    /// Check `snapshot` is the witness of the empty trie, and `root` is the empty root.
+    ///
+    /// A snapshot with any node, even an unvisited one, is not the empty trie.
     #[inline]
-    pub fn root_node_idx(&self) -> Result<TrieRoot<Idx>> {
-        // Revist this once https://github.com/rust-lang/rust/issues/37854 is stable
-        match (
-            self.branches.deref(),
-            self.leaves.deref(),
-            self.unvisited_nodes.deref(),
-        ) {
-            // A empty tree
-            ([], [], []) => Ok(TrieRoot::Empty),
-            // A tree with only one node
-            ([_], [], []) | ([], [_], []) | ([], [], [_]) => Ok(TrieRoot::Node(0)),
-            (branches, _, _) if !branches.is_empty() => {
-                Ok(TrieRoot::Node(branches.len() as Idx - 1))
-            }
-            _ => Err(format!(
-                "Invalid snapshot: \n\
-                a tree with no branches can only have one leaf.\n\
-                a tree with no branches or leaves can only have one unvisited node.\n\
-                Found {} branches, {} leaves, and {} unvisited nodes",
-                self.branches.len(),
-                self.leaves.len(),
-                self.unvisited_nodes.len()
-            )
-            .into()),
+    pub fn verify<V, K>(
+        snapshot: &Snapshot<V, K>,
+        root: TrieRoot<NodeHash>,
+    ) -> Result<Self, VerifyError> {
+        if !root.verify_eq(&TrieRoot::Empty) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: root,
+                actual: TrieRoot::Empty,
+            });
+        }
+
+        if snapshot.node_count() != 0 {
+            return Err(TrieError::from(format!(
+                "Invalid empty trie witness: the snapshot has {} branches, {} leaves, and {} unvisited nodes",
+                snapshot.branches.len(),
+                snapshot.leaves.len(),
+                snapshot.unvisited_nodes.len()
+            ))
+            .into());
         }
+        Ok(EmptyTrieAttestation(()))
     }
+}
 
+/// Counts the nodes reachable from the root, checking no node is reachable twice.
+struct CountReachable {
+    seen: Vec<bool>,
+    branches: Idx,
+    leaves: Idx,
+    unvisited_nodes: Idx,
+}
+
+impl CountReachable {
     #[inline]
-    pub fn trie_root(&self) -> Result<TrieRoot<NodeRef<V>>> {
-        match self.root_node_idx()? {
-            TrieRoot::Node(idx) => Ok(TrieRoot::Node(NodeRef::Stored(idx))),
-            TrieRoot::Empty => Ok(TrieRoot::Empty),
+    fn see(&mut self, idx: Idx) -> Result<()> {
+        match idx_to_usize(idx).and_then(|i| self.seen.get_mut(i)) {
+            Some(seen @ false) => {
+                *seen = true;
+                Ok(())
+            }
+            Some(true) => Err(format!("Invalid snapshot: node {idx} is reachable twice").into()),
+            None => Err(format!("Invalid snapshot: node {idx} not found").into()),
         }
     }
+}
 
-    /// Calculate the merkle root hash of the snapshot.
-    /// This computation can be thought of as verifying a Snapshot has a particular Merkle root hash.
-    /// However, in reality, it is calculating the root hash of the snapshot
-    /// by visiting all nodes touched by the transaction.
+impl<V, K> Visitor<V, K> for CountReachable {
+    #[inline]
+    fn pre_branch(&mut self, idx: Idx, _: &Branch<Idx>) -> Result<VisitControl> {
+        self.see(idx)?;
+        self.branches += 1;
+        Ok(VisitControl::Continue)
+    }
+
+    #[inline]
+    fn leaf(&mut self, idx: Idx, _: &Leaf<V, K>) -> Result<()> {
+        self.see(idx)?;
+        self.leaves += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn unvisited(&mut self, idx: Idx, _: &NodeHash) -> Result<()> {
+        self.see(idx)?;
+        self.unvisited_nodes += 1;
+        Ok(())
+    }
+}
+
+/// Copies the nodes of a snapshot in the order `SnapshotBuilderFold` lays them out.
+struct CanonicalFold<V, K> {
+    /// The layout of the canonical snapshot.
+    layout: Layout,
+    branches: Vec<Branch<Idx>>,
+    leaves: Vec<Leaf<V, K>>,
+    unvisited_nodes: Vec<NodeHash>,
+    /// The new indexes of the subtrees walked whose parent has not been copied yet.
+    stack: Vec<Idx>,
+}
+
+impl<V: Clone, K: Clone> Visitor<V, K> for CanonicalFold<V, K> {
+    #[inline]
+    fn post_branch(&mut self, _: Idx, branch: &Branch<Idx>) -> Result<()> {
+        let (Some(right), Some(left)) = (self.stack.pop(), self.stack.pop()) else {
+            return Err("Invalid snapshot: branch is missing a child".into());
+        };
+
+        self.stack.push(
+            self.layout
+                .encode(NodeIdx::Branch(BranchIdx(self.branches.len() as Idx))),
+        );
+        self.branches.push(Branch {
+            left,
+            right,
+            mask: branch.mask,
+            prior_word: branch.prior_word,
+            prefix: branch.prefix.clone(),
+        });
+        Ok(())
+    }
+
+    #[inline]
+    fn leaf(&mut self, _: Idx, leaf: &Leaf<V, K>) -> Result<()> {
+        self.stack.push(
+            self.layout
+                .encode(NodeIdx::Leaf(LeafIdx(self.leaves.len() as Idx))),
+        );
+        self.leaves.push(leaf.clone());
+        Ok(())
+    }
+
+    #[inline]
+    fn unvisited(&mut self, _: Idx, hash: &NodeHash) -> Result<()> {
+        self.stack
+            .push(self.layout.encode(NodeIdx::Unvisited(UnvisitedIdx(
+                self.unvisited_nodes.len() as Idx
+            ))));
+        self.unvisited_nodes.push(*hash);
+        Ok(())
+    }
+}
+
+/// Hashes a trie bottom up, collecting the hashes of visited nodes.
+struct VisitedNodeHashes<'h, H> {
+    hasher: &'h mut H,
+    /// The hashes of the subtrees walked whose parent has not been hashed yet.
+    stack: Vec<NodeHash>,
+    visited: BTreeSet<NodeHash>,
+}
+
+impl<V: PortableHash, K: TrieKey, H: PortableHasher<32>> Visitor<V, K>
+    for VisitedNodeHashes<'_, H>
+{
+    #[inline]
+    fn post_branch(&mut self, _: Idx, branch: &Branch<Idx>) -> Result<()> {
+        let (Some(right), Some(left)) = (self.stack.pop(), self.stack.pop()) else {
+            return Err("Invalid snapshot: branch is missing a child".into());
+        };
+
+        let hash = branch.hash_branch(self.hasher, &left, &right);
+        self.visited.insert(hash);
+        self.stack.push(hash);
+        Ok(())
+    }
+
+    #[inline]
+    fn leaf(&mut self, _: Idx, leaf: &Leaf<V, K>) -> Result<()> {
+        let hash = leaf.hash_leaf(self.hasher);
+        self.visited.insert(hash);
+        self.stack.push(hash);
+        Ok(())
+    }
+
+    #[inline]
+    fn unvisited(&mut self, _: Idx, hash: &NodeHash) -> Result<()> {
+        self.stack.push(*hash);
+        Ok(())
+    }
+}
+
+/// Hashes a trie bottom up, collecting its visited nodes by hash.
+struct VisitedNodes<'h, H, V, K> {
+    hasher: &'h mut H,
+    /// The hashes of the subtrees walked whose parent has not been hashed yet.
+    stack: Vec<NodeHash>,
+    nodes: DbNodes<V, K>,
+}
+
+impl<V: PortableHash + Clone, K: TrieKey, H: PortableHasher<32>> Visitor<V, K>
+    for VisitedNodes<'_, H, V, K>
+{
+    #[inline]
+    fn post_branch(&mut self, _: Idx, branch: &Branch<Idx>) -> Result<()> {
+        let (Some(right), Some(left)) = (self.stack.pop(), self.stack.pop()) else {
+            return Err("Invalid snapshot: branch is missing a child".into());
+        };
+
+        let hash = branch.hash_branch(self.hasher, &left, &right);
+        self.nodes
+            .insert(hash, Node::Branch(branch.with_children(left, right)));
+        self.stack.push(hash);
+        Ok(())
+    }
+
+    #[inline]
+    fn leaf(&mut self, _: Idx, leaf: &Leaf<V, K>) -> Result<()> {
+        let hash = leaf.hash_leaf(self.hasher);
+        self.nodes.insert(hash, Node::Leaf(leaf.clone()));
+        self.stack.push(hash);
+        Ok(())
+    }
+
+    #[inline]
+    fn unvisited(&mut self, _: Idx, hash: &NodeHash) -> Result<()> {
+        self.stack.push(*hash);
+        Ok(())
+    }
+}
+
+/// Hashes a trie bottom up, writing its visited nodes to a `MemoryDb`.
+struct MemoryDbFold<'h, H, V, K> {
+    hasher: &'h mut H,
+    /// The hashes of the subtrees walked whose parent has not been hashed yet.
+    stack: Vec<NodeHash>,
+    db: MemoryDb<V, K>,
+}
+
+impl<V: PortableHash + Clone, K: TrieKey, H: PortableHasher<32>> Visitor<V, K>
+    for MemoryDbFold<'_, H, V, K>
+{
+    #[inline]
+    fn post_branch(&mut self, _: Idx, branch: &Branch<Idx>) -> Result<()> {
+        let (Some(right), Some(left)) = (self.stack.pop(), self.stack.pop()) else {
+            return Err("Invalid snapshot: branch is missing a child".into());
+        };
+
+        let hash = branch.hash_branch(self.hasher, &left, &right);
+        self.db
+            .set(hash, Node::Branch(branch.with_children(left, right)))?;
+        self.stack.push(hash);
+        Ok(())
+    }
+
+    #[inline]
+    fn leaf(&mut self, _: Idx, leaf: &Leaf<V, K>) -> Result<()> {
+        let hash = leaf.hash_leaf(self.hasher);
+        self.db.set(hash, Node::Leaf(leaf.clone()))?;
+        self.stack.push(hash);
+        Ok(())
+    }
+
+    #[inline]
+    fn unvisited(&mut self, _: Idx, hash: &NodeHash) -> Result<()> {
+        self.stack.push(*hash);
+        Ok(())
+    }
+}
+
+/// The hashes `SubtreeHashes` reuses, and where it records the hashes it computes.
+enum HashCache<'c> {
+    /// Reuse the hashes `calc_root_hash` cached, if there are any.
+    Cached(Option<&'c [Option<NodeHash>]>),
+    /// Record the hash of every visited branch and leaf, reusing those already recorded.
+    Filling(&'c mut [Option<NodeHash>]),
+}
+
+/// Hashes a snapshot's subtree bottom up, for `calc_root_hash` and `Store::calc_subtree_hash`.
+///
+/// A node with a hash in the cache is not walked again.
+/// Filling the cache, that is enough to hash a corrupt snapshot sharing subtrees between branches
+/// in the time of a tree.
+/// Otherwise each visited node takes one from `budget`, which starts at the number of nodes in the snapshot.
+/// Running out means the walk revisited a node, so such a snapshot is rejected
+/// instead of hashed in time exponential in its depth.
+struct SubtreeHashes<'h, 'c, H> {
+    hasher: &'h mut H,
+    cache: HashCache<'c>,
+    budget: usize,
+    /// The hashes of the subtrees walked whose parent has not been hashed yet, the first `stack_len` are set.
     ///
-    /// Always check that the snapshot is of the merkle tree you expect.
+    /// `walk` checks the bit order, so a path has at most `MAX_KEY_BITS` branches,
+    /// and the stack holds at most one hash more than that.
+    /// Keeping it inline leaves the cache the only allocation of `calc_root_hash`.
+    stack: [NodeHash; MAX_KEY_BITS as usize + 1],
+    stack_len: usize,
+    /// The cached hash of the branch whose children `pre_branch` skipped.
+    skipped: Option<NodeHash>,
+}
+
+impl<H> SubtreeHashes<'_, '_, H> {
+    /// Take the node at `idx` from the budget, and return its cached hash if it has one.
     #[inline]
-    pub fn calc_root_hash(
-        &self,
-        hasher: &mut impl PortableHasher<32>,
-    ) -> Result<TrieRoot<NodeHash>> {
-        match self.root_node_idx()? {
-            TrieRoot::Node(idx) => Ok(TrieRoot::Node(self.calc_subtree_hash(hasher, idx)?)),
-            TrieRoot::Empty => Ok(TrieRoot::Empty),
+    fn visit(&mut self, idx: Idx) -> Result<Option<NodeHash>> {
+        let hashes = match &self.cache {
+            HashCache::Cached(hashes) => {
+                self.budget = self.budget.checked_sub(1).ok_or_else(|| {
+                    TrieError::from(format!(
+                        "Invalid snapshot: node {idx} is reachable through more than one branch"
+                    ))
+                })?;
+                *hashes
+            }
+            HashCache::Filling(hashes) => Some(&**hashes),
+        };
+        Ok(hashes
+            .zip(idx_to_usize(idx))
+            .and_then(|(hashes, i)| hashes.get(i).copied().flatten()))
+    }
+
+    /// Push the hash of the node at `idx`, recording it in the cache if filling it.
+    #[inline]
+    fn push(&mut self, idx: Idx, hash: NodeHash) -> Result<()> {
+        if let HashCache::Filling(hashes) = &mut self.cache {
+            if let Some(slot) = idx_to_usize(idx).and_then(|i| hashes.get_mut(i)) {
+                *slot = Some(hash);
+            }
+        }
+        self.push_hash(hash)
+    }
+
+    #[inline]
+    fn push_hash(&mut self, hash: NodeHash) -> Result<()> {
+        let slot = self
+            .stack
+            .get_mut(self.stack_len)
+            .ok_or("Invalid snapshot: the trie is deeper than the key")?;
+        *slot = hash;
+        self.stack_len += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Option<NodeHash> {
+        self.stack_len = self.stack_len.checked_sub(1)?;
+        Some(self.stack[self.stack_len])
+    }
+}
+
+impl<V: PortableHash, K: TrieKey, H: PortableHasher<32>> Visitor<V, K>
+    for SubtreeHashes<'_, '_, H>
+{
+    #[inline]
+    fn pre_branch(&mut self, idx: Idx, _: &Branch<Idx>) -> Result<VisitControl> {
+        self.skipped = self.visit(idx)?;
+        Ok(match self.skipped {
+            Some(_) => VisitControl::SkipSubtree,
+            None => VisitControl::Continue,
+        })
+    }
+
+    #[inline]
+    fn post_branch(&mut self, idx: Idx, branch: &Branch<Idx>) -> Result<()> {
+        // `walk` calls `post_branch` right after a `pre_branch` that skipped the children.
+        if let Some(hash) = self.skipped.take() {
+            return self.push_hash(hash);
         }
+
+        let (Some(right), Some(left)) = (self.pop(), self.pop()) else {
+            return Err("Invalid snapshot: branch is missing a child".into());
+        };
+
+        let hash = branch.hash_branch(self.hasher, &left, &right);
+        self.push(idx, hash)
+    }
+
+    #[inline]
+    fn leaf(&mut self, idx: Idx, leaf: &Leaf<V, K>) -> Result<()> {
+        let hash = match self.visit(idx)? {
+            Some(hash) => hash,
+            None => leaf.hash_leaf(self.hasher),
+        };
+        self.push(idx, hash)
+    }
+
+    #[inline]
+    fn unvisited(&mut self, idx: Idx, hash: &NodeHash) -> Result<()> {
+        self.visit(idx)?;
+        self.push_hash(*hash)
     }
 }
 
-impl<V: PortableHash> Store<V> for Snapshot<V> {
+impl<V: PortableHash, K: TrieKey> Store<V, K> for Snapshot<V, K> {
     type Error = TrieError;
 
-    // TODO fix possible stack overflow
-    // I dislike using an explicit mutable stack.
-    // I have an idea for abusing async for high performance segmented stacks
     /// Calculate the hash of the subtree.
     /// If you know the hashes of both children, you should use `Branch::hash_branch` instead.
     ///
@@ -99,74 +1415,107 @@ impl<V: PortableHash> Store<V> for Snapshot<V> {
         hasher: &mut impl PortableHasher<32>,
         node: Idx,
     ) -> Result<NodeHash> {
-        let idx = node as usize;
-        let leaf_offset = self.branches.len();
-        let unvisited_offset = leaf_offset + self.leaves.len();
-
-        if let Some(branch) = self.branches.get(idx) {
-            let left = self.calc_subtree_hash(hasher, branch.left)?;
-            let right = self.calc_subtree_hash(hasher, branch.right)?;
+        self.hash_subtree(hasher, node, self.cached_hashes())
+    }
 
-            Ok(branch.hash_branch(hasher, &left, &right))
-        } else if let Some(leaf) = self.leaves.get(idx - leaf_offset) {
-            Ok(leaf.hash_leaf(hasher))
-        } else if let Some(hash) = self.unvisited_nodes.get(idx - unvisited_offset) {
-            Ok(*hash)
-        } else {
-            Err(format!(
-                "Invalid snapshot: node {} not found\n\
+    #[inline]
+    fn get_node(&self, idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V, K>>> {
+        match self.layout().decode(idx) {
+            Some(NodeIdx::Branch(idx)) => Ok(Node::Branch(&self.branches[idx.0 as usize])),
+            Some(NodeIdx::Leaf(idx)) => Ok(Node::Leaf(&self.leaves[idx.0 as usize])),
+            Some(NodeIdx::Unvisited(_)) | None => Err(format!(
+                "Invalid snapshot: no visited node at index {}\n\
                 Snapshot has {} branches, {} leaves, and {} unvisited nodes",
                 idx,
                 self.branches.len(),
                 self.leaves.len(),
                 self.unvisited_nodes.len(),
             )
-            .into())
+            .into()),
         }
     }
 
     #[inline]
-    fn get_node(&self, idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V>>> {
-        let idx = idx as usize;
-        let leaf_offset = self.branches.len();
-        let unvisited_offset = leaf_offset + self.leaves.len();
-
-        if idx < leaf_offset {
-            Ok(Node::Branch(&self.branches[idx]))
-        } else if idx < unvisited_offset {
-            Ok(Node::Leaf(&self.leaves[idx - leaf_offset]))
-        } else {
-            Err(format!(
-                "Invalid snapshot: no visited node at index {}\n\
-                Snapshot has {} branches, {} leaves, and {} unvisited nodes",
-                idx,
-                self.branches.len(),
-                self.leaves.len(),
-                self.unvisited_nodes.len(),
-            )
-            .into())
+    fn get_unvisited_hash(&self, idx: Idx) -> Result<Option<NodeHash>> {
+        match self.node_idx(idx)? {
+            NodeIdx::Unvisited(idx) => Ok(Some(self.unvisited_nodes[idx.0 as usize])),
+            NodeIdx::Branch(_) | NodeIdx::Leaf(_) => Ok(None),
         }
     }
 }
 
-type NodeHashMaybeNode<'a, V> = (&'a NodeHash, Option<Node<&'a Branch<Idx>, &'a Leaf<V>>>);
+/// A node a `SnapshotBuilder` could not load from its database, usually because the database does not have it.
+///
+/// The builder keeps this as the source of the `TrieError` it returns,
+/// recover it with `TrieError::downcast_source`.
+/// A missing root means the root was pruned or never written.
+/// A missing node below a loaded parent means its subtree was pruned, or the database is corrupt.
+#[derive(Clone, Debug)]
+pub struct MissingNode {
+    /// The hash of the node that failed to load.
+    pub hash: NodeHash,
+    /// The hash of the branch the node was reached through, or `None` for the root.
+    pub parent: Option<NodeHash>,
+    /// The number of branches above the node, 0 for the root.
+    pub depth: usize,
+    /// The words of the key being read or written, when the load was part of a `Transaction::get`,
+    /// `Transaction::insert` or `Transaction::remove`.
+    pub key: Option<Box<[u32]>>,
+    /// The database's error.
+    pub source: TrieError,
+}
+
+impl MissingNode {
+    #[inline]
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none()
+    }
+}
+
+impl fmt::Display for MissingNode {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error getting {} from database", self.hash)?;
+        match &self.parent {
+            Some(parent) => write!(f, " at depth {} below {parent}", self.depth)?,
+            None => write!(f, " as the root")?,
+        }
+        if let Some(key) = &self.key {
+            write!(f, " while traversing key {key:?}")?;
+        }
+        write!(f, ": {}", self.source)
+    }
+}
 
-pub struct SnapshotBuilder<Db: 'static, V: 'static> {
-    inner: SnapshotBuilderInner<Db, V>,
+impl core::error::Error for MissingNode {
+    #[inline]
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.source)
+    }
 }
 
-#[self_referencing]
-struct SnapshotBuilderInner<Db: 'static, V: 'static> {
+/// A node the builder knows the hash of, and the node itself once it has been loaded.
+type NodeSlot<V, K> = (
+    NodeHash,
+    core::cell::OnceCell<Node<Branch<Idx>, Leaf<V, K>>>,
+);
+
+/// Loads nodes from a database on demand, recording every node a transaction visits.
+///
+/// Loaded nodes live in an append-only arena and refer to their children by index,
+/// so the builder is a plain struct with no borrows of its own.
+/// It is `Send` when its database and values are, but not `Sync`.
+pub struct SnapshotBuilder<Db, V, K = KeyHash> {
     db: Db,
-    bump: Bump,
 
     /// The root of the trie is always at index 0
-    #[borrows(bump)]
-    #[not_covariant]
-    nodes: RefCell<Vec<NodeHashMaybeNode<'this, V>>>,
+    nodes: AppendOnly<NodeSlot<V, K>>,
+
+    #[cfg(feature = "std")]
+    prefetcher: Option<Arc<Prefetcher>>,
 }
 
-impl<Db: DatabaseGet<V>, V: Clone> Store<V> for SnapshotBuilder<Db, V> {
+impl<Db: DatabaseGet<V, K>, V: Clone, K> Store<V, K> for SnapshotBuilder<Db, V, K> {
     type Error = TrieError;
 
     #[inline]
@@ -175,93 +1524,120 @@ impl<Db: DatabaseGet<V>, V: Clone> Store<V> for SnapshotBuilder<Db, V> {
         _: &mut impl PortableHasher<32>,
         hash_idx: Idx,
     ) -> Result<NodeHash, Self::Error> {
-        let hash_idx = hash_idx as usize;
-
-        self.inner.with_nodes(|nodes| {
-            let nodes = nodes.borrow();
-            nodes.get(hash_idx).map(|(hash, _)| **hash).ok_or_else(|| {
+        idx_to_usize(hash_idx)
+            .and_then(|i| self.nodes.get(i))
+            .map(|(hash, _)| *hash)
+            .ok_or_else(|| {
                 format!(
                     "Invalid snapshot: no unvisited node at index {}\n\
                         SnapshotBuilder has {} nodes",
                     hash_idx,
-                    nodes.len()
+                    self.nodes.len()
                 )
                 .into()
             })
-        })
     }
 
     #[inline]
-    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V>>, Self::Error> {
-        let hash_idx = hash_idx as usize;
-        self.inner.with(|this| {
-            let mut nodes = this.nodes.borrow_mut();
-
-            let Some((hash, o_node)) = nodes.get(hash_idx).map(|(hash, o_node)| (hash, *o_node))
-            else {
-                return Err(format!(
-                    "Invalid snapshot: no node at index {}\n\
+    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V, K>>, Self::Error> {
+        let Some((hash, slot)) = idx_to_usize(hash_idx).and_then(|i| self.nodes.get(i)) else {
+            return Err(format!(
+                "Invalid snapshot: no node at index {}\n\
                 SnapshotBuilder has {} nodes",
-                    hash_idx,
-                    nodes.len()
-                )
-                .into());
-            };
+                hash_idx,
+                self.nodes.len()
+            )
+            .into());
+        };
 
-            if let Some(node) = o_node {
-                return Ok(node);
-            }
+        if let Some(node) = slot.get() {
+            return Ok(node.as_ref());
+        }
+
+        let node = self
+            .db
+            .get(hash)
+            .map_err(|e| self.missing_node(hash_idx, *hash, e.into()))?;
+
+        let node = match node {
+            Node::Branch(Branch {
+                mask,
+                left,
+                right,
+                prior_word,
+                prefix,
+            }) => {
+                if !indexable(self.nodes.len() + 2) {
+                    return Err(format!(
+                        "Cannot load branch {hash}: SnapshotBuilder has {} nodes, \
+                        and its children would be past the {MAX_NODES} an `Idx` can index on this target",
+                        self.nodes.len()
+                    )
+                    .into());
+                }
+
+                #[cfg(feature = "std")]
+                if let Some(prefetcher) = &self.prefetcher {
+                    prefetcher.prefetch(left);
+                    prefetcher.prefetch(right);
+                }
 
-            let node = this
-                .db
-                .get(hash)
-                .map_err(|e| format!("Error getting {hash} from database: `{e}`"))?;
+                let left = self.nodes.push((left, Default::default())) as Idx;
+                let right = self.nodes.push((right, Default::default())) as Idx;
 
-            let node = match node {
                 Node::Branch(Branch {
                     mask,
                     left,
                     right,
                     prior_word,
                     prefix,
-                }) => {
-                    let idx = nodes.len() as Idx;
-
-                    let left = this.bump.alloc(left);
-                    let right = this.bump.alloc(right);
-
-                    nodes.push((&*left, None));
-                    nodes.push((&*right, None));
-
-                    Node::Branch(&*this.bump.alloc(Branch {
-                        mask,
-                        left: idx,
-                        right: idx + 1,
-                        prior_word,
-                        prefix,
-                    }))
-                }
-                Node::Leaf(leaf) => Node::Leaf(&*this.bump.alloc(leaf)),
-            };
+                })
+            }
+            Node::Leaf(leaf) => Node::Leaf(leaf),
+        };
 
-            nodes[hash_idx].1 = Some(node);
-            Ok(node)
-        })
+        // Loading a node never loads another, so the slot is still empty.
+        Ok(slot.get_or_init(|| node).as_ref())
     }
 }
 
-impl<Db, V> SnapshotBuilderInner<Db, V> {
-    fn new_with_db(db: Db) -> Self {
-        SnapshotBuilderInnerBuilder {
-            db,
-            bump: Bump::new(),
-            nodes_builder: |_| RefCell::new(Vec::new()),
+impl<Db, V, K> SnapshotBuilder<Db, V, K> {
+    /// Wrap the database error for the node at `hash_idx` in a `MissingNode`.
+    ///
+    /// Nodes do not record their parents, so this scans the loaded branches once per ancestor.
+    /// That is only paid when a load fails.
+    #[cold]
+    fn missing_node(&self, hash_idx: Idx, hash: NodeHash, source: TrieError) -> TrieError {
+        let parent_of = |child: Idx| {
+            self.nodes
+                .iter()
+                .position(|(_, slot)| match slot.get() {
+                    Some(Node::Branch(branch)) => branch.left == child || branch.right == child,
+                    _ => false,
+                })
+                .map(|idx| idx as Idx)
+        };
+
+        let mut parent = None;
+        let mut depth = 0;
+        let mut idx = hash_idx;
+        while let Some(parent_idx) = parent_of(idx) {
+            if depth == 0 {
+                parent = self.nodes.get(parent_idx as usize).map(|(hash, _)| *hash);
+            }
+            depth += 1;
+            idx = parent_idx;
         }
-        .build()
+
+        TrieError::from_source(MissingNode {
+            hash,
+            parent,
+            depth,
+            key: None,
+            source,
+        })
     }
-}
 
-impl<Db, V> SnapshotBuilder<Db, V> {
     /// Create a new `SnapshotBuilder` with the given database from a trie root hash.
     ///
     /// This is an alias for `SnapshotBuilderBuilder::empty(db).with_trie_root_hash(root_hash)`.
@@ -273,13 +1649,37 @@ impl<Db, V> SnapshotBuilder<Db, V> {
     #[inline]
     pub fn empty(db: Db) -> Self {
         SnapshotBuilder {
-            inner: SnapshotBuilderInner::new_with_db(db),
+            db,
+            nodes: AppendOnly::new(),
+            #[cfg(feature = "std")]
+            prefetcher: None,
         }
     }
 
+    /// Queue reads of both children of every branch the builder loads on `prefetcher`.
+    ///
+    /// The prefetched nodes are only found if `Db` is a `CachedDb` over the prefetcher's cache,
+    /// see `stored::prefetch`.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn with_prefetcher(mut self, prefetcher: Arc<Prefetcher>) -> Self {
+        self.prefetcher = Some(prefetcher);
+        self
+    }
+
+    /// Discard all loaded nodes and start over from `root_hash`, as if created with `SnapshotBuilder::new`.
+    ///
+    /// The database handle, prefetcher and the arena's allocations are kept,
+    /// so building consecutive blocks with one builder avoids reallocating the arena per block.
+    #[inline]
+    pub fn reset_to_root(mut self, root_hash: TrieRoot<NodeHash>) -> Self {
+        self.nodes.clear();
+        self.with_trie_root_hash(root_hash)
+    }
+
     #[inline]
     pub fn db(&self) -> &Db {
-        self.inner.borrow_db()
+        &self.db
     }
 
     #[inline]
@@ -292,91 +1692,112 @@ impl<Db, V> SnapshotBuilder<Db, V> {
 
     #[inline]
     pub fn with_root_hash(self, root_hash: NodeHash) -> Self {
-        self.inner.with(|this| {
-            let root_hash = this.bump.alloc(root_hash);
-            this.nodes.borrow_mut().push((&*root_hash, None));
-        });
+        self.nodes.push((root_hash, Default::default()));
         self
     }
 
     #[inline]
-    pub fn trie_root(&self) -> TrieRoot<NodeRef<V>> {
-        self.inner.with_nodes(|nodes| match nodes.borrow().first() {
-            Some(_) => TrieRoot::Node(NodeRef::Stored(0)),
-            None => TrieRoot::Empty,
-        })
+    pub fn trie_root(&self) -> TrieRoot<NodeRef<V, K>> {
+        if self.nodes.is_empty() {
+            TrieRoot::Empty
+        } else {
+            TrieRoot::Node(NodeRef::Stored(0))
+        }
     }
 
     #[inline]
     pub fn get_node_hash(&self, idx: Idx) -> Result<NodeHash, TrieError> {
-        self.inner.with_nodes(|nodes| {
-            let nodes = nodes.borrow();
-            nodes
-                .get(idx as usize)
-                .map(|(hash, _)| **hash)
-                .ok_or_else(|| {
-                    TrieError::from(format!(
-                        "Invalid snapshot: no node at index {}\n\
+        idx_to_usize(idx)
+            .and_then(|i| self.nodes.get(i))
+            .map(|(hash, _)| *hash)
+            .ok_or_else(|| {
+                TrieError::from(format!(
+                    "Invalid snapshot: no node at index {}\n\
                     SnapshotBuilder has {} nodes",
-                        idx,
-                        nodes.len()
-                    ))
-                })
-        })
+                    idx,
+                    self.nodes.len()
+                ))
+            })
     }
 
+    /// Maps each loaded node that is not reachable from any of `roots` with `f`,
+    /// walking only through loaded nodes.
+    /// `f` receives `None` for a node known only by hash.
     #[inline]
-    pub fn build_initial_snapshot(&self) -> Snapshot<V>
+    pub(crate) fn unreachable_nodes<T>(
+        &self,
+        roots: &[Idx],
+        mut f: impl FnMut(&NodeHash, Option<Node<&Branch<Idx>, &Leaf<V, K>>>) -> T,
+    ) -> Vec<T> {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack = roots.to_vec();
+
+        while let Some(idx) = stack.pop() {
+            reachable[idx as usize] = true;
+            if let Some((_, slot)) = self.nodes.get(idx as usize) {
+                if let Some(Node::Branch(branch)) = slot.get() {
+                    stack.push(branch.left);
+                    stack.push(branch.right);
+                }
+            }
+        }
+
+        self.nodes
+            .iter()
+            .zip(reachable)
+            .filter(|(_, reachable)| !reachable)
+            .map(|((hash, slot), _)| f(hash, slot.get().map(Node::as_ref)))
+            .collect()
+    }
+
+    #[inline]
+    pub fn build_initial_snapshot(&self) -> Snapshot<V, K>
     where
         V: Clone,
+        K: Clone,
     {
-        self.inner.with_nodes(|nodes| {
-            let nodes = nodes.borrow();
-            if nodes.is_empty() {
-                Snapshot {
-                    branches: Box::new([]),
-                    leaves: Box::new([]),
-                    unvisited_nodes: Box::new([]),
-                }
-            } else {
-                let mut state = SnapshotBuilderFold::new(&nodes);
-                let root_idx = state.fold(0);
+        if self.nodes.is_empty() {
+            Snapshot {
+                branches: Box::new([]),
+                leaves: Box::new([]),
+                unvisited_nodes: Box::new([]),
+                subtree_hashes: SubtreeHashCache::default(),
+            }
+        } else {
+            let mut state = SnapshotBuilderFold::new(&self.nodes);
+            let root_idx = state.fold(0);
 
-                debug_assert!(
-                    state.branches.is_empty() || root_idx == state.branches.len() as Idx - 1
-                );
-                debug_assert_eq!(state.branch_count, state.branches.len() as u32);
-                debug_assert_eq!(state.leaf_count, state.leaves.len() as u32);
-                debug_assert_eq!(state.unvisited_count, state.unvisited_nodes.len() as u32);
+            debug_assert!(state.branches.is_empty() || root_idx == state.branches.len() as Idx - 1);
+            debug_assert_eq!(state.layout.branches, state.branches.len() as Idx);
+            debug_assert_eq!(state.layout.leaves, state.leaves.len() as Idx);
+            debug_assert_eq!(
+                state.layout.unvisited_nodes,
+                state.unvisited_nodes.len() as Idx
+            );
 
-                state.build()
-            }
-        })
+            state.build()
+        }
     }
 }
 
-struct SnapshotBuilderFold<'v, 'a, V> {
-    nodes: &'v [NodeHashMaybeNode<'a, V>],
-    /// The count of branches that will be in the snapshot
-    branch_count: u32,
-    /// The count of leaves that will be in the snapshot
-    leaf_count: u32,
-    /// The count of unvisited nodes that will be in the snapshot
-    unvisited_count: u32,
+struct SnapshotBuilderFold<'v, V, K> {
+    nodes: &'v AppendOnly<NodeSlot<V, K>>,
+    /// The layout of the snapshot being built
+    layout: Layout,
     branches: Vec<Branch<Idx>>,
-    leaves: Vec<Leaf<V>>,
+    leaves: Vec<Leaf<V, K>>,
     unvisited_nodes: Vec<NodeHash>,
 }
 
-impl<'v, 'a, V> SnapshotBuilderFold<'v, 'a, V> {
+impl<'v, V, K> SnapshotBuilderFold<'v, V, K> {
     #[inline]
-    fn new(nodes: &'v [NodeHashMaybeNode<'a, V>]) -> Self {
+    fn new(nodes: &'v AppendOnly<NodeSlot<V, K>>) -> Self {
         let mut branch_count = 0;
         let mut leaf_count = 0;
         let mut unvisited_count = 0;
 
-        for (_, node) in nodes.iter() {
-            match node {
+        for (_, slot) in nodes.iter() {
+            match slot.get() {
                 Some(Node::Branch(_)) => branch_count += 1,
                 Some(Node::Leaf(_)) => leaf_count += 1,
                 None => unvisited_count += 1,
@@ -385,9 +1806,11 @@ impl<'v, 'a, V> SnapshotBuilderFold<'v, 'a, V> {
 
         SnapshotBuilderFold {
             nodes,
-            branch_count,
-            leaf_count,
-            unvisited_count,
+            layout: Layout {
+                branches: branch_count,
+                leaves: leaf_count,
+                unvisited_nodes: unvisited_count,
+            },
             branches: Vec::with_capacity(branch_count as usize),
             leaves: Vec::with_capacity(leaf_count as usize),
             unvisited_nodes: Vec::with_capacity(unvisited_count as usize),
@@ -396,32 +1819,37 @@ impl<'v, 'a, V> SnapshotBuilderFold<'v, 'a, V> {
 
     #[inline]
     fn push_branch(&mut self, branch: Branch<Idx>) -> Idx {
-        let idx = self.branches.len() as Idx;
+        let idx = BranchIdx(self.branches.len() as Idx);
         self.branches.push(branch);
-        idx
+        self.layout.encode(NodeIdx::Branch(idx))
     }
 
     #[inline]
-    fn push_leaf(&mut self, leaf: Leaf<V>) -> Idx {
-        let idx = self.leaves.len() as Idx;
+    fn push_leaf(&mut self, leaf: Leaf<V, K>) -> Idx {
+        let idx = LeafIdx(self.leaves.len() as Idx);
         self.leaves.push(leaf);
-        self.branch_count + idx
+        self.layout.encode(NodeIdx::Leaf(idx))
     }
 
     #[inline]
     fn push_unvisited(&mut self, hash: NodeHash) -> Idx {
-        let idx = self.unvisited_nodes.len() as Idx;
+        let idx = UnvisitedIdx(self.unvisited_nodes.len() as Idx);
         self.unvisited_nodes.push(hash);
-        self.branch_count + self.leaf_count + idx
+        self.layout.encode(NodeIdx::Unvisited(idx))
     }
 
     #[inline]
     fn fold(&mut self, node_idx: Idx) -> Idx
     where
         V: Clone,
+        K: Clone,
     {
-        match self.nodes[node_idx as usize] {
-            (_, Some(Node::Branch(branch))) => {
+        let nodes = self.nodes;
+        let (hash, slot) = nodes
+            .get(node_idx as usize)
+            .expect("SnapshotBuilder children are pushed before their parent is loaded");
+        match slot.get() {
+            Some(Node::Branch(branch)) => {
                 let left = self.fold(branch.left);
                 let right = self.fold(branch.right);
 
@@ -435,17 +1863,18 @@ impl<'v, 'a, V> SnapshotBuilderFold<'v, 'a, V> {
             }
             // We could remove the clone by taking ownership of the SnapshotBuilder.
             // However, given this only runs on the server we can afford the clone.
-            (_, Some(Node::Leaf(leaf))) => self.push_leaf((*leaf).clone()),
-            (hash, None) => self.push_unvisited(*hash),
+            Some(Node::Leaf(leaf)) => self.push_leaf(leaf.clone()),
+            None => self.push_unvisited(*hash),
         }
     }
 
     #[inline]
-    fn build(self) -> Snapshot<V> {
+    fn build(self) -> Snapshot<V, K> {
         Snapshot {
             branches: self.branches.into_boxed_slice(),
             leaves: self.leaves.into_boxed_slice(),
             unvisited_nodes: self.unvisited_nodes.into_boxed_slice(),
+            subtree_hashes: SubtreeHashCache::default(),
         }
     }
 }
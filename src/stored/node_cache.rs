@@ -0,0 +1,208 @@
+//! A cache of decoded nodes that several `SnapshotBuilder`s can share.
+//!
+//! Nodes are immutable and keyed by their hash, so a cached node is never stale,
+//! whichever builder loaded or wrote it.
+//! Building consecutive blocks re-reads the same upper levels of the trie every block,
+//! sharing one `NodeCache` between the blocks' builders keeps those reads off the database.
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::{
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use std::sync::{PoisonError, RwLock};
+
+use crate::{
+    stored::{DatabaseGet, DatabaseSet, Node, NodeHash},
+    Branch, KeyHash, Leaf,
+};
+
+type DbNode<V, K> = Node<Branch<NodeHash>, Leaf<V, K>>;
+
+/// A bounded map from node hashes to decoded nodes, safe to share between threads.
+///
+/// Nodes are kept in two generations of at most `capacity` nodes each.
+/// When the current generation is full it becomes the previous one, and the old previous one is dropped.
+/// A node found in the previous generation moves back into the current one,
+/// so nodes read every block stay cached.
+#[derive(Debug)]
+pub struct NodeCache<V, K = KeyHash> {
+    capacity: usize,
+    generations: RwLock<Generations<V, K>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Generations<V, K> {
+    current: BTreeMap<NodeHash, DbNode<V, K>>,
+    previous: BTreeMap<NodeHash, DbNode<V, K>>,
+}
+
+impl<V, K> Generations<V, K> {
+    #[inline]
+    fn insert(&mut self, capacity: usize, hash: NodeHash, node: DbNode<V, K>) {
+        if self.current.len() >= capacity && !self.current.contains_key(&hash) {
+            self.previous = mem::take(&mut self.current);
+        }
+        self.current.insert(hash, node);
+    }
+}
+
+impl<V, K> NodeCache<V, K> {
+    /// Create an empty cache holding up to `2 * capacity` nodes.
+    #[inline]
+    pub fn new(capacity: usize) -> Self {
+        NodeCache {
+            capacity: capacity.max(1),
+            generations: RwLock::new(Generations {
+                current: BTreeMap::new(),
+                previous: BTreeMap::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The number of nodes cached.
+    #[inline]
+    pub fn len(&self) -> usize {
+        let generations = self.read();
+        generations.current.len() + generations.previous.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of lookups that found their node.
+    #[inline]
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of lookups that did not find their node.
+    #[inline]
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Whether the node `hash` is cached, without counting a hit or miss.
+    #[inline]
+    pub fn contains(&self, hash: &NodeHash) -> bool {
+        let generations = self.read();
+        generations.current.contains_key(hash) || generations.previous.contains_key(hash)
+    }
+
+    /// Drop every cached node.
+    #[inline]
+    pub fn clear(&self) {
+        let mut generations = self.write();
+        generations.current.clear();
+        generations.previous.clear();
+    }
+
+    #[inline]
+    pub fn insert(&self, hash: NodeHash, node: DbNode<V, K>) {
+        self.write().insert(self.capacity, hash, node);
+    }
+
+    // The maps are valid after any panic, so a poisoned lock is still safe to use.
+    #[inline]
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Generations<V, K>> {
+        self.generations
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[inline]
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Generations<V, K>> {
+        self.generations
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<V: Clone, K: Clone> NodeCache<V, K> {
+    #[inline]
+    pub fn get(&self, hash: &NodeHash) -> Option<DbNode<V, K>> {
+        let generations = self.read();
+        if let Some(node) = generations.current.get(hash) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Some(node.clone());
+        }
+        let Some(node) = generations.previous.get(hash).cloned() else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        drop(generations);
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.write().insert(self.capacity, *hash, node.clone());
+        Some(node)
+    }
+}
+
+/// Wraps a database, looking nodes up in a shared `NodeCache` before reading them from the database.
+///
+/// Nodes read from the database and nodes written to it are added to the cache.
+/// Give each `SnapshotBuilder` its own `CachedDb` over a clone of the same `Arc<NodeCache>`.
+#[derive(Debug)]
+pub struct CachedDb<D, V, K = KeyHash> {
+    db: D,
+    cache: Arc<NodeCache<V, K>>,
+}
+
+impl<D, V, K> CachedDb<D, V, K> {
+    #[inline]
+    pub fn new(db: D, cache: Arc<NodeCache<V, K>>) -> Self {
+        CachedDb { db, cache }
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    #[inline]
+    pub fn cache(&self) -> &Arc<NodeCache<V, K>> {
+        &self.cache
+    }
+}
+
+impl<V: Clone, K: Clone, D: DatabaseGet<V, K>> DatabaseGet<V, K> for CachedDb<D, V, K> {
+    type GetError = D::GetError;
+
+    #[inline]
+    fn get(&self, hash: &NodeHash) -> Result<DbNode<V, K>, Self::GetError> {
+        if let Some(node) = self.cache.get(hash) {
+            return Ok(node);
+        }
+
+        let node = self.db.get(hash)?;
+        self.cache.insert(*hash, node.clone());
+        Ok(node)
+    }
+}
+
+impl<V: Clone, K: Clone, D: DatabaseSet<V, K>> DatabaseSet<V, K> for CachedDb<D, V, K> {
+    type SetError = D::SetError;
+
+    #[inline]
+    fn set(&self, hash: NodeHash, node: DbNode<V, K>) -> Result<(), Self::SetError> {
+        self.db.set(hash, node.clone())?;
+        self.cache.insert(hash, node);
+        Ok(())
+    }
+
+    #[inline]
+    fn begin_batch(&self) -> Result<(), Self::SetError> {
+        self.db.begin_batch()
+    }
+
+    #[inline]
+    fn end_batch(&self, completed: bool) -> Result<(), Self::SetError> {
+        self.db.end_batch(completed)
+    }
+}
@@ -0,0 +1,191 @@
+//! Background prefetch of the children of the branches a `SnapshotBuilder` loads.
+//!
+//! A transaction descends the trie one branch at a time, so each step waits on one database read.
+//! With a `Prefetcher`, loading a branch queues reads of both of its children on worker threads,
+//! which put the nodes in a `NodeCache`.
+//! By the time the transaction descends, the child it needs is usually cached.
+//!
+//! Prefetching is best effort: requests are dropped while the queue is full,
+//! and failed reads are only counted, the builder's own read reports the error.
+//! A read that panics is counted as failed, and the worker moves on to the next hash.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    panic::AssertUnwindSafe,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use std::{
+    panic,
+    sync::{
+        mpsc::{self, SyncSender},
+        Condvar, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::stored::{node_cache::NodeCache, DatabaseGet, NodeHash};
+
+/// Counts of what a `Prefetcher` did with the hashes it was asked to prefetch.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PrefetchStats {
+    /// Hashes queued for a worker.
+    pub requested: u64,
+    /// Hashes not queued because the queue was full.
+    pub dropped: u64,
+    /// Nodes read from the database and added to the cache.
+    pub fetched: u64,
+    /// Hashes skipped because the cache already had the node.
+    pub cached: u64,
+    /// Reads that failed or panicked.
+    pub failed: u64,
+}
+
+impl PrefetchStats {
+    /// The number of queued hashes no worker has finished with yet.
+    #[inline]
+    pub fn pending(&self) -> u64 {
+        // The counters are read one at a time, so a worker may finish a hash between the reads.
+        self.requested
+            .saturating_sub(self.fetched + self.cached + self.failed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    requested: AtomicU64,
+    dropped: AtomicU64,
+    fetched: AtomicU64,
+    cached: AtomicU64,
+    failed: AtomicU64,
+    /// Notified each time a worker finishes with a hash, see `wait_idle`.
+    idle: (Mutex<()>, Condvar),
+}
+
+impl Counters {
+    #[inline]
+    fn finish(&self, counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+        // Taking the lock orders the notification after a waiter's check of `pending`.
+        let _guard = self.idle.0.lock().unwrap_or_else(PoisonError::into_inner);
+        self.idle.1.notify_all();
+    }
+}
+
+/// Worker threads reading nodes into a `NodeCache` ahead of a `SnapshotBuilder`.
+///
+/// Attach it with `SnapshotBuilder::with_prefetcher`,
+/// and give the builder a `CachedDb` over the same cache, so the builder finds the prefetched nodes.
+/// One prefetcher can serve several builders.
+/// Dropping the last handle stops the workers once they have drained the queue.
+#[derive(Debug)]
+pub struct Prefetcher {
+    sender: Option<SyncSender<NodeHash>>,
+    workers: Vec<JoinHandle<()>>,
+    counters: Arc<Counters>,
+}
+
+impl Prefetcher {
+    /// Spawn `workers` threads reading from `db` into `cache`, with room for `queue` pending hashes.
+    ///
+    /// `db` should be the database the builder's `CachedDb` wraps, not the `CachedDb` itself.
+    /// A small queue is enough, a builder requests two children per branch it loads.
+    #[inline]
+    pub fn spawn<D, V, K>(db: D, cache: Arc<NodeCache<V, K>>, workers: usize, queue: usize) -> Self
+    where
+        D: DatabaseGet<V, K> + Clone + Send + 'static,
+        V: Clone + Send + Sync + 'static,
+        K: Clone + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(queue.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        let counters = Arc::new(Counters::default());
+
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let db = db.clone();
+                let cache = cache.clone();
+                let receiver = receiver.clone();
+                let counters = counters.clone();
+                thread::spawn(move || loop {
+                    // The receiver is valid after any panic, so a poisoned lock is still safe to use.
+                    let next = receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .recv();
+                    let Ok(hash) = next else {
+                        return;
+                    };
+
+                    // A panicking read must still be counted, or `wait_idle` would never return.
+                    let counter = panic::catch_unwind(AssertUnwindSafe(|| {
+                        if cache.contains(&hash) {
+                            return &counters.cached;
+                        }
+                        match db.get(&hash) {
+                            Ok(node) => {
+                                cache.insert(hash, node);
+                                &counters.fetched
+                            }
+                            Err(_) => &counters.failed,
+                        }
+                    }))
+                    .unwrap_or(&counters.failed);
+                    counters.finish(counter);
+                })
+            })
+            .collect();
+
+        Prefetcher {
+            sender: Some(sender),
+            workers,
+            counters,
+        }
+    }
+
+    /// Queue a read of the node `hash`, unless the queue is full.
+    #[inline]
+    pub fn prefetch(&self, hash: NodeHash) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        // Counted before sending, so `wait_idle` never misses a hash a worker is handling.
+        self.counters.requested.fetch_add(1, Ordering::Relaxed);
+        if sender.try_send(hash).is_err() {
+            self.counters.requested.fetch_sub(1, Ordering::Relaxed);
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    pub fn stats(&self) -> PrefetchStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        PrefetchStats {
+            requested: load(&self.counters.requested),
+            dropped: load(&self.counters.dropped),
+            fetched: load(&self.counters.fetched),
+            cached: load(&self.counters.cached),
+            failed: load(&self.counters.failed),
+        }
+    }
+
+    /// Block until the workers have finished with every queued hash.
+    #[inline]
+    pub fn wait_idle(&self) {
+        let (lock, condvar) = &self.counters.idle;
+        let mut guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        while self.stats().pending() > 0 {
+            guard = condvar.wait(guard).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl Drop for Prefetcher {
+    #[inline]
+    fn drop(&mut self) {
+        // Closing the channel ends each worker's loop once the queue is empty.
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
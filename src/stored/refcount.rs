@@ -0,0 +1,195 @@
+//! Reference counts of stored nodes, so a garbage collector can tell which nodes no root needs.
+//!
+//! Feed every event of `Transaction::commit_with_events` to `RefCountDb::apply`,
+//! then delete the nodes `RefCountDb::collect` returns.
+//! A node is only returned once its count is zero and no pinned root reaches it,
+//! so a prover that pinned a root with `DatabasePin::pin_root` can keep reading it after newer commits.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    vec::Vec,
+};
+use core::cell::RefCell;
+
+use crate::{
+    errors::error_context,
+    stored::{DatabaseGet, DatabasePin, DatabaseSet, Node, NodeHash},
+    Branch, CommitEvent, Leaf, TrieError,
+};
+
+/// A database that keeps a reference count of each node written through `commit_with_events`,
+/// and the roots pinned against collection.
+#[derive(Debug)]
+pub struct RefCountDb<Db> {
+    db: Db,
+    state: RefCell<RefCounts>,
+}
+
+#[derive(Debug, Default)]
+struct RefCounts {
+    counts: BTreeMap<NodeHash, u64>,
+    pins: BTreeMap<NodeHash, u64>,
+    /// Nodes whose count fell to zero that have not been collected yet.
+    unreferenced: BTreeSet<NodeHash>,
+}
+
+impl<Db> RefCountDb<Db> {
+    #[inline]
+    pub fn new(db: Db) -> Self {
+        RefCountDb {
+            db,
+            state: RefCell::default(),
+        }
+    }
+
+    #[inline]
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    /// Update the reference counts with an event from `Transaction::commit_with_events`.
+    ///
+    /// Orphaning a node that has no references is an error,
+    /// the events are either from another database or were applied twice.
+    #[inline]
+    pub fn apply(&self, event: CommitEvent) -> Result<(), TrieError> {
+        let state = &mut *self.state.borrow_mut();
+        match event {
+            CommitEvent::Written(hash) => {
+                *state.counts.entry(hash).or_insert(0) += 1;
+                state.unreferenced.remove(&hash);
+            }
+            CommitEvent::Orphaned(hash) => {
+                let Some(count) = state.counts.get_mut(&hash) else {
+                    return Err(format!("Orphaned node {hash} has no references").into());
+                };
+                *count -= 1;
+                if *count == 0 {
+                    state.counts.remove(&hash);
+                    state.unreferenced.insert(hash);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of roots, among those committed through `apply`, that reach the node.
+    #[inline]
+    pub fn ref_count(&self, hash: &NodeHash) -> u64 {
+        self.state.borrow().counts.get(hash).copied().unwrap_or(0)
+    }
+
+    /// Return the nodes that are safe to delete, and forget them.
+    ///
+    /// These are the nodes with no references that are not reachable from a pinned root.
+    /// Unreferenced nodes under a pinned root are kept until a later `collect` after the root is unpinned.
+    /// Reads the unreferenced nodes under each pinned root from the database.
+    #[inline]
+    pub fn collect<V, K>(&self) -> Result<Vec<NodeHash>, TrieError>
+    where
+        Db: DatabaseGet<V, K>,
+    {
+        let state = &mut *self.state.borrow_mut();
+
+        // A referenced node is in the current trie, and so is every node below it,
+        // so only unreferenced nodes need to be walked.
+        let mut kept = BTreeSet::new();
+        let mut stack: Vec<NodeHash> = state
+            .pins
+            .keys()
+            .filter(|root| state.unreferenced.contains(root))
+            .copied()
+            .collect();
+
+        while let Some(hash) = stack.pop() {
+            if !kept.insert(hash) {
+                continue;
+            }
+            let node = self
+                .db
+                .get(&hash)
+                .map_err(|e| error_context(e, format_args!("Error getting pinned node {hash}")))?;
+            if let Node::Branch(Branch { left, right, .. }) = node {
+                stack.extend(
+                    [left, right]
+                        .into_iter()
+                        .filter(|child| state.unreferenced.contains(child)),
+                );
+            }
+        }
+
+        let collected = state
+            .unreferenced
+            .difference(&kept)
+            .copied()
+            .collect::<Vec<_>>();
+        state.unreferenced = kept;
+        Ok(collected)
+    }
+}
+
+impl<Db> DatabasePin for RefCountDb<Db> {
+    type PinError = TrieError;
+
+    /// Pin a root that is referenced, or unreferenced but not yet collected.
+    #[inline]
+    fn pin_root(&self, root: NodeHash) -> Result<(), Self::PinError> {
+        let state = &mut *self.state.borrow_mut();
+        if !state.counts.contains_key(&root) && !state.unreferenced.contains(&root) {
+            return Err(format!("Cannot pin {root}, it is not a stored node").into());
+        }
+        *state.pins.entry(root).or_insert(0) += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn unpin_root(&self, root: NodeHash) -> Result<(), Self::PinError> {
+        let pins = &mut self.state.borrow_mut().pins;
+        let Some(count) = pins.get_mut(&root) else {
+            return Err(format!("Cannot unpin {root}, it is not pinned").into());
+        };
+        *count -= 1;
+        if *count == 0 {
+            pins.remove(&root);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn is_pinned(&self, root: &NodeHash) -> Result<bool, Self::PinError> {
+        Ok(self.state.borrow().pins.contains_key(root))
+    }
+}
+
+impl<V, K, Db: DatabaseGet<V, K>> DatabaseGet<V, K> for RefCountDb<Db> {
+    type GetError = Db::GetError;
+
+    #[inline]
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V, K>>, Self::GetError> {
+        self.db.get(hash)
+    }
+}
+
+impl<V, K, Db: DatabaseSet<V, K>> DatabaseSet<V, K> for RefCountDb<Db> {
+    type SetError = Db::SetError;
+
+    #[inline]
+    fn set(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<V, K>>,
+    ) -> Result<(), Self::SetError> {
+        self.db.set(hash, node)
+    }
+
+    #[inline]
+    fn begin_batch(&self) -> Result<(), Self::SetError> {
+        self.db.begin_batch()
+    }
+
+    #[inline]
+    fn end_batch(&self, completed: bool) -> Result<(), Self::SetError> {
+        self.db.end_batch(completed)
+    }
+}
@@ -0,0 +1,302 @@
+//! A streamed encoding of snapshots, verified in one pass without decoding the snapshot.
+//!
+//! A guest whose only job is to check the pre-state root does not need the snapshot's nodes afterwards.
+//! `Snapshot::verify_streaming` hashes the nodes as it reads them, keeping one hash per level of the trie,
+//! so it holds at most `MAX_STREAM_DEPTH` hashes however large the witness is.
+//!
+//! The nodes are written in post-order, children before their parent and left before right,
+//! so every branch finds its children's hashes on top of the stack:
+//!
+//! | bytes | field                                       |
+//! |-------|---------------------------------------------|
+//! | 4     | `STREAM_MAGIC`                              |
+//! | 8     | the number of nodes, little endian          |
+//! | per node | a kind byte, then the node's fields      |
+//!
+//! | kind | node      | fields                                                                                      |
+//! |------|-----------|---------------------------------------------------------------------------------------------|
+//! | 0    | branch    | `bit_idx`, `left_prefix`, `prior_word`, as 4 byte little endian words, a prefix length byte, then the prefix words |
+//! | 1    | leaf      | the 32 bytes of `KeyHash::to_bytes`, a 4 byte little endian length, then the value as fed to the hasher by `PortableHash` |
+//! | 2    | unvisited | the node's 32 byte hash                                                                     |
+//!
+//! A leaf holds its value's hash preimage, so the guest hashes it without knowing the value type.
+//! The stream ends after the last node, anything after it is not read.
+
+use alloc::{format, vec::Vec};
+
+use crate::{
+    spec::{Preimage, MAX_BRANCH_PREFIX_WORDS},
+    stored::{
+        merkle::{NodeIdx, Snapshot},
+        Idx,
+    },
+    transaction::nodes::{hash_branch, hash_leaf_with, BranchMask},
+    KeyHash, NodeHash, PortableHash, PortableHasher, TrieError, TrieRoot, VerifyError,
+};
+
+/// The first bytes of a streamed snapshot.
+pub const STREAM_MAGIC: [u8; 4] = *b"KTSS";
+
+/// The most subtree hashes `Snapshot::verify_streaming` holds at once.
+///
+/// Every branch discriminates on a later bit than its parent, so a path has at most 256 branches,
+/// and post-order keeps one hash per branch on the path, plus the node being hashed.
+pub const MAX_STREAM_DEPTH: usize = 257;
+
+const BRANCH: u8 = 0;
+const LEAF: u8 = 1;
+const UNVISITED: u8 = 2;
+
+/// A source of a streamed snapshot's bytes.
+///
+/// Implemented for every `std::io::Read` with the `std` feature, and for byte slices without it.
+pub trait ReadBytes {
+    /// Fill `buf` from the source, failing if it ends first.
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), TrieError>;
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> ReadBytes for R {
+    #[inline]
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), TrieError> {
+        self.read_exact(buf)
+            .map_err(|e| format!("Error reading a streamed snapshot: {e}").into())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ReadBytes for &[u8] {
+    #[inline]
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), TrieError> {
+        if buf.len() > self.len() {
+            return Err("Error reading a streamed snapshot: the stream ends early".into());
+        }
+        let (read, rest) = self.split_at(buf.len());
+        buf.copy_from_slice(read);
+        *self = rest;
+        Ok(())
+    }
+}
+
+impl<V: PortableHash> Snapshot<V> {
+    /// Encode the snapshot as a stream, see the `stream` module.
+    #[inline]
+    pub fn encode_streaming(&self) -> Result<Vec<u8>, TrieError> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&STREAM_MAGIC);
+
+        let TrieRoot::Node(root) = self.root_node_idx()? else {
+            bytes.extend_from_slice(&0u64.to_le_bytes());
+            return Ok(bytes);
+        };
+
+        bytes.extend_from_slice(&[0; 8]);
+        let mut nodes = 0;
+        self.encode_node(root, None, &mut bytes, &mut nodes)?;
+        bytes[4..12].copy_from_slice(&nodes.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Append the subtree at `idx` to `bytes` in post-order, counting its nodes in `nodes`.
+    fn encode_node(
+        &self,
+        idx: Idx,
+        parent_bit_idx: Option<u32>,
+        bytes: &mut Vec<u8>,
+        nodes: &mut u64,
+    ) -> Result<(), TrieError> {
+        let (branches, leaves, unvisited_nodes) = self.arrays();
+        *nodes += 1;
+        // Every node is written once, unless a corrupt snapshot shares subtrees between branches.
+        if *nodes > (branches.len() + leaves.len() + unvisited_nodes.len()) as u64 {
+            return Err("Invalid snapshot: a node is reachable from more than one branch".into());
+        }
+
+        match self.node_idx(idx)? {
+            NodeIdx::Branch(branch_idx) => {
+                let branch = &branches[branch_idx.0 as usize];
+                let bit_idx = branch.mask.bit_idx();
+                check_order(bit_idx, parent_bit_idx)?;
+                if branch.prefix.len() > MAX_BRANCH_PREFIX_WORDS {
+                    return Err(format!(
+                        "Invalid snapshot: branch {} has {} prefix words, more than `MAX_BRANCH_PREFIX_WORDS`",
+                        branch_idx.0,
+                        branch.prefix.len()
+                    )
+                    .into());
+                }
+
+                self.encode_node(branch.left, Some(bit_idx), bytes, nodes)?;
+                self.encode_node(branch.right, Some(bit_idx), bytes, nodes)?;
+
+                bytes.push(BRANCH);
+                for word in [bit_idx, branch.mask.left_prefix(), branch.prior_word] {
+                    bytes.extend_from_slice(&word.to_le_bytes());
+                }
+                bytes.push(branch.prefix.len() as u8);
+                for word in branch.prefix.iter() {
+                    bytes.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+            NodeIdx::Leaf(leaf_idx) => {
+                let leaf = &leaves[leaf_idx.0 as usize];
+                let mut value = Preimage(Vec::new());
+                leaf.value.portable_hash(&mut value);
+                let value_len = u32::try_from(value.0.len()).map_err(|_| {
+                    TrieError::from(format!(
+                        "Leaf {} has a value of {} bytes, too long to stream",
+                        leaf_idx.0,
+                        value.0.len()
+                    ))
+                })?;
+
+                bytes.push(LEAF);
+                bytes.extend_from_slice(&leaf.key_hash.to_bytes());
+                bytes.extend_from_slice(&value_len.to_le_bytes());
+                bytes.extend_from_slice(&value.0);
+            }
+            NodeIdx::Unvisited(unvisited_idx) => {
+                bytes.push(UNVISITED);
+                bytes.extend_from_slice(&unvisited_nodes[unvisited_idx.0 as usize].bytes);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that the snapshot streamed from `reader` is of the trie at `expected_root`,
+    /// reading and hashing each node once, without decoding the snapshot.
+    ///
+    /// Holds at most `MAX_STREAM_DEPTH` hashes and one branch prefix in memory,
+    /// values are hashed in chunks as they are read.
+    /// Use `Snapshot::calc_root_hash` on a decoded snapshot instead if the transaction needs the nodes afterwards.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify_streaming(
+        mut reader: impl ReadBytes,
+        expected_root: TrieRoot<NodeHash>,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<(), VerifyError> {
+        let mut magic = [0; 4];
+        reader.read_bytes(&mut magic)?;
+        if magic != STREAM_MAGIC {
+            return Err(malformed("the stream does not start with `STREAM_MAGIC`"));
+        }
+        let node_count = u64::from_le_bytes(read_array(&mut reader)?);
+
+        // The hash of each complete subtree not yet under a parent, with its root's bit index if it is a branch.
+        let mut stack: Vec<(NodeHash, Option<u32>)> = Vec::new();
+
+        for _ in 0..node_count {
+            let [kind] = read_array(&mut reader)?;
+            let node = match kind {
+                BRANCH => {
+                    let bit_idx = u32::from_le_bytes(read_array(&mut reader)?);
+                    let left_prefix = u32::from_le_bytes(read_array(&mut reader)?);
+                    let prior_word = u32::from_le_bytes(read_array(&mut reader)?);
+                    let [prefix_len] = read_array(&mut reader)?;
+                    let prefix_len = prefix_len as usize;
+                    if prefix_len > MAX_BRANCH_PREFIX_WORDS {
+                        return Err(malformed(
+                            "a branch has more than `MAX_BRANCH_PREFIX_WORDS` prefix words",
+                        ));
+                    }
+                    let mut prefix = [0; MAX_BRANCH_PREFIX_WORDS];
+                    for word in prefix[..prefix_len].iter_mut() {
+                        *word = u32::from_le_bytes(read_array(&mut reader)?);
+                    }
+                    check_order(bit_idx, None)?;
+
+                    let (Some((right, right_bit_idx)), Some((left, left_bit_idx))) =
+                        (stack.pop(), stack.pop())
+                    else {
+                        return Err(malformed("a branch comes before its children"));
+                    };
+                    check_order_below(left_bit_idx, bit_idx)?;
+                    check_order_below(right_bit_idx, bit_idx)?;
+
+                    let (word_idx, bit) = KeyHash::word_and_bit(bit_idx);
+                    let mask =
+                        BranchMask::new(word_idx as u32, left_prefix, left_prefix ^ (1 << bit));
+                    if mask.left_prefix() != left_prefix {
+                        return Err(malformed(
+                            "a branch's left prefix has bits after its discriminant bit",
+                        ));
+                    }
+                    let hash = hash_branch(
+                        hasher,
+                        &mask,
+                        prior_word,
+                        &prefix[..prefix_len],
+                        &left,
+                        &right,
+                    );
+                    (hash, Some(bit_idx))
+                }
+                LEAF => {
+                    let key_hash = KeyHash::from_bytes(&read_array(&mut reader)?);
+                    let value_len = u32::from_le_bytes(read_array(&mut reader)?) as usize;
+                    let hash = hash_leaf_with(hasher, &key_hash, |hasher| {
+                        let mut chunk = [0; 256];
+                        let mut remaining = value_len;
+                        while remaining > 0 {
+                            let len = remaining.min(chunk.len());
+                            reader.read_bytes(&mut chunk[..len])?;
+                            hasher.portable_update(&chunk[..len]);
+                            remaining -= len;
+                        }
+                        Ok::<_, TrieError>(())
+                    })?;
+                    (hash, None)
+                }
+                UNVISITED => (NodeHash::new(read_array(&mut reader)?), None),
+                _ => return Err(malformed("unknown node kind")),
+            };
+
+            if stack.len() == MAX_STREAM_DEPTH {
+                return Err(malformed("the trie is deeper than `MAX_STREAM_DEPTH`"));
+            }
+            stack.push(node);
+        }
+
+        let actual = match stack.as_slice() {
+            [] => TrieRoot::Empty,
+            [(root, _)] => TrieRoot::Node(*root),
+            _ => return Err(malformed("the nodes do not form a single trie")),
+        };
+        if !actual.verify_eq(&expected_root) {
+            return Err(VerifyError::OldRootMismatch {
+                expected: expected_root,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl ReadBytes) -> Result<[u8; N], TrieError> {
+    let mut bytes = [0; N];
+    reader.read_bytes(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Fails unless a branch discriminating on `bit_idx` can be below one discriminating on `parent_bit_idx`.
+fn check_order(bit_idx: u32, parent_bit_idx: Option<u32>) -> Result<(), TrieError> {
+    if bit_idx >= 256 || parent_bit_idx.is_some_and(|parent_bit_idx| bit_idx <= parent_bit_idx) {
+        return Err(format!(
+            "Invalid snapshot: a branch discriminates on bit {bit_idx}, which does not follow its parent's bit {parent_bit_idx:?}"
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// `check_order` for a child that is a branch discriminating on `bit_idx`, if it is one.
+fn check_order_below(bit_idx: Option<u32>, parent_bit_idx: u32) -> Result<(), TrieError> {
+    bit_idx.map_or(Ok(()), |bit_idx| check_order(bit_idx, Some(parent_bit_idx)))
+}
+
+fn malformed(reason: &str) -> VerifyError {
+    TrieError::from(format!("Invalid streamed snapshot: {reason}")).into()
+}
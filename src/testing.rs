@@ -0,0 +1,149 @@
+//! Assertions comparing a trie against the entries a test expects it to hold.
+
+use core::fmt::{self, Debug, Display};
+use std::collections::{HashMap, HashSet};
+
+use alloc::{format, vec::Vec};
+
+use crate::{
+    stored::{Idx, Store},
+    walk, KeyHash, Leaf, NodeHash, TrieError, TrieRoot, Visitor,
+};
+
+/// One difference between a trie and its expected entries, found by `diff_trie`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TrieMismatch<V> {
+    /// The key is expected, but not in the trie.
+    Missing { key: KeyHash, expected: V },
+    /// The key is in the trie, but not expected.
+    Extra { key: KeyHash, actual: V },
+    /// The key is in the trie with a different value than expected.
+    Different {
+        key: KeyHash,
+        expected: V,
+        actual: V,
+    },
+}
+
+impl<V> TrieMismatch<V> {
+    #[inline]
+    pub fn key(&self) -> &KeyHash {
+        match self {
+            TrieMismatch::Missing { key, .. }
+            | TrieMismatch::Extra { key, .. }
+            | TrieMismatch::Different { key, .. } => key,
+        }
+    }
+}
+
+impl<V: Debug> Display for TrieMismatch<V> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrieMismatch::Missing { key, expected } => {
+                write!(f, "missing {key:?}, expected {expected:?}")
+            }
+            TrieMismatch::Extra { key, actual } => write!(f, "extra {key:?} with {actual:?}"),
+            TrieMismatch::Different {
+                key,
+                expected,
+                actual,
+            } => write!(f, "{key:?} is {actual:?}, expected {expected:?}"),
+        }
+    }
+}
+
+/// Walk the trie in `store` from `root`, and list every difference from `expected`, sorted by key.
+///
+/// The root is indexed as for `walk`.
+/// An unvisited node could hide any keys, so a trie that is not fully stored is an error.
+#[inline]
+pub fn diff_trie<V: Clone + PartialEq, S: Store<V>>(
+    store: &S,
+    root: TrieRoot<Idx>,
+    expected: &HashMap<KeyHash, V>,
+) -> Result<Vec<TrieMismatch<V>>, TrieError> {
+    let mut differ = Differ {
+        expected,
+        found: HashSet::new(),
+        mismatches: Vec::new(),
+    };
+    walk(store, root, &mut differ)?;
+
+    let Differ {
+        found,
+        mut mismatches,
+        ..
+    } = differ;
+    mismatches.extend(
+        expected
+            .iter()
+            .filter(|(key, _)| !found.contains(*key))
+            .map(|(key, value)| TrieMismatch::Missing {
+                key: *key,
+                expected: value.clone(),
+            }),
+    );
+
+    mismatches.sort_by(|a, b| a.key().cmp(b.key()));
+    Ok(mismatches)
+}
+
+/// Assert the trie in `store` at `root` holds exactly `expected`,
+/// panicking with every missing, extra and different entry if it does not.
+///
+/// The root is indexed as for `walk`.
+#[inline]
+#[track_caller]
+pub fn assert_trie_equals<V: Clone + PartialEq + Debug, S: Store<V>>(
+    store: &S,
+    root: TrieRoot<Idx>,
+    expected: &HashMap<KeyHash, V>,
+) {
+    let mismatches = match diff_trie(store, root, expected) {
+        Ok(mismatches) => mismatches,
+        Err(e) => panic!("Error comparing the trie: {e}"),
+    };
+
+    if !mismatches.is_empty() {
+        let mut message = format!(
+            "The trie differs from the expected {} entries in {} places:",
+            expected.len(),
+            mismatches.len()
+        );
+        for mismatch in &mismatches {
+            message.push_str(&format!("\n  {mismatch}"));
+        }
+        panic!("{message}");
+    }
+}
+
+struct Differ<'a, V> {
+    expected: &'a HashMap<KeyHash, V>,
+    found: HashSet<KeyHash>,
+    mismatches: Vec<TrieMismatch<V>>,
+}
+
+impl<V: Clone + PartialEq> Visitor<V> for Differ<'_, V> {
+    fn leaf(&mut self, _idx: Idx, leaf: &Leaf<V>) -> Result<(), TrieError> {
+        let key = leaf.key_hash;
+        self.found.insert(key);
+        match self.expected.get(&key) {
+            Some(expected) if *expected == leaf.value => {}
+            Some(expected) => self.mismatches.push(TrieMismatch::Different {
+                key,
+                expected: expected.clone(),
+                actual: leaf.value.clone(),
+            }),
+            None => self.mismatches.push(TrieMismatch::Extra {
+                key,
+                actual: leaf.value.clone(),
+            }),
+        }
+        Ok(())
+    }
+
+    fn unvisited(&mut self, idx: Idx, hash: &NodeHash) -> Result<(), TrieError> {
+        Err(format!("Cannot compare the trie, node {idx} is only stored as its hash {hash}").into())
+    }
+}
@@ -0,0 +1,95 @@
+use alloc::format;
+
+use crate::{stored::Store, PortableHash, PortableUpdate, Transaction, TrieError, TrieKey};
+
+/// A value stored with the block heights it was created and last changed at, see `Transaction::insert_at`.
+///
+/// The heights are part of the leaf, so an inclusion proof of the leaf proves when its value last changed.
+/// They cost 16 bytes per leaf.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Timestamped<V> {
+    pub value: V,
+    /// The height the key was inserted at, since it was last removed.
+    pub created: u64,
+    /// The height the value last changed at.
+    pub updated: u64,
+}
+
+impl<V: PortableHash> PortableHash for Timestamped<V> {
+    #[inline]
+    fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
+        self.value.portable_hash(hasher);
+        self.created.portable_hash(hasher);
+        self.updated.portable_hash(hasher);
+    }
+}
+
+impl<S: Store<Timestamped<V>, K>, V: PartialEq, K: TrieKey> Transaction<S, Timestamped<V>, K> {
+    /// Insert `value` at `key_hash` as of block `height`.
+    ///
+    /// A new key is created and updated at `height`.
+    /// An existing key keeps its `created` height, and is updated at `height` if `value` differs from its value.
+    /// Inserting the value a key already holds leaves it untouched.
+    ///
+    /// Fails if the key was last updated after `height`, heights must not go backwards.
+    #[inline]
+    pub fn insert_at(&mut self, key_hash: &K, value: V, height: u64) -> Result<(), TrieError> {
+        let created = match self.get(key_hash)? {
+            Some(existing) => {
+                if height < existing.updated {
+                    return Err(format!(
+                        "Height {height} is before {key_hash:?} was last updated, at {}",
+                        existing.updated
+                    )
+                    .into());
+                }
+                if existing.value == value {
+                    return Ok(());
+                }
+                existing.created
+            }
+            None => height,
+        };
+
+        self.insert(
+            key_hash,
+            Timestamped {
+                value,
+                created,
+                updated: height,
+            },
+        )
+    }
+
+    /// Change the value at `key_hash` with `f` as of block `height`, inserting `V::default()` first for a new key.
+    ///
+    /// The heights are maintained as by `insert_at`, which fails if the key was last updated after `height`.
+    #[inline]
+    pub fn update_at(
+        &mut self,
+        key_hash: &K,
+        height: u64,
+        f: impl FnOnce(&mut V),
+    ) -> Result<(), TrieError>
+    where
+        V: Clone + Default,
+    {
+        let Some(existing) = self.get(key_hash)? else {
+            let mut value = V::default();
+            f(&mut value);
+            return self.insert(
+                key_hash,
+                Timestamped {
+                    value,
+                    created: height,
+                    updated: height,
+                },
+            );
+        };
+
+        let mut value = existing.value.clone();
+        f(&mut value);
+        self.insert_at(key_hash, value, height)
+    }
+}
@@ -1,71 +1,679 @@
 pub(crate) mod nodes;
+pub(crate) mod overlay;
 
 use alloc::borrow::Cow;
-use alloc::{boxed::Box, format};
-use core::mem;
+use alloc::{boxed::Box, format, sync::Arc, vec, vec::Vec};
+use core::{cell::RefCell, iter, mem, ops::RangeInclusive, time::Duration};
 
 use crate::stored::DatabaseGet;
-use crate::{stored, KeyHash, NodeHash, PortableHash, PortableHasher};
+use crate::{
+    counted::{check_counted, root_leaf_count, PrefixCountEnd, PrefixCountProof},
+    errors::{error_context, LeakedPlaceholder},
+    stored, verify, KeyHash, NodeHash, PortableHash, PortableHasher, PortableUpdate, TrieKey,
+};
 use crate::{
     stored::{
-        merkle::{Snapshot, SnapshotBuilder},
-        DatabaseSet, Store,
+        merkle::{MissingNode, Snapshot, SnapshotBuilder},
+        DatabaseSet, DynDatabaseSet, Store,
     },
-    TrieError,
+    AuditedBatch, DeletionProof, InclusionProof, Journal, LeafPath, PrefixRemovalProof,
+    RemovedSubtree, TrieError, VerifyError,
 };
 
 use self::nodes::{
-    Branch, KeyPosition, KeyPositionAdjacent, Leaf, Node, NodeRef, StoredLeafRef, TrieRoot,
+    key_bits, Branch, BranchMask, KeyPosition, KeyPositionAdjacent, KeyRange, Leaf, Neighbor,
+    NeighborSearch, Node, NodeRef, PrefixSide, StoredLeafRef, TrieRoot, MAX_KEY_BITS,
 };
 
-pub struct Transaction<S, V> {
+/// A change to the database reported by `Transaction::commit_with_events`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommitEvent {
+    /// The node was written to the database.
+    Written(NodeHash),
+    /// The node was part of the trie before the transaction, and is not reachable from the new root.
+    Orphaned(NodeHash),
+}
+
+/// The shape of a transaction's in-memory trie, from `Transaction::modified_shape`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ModifiedShape {
+    /// The number of `NodeRef::ModBranch`s.
+    pub mod_branches: usize,
+    /// The number of `NodeRef::ModLeaf`s.
+    pub mod_leaves: usize,
+    /// The number of `NodeRef::Stored`s, the unmodified subtrees hanging off modified branches.
+    pub stored: usize,
+    /// The most `NodeRef::ModBranch`s on any path from the root.
+    pub depth: usize,
+}
+
+/// The node hashes computed by `Transaction::calc_root_hash_counted`.
+///
+/// In a zkVM guest, hashing dominates the cost of computing a root,
+/// so these counts track cycles and can pin the hashing work of a workload in a regression test.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct HashCounts {
+    /// The number of modified branches hashed.
+    pub branches: u64,
+    /// The number of modified leaves hashed.
+    pub leaves: u64,
+    /// The number of nodes of unmodified subtrees the store hashed.
+    /// 0 for a `SnapshotBuilder`, which knows them by hash,
+    /// and for a `Snapshot` whose hashes are cached by `Snapshot::calc_root_hash`.
+    pub stored: u64,
+}
+
+impl HashCounts {
+    /// The number of nodes hashed.
+    #[inline]
+    pub fn total(&self) -> u64 {
+        self.branches + self.leaves + self.stored
+    }
+}
+
+/// The node a `Transaction::seek` stopped at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SeekNode<K = KeyHash> {
+    /// The trie is empty.
+    Empty,
+    /// The key leaves the branch's prefix before its discriminant bit, so no key under the branch matches it.
+    Branch(BranchMask),
+    /// The leaf at the end of the key's path, holding `key_hash`.
+    Leaf(K),
+}
+
+/// How far a key's path reaches into the trie, from `Transaction::seek`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SeekResult<K = KeyHash> {
+    /// The number of leading bits, in trie order, the key shares with every key under `node`.
+    pub matched_bits: u32,
+    /// The number of branches descended through to reach `node`.
+    pub depth: usize,
+    pub node: SeekNode<K>,
+    /// Whether `node` is a leaf holding the key.
+    pub exact: bool,
+}
+
+impl<K: TrieKey> SeekResult<K> {
+    fn at_branch<T>(branch: &Branch<T>, key_hash: &K, depth: usize) -> Self {
+        // Bits before the branch's prefix were matched by the branches above it.
+        let mut words = key_hash.words().to_vec();
+        branch.child_key_prefix(false, &mut words);
+        let matched_bits = first_diff_bit(key_hash.words(), &words).min(branch.mask.bit_idx());
+
+        SeekResult {
+            matched_bits,
+            depth,
+            node: SeekNode::Branch(branch.mask),
+            exact: false,
+        }
+    }
+
+    fn at_leaf(leaf_key: K, key_hash: &K, depth: usize) -> Self {
+        SeekResult {
+            matched_bits: first_diff_bit(key_hash.words(), leaf_key.words()),
+            depth,
+            node: SeekNode::Leaf(leaf_key),
+            exact: leaf_key == *key_hash,
+        }
+    }
+}
+
+/// The index of the first bit, in trie order, where `a` and `b` differ, or the bits in `a` if they are equal.
+pub(crate) fn first_diff_bit(a: &[u32], b: &[u32]) -> u32 {
+    iter::zip(a, b)
+        .enumerate()
+        .find(|(_, (a, b))| a != b)
+        .map_or(a.len() as u32 * 32, |(i, (a, b))| {
+            i as u32 * 32 + (a ^ b).trailing_zeros()
+        })
+}
+
+/// Record `key_hash` in a `MissingNode` error, so it names the key whose path failed to load.
+fn with_missing_key<K: TrieKey>(error: TrieError, key_hash: &K) -> TrieError {
+    match error.downcast_source::<MissingNode>() {
+        Some(missing) if missing.key.is_none() => {
+            let missing = MissingNode {
+                key: Some(key_hash.words().into()),
+                ..missing.clone()
+            };
+            error
+                .context(format_args!("Error traversing key {key_hash:?}"))
+                .with_source(missing)
+        }
+        _ => error,
+    }
+}
+
+/// Counts the digests computed through it.
+#[derive(Default)]
+struct CountingHasher<H> {
+    hasher: H,
+    digests: u64,
+}
+
+impl<H: PortableHasher<32>> PortableHasher<32> for CountingHasher<H> {
+    const LEAF_TAG: &'static [u8] = H::LEAF_TAG;
+    const BRANCH_TAG: &'static [u8] = H::BRANCH_TAG;
+    const COUNTED: bool = H::COUNTED;
+
+    #[inline(always)]
+    fn finalize_reset(&mut self) -> [u8; 32] {
+        self.digests += 1;
+        self.hasher.finalize_reset()
+    }
+}
+
+impl<H: PortableUpdate> PortableUpdate for CountingHasher<H> {
+    #[inline(always)]
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.hasher.portable_update(data);
+    }
+
+    #[inline(always)]
+    fn portable_update_u32_slice(&mut self, words: &[u32]) {
+        self.hasher.portable_update_u32_slice(words);
+    }
+}
+
+/// The storage a commit adds and frees, from `Transaction::commit_with_usage`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct StorageUsage {
+    /// The encoded size of the leaves written.
+    pub leaf_bytes_written: u64,
+    /// The encoded size of the leaves of the old trie that are no longer reachable.
+    pub leaf_bytes_removed: u64,
+    /// The number of branches and leaves written.
+    pub nodes_written: u64,
+    /// The number of nodes of the old trie that are no longer reachable.
+    pub nodes_orphaned: u64,
+}
+
+impl StorageUsage {
+    /// The change in the size of the leaves stored.
+    #[inline]
+    pub fn leaf_bytes_delta(&self) -> i128 {
+        self.leaf_bytes_written as i128 - self.leaf_bytes_removed as i128
+    }
+
+    /// The change in the number of nodes stored.
+    #[inline]
+    pub fn node_delta(&self) -> i128 {
+        self.nodes_written as i128 - self.nodes_orphaned as i128
+    }
+}
+
+/// What a commit did, from `Transaction::commit`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CommitReceipt {
+    /// The root hash of the committed trie.
+    pub root: TrieRoot<NodeHash>,
+    /// The number of branches and leaves written.
+    pub nodes_written: u64,
+    /// The number of keys written that were not in the old trie.
+    pub leaves_inserted: u64,
+    /// The number of keys written that were in the old trie.
+    /// A key inserted with its old value counts as updated.
+    pub leaves_updated: u64,
+    /// The number of keys of the old trie that are no longer in the trie.
+    pub leaves_removed: u64,
+    /// The wall-clock time spent hashing and writing the modified nodes.
+    ///
+    /// `None` without the `std` feature, and on wasm targets, which have no clock.
+    pub elapsed: Option<Duration>,
+}
+
+/// How `Transaction::commit_replicated` handles a secondary store failing to write a node.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReplicationMode {
+    /// Finish the commit, reporting every failed write to a secondary in `ReplicatedCommit::failures`.
+    BestEffort,
+    /// Fail the commit on the first failed write to any store.
+    AllOrNothing,
+}
+
+/// A node a secondary store failed to write, from `Transaction::commit_replicated`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ReplicaFailure {
+    /// The index of the store in `secondaries`.
+    pub replica: usize,
+    pub hash: NodeHash,
+    pub error: TrieError,
+}
+
+/// What a replicated commit did, from `Transaction::commit_replicated`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ReplicatedCommit {
+    /// The root hash of the committed trie.
+    pub root: TrieRoot<NodeHash>,
+    /// The number of branches and leaves written to the primary store.
+    pub nodes_written: u64,
+    /// The failed writes to secondary stores, in the order they happened.
+    /// Always empty with `ReplicationMode::AllOrNothing`.
+    pub failures: Vec<ReplicaFailure>,
+}
+
+/// A score in `0..=u64::MAX` for sampling the key with `words`, spread evenly by `seed`.
+#[inline]
+fn sample_score(words: &[u32], seed: u64) -> u64 {
+    let mut x = words.iter().take(2).fold(seed, |x, word| {
+        (x ^ u64::from(*word)).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    });
+    // The splitmix64 finalizer.
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+pub struct Transaction<S, V, K = KeyHash> {
     pub data_store: S,
-    current_root: TrieRoot<NodeRef<V>>,
+    current_root: TrieRoot<NodeRef<V, K>>,
+    /// The root hash of `current_root` once computed, cleared whenever the trie may be modified.
+    root_hash: stored::OnceCell<TrieRoot<NodeHash>>,
 }
 
-impl<Db: DatabaseSet<V>, V: Clone + PortableHash> Transaction<SnapshotBuilder<Db, V>, V> {
-    /// Write modified nodes to the database and return the root hash.
+impl<Db: DatabaseSet<V, K>, V: Clone + PortableHash, K: TrieKey>
+    Transaction<SnapshotBuilder<Db, V, K>, V, K>
+{
+    /// Write modified nodes to the database and return the root hash,
+    /// along with the number of nodes written and keys changed.
     /// Calling this method will write all modified nodes to the database.
     /// Calling this method again will rewrite the nodes to the database.
     ///
-    /// Caching writes is the responsibility of the `DatabaseSet` implementation.
+    /// Caching writes is the responsibility of the `DatabaseSet` implementation,
+    /// the writes of one commit are wrapped in a batch, see `DatabaseSet::begin_batch`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit(&self, hasher: &mut impl PortableHasher<32>) -> Result<CommitReceipt, TrieError> {
+        #[cfg(all(feature = "std", not(target_family = "wasm")))]
+        let start = std::time::Instant::now();
+
+        let mut nodes_written = 0;
+        let mut written_keys = Vec::new();
+        let root = self.commit_inner(hasher, &mut |_, leaf| {
+            nodes_written += 1;
+            if let Some(leaf) = leaf {
+                written_keys.push(leaf.key_hash);
+            }
+            Ok(())
+        })?;
+
+        #[cfg(all(feature = "std", not(target_family = "wasm")))]
+        let elapsed = Some(start.elapsed());
+        #[cfg(not(all(feature = "std", not(target_family = "wasm"))))]
+        let elapsed = None;
+
+        // A written key replaced a leaf of the old trie exactly when that leaf is orphaned.
+        let mut removed_keys: Vec<K> = self
+            .orphaned_nodes(|_, node| match node {
+                Some(Node::Leaf(leaf)) => Some(leaf.key_hash),
+                _ => None,
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        removed_keys.sort_unstable_by(K::cmp_trie_order);
+
+        let leaves_updated = written_keys
+            .iter()
+            .filter(|key| {
+                removed_keys
+                    .binary_search_by(|removed| removed.cmp_trie_order(key))
+                    .is_ok()
+            })
+            .count();
+
+        Ok(CommitReceipt {
+            root,
+            nodes_written,
+            leaves_inserted: (written_keys.len() - leaves_updated) as u64,
+            leaves_updated: leaves_updated as u64,
+            leaves_removed: (removed_keys.len() - leaves_updated) as u64,
+            elapsed,
+        })
+    }
+
+    /// Like `commit`, then read a sample of the written keys back from the database to check the commit round trips.
+    ///
+    /// About `sample_rate` of the keys written are sampled, `0.0` samples none and `1.0` samples all.
+    /// The sample is drawn from the keys and the new root, so it differs between commits but is reproducible.
+    /// Each sampled key is read through a fresh `SnapshotBuilder` at the new root and must hold its written value,
+    /// and the nodes read along the way must hash back to the new root.
+    /// This catches a `DatabaseSet` that mis-encodes or loses nodes before the root is published.
+    ///
+    /// The nodes are written before they are checked, a failed check means the database must not be trusted with the new root.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit_checked(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        sample_rate: f64,
+    ) -> Result<CommitReceipt, TrieError> {
+        let receipt = self.commit(hasher)?;
+        let (TrieRoot::Node(root_hash), TrieRoot::Node(node_ref)) =
+            (&receipt.root, &self.current_root)
+        else {
+            return Ok(receipt);
+        };
+
+        // A saturating cast, so rates above one sample every key and NaN samples none.
+        let threshold = (sample_rate * u64::MAX as f64) as u64;
+        let seed = u64::from_le_bytes(root_hash.bytes[..8].try_into().unwrap_or_default());
+        let mut sample = Vec::new();
+        Self::modified_leaves(node_ref, &mut |leaf| {
+            if sample_rate >= 1.0 || sample_score(leaf.key_hash.words(), seed) < threshold {
+                sample.push(leaf);
+            }
+        });
+
+        let check = Transaction::from_snapshot_builder(SnapshotBuilder::<_, V, K>::new(
+            self.data_store.db(),
+            receipt.root,
+        ));
+        for leaf in sample {
+            let read = check
+                .get(&leaf.key_hash)
+                .map_err(|e| error_context(e, "Commit check failed reading back the new trie"))?;
+            let matches = read.is_some_and(|value| {
+                nodes::hash_leaf(hasher, &leaf.key_hash, value) == leaf.hash_leaf(hasher)
+            });
+            if !matches {
+                return Err(format!(
+                    "Commit check failed: {:?} does not read back its written value",
+                    leaf.key_hash
+                )
+                .into());
+            }
+        }
+
+        let read_root = check.build_initial_snapshot().calc_root_hash(hasher)?;
+        if read_root != receipt.root {
+            return Err(format!(
+                "Commit check failed: the nodes read back hash to {read_root:?}, not the committed root {:?}",
+                receipt.root
+            )
+            .into());
+        }
+        Ok(receipt)
+    }
+
+    /// Like `commit`, but reports every node written, then every node of the old trie that is no longer reachable.
+    ///
+    /// This lets a reference-counting garbage collector increment on `CommitEvent::Written`
+    /// and decrement on `CommitEvent::Orphaned` as the trie changes,
+    /// instead of running an offline mark-and-sweep.
+    /// A modified node that is rewritten unchanged is reported both written and orphaned, so the counts balance.
+    /// Likewise, calling this method again reports the same events again.
     ///
     /// Caller must ensure that the hasher is reset before calling this method.
     #[inline]
-    pub fn commit(
+    pub fn commit_with_events(
         &self,
         hasher: &mut impl PortableHasher<32>,
+        on_event: &mut impl FnMut(CommitEvent) -> Result<(), TrieError>,
     ) -> Result<TrieRoot<NodeHash>, TrieError> {
-        let store_modified_branch =
-            &mut |hash: &NodeHash, branch: &Branch<NodeRef<V>>, left: NodeHash, right: NodeHash| {
-                let branch = Branch {
-                    left,
-                    right,
-                    mask: branch.mask,
-                    prior_word: branch.prior_word,
-                    prefix: branch.prefix.clone(),
+        let root_hash =
+            self.commit_inner(hasher, &mut |hash, _| on_event(CommitEvent::Written(*hash)))?;
+
+        for hash in self.orphaned_nodes(|hash, _| *hash) {
+            on_event(CommitEvent::Orphaned(hash))?;
+        }
+
+        Ok(root_hash)
+    }
+
+    /// Like `commit`, but also returns the storage the commit adds and frees,
+    /// for chains that charge state rent.
+    ///
+    /// `leaf_len` must return the size of a leaf as the database encodes it.
+    /// Nodes are counted like `commit_with_events` reports them,
+    /// so a modified leaf that is rewritten unchanged is counted both written and removed.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit_with_usage(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        mut leaf_len: impl FnMut(&Leaf<V, K>) -> usize,
+    ) -> Result<(TrieRoot<NodeHash>, StorageUsage), TrieError> {
+        let mut usage = StorageUsage::default();
+
+        let root_hash = self.commit_inner(hasher, &mut |_, leaf| {
+            usage.nodes_written += 1;
+            if let Some(leaf) = leaf {
+                usage.leaf_bytes_written += leaf_len(leaf) as u64;
+            }
+            Ok(())
+        })?;
+
+        for removed_leaf_len in self.orphaned_nodes(|_, node| match node {
+            Some(Node::Leaf(leaf)) => Some(leaf_len(leaf)),
+            _ => None,
+        }) {
+            usage.nodes_orphaned += 1;
+            usage.leaf_bytes_removed += removed_leaf_len.unwrap_or(0) as u64;
+        }
+
+        Ok((root_hash, usage))
+    }
+
+    /// Like `commit`, but also writes every node to each of the `secondaries`,
+    /// so a replicated deployment does not have to wrap its stores in a `DatabaseSet` of its own.
+    ///
+    /// Each node is written to the database of the `SnapshotBuilder`, the primary, then to the secondaries in order.
+    /// Only the primary's writes are wrapped in a batch, see `DatabaseSet::begin_batch`.
+    /// A failed write to the primary always fails the commit,
+    /// `mode` decides whether a failed write to a secondary does.
+    ///
+    /// Nothing is rolled back when a commit fails.
+    /// Nodes are addressed by hash, so the nodes already written are harmless,
+    /// but none of the stores may be assumed to hold the new trie, so do not publish its root.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit_replicated(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        secondaries: &[&dyn DynDatabaseSet<V, K>],
+        mode: ReplicationMode,
+    ) -> Result<ReplicatedCommit, TrieError> {
+        let mut nodes_written = 0;
+        let mut failures = Vec::new();
+
+        let root = self.in_batch(|| {
+            self.write_modified(hasher, &mut |hash, node| {
+                let node = match node {
+                    Node::Branch(branch) => Node::Branch(branch),
+                    Node::Leaf(leaf) => Node::Leaf(leaf.clone()),
                 };
 
-                self.data_store
-                    .db()
-                    .set(*hash, Node::Branch(branch))
-                    .map_err(|e| format!("Error writing branch {hash} to database: {e}").into())
-            };
+                self.data_store.db().set(*hash, node.clone()).map_err(|e| {
+                    error_context(
+                        e,
+                        format_args!("Error writing node {hash} to the primary database"),
+                    )
+                })?;
+                nodes_written += 1;
+
+                for (replica, secondary) in secondaries.iter().enumerate() {
+                    if let Err(error) = secondary.set_dyn(*hash, node.clone()) {
+                        match mode {
+                            ReplicationMode::BestEffort => failures.push(ReplicaFailure {
+                                replica,
+                                hash: *hash,
+                                error,
+                            }),
+                            ReplicationMode::AllOrNothing => {
+                                return Err(error.context(format_args!(
+                                    "Error writing node {hash} to secondary database {replica}"
+                                )))
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            })
+        })?;
 
-        let store_modified_leaf = &mut |hash: &NodeHash, leaf: &Leaf<V>| {
-            self.data_store
-                .db()
-                .set(*hash, Node::Leaf(leaf.clone()))
-                .map_err(|e| format!("Error writing leaf {hash} to database: {e}").into())
+        Ok(ReplicatedCommit {
+            root,
+            nodes_written,
+            failures,
+        })
+    }
+
+    /// Map each node of the old trie that is no longer reachable with `f`.
+    #[inline]
+    fn orphaned_nodes<T>(
+        &self,
+        f: impl FnMut(&NodeHash, Option<Node<&Branch<stored::Idx>, &Leaf<V, K>>>) -> T,
+    ) -> Vec<T> {
+        // Only nodes still referenced as stored nodes are carried over from the old trie.
+        let mut kept = Vec::new();
+        if let TrieRoot::Node(node_ref) = &self.current_root {
+            Self::stored_refs(node_ref, &mut kept);
+        }
+
+        self.data_store.unreachable_nodes(&kept, f)
+    }
+
+    #[inline]
+    fn commit_inner(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        on_written: &mut impl FnMut(&NodeHash, Option<&Leaf<V, K>>) -> Result<(), TrieError>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        self.in_batch(|| {
+            self.write_modified(hasher, &mut |hash, node| match node {
+                Node::Branch(branch) => {
+                    self.data_store
+                        .db()
+                        .set(*hash, Node::Branch(branch))
+                        .map_err(|e| {
+                            error_context(
+                                e,
+                                format_args!("Error writing branch {hash} to database"),
+                            )
+                        })?;
+                    on_written(hash, None)
+                }
+                Node::Leaf(leaf) => {
+                    self.data_store
+                        .db()
+                        .set(*hash, Node::Leaf(leaf.clone()))
+                        .map_err(|e| {
+                            error_context(e, format_args!("Error writing leaf {hash} to database"))
+                        })?;
+                    on_written(hash, Some(leaf))
+                }
+            })
+        })
+    }
+
+    /// Run `write` between `DatabaseSet::begin_batch` and `DatabaseSet::end_batch` of the database.
+    #[inline]
+    fn in_batch<T>(&self, write: impl FnOnce() -> Result<T, TrieError>) -> Result<T, TrieError> {
+        let db = self.data_store.db();
+        db.begin_batch()
+            .map_err(|e| error_context(e, "Error beginning a database write batch"))?;
+
+        let written = write();
+        let ended = db.end_batch(written.is_ok());
+        // A failed write is the more useful error.
+        let written = written?;
+        ended.map_err(|e| error_context(e, "Error ending a database write batch"))?;
+        Ok(written)
+    }
+
+    /// Hash the modified nodes, passing each to `write` with its hash, children before parents.
+    #[inline]
+    fn write_modified(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        write: &mut impl FnMut(&NodeHash, Node<Branch<NodeHash>, &Leaf<V, K>>) -> Result<(), TrieError>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        // Both callbacks write.
+        let write = RefCell::new(write);
+
+        let write_branch = &mut |hash: &NodeHash,
+                                 branch: &Branch<NodeRef<V, K>>,
+                                 left: NodeHash,
+                                 right: NodeHash| {
+            (write.borrow_mut())(hash, Node::Branch(branch.with_children(left, right)))
         };
+        let write_leaf =
+            &mut |hash: &NodeHash, leaf: &Leaf<V, K>| (write.borrow_mut())(hash, Node::Leaf(leaf));
 
-        let root_hash =
-            self.calc_root_hash_inner(hasher, store_modified_branch, store_modified_leaf)?;
-        Ok(root_hash)
+        self.calc_root_hash_inner(hasher, write_branch, write_leaf)
+    }
+
+    /// Call `f` on each leaf of the modified trie that is not yet stored.
+    #[inline]
+    fn modified_leaves<'a>(node_ref: &'a NodeRef<V, K>, f: &mut impl FnMut(&'a Leaf<V, K>)) {
+        match node_ref {
+            NodeRef::ModBranch(branch) => {
+                Self::modified_leaves(&branch.left, f);
+                Self::modified_leaves(&branch.right, f);
+            }
+            NodeRef::ModLeaf(leaf) => f(leaf),
+            NodeRef::Stored(_) => {}
+        }
+    }
+
+    /// Collect the indexes of the stored nodes referenced by the modified trie.
+    #[inline]
+    fn stored_refs(node_ref: &NodeRef<V, K>, stored: &mut Vec<stored::Idx>) {
+        match node_ref {
+            NodeRef::ModBranch(branch) => {
+                Self::stored_refs(&branch.left, stored);
+                Self::stored_refs(&branch.right, stored);
+            }
+            NodeRef::ModLeaf(_) => {}
+            NodeRef::Stored(idx) => stored.push(*idx),
+        }
+    }
+}
+
+impl<Db: DatabaseSet<V>, V: Clone + PortableHash> Transaction<SnapshotBuilder<Db, V>, V> {
+    /// Commit the transaction, then replay `journal` against the freshly built `Snapshot`.
+    ///
+    /// Every operation must return the same value it returned against the database,
+    /// and the replay must reach the committed root.
+    /// This catches witness construction bugs on the server, before paying for a proof.
+    ///
+    /// `journal` must contain every operation applied to this transaction.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit_audited(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        journal: &Journal<V>,
+    ) -> Result<AuditedBatch<V>, VerifyError>
+    where
+        V: PartialEq,
+    {
+        let old_root = match self.data_store.trie_root() {
+            TrieRoot::Node(_) => TrieRoot::Node(self.data_store.get_node_hash(0)?),
+            TrieRoot::Empty => TrieRoot::Empty,
+        };
+        let new_root = self.commit(hasher)?.root;
+        let snapshot = self.build_initial_snapshot();
+
+        verify::audit_journal(old_root, new_root, &snapshot, journal, hasher)?;
+
+        Ok(AuditedBatch {
+            old_root,
+            new_root,
+            snapshot,
+            ops: journal.ops().to_vec(),
+        })
     }
 }
 
-impl<S: Store<V>, V: PortableHash> Transaction<S, V> {
+impl<S: Store<V, K>, V: PortableHash, K: TrieKey> Transaction<S, V, K> {
     /// Caller must ensure that the hasher is reset before calling this method.
     #[inline]
     pub fn calc_root_hash_inner(
@@ -73,24 +681,26 @@ impl<S: Store<V>, V: PortableHash> Transaction<S, V> {
         hasher: &mut impl PortableHasher<32>,
         on_modified_branch: &mut impl FnMut(
             &NodeHash,
-            &Branch<NodeRef<V>>,
+            &Branch<NodeRef<V, K>>,
             NodeHash,
             NodeHash,
         ) -> Result<(), TrieError>,
-        on_modified_leaf: &mut impl FnMut(&NodeHash, &Leaf<V>) -> Result<(), TrieError>,
+        on_modified_leaf: &mut impl FnMut(&NodeHash, &Leaf<V, K>) -> Result<(), TrieError>,
     ) -> Result<TrieRoot<NodeHash>, TrieError> {
         let root_hash = match &self.current_root {
-            TrieRoot::Empty => return Ok(TrieRoot::Empty),
-            TrieRoot::Node(node_ref) => Self::calc_root_hash_node(
+            TrieRoot::Empty => TrieRoot::Empty,
+            TrieRoot::Node(node_ref) => TrieRoot::Node(Self::calc_root_hash_node(
                 hasher,
                 &self.data_store,
                 node_ref,
                 on_modified_leaf,
                 on_modified_branch,
-            )?,
+            )?),
         };
 
-        Ok(TrieRoot::Node(root_hash))
+        // Keeps the first root computed since the last modification.
+        let _ = self.root_hash.set(root_hash);
+        Ok(root_hash)
     }
 
     /// Calculate the root hash of the trie.
@@ -104,15 +714,66 @@ impl<S: Store<V>, V: PortableHash> Transaction<S, V> {
         self.calc_root_hash_inner(hasher, &mut |_, _, _, _| Ok(()), &mut |_, _| Ok(()))
     }
 
+    /// Like `calc_root_hash`, but also counts the nodes hashed.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn calc_root_hash_counted<H: PortableHasher<32>>(
+        &self,
+        hasher: &mut H,
+    ) -> Result<(TrieRoot<NodeHash>, HashCounts), TrieError> {
+        let mut counting = CountingHasher {
+            hasher: mem::take(hasher),
+            digests: 0,
+        };
+        let mut counts = HashCounts::default();
+
+        let root_hash = self.calc_root_hash_inner(
+            &mut counting,
+            &mut |_, _, _, _| {
+                counts.branches += 1;
+                Ok(())
+            },
+            &mut |_, _| {
+                counts.leaves += 1;
+                Ok(())
+            },
+        );
+        *hasher = counting.hasher;
+
+        counts.stored = counting.digests - counts.branches - counts.leaves;
+        Ok((root_hash?, counts))
+    }
+
+    /// The root hash computed by `calc_root_hash` or a commit since the trie was last modified,
+    /// or `None` if the trie was modified since, or the root was never computed.
+    ///
+    /// Use this to avoid rehashing the modified nodes when nothing changed.
+    /// If the root was computed with several hashers since the last modification, this is the first root computed.
+    /// A transaction created from a `SnapshotBuilder` starts with the builder's root hash.
+    #[inline]
+    pub fn root_hash_cached(&self) -> Option<TrieRoot<NodeHash>> {
+        self.root_hash.get().copied()
+    }
+
+    /// Whether the trie may have been modified since its root hash was last computed.
+    ///
+    /// `insert`, a successful `remove`, and `entry` mark the trie as modified,
+    /// even if they write the value already present.
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.root_hash.get().is_none()
+    }
+
     #[inline]
     fn calc_root_hash_node(
         hasher: &mut impl PortableHasher<32>,
         data_store: &S,
-        node_ref: &NodeRef<V>,
-        on_modified_leaf: &mut impl FnMut(&NodeHash, &Leaf<V>) -> Result<(), TrieError>,
+        node_ref: &NodeRef<V, K>,
+        on_modified_leaf: &mut impl FnMut(&NodeHash, &Leaf<V, K>) -> Result<(), TrieError>,
         on_modified_branch: &mut impl FnMut(
             &NodeHash,
-            &Branch<NodeRef<V>>,
+            &Branch<NodeRef<V, K>>,
             NodeHash,
             NodeHash,
         ) -> Result<(), TrieError>,
@@ -135,6 +796,10 @@ impl<S: Store<V>, V: PortableHash> Transaction<S, V> {
                     on_modified_branch,
                 )?;
 
+                if cfg!(debug_assertions) {
+                    Self::debug_check_branch(branch)?;
+                }
+
                 let hash = branch.hash_branch(hasher, &left, &right);
                 on_modified_branch(&hash, branch, left, right)?;
                 Ok(hash)
@@ -145,6 +810,12 @@ impl<S: Store<V>, V: PortableHash> Transaction<S, V> {
                 on_modified_leaf(&hash, leaf)?;
                 Ok(hash)
             }
+            NodeRef::Stored(_) if node_ref.is_temp_null_stored() => Err(LeakedPlaceholder {
+                key: None,
+                depth: None,
+                source: None,
+            }
+            .into()),
             NodeRef::Stored(stored_idx) => data_store
                 .calc_subtree_hash(hasher, *stored_idx)
                 .map_err(|e| {
@@ -158,9 +829,35 @@ impl<S: Store<V>, V: PortableHash> Transaction<S, V> {
                 }),
         }
     }
+
+    /// Check a modified branch's own invariants, and those relating it to its modified children.
+    ///
+    /// Branches loaded from an untrusted snapshot may break them, so this returns an error rather than panicking.
+    #[inline]
+    fn debug_check_branch(branch: &Branch<NodeRef<V, K>>) -> Result<(), TrieError> {
+        branch.check_invariants()?;
+
+        for child in [&branch.left, &branch.right] {
+            if let NodeRef::ModBranch(child) = child {
+                if !child
+                    .mask
+                    .is_valid_child_of(&branch.mask, child.prior_word, &child.prefix)
+                {
+                    return Err(format!(
+                        "Invalid branch: {child:?} is not a valid child of {branch:?}"
+                    )
+                    .into());
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
-impl<Db: 'static + DatabaseGet<V>, V: Clone> Transaction<SnapshotBuilder<Db, V>, V> {
+impl<Db: 'static + DatabaseGet<V, K>, V: Clone, K: TrieKey>
+    Transaction<SnapshotBuilder<Db, V, K>, V, K>
+{
     /// This method is like standard `Transaction::get` but won't affect the Transaction or any Snapshot built from it.
     /// You should use this method to check precondition before modifying the Transaction.
     ///
@@ -169,7 +866,7 @@ impl<Db: 'static + DatabaseGet<V>, V: Clone> Transaction<SnapshotBuilder<Db, V>,
     #[inline]
     pub fn get_exclude_from_txn<'s>(
         &'s self,
-        key_hash: &KeyHash,
+        key_hash: &K,
     ) -> Result<Option<Cow<'s, V>>, TrieError> {
         match &self.current_root {
             TrieRoot::Empty => Ok(None),
@@ -181,9 +878,9 @@ impl<Db: 'static + DatabaseGet<V>, V: Clone> Transaction<SnapshotBuilder<Db, V>,
 
     #[inline]
     fn get_node_exclude_from_txn<'root, 's: 'root>(
-        data_store: &'s SnapshotBuilder<Db, V>,
-        mut node_ref: &'root NodeRef<V>,
-        key_hash: &KeyHash,
+        data_store: &'s SnapshotBuilder<Db, V, K>,
+        mut node_ref: &'root NodeRef<V, K>,
+        key_hash: &K,
     ) -> Result<Option<Cow<'root, V>>, TrieError> {
         loop {
             match node_ref {
@@ -202,7 +899,7 @@ impl<Db: 'static + DatabaseGet<V>, V: Clone> Transaction<SnapshotBuilder<Db, V>,
                 NodeRef::Stored(stored_idx) => {
                     let stored_hash = data_store
                         .get_node_hash(*stored_idx)
-                        .map_err(|e| format!("Error in `get_node_exclude_from_txn`: {e}"))?;
+                        .map_err(|e| error_context(e, "Error in `get_node_exclude_from_txn`"))?;
 
                     return Self::get_stored_node_exclude_from_txn(
                         data_store.db(),
@@ -219,12 +916,12 @@ impl<Db: 'static + DatabaseGet<V>, V: Clone> Transaction<SnapshotBuilder<Db, V>,
     fn get_stored_node_exclude_from_txn(
         database: &Db,
         mut stored_hash: NodeHash,
-        key_hash: &KeyHash,
+        key_hash: &K,
     ) -> Result<Option<V>, TrieError> {
         loop {
             let node = database
                 .get(&stored_hash)
-                .map_err(|e| format!("Error in `get_stored_node_exclude_from_txn`: {e}"))?;
+                .map_err(|e| error_context(e, "Error in `get_stored_node_exclude_from_txn`"))?;
 
             match node {
                 Node::Branch(branch) => match branch.key_position(key_hash) {
@@ -244,28 +941,89 @@ impl<Db: 'static + DatabaseGet<V>, V: Clone> Transaction<SnapshotBuilder<Db, V>,
     }
 }
 
-impl<S: Store<V>, V> Transaction<S, V> {
+impl<S, V, K> Transaction<S, V, K> {
+    /// The in-memory root of the transaction.
+    ///
+    /// Modified nodes are `NodeRef::ModBranch` and `NodeRef::ModLeaf`,
+    /// the rest of the trie is referenced by `NodeRef::Stored` index into `data_store`.
+    #[inline]
+    pub fn current_root(&self) -> &TrieRoot<NodeRef<V, K>> {
+        &self.current_root
+    }
+
+    /// Count the modified and stored nodes of the in-memory trie, and the depth of its modified spine.
+    ///
+    /// Useful for tests that bound how much memory a workload keeps in a transaction.
+    #[inline]
+    pub fn modified_shape(&self) -> ModifiedShape {
+        let mut shape = ModifiedShape::default();
+        if let TrieRoot::Node(node_ref) = &self.current_root {
+            Self::add_to_shape(node_ref, 0, &mut shape);
+        }
+        shape
+    }
+
+    // TODO use a stack instead of recursion
+    #[inline]
+    fn add_to_shape(node_ref: &NodeRef<V, K>, depth: usize, shape: &mut ModifiedShape) {
+        match node_ref {
+            NodeRef::ModBranch(branch) => {
+                shape.mod_branches += 1;
+                shape.depth = shape.depth.max(depth + 1);
+                Self::add_to_shape(&branch.left, depth + 1, shape);
+                Self::add_to_shape(&branch.right, depth + 1, shape);
+            }
+            NodeRef::ModLeaf(_) => shape.mod_leaves += 1,
+            NodeRef::Stored(_) => shape.stored += 1,
+        }
+    }
+}
+
+impl<S: Store<V, K>, V, K: TrieKey> Transaction<S, V, K> {
     #[inline]
-    pub fn get(&self, key_hash: &KeyHash) -> Result<Option<&V>, TrieError> {
+    pub fn get(&self, key_hash: &K) -> Result<Option<&V>, TrieError> {
         match &self.current_root {
             TrieRoot::Empty => Ok(None),
-            TrieRoot::Node(node_ref) => Self::get_node(&self.data_store, node_ref, key_hash),
+            TrieRoot::Node(node_ref) => Self::get_node(&self.data_store, node_ref, key_hash)
+                .map_err(|e| with_missing_key(e, key_hash)),
         }
     }
 
+    /// Check a branch loaded from the store with `Branch::check_bit_order`,
+    /// and return its discriminant bit, the parent bit of the next branch on the path.
+    ///
+    /// A corrupt store, like a `Snapshot` of an untrusted witness, can refer back to a branch on the path,
+    /// so every traversal through stored branches checks each one before descending.
+    #[inline]
+    fn check_stored_branch(
+        branch: &Branch<stored::Idx>,
+        stored_idx: stored::Idx,
+        parent_bit_idx: Option<u32>,
+        key_bits: u32,
+    ) -> Result<Option<u32>, TrieError> {
+        branch
+            .check_bit_order(parent_bit_idx, key_bits)
+            .map_err(|e| error_context(e, format_args!("Invalid stored branch {stored_idx}")))?;
+        Ok(Some(branch.mask.bit_idx()))
+    }
+
     #[inline]
     fn get_node<'root, 's: 'root>(
         data_store: &'s S,
-        mut node_ref: &'root NodeRef<V>,
-        key_hash: &KeyHash,
+        mut node_ref: &'root NodeRef<V, K>,
+        key_hash: &K,
     ) -> Result<Option<&'root V>, TrieError> {
+        let mut parent_bit_idx = None;
         loop {
             match node_ref {
-                NodeRef::ModBranch(branch) => match branch.key_position(key_hash) {
-                    KeyPosition::Left => node_ref = &branch.left,
-                    KeyPosition::Right => node_ref = &branch.right,
-                    KeyPosition::Adjacent(_) => return Ok(None),
-                },
+                NodeRef::ModBranch(branch) => {
+                    parent_bit_idx = Some(branch.mask.bit_idx());
+                    match branch.key_position(key_hash) {
+                        KeyPosition::Left => node_ref = &branch.left,
+                        KeyPosition::Right => node_ref = &branch.right,
+                        KeyPosition::Adjacent(_) => return Ok(None),
+                    }
+                }
                 NodeRef::ModLeaf(leaf) => {
                     if leaf.key_hash == *key_hash {
                         return Ok(Some(&leaf.value));
@@ -274,7 +1032,12 @@ impl<S: Store<V>, V> Transaction<S, V> {
                     }
                 }
                 NodeRef::Stored(stored_idx) => {
-                    return Self::get_stored_node(data_store, *stored_idx, key_hash);
+                    return Self::get_stored_node(
+                        data_store,
+                        *stored_idx,
+                        key_hash,
+                        parent_bit_idx,
+                    );
                 }
             }
         }
@@ -284,19 +1047,28 @@ impl<S: Store<V>, V> Transaction<S, V> {
     fn get_stored_node<'s>(
         data_store: &'s S,
         mut stored_idx: stored::Idx,
-        key_hash: &KeyHash,
-    ) -> Result<Option<&'s V>, TrieError> {
+        key_hash: &K,
+        mut parent_bit_idx: Option<u32>,
+    ) -> Result<Option<&'s V>, TrieError>
+    where
+        K: 's,
+    {
+        let key_bits = key_bits(key_hash);
         loop {
             let node = data_store
                 .get_node(stored_idx)
-                .map_err(|e| format!("Error in `get_stored_node`: {e}"))?;
+                .map_err(|e| error_context(e, "Error in `get_stored_node`"))?;
 
             match node {
-                Node::Branch(branch) => match branch.key_position(key_hash) {
-                    KeyPosition::Left => stored_idx = branch.left,
-                    KeyPosition::Right => stored_idx = branch.right,
-                    KeyPosition::Adjacent(_) => return Ok(None),
-                },
+                Node::Branch(branch) => {
+                    parent_bit_idx =
+                        Self::check_stored_branch(branch, stored_idx, parent_bit_idx, key_bits)?;
+                    match branch.key_position(key_hash) {
+                        KeyPosition::Left => stored_idx = branch.left,
+                        KeyPosition::Right => stored_idx = branch.right,
+                        KeyPosition::Adjacent(_) => return Ok(None),
+                    }
+                }
                 Node::Leaf(leaf) => {
                     if leaf.key_hash == *key_hash {
                         break;
@@ -309,15 +1081,373 @@ impl<S: Store<V>, V> Transaction<S, V> {
 
         match data_store
             .get_node(stored_idx)
-            .map_err(|e| format!("Error in `get_stored_node`: {e}"))?
+            .map_err(|e| error_context(e, "Error in `get_stored_node`"))?
         {
             Node::Leaf(leaf) => Ok(Some(&leaf.value)),
             _ => unreachable!("Prior loop only breaks on a leaf"),
         }
     }
 
+    /// Returns every entry with a key in `range`, ordered by `TrieKey::cmp_trie_order`.
+    ///
+    /// The bounds of `range` are compared with `TrieKey::cmp_trie_order`, not `Ord`.
+    ///
+    /// Against a `SnapshotBuilder` this records the nodes bounding the range,
+    /// so the `Snapshot` proves that no key in the range was omitted.
+    /// Replaying `range_get` against that `Snapshot` returns the same entries.
+    #[inline]
+    pub fn range_get(&self, range: RangeInclusive<K>) -> Result<Vec<(K, &V)>, TrieError> {
+        let mut entries = Vec::new();
+
+        if range.start().cmp_trie_order(range.end()).is_gt() {
+            return Ok(entries);
+        }
+
+        let key_range = KeyRange {
+            start: Some(range.start()),
+            end: Some(range.end()),
+        };
+
+        if let TrieRoot::Node(node_ref) = &self.current_root {
+            Self::range_get_node(&self.data_store, node_ref, key_range, None, &mut entries)?;
+        }
+
+        Ok(entries)
+    }
+
+    #[inline]
+    fn range_get_node<'root, 's: 'root>(
+        data_store: &'s S,
+        node_ref: &'root NodeRef<V, K>,
+        key_range: KeyRange<K>,
+        parent_bit_idx: Option<u32>,
+        entries: &mut Vec<(K, &'root V)>,
+    ) -> Result<(), TrieError> {
+        match node_ref {
+            NodeRef::ModBranch(branch) => {
+                let (left, right) = key_range.split(branch);
+                let bit_idx = Some(branch.mask.bit_idx());
+
+                if let Some(left) = left {
+                    Self::range_get_node(data_store, &branch.left, left, bit_idx, entries)?;
+                }
+                if let Some(right) = right {
+                    Self::range_get_node(data_store, &branch.right, right, bit_idx, entries)?;
+                }
+                Ok(())
+            }
+            NodeRef::ModLeaf(leaf) => {
+                if key_range.contains(&leaf.key_hash) {
+                    entries.push((leaf.key_hash, &leaf.value));
+                }
+                Ok(())
+            }
+            NodeRef::Stored(stored_idx) => Self::range_get_stored_node(
+                data_store,
+                *stored_idx,
+                key_range,
+                parent_bit_idx,
+                entries,
+            ),
+        }
+    }
+
+    #[inline]
+    fn range_get_stored_node<'s>(
+        data_store: &'s S,
+        stored_idx: stored::Idx,
+        key_range: KeyRange<K>,
+        parent_bit_idx: Option<u32>,
+        entries: &mut Vec<(K, &'s V)>,
+    ) -> Result<(), TrieError>
+    where
+        K: 's,
+    {
+        let node = data_store
+            .get_node(stored_idx)
+            .map_err(|e| error_context(e, "Error in `range_get_stored_node`"))?;
+
+        match node {
+            Node::Branch(branch) => {
+                // Without bounds the range does not look at the branch's bits, any width will do.
+                let key_bits = key_range
+                    .start
+                    .or(key_range.end)
+                    .map_or(MAX_KEY_BITS, key_bits);
+                let bit_idx =
+                    Self::check_stored_branch(branch, stored_idx, parent_bit_idx, key_bits)?;
+                let (left, right) = key_range.split(branch);
+
+                if let Some(left) = left {
+                    Self::range_get_stored_node(data_store, branch.left, left, bit_idx, entries)?;
+                }
+                if let Some(right) = right {
+                    Self::range_get_stored_node(data_store, branch.right, right, bit_idx, entries)?;
+                }
+                Ok(())
+            }
+            Node::Leaf(leaf) => {
+                if key_range.contains(&leaf.key_hash) {
+                    entries.push((leaf.key_hash, &leaf.value));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the entry with the first key after `key_hash` in trie order, see `TrieKey::cmp_trie_order`.
+    /// `key_hash` itself need not be in the trie.
+    ///
+    /// Against a `SnapshotBuilder` this records the path to `key_hash` and the path to the entry returned,
+    /// so the `Snapshot` proves that no key between them was omitted.
+    #[inline]
+    pub fn next_key_after(&self, key_hash: &K) -> Result<Option<(K, &V)>, TrieError> {
+        self.neighbor(key_hash, Neighbor::Next)
+    }
+
+    /// Returns the entry with the last key before `key_hash` in trie order, see `TrieKey::cmp_trie_order`.
+    /// `key_hash` itself need not be in the trie.
+    ///
+    /// Records the same witness as `next_key_after`.
+    #[inline]
+    pub fn prev_key_before(&self, key_hash: &K) -> Result<Option<(K, &V)>, TrieError> {
+        self.neighbor(key_hash, Neighbor::Prev)
+    }
+
+    /// Follow the path of `key_hash` as far as it goes, reporting where it stops and how much of the key matched.
+    ///
+    /// The result explains a `get` returning `None`,
+    /// and `matched_bits` measures how close the nearest keys are, for allocating keys far from existing ones.
+    /// Against a `SnapshotBuilder` this records the same path as `get`.
+    #[inline]
+    pub fn seek(&self, key_hash: &K) -> Result<SeekResult<K>, TrieError> {
+        let mut node_ref = match &self.current_root {
+            TrieRoot::Empty => {
+                return Ok(SeekResult {
+                    matched_bits: 0,
+                    depth: 0,
+                    node: SeekNode::Empty,
+                    exact: false,
+                })
+            }
+            TrieRoot::Node(node_ref) => node_ref,
+        };
+
+        let mut depth = 0;
+        let mut parent_bit_idx = None;
+        loop {
+            match node_ref {
+                NodeRef::ModBranch(branch) => {
+                    parent_bit_idx = Some(branch.mask.bit_idx());
+                    match branch.key_position(key_hash) {
+                        KeyPosition::Left => node_ref = &branch.left,
+                        KeyPosition::Right => node_ref = &branch.right,
+                        KeyPosition::Adjacent(_) => {
+                            return Ok(SeekResult::at_branch(branch, key_hash, depth))
+                        }
+                    }
+                }
+                NodeRef::ModLeaf(leaf) => {
+                    return Ok(SeekResult::at_leaf(leaf.key_hash, key_hash, depth))
+                }
+                NodeRef::Stored(stored_idx) => {
+                    return Self::seek_stored(
+                        &self.data_store,
+                        *stored_idx,
+                        key_hash,
+                        depth,
+                        parent_bit_idx,
+                    )
+                }
+            }
+            depth += 1;
+        }
+    }
+
+    #[inline]
+    fn seek_stored(
+        data_store: &S,
+        mut stored_idx: stored::Idx,
+        key_hash: &K,
+        mut depth: usize,
+        mut parent_bit_idx: Option<u32>,
+    ) -> Result<SeekResult<K>, TrieError> {
+        let key_bits = key_bits(key_hash);
+        loop {
+            let node = data_store
+                .get_node(stored_idx)
+                .map_err(|e| error_context(e, "Error in `seek_stored`"))?;
+
+            match node {
+                Node::Branch(branch) => {
+                    parent_bit_idx =
+                        Self::check_stored_branch(branch, stored_idx, parent_bit_idx, key_bits)?;
+                    match branch.key_position(key_hash) {
+                        KeyPosition::Left => stored_idx = branch.left,
+                        KeyPosition::Right => stored_idx = branch.right,
+                        KeyPosition::Adjacent(_) => {
+                            return Ok(SeekResult::at_branch(branch, key_hash, depth))
+                        }
+                    }
+                }
+                Node::Leaf(leaf) => return Ok(SeekResult::at_leaf(leaf.key_hash, key_hash, depth)),
+            }
+            depth += 1;
+        }
+    }
+
+    #[inline]
+    fn neighbor(&self, key_hash: &K, neighbor: Neighbor) -> Result<Option<(K, &V)>, TrieError> {
+        match &self.current_root {
+            TrieRoot::Empty => Ok(None),
+            TrieRoot::Node(node_ref) => {
+                Self::neighbor_node(&self.data_store, node_ref, key_hash, neighbor, None)
+            }
+        }
+    }
+
+    // TODO use a stack instead of recursion
+    #[inline]
+    fn neighbor_node<'root, 's: 'root>(
+        data_store: &'s S,
+        node_ref: &'root NodeRef<V, K>,
+        key_hash: &K,
+        neighbor: Neighbor,
+        parent_bit_idx: Option<u32>,
+    ) -> Result<Option<(K, &'root V)>, TrieError> {
+        match node_ref {
+            NodeRef::ModBranch(branch) => {
+                let bit_idx = Some(branch.mask.bit_idx());
+                match branch.neighbor_search(key_hash, neighbor) {
+                    NeighborSearch::Absent => Ok(None),
+                    NeighborSearch::First => Self::first_node(data_store, node_ref, neighbor),
+                    NeighborSearch::Descend { descend, fallback } => {
+                        match Self::neighbor_node(data_store, descend, key_hash, neighbor, bit_idx)?
+                        {
+                            Some(entry) => Ok(Some(entry)),
+                            None => match fallback {
+                                Some(fallback) => Self::first_node(data_store, fallback, neighbor),
+                                None => Ok(None),
+                            },
+                        }
+                    }
+                }
+            }
+            NodeRef::ModLeaf(leaf) => Ok(neighbor
+                .accepts(&leaf.key_hash, key_hash)
+                .then_some((leaf.key_hash, &leaf.value))),
+            NodeRef::Stored(stored_idx) => Self::neighbor_stored_node(
+                data_store,
+                *stored_idx,
+                key_hash,
+                neighbor,
+                parent_bit_idx,
+            ),
+        }
+    }
+
     #[inline]
-    pub fn insert(&mut self, key_hash: &KeyHash, value: V) -> Result<(), TrieError> {
+    fn neighbor_stored_node<'s>(
+        data_store: &'s S,
+        stored_idx: stored::Idx,
+        key_hash: &K,
+        neighbor: Neighbor,
+        parent_bit_idx: Option<u32>,
+    ) -> Result<Option<(K, &'s V)>, TrieError>
+    where
+        K: 's,
+    {
+        let node = data_store
+            .get_node(stored_idx)
+            .map_err(|e| error_context(e, "Error in `neighbor_stored_node`"))?;
+
+        match node {
+            Node::Branch(branch) => {
+                let bit_idx = Self::check_stored_branch(
+                    branch,
+                    stored_idx,
+                    parent_bit_idx,
+                    key_bits(key_hash),
+                )?;
+                match branch.neighbor_search(key_hash, neighbor) {
+                    NeighborSearch::Absent => Ok(None),
+                    NeighborSearch::First => {
+                        Self::first_stored_node(data_store, stored_idx, neighbor)
+                    }
+                    NeighborSearch::Descend { descend, fallback } => {
+                        match Self::neighbor_stored_node(
+                            data_store, *descend, key_hash, neighbor, bit_idx,
+                        )? {
+                            Some(entry) => Ok(Some(entry)),
+                            None => match fallback {
+                                Some(fallback) => {
+                                    Self::first_stored_node(data_store, *fallback, neighbor)
+                                }
+                                None => Ok(None),
+                            },
+                        }
+                    }
+                }
+            }
+            Node::Leaf(leaf) => Ok(neighbor
+                .accepts(&leaf.key_hash, key_hash)
+                .then_some((leaf.key_hash, &leaf.value))),
+        }
+    }
+
+    /// Returns the first entry under `node_ref` in the direction of `neighbor`.
+    #[inline]
+    fn first_node<'root, 's: 'root>(
+        data_store: &'s S,
+        mut node_ref: &'root NodeRef<V, K>,
+        neighbor: Neighbor,
+    ) -> Result<Option<(K, &'root V)>, TrieError> {
+        loop {
+            match node_ref {
+                NodeRef::ModBranch(branch) => {
+                    node_ref = neighbor.first_child(&branch.left, &branch.right);
+                }
+                NodeRef::ModLeaf(leaf) => return Ok(Some((leaf.key_hash, &leaf.value))),
+                NodeRef::Stored(stored_idx) => {
+                    return Self::first_stored_node(data_store, *stored_idx, neighbor);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn first_stored_node<'s>(
+        data_store: &'s S,
+        mut stored_idx: stored::Idx,
+        neighbor: Neighbor,
+    ) -> Result<Option<(K, &'s V)>, TrieError>
+    where
+        K: 's,
+    {
+        // Following first children never compares a key, so only the order of the branches matters.
+        let mut parent_bit_idx = None;
+        loop {
+            match data_store
+                .get_node(stored_idx)
+                .map_err(|e| error_context(e, "Error in `first_stored_node`"))?
+            {
+                Node::Branch(branch) => {
+                    parent_bit_idx = Self::check_stored_branch(
+                        branch,
+                        stored_idx,
+                        parent_bit_idx,
+                        MAX_KEY_BITS,
+                    )?;
+                    stored_idx = neighbor.first_child(branch.left, branch.right);
+                }
+                Node::Leaf(leaf) => return Ok(Some((leaf.key_hash, &leaf.value))),
+            }
+        }
+    }
+
+    #[inline]
+    pub fn insert(&mut self, key_hash: &K, value: V) -> Result<(), TrieError> {
+        self.root_hash.take();
         match &mut self.current_root {
             TrieRoot::Empty => {
                 self.current_root = TrieRoot::Node(NodeRef::ModLeaf(Box::new(Leaf {
@@ -327,26 +1457,78 @@ impl<S: Store<V>, V> Transaction<S, V> {
                 Ok(())
             }
             TrieRoot::Node(node_ref) => {
-                Self::insert_node(&mut self.data_store, node_ref, key_hash, value)
+                let inserted = Self::insert_node(&mut self.data_store, node_ref, key_hash, value)
+                    .map_err(|e| with_missing_key(e, key_hash));
+                self.debug_checked(key_hash, inserted)
             }
         }
     }
 
+    /// In debug builds, return an error if a `NodeRef::temp_null_stored` placeholder
+    /// was left on the path to `key_hash`, or beside it.
+    ///
+    /// `calc_root_hash` refuses to hash placeholders in all builds,
+    /// this catches them at the operation that leaked them.
+    #[inline]
+    fn debug_check_path(&self, key_hash: &K) -> Result<(), LeakedPlaceholder> {
+        if !cfg!(debug_assertions) {
+            return Ok(());
+        }
+
+        let TrieRoot::Node(root) = &self.current_root else {
+            return Ok(());
+        };
+        match placeholder_on_path(root, key_hash) {
+            Some(depth) => Err(LeakedPlaceholder {
+                key: Some(key_hash.words().into()),
+                depth: Some(depth),
+                source: None,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Run `debug_check_path` after an operation on `key_hash` returned `result`, whether it failed or not.
+    ///
+    /// Failed operations are where placeholders leak, so a leak is reported in place of the operation's error,
+    /// with that error as the source of the `LeakedPlaceholder`.
+    #[inline]
+    fn debug_checked<T>(&self, key_hash: &K, result: Result<T, TrieError>) -> Result<T, TrieError> {
+        match (self.debug_check_path(key_hash), result) {
+            (Ok(()), result) => result,
+            (Err(leak), Ok(_)) => Err(leak.into()),
+            (Err(leak), Err(e)) => Err(LeakedPlaceholder {
+                source: Some(e),
+                ..leak
+            }
+            .into()),
+        }
+    }
+
     #[inline(always)]
     fn insert_node<'root, 's: 'root>(
         data_store: &'s mut S,
-        mut node_ref: &'root mut NodeRef<V>,
-        key_hash: &KeyHash,
+        mut node_ref: &'root mut NodeRef<V, K>,
+        key_hash: &K,
         value: V,
     ) -> Result<(), TrieError> {
+        // The word index of the last branch we descended through.
+        let mut parent_word_idx = 0;
+        let mut parent_bit_idx = None;
+        let key_bits = key_bits(key_hash);
+
         loop {
             match node_ref {
                 NodeRef::ModBranch(branch) => match branch.key_position(key_hash) {
                     KeyPosition::Left => {
+                        parent_word_idx = branch.mask.word_idx();
+                        parent_bit_idx = Some(branch.mask.bit_idx());
                         node_ref = &mut branch.left;
                         continue;
                     }
                     KeyPosition::Right => {
+                        parent_word_idx = branch.mask.word_idx();
+                        parent_bit_idx = Some(branch.mask.bit_idx());
                         node_ref = &mut branch.right;
                         continue;
                     }
@@ -368,6 +1550,7 @@ impl<S: Store<V>, V> Transaction<S, V> {
 
                         return Ok(());
                     } else {
+                        leaf.check_on_path(key_hash, parent_word_idx)?;
                         let old_leaf = mem::replace(node_ref, NodeRef::temp_null_stored());
                         let NodeRef::ModLeaf(old_leaf) = old_leaf else {
                             unreachable!("We just matched a ModLeaf");
@@ -377,7 +1560,8 @@ impl<S: Store<V>, V> Transaction<S, V> {
                             value,
                         });
 
-                        let (new_branch, _) = Branch::new_from_leafs(0, old_leaf, new_leaf);
+                        let (new_branch, _) =
+                            Branch::new_from_leafs(parent_word_idx, old_leaf, new_leaf)?;
 
                         *node_ref = NodeRef::ModBranch(new_branch);
                         return Ok(());
@@ -385,10 +1569,19 @@ impl<S: Store<V>, V> Transaction<S, V> {
                 }
                 NodeRef::Stored(stored_idx) => {
                     let new_node = data_store.get_node(*stored_idx).map_err(|e| {
-                        format!("Error at `{}:{}:{}`: `{e}`", file!(), line!(), column!())
+                        error_context(
+                            e,
+                            format_args!("Error at `{}:{}:{}`", file!(), line!(), column!()),
+                        )
                     })?;
                     match new_node {
                         Node::Branch(new_branch) => {
+                            Self::check_stored_branch(
+                                new_branch,
+                                *stored_idx,
+                                parent_bit_idx,
+                                key_bits,
+                            )?;
                             *node_ref = NodeRef::ModBranch(Box::new(Branch {
                                 left: NodeRef::Stored(new_branch.left),
                                 right: NodeRef::Stored(new_branch.right),
@@ -408,16 +1601,15 @@ impl<S: Store<V>, V> Transaction<S, V> {
 
                                 return Ok(());
                             } else {
+                                leaf.check_on_path(key_hash, parent_word_idx)?;
                                 let (new_branch, _) = Branch::new_from_leafs(
-                                    // TODO we can use the most recent branch.word_idx - 1
-                                    // not sure if it's worth it, 0 is always correct.
-                                    0,
+                                    parent_word_idx,
                                     StoredLeafRef::new(leaf, *stored_idx),
                                     Box::new(Leaf {
                                         key_hash: *key_hash,
                                         value,
                                     }),
-                                );
+                                )?;
 
                                 *node_ref = NodeRef::ModBranch(new_branch);
                                 return Ok(());
@@ -430,16 +1622,584 @@ impl<S: Store<V>, V> Transaction<S, V> {
     }
 }
 
-impl<S: Store<V>, V: PortableHash + Clone> Transaction<S, V> {
+impl<S: Store<V>, V> Transaction<S, V> {
+    /// Returns every key starting with the first `len_bits` bits of `prefix_bits`,
+    /// ordered by `KeyHash::cmp_trie_order`.
+    ///
+    /// Bit `j` of `prefix_bits[i]` is bit `8 * i + j` of the key, where bit 0 is the least significant.
+    /// This matches the byte layout of `KeyHash::from_bytes`,
+    /// so a namespace stored in the first bytes of a key can be passed as is.
+    ///
+    /// The keys are read lazily from the prefix subtree, so memory grows with the depth of the trie,
+    /// not with the number of keys. The iterator stops after the first error.
+    /// The keys under a prefix are contiguous in trie order, so a drained iterator
+    /// records the same witness as `range_get` over the prefix.
+    #[inline]
+    pub fn keys_with_prefix(
+        &self,
+        prefix_bits: &[u8],
+        len_bits: usize,
+    ) -> Result<impl Iterator<Item = Result<KeyHash, TrieError>> + '_, TrieError> {
+        let (start, end) = KeyHash::prefix_bounds(prefix_bits, len_bits)?;
+
+        let stack = match &self.current_root {
+            TrieRoot::Node(node_ref) => vec![(PrefixCursor::new(node_ref), None, (true, true))],
+            TrieRoot::Empty => Vec::new(),
+        };
+
+        Ok(PrefixKeys {
+            data_store: &self.data_store,
+            start,
+            end,
+            stack,
+        })
+    }
+}
+
+/// A node `PrefixKeys` has yet to visit.
+enum PrefixCursor<'txn, V> {
+    Mod(&'txn NodeRef<V>),
+    Stored(stored::Idx),
+}
+
+impl<'txn, V> PrefixCursor<'txn, V> {
+    #[inline]
+    fn new(node_ref: &'txn NodeRef<V>) -> Self {
+        match node_ref {
+            NodeRef::Stored(idx) => PrefixCursor::Stored(*idx),
+            node_ref => PrefixCursor::Mod(node_ref),
+        }
+    }
+}
+
+/// The iterator returned by `Transaction::keys_with_prefix`.
+struct PrefixKeys<'txn, S, V> {
+    data_store: &'txn S,
+    start: KeyHash,
+    end: KeyHash,
+    /// Each node to visit, the discriminant bit of its parent,
+    /// and whether `start` and `end` still bound its keys.
+    /// The left child is on top, so keys come out in trie order.
+    stack: Vec<(PrefixCursor<'txn, V>, Option<u32>, (bool, bool))>,
+}
+
+impl<S: Store<V>, V> PrefixKeys<'_, S, V> {
+    #[inline]
+    fn fail(&mut self, error: TrieError) -> Option<Result<KeyHash, TrieError>> {
+        self.stack.clear();
+        Some(Err(error))
+    }
+}
+
+impl<S: Store<V>, V> Iterator for PrefixKeys<'_, S, V> {
+    type Item = Result<KeyHash, TrieError>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((cursor, parent_bit_idx, (has_start, has_end))) = self.stack.pop() {
+            let key_range = KeyRange {
+                start: has_start.then_some(&self.start),
+                end: has_end.then_some(&self.end),
+            };
+
+            let (bit_idx, (left, right), children) = match cursor {
+                PrefixCursor::Mod(NodeRef::ModBranch(branch)) => (
+                    branch.mask.bit_idx(),
+                    key_range.split(branch),
+                    [
+                        PrefixCursor::new(&branch.left),
+                        PrefixCursor::new(&branch.right),
+                    ],
+                ),
+                PrefixCursor::Mod(NodeRef::ModLeaf(leaf)) => {
+                    if key_range.contains(&leaf.key_hash) {
+                        return Some(Ok(leaf.key_hash));
+                    }
+                    continue;
+                }
+                PrefixCursor::Mod(NodeRef::Stored(_)) => {
+                    unreachable!(
+                        "`PrefixCursor::new` turns stored nodes into `PrefixCursor::Stored`"
+                    )
+                }
+                PrefixCursor::Stored(stored_idx) => {
+                    let node = match self.data_store.get_node(stored_idx) {
+                        Ok(node) => node,
+                        Err(e) => {
+                            return self.fail(error_context(e, "Error in `keys_with_prefix`"))
+                        }
+                    };
+                    match node {
+                        Node::Branch(branch) => {
+                            if let Err(e) = Transaction::<S, V>::check_stored_branch(
+                                branch,
+                                stored_idx,
+                                parent_bit_idx,
+                                key_bits(&self.start),
+                            ) {
+                                return self.fail(e);
+                            }
+                            (
+                                branch.mask.bit_idx(),
+                                key_range.split(branch),
+                                [
+                                    PrefixCursor::Stored(branch.left),
+                                    PrefixCursor::Stored(branch.right),
+                                ],
+                            )
+                        }
+                        Node::Leaf(leaf) => {
+                            if key_range.contains(&leaf.key_hash) {
+                                return Some(Ok(leaf.key_hash));
+                            }
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let bounds = |range: KeyRange<KeyHash>| (range.start.is_some(), range.end.is_some());
+            let (left, right) = (left.map(bounds), right.map(bounds));
+            let [left_child, right_child] = children;
+            if let Some(right) = right {
+                self.stack.push((right_child, Some(bit_idx), right));
+            }
+            if let Some(left) = left {
+                self.stack.push((left_child, Some(bit_idx), left));
+            }
+        }
+
+        None
+    }
+}
+
+impl<S: Store<V>, V: PortableHash + Clone> Transaction<S, V> {
+    /// The number of leaves in the trie, read from its root hash.
+    ///
+    /// `hasher` must hash in counted mode, see `counted`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn count_leaves<H: PortableHasher<32>>(&self, hasher: &mut H) -> Result<u32, TrieError> {
+        check_counted::<H>()?;
+        Ok(root_leaf_count(self.calc_root_hash(hasher)?))
+    }
+
+    /// Prove the number of leaves whose keys start with the first `len_bits` bits of `prefix_bits`,
+    /// numbered as in `keys_with_prefix`, at the transaction's current root.
+    ///
+    /// `hasher` must hash in counted mode, see `counted`.
+    /// Hashes every sibling on the path to the prefix, without modifying the trie.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn prove_prefix_count<H: PortableHasher<32>>(
+        &self,
+        prefix_bits: &[u8],
+        len_bits: usize,
+        hasher: &mut H,
+    ) -> Result<PrefixCountProof<V>, TrieError> {
+        check_counted::<H>()?;
+        let (min, _) = KeyHash::prefix_bounds(prefix_bits, len_bits)?;
+
+        let TrieRoot::Node(root) = &self.current_root else {
+            return Ok(PrefixCountProof {
+                path: Box::new([]),
+                end: PrefixCountEnd::Empty,
+            });
+        };
+
+        let mut path = Vec::new();
+        let (_, end) = Self::prefix_count_path(
+            hasher,
+            &self.data_store,
+            root,
+            None,
+            &min,
+            len_bits,
+            &mut path,
+        )?;
+
+        Ok(PrefixCountProof {
+            path: path.into_boxed_slice(),
+            end,
+        })
+    }
+
+    /// Record the branches from `node_ref` down the prefix of `min`'s first `len_bits` bits in `path`.
+    ///
+    /// Returns the hash of `node_ref` and the node the path ends at.
+    #[inline]
+    fn prefix_count_path(
+        hasher: &mut impl PortableHasher<32>,
+        data_store: &S,
+        node_ref: &NodeRef<V>,
+        parent_bit_idx: Option<u32>,
+        min: &KeyHash,
+        len_bits: usize,
+        path: &mut Vec<Branch<NodeHash>>,
+    ) -> Result<(NodeHash, PrefixCountEnd<V>), TrieError> {
+        let stored_children: [NodeRef<V>; 2];
+        let (branch, left, right) = match node_ref {
+            NodeRef::ModBranch(branch) => {
+                (branch.with_children((), ()), &branch.left, &branch.right)
+            }
+            NodeRef::ModLeaf(leaf) => {
+                return Ok((
+                    leaf.hash_leaf(hasher),
+                    PrefixCountEnd::Leaf(Leaf::clone(leaf)),
+                ));
+            }
+            NodeRef::Stored(idx) => match data_store
+                .get_node(*idx)
+                .map_err(|e| error_context(e, "Error in `prefix_count_path`"))?
+            {
+                Node::Branch(branch) => {
+                    Self::check_stored_branch(branch, *idx, parent_bit_idx, key_bits(min))?;
+                    stored_children = [NodeRef::Stored(branch.left), NodeRef::Stored(branch.right)];
+                    let [left, right] = &stored_children;
+                    (branch.with_children((), ()), left, right)
+                }
+                Node::Leaf(leaf) => {
+                    return Ok((leaf.hash_leaf(hasher), PrefixCountEnd::Leaf(leaf.clone())));
+                }
+            },
+        };
+
+        let go_right = match branch.key_position(min) {
+            _ if branch.mask.bit_idx() as usize >= len_bits => None,
+            KeyPosition::Left => Some(false),
+            KeyPosition::Right => Some(true),
+            KeyPosition::Adjacent(_) => None,
+        };
+        let Some(go_right) = go_right else {
+            let left = Self::hash_node(hasher, data_store, left)?;
+            let right = Self::hash_node(hasher, data_store, right)?;
+            let end = branch.with_children(left, right);
+            return Ok((
+                end.hash_branch(hasher, &left, &right),
+                PrefixCountEnd::Branch(end),
+            ));
+        };
+        let (child, other) = if go_right {
+            (right, left)
+        } else {
+            (left, right)
+        };
+
+        let path_idx = path.len();
+        let other_hash = Self::hash_node(hasher, data_store, other)?;
+        path.push(branch.with_children(other_hash, other_hash));
+
+        let bit_idx = Some(branch.mask.bit_idx());
+        let (child_hash, end) =
+            Self::prefix_count_path(hasher, data_store, child, bit_idx, min, len_bits, path)?;
+
+        let node = &mut path[path_idx];
+        if go_right {
+            node.right = child_hash;
+        } else {
+            node.left = child_hash;
+        }
+        Ok((node.hash_branch(hasher, &node.left, &node.right), end))
+    }
+}
+
+impl<S: Store<V>, V: PortableHash + Clone> Transaction<S, V> {
+    /// Remove every key starting with the first `len_bits` bits of `prefix_bits`, numbered as in `keys_with_prefix`,
+    /// returning whether any key was removed.
+    ///
+    /// The keys under a prefix are one subtree, which is detached whole instead of removing its keys one by one.
+    /// The witness records the path down to the subtree and the subtree's root node, but none of the nodes below it,
+    /// so the removal costs the same to replay however many keys it removes.
+    #[inline]
+    pub fn remove_prefix(
+        &mut self,
+        prefix_bits: &[u8],
+        len_bits: usize,
+    ) -> Result<bool, TrieError> {
+        let (min, max) = KeyHash::prefix_bounds(prefix_bits, len_bits)?;
+        let TrieRoot::Node(root) = &self.current_root else {
+            return Ok(false);
+        };
+
+        let mut turns = Vec::new();
+        if !Self::prefix_subtree_turns(
+            &self.data_store,
+            root,
+            None,
+            (&min, &max, len_bits),
+            &mut turns,
+        )? {
+            return Ok(false);
+        }
+        self.root_hash.take();
+
+        let Some((&removed_right, turns)) = turns.split_last() else {
+            self.current_root = TrieRoot::Empty;
+            return Ok(true);
+        };
+
+        let TrieRoot::Node(root) = &mut self.current_root else {
+            unreachable!("We just found a subtree");
+        };
+        let mut node_ref = root;
+        // The word index of the branch above `node_ref`.
+        let mut grandparent_word_idx = 0;
+        for &go_right in turns {
+            Self::load_node(&self.data_store, node_ref)?;
+            let NodeRef::ModBranch(branch) = node_ref else {
+                unreachable!("The path only turns at branches");
+            };
+            grandparent_word_idx = branch.mask.word_idx();
+            node_ref = if go_right {
+                &mut branch.right
+            } else {
+                &mut branch.left
+            };
+        }
+
+        Self::load_node(&self.data_store, node_ref)?;
+        let NodeRef::ModBranch(parent) = mem::replace(node_ref, NodeRef::temp_null_stored()) else {
+            unreachable!("The path only turns at branches");
+        };
+        let Branch {
+            left,
+            right,
+            mask,
+            prior_word,
+            prefix,
+        } = *parent;
+
+        let sibling = if removed_right { left } else { right };
+        let parent = Branch {
+            left: (),
+            right: (),
+            mask,
+            prior_word,
+            prefix,
+        };
+        let promoted =
+            Self::promote_sibling(&self.data_store, sibling, &parent, grandparent_word_idx)
+                .map(|sibling| *node_ref = sibling);
+
+        // `min` has the prefix, so its path passes where the removed subtree was.
+        self.debug_checked(&min, promoted).map(|()| true)
+    }
+
+    /// Like `remove_prefix`, but also returns a `PrefixRemovalProof` of the removal,
+    /// or `None` if no key starts with the prefix.
+    ///
+    /// The proof relates the root of the transaction immediately before the removal
+    /// to the root immediately after it.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn remove_prefix_with_proof(
+        &mut self,
+        prefix_bits: &[u8],
+        len_bits: usize,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<Option<PrefixRemovalProof<V>>, TrieError> {
+        let (min, max) = KeyHash::prefix_bounds(prefix_bits, len_bits)?;
+        let TrieRoot::Node(root) = &self.current_root else {
+            return Ok(None);
+        };
+
+        let mut path = Vec::new();
+        let mut sibling = None;
+        let Some((_, removed)) = Self::prefix_removal_path(
+            hasher,
+            &self.data_store,
+            root,
+            (0, None),
+            (&min, &max, len_bits),
+            &mut path,
+            &mut sibling,
+        )?
+        else {
+            return Ok(None);
+        };
+
+        let removed_any = self.remove_prefix(prefix_bits, len_bits)?;
+        debug_assert!(removed_any);
+
+        Ok(Some(PrefixRemovalProof {
+            path: path.into_boxed_slice(),
+            removed,
+            sibling,
+        }))
+    }
+
+    /// Record the turns from `node_ref` down to the subtree of the keys in `min..=max`,
+    /// the keys starting with a `len_bits` bit prefix.
+    ///
+    /// Returns false if no key starts with the prefix.
+    #[inline]
+    fn prefix_subtree_turns(
+        data_store: &S,
+        node_ref: &NodeRef<V>,
+        parent_bit_idx: Option<u32>,
+        (min, max, len_bits): (&KeyHash, &KeyHash, usize),
+        turns: &mut Vec<bool>,
+    ) -> Result<bool, TrieError> {
+        let stored_children: [NodeRef<V>; 2];
+        let (bit_idx, side, left, right) = match node_ref {
+            NodeRef::ModBranch(branch) => (
+                branch.mask.bit_idx(),
+                branch.prefix_side(min, max, len_bits),
+                &branch.left,
+                &branch.right,
+            ),
+            NodeRef::ModLeaf(leaf) => {
+                return Ok(leaf.key_hash.matches_prefix(min, len_bits as u32))
+            }
+            NodeRef::Stored(idx) => match data_store
+                .get_node(*idx)
+                .map_err(|e| error_context(e, "Error in `prefix_subtree_turns`"))?
+            {
+                Node::Branch(branch) => {
+                    Self::check_stored_branch(branch, *idx, parent_bit_idx, key_bits(min))?;
+                    stored_children = [NodeRef::Stored(branch.left), NodeRef::Stored(branch.right)];
+                    let [left, right] = &stored_children;
+                    (
+                        branch.mask.bit_idx(),
+                        branch.prefix_side(min, max, len_bits),
+                        left,
+                        right,
+                    )
+                }
+                Node::Leaf(leaf) => return Ok(leaf.key_hash.matches_prefix(min, len_bits as u32)),
+            },
+        };
+
+        match side {
+            PrefixSide::Inside => Ok(true),
+            PrefixSide::Outside => Ok(false),
+            PrefixSide::Descend { go_right } => {
+                turns.push(go_right);
+                let child = if go_right { right } else { left };
+                Self::prefix_subtree_turns(
+                    data_store,
+                    child,
+                    Some(bit_idx),
+                    (min, max, len_bits),
+                    turns,
+                )
+            }
+        }
+    }
+
+    /// Record the branches from `node_ref` down to the subtree of the keys in `min..=max` in `path`,
+    /// and the subtree's sibling if it is a branch that `remove_prefix` will modify.
+    ///
+    /// Returns the hash of `node_ref` and the subtree's root, or `None` if no key starts with the prefix.
+    #[inline]
+    fn prefix_removal_path(
+        hasher: &mut impl PortableHasher<32>,
+        data_store: &S,
+        node_ref: &NodeRef<V>,
+        (parent_word_idx, parent_bit_idx): (usize, Option<u32>),
+        (min, max, len_bits): (&KeyHash, &KeyHash, usize),
+        path: &mut Vec<Branch<NodeHash>>,
+        sibling: &mut Option<Branch<NodeHash>>,
+    ) -> Result<Option<(NodeHash, RemovedSubtree<V>)>, TrieError> {
+        let leaf_in_prefix = |leaf: &Leaf<V>, hasher: &mut _| {
+            leaf.key_hash
+                .matches_prefix(min, len_bits as u32)
+                .then(|| (leaf.hash_leaf(hasher), RemovedSubtree::Leaf(leaf.clone())))
+        };
+
+        let stored_children: [NodeRef<V>; 2];
+        let (branch, left, right) = match node_ref {
+            NodeRef::ModBranch(branch) => {
+                (branch.with_children((), ()), &branch.left, &branch.right)
+            }
+            NodeRef::ModLeaf(leaf) => return Ok(leaf_in_prefix(leaf, hasher)),
+            NodeRef::Stored(idx) => match data_store
+                .get_node(*idx)
+                .map_err(|e| error_context(e, "Error in `prefix_removal_path`"))?
+            {
+                Node::Branch(branch) => {
+                    Self::check_stored_branch(branch, *idx, parent_bit_idx, key_bits(min))?;
+                    stored_children = [NodeRef::Stored(branch.left), NodeRef::Stored(branch.right)];
+                    let [left, right] = &stored_children;
+                    (branch.with_children((), ()), left, right)
+                }
+                Node::Leaf(leaf) => return Ok(leaf_in_prefix(leaf, hasher)),
+            },
+        };
+
+        let go_right = match branch.prefix_side(min, max, len_bits) {
+            PrefixSide::Descend { go_right } => go_right,
+            PrefixSide::Outside => return Ok(None),
+            PrefixSide::Inside => {
+                let left = Self::hash_node(hasher, data_store, left)?;
+                let right = Self::hash_node(hasher, data_store, right)?;
+                let removed = branch.with_children(left, right);
+                return Ok(Some((
+                    removed.hash_branch(hasher, &left, &right),
+                    RemovedSubtree::Branch(removed),
+                )));
+            }
+        };
+        let (child, other) = if go_right {
+            (right, left)
+        } else {
+            (left, right)
+        };
+
+        let path_idx = path.len();
+        let other_hash = Self::hash_node(hasher, data_store, other)?;
+        path.push(branch.with_children(other_hash, other_hash));
+
+        let Some((child_hash, removed)) = Self::prefix_removal_path(
+            hasher,
+            data_store,
+            child,
+            (branch.mask.word_idx(), Some(branch.mask.bit_idx())),
+            (min, max, len_bits),
+            path,
+            sibling,
+        )?
+        else {
+            return Ok(None);
+        };
+
+        let node = &mut path[path_idx];
+        if go_right {
+            node.right = child_hash;
+        } else {
+            node.left = child_hash;
+        }
+        let hash = node.hash_branch(hasher, &node.left, &node.right);
+
+        // The child is the removed subtree, so `remove_prefix` will promote `other`.
+        if path.len() == path_idx + 1 && branch.mask.word_idx() != parent_word_idx {
+            *sibling = Self::branch_with_child_hashes(hasher, data_store, other)?;
+        }
+
+        Ok(Some((hash, removed)))
+    }
+}
+
+impl<S: Store<V, K>, V: PortableHash + Clone, K: TrieKey> Transaction<S, V, K> {
     /// This method allows for getting, inserting, and updating a entry in the trie with a single lookup.
     /// We match the standard library's `Entry` API for the most part.
     ///
-    /// Note: Use of `entry` renders the trie path even if the entry is not modified.
+    /// Note: Use of `entry` renders the branches on the trie path even if the entry is not modified.
     /// This incurs allocations, now and unnecessary rehashing later when calculating the root hash.
     /// For this reason you should prefer `get` if you have a high probability of not modifying the entry.
+    /// A stored leaf is only copied into the trie once the entry is mutably borrowed or modified.
     #[inline]
-    pub fn entry<'txn>(&'txn mut self, key_hash: &KeyHash) -> Result<Entry<'txn, V>, TrieError> {
+    pub fn entry<'txn>(&'txn mut self, key_hash: &K) -> Result<Entry<'txn, V, K>, TrieError> {
+        self.root_hash.take();
         let mut key_position = KeyPositionAdjacent::PrefixOfWord(usize::MAX);
+        let mut parent_word_idx = 0;
+        let mut parent_bit_idx = None;
+        let key_bits = key_bits(key_hash);
+        let mut turns = Vec::new();
+        let data_store = &self.data_store;
 
         match self.current_root {
             TrieRoot::Empty => Ok(Entry::VacantEmptyTrie(VacantEntryEmptyTrie {
@@ -448,6 +2208,7 @@ impl<S: Store<V>, V: PortableHash + Clone> Transaction<S, V> {
             })),
             TrieRoot::Node(ref mut root) => {
                 let mut node_ref = root;
+                let mut stored_leaf = None;
                 loop {
                     let go_right = match &*node_ref {
                         NodeRef::ModBranch(branch) => match branch.key_position(key_hash) {
@@ -460,21 +2221,27 @@ impl<S: Store<V>, V: PortableHash + Clone> Transaction<S, V> {
                         },
                         NodeRef::ModLeaf(_) => break,
                         NodeRef::Stored(idx) => {
-                            let loaded_node = self.data_store.get_node(*idx).map_err(|e| {
-                                format!(
-                                    "Error in `entry` at {file}:{line}:{column}: could not get stored node: {e}",
-                                    file = file!(),
-                                    line = line!(),
-                                    column = column!(),
-                                )
-                            })?;
+                            let loaded_node = data_store
+                                .get_node(*idx)
+                                .map_err(|e| error_context(e, "Error in `entry`"))?;
 
                             match loaded_node {
                                 Node::Branch(branch) => {
+                                    Self::check_stored_branch(
+                                        branch,
+                                        *idx,
+                                        parent_bit_idx,
+                                        key_bits,
+                                    )?;
                                     // Connect the new branch to the trie.
                                     *node_ref =
                                         NodeRef::ModBranch(Box::new(Branch::from_stored(branch)));
                                 }
+                                // The leaf is only cloned into the trie once the entry is modified.
+                                Node::Leaf(leaf) if leaf.key_hash == *key_hash => {
+                                    stored_leaf = Some(leaf);
+                                    break;
+                                }
                                 Node::Leaf(leaf) => {
                                     *node_ref = NodeRef::ModLeaf(Box::new(leaf.clone()));
                                 }
@@ -485,18 +2252,32 @@ impl<S: Store<V>, V: PortableHash + Clone> Transaction<S, V> {
 
                     match (go_right, node_ref) {
                         (true, NodeRef::ModBranch(ref mut branch)) => {
+                            parent_word_idx = branch.mask.word_idx();
+                            parent_bit_idx = Some(branch.mask.bit_idx());
                             node_ref = &mut branch.right;
                         }
                         (false, NodeRef::ModBranch(ref mut branch)) => {
+                            parent_word_idx = branch.mask.word_idx();
+                            parent_bit_idx = Some(branch.mask.bit_idx());
                             node_ref = &mut branch.left;
                         }
                         _ => unreachable!("We just matched a ModBranch"),
                     }
+                    turns.push(go_right);
+                }
+
+                if let Some(stored) = stored_leaf {
+                    return Ok(Entry::Occupied(OccupiedEntry {
+                        node_ref,
+                        stored: Some(stored),
+                        turns,
+                    }));
                 }
 
                 // This convoluted return makes the borrow checker happy.
                 if let NodeRef::ModLeaf(leaf) = &*node_ref {
                     if leaf.key_hash != *key_hash {
+                        leaf.check_on_path(key_hash, parent_word_idx)?;
                         // This is a logical null
                         // TODO we should break VacantEntry into two types VacantEntryBranch and VacantEntryLeaf
                         debug_assert_eq!(
@@ -508,27 +2289,523 @@ impl<S: Store<V>, V: PortableHash + Clone> Transaction<S, V> {
                             parent: node_ref,
                             key_hash: *key_hash,
                             key_position,
+                            parent_word_idx,
+                            turns,
                         }));
                     }
                 };
 
-                if let NodeRef::ModBranch(_) = &*node_ref {
-                    Ok(Entry::Vacant(VacantEntry {
+                match node_ref {
+                    NodeRef::ModBranch(_) => Ok(Entry::Vacant(VacantEntry {
                         parent: node_ref,
                         key_hash: *key_hash,
                         key_position,
-                    }))
-                } else if let NodeRef::ModLeaf(leaf) = &mut *node_ref {
-                    Ok(Entry::Occupied(OccupiedEntry { leaf }))
+                        parent_word_idx,
+                        turns,
+                    })),
+                    NodeRef::ModLeaf(_) => Ok(Entry::Occupied(OccupiedEntry {
+                        node_ref,
+                        stored: None,
+                        turns,
+                    })),
+                    NodeRef::Stored(_) => {
+                        unreachable!("prior loop only breaks on a leaf or branch")
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove the entry at `key_hash`, returning its value.
+    ///
+    /// The removed leaf's parent branch is replaced by the leaf's sibling,
+    /// so the trie is left exactly as if the key had never been inserted.
+    ///
+    /// If the key is absent, nothing is modified, but the path proving its absence is still recorded.
+    #[inline]
+    pub fn remove(&mut self, key_hash: &K) -> Result<Option<V>, TrieError> {
+        if self.get(key_hash)?.is_none() {
+            return Ok(None);
+        }
+        self.root_hash.take();
+
+        let TrieRoot::Node(root) = &mut self.current_root else {
+            unreachable!("An empty trie has no entries");
+        };
+
+        Self::load_node(&self.data_store, root)?;
+
+        if let NodeRef::ModLeaf(_) = root {
+            let TrieRoot::Node(NodeRef::ModLeaf(leaf)) = mem::take(&mut self.current_root) else {
+                unreachable!("We just matched a ModLeaf");
+            };
+
+            return Ok(Some(leaf.value));
+        }
+
+        let removed = Self::remove_under_branch(&self.data_store, root, key_hash)
+            .map_err(|e| with_missing_key(e, key_hash));
+        self.debug_checked(key_hash, removed).map(Some)
+    }
+
+    /// Like `remove`, but also returns a `DeletionProof` of the removal.
+    ///
+    /// The proof relates the root of the transaction immediately before the removal
+    /// to the root immediately after it.
+    /// The removed value is the proof's `leaf.value`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn remove_with_proof(
+        &mut self,
+        key_hash: &K,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<Option<DeletionProof<V, K>>, TrieError> {
+        let TrieRoot::Node(root) = &self.current_root else {
+            return Ok(None);
+        };
+
+        let mut path = Vec::new();
+        let mut sibling = None;
+        if Self::deletion_path(
+            hasher,
+            &self.data_store,
+            root,
+            (0, None),
+            key_hash,
+            &mut path,
+            &mut sibling,
+        )?
+        .is_none()
+        {
+            return Ok(None);
+        }
+
+        let Some(value) = self.remove(key_hash)? else {
+            unreachable!("We just found the leaf");
+        };
+
+        Ok(Some(DeletionProof {
+            path: path.into_boxed_slice(),
+            leaf: Leaf {
+                key_hash: *key_hash,
+                value,
+            },
+            sibling,
+        }))
+    }
+
+    /// Prove that `key_hash` is in the trie at the transaction's current root,
+    /// or return `None` if the key is absent.
+    ///
+    /// Hashes every sibling on the path to the leaf, without modifying the trie.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn prove_inclusion(
+        &self,
+        key_hash: &K,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<Option<InclusionProof<V, K>>, TrieError> {
+        let TrieRoot::Node(root) = &self.current_root else {
+            return Ok(None);
+        };
+
+        let mut path = Vec::new();
+        if Self::deletion_path(
+            hasher,
+            &self.data_store,
+            root,
+            (0, None),
+            key_hash,
+            &mut path,
+            &mut None,
+        )?
+        .is_none()
+        {
+            return Ok(None);
+        }
+
+        let Some(value) = self.get(key_hash)? else {
+            unreachable!("We just found the leaf");
+        };
+
+        Ok(Some(InclusionProof {
+            path: path.into_boxed_slice(),
+            leaf: Leaf {
+                key_hash: *key_hash,
+                value: value.clone(),
+            },
+        }))
+    }
+
+    /// Prove the leaf at the end of `leaf_path` is in the trie at the transaction's current root.
+    ///
+    /// Follows the turns recorded by `Entry::or_insert_with_proof` instead of comparing the key at each branch,
+    /// and hashes every sibling on the way, like `prove_inclusion`.
+    /// Returns an error if the trie was modified since the path was recorded, and it no longer leads to the leaf.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn prove_leaf_path(
+        &self,
+        leaf_path: &LeafPath<K>,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<InclusionProof<V, K>, TrieError> {
+        let stale = || {
+            TrieError::from(format!(
+                "Stale leaf path: the trie no longer has a leaf for {:?} at the end of the path",
+                leaf_path.key_hash
+            ))
+        };
+        let TrieRoot::Node(root) = &self.current_root else {
+            return Err(stale());
+        };
+        let mut node_ref = root;
+
+        let mut path = Vec::with_capacity(leaf_path.turns.len());
+        let mut turns = leaf_path.turns.iter();
+        let mut stored_idx = None;
+
+        // Descend through the modified branches, then through the store.
+        for &go_right in turns.by_ref() {
+            let branch = match node_ref {
+                NodeRef::ModBranch(branch) => branch,
+                NodeRef::ModLeaf(_) => return Err(stale()),
+                NodeRef::Stored(idx) => {
+                    stored_idx = Some((*idx, go_right));
+                    break;
+                }
+            };
+
+            let (child, other) = if go_right {
+                (&branch.right, &branch.left)
+            } else {
+                (&branch.left, &branch.right)
+            };
+            let other_hash = Self::hash_node(hasher, &self.data_store, other)?;
+            path.push(branch.with_children(other_hash, other_hash));
+            node_ref = child;
+        }
+
+        let leaf = match stored_idx {
+            None => match node_ref {
+                NodeRef::ModLeaf(leaf) => &**leaf,
+                NodeRef::ModBranch(_) => return Err(stale()),
+                NodeRef::Stored(idx) => match self
+                    .data_store
+                    .get_node(*idx)
+                    .map_err(|e| error_context(e, "Error in `prove_leaf_path`"))?
+                {
+                    Node::Leaf(leaf) => leaf,
+                    Node::Branch(_) => return Err(stale()),
+                },
+            },
+            Some((mut idx, first_turn)) => {
+                let mut next_turn = Some(first_turn);
+                loop {
+                    let node = self
+                        .data_store
+                        .get_node(idx)
+                        .map_err(|e| error_context(e, "Error in `prove_leaf_path`"))?;
+
+                    match (node, next_turn) {
+                        (Node::Branch(branch), Some(go_right)) => {
+                            let (child, other) = if go_right {
+                                (branch.right, branch.left)
+                            } else {
+                                (branch.left, branch.right)
+                            };
+                            let other_hash =
+                                Self::hash_node(hasher, &self.data_store, &NodeRef::Stored(other))?;
+                            path.push(branch.with_children(other_hash, other_hash));
+                            idx = child;
+                            next_turn = turns.next().copied();
+                        }
+                        (Node::Leaf(leaf), None) => break leaf,
+                        _ => return Err(stale()),
+                    }
+                }
+            }
+        };
+
+        if leaf.key_hash != leaf_path.key_hash {
+            return Err(stale());
+        }
+
+        // Fill in the children on the path, from the leaf up.
+        let mut hash = leaf.hash_leaf(hasher);
+        for (branch, &go_right) in path.iter_mut().zip(&leaf_path.turns).rev() {
+            if go_right {
+                branch.right = hash;
+            } else {
+                branch.left = hash;
+            }
+            hash = branch.hash_branch(hasher, &branch.left, &branch.right);
+        }
+
+        Ok(InclusionProof {
+            path: path.into_boxed_slice(),
+            leaf: leaf.clone(),
+        })
+    }
+
+    /// Replace a `NodeRef::Stored` with the modifiable node it refers to.
+    #[inline]
+    fn load_node(data_store: &S, node_ref: &mut NodeRef<V, K>) -> Result<(), TrieError> {
+        if let NodeRef::Stored(idx) = node_ref {
+            match data_store.get_node(*idx).map_err(|e| {
+                error_context(
+                    e,
+                    format_args!("Error at `{}:{}:{}`", file!(), line!(), column!()),
+                )
+            })? {
+                Node::Branch(branch) => {
+                    *node_ref = NodeRef::ModBranch(Box::new(Branch::from_stored(branch)))
+                }
+                Node::Leaf(leaf) => *node_ref = NodeRef::ModLeaf(Box::new(leaf.clone())),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove the leaf at `key_hash` from under the branch `node_ref`.
+    ///
+    /// The caller must ensure the key is present.
+    #[inline]
+    fn remove_under_branch(
+        data_store: &S,
+        mut node_ref: &mut NodeRef<V, K>,
+        key_hash: &K,
+    ) -> Result<V, TrieError> {
+        // The word index of the branch above `node_ref`.
+        let mut grandparent_word_idx = 0;
+
+        loop {
+            let (go_right, child_is_leaf) = {
+                let NodeRef::ModBranch(branch) = &mut *node_ref else {
+                    unreachable!("We only descend through loaded branches");
+                };
+
+                let (go_right, child) = match branch.key_position(key_hash) {
+                    KeyPosition::Left => (false, &mut branch.left),
+                    KeyPosition::Right => (true, &mut branch.right),
+                    KeyPosition::Adjacent(_) => unreachable!("The key is present"),
+                };
+                Self::load_node(data_store, child)?;
+
+                (go_right, matches!(child, NodeRef::ModLeaf(_)))
+            };
+
+            if child_is_leaf {
+                let NodeRef::ModBranch(parent) =
+                    mem::replace(node_ref, NodeRef::temp_null_stored())
+                else {
+                    unreachable!("We just matched a ModBranch");
+                };
+                let Branch {
+                    left,
+                    right,
+                    mask,
+                    prior_word,
+                    prefix,
+                } = *parent;
+
+                let (leaf, sibling) = if go_right {
+                    (right, left)
                 } else {
-                    unreachable!("prior loop only breaks on a leaf or branch");
+                    (left, right)
+                };
+                let NodeRef::ModLeaf(leaf) = leaf else {
+                    unreachable!("We just loaded the leaf");
+                };
+                debug_assert_eq!(leaf.key_hash, *key_hash);
+
+                let parent = Branch {
+                    left: (),
+                    right: (),
+                    mask,
+                    prior_word,
+                    prefix,
+                };
+                *node_ref =
+                    Self::promote_sibling(data_store, sibling, &parent, grandparent_word_idx)?;
+
+                return Ok(leaf.value);
+            }
+
+            let NodeRef::ModBranch(branch) = node_ref else {
+                unreachable!("We just matched a ModBranch");
+            };
+            grandparent_word_idx = branch.mask.word_idx();
+            node_ref = if go_right {
+                &mut branch.right
+            } else {
+                &mut branch.left
+            };
+        }
+    }
+
+    /// Prepare `sibling` to take the place of its removed `parent`.
+    #[inline]
+    fn promote_sibling(
+        data_store: &S,
+        mut sibling: NodeRef<V, K>,
+        parent: &Branch<()>,
+        grandparent_word_idx: usize,
+    ) -> Result<NodeRef<V, K>, TrieError> {
+        // The sibling already covers the words it needs, no need to load it.
+        if parent.mask.word_idx() == grandparent_word_idx {
+            return Ok(sibling);
+        }
+
+        if let NodeRef::Stored(idx) = sibling {
+            match data_store.get_node(idx).map_err(|e| {
+                error_context(
+                    e,
+                    format_args!("Error at `{}:{}:{}`", file!(), line!(), column!()),
+                )
+            })? {
+                Node::Branch(branch) => {
+                    sibling = NodeRef::ModBranch(Box::new(Branch::from_stored(branch)))
                 }
+                Node::Leaf(_) => return Ok(sibling),
+            }
+        }
+
+        if let NodeRef::ModBranch(branch) = &mut sibling {
+            branch.absorb_parent_prefix(parent, grandparent_word_idx);
+        }
+
+        Ok(sibling)
+    }
+
+    /// Record the branches from `node_ref` down to the leaf at `key_hash` in `path`,
+    /// and the leaf's sibling if it is a branch that `remove` will modify.
+    ///
+    /// Returns the hash of `node_ref`, or `None` if the key is absent.
+    #[inline]
+    fn deletion_path(
+        hasher: &mut impl PortableHasher<32>,
+        data_store: &S,
+        node_ref: &NodeRef<V, K>,
+        (parent_word_idx, parent_bit_idx): (usize, Option<u32>),
+        key_hash: &K,
+        path: &mut Vec<Branch<NodeHash>>,
+        sibling: &mut Option<Branch<NodeHash>>,
+    ) -> Result<Option<NodeHash>, TrieError> {
+        let stored_children: [NodeRef<V, K>; 2];
+        let (branch, left, right) = match node_ref {
+            NodeRef::ModBranch(branch) => {
+                (branch.with_children((), ()), &branch.left, &branch.right)
             }
+            NodeRef::ModLeaf(leaf) => {
+                return Ok((leaf.key_hash == *key_hash).then(|| leaf.hash_leaf(hasher)));
+            }
+            NodeRef::Stored(idx) => match data_store
+                .get_node(*idx)
+                .map_err(|e| error_context(e, "Error in `deletion_path`"))?
+            {
+                Node::Branch(branch) => {
+                    Self::check_stored_branch(branch, *idx, parent_bit_idx, key_bits(key_hash))?;
+                    stored_children = [NodeRef::Stored(branch.left), NodeRef::Stored(branch.right)];
+                    let [left, right] = &stored_children;
+                    (branch.with_children((), ()), left, right)
+                }
+                Node::Leaf(leaf) => {
+                    return Ok((leaf.key_hash == *key_hash).then(|| leaf.hash_leaf(hasher)));
+                }
+            },
+        };
+
+        let go_right = match branch.key_position(key_hash) {
+            KeyPosition::Left => false,
+            KeyPosition::Right => true,
+            KeyPosition::Adjacent(_) => return Ok(None),
+        };
+        let (child, other) = if go_right {
+            (right, left)
+        } else {
+            (left, right)
+        };
+
+        let path_idx = path.len();
+        let other_hash = Self::hash_node(hasher, data_store, other)?;
+        path.push(branch.with_children(other_hash, other_hash));
+
+        let Some(child_hash) = Self::deletion_path(
+            hasher,
+            data_store,
+            child,
+            (branch.mask.word_idx(), Some(branch.mask.bit_idx())),
+            key_hash,
+            path,
+            sibling,
+        )?
+        else {
+            return Ok(None);
+        };
+
+        let node = &mut path[path_idx];
+        if go_right {
+            node.right = child_hash;
+        } else {
+            node.left = child_hash;
+        }
+        let hash = node.hash_branch(hasher, &node.left, &node.right);
+
+        // The child was the leaf, so `remove` will promote `other`.
+        if path.len() == path_idx + 1 && branch.mask.word_idx() != parent_word_idx {
+            *sibling = Self::branch_with_child_hashes(hasher, data_store, other)?;
+        }
+
+        Ok(Some(hash))
+    }
+
+    #[inline]
+    fn hash_node(
+        hasher: &mut impl PortableHasher<32>,
+        data_store: &S,
+        node_ref: &NodeRef<V, K>,
+    ) -> Result<NodeHash, TrieError> {
+        Self::calc_root_hash_node(
+            hasher,
+            data_store,
+            node_ref,
+            &mut |_, _| Ok(()),
+            &mut |_, _, _, _| Ok(()),
+        )
+    }
+
+    /// Returns `node_ref` as a `Branch<NodeHash>`, or `None` if it is a leaf.
+    #[inline]
+    fn branch_with_child_hashes(
+        hasher: &mut impl PortableHasher<32>,
+        data_store: &S,
+        node_ref: &NodeRef<V, K>,
+    ) -> Result<Option<Branch<NodeHash>>, TrieError> {
+        match node_ref {
+            NodeRef::ModBranch(branch) => Ok(Some(branch.with_children(
+                Self::hash_node(hasher, data_store, &branch.left)?,
+                Self::hash_node(hasher, data_store, &branch.right)?,
+            ))),
+            NodeRef::ModLeaf(_) => Ok(None),
+            NodeRef::Stored(idx) => match data_store
+                .get_node(*idx)
+                .map_err(|e| error_context(e, "Error in `branch_with_child_hashes`"))?
+            {
+                Node::Branch(branch) => Ok(Some(branch.with_children(
+                    Self::hash_node(hasher, data_store, &NodeRef::Stored(branch.left))?,
+                    Self::hash_node(hasher, data_store, &NodeRef::Stored(branch.right))?,
+                ))),
+                Node::Leaf(_) => Ok(None),
+            },
         }
     }
 }
 
-impl<Db, V: PortableHash + Clone> Transaction<SnapshotBuilder<Db, V>, V> {
+impl<Db, V: PortableHash + Clone, K: TrieKey> Transaction<SnapshotBuilder<Db, V, K>, V, K> {
     /// An alias for `SnapshotBuilder::new_with_db`.
     ///
     /// Builds a snapshot of the trie before the transaction.
@@ -538,83 +2815,126 @@ impl<Db, V: PortableHash + Clone> Transaction<SnapshotBuilder<Db, V>, V> {
     ///
     /// Note: All operations including get affect the contents of the snapshot.
     #[inline]
-    pub fn build_initial_snapshot(&self) -> Snapshot<V> {
+    pub fn build_initial_snapshot(&self) -> Snapshot<V, K> {
         self.data_store.build_initial_snapshot()
     }
 
     #[inline]
-    pub fn from_snapshot_builder(builder: SnapshotBuilder<Db, V>) -> Self {
+    pub fn from_snapshot_builder(builder: SnapshotBuilder<Db, V, K>) -> Self {
+        let root_hash = stored::OnceCell::new();
+        let _ = root_hash.set(match builder.trie_root() {
+            TrieRoot::Node(_) => TrieRoot::Node(
+                builder
+                    .get_node_hash(0)
+                    .expect("A non-empty builder has a root"),
+            ),
+            TrieRoot::Empty => TrieRoot::Empty,
+        });
+
         Transaction {
             current_root: builder.trie_root(),
             data_store: builder,
+            root_hash,
         }
     }
 }
 
-impl<Db, V: PortableHash + Clone> TryFrom<SnapshotBuilder<Db, V>>
-    for Transaction<SnapshotBuilder<Db, V>, V>
+impl<Db, V: PortableHash + Clone, K: TrieKey> TryFrom<SnapshotBuilder<Db, V, K>>
+    for Transaction<SnapshotBuilder<Db, V, K>, V, K>
 {
     type Error = TrieError;
 
     #[inline]
-    fn try_from(value: SnapshotBuilder<Db, V>) -> Result<Self, Self::Error> {
+    fn try_from(value: SnapshotBuilder<Db, V, K>) -> Result<Self, Self::Error> {
         Ok(Transaction::from_snapshot_builder(value))
     }
 }
 
-impl<'s, V: PortableHash + Clone> Transaction<&'s Snapshot<V>, V> {
+impl<'s, V: PortableHash + Clone, K: TrieKey> Transaction<&'s Snapshot<V, K>, V, K> {
     /// Create a `Transaction` from a borrowed `Snapshot`.
     #[inline]
-    pub fn from_snapshot(snapshot: &'s Snapshot<V>) -> Result<Self, TrieError> {
+    pub fn from_snapshot(snapshot: &'s Snapshot<V, K>) -> Result<Self, TrieError> {
         Ok(Transaction {
             current_root: snapshot.trie_root()?,
             data_store: snapshot,
+            root_hash: stored::OnceCell::new(),
         })
     }
 }
 
-impl<V: PortableHash + Clone> Transaction<Snapshot<V>, V> {
+impl<V: PortableHash + Clone, K: TrieKey> Transaction<Snapshot<V, K>, V, K> {
     /// Create a `Transaction` from a owned `Snapshot`.
     #[inline]
-    pub fn from_snapshot_owned(snapshot: Snapshot<V>) -> Result<Self, TrieError> {
+    pub fn from_snapshot_owned(snapshot: Snapshot<V, K>) -> Result<Self, TrieError> {
+        Ok(Transaction {
+            current_root: snapshot.trie_root()?,
+            data_store: snapshot,
+            root_hash: stored::OnceCell::new(),
+        })
+    }
+}
+
+impl<V: PortableHash + Clone, K: TrieKey> Transaction<Arc<Snapshot<V, K>>, V, K> {
+    /// Create a `Transaction` from a shared `Snapshot`.
+    ///
+    /// With `std` a `Snapshot` is `Sync`, so verifiers on several threads can each hold a `Transaction`
+    /// over the same snapshot without borrowing it from one owner.
+    #[inline]
+    pub fn from_snapshot_shared(snapshot: Arc<Snapshot<V, K>>) -> Result<Self, TrieError> {
         Ok(Transaction {
             current_root: snapshot.trie_root()?,
             data_store: snapshot,
+            root_hash: stored::OnceCell::new(),
         })
     }
 }
 
-impl<'s, V: PortableHash + Clone> TryFrom<&'s Snapshot<V>> for Transaction<&'s Snapshot<V>, V> {
+impl<'s, V: PortableHash + Clone, K: TrieKey> TryFrom<&'s Snapshot<V, K>>
+    for Transaction<&'s Snapshot<V, K>, V, K>
+{
     type Error = TrieError;
 
     #[inline]
-    fn try_from(value: &'s Snapshot<V>) -> Result<Self, Self::Error> {
+    fn try_from(value: &'s Snapshot<V, K>) -> Result<Self, Self::Error> {
         Self::from_snapshot(value)
     }
 }
 
-impl<V: PortableHash + Clone> TryFrom<Snapshot<V>> for Transaction<Snapshot<V>, V> {
+impl<V: PortableHash + Clone, K: TrieKey> TryFrom<Snapshot<V, K>>
+    for Transaction<Snapshot<V, K>, V, K>
+{
     type Error = TrieError;
 
     #[inline]
-    fn try_from(value: Snapshot<V>) -> Result<Self, Self::Error> {
+    fn try_from(value: Snapshot<V, K>) -> Result<Self, Self::Error> {
         Self::from_snapshot_owned(value)
     }
 }
 
-pub enum Entry<'a, V> {
+impl<V: PortableHash + Clone, K: TrieKey> TryFrom<Arc<Snapshot<V, K>>>
+    for Transaction<Arc<Snapshot<V, K>>, V, K>
+{
+    type Error = TrieError;
+
+    #[inline]
+    fn try_from(value: Arc<Snapshot<V, K>>) -> Result<Self, Self::Error> {
+        Self::from_snapshot_shared(value)
+    }
+}
+
+pub enum Entry<'a, V, K = KeyHash> {
     /// A Leaf
-    Occupied(OccupiedEntry<'a, V>),
+    Occupied(OccupiedEntry<'a, V, K>),
     /// The first Branch that proves the key is not in the trie.
-    Vacant(VacantEntry<'a, V>),
-    VacantEmptyTrie(VacantEntryEmptyTrie<'a, V>),
+    Vacant(VacantEntry<'a, V, K>),
+    VacantEmptyTrie(VacantEntryEmptyTrie<'a, V, K>),
 }
 
-impl<'a, V> Entry<'a, V> {
+impl<'a, V: Clone, K: TrieKey> Entry<'a, V, K> {
     #[inline]
     pub fn get(&self) -> Option<&V> {
         match self {
-            Entry::Occupied(OccupiedEntry { leaf }) => Some(&leaf.value),
+            Entry::Occupied(o) => Some(o.get()),
             _ => None,
         }
     }
@@ -622,7 +2942,7 @@ impl<'a, V> Entry<'a, V> {
     #[inline]
     pub fn get_mut(&mut self) -> Option<&mut V> {
         match self {
-            Entry::Occupied(OccupiedEntry { leaf }) => Some(&mut leaf.value),
+            Entry::Occupied(o) => Some(o.get_mut()),
             _ => None,
         }
     }
@@ -630,7 +2950,7 @@ impl<'a, V> Entry<'a, V> {
     #[inline]
     pub fn into_mut(self) -> Option<&'a mut V> {
         match self {
-            Entry::Occupied(OccupiedEntry { leaf }) => Some(&mut leaf.value),
+            Entry::Occupied(o) => Some(o.into_mut()),
             _ => None,
         }
     }
@@ -664,10 +2984,10 @@ impl<'a, V> Entry<'a, V> {
     #[inline]
     pub fn or_insert_with_key<F>(self, default: F) -> &'a mut V
     where
-        F: FnOnce(&KeyHash) -> V,
+        F: FnOnce(&K) -> V,
     {
         match self {
-            Entry::Occupied(o) => &mut o.leaf.value,
+            Entry::Occupied(o) => o.into_mut(),
             Entry::VacantEmptyTrie(entry) => {
                 let value = default(entry.key());
                 entry.insert(value)
@@ -679,10 +2999,30 @@ impl<'a, V> Entry<'a, V> {
         }
     }
 
+    /// Like `or_insert_with`, but also returns the path to the entry's leaf,
+    /// for `Transaction::prove_leaf_path` to prove the leaf once the caller is done with the entry.
+    ///
+    /// The path stays valid until the trie is next modified, it survives a commit.
+    /// Emitting an event with a proof against the post-state root then needs no second search for the key.
+    #[inline]
+    pub fn or_insert_with_proof<F>(self, default: F) -> (&'a mut V, LeafPath<K>)
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(o) => {
+                let path = o.path();
+                (o.into_mut(), path)
+            }
+            Entry::VacantEmptyTrie(entry) => entry.insert_with_path(default()),
+            Entry::Vacant(entry) => entry.insert_with_path(default()),
+        }
+    }
+
     #[inline]
-    pub fn key(&self) -> &KeyHash {
+    pub fn key(&self) -> &K {
         match self {
-            Entry::Occupied(OccupiedEntry { leaf }) => &leaf.key_hash,
+            Entry::Occupied(o) => o.key(),
             Entry::Vacant(VacantEntry { key_hash, .. })
             | Entry::VacantEmptyTrie(VacantEntryEmptyTrie { key_hash, .. }) => key_hash,
         }
@@ -693,8 +3033,8 @@ impl<'a, V> Entry<'a, V> {
         F: FnOnce(&mut V),
     {
         match self {
-            Entry::Occupied(OccupiedEntry { ref mut leaf }) => {
-                f(&mut leaf.value);
+            Entry::Occupied(ref mut o) => {
+                f(o.get_mut());
                 self
             }
             _ => self,
@@ -711,115 +3051,218 @@ impl<'a, V> Entry<'a, V> {
     }
 }
 
-pub struct OccupiedEntry<'a, V> {
+pub struct OccupiedEntry<'a, V, K = KeyHash> {
     /// This always points to a Leaf.
     /// It may be a ModLeaf or a stored Leaf.
-    leaf: &'a mut Leaf<V>,
+    node_ref: &'a mut NodeRef<V, K>,
+    /// The leaf at `node_ref` while it is stored.
+    /// It is cloned into a ModLeaf the first time the entry is mutably borrowed or modified.
+    stored: Option<&'a Leaf<V, K>>,
+    /// Whether the path from the root to the leaf turns right at each branch.
+    turns: Vec<bool>,
 }
 
-impl<'a, V> OccupiedEntry<'a, V> {
+impl<'a, V: Clone, K: TrieKey> OccupiedEntry<'a, V, K> {
     #[inline]
-    pub fn key(&self) -> &KeyHash {
-        &self.leaf.key_hash
+    pub fn key(&self) -> &K {
+        &self.leaf().key_hash
     }
 
     #[inline]
     pub fn get(&self) -> &V {
-        &self.leaf.value
+        &self.leaf().value
     }
 
     #[inline]
     pub fn get_mut(&mut self) -> &mut V {
-        &mut self.leaf.value
+        if let Some(stored) = self.stored.take() {
+            *self.node_ref = NodeRef::ModLeaf(Box::new(stored.clone()));
+        }
+        &mut mod_leaf(self.node_ref).value
     }
 
     #[inline]
     pub fn into_mut(self) -> &'a mut V {
-        &mut self.leaf.value
+        let OccupiedEntry {
+            node_ref, stored, ..
+        } = self;
+        if let Some(stored) = stored {
+            *node_ref = NodeRef::ModLeaf(Box::new(stored.clone()));
+        }
+        &mut mod_leaf(node_ref).value
     }
 
     #[inline]
     pub fn insert(&mut self, value: V) -> V {
-        mem::replace(&mut self.leaf.value, value)
+        match self.stored.take() {
+            // Replace the stored leaf without cloning its value into the trie first.
+            Some(stored) => {
+                *self.node_ref = NodeRef::ModLeaf(Box::new(Leaf {
+                    key_hash: stored.key_hash,
+                    value,
+                }));
+                stored.value.clone()
+            }
+            None => mem::replace(&mut mod_leaf(self.node_ref).value, value),
+        }
+    }
+
+    /// The path to the entry's leaf, see `Entry::or_insert_with_proof`.
+    #[inline]
+    pub fn path(&self) -> LeafPath<K> {
+        LeafPath {
+            key_hash: self.leaf().key_hash,
+            turns: self.turns.clone(),
+        }
+    }
+
+    #[inline]
+    fn leaf(&self) -> &Leaf<V, K> {
+        match (self.stored, &*self.node_ref) {
+            (Some(stored), _) => stored,
+            (None, NodeRef::ModLeaf(leaf)) => leaf,
+            _ => unreachable!("An occupied entry's leaf is a ModLeaf once it is no longer stored"),
+        }
+    }
+}
+
+/// The leaf of an `OccupiedEntry` that is no longer stored.
+#[inline]
+fn mod_leaf<V, K>(node_ref: &mut NodeRef<V, K>) -> &mut Leaf<V, K> {
+    match node_ref {
+        NodeRef::ModLeaf(leaf) => leaf,
+        _ => unreachable!("An occupied entry's leaf is a ModLeaf once it is no longer stored"),
     }
 }
 
-pub struct VacantEntry<'a, V> {
-    parent: &'a mut NodeRef<V>,
-    key_hash: KeyHash,
+/// The depth of a `NodeRef::temp_null_stored` placeholder on the path from `node_ref` to `key_hash`, or beside it.
+///
+/// `calc_root_hash` refuses to hash placeholders in all builds,
+/// debug builds look for them after each operation to catch the one that leaked them.
+#[inline]
+fn placeholder_on_path<V, K: TrieKey>(mut node_ref: &NodeRef<V, K>, key_hash: &K) -> Option<usize> {
+    let mut depth = 0;
+    loop {
+        let NodeRef::ModBranch(branch) = node_ref else {
+            return node_ref.is_temp_null_stored().then_some(depth);
+        };
+        if branch.left.is_temp_null_stored() || branch.right.is_temp_null_stored() {
+            return Some(depth + 1);
+        }
+
+        node_ref = match branch.key_position(key_hash) {
+            KeyPosition::Left => &branch.left,
+            KeyPosition::Right => &branch.right,
+            KeyPosition::Adjacent(_) => return None,
+        };
+        depth += 1;
+    }
+}
+
+pub struct VacantEntry<'a, V, K = KeyHash> {
+    parent: &'a mut NodeRef<V, K>,
+    key_hash: K,
     key_position: KeyPositionAdjacent,
+    /// The word index of the branch above `parent`, or 0 if `parent` is the root.
+    parent_word_idx: usize,
+    /// Whether the path from the root to `parent` turns right at each branch.
+    turns: Vec<bool>,
 }
 
-impl<'a, V> VacantEntry<'a, V> {
+impl<'a, V, K: TrieKey> VacantEntry<'a, V, K> {
     #[inline]
-    pub fn key(&self) -> &KeyHash {
+    pub fn key(&self) -> &K {
         &self.key_hash
     }
 
     #[inline]
-    pub fn into_key(self) -> KeyHash {
+    pub fn into_key(self) -> K {
         self.key_hash
     }
 
     #[inline]
     pub fn insert(self, value: V) -> &'a mut V {
+        self.insert_leaf(value).0
+    }
+
+    /// Like `insert`, but also returns the path to the new leaf, see `Entry::or_insert_with_proof`.
+    #[inline]
+    pub fn insert_with_path(mut self, value: V) -> (&'a mut V, LeafPath<K>) {
+        let mut turns = mem::take(&mut self.turns);
+        let key_hash = self.key_hash;
+
+        let (value, new_leaf_is_right) = self.insert_leaf(value);
+        turns.push(new_leaf_is_right);
+
+        (value, LeafPath { key_hash, turns })
+    }
+
+    /// Insert the leaf under a new branch in place of `parent`,
+    /// returning its value and whether it is the new branch's right child.
+    #[inline]
+    fn insert_leaf(self, value: V) -> (&'a mut V, bool) {
         let VacantEntry {
             parent,
             key_hash,
             key_position,
+            parent_word_idx,
+            ..
         } = self;
-        if let NodeRef::ModBranch(branch) = parent {
-            let leaf =
-                branch.new_adjacent_leaf_ret(key_position, Box::new(Leaf { key_hash, value }));
-            return &mut leaf.value;
-        };
-
-        let owned_parent = mem::replace(parent, NodeRef::temp_null_stored());
-        match owned_parent {
+        let leaf = Box::new(Leaf { key_hash, value });
+        match mem::replace(parent, NodeRef::temp_null_stored()) {
+            NodeRef::ModBranch(mut branch) => {
+                branch.new_adjacent_leaf(key_position, leaf);
+                *parent = NodeRef::ModBranch(branch);
+            }
             NodeRef::ModLeaf(old_leaf) => {
-                let (new_branch, new_leaf_is_right) =
-                    Branch::new_from_leafs(0, old_leaf, Box::new(Leaf { key_hash, value }));
-
+                let (new_branch, _) = Branch::new_from_leafs(parent_word_idx, old_leaf, leaf)
+                    .unwrap_or_else(|e| {
+                        unreachable!(
+                            "`entry` checked the leaf is on the path to a different key: {e}"
+                        )
+                    });
                 *parent = NodeRef::ModBranch(new_branch);
-
-                match parent {
-                    NodeRef::ModBranch(branch) => {
-                        let leaf = if new_leaf_is_right {
-                            &mut branch.right
-                        } else {
-                            &mut branch.left
-                        };
-
-                        match leaf {
-                            NodeRef::ModLeaf(ref mut leaf) => &mut leaf.value,
-                            _ => {
-                                unreachable!("new_from_leafs returns the location of the new leaf")
-                            }
-                        }
-                    }
-                    _ => unreachable!("new_from_leafs returns a ModBranch"),
-                }
             }
             _ => {
                 unreachable!("`entry` ensures VacantEntry should never point to a Stored node")
             }
         }
+        // The entry cannot return an error, and only holds the subtree it modified.
+        debug_assert_eq!(
+            placeholder_on_path(parent, &key_hash),
+            None,
+            "a placeholder node was left below a vacant entry for {key_hash:?}"
+        );
+
+        let NodeRef::ModBranch(branch) = parent else {
+            unreachable!("The new leaf is under a new ModBranch");
+        };
+        let new_leaf_is_right = branch.key_position(&key_hash) == KeyPosition::Right;
+        let leaf = if new_leaf_is_right {
+            &mut branch.right
+        } else {
+            &mut branch.left
+        };
+        match leaf {
+            NodeRef::ModLeaf(leaf) => (&mut leaf.value, new_leaf_is_right),
+            _ => unreachable!("The new leaf is a child of the new branch"),
+        }
     }
 }
 
-pub struct VacantEntryEmptyTrie<'a, V> {
-    root: &'a mut TrieRoot<NodeRef<V>>,
-    key_hash: KeyHash,
+pub struct VacantEntryEmptyTrie<'a, V, K = KeyHash> {
+    root: &'a mut TrieRoot<NodeRef<V, K>>,
+    key_hash: K,
 }
 
-impl<'a, V> VacantEntryEmptyTrie<'a, V> {
+impl<'a, V, K: TrieKey> VacantEntryEmptyTrie<'a, V, K> {
     #[inline]
-    pub fn key(&self) -> &KeyHash {
+    pub fn key(&self) -> &K {
         &self.key_hash
     }
 
     #[inline]
-    pub fn into_key(self) -> KeyHash {
+    pub fn into_key(self) -> K {
         self.key_hash
     }
 
@@ -833,4 +3276,17 @@ impl<'a, V> VacantEntryEmptyTrie<'a, V> {
             _ => unreachable!("We just set root to a ModLeaf"),
         }
     }
+
+    /// Like `insert`, but also returns the path to the new leaf, the root, see `Entry::or_insert_with_proof`.
+    #[inline]
+    pub fn insert_with_path(self, value: V) -> (&'a mut V, LeafPath<K>) {
+        let key_hash = self.key_hash;
+        (
+            self.insert(value),
+            LeafPath {
+                key_hash,
+                turns: Vec::new(),
+            },
+        )
+    }
 }
@@ -1,7 +1,19 @@
-use alloc::boxed::Box;
-use core::{fmt, iter, mem};
+use alloc::{boxed::Box, format, vec::Vec};
+use core::{fmt, iter, mem, ops::RangeInclusive};
 
-use crate::{hash::PortableHasher, stored, KeyHash, NodeHash, PortableHash, PortableUpdate};
+use crate::{
+    counted, hash::PortableHasher, spec, stored, KeyHash, NodeHash, PortableHash, PortableUpdate,
+    TrieError, TrieKey,
+};
+
+/// The widest key a trie supports, a branch discriminates on a bit below this.
+pub(crate) const MAX_KEY_BITS: u32 = 256;
+
+/// The number of bits in `key_hash`, the bound on the discriminant bits along its path.
+#[inline(always)]
+pub(crate) fn key_bits<K: TrieKey>(key_hash: &K) -> u32 {
+    u32::try_from(key_hash.words().len() * 32).unwrap_or(u32::MAX)
+}
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
@@ -11,6 +23,102 @@ pub enum TrieRoot<T> {
     Node(T),
 }
 
+impl TrieRoot<NodeHash> {
+    /// Compare two roots in constant time.
+    ///
+    /// Whether a root is empty is not treated as secret,
+    /// but the comparison does not short circuit on it.
+    #[cfg(feature = "subtle")]
+    #[inline]
+    pub fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        use subtle::ConstantTimeEq;
+
+        let empty_hash = NodeHash::new([0; 32]);
+        let (self_tag, self_hash) = match self {
+            TrieRoot::Empty => (0u8, &empty_hash),
+            TrieRoot::Node(hash) => (1u8, hash),
+        };
+        let (other_tag, other_hash) = match other {
+            TrieRoot::Empty => (0u8, &empty_hash),
+            TrieRoot::Node(hash) => (1u8, hash),
+        };
+
+        self_tag.ct_eq(&other_tag) & self_hash.ct_eq(other_hash)
+    }
+
+    /// The equality used by verification helpers.
+    /// Constant time when the `subtle` feature is enabled.
+    #[inline]
+    pub(crate) fn verify_eq(&self, other: &Self) -> bool {
+        #[cfg(feature = "subtle")]
+        return self.ct_eq(other).into();
+
+        #[cfg(not(feature = "subtle"))]
+        return self == other;
+    }
+
+    /// The first `N` bytes of the root hash, for contracts that only store a truncated commitment.
+    ///
+    /// A truncated root is a weaker commitment than the full root.
+    /// Against an `N` byte commitment, forging a trie that matches a root someone else committed to
+    /// takes about `2^(8 * N)` hashes, 2^160 for 20 bytes.
+    /// But a prover that picks both tries, and gets one of them committed,
+    /// only needs a collision, which takes about `2^(4 * N)` hashes by the birthday bound, 2^80 for 20 bytes.
+    /// Only truncate when no untrusted party chooses the committed state, or when that margin is acceptable.
+    ///
+    /// The empty trie stays `TrieRoot::Empty`.
+    #[inline]
+    pub fn truncated<const N: usize>(&self) -> TrieRoot<[u8; N]> {
+        const { assert!(N > 0 && N <= 32, "A truncated root has 1 to 32 bytes") };
+
+        match self {
+            TrieRoot::Empty => TrieRoot::Empty,
+            TrieRoot::Node(hash) => {
+                let mut bytes = [0; N];
+                bytes.copy_from_slice(&hash.bytes[..N]);
+                TrieRoot::Node(bytes)
+            }
+        }
+    }
+
+    /// Returns true if this root truncates to `commitment`, see `truncated` for the security trade-off.
+    /// Constant time when the `subtle` feature is enabled.
+    #[inline]
+    pub fn matches_truncated<const N: usize>(&self, commitment: &TrieRoot<[u8; N]>) -> bool {
+        let truncated = self.truncated::<N>();
+
+        #[cfg(feature = "subtle")]
+        {
+            use subtle::ConstantTimeEq;
+
+            let empty = [0; N];
+            let (self_tag, self_bytes) = match &truncated {
+                TrieRoot::Empty => (0u8, &empty),
+                TrieRoot::Node(bytes) => (1u8, bytes),
+            };
+            let (other_tag, other_bytes) = match commitment {
+                TrieRoot::Empty => (0u8, &empty),
+                TrieRoot::Node(bytes) => (1u8, bytes),
+            };
+
+            (self_tag.ct_eq(&other_tag) & self_bytes[..].ct_eq(&other_bytes[..])).into()
+        }
+
+        #[cfg(not(feature = "subtle"))]
+        {
+            truncated == *commitment
+        }
+    }
+}
+
+#[cfg(feature = "subtle")]
+impl subtle::ConstantTimeEq for TrieRoot<NodeHash> {
+    #[inline]
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        TrieRoot::ct_eq(self, other)
+    }
+}
+
 impl From<NodeHash> for TrieRoot<NodeHash> {
     #[inline]
     fn from(hash: NodeHash) -> Self {
@@ -66,6 +174,16 @@ pub enum Node<B, L> {
     Leaf(L),
 }
 
+impl<B, L> Node<B, L> {
+    #[inline]
+    pub fn as_ref(&self) -> Node<&B, &L> {
+        match self {
+            Node::Branch(branch) => Node::Branch(branch),
+            Node::Leaf(leaf) => Node::Leaf(leaf),
+        }
+    }
+}
+
 /// A Node representation which may be partially modified.
 /// `ModBranch` and `ModLeaf` are used to represent a node which has been modified in the current transaction.
 /// `Stored` is used to represent an unmodified node stored in the database.
@@ -74,20 +192,28 @@ pub enum Node<B, L> {
 /// When executing against a `SnapshotBuilder`, it's a reference to a `NodeHash`,
 /// which can in turn be used to retrieve the `Node`.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum NodeRef<V> {
+pub enum NodeRef<V, K = KeyHash> {
     ModBranch(Box<Branch<Self>>),
-    ModLeaf(Box<Leaf<V>>),
+    ModLeaf(Box<Leaf<V, K>>),
     Stored(stored::Idx),
 }
 
-impl<V> NodeRef<V> {
+impl<V, K> NodeRef<V, K> {
     #[inline(always)]
     pub fn temp_null_stored() -> Self {
-        NodeRef::Stored(u32::MAX)
+        NodeRef::Stored(stored::Idx::MAX)
+    }
+
+    /// Returns true if this is the placeholder made by `temp_null_stored`.
+    /// A placeholder must never be reachable once an operation returns.
+    #[inline(always)]
+    pub fn is_temp_null_stored(&self) -> bool {
+        matches!(self, NodeRef::Stored(stored::Idx::MAX))
     }
 }
 
-impl<V> fmt::Debug for NodeRef<V> {
+impl<V, K: fmt::Debug> fmt::Debug for NodeRef<V, K> {
+    #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::ModBranch(b) => f.debug_tuple("ModBranch").field(b).finish(),
@@ -97,42 +223,42 @@ impl<V> fmt::Debug for NodeRef<V> {
     }
 }
 
-impl<V> From<Box<Branch<NodeRef<V>>>> for NodeRef<V> {
+impl<V, K> From<Box<Branch<NodeRef<V, K>>>> for NodeRef<V, K> {
     #[inline]
-    fn from(branch: Box<Branch<NodeRef<V>>>) -> Self {
+    fn from(branch: Box<Branch<NodeRef<V, K>>>) -> Self {
         NodeRef::ModBranch(branch)
     }
 }
 
-impl<V> From<Box<Leaf<V>>> for NodeRef<V> {
+impl<V, K> From<Box<Leaf<V, K>>> for NodeRef<V, K> {
     #[inline]
-    fn from(leaf: Box<Leaf<V>>) -> Self {
+    fn from(leaf: Box<Leaf<V, K>>) -> Self {
         NodeRef::ModLeaf(leaf)
     }
 }
 
-pub struct StoredLeafRef<'s, V> {
-    leaf: &'s Leaf<V>,
+pub struct StoredLeafRef<'s, V, K = KeyHash> {
+    leaf: &'s Leaf<V, K>,
     stored: stored::Idx,
 }
 
-impl<'s, V> From<StoredLeafRef<'s, V>> for NodeRef<V> {
+impl<'s, V, K> From<StoredLeafRef<'s, V, K>> for NodeRef<V, K> {
     #[inline]
-    fn from(leaf: StoredLeafRef<'s, V>) -> Self {
+    fn from(leaf: StoredLeafRef<'s, V, K>) -> Self {
         NodeRef::Stored(leaf.stored)
     }
 }
 
-impl<'s, V> AsRef<Leaf<V>> for StoredLeafRef<'s, V> {
+impl<'s, V, K> AsRef<Leaf<V, K>> for StoredLeafRef<'s, V, K> {
     #[inline]
-    fn as_ref(&self) -> &Leaf<V> {
+    fn as_ref(&self) -> &Leaf<V, K> {
         self.leaf
     }
 }
 
-impl<'s, V> StoredLeafRef<'s, V> {
+impl<'s, V, K> StoredLeafRef<'s, V, K> {
     #[inline]
-    pub fn new(leaf: &'s Leaf<V>, stored: stored::Idx) -> Self {
+    pub fn new(leaf: &'s Leaf<V, K>, stored: stored::Idx) -> Self {
         Self { leaf, stored }
     }
 }
@@ -148,6 +274,7 @@ pub struct BranchMask {
 }
 
 impl BranchMask {
+    #[inline]
     pub const fn new(word_idx: u32, a: u32, b: u32) -> Self {
         Self::new_inner(word_idx, a, a ^ b)
     }
@@ -189,6 +316,18 @@ impl BranchMask {
         (hash_segment & self.prefix_discriminant_mask()) == self.right_prefix()
     }
 
+    /// The index of the discriminant bit in the key.
+    #[inline(always)]
+    pub const fn bit_idx(&self) -> u32 {
+        self.bit_idx
+    }
+
+    /// The bits of the discriminant word below the discriminant bit, shared by both children.
+    #[inline(always)]
+    pub const fn left_prefix(&self) -> u32 {
+        self.left_prefix
+    }
+
     #[inline(always)]
     pub const fn word_idx(&self) -> usize {
         (self.bit_idx / 32) as usize
@@ -229,6 +368,114 @@ impl BranchMask {
     pub const fn trailing_bits_mask(&self) -> u32 {
         u32::MAX << (self.relative_bit_idx() + 1)
     }
+
+    /// Check that a branch with this mask, `prior_word` and `prefix` can be a child of a branch with `parent` mask.
+    ///
+    /// - The discriminant bit comes after the parent's.
+    /// - `prefix` covers exactly the words from the parent's word to the word before `prior_word`.
+    /// - The bits of the parent's word above its discriminant bit,
+    ///   stored in `left_prefix`, `prior_word` or `prefix` depending on how far below the parent this branch is,
+    ///   lead to one of the parent's children.
+    #[inline]
+    pub fn is_valid_child_of(&self, parent: &BranchMask, prior_word: u32, prefix: &[u32]) -> bool {
+        let word_idx = self.word_idx();
+        let parent_word_idx = parent.word_idx();
+
+        if self.bit_idx <= parent.bit_idx
+            || prefix.len() != word_idx.saturating_sub(parent_word_idx + 1)
+        {
+            return false;
+        }
+
+        let parent_word = if word_idx == parent_word_idx {
+            self.left_prefix
+        } else if word_idx == parent_word_idx + 1 {
+            prior_word
+        } else {
+            prefix[0]
+        };
+
+        parent.is_left_descendant(parent_word) || parent.is_right_descendant(parent_word)
+    }
+}
+
+/// Key prefixes, for routing keys to shards and bounding ranges.
+///
+/// The first `len` bits of a key are bits `0..len` as numbered by `KeyHash::word_and_bit`,
+/// the order branches discriminate on them, so a prefix is the low bits of the first words.
+/// The keys starting with a prefix are contiguous in trie order, from `prefix_min` to `prefix_max`.
+///
+/// The methods panic if `len` is more than 256.
+impl KeyHash {
+    /// The bits of word `word_idx` among the first `len` bits of a key.
+    #[inline(always)]
+    const fn prefix_word_mask(word_idx: usize, len: u32) -> u32 {
+        assert!(len <= 256, "A key has 256 bits");
+        let (len_word_idx, len_bit) = KeyHash::word_and_bit(len);
+        if word_idx < len_word_idx {
+            u32::MAX
+        } else if word_idx == len_word_idx {
+            (1 << len_bit) - 1
+        } else {
+            0
+        }
+    }
+
+    /// This key with its first `len` bits replaced by those of `prefix`.
+    #[inline]
+    pub const fn with_prefix_bits(&self, prefix: &KeyHash, len: u32) -> KeyHash {
+        let mut words = self.0;
+        let mut i = 0;
+        while i < words.len() {
+            let mask = Self::prefix_word_mask(i, len);
+            words[i] = (words[i] & !mask) | (prefix.0[i] & mask);
+            i += 1;
+        }
+        KeyHash(words)
+    }
+
+    /// Returns true if the first `len` bits of this key are those of `prefix`.
+    #[inline]
+    pub const fn matches_prefix(&self, prefix: &KeyHash, len: u32) -> bool {
+        let mut i = 0;
+        while i < self.0.len() {
+            if (self.0[i] ^ prefix.0[i]) & Self::prefix_word_mask(i, len) != 0 {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    /// The first key in trie order starting with the first `len` bits of this key.
+    #[inline]
+    pub const fn prefix_min(&self, len: u32) -> KeyHash {
+        let mut words = self.0;
+        let mut i = 0;
+        while i < words.len() {
+            words[i] &= Self::prefix_word_mask(i, len);
+            i += 1;
+        }
+        KeyHash(words)
+    }
+
+    /// The last key in trie order starting with the first `len` bits of this key.
+    #[inline]
+    pub const fn prefix_max(&self, len: u32) -> KeyHash {
+        let mut words = self.0;
+        let mut i = 0;
+        while i < words.len() {
+            words[i] |= !Self::prefix_word_mask(i, len);
+            i += 1;
+        }
+        KeyHash(words)
+    }
+
+    /// Every key starting with the first `len` bits of this key, for `Transaction::range_get` and `prove_range`.
+    #[inline]
+    pub const fn prefix_range(&self, len: u32) -> RangeInclusive<KeyHash> {
+        RangeInclusive::new(self.prefix_min(len), self.prefix_max(len))
+    }
 }
 
 #[cfg(all(feature = "std", test))]
@@ -261,6 +508,171 @@ mod tests {
         }
 
     }
+
+    proptest! {
+        #[test]
+        fn test_hash_branch_matches_field_by_field(
+            word_idx in 0u32..8,
+            a: u32,
+            b: u32,
+            prior_word: u32,
+            prefix in prop::collection::vec(any::<u32>(), 0..12),
+            left: [u8; 32],
+            right: [u8; 32],
+        ) {
+            use crate::DigestHasher;
+            use sha2::Sha256;
+
+            let branch = Branch {
+                left: (),
+                right: (),
+                mask: BranchMask::new(word_idx, a, b),
+                prior_word,
+                prefix: prefix.clone().into_boxed_slice(),
+            };
+
+            let hasher = &mut DigestHasher::<Sha256>::default();
+            let hash = branch.hash_branch(hasher, &NodeHash::new(left), &NodeHash::new(right));
+
+            hasher.portable_update(left);
+            hasher.portable_update(right);
+            hasher.portable_update(branch.mask.bit_idx.to_le_bytes());
+            hasher.portable_update(branch.mask.left_prefix.to_le_bytes());
+            hasher.portable_update(prior_word.to_le_bytes());
+            prefix.iter().for_each(|word| hasher.portable_update(word.to_le_bytes()));
+            let expected: [u8; 32] = hasher.finalize_reset();
+
+            prop_assert_eq!(hash, NodeHash::new(expected));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_key_bits_match_key_position(a: [u32; 8], b: [u32; 8]) {
+            let (a, b) = (KeyHash(a), KeyHash(b));
+            prop_assume!(a != b);
+
+            let bit_idx = iter::zip(a.bits(), b.bits()).position(|(a, b)| a != b).unwrap() as u32;
+            let (word_idx, bit) = KeyHash::word_and_bit(bit_idx);
+            prop_assert_eq!((a.0[word_idx] ^ b.0[word_idx]).trailing_zeros(), bit);
+
+            // The root branch over `a` and `b`.
+            let branch = Branch {
+                left: (),
+                right: (),
+                mask: BranchMask::new(word_idx as u32, a.0[word_idx], b.0[word_idx]),
+                prior_word: if word_idx == 0 { 0 } else { a.0[word_idx - 1] },
+                prefix: a.0[..word_idx.saturating_sub(1)].into(),
+            };
+            prop_assert_eq!(branch.mask.bit_idx(), bit_idx);
+
+            for key in [a, b] {
+                let expected = if key.bit(bit_idx) { KeyPosition::Right } else { KeyPosition::Left };
+                prop_assert_eq!(branch.key_position(&key), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_bits_order() {
+        let key = KeyHash([0b10, 0, 0, 0, 0, 0, 0, 1 << 31]);
+        let set: Vec<_> = key
+            .bits()
+            .enumerate()
+            .filter(|(_, bit)| *bit)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(set, [1, 255]);
+        assert_eq!(KeyHash::word_and_bit(255), (7, 31));
+    }
+
+    #[test]
+    fn test_leaked_placeholder_is_an_error() {
+        use crate::{
+            stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+            DigestHasher, LeakedPlaceholder, Transaction,
+        };
+        use sha2::Sha256;
+
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let left = KeyHash([0; 8]);
+        let right = KeyHash([1, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+        txn.insert(&left, 0).unwrap();
+        txn.insert(&right, 1).unwrap();
+
+        // Simulate a bug that leaves a placeholder behind.
+        let TrieRoot::Node(NodeRef::ModBranch(root)) = &mut txn.current_root else {
+            panic!("Two keys make a branch");
+        };
+        root.left = NodeRef::temp_null_stored();
+
+        let err = txn.calc_root_hash(hasher).unwrap_err();
+        let leak = err.downcast_source::<LeakedPlaceholder>().unwrap();
+        assert_eq!(leak.key, None);
+
+        if cfg!(debug_assertions) {
+            let err = txn.insert(&right, 2).unwrap_err();
+            let leak = err.downcast_source::<LeakedPlaceholder>().unwrap();
+            assert_eq!(leak.key.as_deref(), Some(&right.0[..]));
+            assert_eq!(leak.depth, Some(1));
+        }
+    }
+
+    #[test]
+    fn test_branch_invariants() {
+        use crate::{
+            stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+            Transaction,
+        };
+
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+        txn.insert(&KeyHash([0; 8]), 0).unwrap();
+        txn.insert(&KeyHash([1, 0, 0, 0, 0, 0, 0, 0]), 1).unwrap();
+        txn.insert(&KeyHash([0, 0, 1, 0, 0, 0, 0, 0]), 2).unwrap();
+
+        let TrieRoot::Node(NodeRef::ModBranch(root)) = &txn.current_root else {
+            panic!("Three keys make a branch");
+        };
+        let NodeRef::ModBranch(child) = &root.left else {
+            panic!("The keys starting with 0 share a branch");
+        };
+
+        assert_eq!(root.check_invariants(), Ok(()));
+        assert_eq!(child.check_invariants(), Ok(()));
+        assert_eq!(child.mask.word_idx(), 2);
+        assert_eq!(&*child.prefix, &[0]);
+        assert!(child
+            .mask
+            .is_valid_child_of(&root.mask, child.prior_word, &child.prefix));
+
+        // The parent's word is missing from the prefix.
+        assert!(!child
+            .mask
+            .is_valid_child_of(&root.mask, child.prior_word, &[]));
+        // A branch is not its own child.
+        assert!(!root.mask.is_valid_child_of(&root.mask, 0, &[]));
+        // The parent's word does not lead to either of its children.
+        let parent = BranchMask::new(0, 0b00, 0b10);
+        assert!(!child
+            .mask
+            .is_valid_child_of(&parent, child.prior_word, &[1]));
+
+        let mut bad = child.with_children((), ());
+        bad.mask.left_prefix = u32::MAX;
+        assert!(bad.check_invariants().is_err());
+
+        let mut bad = root.with_children((), ());
+        bad.prior_word = 1;
+        assert!(bad.check_invariants().is_err());
+
+        let mut bad = root.with_children((), ());
+        bad.prefix = [0].into();
+        assert!(bad.check_invariants().is_err());
+    }
 }
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -273,8 +685,14 @@ pub struct Branch<NR> {
     /// Common to both children.
     /// Will be 0 if this node is the root.
     pub prior_word: u32,
-    /// The the segment of the hash key from the parent branch to `prior_word`.
-    /// Will be empty if the parent_branch.mask.bit_idx / 32 ==  self.mask.bit_idx / 32.
+    /// The segment of the hash key from the parent branch's word to `prior_word`.
+    /// That is the words `parent_branch.mask.bit_idx / 32..(self.mask.bit_idx / 32) - 1`,
+    /// or `0..(self.mask.bit_idx / 32) - 1` for the root.
+    /// Will be empty if the parent_branch.mask.bit_idx / 32 + 1 >=  self.mask.bit_idx / 32.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::spec::deserialize_prefix")
+    )]
     pub prefix: Box<[u32]>,
 }
 
@@ -310,43 +728,328 @@ pub enum KeyPositionAdjacent {
     PrefixVec(usize),
 }
 
-impl<NR> Branch<NR> {
-    /// Returns the position of the key relative to the branch.
+/// Where a key falls in trie order relative to the keys under a branch.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum KeySide {
+    /// The key comes before every key under the branch.
+    Before,
+    /// The key would be under the left child.
+    Left,
+    /// The key would be under the right child.
+    Right,
+    /// The key comes after every key under the branch.
+    After,
+}
+
+/// Where the keys starting with a prefix fall relative to a branch, see `Branch::prefix_side`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PrefixSide {
+    /// The branch discriminates on a bit of the prefix, and its keys with the prefix are under this child.
+    Descend { go_right: bool },
+    /// Every key under the branch starts with the prefix.
+    Inside,
+    /// No key under the branch starts with the prefix.
+    Outside,
+}
+
+/// Which neighbor of a key in trie order to search for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Neighbor {
+    /// The first key after it.
+    Next,
+    /// The last key before it.
+    Prev,
+}
+
+impl Neighbor {
+    /// Returns true if `candidate` is on the searched side of `key_hash`.
+    #[inline(always)]
+    pub fn accepts<K: TrieKey>(self, candidate: &K, key_hash: &K) -> bool {
+        let ordering = candidate.cmp_trie_order(key_hash);
+        match self {
+            Neighbor::Next => ordering.is_gt(),
+            Neighbor::Prev => ordering.is_lt(),
+        }
+    }
+
+    /// The child holding the first keys in the search direction.
     #[inline(always)]
-    pub fn key_position(&self, key_hash: &KeyHash) -> KeyPosition {
-        let word_idx = self.mask.bit_idx as usize / 32;
-        debug_assert!(word_idx < 8);
+    pub fn first_child<T>(self, left: T, right: T) -> T {
+        match self {
+            Neighbor::Next => left,
+            Neighbor::Prev => right,
+        }
+    }
+}
 
-        debug_assert!(self.prefix.len() <= word_idx);
-        let prefix_offset = word_idx.saturating_sub(self.prefix.len() + 1);
+/// Where the neighbor of a key is under a branch, from `Branch::neighbor_search`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum NeighborSearch<T> {
+    /// Every key under the branch is on the wrong side of the key.
+    Absent,
+    /// Every key under the branch is on the searched side of the key,
+    /// the neighbor is the first of them.
+    First,
+    /// Search under `descend`, then take the first key under `fallback` if there is one.
+    Descend { descend: T, fallback: Option<T> },
+}
 
-        let prefix_diff = iter::zip(
-            self.prefix.iter(),
-            key_hash.0.iter().enumerate().skip(prefix_offset),
-        )
-        .find(|(branch_word, (_, key_word))| branch_word != key_word);
+/// An inclusive range of keys in trie order, see `TrieKey::cmp_trie_order`.
+/// A `None` bound is already known to be satisfied by every key in the current subtree.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct KeyRange<'a, K> {
+    pub start: Option<&'a K>,
+    pub end: Option<&'a K>,
+}
 
-        if let Some((_, (idx, _))) = prefix_diff {
-            return KeyPosition::Adjacent(KeyPositionAdjacent::PrefixVec(idx));
+impl<'a, K: TrieKey> KeyRange<'a, K> {
+    #[inline]
+    pub fn contains(&self, key_hash: &K) -> bool {
+        self.start
+            .is_none_or(|start| start.cmp_trie_order(key_hash).is_le())
+            && self
+                .end
+                .is_none_or(|end| key_hash.cmp_trie_order(end).is_le())
+    }
+
+    /// Split the range between the children of `branch`.
+    /// Returns `None` for a child whose keys are all outside the range.
+    #[inline]
+    pub fn split<NR>(&self, branch: &Branch<NR>) -> (Option<Self>, Option<Self>) {
+        let mut left = Some(*self);
+        let mut right = Some(*self);
+
+        if let Some(start) = self.start {
+            match branch.key_side(start) {
+                KeySide::Before => {
+                    left = left.map(|r| r.with_start(None));
+                    right = right.map(|r| r.with_start(None));
+                }
+                KeySide::Left => right = right.map(|r| r.with_start(None)),
+                KeySide::Right => left = None,
+                KeySide::After => return (None, None),
+            }
         }
 
-        // If sub wraps around to the last word, the prior word is 0.
-        let prior_word_idx = word_idx.wrapping_sub(1);
-        let prior_word = key_hash.0.get(prior_word_idx).unwrap_or(&0);
+        if let Some(end) = self.end {
+            match branch.key_side(end) {
+                KeySide::Before => return (None, None),
+                KeySide::Left => right = None,
+                KeySide::Right => left = left.map(|r| r.with_end(None)),
+                KeySide::After => {
+                    left = left.map(|r| r.with_end(None));
+                    right = right.map(|r| r.with_end(None));
+                }
+            }
+        }
 
-        if self.prior_word != *prior_word {
-            return KeyPosition::Adjacent(KeyPositionAdjacent::PriorWord(prior_word_idx));
+        (left, right)
+    }
+
+    #[inline(always)]
+    fn with_start(self, start: Option<&'a K>) -> Self {
+        Self { start, ..self }
+    }
+
+    #[inline(always)]
+    fn with_end(self, end: Option<&'a K>) -> Self {
+        Self { end, ..self }
+    }
+}
+
+impl<NR> Branch<NR> {
+    /// Returns where the keys starting with the first `len_bits` bits of `min` fall relative to this branch,
+    /// where `min..=max` is `min.prefix_range(len_bits)`.
+    ///
+    /// The caller must have followed the prefix down to this branch.
+    #[inline]
+    pub(crate) fn prefix_side(&self, min: &KeyHash, max: &KeyHash, len_bits: usize) -> PrefixSide {
+        if (self.mask.bit_idx() as usize) < len_bits {
+            return match self.key_position(min) {
+                KeyPosition::Left => PrefixSide::Descend { go_right: false },
+                KeyPosition::Right => PrefixSide::Descend { go_right: true },
+                KeyPosition::Adjacent(_) => PrefixSide::Outside,
+            };
         }
 
-        let hash_segment = key_hash.0[word_idx];
+        // The keys under the branch agree on every bit of the prefix, so they are all in it or all outside it.
+        if self.key_side(min) != KeySide::After && self.key_side(max) != KeySide::Before {
+            PrefixSide::Inside
+        } else {
+            PrefixSide::Outside
+        }
+    }
 
-        if self.mask.is_left_descendant(hash_segment) {
-            KeyPosition::Left
-        } else if self.mask.is_right_descendant(hash_segment) {
-            KeyPosition::Right
+    /// Returns where the key falls in trie order relative to the keys under this branch.
+    #[inline]
+    pub(crate) fn key_side<K: TrieKey>(&self, key_hash: &K) -> KeySide {
+        let (branch_word, key_word) = match self.key_position(key_hash) {
+            KeyPosition::Left => return KeySide::Left,
+            KeyPosition::Right => return KeySide::Right,
+            KeyPosition::Adjacent(KeyPositionAdjacent::PrefixVec(word_idx)) => {
+                let prefix_offset = self.mask.word_idx().saturating_sub(self.prefix.len() + 1);
+                (
+                    self.prefix[word_idx - prefix_offset],
+                    key_hash.words()[word_idx],
+                )
+            }
+            KeyPosition::Adjacent(KeyPositionAdjacent::PriorWord(word_idx)) => (
+                self.prior_word,
+                *key_hash.words().get(word_idx).unwrap_or(&0),
+            ),
+            KeyPosition::Adjacent(KeyPositionAdjacent::PrefixOfWord(word_idx)) => {
+                let prefix_mask = self.mask.prefix_mask();
+                (
+                    self.mask.left_prefix & prefix_mask,
+                    key_hash.words()[word_idx] & prefix_mask,
+                )
+            }
+        };
+
+        debug_assert_ne!(branch_word, key_word);
+        let first_diff_bit = (branch_word ^ key_word).trailing_zeros();
+
+        if (key_word >> first_diff_bit) & 1 == 0 {
+            KeySide::Before
         } else {
-            KeyPosition::Adjacent(KeyPositionAdjacent::PrefixOfWord(word_idx))
+            KeySide::After
+        }
+    }
+
+    /// Returns where to search for the `neighbor` of `key_hash` under this branch.
+    #[inline]
+    pub(crate) fn neighbor_search<K: TrieKey>(
+        &self,
+        key_hash: &K,
+        neighbor: Neighbor,
+    ) -> NeighborSearch<&NR> {
+        match (self.key_side(key_hash), neighbor) {
+            (KeySide::Before, Neighbor::Next) | (KeySide::After, Neighbor::Prev) => {
+                NeighborSearch::First
+            }
+            (KeySide::After, Neighbor::Next) | (KeySide::Before, Neighbor::Prev) => {
+                NeighborSearch::Absent
+            }
+            (KeySide::Left, _) => NeighborSearch::Descend {
+                descend: &self.left,
+                fallback: (neighbor == Neighbor::Next).then_some(&self.right),
+            },
+            (KeySide::Right, _) => NeighborSearch::Descend {
+                descend: &self.right,
+                fallback: (neighbor == Neighbor::Prev).then_some(&self.left),
+            },
+        }
+    }
+
+    /// Check the invariants a branch holds on its own, without knowing its parent.
+    ///
+    /// - The discriminant bit is within the 256 bit key.
+    /// - `left_prefix` has a 0 discriminant bit and no bits after it.
+    /// - `prefix` is no longer than the words before `prior_word`.
+    /// - `prior_word` is 0 if the discriminant bit is in the first word.
+    ///
+    /// Use `BranchMask::is_valid_child_of` to check a branch against its parent.
+    #[inline]
+    pub fn check_invariants(&self) -> Result<(), TrieError> {
+        let mask = &self.mask;
+        if mask.bit_idx >= MAX_KEY_BITS {
+            return Err(format!("Invalid branch: bit_idx {} is out of range", mask.bit_idx).into());
+        }
+
+        if mask.left_prefix & !mask.prefix_mask() != 0 {
+            return Err(format!(
+                "Invalid branch: left_prefix {:#034b} has bits at or after bit {}",
+                mask.left_prefix,
+                mask.relative_bit_idx()
+            )
+            .into());
+        }
+
+        let word_idx = mask.word_idx();
+        if self.prefix.len() > word_idx.saturating_sub(1) {
+            return Err(format!(
+                "Invalid branch: prefix of {} words is longer than the {} words before prior_word",
+                self.prefix.len(),
+                word_idx.saturating_sub(1)
+            )
+            .into());
+        }
+
+        if word_idx == 0 && self.prior_word != 0 {
+            return Err(format!(
+                "Invalid branch: prior_word {} is not 0 on the first word",
+                self.prior_word
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Check this branch can sit below a branch discriminating on `parent_bit_idx`,
+    /// or `None` at the top of a traversal, on the path to a key of `key_bits` bits.
+    ///
+    /// The discriminant bit must be within the key and after the parent's,
+    /// and `prefix` no longer than the words before `prior_word`, so `key_position` stays within the key.
+    /// A stored trie refers to its nodes by index, so a corrupt one can contain cycles.
+    /// Checking every branch of a traversal rules them out, a path has at most `key_bits` branches.
+    #[inline]
+    pub(crate) fn check_bit_order(
+        &self,
+        parent_bit_idx: Option<u32>,
+        key_bits: u32,
+    ) -> Result<(), TrieError> {
+        let bit_idx = self.mask.bit_idx;
+        if bit_idx >= key_bits
+            || parent_bit_idx.is_some_and(|parent_bit_idx| bit_idx <= parent_bit_idx)
+        {
+            return Err(format!(
+                "Invalid branch: discriminates on bit {bit_idx}, which does not follow its parent's bit {parent_bit_idx:?} within a {key_bits} bit key"
+            )
+            .into());
+        }
+
+        let word_idx = self.mask.word_idx();
+        if self.prefix.len() > word_idx.saturating_sub(1) {
+            return Err(format!(
+                "Invalid branch: prefix of {} words is longer than the {} words before prior_word",
+                self.prefix.len(),
+                word_idx.saturating_sub(1)
+            )
+            .into());
         }
+
+        Ok(())
+    }
+
+    /// Write the bits shared by every key under one child into `words`, in trie order,
+    /// and return how many bits that is.
+    ///
+    /// `words` must already hold the words written for this branch's ancestors.
+    /// It is truncated to the word of the discriminant bit, and bits after the discriminant bit are 0.
+    #[inline]
+    pub(crate) fn child_key_prefix(&self, right: bool, words: &mut Vec<u32>) -> u32 {
+        let word_idx = self.mask.word_idx();
+        words.resize(word_idx + 1, 0);
+
+        if word_idx > 0 {
+            let prefix_start = word_idx - 1 - self.prefix.len();
+            words[prefix_start..word_idx - 1].copy_from_slice(&self.prefix);
+            words[word_idx - 1] = self.prior_word;
+        }
+        words[word_idx] = if right {
+            self.mask.right_prefix()
+        } else {
+            self.mask.left_prefix
+        };
+
+        self.mask.bit_idx + 1
+    }
+
+    /// Returns the position of the key relative to the branch.
+    #[inline(always)]
+    pub fn key_position<K: TrieKey>(&self, key_hash: &K) -> KeyPosition {
+        key_position(&self.mask, self.prior_word, &self.prefix, key_hash)
     }
 
     /// Hash a branch node with known child hashes.
@@ -359,22 +1062,172 @@ impl<NR> Branch<NR> {
         left: &NodeHash,
         right: &NodeHash,
     ) -> NodeHash {
-        hasher.portable_update(left);
-        hasher.portable_update(right);
-        hasher.portable_update(self.mask.bit_idx.to_le_bytes());
-        hasher.portable_update(self.mask.left_prefix.to_le_bytes());
-        hasher.portable_update(self.prior_word.to_le_bytes());
+        hash_branch(
+            hasher,
+            &self.mask,
+            self.prior_word,
+            &self.prefix,
+            left,
+            right,
+        )
+    }
 
-        self.prefix
-            .iter()
-            .for_each(|word| hasher.portable_update(word.to_le_bytes()));
+    /// A copy of this branch with different children.
+    #[inline]
+    pub(crate) fn with_children<T>(&self, left: T, right: T) -> Branch<T> {
+        Branch {
+            left,
+            right,
+            mask: self.mask,
+            prior_word: self.prior_word,
+            prefix: self.prefix.clone(),
+        }
+    }
 
-        NodeHash::new(hasher.finalize_reset())
+    /// Take over the words covered by `parent` after it is removed,
+    /// so this branch can replace `parent` as a child of the grandparent.
+    ///
+    /// `grandparent_word_idx` must be the word index of the grandparent's discriminant bit,
+    /// or 0 if `parent` was the root.
+    #[inline]
+    pub(crate) fn absorb_parent_prefix<P>(
+        &mut self,
+        parent: &Branch<P>,
+        grandparent_word_idx: usize,
+    ) {
+        let parent_word_idx = parent.mask.word_idx();
+
+        if parent_word_idx == grandparent_word_idx {
+            // The parent's prefix was empty, and so was the word range it covered.
+            debug_assert!(parent.prefix.is_empty());
+        } else if self.mask.word_idx() == parent_word_idx {
+            debug_assert!(self.prefix.is_empty());
+            self.prefix = parent.prefix.clone();
+        } else {
+            self.prefix = parent
+                .prefix
+                .iter()
+                .chain(iter::once(&parent.prior_word))
+                .chain(self.prefix.iter())
+                .copied()
+                .collect();
+        }
     }
 }
 
-impl<V> Branch<NodeRef<V>> {
-    pub(crate) fn from_stored(branch: &Branch<stored::Idx>) -> Branch<NodeRef<V>> {
+/// `Branch::key_position` over a borrowed prefix.
+#[inline(always)]
+pub(crate) fn key_position<K: TrieKey>(
+    mask: &BranchMask,
+    prior_word: u32,
+    prefix: &[u32],
+    key_hash: &K,
+) -> KeyPosition {
+    let key_words = key_hash.words();
+    let word_idx = mask.bit_idx as usize / 32;
+
+    // A corrupt stored branch can discriminate past the end of the key, or carry too long a prefix.
+    // Traversals reject those with `Branch::check_bit_order`, here they only must not index out of range.
+    let Some(&hash_segment) = key_words.get(word_idx) else {
+        return KeyPosition::Adjacent(KeyPositionAdjacent::PrefixOfWord(word_idx));
+    };
+    if prefix.len() > word_idx {
+        return KeyPosition::Adjacent(KeyPositionAdjacent::PrefixOfWord(word_idx));
+    }
+    let prefix_offset = word_idx.saturating_sub(prefix.len() + 1);
+
+    let prefix_diff = iter::zip(
+        prefix.iter(),
+        key_words.iter().enumerate().skip(prefix_offset),
+    )
+    .find(|(branch_word, (_, key_word))| branch_word != key_word);
+
+    if let Some((_, (idx, _))) = prefix_diff {
+        return KeyPosition::Adjacent(KeyPositionAdjacent::PrefixVec(idx));
+    }
+
+    // If sub wraps around to the last word, the prior word is 0.
+    let prior_word_idx = word_idx.wrapping_sub(1);
+    let key_prior_word = key_words.get(prior_word_idx).unwrap_or(&0);
+
+    if prior_word != *key_prior_word {
+        return KeyPosition::Adjacent(KeyPositionAdjacent::PriorWord(prior_word_idx));
+    }
+
+    if mask.is_left_descendant(hash_segment) {
+        KeyPosition::Left
+    } else if mask.is_right_descendant(hash_segment) {
+        KeyPosition::Right
+    } else {
+        KeyPosition::Adjacent(KeyPositionAdjacent::PrefixOfWord(word_idx))
+    }
+}
+
+/// `Branch::hash_branch` over a borrowed prefix.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub(crate) fn hash_branch<H: PortableHasher<32>>(
+    hasher: &mut H,
+    mask: &BranchMask,
+    prior_word: u32,
+    prefix: &[u32],
+    left: &NodeHash,
+    right: &NodeHash,
+) -> NodeHash {
+    // Small updates are expensive in a zkVM, so we feed the hasher a single buffer.
+    // A canonical branch has at most `MAX_BRANCH_PREFIX_WORDS` prefix words, anything longer gets a second update.
+    const HEADER_LEN: usize = spec::BRANCH_PREFIX;
+    let mut buf = [0; HEADER_LEN + spec::MAX_BRANCH_PREFIX_WORDS * 4];
+
+    if !H::BRANCH_TAG.is_empty() {
+        hasher.portable_update(H::BRANCH_TAG);
+    }
+
+    buf[..32].copy_from_slice(&left.bytes);
+    buf[32..64].copy_from_slice(&right.bytes);
+    buf[64..68].copy_from_slice(&mask.bit_idx.to_le_bytes());
+    buf[68..72].copy_from_slice(&mask.left_prefix.to_le_bytes());
+    buf[72..76].copy_from_slice(&prior_word.to_le_bytes());
+
+    if prefix.len() <= spec::MAX_BRANCH_PREFIX_WORDS {
+        for (bytes, word) in buf[HEADER_LEN..]
+            .as_chunks_mut::<4>()
+            .0
+            .iter_mut()
+            .zip(prefix.iter())
+        {
+            *bytes = word.to_le_bytes();
+        }
+        hasher.portable_update(&buf[..HEADER_LEN + prefix.len() * 4]);
+    } else {
+        hasher.portable_update(&buf[..HEADER_LEN]);
+        hasher.portable_update_u32_slice(prefix);
+    }
+
+    finalize_node(hasher, || {
+        counted::leaf_count(left).saturating_add(counted::leaf_count(right))
+    })
+}
+
+/// Finish hashing a node, folding in the number of leaves under it if `H::COUNTED`, see `counted`.
+///
+/// `leaves` is only called in counted mode.
+#[inline(always)]
+pub(crate) fn finalize_node<H: PortableHasher<32>>(
+    hasher: &mut H,
+    leaves: impl FnOnce() -> u32,
+) -> NodeHash {
+    let hash = NodeHash::new(hasher.finalize_reset());
+    if H::COUNTED {
+        counted::with_leaf_count(hash, leaves())
+    } else {
+        hash
+    }
+}
+
+impl<V, K: TrieKey> Branch<NodeRef<V, K>> {
+    pub(crate) fn from_stored(branch: &Branch<stored::Idx>) -> Branch<NodeRef<V, K>> {
         Branch {
             left: NodeRef::Stored(branch.left),
             right: NodeRef::Stored(branch.right),
@@ -394,7 +1247,7 @@ impl<V> Branch<NodeRef<V>> {
     pub(crate) fn new_adjacent_leaf(
         self: &mut Box<Self>,
         key_position: KeyPositionAdjacent,
-        leaf: Box<Leaf<V>>,
+        leaf: Box<Leaf<V, K>>,
     ) {
         self.new_adjacent_leaf_ret(key_position, leaf);
     }
@@ -404,17 +1257,17 @@ impl<V> Branch<NodeRef<V>> {
     /// The old branch will be moved to a new Box, under the new branch.
     // inline(always) is used to increase the odds of the compiler removing the return when unused.
     #[inline(always)]
-    pub(crate) fn new_adjacent_leaf_ret<'a>(
-        self: &'a mut Box<Self>,
+    pub(crate) fn new_adjacent_leaf_ret(
+        self: &mut Box<Self>,
         key_position: KeyPositionAdjacent,
-        leaf: Box<Leaf<V>>,
-    ) -> &'a mut Leaf<V> {
+        leaf: Box<Leaf<V, K>>,
+    ) -> &mut Leaf<V, K> {
         let (mask, prior_word, prefix, leaf_word) = match key_position {
             KeyPositionAdjacent::PrefixOfWord(word_idx) => {
                 debug_assert_eq!(self.mask.word_idx(), word_idx);
 
                 let branch_word = self.mask.left_prefix;
-                let leaf_word = leaf.key_hash.0[word_idx];
+                let leaf_word = leaf.key_hash.words()[word_idx];
 
                 let mask = BranchMask::new_with_mask(
                     word_idx as u32,
@@ -427,7 +1280,7 @@ impl<V> Branch<NodeRef<V>> {
                     self.prior_word,
                     word_idx
                         .checked_sub(1)
-                        .map(|i| leaf.key_hash.0[i])
+                        .map(|i| leaf.key_hash.words()[i])
                         .unwrap_or(0)
                 );
 
@@ -442,43 +1295,45 @@ impl<V> Branch<NodeRef<V>> {
                 debug_assert_eq!(word_idx, self.mask.word_idx() - 1);
 
                 let branch_word = self.prior_word;
-                let leaf_word = leaf.key_hash.0[word_idx];
+                let leaf_word = leaf.key_hash.words()[word_idx];
 
                 let mask = BranchMask::new(word_idx as u32, branch_word, leaf_word);
 
                 // If sub wraps around to the last word, the prior word is 0.
                 // This is a little optimization since we are already paying for a bounds check.
                 let prior_word_idx = word_idx.wrapping_sub(1);
-                let prior_word = leaf.key_hash.0.get(prior_word_idx).unwrap_or(&0);
+                let prior_word = leaf.key_hash.words().get(prior_word_idx).unwrap_or(&0);
+
+                // The last word of the old prefix is the new branch's prior word.
+                let prefix_len = self.prefix.len().saturating_sub(1);
+                let prefix = self.prefix[..prefix_len].into();
+                self.prefix = Box::new([]);
 
-                (mask, *prior_word, mem::take(&mut self.prefix), leaf_word)
+                (mask, *prior_word, prefix, leaf_word)
             }
             KeyPositionAdjacent::PrefixVec(word_idx) => {
                 debug_assert!(self.mask.word_idx() - word_idx >= 2);
                 debug_assert!(!self.prefix.is_empty());
 
-                // we don't include word or prior_word in the prefix
-                let key_prefix = &leaf.key_hash.0[..word_idx.saturating_sub(1)];
-                let delta_in_prefix = key_prefix
-                    .iter()
-                    .rev()
-                    .zip(self.prefix.iter().rev())
-                    .enumerate()
-                    .find(|(_, (key_word, branch_word))| key_word != branch_word);
+                let prefix_offset = self.mask.word_idx().saturating_sub(self.prefix.len() + 1);
+                let relative_word_idx = word_idx - prefix_offset;
 
-                debug_assert_eq!(delta_in_prefix, None);
-
-                let prefix_offset = word_idx.saturating_sub(self.prefix.len() + 1);
+                debug_assert_eq!(
+                    self.prefix[..relative_word_idx],
+                    leaf.key_hash.words()[prefix_offset..word_idx]
+                );
 
-                let new_prefix = leaf.key_hash.0[prefix_offset..word_idx.saturating_sub(1)].into();
-                let old_prefix = self.prefix[word_idx + 1 - prefix_offset..].into();
+                // The new branch covers the words before `word_idx`,
+                // the old branch keeps `word_idx` and everything after it.
+                let new_prefix = self.prefix[..relative_word_idx.saturating_sub(1)].into();
+                let old_prefix = self.prefix[relative_word_idx..].into();
 
-                let branch_word = self.prefix[word_idx - prefix_offset];
-                let leaf_word = leaf.key_hash.0[word_idx];
+                let branch_word = self.prefix[relative_word_idx];
+                let leaf_word = leaf.key_hash.words()[word_idx];
                 let mask = BranchMask::new(word_idx as u32, branch_word, leaf_word);
 
                 let prior_word_idx = word_idx.wrapping_sub(1);
-                let prior_word = leaf.key_hash.0.get(prior_word_idx).unwrap_or(&0);
+                let prior_word = leaf.key_hash.words().get(prior_word_idx).unwrap_or(&0);
 
                 self.prefix = old_prefix;
 
@@ -522,35 +1377,47 @@ impl<V> Branch<NodeRef<V>> {
     /// Create a new branch above two leafs.
     /// Returns the new branch and a bool indicating if the new leaf is the right child.
     ///
-    /// # Panics
-    /// Panics if the keys are the same.
+    /// `prefix_start_idx` must be the word index of the parent branch's discriminant bit,
+    /// or 0 if the new branch will be the root.
+    ///
+    /// Returns an error if the keys agree from `prefix_start_idx` on,
+    /// which a corrupt snapshot placing a leaf off its key's path can cause.
     #[inline]
     pub(crate) fn new_from_leafs(
         prefix_start_idx: usize,
-        old_leaf: impl AsRef<Leaf<V>> + Into<NodeRef<V>>,
-        new_leaf: Box<Leaf<V>>,
-    ) -> (Box<Self>, bool) {
-        let Some((word_idx, (a, b))) = iter::zip(new_leaf.key_hash.0, old_leaf.as_ref().key_hash.0)
+        old_leaf: impl AsRef<Leaf<V, K>> + Into<NodeRef<V, K>>,
+        new_leaf: Box<Leaf<V, K>>,
+    ) -> Result<(Box<Self>, bool), TrieError> {
+        let new_words = new_leaf.key_hash.words();
+        let old_words = old_leaf.as_ref().key_hash.words();
+        debug_assert_eq!(new_words.len(), old_words.len());
+
+        let Some((word_idx, (&a, &b))) = iter::zip(new_words, old_words)
             .enumerate()
             .skip(prefix_start_idx)
             .find(|(_, (a, b))| a != b)
         else {
-            panic!("The keys are the same")
+            return Err(format!(
+                "Invalid snapshot: cannot branch between leaves {:?} and {:?}, they agree from word {prefix_start_idx} on",
+                old_leaf.as_ref().key_hash, new_leaf.key_hash
+            )
+            .into());
         };
 
-        debug_assert!(new_leaf.key_hash.0[..word_idx] == old_leaf.as_ref().key_hash.0[..word_idx]);
+        debug_assert!(new_words[..word_idx] == old_words[..word_idx]);
 
         let prior_word_idx = word_idx.saturating_sub(1);
-        let prefix = new_leaf.key_hash.0[prefix_start_idx..prior_word_idx].into();
+        let prefix = if prefix_start_idx < prior_word_idx {
+            new_words[prefix_start_idx..prior_word_idx].into()
+        } else {
+            Box::default()
+        };
         let prior_word = if word_idx == 0 {
             0
         } else {
-            debug_assert_eq!(
-                new_leaf.key_hash.0[prior_word_idx],
-                old_leaf.as_ref().key_hash.0[prior_word_idx]
-            );
+            debug_assert_eq!(new_words[prior_word_idx], old_words[prior_word_idx]);
 
-            new_leaf.key_hash.0[prior_word_idx]
+            new_words[prior_word_idx]
         };
 
         let mask = BranchMask::new(word_idx as u32, a, b);
@@ -572,7 +1439,7 @@ impl<V> Branch<NodeRef<V>> {
             (old_leaf.into(), new_leaf.into(), true)
         };
 
-        (
+        Ok((
             Box::new(Branch {
                 left,
                 right,
@@ -582,18 +1449,18 @@ impl<V> Branch<NodeRef<V>> {
             }),
             // TODO use an enum
             is_right,
-        )
+        ))
     }
 }
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Leaf<V> {
-    pub key_hash: KeyHash,
+pub struct Leaf<V, K = KeyHash> {
+    pub key_hash: K,
     pub value: V,
 }
 
-impl<V> fmt::Debug for Leaf<V> {
+impl<V, K: fmt::Debug> fmt::Debug for Leaf<V, K> {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Leaf")
@@ -602,22 +1469,77 @@ impl<V> fmt::Debug for Leaf<V> {
     }
 }
 
-impl<V: PortableHash> PortableHash for Leaf<V> {
+impl<V: PortableHash, K: TrieKey> PortableHash for Leaf<V, K> {
     #[inline]
     fn portable_hash<H: PortableUpdate>(&self, hasher: &mut H) {
-        hasher.portable_update(self.key_hash.to_bytes());
+        hasher.portable_update_u32_slice(self.key_hash.words());
         self.value.portable_hash(hasher);
     }
 }
 
-impl<V: PortableHash> Leaf<V> {
+impl<V, K: TrieKey> Leaf<V, K> {
+    /// Check that this leaf, found below a branch on word `parent_word_idx` while searching for `key_hash`,
+    /// shares the words above that branch with `key_hash`.
+    ///
+    /// Only a malformed snapshot or a misbehaving database can place a leaf off its key's path.
+    #[inline]
+    pub(crate) fn check_on_path(
+        &self,
+        key_hash: &K,
+        parent_word_idx: usize,
+    ) -> Result<(), TrieError> {
+        let leaf_words = self.key_hash.words().get(..parent_word_idx);
+        if leaf_words.is_some() && leaf_words == key_hash.words().get(..parent_word_idx) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Invalid snapshot: leaf {:?} is not on the path to {key_hash:?}",
+                self.key_hash
+            )
+            .into())
+        }
+    }
+}
+
+impl<V: PortableHash, K: TrieKey> Leaf<V, K> {
     /// Hash a leaf node.
     ///
     /// Caller must ensure that the hasher is reset before calling this function.
     #[inline]
     pub fn hash_leaf<H: PortableHasher<32>>(&self, hasher: &mut H) -> NodeHash {
-        hasher.portable_update(self.key_hash.to_bytes());
-        self.value.portable_hash(hasher);
-        NodeHash::new(hasher.finalize_reset())
+        hash_leaf(hasher, &self.key_hash, &self.value)
+    }
+}
+
+/// `Leaf::hash_leaf` over a borrowed value.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub(crate) fn hash_leaf<H: PortableHasher<32>, V: PortableHash, K: TrieKey>(
+    hasher: &mut H,
+    key_hash: &K,
+    value: &V,
+) -> NodeHash {
+    let Ok(hash) = hash_leaf_with(hasher, key_hash, |hasher| {
+        value.portable_hash(hasher);
+        Ok::<_, core::convert::Infallible>(())
+    });
+    hash
+}
+
+/// `hash_leaf` with the value's bytes fed to the hasher by `hash_value`, for values that are not in memory.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub(crate) fn hash_leaf_with<H: PortableHasher<32>, K: TrieKey, E>(
+    hasher: &mut H,
+    key_hash: &K,
+    hash_value: impl FnOnce(&mut H) -> Result<(), E>,
+) -> Result<NodeHash, E> {
+    if !H::LEAF_TAG.is_empty() {
+        hasher.portable_update(H::LEAF_TAG);
     }
+    hasher.portable_update_u32_slice(key_hash.words());
+    hash_value(hasher)?;
+    Ok(finalize_node(hasher, || 1))
 }
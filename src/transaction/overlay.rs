@@ -0,0 +1,357 @@
+use alloc::{boxed::Box, format, vec::Vec};
+use core::{cell::OnceCell, mem};
+
+use crate::{
+    stored::{self, arena::AppendOnly, Idx, Store},
+    Branch, KeyHash, Leaf, Node, NodeHash, NodeRef, PortableHash, PortableHasher, Transaction,
+    TrieError, TrieKey, TrieRoot,
+};
+
+/// The first index an `Overlay` gives the parent's modified nodes.
+/// Indexes below it are the indexes of the parent's store.
+const OVERLAY_IDX_START: Idx = 1 << (Idx::BITS - 1);
+
+/// A modified node of the parent, and the overlay's copy of it once loaded.
+struct OverlaySlot {
+    /// Whether the path from the parent's root to the node turns right at each branch.
+    path: Box<[bool]>,
+    branch: OnceCell<Branch<Idx>>,
+}
+
+/// A store that reads the modified nodes of a parent `Transaction`, then the parent's store,
+/// from `Transaction::overlay`.
+///
+/// The parent's unmodified nodes keep their indexes in the parent's store.
+/// The parent's modified nodes get indexes from `2^(Idx::BITS - 1)` up as the overlay reaches them,
+/// so the parent's store must not use indexes that high, and an overlay cannot be layered on another overlay.
+pub struct Overlay<'p, S, V, K = KeyHash> {
+    parent: &'p mut Transaction<S, V, K>,
+    nodes: AppendOnly<OverlaySlot>,
+}
+
+impl<'p, S: Store<V, K>, V, K: TrieKey> Overlay<'p, S, V, K> {
+    /// The parent's modified node at `path`.
+    #[inline]
+    fn resolve(&self, path: &[bool]) -> Result<&NodeRef<V, K>, TrieError> {
+        let TrieRoot::Node(root) = &self.parent.current_root else {
+            return Err("Invalid overlay: the parent's trie is empty".into());
+        };
+        let mut node_ref = root;
+
+        for &go_right in path {
+            let NodeRef::ModBranch(branch) = node_ref else {
+                return Err("Invalid overlay: a path leaves the parent's modified branches".into());
+            };
+            node_ref = if go_right {
+                &branch.right
+            } else {
+                &branch.left
+            };
+        }
+        Ok(node_ref)
+    }
+
+    /// The overlay's index of a node of the parent, at `path`.
+    #[inline]
+    fn overlay_idx(&self, node_ref: &NodeRef<V, K>, path: Box<[bool]>) -> Result<Idx, TrieError> {
+        match node_ref {
+            NodeRef::Stored(idx) if *idx >= OVERLAY_IDX_START => Err(format!(
+                "Invalid overlay: the parent's store uses index {idx}, which is reserved for the overlay"
+            )
+            .into()),
+            NodeRef::Stored(idx) => Ok(*idx),
+            NodeRef::ModBranch(_) | NodeRef::ModLeaf(_) => {
+                let slot = self.nodes.push(OverlaySlot {
+                    path,
+                    branch: OnceCell::new(),
+                });
+                Idx::try_from(slot)
+                    .ok()
+                    .and_then(|slot| OVERLAY_IDX_START.checked_add(slot))
+                    .ok_or_else(|| {
+                        format!("Invalid overlay: slot {slot} is past the last index of the overlay")
+                            .into()
+                    })
+            }
+        }
+    }
+
+    #[inline]
+    fn slot(&self, idx: Idx) -> Result<&OverlaySlot, TrieError> {
+        stored::idx_to_usize(idx - OVERLAY_IDX_START)
+            .and_then(|slot| self.nodes.get(slot))
+            .ok_or_else(|| {
+                format!(
+                    "Invalid overlay: no node at index {idx}\n\
+                    Overlay has {} nodes",
+                    self.nodes.len()
+                )
+                .into()
+            })
+    }
+}
+
+impl<'p, S: Store<V, K>, V: PortableHash, K: TrieKey> Store<V, K> for Overlay<'p, S, V, K> {
+    type Error = TrieError;
+
+    #[inline]
+    fn calc_subtree_hash(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+        hash_idx: Idx,
+    ) -> Result<NodeHash, Self::Error> {
+        if hash_idx < OVERLAY_IDX_START {
+            return self
+                .parent
+                .data_store
+                .calc_subtree_hash(hasher, hash_idx)
+                .map_err(Into::into);
+        }
+
+        let node_ref = self.resolve(&self.slot(hash_idx)?.path)?;
+        Transaction::<S, V, K>::calc_root_hash_node(
+            hasher,
+            &self.parent.data_store,
+            node_ref,
+            &mut |_, _| Ok(()),
+            &mut |_, _, _, _| Ok(()),
+        )
+    }
+
+    #[inline]
+    fn get_node(&self, hash_idx: Idx) -> Result<Node<&Branch<Idx>, &Leaf<V, K>>, Self::Error> {
+        if hash_idx < OVERLAY_IDX_START {
+            return self
+                .parent
+                .data_store
+                .get_node(hash_idx)
+                .map_err(Into::into);
+        }
+
+        let slot = self.slot(hash_idx)?;
+        if let Some(branch) = slot.branch.get() {
+            return Ok(Node::Branch(branch));
+        }
+
+        match self.resolve(&slot.path)? {
+            NodeRef::ModBranch(branch) => {
+                let child_path = |right| slot.path.iter().copied().chain([right]).collect();
+                let left = self.overlay_idx(&branch.left, child_path(false))?;
+                let right = self.overlay_idx(&branch.right, child_path(true))?;
+
+                // Loading a node never loads another, so the slot is still empty.
+                Ok(Node::Branch(
+                    slot.branch
+                        .get_or_init(|| branch.with_children(left, right)),
+                ))
+            }
+            NodeRef::ModLeaf(leaf) => Ok(Node::Leaf(leaf)),
+            NodeRef::Stored(_) => unreachable!("The overlay only indexes modified nodes"),
+        }
+    }
+
+    #[inline]
+    fn get_unvisited_hash(&self, hash_idx: Idx) -> Result<Option<NodeHash>, Self::Error> {
+        if hash_idx < OVERLAY_IDX_START {
+            return self
+                .parent
+                .data_store
+                .get_unvisited_hash(hash_idx)
+                .map_err(Into::into);
+        }
+        Ok(None)
+    }
+}
+
+impl<S: Store<V, K>, V: PortableHash + Clone, K: TrieKey> Transaction<S, V, K> {
+    /// A child transaction over this one, for trying changes without cloning the modified trie.
+    ///
+    /// The child starts with this transaction's trie.
+    /// Its reads fall through to this transaction's modified nodes, then to its store,
+    /// and its writes stay in the child until `merge_into_parent`.
+    /// Dropping the child discards them.
+    ///
+    /// A block builder can simulate each candidate transaction in a child,
+    /// and merge the ones it includes into the block.
+    /// Against a `SnapshotBuilder`, every node the child reads from the store is recorded in the parent's snapshot,
+    /// whether or not the child is merged.
+    #[inline]
+    pub fn overlay(&mut self) -> Result<Transaction<Overlay<'_, S, V, K>, V, K>, TrieError> {
+        let overlay = Overlay {
+            parent: self,
+            nodes: AppendOnly::new(),
+        };
+
+        let current_root = match &overlay.parent.current_root {
+            TrieRoot::Empty => TrieRoot::Empty,
+            TrieRoot::Node(node_ref) => TrieRoot::Node(NodeRef::Stored(
+                overlay.overlay_idx(node_ref, Box::new([]))?,
+            )),
+        };
+
+        let root_hash = stored::OnceCell::new();
+        if let Some(hash) = overlay.parent.root_hash.get() {
+            let _ = root_hash.set(*hash);
+        }
+
+        Ok(Transaction {
+            data_store: overlay,
+            current_root,
+            root_hash,
+        })
+    }
+}
+
+impl<'p, S: Store<V, K>, V: PortableHash + Clone, K: TrieKey>
+    Transaction<Overlay<'p, S, V, K>, V, K>
+{
+    /// Apply the child's changes to the parent it was made from by `Transaction::overlay`.
+    ///
+    /// The parent's modified nodes the child did not change are moved, not copied, into the merged trie.
+    /// Every reference is checked before anything is moved, so on error the parent is left as it was.
+    #[inline]
+    pub fn merge_into_parent(self) -> Result<(), TrieError> {
+        let Transaction {
+            data_store: Overlay { parent, nodes },
+            mut current_root,
+            root_hash,
+        } = self;
+
+        if let TrieRoot::Node(node_ref) = &current_root {
+            let mut paths = Vec::new();
+            Self::parent_paths(node_ref, &nodes, &mut paths)?;
+            Self::check_parent_paths(&parent.current_root, paths)?;
+        }
+
+        let mut old_root = mem::take(&mut parent.current_root);
+        if let TrieRoot::Node(node_ref) = &mut current_root {
+            Self::detach_parent_nodes(node_ref, &nodes, &mut old_root)
+                .expect("merge_into_parent checks every reference before detaching");
+        }
+
+        parent.current_root = current_root;
+        parent.root_hash = root_hash;
+        Ok(())
+    }
+
+    /// Collect the paths of the parent's modified nodes the overlay refers to under `node_ref`.
+    #[inline]
+    fn parent_paths<'n>(
+        node_ref: &NodeRef<V, K>,
+        nodes: &'n AppendOnly<OverlaySlot>,
+        paths: &mut Vec<&'n [bool]>,
+    ) -> Result<(), TrieError> {
+        match node_ref {
+            NodeRef::ModBranch(branch) => {
+                Self::parent_paths(&branch.left, nodes, paths)?;
+                Self::parent_paths(&branch.right, nodes, paths)
+            }
+            NodeRef::ModLeaf(_) => Ok(()),
+            NodeRef::Stored(idx) if *idx < OVERLAY_IDX_START => Ok(()),
+            NodeRef::Stored(idx) => {
+                let Some(slot) =
+                    stored::idx_to_usize(*idx - OVERLAY_IDX_START).and_then(|slot| nodes.get(slot))
+                else {
+                    return Err(format!("Invalid overlay: no node at index {idx}").into());
+                };
+                paths.push(&slot.path);
+                Ok(())
+            }
+        }
+    }
+
+    /// Check each of `paths` leads through the parent's modified branches to a node `detach_parent_nodes` can take,
+    /// and no node is taken twice, itself or as part of a taken ancestor.
+    #[inline]
+    fn check_parent_paths(
+        old_root: &TrieRoot<NodeRef<V, K>>,
+        mut paths: Vec<&[bool]>,
+    ) -> Result<(), TrieError> {
+        // Sorted, a path is followed by the paths it is a prefix of.
+        paths.sort_unstable();
+        if paths.windows(2).any(|pair| pair[1].starts_with(pair[0])) {
+            return Err(
+                "Invalid overlay: a modified node of the parent is referenced twice".into(),
+            );
+        }
+
+        for path in paths {
+            let TrieRoot::Node(root) = old_root else {
+                return Err("Invalid overlay: the parent's trie is empty".into());
+            };
+            let mut parent_node = root;
+            for &go_right in path {
+                let NodeRef::ModBranch(branch) = parent_node else {
+                    return Err(
+                        "Invalid overlay: a path leaves the parent's modified branches".into(),
+                    );
+                };
+                parent_node = if go_right {
+                    &branch.right
+                } else {
+                    &branch.left
+                };
+            }
+            if parent_node.is_temp_null_stored() {
+                return Err(
+                    "Invalid overlay: a modified node of the parent is referenced twice".into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the overlay's references to the parent's modified nodes under `node_ref`
+    /// with the nodes themselves, taken from `old_root`.
+    ///
+    /// The referenced subtrees are disjoint,
+    /// a child only refers to a modified node of the parent if it did not load the node's parent.
+    // TODO use a stack instead of recursion
+    #[inline]
+    fn detach_parent_nodes(
+        node_ref: &mut NodeRef<V, K>,
+        nodes: &AppendOnly<OverlaySlot>,
+        old_root: &mut TrieRoot<NodeRef<V, K>>,
+    ) -> Result<(), TrieError> {
+        match node_ref {
+            NodeRef::ModBranch(branch) => {
+                Self::detach_parent_nodes(&mut branch.left, nodes, old_root)?;
+                Self::detach_parent_nodes(&mut branch.right, nodes, old_root)
+            }
+            NodeRef::ModLeaf(_) => Ok(()),
+            NodeRef::Stored(idx) if *idx < OVERLAY_IDX_START => Ok(()),
+            NodeRef::Stored(idx) => {
+                let Some(slot) =
+                    stored::idx_to_usize(*idx - OVERLAY_IDX_START).and_then(|slot| nodes.get(slot))
+                else {
+                    return Err(format!("Invalid overlay: no node at index {idx}").into());
+                };
+
+                let TrieRoot::Node(root) = &mut *old_root else {
+                    return Err("Invalid overlay: the parent's trie is empty".into());
+                };
+                let mut parent_node = root;
+                for &go_right in slot.path.iter() {
+                    let NodeRef::ModBranch(branch) = parent_node else {
+                        return Err(
+                            "Invalid overlay: a path leaves the parent's modified branches".into(),
+                        );
+                    };
+                    parent_node = if go_right {
+                        &mut branch.right
+                    } else {
+                        &mut branch.left
+                    };
+                }
+
+                if parent_node.is_temp_null_stored() {
+                    return Err(
+                        "Invalid overlay: a modified node of the parent is referenced twice".into(),
+                    );
+                }
+                *node_ref = mem::replace(parent_node, NodeRef::temp_null_stored());
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{
+    errors::error_context,
+    stored::{merkle::SnapshotBuilder, DatabaseSet, Store},
+    KeyHash, NodeHash, PortableHash, PortableHasher, Transaction, TrieError, TrieKey, TrieRoot,
+};
+
+/// Converts between the values a caller works with and the values stored in the trie,
+/// such as decrypting values on read and encrypting them on write.
+///
+/// The trie only ever sees the stored representation, so that is what leaves hash,
+/// and what snapshots, proofs and databases hold.
+pub trait ValueTransform<K = KeyHash> {
+    /// The value as the caller sees it.
+    type Value;
+    /// The value as the trie stores and hashes it.
+    type Stored;
+
+    /// Encode `value` for storage at `key_hash`.
+    ///
+    /// The key is passed so an encryption layer can bind a value to its key, as associated data or a nonce.
+    /// Encoding must be deterministic if roots are to be reproduced from the decoded values.
+    fn encode(&self, key_hash: &K, value: &Self::Value) -> Result<Self::Stored, TrieError>;
+
+    /// Decode a value stored at `key_hash`.
+    fn decode(&self, key_hash: &K, stored: &Self::Stored) -> Result<Self::Value, TrieError>;
+}
+
+impl<T: ValueTransform<K>, K> ValueTransform<K> for &T {
+    type Value = T::Value;
+    type Stored = T::Stored;
+
+    #[inline]
+    fn encode(&self, key_hash: &K, value: &Self::Value) -> Result<Self::Stored, TrieError> {
+        (**self).encode(key_hash, value)
+    }
+
+    #[inline]
+    fn decode(&self, key_hash: &K, stored: &Self::Stored) -> Result<Self::Value, TrieError> {
+        (**self).decode(key_hash, stored)
+    }
+}
+
+/// A `Transaction` over stored values, read and written through a `ValueTransform`.
+///
+/// Every value passes through the transform, so no call site can insert a decoded value,
+/// or hash one in place of its stored representation.
+/// Use `transaction` for anything that works on the stored values, such as proofs or building a snapshot.
+pub struct Transformed<S, T: ValueTransform<K>, K = KeyHash> {
+    txn: Transaction<S, T::Stored, K>,
+    transform: T,
+}
+
+impl<S, T: ValueTransform<K>, K> Transformed<S, T, K> {
+    #[inline]
+    pub fn new(txn: Transaction<S, T::Stored, K>, transform: T) -> Self {
+        Transformed { txn, transform }
+    }
+
+    #[inline]
+    pub fn transaction(&self) -> &Transaction<S, T::Stored, K> {
+        &self.txn
+    }
+
+    #[inline]
+    pub fn transform(&self) -> &T {
+        &self.transform
+    }
+
+    #[inline]
+    pub fn into_parts(self) -> (Transaction<S, T::Stored, K>, T) {
+        (self.txn, self.transform)
+    }
+}
+
+impl<S: Store<T::Stored, K>, T: ValueTransform<K>, K: TrieKey> Transformed<S, T, K> {
+    /// Get and decode the value at `key_hash`.
+    #[inline]
+    pub fn get(&self, key_hash: &K) -> Result<Option<T::Value>, TrieError> {
+        self.txn
+            .get(key_hash)?
+            .map(|stored| self.decode(key_hash, stored))
+            .transpose()
+    }
+
+    /// Get the value at `key_hash` as it is stored, without decoding it.
+    #[inline]
+    pub fn get_stored(&self, key_hash: &K) -> Result<Option<&T::Stored>, TrieError> {
+        self.txn.get(key_hash)
+    }
+
+    /// Encode `value` and insert it at `key_hash`.
+    #[inline]
+    pub fn insert(&mut self, key_hash: &K, value: &T::Value) -> Result<(), TrieError> {
+        let stored = self.transform.encode(key_hash, value).map_err(|e| {
+            error_context(e, format_args!("Error encoding the value for {key_hash:?}"))
+        })?;
+        self.txn.insert(key_hash, stored)
+    }
+
+    fn decode(&self, key_hash: &K, stored: &T::Stored) -> Result<T::Value, TrieError> {
+        self.transform
+            .decode(key_hash, stored)
+            .map_err(|e| error_context(e, format_args!("Error decoding the value at {key_hash:?}")))
+    }
+}
+
+impl<S, T, K> Transformed<S, T, K>
+where
+    S: Store<T::Stored, K>,
+    T: ValueTransform<K>,
+    T::Stored: PortableHash + Clone,
+    K: TrieKey,
+{
+    /// Remove the value at `key_hash`, returning it decoded.
+    #[inline]
+    pub fn remove(&mut self, key_hash: &K) -> Result<Option<T::Value>, TrieError> {
+        match self.txn.remove(key_hash)? {
+            Some(stored) => self.decode(key_hash, &stored).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The root hash of the trie of stored values, see `Transaction::calc_root_hash`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn calc_root_hash(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        self.txn.calc_root_hash(hasher)
+    }
+}
+
+impl<Db, T, K> Transformed<SnapshotBuilder<Db, T::Stored, K>, T, K>
+where
+    Db: DatabaseSet<T::Stored, K>,
+    T: ValueTransform<K>,
+    T::Stored: PortableHash + Clone,
+    K: TrieKey,
+{
+    /// Write the modified nodes to the database, see `Transaction::commit`.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>, TrieError> {
+        Ok(self.txn.commit(hasher)?.root)
+    }
+}
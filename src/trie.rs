@@ -0,0 +1,99 @@
+use alloc::format;
+use core::marker::PhantomData;
+
+use crate::{
+    stored::{
+        merkle::{Snapshot, SnapshotBuilder},
+        DatabaseGet, DatabaseSet,
+    },
+    CommitReceipt, KeyHash, NodeHash, PortableHash, PortableHasher, Transaction, TrieError,
+    TrieRoot,
+};
+
+/// A trie in a database, at the root of its last commit.
+///
+/// The common server workflow in one place: open a `transaction` at the current root, apply operations to it,
+/// then `commit` it, which writes the modified nodes, moves the trie to the new root,
+/// and returns the `Snapshot` a verifier replays the operations against.
+///
+/// Use `Transaction` and `SnapshotBuilder` directly for anything else.
+#[derive(Clone, Debug)]
+pub struct Trie<Db, V> {
+    db: Db,
+    root: TrieRoot<NodeHash>,
+    _values: PhantomData<fn() -> V>,
+}
+
+impl<Db, V> Trie<Db, V> {
+    /// The trie at `root` in `db`, `TrieRoot::Empty` for a new trie.
+    #[inline]
+    pub fn open(db: Db, root: TrieRoot<NodeHash>) -> Self {
+        Trie {
+            db,
+            root,
+            _values: PhantomData,
+        }
+    }
+
+    /// The root of the last commit.
+    #[inline]
+    pub fn root(&self) -> TrieRoot<NodeHash> {
+        self.root
+    }
+
+    #[inline]
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    #[inline]
+    pub fn into_db(self) -> Db {
+        self.db
+    }
+}
+
+impl<Db: DatabaseGet<V> + Clone, V: PortableHash + Clone> Trie<Db, V> {
+    /// A transaction over the trie at its current root.
+    #[inline]
+    pub fn transaction(&self) -> Transaction<SnapshotBuilder<Db, V>, V> {
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(self.db.clone(), self.root))
+    }
+
+    /// The value at `key_hash` at the current root.
+    #[inline]
+    pub fn get(&self, key_hash: &KeyHash) -> Result<Option<V>, TrieError> {
+        Ok(self.transaction().get(key_hash)?.cloned())
+    }
+}
+
+impl<Db: DatabaseSet<V> + Clone, V: PortableHash + Clone> Trie<Db, V> {
+    /// Write the nodes `txn` modified, and move the trie to its new root.
+    ///
+    /// Returns the commit's receipt and the snapshot of the nodes `txn` read,
+    /// the witness a verifier needs to replay its operations from the old root.
+    /// Fails without writing if `txn` was not opened at the current root.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn commit(
+        &mut self,
+        txn: &Transaction<SnapshotBuilder<Db, V>, V>,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<(CommitReceipt, Snapshot<V>), TrieError> {
+        let opened_at = match txn.data_store.trie_root() {
+            TrieRoot::Node(_) => TrieRoot::Node(txn.data_store.get_node_hash(0)?),
+            TrieRoot::Empty => TrieRoot::Empty,
+        };
+        if opened_at != self.root {
+            return Err(format!(
+                "The transaction was opened at {opened_at:?}, not the trie's current root {:?}",
+                self.root
+            )
+            .into());
+        }
+
+        let receipt = txn.commit(hasher)?;
+        self.root = receipt.root;
+        Ok((receipt, txn.build_initial_snapshot()))
+    }
+}
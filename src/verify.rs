@@ -0,0 +1,491 @@
+use alloc::{borrow::Cow, boxed::Box, collections::BTreeSet, format, vec::Vec};
+
+use crate::{
+    cycle_probe::{measure, CycleProbe, Phase},
+    spec::NodeLimits,
+    stored::{merkle::Snapshot, Store},
+    KeyHash, NodeHash, ParamsRoot, PortableHash, PortableHasher, RootParams, Transaction,
+    TrieError, TrieKey, TrieRoot, VerifyError,
+};
+
+/// An operation replayed against a `Snapshot` by `verify_batch`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Op<V> {
+    Get(KeyHash),
+    Insert(KeyHash, V),
+    Remove(KeyHash),
+}
+
+impl<V> Op<V> {
+    #[inline]
+    pub fn key_hash(&self) -> &KeyHash {
+        match self {
+            Op::Get(key_hash) | Op::Insert(key_hash, _) | Op::Remove(key_hash) => key_hash,
+        }
+    }
+
+    /// Apply the operation to a transaction.
+    /// Returns the value read by an `Op::Get` or removed by an `Op::Remove`, and `None` for an `Op::Insert`.
+    #[inline]
+    pub fn apply<'txn, S: Store<V>>(
+        &self,
+        txn: &'txn mut Transaction<S, V>,
+    ) -> Result<Option<Cow<'txn, V>>, TrieError>
+    where
+        V: PortableHash + Clone,
+    {
+        match self {
+            Op::Get(key_hash) => Ok(txn.get(key_hash)?.map(Cow::Borrowed)),
+            Op::Insert(key_hash, value) => {
+                txn.insert(key_hash, value.clone())?;
+                Ok(None)
+            }
+            Op::Remove(key_hash) => Ok(txn.remove(key_hash)?.map(Cow::Owned)),
+        }
+    }
+}
+
+/// A record of the operations applied to a `Transaction` and the value each one returned.
+///
+/// Apply operations through `Journal::apply` on the server,
+/// then pass the journal to `Transaction::commit_audited`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Journal<V> {
+    ops: Vec<Op<V>>,
+    results: Vec<Option<V>>,
+}
+
+impl<V> Default for Journal<V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> Journal<V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            ops: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Apply `op` to `txn` and record it along with its result.
+    #[inline]
+    pub fn apply<S: Store<V>>(
+        &mut self,
+        txn: &mut Transaction<S, V>,
+        op: Op<V>,
+    ) -> Result<Option<&V>, TrieError>
+    where
+        V: PortableHash + Clone,
+    {
+        let result = op.apply(txn)?.map(Cow::into_owned);
+        self.ops.push(op);
+        self.results.push(result);
+
+        Ok(self.results.last().and_then(Option::as_ref))
+    }
+
+    #[inline]
+    pub fn ops(&self) -> &[Op<V>] {
+        &self.ops
+    }
+
+    /// The value returned by each operation, in the same order as `ops`.
+    #[inline]
+    pub fn results(&self) -> &[Option<V>] {
+        &self.results
+    }
+
+    #[inline]
+    pub fn into_ops(self) -> Vec<Op<V>> {
+        self.ops
+    }
+}
+
+/// A committed batch that has already been replayed against its own `Snapshot`.
+///
+/// Contains everything `verify_batch` needs.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AuditedBatch<V> {
+    pub old_root: TrieRoot<NodeHash>,
+    pub new_root: TrieRoot<NodeHash>,
+    pub snapshot: Snapshot<V>,
+    pub ops: Vec<Op<V>>,
+}
+
+impl<V: PortableHash + Clone> AuditedBatch<V> {
+    /// Run `verify_batch` on this batch.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify(&self, hasher: &mut impl PortableHasher<32>) -> Result<(), VerifyError> {
+        verify_batch(
+            self.old_root,
+            self.new_root,
+            &self.snapshot,
+            &self.ops,
+            hasher,
+        )
+    }
+
+    /// The writes that undo this batch, each key it inserted or removed with its value before the batch,
+    /// or `None` if the key was absent.
+    ///
+    /// The old values are read from the batch's own `Snapshot`,
+    /// which holds the leaf of every key the batch wrote, or proves the key was absent.
+    /// Keys are listed once, in the order the batch first wrote them.
+    #[inline]
+    pub fn inverse(&self) -> Result<Vec<(KeyHash, Option<V>)>, TrieError> {
+        let txn = Transaction::from_snapshot(&self.snapshot)?;
+        let mut seen = BTreeSet::new();
+        let mut inverse = Vec::new();
+
+        for op in &self.ops {
+            if let Op::Insert(key_hash, _) | Op::Remove(key_hash) = op {
+                if seen.insert(*key_hash) {
+                    inverse.push((*key_hash, txn.get(key_hash)?.cloned()));
+                }
+            }
+        }
+        Ok(inverse)
+    }
+
+    /// Undo this batch on `txn`, a transaction over the trie at `new_root`, to handle a reorg.
+    ///
+    /// Applies `inverse`, then checks that `txn` is back at `old_root`, which it returns.
+    /// Commit `txn` afterwards to write the parent's nodes again,
+    /// so a database that pruned the parent's state need not rebuild it from genesis.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn revert<S: Store<V>>(
+        &self,
+        txn: &mut Transaction<S, V>,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<TrieRoot<NodeHash>, VerifyError> {
+        let start = txn.calc_root_hash(hasher)?;
+        if !start.verify_eq(&self.new_root) {
+            return Err(TrieError::from(format!(
+                "Cannot revert a batch producing {:?} from {start:?}",
+                self.new_root
+            ))
+            .into());
+        }
+
+        for (key_hash, old_value) in self.inverse()? {
+            match old_value {
+                Some(value) => txn.insert(&key_hash, value)?,
+                None => {
+                    txn.remove(&key_hash)?;
+                }
+            }
+        }
+
+        let reverted = txn.calc_root_hash(hasher)?;
+        if !reverted.verify_eq(&self.old_root) {
+            return Err(VerifyError::RevertMismatch {
+                expected: self.old_root,
+                actual: reverted,
+            });
+        }
+        Ok(reverted)
+    }
+}
+
+/// The batches of consecutive transactions, each starting from the root the previous one produced.
+///
+/// Lets a guest verify several blocks with one call.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SnapshotChain<V> {
+    batches: Vec<AuditedBatch<V>>,
+}
+
+impl<V> Default for SnapshotChain<V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> SnapshotChain<V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            batches: Vec::new(),
+        }
+    }
+
+    /// Append a batch, which must start from the root the last batch produced.
+    #[inline]
+    pub fn push(&mut self, batch: AuditedBatch<V>) -> Result<(), VerifyError> {
+        if let Some(last) = self.batches.last() {
+            if last.new_root != batch.old_root {
+                return Err(VerifyError::ChainBroken {
+                    batch_idx: self.batches.len(),
+                    expected: last.new_root,
+                    actual: batch.old_root,
+                });
+            }
+        }
+
+        self.batches.push(batch);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn batches(&self) -> &[AuditedBatch<V>] {
+        &self.batches
+    }
+
+    #[inline]
+    pub fn into_batches(self) -> Vec<AuditedBatch<V>> {
+        self.batches
+    }
+}
+
+impl<V: PortableHash + Clone> SnapshotChain<V> {
+    /// Verify every batch with `verify_batch`, and that each starts from the root the previous one produced.
+    ///
+    /// Returns the root before the first batch and the root after the last.
+    /// A chain built with `push` is linked already,
+    /// but one received from an untrusted prover may not be, so the links are always checked.
+    ///
+    /// Caller must ensure that the hasher is reset before calling this method.
+    #[inline]
+    pub fn verify(
+        &self,
+        hasher: &mut impl PortableHasher<32>,
+    ) -> Result<(TrieRoot<NodeHash>, TrieRoot<NodeHash>), VerifyError> {
+        let (Some(first), Some(last)) = (self.batches.first(), self.batches.last()) else {
+            return Err(TrieError::from("Cannot verify an empty SnapshotChain").into());
+        };
+
+        for (batch_idx, batch) in self.batches.iter().enumerate() {
+            if let Some(prev) = batch_idx.checked_sub(1).map(|idx| &self.batches[idx]) {
+                if !prev.new_root.verify_eq(&batch.old_root) {
+                    return Err(VerifyError::ChainBroken {
+                        batch_idx,
+                        expected: prev.new_root,
+                        actual: batch.old_root,
+                    });
+                }
+            }
+
+            batch.verify(hasher)?;
+        }
+
+        Ok((first.old_root, last.new_root))
+    }
+}
+
+/// Replay `journal` against `snapshot`, checking each result and the final root.
+#[inline]
+pub(crate) fn audit_journal<V: PortableHash + Clone + PartialEq>(
+    old_root: TrieRoot<NodeHash>,
+    new_root: TrieRoot<NodeHash>,
+    snapshot: &Snapshot<V>,
+    journal: &Journal<V>,
+    hasher: &mut impl PortableHasher<32>,
+) -> Result<(), VerifyError> {
+    replay_snapshot(old_root, new_root, snapshot, hasher, &mut (), |txn| {
+        for (op_idx, (op, result)) in journal.ops.iter().zip(journal.results.iter()).enumerate() {
+            if op.apply(txn)?.as_deref() != result.as_ref() {
+                return Err(VerifyError::ResultMismatch { op_idx });
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Verify that applying `ops` to the trie at `old_root` produces `new_root`.
+///
+/// This is the whole verifier side of a batch, meant to run in a zkVM or other trusted environment:
+/// 1. Check that `snapshot` hashes to `old_root`, and is a canonical trie, see `Snapshot::check_canonical`,
+///    with no node larger than `NodeLimits::default()`, see `Snapshot::check_limits`.
+/// 2. Replay `ops` against the snapshot.
+/// 3. Check that the resulting root is `new_root`.
+///
+/// Skipping the first step would let a prover pick any pre-state it likes,
+/// so prefer this function over hand-rolling the sequence.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn verify_batch<V: PortableHash + Clone>(
+    old_root: TrieRoot<NodeHash>,
+    new_root: TrieRoot<NodeHash>,
+    snapshot: &Snapshot<V>,
+    ops: &[Op<V>],
+    hasher: &mut impl PortableHasher<32>,
+) -> Result<(), VerifyError> {
+    verify_batch_probed(old_root, new_root, snapshot, ops, hasher, &mut ())
+}
+
+/// Like `verify_batch`, reporting its `Phase::Hash` and `Phase::Replay` phases to `probe`.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn verify_batch_probed<V: PortableHash + Clone>(
+    old_root: TrieRoot<NodeHash>,
+    new_root: TrieRoot<NodeHash>,
+    snapshot: &Snapshot<V>,
+    ops: &[Op<V>],
+    hasher: &mut impl PortableHasher<32>,
+    probe: &mut impl CycleProbe,
+) -> Result<(), VerifyError> {
+    replay_snapshot(old_root, new_root, snapshot, hasher, probe, |txn| {
+        for op in ops {
+            op.apply(txn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Like `verify_batch`, but first check that both roots were computed under `params`.
+///
+/// `params` is the verifier's own configuration, fixed in the guest, never taken from the witness.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn verify_batch_with_params<V: PortableHash + Clone>(
+    params: &RootParams,
+    old_root: ParamsRoot,
+    new_root: ParamsRoot,
+    snapshot: &Snapshot<V>,
+    ops: &[Op<V>],
+    hasher: &mut impl PortableHasher<32>,
+) -> Result<(), VerifyError> {
+    params.check_supported()?;
+    let old_root = old_root.check(params)?;
+    let new_root = new_root.check(params)?;
+    verify_batch(old_root, new_root, snapshot, ops, hasher)
+}
+
+/// Like `verify_batch`, but against roots a contract stored truncated to their first `N` bytes,
+/// see `TrieRoot::truncated` for what truncating gives up.
+///
+/// Caller must ensure that the hasher is reset before calling this function.
+#[inline]
+pub fn verify_batch_truncated<V: PortableHash + Clone, const N: usize>(
+    old_root: TrieRoot<[u8; N]>,
+    new_root: TrieRoot<[u8; N]>,
+    snapshot: &Snapshot<V>,
+    ops: &[Op<V>],
+    hasher: &mut impl PortableHasher<32>,
+) -> Result<(), VerifyError> {
+    let expected = |root: TrieRoot<[u8; N]>| match root {
+        TrieRoot::Empty => TrieRoot::Empty,
+        TrieRoot::Node(bytes) => TrieRoot::Node(Box::from(&bytes[..])),
+    };
+
+    replay_snapshot_with(
+        snapshot,
+        hasher,
+        &mut (),
+        |actual| {
+            if actual.matches_truncated(&old_root) {
+                Ok(())
+            } else {
+                Err(VerifyError::OldTruncatedRootMismatch {
+                    expected: expected(old_root),
+                    actual,
+                })
+            }
+        },
+        |txn| {
+            for op in ops {
+                op.apply(txn)?;
+            }
+            Ok(())
+        },
+        |actual| {
+            if actual.matches_truncated(&new_root) {
+                Ok(())
+            } else {
+                Err(VerifyError::NewTruncatedRootMismatch {
+                    expected: expected(new_root),
+                    actual,
+                })
+            }
+        },
+    )
+}
+
+#[inline]
+pub(crate) fn replay_snapshot<'s, V: PortableHash + Clone>(
+    old_root: TrieRoot<NodeHash>,
+    new_root: TrieRoot<NodeHash>,
+    snapshot: &'s Snapshot<V>,
+    hasher: &mut impl PortableHasher<32>,
+    probe: &mut impl CycleProbe,
+    replay: impl FnOnce(&mut Transaction<&'s Snapshot<V>, V>) -> Result<(), VerifyError>,
+) -> Result<(), VerifyError> {
+    replay_snapshot_with(
+        snapshot,
+        hasher,
+        probe,
+        |actual| {
+            if actual.verify_eq(&old_root) {
+                Ok(())
+            } else {
+                Err(VerifyError::OldRootMismatch {
+                    expected: old_root,
+                    actual,
+                })
+            }
+        },
+        replay,
+        |actual| {
+            if actual.verify_eq(&new_root) {
+                Ok(())
+            } else {
+                Err(VerifyError::NewRootMismatch {
+                    expected: new_root,
+                    actual,
+                })
+            }
+        },
+    )
+}
+
+/// Check the snapshot is within `NodeLimits::default()`, its root with `check_old`, and that it is canonical.
+#[inline]
+pub(crate) fn check_snapshot<V: PortableHash, K: TrieKey>(
+    snapshot: &Snapshot<V, K>,
+    hasher: &mut impl PortableHasher<32>,
+    check_old: impl FnOnce(TrieRoot<NodeHash>) -> Result<(), VerifyError>,
+) -> Result<(), VerifyError> {
+    snapshot.check_limits(&NodeLimits::default())?;
+    check_old(snapshot.calc_root_hash(hasher)?)?;
+    snapshot.check_canonical()?;
+    Ok(())
+}
+
+/// Check the snapshot's root with `check_old`, replay against it, and check the resulting root with `check_new`.
+#[inline]
+fn replay_snapshot_with<'s, V: PortableHash + Clone>(
+    snapshot: &'s Snapshot<V>,
+    hasher: &mut impl PortableHasher<32>,
+    probe: &mut impl CycleProbe,
+    check_old: impl FnOnce(TrieRoot<NodeHash>) -> Result<(), VerifyError>,
+    replay: impl FnOnce(&mut Transaction<&'s Snapshot<V>, V>) -> Result<(), VerifyError>,
+    check_new: impl FnOnce(TrieRoot<NodeHash>) -> Result<(), VerifyError>,
+) -> Result<(), VerifyError> {
+    measure(probe, Phase::Hash, || {
+        check_snapshot(snapshot, hasher, check_old)
+    })?;
+
+    let txn = measure(probe, Phase::Replay, || {
+        let mut txn = Transaction::from_snapshot(snapshot)?;
+        replay(&mut txn)?;
+        Ok::<_, VerifyError>(txn)
+    })?;
+
+    let new_root = measure(probe, Phase::Hash, || txn.calc_root_hash(hasher))?;
+    check_new(new_root)
+}
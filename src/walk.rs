@@ -0,0 +1,142 @@
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+use core::ops::ControlFlow;
+
+use crate::{
+    errors::error_context,
+    stored::{DatabaseGet, Idx, Store},
+    transaction::nodes::{Branch, Leaf, Node, TrieRoot, MAX_KEY_BITS},
+    KeyHash, NodeHash, TrieError,
+};
+
+/// Returned by `Visitor::pre_branch` to control whether `walk` descends into a branch.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum VisitControl {
+    /// Walk the branch's children.
+    #[default]
+    Continue,
+    /// Skip the branch's children.
+    SkipSubtree,
+}
+
+/// Callbacks for `walk`.
+///
+/// Every method has a default that does nothing, so visitors only implement what they need.
+/// Children are always walked left before right, so leaves are visited in `TrieKey::cmp_trie_order`.
+pub trait Visitor<V, K = KeyHash> {
+    /// Called before a branch's children are walked.
+    #[inline]
+    fn pre_branch(&mut self, _idx: Idx, _branch: &Branch<Idx>) -> Result<VisitControl, TrieError> {
+        Ok(VisitControl::Continue)
+    }
+
+    /// Called after a branch's children are walked.
+    /// Also called when `pre_branch` skipped them, so `pre_branch` and `post_branch` always pair up.
+    #[inline]
+    fn post_branch(&mut self, _idx: Idx, _branch: &Branch<Idx>) -> Result<(), TrieError> {
+        Ok(())
+    }
+
+    #[inline]
+    fn leaf(&mut self, _idx: Idx, _leaf: &Leaf<V, K>) -> Result<(), TrieError> {
+        Ok(())
+    }
+
+    /// Called for nodes the store only knows by hash, such as the unvisited nodes of a `Snapshot`.
+    #[inline]
+    fn unvisited(&mut self, _idx: Idx, _hash: &NodeHash) -> Result<(), TrieError> {
+        Ok(())
+    }
+}
+
+/// Walk the trie in `store` depth first, from `root`, calling `visitor` on each node.
+///
+/// Each branch must discriminate on a later bit than its parent, see `Branch::check_bit_order`,
+/// so a corrupt store with a cycle, or a path deeper than the key, is an error instead of an endless walk.
+///
+/// Against a `Snapshot`, use `Snapshot::root_node_idx` as the root.
+/// Against a `SnapshotBuilder` the root is at index 0,
+/// note that walking a `SnapshotBuilder` loads every node walked into the witness.
+#[inline]
+pub fn walk<V, K, S: Store<V, K>>(
+    store: &S,
+    root: TrieRoot<Idx>,
+    visitor: &mut impl Visitor<V, K>,
+) -> Result<(), TrieError> {
+    match root {
+        TrieRoot::Node(idx) => walk_node(store, idx, None, visitor),
+        TrieRoot::Empty => Ok(()),
+    }
+}
+
+// TODO use a stack instead of recursion
+fn walk_node<V, K, S: Store<V, K>>(
+    store: &S,
+    idx: Idx,
+    parent_bit_idx: Option<u32>,
+    visitor: &mut impl Visitor<V, K>,
+) -> Result<(), TrieError> {
+    if let Some(hash) = store
+        .get_unvisited_hash(idx)
+        .map_err(|e| error_context(e, "Error in `walk`"))?
+    {
+        return visitor.unvisited(idx, &hash);
+    }
+
+    match store
+        .get_node(idx)
+        .map_err(|e| error_context(e, "Error in `walk`"))?
+    {
+        Node::Branch(branch) => {
+            branch
+                .check_bit_order(parent_bit_idx, MAX_KEY_BITS)
+                .map_err(|e| error_context(e, format_args!("Error in `walk` at branch {idx}")))?;
+            if visitor.pre_branch(idx, branch)? == VisitControl::Continue {
+                let bit_idx = Some(branch.mask.bit_idx());
+                walk_node(store, branch.left, bit_idx, visitor)?;
+                walk_node(store, branch.right, bit_idx, visitor)?;
+            }
+            visitor.post_branch(idx, branch)
+        }
+        Node::Leaf(leaf) => visitor.leaf(idx, leaf),
+    }
+}
+
+/// Stream every node reachable from `root` in `db` to `sink`, each once, parents before children.
+///
+/// Meant for backing up a root from a live database.
+/// Nodes are immutable and keyed by their hash, so writes of newer roots cannot make the backup inconsistent,
+/// as long as nothing deletes the nodes of `root` meanwhile, see `stored::DatabasePin`.
+///
+/// Returns `ControlFlow::Break` if `sink` stopped the walk early.
+#[inline]
+pub fn walk_nodes<V, K, Db: DatabaseGet<V, K>>(
+    db: &Db,
+    root: TrieRoot<NodeHash>,
+    mut sink: impl FnMut(NodeHash, &Node<Branch<NodeHash>, Leaf<V, K>>) -> ControlFlow<()>,
+) -> Result<ControlFlow<()>, TrieError> {
+    let mut stack = match root {
+        TrieRoot::Node(hash) => vec![hash],
+        TrieRoot::Empty => Vec::new(),
+    };
+    let mut seen = BTreeSet::new();
+
+    while let Some(hash) = stack.pop() {
+        if !seen.insert(hash) {
+            continue;
+        }
+
+        let node = db
+            .get(&hash)
+            .map_err(|e| error_context(e, format_args!("Error in `walk_nodes` getting {hash}")))?;
+        if sink(hash, &node).is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+
+        if let Node::Branch(branch) = node {
+            stack.push(branch.right);
+            stack.push(branch.left);
+        }
+    }
+
+    Ok(ControlFlow::Continue(()))
+}
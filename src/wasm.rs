@@ -0,0 +1,64 @@
+//! `wasm-bindgen` bindings for checking batches in a browser light client.
+//!
+//! A batch crosses the JS boundary as the `bincode` encoding of an `AuditedBatch<Vec<u8>>`,
+//! and is hashed with SHA-256, as `DigestHasher<Sha256>` does on the server.
+//! A root crosses it as its 32 bytes, or as no bytes for the empty trie.
+
+use alloc::{format, vec::Vec};
+
+use sha2::Sha256;
+use wasm_bindgen::prelude::*;
+
+use crate::{AuditedBatch, DigestHasher, NodeHash, TrieError, TrieRoot, VerifyError};
+
+/// Check that the encoded batch starts from `trusted_root` and replays to the root it claims,
+/// returning the new root.
+///
+/// Unlike `verify_batch_js`, this runs outside of a JS host too.
+#[inline]
+pub fn verify_encoded_batch(
+    trusted_root: &[u8],
+    batch: &[u8],
+) -> Result<TrieRoot<NodeHash>, VerifyError> {
+    let trusted_root = decode_root(trusted_root)?;
+    let batch: AuditedBatch<Vec<u8>> = bincode::deserialize(batch)
+        .map_err(|e| TrieError::from(format!("Invalid batch encoding: {e}")))?;
+
+    if !batch.old_root.verify_eq(&trusted_root) {
+        return Err(VerifyError::OldRootMismatch {
+            expected: trusted_root,
+            actual: batch.old_root,
+        });
+    }
+
+    batch.verify(&mut DigestHasher::<Sha256>::default())?;
+    Ok(batch.new_root)
+}
+
+/// `verify_encoded_batch` for JS, returning the new root's bytes and throwing an `Error` if the batch is invalid.
+// Exported to JS, so it is never inlined.
+#[allow(clippy::missing_inline_in_public_items)]
+#[wasm_bindgen(js_name = verifyBatch)]
+pub fn verify_batch_js(trusted_root: &[u8], batch: &[u8]) -> Result<Vec<u8>, JsError> {
+    match verify_encoded_batch(trusted_root, batch) {
+        Ok(TrieRoot::Node(hash)) => Ok(hash.bytes.to_vec()),
+        Ok(TrieRoot::Empty) => Ok(Vec::new()),
+        Err(e) => Err(JsError::new(&format!("{e}"))),
+    }
+}
+
+#[inline]
+fn decode_root(bytes: &[u8]) -> Result<TrieRoot<NodeHash>, TrieError> {
+    match bytes {
+        [] => Ok(TrieRoot::Empty),
+        bytes => <[u8; 32]>::try_from(bytes)
+            .map(|bytes| TrieRoot::Node(NodeHash::new(bytes)))
+            .map_err(|_| {
+                format!(
+                    "Invalid root: expected 32 bytes, or none for the empty trie, found {}",
+                    bytes.len()
+                )
+                .into()
+            }),
+    }
+}
@@ -0,0 +1,167 @@
+#![cfg(feature = "test-utils")]
+
+use std::{alloc::System, rc::Rc};
+
+use kairos_trie::{
+    alloc_count::{AllocCounts, CountingAlloc},
+    raw::RawSnapshot,
+    stored::{
+        flat::{FixedFlatSnapshot, FlatSnapshot},
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+    },
+    DigestHasher, FlatError, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[global_allocator]
+static ALLOC: CountingAlloc<System> = CountingAlloc::new(System);
+
+/// A witness reading every `stride`th of `keys` keys, and its root.
+fn witness(keys: u64, stride: usize) -> (Snapshot<[u8; 8]>, TrieRoot<NodeHash>) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<[u8; 8]>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..keys {
+        txn.insert(&KeyHash::from_u64(i), i.to_le_bytes()).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for i in (0..keys).step_by(stride) {
+        txn.get(&KeyHash::from_u64(i)).unwrap();
+    }
+    (txn.build_initial_snapshot(), root)
+}
+
+fn verify_root(snapshot: &Snapshot<[u8; 8]>, root: TrieRoot<NodeHash>) -> AllocCounts {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (actual, counts) = ALLOC.count(|| snapshot.calc_root_hash(hasher).unwrap());
+    assert_eq!(actual, root);
+    counts
+}
+
+fn allocates_nothing<R>(f: impl FnOnce() -> R) -> R {
+    let (ret, counts) = ALLOC.count(f);
+    assert_eq!(counts, AllocCounts::default());
+    ret
+}
+
+/// Flat snapshots verify a root and read the witness without allocating.
+fn flat_verification_allocates_nothing(keys: u64, stride: usize) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (snapshot, root) = witness(keys, stride);
+    let reads: Vec<KeyHash> = (0..keys).step_by(stride).map(KeyHash::from_u64).collect();
+
+    let buf = snapshot.to_flat();
+    let flat = buf.as_flat();
+    assert_eq!(allocates_nothing(|| flat.calc_root_hash(hasher)), Ok(root));
+    for (key, i) in reads.iter().zip((0..keys).step_by(stride)) {
+        let value = allocates_nothing(|| flat.get(key).unwrap().copied());
+        assert_eq!(value, Some(i.to_le_bytes()));
+    }
+
+    let fixed_buf = snapshot.to_fixed_flat();
+    let fixed = fixed_buf.as_flat();
+    assert_eq!(allocates_nothing(|| fixed.calc_root_hash(hasher)), Ok(root));
+    for (key, i) in reads.iter().zip((0..keys).step_by(stride)) {
+        let value = allocates_nothing(|| fixed.get(key).unwrap().copied());
+        assert_eq!(value, Some(i.to_le_bytes()));
+    }
+}
+
+/// Flat snapshot errors are reported without allocating.
+fn flat_errors_allocate_nothing() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<[u8; 8]>::empty());
+    let keys = [KeyHash([0; 8]), KeyHash([0, 0, 1, 0, 0, 0, 0, 0])];
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys.iter() {
+        txn.insert(key, [1; 8]).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // Only the first key is read, so the second leaf is unvisited.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&keys[0]).unwrap();
+    let snapshot = txn.build_initial_snapshot();
+    let buf = snapshot.to_flat();
+    let flat = buf.as_flat();
+    assert!(matches!(
+        allocates_nothing(|| flat.get(&keys[1])),
+        Err(FlatError::Unvisited(_))
+    ));
+
+    // The keys first differ in their third word, so the root branch has a one word prefix.
+    let broken = FlatSnapshot::new(&buf.branches, &[], &buf.leaves, &buf.unvisited_nodes);
+    assert!(matches!(
+        allocates_nothing(|| broken.calc_root_hash(hasher)),
+        Err(FlatError::PrefixOutOfRange(_))
+    ));
+
+    let broken =
+        FlatSnapshot::<[u8; 8]>::new(&buf.branches, &buf.prefixes, &[], &buf.unvisited_nodes);
+    assert!(matches!(
+        allocates_nothing(|| broken.calc_root_hash(hasher)),
+        Err(FlatError::NodeNotFound(_))
+    ));
+
+    // A leaf is 40 bytes, cut the last one short.
+    let fixed_buf = snapshot.to_fixed_flat();
+    let leaves = &fixed_buf.leaves[..fixed_buf.leaves.len() - 1];
+    let broken = FixedFlatSnapshot::<8>::new(
+        &fixed_buf.branches,
+        &fixed_buf.prefixes,
+        leaves,
+        &fixed_buf.unvisited_nodes,
+    );
+    assert_eq!(
+        allocates_nothing(|| broken.calc_root_hash(hasher)),
+        Err(FlatError::MisalignedLeaves {
+            bytes: 39,
+            stride: 40
+        })
+    );
+}
+
+// One test per binary, so no other test allocates while the counts are taken.
+#[test]
+fn snapshot_root_verification_allocations_are_bounded() {
+    let (ret, counts) = ALLOC.count(|| vec![0u8; 10]);
+    assert_eq!(
+        counts,
+        AllocCounts {
+            allocations: 1,
+            bytes: 10
+        }
+    );
+    drop(ret);
+
+    // Verifying a root allocates one table of subtree hashes, however large the witness.
+    for (keys, stride) in [(1, 1), (16, 3), (1000, 7), (1000, 1)] {
+        let (snapshot, root) = witness(keys, stride);
+        let raw = RawSnapshot::new(&snapshot);
+        let nodes = raw.branches().len() + raw.leaves().len();
+        let counts = verify_root(&snapshot, root);
+        assert_eq!(counts.allocations, 1, "{keys} keys read every {stride}");
+        assert_eq!(
+            counts.bytes,
+            nodes * std::mem::size_of::<Option<NodeHash>>()
+        );
+
+        // Later calls reuse the table.
+        assert_eq!(verify_root(&snapshot, root), AllocCounts::default());
+    }
+
+    // The witness of the empty trie allocates nothing.
+    let (snapshot, root) = witness(0, 1);
+    assert_eq!(verify_root(&snapshot, root), AllocCounts::default());
+
+    for (keys, stride) in [(0, 1), (1, 1), (16, 3), (1000, 7)] {
+        flat_verification_allocates_nothing(keys, stride);
+    }
+    flat_errors_allocate_nothing();
+}
@@ -0,0 +1,181 @@
+use proptest::prelude::*;
+
+use kairos_trie::{
+    decode_value, spec::NodeLimits, BoundedDecode, DecodeError, PortableHash, PortableUpdate,
+    TrieError,
+};
+
+struct Encoding(Vec<u8>);
+
+impl PortableUpdate for Encoding {
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.extend_from_slice(data.as_ref());
+    }
+}
+
+fn encode(value: &impl PortableHash) -> Vec<u8> {
+    let mut encoding = Encoding(Vec::new());
+    value.portable_hash(&mut encoding);
+    encoding.0
+}
+
+fn round_trips<V: BoundedDecode + PartialEq + std::fmt::Debug>(
+    value: V,
+) -> Result<(), TestCaseError> {
+    let bytes = encode(&value);
+    prop_assert_eq!(decode_value::<V>(&bytes, bytes.len()).unwrap(), value);
+    if !bytes.is_empty() {
+        prop_assert_eq!(
+            decode_value::<V>(&bytes, bytes.len() - 1),
+            Err(DecodeError::TooLong {
+                bytes: bytes.len(),
+                max: bytes.len() - 1
+            })
+        );
+    }
+    Ok(())
+}
+
+type Account = (u64, Option<u32>, bool, String);
+
+proptest! {
+    #[test]
+    fn prop_round_trips(
+        int: (u8, i16, u32, i64, u128),
+        account: Account,
+        key: [u8; 32],
+        bytes: Vec<u8>,
+        c: char,
+    ) {
+        round_trips(int)?;
+        round_trips(account)?;
+        round_trips((key, bytes))?;
+        round_trips(c)?;
+        round_trips(())?;
+    }
+
+    #[test]
+    fn prop_arbitrary_bytes_never_panic(bytes: Vec<u8>) {
+        let _ = decode_value::<Account>(&bytes, usize::MAX);
+        let _ = decode_value::<(char, [u8; 3], Option<Option<bool>>)>(&bytes, usize::MAX);
+        let _ = decode_value::<u64>(&bytes, 4);
+    }
+}
+
+#[test]
+fn malformed_values_are_errors() {
+    assert_eq!(
+        decode_value::<u64>(&[1, 2, 3], 8),
+        Err(DecodeError::Truncated {
+            needed: 8,
+            remaining: 3
+        })
+    );
+    assert_eq!(
+        decode_value::<u32>(&[0; 5], 8),
+        Err(DecodeError::TrailingBytes(1))
+    );
+    assert!(matches!(
+        decode_value::<bool>(&[2], 1),
+        Err(DecodeError::Invalid(_))
+    ));
+    assert!(matches!(
+        decode_value::<Option<u8>>(&2u32.to_le_bytes(), 8),
+        Err(DecodeError::Invalid(_))
+    ));
+    assert!(matches!(
+        decode_value::<char>(&0xD800u32.to_le_bytes(), 8),
+        Err(DecodeError::Invalid(_))
+    ));
+    assert!(matches!(
+        decode_value::<String>(&[0xff], 8),
+        Err(DecodeError::Invalid(_))
+    ));
+
+    // The error is kept as the source of a `TrieError`.
+    let limits = NodeLimits {
+        max_value_bytes: 4,
+        ..NodeLimits::default()
+    };
+    let err =
+        TrieError::from(decode_value::<Vec<u8>>(&[0; 5], limits.max_value_bytes).unwrap_err());
+    assert_eq!(
+        err.downcast_source::<DecodeError>(),
+        Some(&DecodeError::TooLong { bytes: 5, max: 4 })
+    );
+}
+
+#[cfg(feature = "serde")]
+mod serde_witness {
+    use std::rc::Rc;
+
+    use kairos_trie::{
+        stored::{
+            memory_db::MemoryDb,
+            merkle::{Snapshot, SnapshotBuilder},
+        },
+        Bounded, DigestHasher, KeyHash, Transaction, TrieRoot,
+    };
+    use sha2::Sha256;
+
+    fn witness<V: Clone + kairos_trie::PortableHash>(
+        values: impl Fn(u64) -> V,
+    ) -> (Snapshot<V>, TrieRoot<kairos_trie::NodeHash>) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<V>::empty());
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for i in 0..32 {
+            txn.insert(&KeyHash::from_u64(i), values(i)).unwrap();
+        }
+        let root = txn.commit(hasher).unwrap().root;
+
+        let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for i in 0..32 {
+            txn.get(&KeyHash::from_u64(i)).unwrap();
+        }
+        (txn.build_initial_snapshot(), root)
+    }
+
+    #[test]
+    fn bounded_values_hash_like_their_contents() {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let account = |i: u64| (i, vec![i as u8; i as usize]);
+        let (_, plain_root) = witness(account);
+        let (snapshot, root) = witness(|i| Bounded::<_, 64>(account(i)));
+        assert_eq!(root, plain_root);
+
+        let bytes = bincode::serialize(&snapshot).unwrap();
+        let decoded: Snapshot<Bounded<(u64, Vec<u8>), 64>> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, snapshot);
+        assert_eq!(decoded.calc_root_hash(hasher).unwrap(), root);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let decoded: Snapshot<Bounded<(u64, Vec<u8>), 64>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, snapshot);
+    }
+
+    #[test]
+    fn malformed_witness_values_are_errors() {
+        // The longest value is 8 + 31 bytes.
+        let (snapshot, _) = witness(|i| Bounded::<_, 64>((i, vec![0u8; i as usize])));
+        let bytes = bincode::serialize(&snapshot).unwrap();
+        let err = bincode::deserialize::<Snapshot<Bounded<(u64, Vec<u8>), 32>>>(&bytes)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("longer than the limit of 32 bytes"), "{err}");
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let err = serde_json::from_str::<Snapshot<Bounded<(u64, Vec<u8>), 32>>>(&json)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("longer than the limit of 32 bytes"), "{err}");
+
+        let (snapshot, _) = witness(|i| Bounded::<u8>(i as u8));
+        let bytes = bincode::serialize(&snapshot).unwrap();
+        let err = bincode::deserialize::<Snapshot<Bounded<bool>>>(&bytes)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("a bool must be 0 or 1"), "{err}");
+    }
+}
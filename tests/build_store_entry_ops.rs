@@ -7,7 +7,7 @@ use kairos_trie::{
     stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
     KeyHash, Transaction, TrieRoot,
 };
-use utils::operations::*;
+use utils::{arb_structured_key_hash, operations::*};
 
 fn end_to_end_entry_ops(batches: Vec<Vec<Operation>>) {
     // The persistent backing, likely rocksdb
@@ -50,6 +50,18 @@ proptest! {
         batches in arb_batches(1..5000usize, 1..100_000usize, 1000, 10_000)) {
         end_to_end_entry_ops(batches);
     }
+
+    #[test]
+    fn prop_end_to_end_entry_ops_structured_keys(
+        batches in arb_batches_with_keys(arb_structured_key_hash(), 1..500usize, 1..5_000usize, 100, 1_000)) {
+        end_to_end_entry_ops(batches);
+    }
+
+    #[test]
+    fn prop_reused_builder_matches_fresh_builders(
+        batches in arb_batches(1..500usize, 1..5_000usize, 100, 1_000)) {
+        reused_builder_matches_fresh_builders(batches);
+    }
 }
 
 #[test]
@@ -103,3 +115,31 @@ fn leaf_prefix_entry_or_insert() {
 
     end_to_end_entry_ops(failed);
 }
+
+/// Resetting one builder between batches must behave exactly like building a new one per batch.
+fn reused_builder_matches_fresh_builders(batches: Vec<Vec<Operation>>) {
+    let fresh_db = Rc::new(MemoryDb::<[u8; 8]>::empty());
+    let reused_db = Rc::new(MemoryDb::<[u8; 8]>::empty());
+    let mut fresh_map = HashMap::new();
+    let mut reused_map = HashMap::new();
+
+    let mut prior_root_hash = TrieRoot::default();
+    let mut builder = SnapshotBuilder::empty(reused_db);
+
+    for batch in batches.iter() {
+        let (fresh_root_hash, fresh_snapshot) =
+            run_against_snapshot_builder(batch, prior_root_hash, fresh_db.clone(), &mut fresh_map);
+
+        let (new_root_hash, snapshot, used_builder) = run_against_builder(
+            batch,
+            builder.reset_to_root(prior_root_hash),
+            &mut reused_map,
+        );
+        builder = used_builder;
+
+        assert_eq!(new_root_hash, fresh_root_hash);
+        assert_eq!(snapshot, fresh_snapshot);
+
+        prior_root_hash = new_root_hash;
+    }
+}
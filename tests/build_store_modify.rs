@@ -6,8 +6,9 @@ use proptest::prelude::*;
 
 use kairos_trie::{
     stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
-    KeyHash, Transaction, TrieRoot,
+    DigestHasher, KeyHash, Transaction, TrieRoot,
 };
+use sha2::Sha256;
 use utils::{insert_get::*, *};
 
 prop_compose! {
@@ -25,6 +26,46 @@ proptest! {
     ) {
         end_to_end_example(maps)
     }
+
+    #[test]
+    fn prop_root_independent_of_insert_order(
+        keys in prop::collection::vec(arb_structured_key_hash(), 0..200),
+        split in any::<prop::sample::Index>(),
+    ) {
+        root_independent_of_insert_order(keys, split.index(usize::MAX))
+    }
+}
+
+/// The trie structure, and so the root, depends only on the set of keys and values,
+/// not on the order or batching of inserts.
+fn root_independent_of_insert_order(keys: Vec<KeyHash>, split: usize) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+    for key in keys.iter() {
+        txn.insert(key, key.0[0] as u64).unwrap();
+    }
+    let expected = txn.calc_root_hash(hasher).unwrap();
+
+    // Insert in reverse order over two commits.
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let split = split % (keys.len() + 1);
+    let (second, first) = keys.split_at(keys.len() - split);
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in first.iter().rev() {
+        txn.insert(key, key.0[0] as u64).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for key in second.iter().rev() {
+        *txn.entry(key).unwrap().or_default() = key.0[0] as u64;
+    }
+
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), expected);
 }
 
 #[test]
@@ -0,0 +1,115 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn committed_db() -> (Rc<MemoryDb<u64>>, TrieRoot<kairos_trie::NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..64 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (db, root)
+}
+
+#[test]
+fn builder_snapshots_do_not_depend_on_access_order() {
+    let (db, root) = committed_db();
+    let keys = [3, 60, 17, 42, 8];
+
+    let mut forward = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    for i in keys {
+        forward.get(&KeyHash::from_u64(i)).unwrap();
+    }
+    forward.insert(&KeyHash::from_u64(100), 100).unwrap();
+
+    let mut backward = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    backward.insert(&KeyHash::from_u64(100), 100).unwrap();
+    for i in keys.into_iter().rev() {
+        backward.get(&KeyHash::from_u64(i)).unwrap();
+    }
+
+    let snapshot = forward.build_initial_snapshot();
+    assert_eq!(snapshot, backward.build_initial_snapshot());
+    assert_eq!(snapshot.canonicalize().unwrap(), snapshot);
+}
+
+#[cfg(feature = "serde")]
+mod reordered {
+    use super::*;
+
+    use kairos_trie::{stored::merkle::Snapshot, stored::Idx, Branch, Leaf, NodeHash};
+
+    /// The fields of a `Snapshot`, in the order it serializes them.
+    type Parts = (Vec<Branch<Idx>>, Vec<Leaf<u64>>, Vec<NodeHash>);
+
+    fn to_parts(snapshot: &Snapshot<u64>) -> Parts {
+        bincode::deserialize(&bincode::serialize(snapshot).unwrap()).unwrap()
+    }
+
+    fn from_parts(parts: &Parts) -> Snapshot<u64> {
+        bincode::deserialize(&bincode::serialize(parts).unwrap()).unwrap()
+    }
+
+    fn snapshot() -> Snapshot<u64> {
+        let (db, root) = committed_db();
+        let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for i in [1, 5, 9, 33] {
+            txn.get(&KeyHash::from_u64(i)).unwrap();
+        }
+        txn.build_initial_snapshot()
+    }
+
+    #[test]
+    fn canonicalize_restores_the_builder_order() {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let snapshot = snapshot();
+
+        // Reverse the leaves, and add an unvisited node no branch points to.
+        let (mut branches, mut leaves, mut unvisited) = to_parts(&snapshot);
+        let leaf_start = branches.len() as Idx;
+        let leaf_end = leaf_start + leaves.len() as Idx;
+        leaves.reverse();
+        for branch in branches.iter_mut() {
+            for child in [&mut branch.left, &mut branch.right] {
+                if (leaf_start..leaf_end).contains(child) {
+                    *child = leaf_end - 1 - (*child - leaf_start);
+                }
+            }
+        }
+        unvisited.push(NodeHash::new([7; 32]));
+        let reordered = from_parts(&(branches, leaves, unvisited));
+
+        assert_ne!(reordered, snapshot);
+        assert_eq!(
+            reordered.calc_root_hash(hasher).unwrap(),
+            snapshot.calc_root_hash(hasher).unwrap()
+        );
+
+        let canonical = reordered.canonicalize().unwrap();
+        assert_eq!(canonical, snapshot);
+        assert_eq!(
+            bincode::serialize(&canonical).unwrap(),
+            bincode::serialize(&snapshot).unwrap()
+        );
+    }
+
+    #[test]
+    fn canonicalize_rejects_shared_nodes() {
+        let (mut branches, leaves, unvisited) = to_parts(&snapshot());
+        let root = branches.len() - 1;
+        branches[root].right = branches[root].left;
+
+        assert!(from_parts(&(branches, leaves, unvisited))
+            .canonicalize()
+            .is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    casper::{execute_deploys, GlobalState, StateCodec},
+    stored::{memory_db::MemoryDb, merkle::Snapshot},
+    DigestHasher, TrieError, TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+
+/// Stand ins for Casper's `Key` and `StoredValue`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Key {
+    Account([u8; 4]),
+    Balance([u8; 4]),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum StoredValue {
+    Account(String),
+    Balance(u64),
+}
+
+/// A stand in for `bytesrepr`.
+struct Bytesrepr;
+
+impl StateCodec for Bytesrepr {
+    type Key = Key;
+    type Value = StoredValue;
+    type Error = String;
+
+    fn key_to_bytes(key: &Key) -> Result<Vec<u8>, String> {
+        Ok(match key {
+            Key::Account(addr) => [&[0], &addr[..]].concat(),
+            Key::Balance(addr) => [&[1], &addr[..]].concat(),
+        })
+    }
+
+    fn value_to_bytes(value: &StoredValue) -> Result<Vec<u8>, String> {
+        Ok(match value {
+            StoredValue::Account(name) => [&[0], name.as_bytes()].concat(),
+            StoredValue::Balance(balance) => [&[1], &balance.to_le_bytes()[..]].concat(),
+        })
+    }
+
+    fn value_from_bytes(bytes: &[u8]) -> Result<StoredValue, String> {
+        match bytes.split_first() {
+            Some((0, name)) => String::from_utf8(name.to_vec())
+                .map(StoredValue::Account)
+                .map_err(|e| e.to_string()),
+            Some((1, balance)) => balance
+                .try_into()
+                .map(|balance| StoredValue::Balance(u64::from_le_bytes(balance)))
+                .map_err(|_| "Invalid balance".to_string()),
+            _ => Err("Unknown tag".to_string()),
+        }
+    }
+}
+
+type Hasher = DigestHasher<Sha256>;
+
+/// Move `amount` from one account's balance to another's, creating the recipient's account if needed.
+fn transfer<S: kairos_trie::stored::Store<Vec<u8>>>(
+    state: &mut GlobalState<S, Bytesrepr, Hasher>,
+    (from, to, amount): ([u8; 4], [u8; 4], u64),
+) -> Result<(), TrieError> {
+    let Some(StoredValue::Balance(from_balance)) = state.read(&Key::Balance(from))? else {
+        return Err("Sender has no balance".into());
+    };
+    let to_balance = match state.read(&Key::Balance(to))? {
+        Some(StoredValue::Balance(balance)) => balance,
+        _ => {
+            state.write(&Key::Account(to), &StoredValue::Account(format!("{to:?}")))?;
+            0
+        }
+    };
+
+    state.write(
+        &Key::Balance(from),
+        &StoredValue::Balance(from_balance - amount),
+    )?;
+    state.write(
+        &Key::Balance(to),
+        &StoredValue::Balance(to_balance + amount),
+    )?;
+
+    if from_balance == amount {
+        state.prune(&Key::Balance(from))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn deploy_witnesses_replay_in_the_guest() {
+    let hasher = &mut Hasher::default();
+    let db = Rc::new(MemoryDb::<Vec<u8>>::empty());
+
+    // Genesis
+    let [genesis] = execute_deploys(
+        db.clone(),
+        TrieRoot::Empty,
+        hasher,
+        [()],
+        |state: &mut GlobalState<_, Bytesrepr, Hasher>, ()| {
+            state.write(
+                &Key::Account([0; 4]),
+                &StoredValue::Account("genesis".into()),
+            )?;
+            state.write(&Key::Balance([0; 4]), &StoredValue::Balance(100))
+        },
+    )
+    .unwrap()
+    .try_into()
+    .unwrap();
+
+    let deploys = [
+        ([0; 4], [1; 4], 30),
+        ([1; 4], [2; 4], 30),
+        ([0; 4], [2; 4], 70),
+    ];
+    let witnesses =
+        execute_deploys(db, genesis.post_state_root, hasher, deploys, transfer::<_>).unwrap();
+
+    assert_eq!(witnesses.len(), deploys.len());
+    assert_eq!(witnesses[0].pre_state_root, genesis.post_state_root);
+    for (witness, next) in witnesses.iter().zip(witnesses.iter().skip(1)) {
+        assert_eq!(witness.post_state_root, next.pre_state_root);
+    }
+
+    for (witness, deploy) in witnesses.iter().zip(deploys) {
+        witness
+            .replay(
+                hasher,
+                |state: &mut GlobalState<&Snapshot<Vec<u8>>, Bytesrepr, Hasher>| {
+                    transfer(state, deploy)
+                },
+            )
+            .unwrap();
+    }
+
+    // The guest can read back what the deploy wrote.
+    let last = witnesses.last().unwrap();
+    last.replay(hasher, |state: &mut GlobalState<_, Bytesrepr, Hasher>| {
+        transfer(state, deploys[2])?;
+        assert_eq!(
+            state.read(&Key::Balance([2; 4]))?,
+            Some(StoredValue::Balance(100))
+        );
+        Ok(())
+    })
+    .unwrap();
+
+    // Replaying a different deploy is caught.
+    assert!(matches!(
+        witnesses[1].replay(hasher, |state: &mut GlobalState<_, Bytesrepr, Hasher>| {
+            transfer(state, ([1; 4], [2; 4], 29))
+        }),
+        Err(VerifyError::NewRootMismatch { .. })
+    ));
+}
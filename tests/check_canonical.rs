@@ -0,0 +1,79 @@
+#![cfg(feature = "serde")]
+
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+        Idx,
+    },
+    Branch, DigestHasher, KeyHash, Leaf, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+/// The fields of a `Snapshot`, in the order it serializes them.
+type Parts = (Vec<Branch<Idx>>, Vec<Leaf<u64>>, Vec<NodeHash>);
+
+fn to_parts(snapshot: &Snapshot<u64>) -> Parts {
+    bincode::deserialize(&bincode::serialize(snapshot).unwrap()).unwrap()
+}
+
+fn from_parts(parts: &Parts) -> Snapshot<u64> {
+    bincode::deserialize(&bincode::serialize(parts).unwrap()).unwrap()
+}
+
+fn snapshot() -> Snapshot<u64> {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..64 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for i in [2, 7, 19, 40, 63] {
+        txn.get(&KeyHash::from_u64(i)).unwrap();
+    }
+    txn.build_initial_snapshot()
+}
+
+#[test]
+fn builder_snapshots_are_canonical() {
+    snapshot().check_canonical().unwrap();
+    from_parts(&(vec![], vec![], vec![]))
+        .check_canonical()
+        .unwrap();
+}
+
+#[test]
+fn rejects_a_root_prefix_of_the_wrong_length() {
+    let (mut branches, leaves, unvisited) = to_parts(&snapshot());
+    let root = branches.len() - 1;
+    let mut prefix = branches[root].prefix.to_vec();
+    prefix.push(0);
+    branches[root].prefix = prefix.into();
+
+    assert!(from_parts(&(branches, leaves, unvisited))
+        .check_canonical()
+        .is_err());
+}
+
+#[test]
+fn rejects_a_leaf_on_the_wrong_side() {
+    let (branches, mut leaves, unvisited) = to_parts(&snapshot());
+
+    // Two keys part at the branch above both, so swapping them puts each on the other's side.
+    let last = leaves.len() - 1;
+    let key = leaves[0].key_hash;
+    leaves[0].key_hash = leaves[last].key_hash;
+    leaves[last].key_hash = key;
+
+    assert!(from_parts(&(branches, leaves, unvisited))
+        .check_canonical()
+        .is_err());
+}
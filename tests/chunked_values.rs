@@ -0,0 +1,121 @@
+mod utils;
+
+use std::{collections::BTreeMap, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    ChunkConfig, ChunkProof, ChunkedValue, DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_key_hash;
+
+const CONFIG: ChunkConfig = ChunkConfig {
+    max_inline_len: 16,
+    chunk_len: 7,
+    max_value_len: 1024,
+};
+
+proptest! {
+    #[test]
+    fn prop_get_chunk_round_trip(
+        values in prop::collection::btree_map(arb_key_hash(), prop::collection::vec(any::<u8>(), 0..200), 1..20),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::empty());
+        let mut chunk_db = BTreeMap::new();
+
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for (key, bytes) in values.iter() {
+            let (value, chunks) = CONFIG.encode(hasher, bytes).unwrap();
+            prop_assert_eq!(value.len(), bytes.len() as u64);
+            prop_assert_eq!(chunks.is_some(), bytes.len() > CONFIG.max_inline_len);
+
+            if let Some(chunks) = chunks {
+                chunk_db.insert(chunks.root(), chunks);
+            }
+            txn.insert(key, value).unwrap();
+        }
+        let root = txn.commit(hasher).unwrap().root;
+
+        // Read every chunk on the server, then check each one in the guest.
+        let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        let mut reads = Vec::new();
+        for (key, bytes) in values.iter() {
+            let chunk_count = txn.get(key).unwrap().unwrap().chunk_count();
+            for idx in 0..chunk_count {
+                let (chunk, proof) = txn.get_chunk(key, idx, &chunk_db).unwrap().unwrap();
+                let expected: &[u8] = if bytes.len() > CONFIG.max_inline_len {
+                    bytes.chunks(CONFIG.chunk_len).nth(idx as usize).unwrap()
+                } else {
+                    bytes
+                };
+                prop_assert_eq!(&*chunk, expected);
+                reads.push((*key, idx, chunk, proof));
+            }
+        }
+
+        let snapshot = txn.build_initial_snapshot();
+        prop_assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+
+        let txn = Transaction::from_snapshot(&snapshot).unwrap();
+        for (key, idx, chunk, proof) in reads {
+            let value = txn.get(&key).unwrap().unwrap();
+            value.verify_chunk(hasher, idx, &chunk, &proof).unwrap();
+        }
+    }
+}
+
+#[test]
+fn verify_chunk_rejects_tampering() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let bytes: Vec<u8> = (0..=255).collect();
+
+    let (value, chunks) = CONFIG.encode(hasher, &bytes).unwrap();
+    let chunks = chunks.unwrap();
+    assert_eq!(value.chunk_count(), 37);
+
+    let (chunk, proof) = chunks.prove(36).unwrap();
+    value.verify_chunk(hasher, 36, chunk, &proof).unwrap();
+
+    // A different chunk, index, or proof must not verify.
+    assert!(value.verify_chunk(hasher, 36, &[0], &proof).is_err());
+    assert!(value.verify_chunk(hasher, 35, chunk, &proof).is_err());
+    assert!(value.verify_chunk(hasher, 37, chunk, &proof).is_err());
+
+    let mut siblings = proof.siblings.to_vec();
+    siblings[0] = NodeHash::new([0; 32]);
+    let bad_proof = ChunkProof {
+        siblings: siblings.into_boxed_slice(),
+    };
+    assert!(value.verify_chunk(hasher, 36, chunk, &bad_proof).is_err());
+
+    let (inline, _) = CONFIG.encode(hasher, &bytes[..4]).unwrap();
+    assert_eq!(inline, ChunkedValue::Inline(bytes[..4].to_vec()));
+    assert!(inline
+        .verify_chunk(hasher, 0, &bytes[..4], &ChunkProof::default())
+        .is_ok());
+    assert!(inline
+        .verify_chunk(hasher, 0, &bytes[..3], &ChunkProof::default())
+        .is_err());
+}
+
+#[test]
+fn encode_rejects_values_over_the_limit() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    assert!(CONFIG.encode(hasher, &[0; 1024]).is_ok());
+    assert!(CONFIG.encode(hasher, &[0; 1025]).is_err());
+
+    let zero_chunks = ChunkConfig {
+        chunk_len: 0,
+        ..CONFIG
+    };
+    assert!(zero_chunks.encode(hasher, &[0; 64]).is_err());
+
+    let missing = KeyHash([1; 8]);
+    let db = Rc::new(MemoryDb::<ChunkedValue>::empty());
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, TrieRoot::Empty));
+    assert_eq!(txn.get_chunk(&missing, 0, &BTreeMap::new()).unwrap(), None);
+}
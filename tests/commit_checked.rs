@@ -0,0 +1,129 @@
+use std::{cell::Cell, rc::Rc};
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, DatabaseGet, DatabaseSet},
+    Branch, DigestHasher, KeyHash, Leaf, Node, NodeHash, Transaction, TrieError, TrieRoot,
+};
+use sha2::Sha256;
+
+/// A database that stores some nodes wrong, and counts its reads.
+struct MisEncodingDb {
+    db: MemoryDb<u64>,
+    /// Store the value of this key off by one.
+    bad_value: Option<KeyHash>,
+    /// Store every branch with its children swapped.
+    bad_branches: bool,
+    gets: Cell<u64>,
+}
+
+impl MisEncodingDb {
+    fn new(bad_value: Option<u64>, bad_branches: bool) -> Rc<Self> {
+        Rc::new(MisEncodingDb {
+            db: MemoryDb::empty(),
+            bad_value: bad_value.map(KeyHash::from_u64),
+            bad_branches,
+            gets: Cell::new(0),
+        })
+    }
+}
+
+impl DatabaseGet<u64> for MisEncodingDb {
+    type GetError = TrieError;
+
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<u64>>, TrieError> {
+        self.gets.set(self.gets.get() + 1);
+        self.db.get(hash).map_err(Into::into)
+    }
+}
+
+impl DatabaseSet<u64> for MisEncodingDb {
+    type SetError = TrieError;
+
+    fn set(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<u64>>,
+    ) -> Result<(), TrieError> {
+        let node = match node {
+            Node::Leaf(mut leaf) if Some(leaf.key_hash) == self.bad_value => {
+                leaf.value += 1;
+                Node::Leaf(leaf)
+            }
+            Node::Branch(branch) if self.bad_branches => Node::Branch(Branch {
+                left: branch.right,
+                right: branch.left,
+                ..branch
+            }),
+            node => node,
+        };
+        self.db.set(hash, node).map_err(Into::into)
+    }
+}
+
+fn commit_checked(
+    db: &Rc<MisEncodingDb>,
+    sample_rate: f64,
+) -> Result<TrieRoot<NodeHash>, TrieError> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..100 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    txn.commit_checked(&mut DigestHasher::<Sha256>::default(), sample_rate)
+        .map(|receipt| receipt.root)
+}
+
+#[test]
+fn commit_checked_matches_commit() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    assert_eq!(
+        txn.commit_checked(hasher, 1.0).unwrap().root,
+        TrieRoot::Empty
+    );
+
+    for i in 0..100 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let checked = txn.commit_checked(hasher, 1.0).unwrap();
+    let receipt = txn.commit(hasher).unwrap();
+    assert_eq!(checked.root, receipt.root);
+    assert_eq!(checked.leaves_inserted, 100);
+
+    // Only the keys this transaction wrote are sampled.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, receipt.root));
+    txn.insert(&KeyHash::from_u64(200), 200).unwrap();
+    assert_eq!(txn.commit_checked(hasher, 1.0).unwrap().leaves_inserted, 1);
+}
+
+#[test]
+fn commit_checked_catches_mis_encoded_nodes() {
+    let bad_value = MisEncodingDb::new(Some(42), false);
+    let error = commit_checked(&bad_value, 1.0).unwrap_err();
+    assert!(error.display().starts_with("Commit check failed"));
+
+    let bad_branches = MisEncodingDb::new(None, true);
+    let error = commit_checked(&bad_branches, 1.0).unwrap_err();
+    assert!(error.display().starts_with("Commit check failed"));
+
+    // Sampling nothing checks nothing.
+    let bad_value = MisEncodingDb::new(Some(42), false);
+    assert!(commit_checked(&bad_value, 0.0).is_ok());
+}
+
+#[test]
+fn commit_checked_reads_in_proportion_to_the_rate() {
+    let gets = |sample_rate| {
+        let db = MisEncodingDb::new(None, false);
+        commit_checked(&db, sample_rate).unwrap();
+        db.gets.get()
+    };
+
+    let (none, some, all) = (gets(0.0), gets(0.2), gets(1.0));
+    assert_eq!(none, 0);
+    assert!(0 < some && some < all, "{none} {some} {all}");
+    assert_eq!(gets(f64::NAN), 0);
+    assert_eq!(gets(2.0), all);
+}
@@ -0,0 +1,208 @@
+mod utils;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    rc::Rc,
+};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, DatabaseGet},
+    CommitEvent, DigestHasher, KeyHash, Leaf, Node, NodeHash, StorageUsage, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_structured_key_hash;
+
+/// Every node hash reachable from `root` in `db`.
+fn reachable(db: &MemoryDb<u64>, root: TrieRoot<NodeHash>) -> BTreeSet<NodeHash> {
+    let mut hashes = BTreeSet::new();
+    let mut stack: Vec<NodeHash> = match root {
+        TrieRoot::Node(hash) => vec![hash],
+        TrieRoot::Empty => vec![],
+    };
+
+    while let Some(hash) = stack.pop() {
+        hashes.insert(hash);
+        if let Node::Branch(branch) = db.get(&hash).unwrap() {
+            stack.push(branch.left);
+            stack.push(branch.right);
+        }
+    }
+
+    hashes
+}
+
+proptest! {
+    #[test]
+    fn prop_commit_events_track_reachability(
+        keys in prop::collection::vec(arb_structured_key_hash(), 1..100),
+        ops in prop::collection::vec((any::<prop::sample::Index>(), any::<bool>(), 0..3u64), 0..50),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for key in keys.iter() {
+            txn.insert(key, 0).unwrap();
+        }
+        let old_root = txn.commit(hasher).unwrap().root;
+
+        let old_map: BTreeMap<KeyHash, u64> = keys.iter().map(|key| (*key, 0)).collect();
+        let mut new_map = old_map.clone();
+        let mut inserted = BTreeSet::new();
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+        for (idx, remove, value) in ops {
+            let key = keys[idx.index(keys.len())];
+            if remove {
+                txn.remove(&key).unwrap();
+                new_map.remove(&key);
+            } else {
+                txn.insert(&key, value).unwrap();
+                new_map.insert(key, value);
+                inserted.insert(key);
+            }
+        }
+
+        let mut written = BTreeSet::new();
+        let mut orphaned = BTreeSet::new();
+        let new_root = txn
+            .commit_with_events(hasher, &mut |event| {
+                match event {
+                    CommitEvent::Written(hash) => assert!(written.insert(hash)),
+                    CommitEvent::Orphaned(hash) => assert!(orphaned.insert(hash)),
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let old_nodes = reachable(&db, old_root);
+        let new_nodes = reachable(&db, new_root);
+
+        prop_assert!(written.is_subset(&new_nodes));
+        prop_assert!(orphaned.is_subset(&old_nodes));
+        // Nodes rewritten unchanged are both written and orphaned.
+        let dropped: BTreeSet<_> = old_nodes.difference(&new_nodes).copied().collect();
+        let net_orphaned: BTreeSet<_> = orphaned.difference(&written).copied().collect();
+        prop_assert_eq!(net_orphaned, dropped);
+
+        // Usage counts the same nodes the events report.
+        let (usage_root, usage) = txn.commit_with_usage(hasher, |_| 40).unwrap();
+        prop_assert_eq!(usage_root, new_root);
+        prop_assert_eq!(usage.nodes_written, written.len() as u64);
+        prop_assert_eq!(usage.nodes_orphaned, orphaned.len() as u64);
+        prop_assert_eq!(
+            usage.node_delta(),
+            new_nodes.len() as i128 - old_nodes.len() as i128
+        );
+
+        // So does the receipt, and every key still inserted is written.
+        let written_keys: BTreeSet<_> = inserted
+            .into_iter()
+            .filter(|key| new_map.contains_key(key))
+            .collect();
+        let updated = written_keys
+            .iter()
+            .filter(|key| old_map.contains_key(key))
+            .count() as u64;
+        let receipt = txn.commit(hasher).unwrap();
+        prop_assert_eq!(receipt.root, new_root);
+        prop_assert_eq!(receipt.nodes_written, written.len() as u64);
+        prop_assert_eq!(receipt.leaves_updated, updated);
+        prop_assert_eq!(receipt.leaves_inserted, written_keys.len() as u64 - updated);
+        prop_assert_eq!(
+            receipt.leaves_removed,
+            old_map.keys().filter(|key| !new_map.contains_key(key)).count() as u64
+        );
+        prop_assert!(receipt.elapsed.is_some());
+    }
+}
+
+#[test]
+fn removing_keys_orphans_their_path() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let keys = [KeyHash([0; 8]), KeyHash([1, 0, 0, 0, 0, 0, 0, 0])];
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for (i, key) in keys.iter().enumerate() {
+        txn.insert(key, i as u64).unwrap();
+    }
+    let old_root = txn.commit(hasher).unwrap().root;
+    let old_nodes = reachable(&db, old_root);
+    assert_eq!(old_nodes.len(), 3);
+
+    // Reads alone orphan nothing.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+    txn.get(&keys[0]).unwrap();
+    let mut events = Vec::new();
+    let root = txn
+        .commit_with_events(hasher, &mut |event| {
+            events.push(event);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(root, old_root);
+    assert!(events.is_empty());
+
+    // Removing a key orphans the old root branch and the removed leaf, the sibling leaf becomes the root.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+    txn.remove(&keys[0]).unwrap();
+    let mut events = Vec::new();
+    let new_root = txn
+        .commit_with_events(hasher, &mut |event| {
+            events.push(event);
+            Ok(())
+        })
+        .unwrap();
+
+    let TrieRoot::Node(new_root_hash) = new_root else {
+        panic!("One key remains");
+    };
+    assert!(old_nodes.contains(&new_root_hash));
+    let orphaned: BTreeSet<_> = events
+        .iter()
+        .map(|event| match event {
+            CommitEvent::Orphaned(hash) => *hash,
+            CommitEvent::Written(_) => panic!("Nothing is modified"),
+        })
+        .collect();
+    assert_eq!(orphaned.len(), 2);
+    assert!(!orphaned.contains(&new_root_hash));
+    assert!(orphaned.is_subset(&old_nodes));
+}
+
+#[test]
+fn commit_with_usage_accounts_leaf_bytes() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let keys = [KeyHash([0; 8]), KeyHash([1, 0, 0, 0, 0, 0, 0, 0])];
+    // The key and an 8 byte value.
+    let leaf_len = |_: &Leaf<u64>| 40;
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for (i, key) in keys.iter().enumerate() {
+        txn.insert(key, i as u64).unwrap();
+    }
+    let (old_root, usage) = txn.commit_with_usage(hasher, leaf_len).unwrap();
+    assert_eq!(
+        usage,
+        StorageUsage {
+            leaf_bytes_written: 80,
+            leaf_bytes_removed: 0,
+            nodes_written: 3,
+            nodes_orphaned: 0,
+        }
+    );
+
+    // Removing a key frees its leaf and the root branch.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, old_root));
+    txn.remove(&keys[0]).unwrap();
+    let (_, usage) = txn.commit_with_usage(hasher, leaf_len).unwrap();
+    assert_eq!(usage.leaf_bytes_removed, 40);
+    assert_eq!(usage.leaf_bytes_delta(), -40);
+    assert_eq!(usage.node_delta(), -2);
+}
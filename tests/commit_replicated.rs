@@ -0,0 +1,103 @@
+use std::{cell::Cell, rc::Rc};
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, DatabaseGet, DatabaseSet},
+    Branch, DigestHasher, KeyHash, Leaf, Node, NodeHash, ReplicationMode, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+/// A store that rejects every write after the first `capacity`.
+struct FullDb {
+    capacity: u64,
+    writes: Cell<u64>,
+}
+
+impl DatabaseGet<u64> for FullDb {
+    type GetError = String;
+
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<u64>>, String> {
+        Err(format!("{hash} not found"))
+    }
+}
+
+impl DatabaseSet<u64> for FullDb {
+    type SetError = String;
+
+    fn set(&self, _: NodeHash, _: Node<Branch<NodeHash>, Leaf<u64>>) -> Result<(), String> {
+        if self.writes.get() >= self.capacity {
+            return Err("store is full".to_owned());
+        }
+        self.writes.set(self.writes.get() + 1);
+        Ok(())
+    }
+}
+
+fn txn_with_keys(
+    db: &Rc<MemoryDb<u64>>,
+    keys: std::ops::Range<u64>,
+) -> Transaction<SnapshotBuilder<Rc<MemoryDb<u64>>, u64>, u64> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in keys {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    txn
+}
+
+#[test]
+fn secondaries_receive_every_node() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let primary = Rc::new(MemoryDb::<u64>::empty());
+    let secondary = MemoryDb::<u64>::empty();
+    let full = FullDb {
+        capacity: 10,
+        writes: Cell::new(0),
+    };
+
+    let txn = txn_with_keys(&primary, 0..50);
+    let commit = txn
+        .commit_replicated(hasher, &[&secondary, &full], ReplicationMode::BestEffort)
+        .unwrap();
+    assert_eq!(commit.root, txn.calc_root_hash(hasher).unwrap());
+    // 50 leaves and 49 branches.
+    assert_eq!(commit.nodes_written, 99);
+
+    // The full store failed every write after its first 10, and nothing else did.
+    assert_eq!(commit.failures.len(), 89);
+    assert!(commit.failures.iter().all(|f| f.replica == 1));
+    assert_eq!(full.writes.get(), 10);
+
+    // The secondary holds the whole trie.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(&secondary, commit.root));
+    for i in 0..50 {
+        assert_eq!(txn.get(&KeyHash::from_u64(i)).unwrap(), Some(&i));
+    }
+}
+
+#[test]
+fn all_or_nothing_fails_on_the_first_failed_write() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let primary = Rc::new(MemoryDb::<u64>::empty());
+    let full = FullDb {
+        capacity: 10,
+        writes: Cell::new(0),
+    };
+
+    let txn = txn_with_keys(&primary, 0..50);
+    let err = txn
+        .commit_replicated(hasher, &[&full], ReplicationMode::AllOrNothing)
+        .unwrap_err();
+    assert!(format!("{err}").contains("secondary database 0"));
+    assert_eq!(full.writes.get(), 10);
+
+    // With room for every node the same commit succeeds.
+    let roomy = FullDb {
+        capacity: u64::MAX,
+        writes: Cell::new(0),
+    };
+    let commit = txn
+        .commit_replicated(hasher, &[&roomy], ReplicationMode::AllOrNothing)
+        .unwrap();
+    assert!(commit.failures.is_empty());
+    assert_eq!(roomy.writes.get(), commit.nodes_written);
+}
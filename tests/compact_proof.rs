@@ -0,0 +1,101 @@
+mod utils;
+
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    verify_from_bytes, DigestHasher, EncodeError, KeyHash, Transaction, TrieRoot,
+};
+use sha2::{Digest, Sha256};
+use utils::arb_structured_key_hash;
+
+fn txn_with(keys: &[KeyHash]) -> Transaction<SnapshotBuilder<Rc<MemoryDb<u64>>, u64>, u64> {
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::empty()),
+        TrieRoot::Empty,
+    ));
+    for (i, key) in keys.iter().enumerate() {
+        txn.insert(key, i as u64).unwrap();
+    }
+    txn
+}
+
+proptest! {
+    #[test]
+    fn prop_compact_proofs_verify(
+        keys in prop::collection::btree_set(arb_structured_key_hash(), 1..100),
+        idx in any::<prop::sample::Index>(),
+        flip in any::<prop::sample::Index>(),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let keys: Vec<_> = keys.into_iter().collect();
+        let txn = txn_with(&keys);
+        let root = txn.calc_root_hash(hasher).unwrap();
+
+        let idx = idx.index(keys.len());
+        let proof = txn.prove_inclusion(&keys[idx], hasher).unwrap().unwrap();
+        let bytes = proof.encode_compact().unwrap();
+
+        let (key, value) = verify_from_bytes(hasher, &bytes, root).unwrap();
+        prop_assert_eq!(key, keys[idx]);
+        prop_assert_eq!(value, &(idx as u64).to_le_bytes()[..]);
+
+        let fixed = proof.encode_fixed::<4096>().unwrap();
+        prop_assert_eq!(verify_from_bytes(hasher, &fixed, root).unwrap(), (key, value));
+
+        // Any change to the meaningful bytes is caught.
+        let mut tampered = bytes.clone();
+        tampered[flip.index(bytes.len())] ^= 1;
+        prop_assert!(verify_from_bytes(hasher, &tampered, root).is_err());
+    }
+}
+
+#[test]
+fn compact_proofs_are_small() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let keys: Vec<_> = (0..1024)
+        .map(|i: u64| KeyHash::from_bytes(&Sha256::digest(i.to_le_bytes()).into()))
+        .collect();
+    let txn = txn_with(&keys);
+    let root = txn.calc_root_hash(hasher).unwrap();
+
+    let proof = txn.prove_inclusion(&keys[7], hasher).unwrap().unwrap();
+    let bytes = proof.encode_compact().unwrap();
+    // The key, the value and its length, the depth, then a bit index and sibling per branch.
+    assert_eq!(bytes.len(), 32 + 1 + 8 + 1 + proof.path.len() * 33);
+
+    let fixed = proof.encode_fixed::<512>().unwrap();
+    verify_from_bytes(hasher, &fixed, root).unwrap();
+    assert_eq!(
+        proof.encode_fixed::<64>(),
+        Err(EncodeError::TooLong {
+            len: bytes.len(),
+            capacity: 64
+        })
+    );
+
+    assert!(verify_from_bytes(hasher, &bytes[..bytes.len() - 1], root).is_err());
+    assert!(verify_from_bytes(hasher, &bytes, TrieRoot::Empty).is_err());
+}
+
+#[test]
+fn single_leaf_proofs_have_no_branches() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let key = KeyHash::from_u64(5);
+    let txn = txn_with(&[key]);
+    let root = txn.calc_root_hash(hasher).unwrap();
+
+    let bytes = txn
+        .prove_inclusion(&key, hasher)
+        .unwrap()
+        .unwrap()
+        .encode_compact()
+        .unwrap();
+    assert_eq!(bytes.len(), 32 + 1 + 8 + 1);
+    assert_eq!(
+        verify_from_bytes(hasher, &bytes, root).unwrap(),
+        (key, 0u64.to_le_bytes().as_slice())
+    );
+}
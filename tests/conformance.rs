@@ -0,0 +1,39 @@
+#![cfg(feature = "test-utils")]
+
+use kairos_trie::{
+    stored::{
+        conformance::{check_database, ConformanceConfig},
+        faulty::{Fault, FaultyDb},
+        memory_db::MemoryDb,
+    },
+    DigestHasher,
+};
+use sha2::Sha256;
+
+const CONFIG: ConformanceConfig = ConformanceConfig {
+    seed: 7,
+    rounds: 16,
+    ops_per_round: 32,
+};
+
+#[test]
+fn memory_db_conforms() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = MemoryDb::<Vec<u8>>::empty();
+
+    let report = check_database(&db, hasher, CONFIG).unwrap();
+    assert_eq!(report.rounds, CONFIG.rounds);
+    assert!(report.entries > 0);
+}
+
+#[test]
+fn faulty_dbs_do_not_conform() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    for fault in [Fault::Error, Fault::WrongNode] {
+        let db = FaultyDb::every(MemoryDb::<Vec<u8>>::empty(), 97, 500, 10_000, fault);
+        let violation = check_database(&db, hasher, CONFIG).unwrap_err();
+        assert_eq!(violation.seed, CONFIG.seed);
+        assert!(violation.to_string().contains("round"), "{violation}");
+    }
+}
@@ -0,0 +1,266 @@
+#![cfg(feature = "serde")]
+
+use std::rc::Rc;
+
+use kairos_trie::{
+    prove_range,
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+        Idx,
+    },
+    walk, Branch, BranchMask, DigestHasher, FlatError, KeyHash, Leaf, NodeHash, TaggedHasher,
+    Transaction, TrieKey, TrieParams, TrieRoot, Visitor,
+};
+use sha2::Sha256;
+
+struct NoopVisitor;
+
+impl Visitor<u64> for NoopVisitor {}
+
+struct Counted;
+
+impl TrieParams for Counted {
+    const LEAF_TAG: &'static [u8] = b"counted/leaf";
+    const BRANCH_TAG: &'static [u8] = b"counted/branch";
+    const COUNTED: bool = true;
+}
+
+/// The fields of a `Snapshot`, in the order it serializes them.
+type Parts = (Vec<Branch<Idx>>, Vec<Leaf<u64>>, Vec<NodeHash>);
+
+fn to_parts(snapshot: &Snapshot<u64>) -> Parts {
+    bincode::deserialize(&bincode::serialize(snapshot).unwrap()).unwrap()
+}
+
+fn from_parts(parts: &Parts) -> Snapshot<u64> {
+    bincode::deserialize(&bincode::serialize(parts).unwrap()).unwrap()
+}
+
+fn snapshot() -> (Snapshot<u64>, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..64 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for i in [2, 7, 19, 40, 63] {
+        txn.get(&KeyHash::from_u64(i)).unwrap();
+    }
+    (txn.build_initial_snapshot(), root)
+}
+
+/// 32 branches on the bits of the first word, each with the next as both children,
+/// so a walk that doesn't notice the sharing takes 2^32 paths to the one leaf.
+fn shared_chain() -> Parts {
+    let leaf_idx = 32;
+    let branches = (0..32)
+        .map(|i: Idx| {
+            let child = if i == 0 { leaf_idx } else { i - 1 };
+            Branch {
+                left: child,
+                right: child,
+                mask: BranchMask::new(0, 0, 1 << (31 - i)),
+                prior_word: 0,
+                prefix: Box::default(),
+            }
+        })
+        .collect();
+    let leaf = Leaf {
+        key_hash: KeyHash([0; 8]),
+        value: 0,
+    };
+    (branches, vec![leaf], vec![])
+}
+
+#[test]
+fn a_branch_cycle_is_an_error() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (snapshot, root) = snapshot();
+    let (mut branches, leaves, unvisited) = to_parts(&snapshot);
+    let root_idx = branches.len() - 1;
+    branches[root_idx].left = root_idx as Idx;
+    branches[root_idx].right = root_idx as Idx;
+    let corrupt = from_parts(&(branches, leaves, unvisited));
+
+    assert!(corrupt.calc_root_hash(hasher).is_err());
+    assert!(corrupt.get(&KeyHash::from_u64(2)).is_err());
+    assert!(corrupt.replay_reads(hasher, root, &[]).is_err());
+
+    // A transaction over the snapshot follows stored branches without hashing them first.
+    let key = KeyHash::from_u64(2);
+    let mut txn = Transaction::from_snapshot(&corrupt).unwrap();
+    assert!(txn.get(&key).is_err());
+    assert!(txn.seek(&key).is_err());
+    assert!(txn.next_key_after(&key).is_err());
+    assert!(txn
+        .range_get(KeyHash([0; 8])..=KeyHash([u32::MAX; 8]))
+        .is_err());
+    assert!(txn.insert(&key, 0).is_err());
+
+    let mut txn = Transaction::from_snapshot(&corrupt).unwrap();
+    assert!(txn.entry(&key).is_err());
+
+    let root = TrieRoot::Node(root_idx as Idx);
+    assert!(prove_range(&corrupt, root, key..=key, hasher).is_err());
+
+    // Walks check the bit order of every branch they visit.
+    assert!(walk(&corrupt, corrupt.root_node_idx().unwrap(), &mut NoopVisitor).is_err());
+    assert!(corrupt.canonicalize().is_err());
+    assert!(corrupt.to_memory_db(hasher).is_err());
+    assert!(corrupt.is_equivalent_to(&snapshot, hasher).is_err());
+    assert!(Snapshot::difference(&snapshot, &corrupt, hasher).is_err());
+
+    let buf = corrupt.to_flat();
+    let flat = buf.as_flat();
+    assert!(matches!(
+        flat.calc_root_hash(hasher),
+        Err(FlatError::BranchOutOfOrder(_))
+    ));
+    assert!(matches!(
+        flat.get(&KeyHash::from_u64(2)),
+        Err(FlatError::BranchOutOfOrder(_))
+    ));
+}
+
+#[test]
+fn a_branch_that_is_its_own_child_is_an_error() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (snapshot, _) = snapshot();
+    let (mut branches, leaves, unvisited) = to_parts(&snapshot);
+    let root_idx = branches.len() - 1;
+    branches[root_idx].left = root_idx as Idx;
+    let corrupt = from_parts(&(branches, leaves, unvisited));
+
+    // Keys on the left follow the root back to itself, keys on the right hash it as a sibling.
+    for i in [2, 7, 19, 40, 63] {
+        let key = KeyHash::from_u64(i);
+        let mut txn = Transaction::from_snapshot(&corrupt).unwrap();
+        assert!(txn.prove_inclusion(&key, hasher).is_err());
+        assert!(txn.remove_with_proof(&key, hasher).is_err());
+
+        let root = TrieRoot::Node(root_idx as Idx);
+        assert!(prove_range(&corrupt, root, key..=key, hasher).is_err());
+
+        let counted = &mut TaggedHasher::<DigestHasher<Sha256>, Counted>::default();
+        assert!(txn
+            .prove_prefix_count(&key.to_bytes(), 256, counted)
+            .is_err());
+        assert!(txn
+            .remove_prefix_with_proof(&key.to_bytes(), 256, hasher)
+            .is_err());
+    }
+
+    // `remove_prefix` doesn't hash the root's left child, only keys under it reach the root again.
+    for i in [2, 40] {
+        let mut txn = Transaction::from_snapshot(&corrupt).unwrap();
+        assert!(txn
+            .remove_prefix(&KeyHash::from_u64(i).to_bytes(), 256)
+            .is_err());
+    }
+
+    // Without a prefix every key is in range, so the walk reaches the loop.
+    let txn = Transaction::from_snapshot(&corrupt).unwrap();
+    assert!(txn
+        .keys_with_prefix(&[], 0)
+        .unwrap()
+        .any(|key| key.is_err()));
+}
+
+#[test]
+fn a_branch_out_of_bit_order_is_an_error() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (mut branches, leaves, unvisited) = shared_chain();
+
+    // The root's child discriminates on bit 1, an earlier bit than the root's.
+    branches[31].mask = BranchMask::new(0, 0, 1 << 5);
+    let corrupt = from_parts(&(branches, leaves, unvisited));
+    assert!(corrupt.calc_root_hash(hasher).is_err());
+    assert!(corrupt.get(&KeyHash([0; 8])).is_err());
+
+    let buf = corrupt.to_flat();
+    assert_eq!(
+        buf.as_flat().calc_root_hash(hasher),
+        Err(FlatError::BranchOutOfOrder(30))
+    );
+    assert_eq!(
+        buf.as_flat().get(&KeyHash([0; 8])),
+        Err(FlatError::BranchOutOfOrder(30))
+    );
+}
+
+#[test]
+fn shared_subtrees_are_hashed_once_or_rejected() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let parts = shared_chain();
+    let snapshot = from_parts(&parts);
+
+    // Each branch's children are cached, so the snapshot hashes in linear time.
+    let root = snapshot.calc_root_hash(hasher).unwrap();
+    assert_eq!(snapshot.get(&KeyHash([0; 8])).unwrap(), Some(&0));
+
+    // Without the cache, a traversal stops once it has visited more nodes than the snapshot holds.
+    assert!(from_parts(&parts).replay_reads(hasher, root, &[]).is_err());
+
+    // The flat snapshot has no cache either.
+    let buf = snapshot.to_flat();
+    assert!(matches!(
+        buf.as_flat().calc_root_hash(hasher),
+        Err(FlatError::SharedNode(_))
+    ));
+}
+
+/// A 20 byte key, narrower than the 256 bits of a `KeyHash`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+struct Address([u32; 5]);
+
+impl TrieKey for Address {
+    fn words(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+#[test]
+fn a_branch_past_the_end_of_the_key_is_an_error() {
+    let leaf = |word| Leaf {
+        key_hash: Address([0, 0, 0, 0, word]),
+        value: 0u64,
+    };
+    // Bit 200 is in the seventh word, past the five words of an `Address`.
+    let branch = Branch {
+        left: 1,
+        right: 2,
+        mask: BranchMask::new(6, 0, 1 << 8),
+        prior_word: 0,
+        prefix: Box::default(),
+    };
+    let parts = (vec![branch], vec![leaf(0), leaf(1)], Vec::<NodeHash>::new());
+    let corrupt: Snapshot<u64, Address> =
+        bincode::deserialize(&bincode::serialize(&parts).unwrap()).unwrap();
+
+    let key = Address([0; 5]);
+    assert!(corrupt.get(&key).is_err());
+
+    let mut txn = Transaction::from_snapshot(&corrupt).unwrap();
+    assert!(txn.get(&key).is_err());
+    assert!(txn.insert(&key, 1).is_err());
+}
+
+#[test]
+fn a_prefix_longer_than_its_words_is_an_error() {
+    let (mut branches, leaves, unvisited) = shared_chain();
+
+    // The root discriminates on a bit of the first word, so it has no words before `prior_word`.
+    branches[31].prefix = Box::new([0, 0]);
+    let corrupt = from_parts(&(branches, leaves, unvisited));
+    assert!(corrupt.get(&KeyHash([0; 8])).is_err());
+
+    let txn = Transaction::from_snapshot(&corrupt).unwrap();
+    assert!(txn.get(&KeyHash([0; 8])).is_err());
+}
@@ -0,0 +1,213 @@
+mod utils;
+
+use std::{collections::BTreeMap, rc::Rc};
+
+use proptest::{prelude::*, sample::Index};
+
+use kairos_trie::{
+    counted::{leaf_count, root_leaf_count, with_leaf_count, PrefixCountEnd, PrefixCountProof},
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, TaggedHasher, Transaction, TrieParams, TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+struct Counted;
+
+impl TrieParams for Counted {
+    const LEAF_TAG: &'static [u8] = b"counted/leaf";
+    const BRANCH_TAG: &'static [u8] = b"counted/branch";
+    const COUNTED: bool = true;
+}
+
+type CountedHasher = TaggedHasher<DigestHasher<Sha256>, Counted>;
+type Db = Rc<MemoryDb<u64>>;
+
+fn committed(map: &BTreeMap<KeyHash, u64>) -> (Db, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for (k, v) in map.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+    let root = txn.commit(&mut CountedHasher::default()).unwrap().root;
+    (db, root)
+}
+
+/// Whether the first `len_bits` bits of `key`, in `KeyHash::from_bytes` layout, are those of `prefix`.
+fn starts_with(key: &KeyHash, prefix: &[u8; 32], len_bits: usize) -> bool {
+    let key = key.to_bytes();
+    (0..len_bits).all(|bit| (key[bit / 8] >> (bit % 8)) & 1 == (prefix[bit / 8] >> (bit % 8)) & 1)
+}
+
+fn prefix_count_end_to_end(
+    map: BTreeMap<KeyHash, u64>,
+    prefix: [u8; 32],
+    len_bits: usize,
+) -> Result<(), TestCaseError> {
+    let hasher = &mut CountedHasher::default();
+    let (db, root) = committed(&map);
+    prop_assert_eq!(root_leaf_count(root), map.len() as u32);
+
+    let expected = map
+        .keys()
+        .filter(|key| starts_with(key, &prefix, len_bits))
+        .count() as u32;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    prop_assert_eq!(txn.count_leaves(hasher).unwrap(), map.len() as u32);
+    let proof = txn.prove_prefix_count(&prefix, len_bits, hasher).unwrap();
+    prop_assert_eq!(
+        proof.verify(hasher, root, &prefix, len_bits).unwrap(),
+        expected
+    );
+
+    // A transaction proves the same count over its modified nodes, before committing.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::<u64>::empty()),
+        TrieRoot::Empty,
+    ));
+    for (k, v) in map.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+    prop_assert_eq!(
+        txn.prove_prefix_count(&prefix, len_bits, hasher).unwrap(),
+        proof.clone()
+    );
+
+    // Changing any hash on the path breaks the proof.
+    for i in 0..proof.path.len() {
+        let mut tampered = proof.clone();
+        let mut path = tampered.path.to_vec();
+        path[i].left = NodeHash::new([7; 32]);
+        path[i].right = NodeHash::new([7; 32]);
+        tampered.path = path.into_boxed_slice();
+        prop_assert!(tampered.verify(hasher, root, &prefix, len_bits).is_err());
+    }
+
+    let wrong_root = TrieRoot::Node(NodeHash::new([7; 32]));
+    prop_assert!(proof.verify(hasher, wrong_root, &prefix, len_bits).is_err());
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn prop_prefix_count(
+        map in prop::collection::btree_map(arb_key_hash(), any::<u64>(), 0..100),
+        random_prefix: [u8; 32],
+        key_prefix: Option<Index>,
+        len_bits in 0usize..=256,
+    ) {
+        let prefix = match key_prefix {
+            Some(idx) if !map.is_empty() => idx.get(&map.keys().collect::<Vec<_>>()).to_bytes(),
+            _ => random_prefix,
+        };
+        prefix_count_end_to_end(map, prefix, len_bits)?;
+    }
+
+    #[test]
+    fn prop_prefix_count_structured_keys(
+        map in prop::collection::btree_map(arb_structured_key_hash(), any::<u64>(), 0..100),
+        prefix in arb_structured_key_hash(),
+        len_bits in 0usize..=256,
+    ) {
+        prefix_count_end_to_end(map, prefix.to_bytes(), len_bits)?;
+    }
+}
+
+#[test]
+fn prefix_count_of_u64_keys() {
+    let hasher = &mut CountedHasher::default();
+    let map = BTreeMap::from_iter((0..100).map(|i| (KeyHash::from_u64(i), i)));
+    let (db, root) = committed(&map);
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+
+    // The low 2 bits of 0..100 are 01 for 25 keys.
+    let proof = txn.prove_prefix_count(&[0b01], 2, hasher).unwrap();
+    assert_eq!(proof.verify(hasher, root, &[0b01], 2).unwrap(), 25);
+    assert!(matches!(proof.end, PrefixCountEnd::Branch(_)));
+
+    // Every key, with a proof of the root alone.
+    let proof = txn.prove_prefix_count(&[], 0, hasher).unwrap();
+    assert!(proof.path.is_empty());
+    assert_eq!(proof.verify(hasher, root, &[], 0).unwrap(), 100);
+
+    // A single key.
+    let key = KeyHash::from_u64(42).to_bytes();
+    let proof = txn.prove_prefix_count(&key, 256, hasher).unwrap();
+    assert!(matches!(proof.end, PrefixCountEnd::Leaf(_)));
+    assert_eq!(proof.verify(hasher, root, &key, 256).unwrap(), 1);
+
+    // An end whose count is changed no longer matches the root.
+    let proof = txn.prove_prefix_count(&[0b01], 2, hasher).unwrap();
+    let PrefixCountEnd::Branch(mut end) = proof.end else {
+        panic!("The prefix ends at a branch");
+    };
+    end.left = with_leaf_count(end.left, leaf_count(&end.left) + 1);
+    let tampered = PrefixCountProof::<u64> {
+        path: proof.path,
+        end: PrefixCountEnd::Branch(end),
+    };
+    assert!(tampered.verify(hasher, root, &[0b01], 2).is_err());
+}
+
+#[test]
+fn prefix_count_of_empty_trie() {
+    let hasher = &mut CountedHasher::default();
+    let (db, root) = committed(&BTreeMap::new());
+    assert_eq!(root, TrieRoot::Empty);
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    assert_eq!(txn.count_leaves(hasher).unwrap(), 0);
+    let proof = txn.prove_prefix_count(&[], 0, hasher).unwrap();
+    assert_eq!(proof.end, PrefixCountEnd::Empty);
+    assert_eq!(proof.verify(hasher, root, &[], 0).unwrap(), 0);
+
+    // An empty proof cannot hide a non-empty trie.
+    let (_, root) = committed(&BTreeMap::from([(KeyHash::from_u64(1), 1)]));
+    assert!(proof.verify(hasher, root, &[], 0).is_err());
+}
+
+#[test]
+fn counts_need_a_counted_hasher() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let map = BTreeMap::from_iter((0..10).map(|i| (KeyHash::from_u64(i), i)));
+    let (db, root) = committed(&map);
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+
+    assert!(txn.count_leaves(hasher).is_err());
+    assert!(txn.prove_prefix_count(&[], 0, hasher).is_err());
+
+    let proof = txn
+        .prove_prefix_count(&[], 0, &mut CountedHasher::default())
+        .unwrap();
+    assert!(proof.verify(hasher, root, &[], 0).is_err());
+}
+
+#[test]
+fn counted_mode_changes_the_root() {
+    let map = BTreeMap::from_iter((0..10).map(|i| (KeyHash::from_u64(i), i)));
+    let (_, counted_root) = committed(&map);
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::<u64>::empty()),
+        TrieRoot::Empty,
+    ));
+    for (k, v) in map.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+    let root = txn
+        .calc_root_hash(&mut TaggedHasher::<DigestHasher<Sha256>, Counted>::default())
+        .unwrap();
+    assert_eq!(root, counted_root);
+
+    struct Uncounted;
+    impl TrieParams for Uncounted {
+        const LEAF_TAG: &'static [u8] = b"counted/leaf";
+        const BRANCH_TAG: &'static [u8] = b"counted/branch";
+    }
+    let uncounted = txn
+        .calc_root_hash(&mut TaggedHasher::<DigestHasher<Sha256>, Uncounted>::default())
+        .unwrap();
+    assert_ne!(uncounted, counted_root);
+}
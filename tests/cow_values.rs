@@ -0,0 +1,45 @@
+#![cfg(feature = "serde")]
+
+use std::{borrow::Cow, rc::Rc};
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::Snapshot, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[test]
+fn cow_snapshot_borrows_values_from_the_witness() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<Vec<u8>>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..32u64 {
+        txn.insert(&KeyHash::from_u64(i), vec![i as u8; 16])
+            .unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    assert_eq!(txn.get(&KeyHash::from_u64(5)).unwrap(), Some(&vec![5; 16]));
+    txn.insert(&KeyHash::from_u64(100), vec![1, 2, 3]).unwrap();
+    let new_root = txn.calc_root_hash(hasher).unwrap();
+    let witness = bincode::serialize(&txn.build_initial_snapshot()).unwrap();
+
+    // The guest side.
+    let borrowed: Snapshot<&[u8]> = bincode::deserialize(&witness).unwrap();
+    let snapshot = Snapshot::<Cow<[u8]>>::from(borrowed);
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+
+    let mut txn = Transaction::from_snapshot(&snapshot).unwrap();
+    let Some(Cow::Borrowed(value)) = txn.get(&KeyHash::from_u64(5)).unwrap() else {
+        panic!("The value is borrowed from the witness");
+    };
+    assert_eq!(*value, [5; 16]);
+    assert!(witness.as_ptr_range().contains(&value.as_ptr()));
+
+    txn.insert(&KeyHash::from_u64(100), Cow::Owned(vec![1, 2, 3]))
+        .unwrap();
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), new_root);
+}
@@ -0,0 +1,92 @@
+use std::{cell::Cell, rc::Rc};
+
+use kairos_trie::{
+    cycle_probe::{measure, CycleProbe, Phase, PhaseCycles},
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    verify_batch_probed, DigestHasher, KeyHash, Op, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[derive(Default)]
+struct Recorder(Vec<(bool, Phase)>);
+
+impl CycleProbe for Recorder {
+    fn start(&mut self, phase: Phase) {
+        self.0.push((true, phase));
+    }
+
+    fn end(&mut self, phase: Phase) {
+        self.0.push((false, phase));
+    }
+}
+
+#[test]
+fn verify_batch_reports_its_phases() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..20 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let old_root = txn.commit(hasher).unwrap().root;
+
+    let ops = [
+        Op::Get(KeyHash::from_u64(3)),
+        Op::Insert(KeyHash::from_u64(30), 30),
+    ];
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, old_root));
+    for op in &ops {
+        op.apply(&mut txn).unwrap();
+    }
+    let new_root = txn.commit(hasher).unwrap().root;
+    let snapshot = txn.build_initial_snapshot();
+
+    let mut recorder = Recorder::default();
+    verify_batch_probed(old_root, new_root, &snapshot, &ops, hasher, &mut recorder).unwrap();
+    assert_eq!(
+        recorder.0,
+        [
+            (true, Phase::Hash),
+            (false, Phase::Hash),
+            (true, Phase::Replay),
+            (false, Phase::Replay),
+            (true, Phase::Hash),
+            (false, Phase::Hash),
+        ]
+    );
+
+    // A failing batch still closes the phase it failed in.
+    let mut recorder = Recorder::default();
+    assert!(
+        verify_batch_probed(new_root, new_root, &snapshot, &ops, hasher, &mut recorder).is_err()
+    );
+    assert_eq!(recorder.0, [(true, Phase::Hash), (false, Phase::Hash)]);
+}
+
+#[test]
+fn phase_cycles_totals_each_phase() {
+    let clock = Cell::new(0);
+    let tick = |cycles| clock.set(clock.get() + cycles);
+    let mut cycles = PhaseCycles::new(|| clock.get());
+
+    tick(5);
+    measure(&mut cycles, Phase::Decode, || tick(100));
+    measure(&mut cycles, Phase::Hash, || tick(20));
+    tick(7);
+    measure(&mut cycles, Phase::Replay, || tick(3));
+    measure(&mut cycles, Phase::Hash, || tick(1));
+
+    assert_eq!(
+        cycles.totals(),
+        [(Phase::Decode, 100), (Phase::Hash, 21), (Phase::Replay, 3)]
+    );
+    assert_eq!(
+        Phase::ALL.map(Phase::label),
+        [
+            "kairos-trie:decode",
+            "kairos-trie:hash",
+            "kairos-trie:replay"
+        ]
+    );
+}
@@ -0,0 +1,92 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{delta::DeltaSnapshot, memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+type Txn = Transaction<SnapshotBuilder<Rc<MemoryDb<u64>>, u64>, u64>;
+
+/// A trie of 100 keys, committed to a fresh database, and a transaction over its root.
+fn committed_trie(hasher: &mut DigestHasher<Sha256>) -> (TrieRoot<kairos_trie::NodeHash>, Txn) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..100 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    (
+        root,
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root)),
+    )
+}
+
+#[test]
+fn verify_applies_updates() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (old_root, mut txn) = committed_trie(hasher);
+
+    let updates: Vec<_> = [3, 17, 64].map(|i| (KeyHash::from_u64(i), i + 1000)).into();
+    for (key, value) in &updates {
+        txn.insert(key, *value).unwrap();
+    }
+    txn.get(&KeyHash::from_u64(40)).unwrap();
+    let new_root = txn.calc_root_hash(hasher).unwrap();
+
+    let delta = DeltaSnapshot::new(txn.build_initial_snapshot(), updates.clone()).unwrap();
+    assert_eq!(delta.verify(hasher, old_root).unwrap(), new_root);
+
+    // The read leaf is unchanged, the updated ones carry both values.
+    assert_eq!(delta.leaves().len(), 4);
+    let mut changes: Vec<_> = delta
+        .changes()
+        .map(|(k, old, new)| (*k, *old, *new))
+        .collect();
+    changes.sort();
+    let mut expected: Vec<_> = updates.iter().map(|&(k, v)| (k, v - 1000, v)).collect();
+    expected.sort();
+    assert_eq!(changes, expected);
+}
+
+#[test]
+fn verify_rejects_the_wrong_old_root() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (old_root, mut txn) = committed_trie(hasher);
+
+    txn.insert(&KeyHash::from_u64(5), 0).unwrap();
+    let delta =
+        DeltaSnapshot::new(txn.build_initial_snapshot(), [(KeyHash::from_u64(5), 1)]).unwrap();
+
+    let new_root = delta.verify(hasher, old_root).unwrap();
+    assert!(delta.verify(hasher, new_root).is_err());
+    assert!(delta.verify(hasher, TrieRoot::Empty).is_err());
+}
+
+#[test]
+fn updates_need_a_leaf_in_the_snapshot() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (_, txn) = committed_trie(hasher);
+
+    txn.get(&KeyHash::from_u64(5)).unwrap();
+    let err =
+        DeltaSnapshot::new(txn.build_initial_snapshot(), [(KeyHash::from_u64(6), 0)]).unwrap_err();
+    assert!(format!("{err}").contains("no leaf"));
+}
+
+#[test]
+fn empty_trie() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::<_, u64>::new(
+        Rc::new(MemoryDb::<u64>::empty()),
+        TrieRoot::Empty,
+    ));
+
+    let delta = DeltaSnapshot::new(txn.build_initial_snapshot(), []).unwrap();
+    assert_eq!(
+        delta.verify(hasher, TrieRoot::Empty).unwrap(),
+        TrieRoot::Empty
+    );
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "derive")]
+
+use kairos_trie::{PortableHash, PortableUpdate};
+
+/// Records the bytes hashed, to check the derived encoding byte for byte.
+#[derive(Default)]
+struct Bytes(Vec<u8>);
+
+impl PortableUpdate for Bytes {
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.extend_from_slice(data.as_ref());
+    }
+}
+
+fn hashed(value: &impl PortableHash) -> Vec<u8> {
+    let mut bytes = Bytes::default();
+    value.portable_hash(&mut bytes);
+    bytes.0
+}
+
+#[derive(PortableHash)]
+struct Account {
+    balance: u64,
+    nonce: u32,
+    frozen: bool,
+}
+
+#[derive(PortableHash)]
+struct Wrapper<T>(T, u16);
+
+#[derive(PortableHash)]
+struct Unit;
+
+#[derive(PortableHash)]
+enum Entry {
+    Empty,
+    Account(Account),
+    Contract { code: Vec<u8>, owner: [u8; 4] },
+}
+
+#[test]
+fn structs_hash_fields_in_declaration_order() {
+    let account = Account {
+        balance: 1,
+        nonce: 2,
+        frozen: true,
+    };
+    assert_eq!(
+        hashed(&account),
+        [&1u64.to_le_bytes()[..], &2u32.to_le_bytes(), &[1]].concat()
+    );
+
+    assert_eq!(
+        hashed(&Wrapper(7u8, 3)),
+        [&[7][..], &3u16.to_le_bytes()].concat()
+    );
+    assert!(hashed(&Unit).is_empty());
+}
+
+#[test]
+fn enums_hash_the_variant_index_first() {
+    assert_eq!(hashed(&Entry::Empty), 0u32.to_le_bytes());
+
+    let account = Account {
+        balance: 5,
+        nonce: 0,
+        frozen: false,
+    };
+    assert_eq!(
+        hashed(&Entry::Account(account)),
+        [
+            &1u32.to_le_bytes()[..],
+            &hashed(&Account {
+                balance: 5,
+                nonce: 0,
+                frozen: false,
+            })
+        ]
+        .concat()
+    );
+
+    assert_eq!(
+        hashed(&Entry::Contract {
+            code: vec![9, 9],
+            owner: [1, 2, 3, 4],
+        }),
+        [&2u32.to_le_bytes()[..], &[9, 9], &[1, 2, 3, 4]].concat()
+    );
+}
+
+#[test]
+fn derived_items_of_collections_delimit_their_fields() {
+    let contract = |code: Vec<u8>| Entry::Contract {
+        code,
+        owner: [1, 2, 3, 4],
+    };
+    assert_eq!(
+        hashed(&vec![contract(vec![9, 9])]),
+        [
+            &1u64.to_le_bytes()[..],
+            &2u32.to_le_bytes(),
+            &2u64.to_le_bytes(),
+            &[9, 9],
+            &[1, 2, 3, 4],
+        ]
+        .concat()
+    );
+}
@@ -0,0 +1,46 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{EmptyTrieAttestation, SnapshotBuilder},
+    },
+    verify_batch, DigestHasher, KeyHash, Op, Transaction, TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+
+#[test]
+fn genesis_witness_is_attested_empty() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    txn.insert(&KeyHash::from_u64(1), 10).unwrap();
+    let snapshot = txn.build_initial_snapshot();
+    let root = txn.commit(hasher).unwrap().root;
+
+    EmptyTrieAttestation::verify(&snapshot, TrieRoot::Empty).unwrap();
+    verify_batch(
+        TrieRoot::Empty,
+        root,
+        &snapshot,
+        &[Op::Insert(KeyHash::from_u64(1), 10)],
+        hasher,
+    )
+    .unwrap();
+
+    // The guest trusts a non-empty root, so the witness is not attested empty.
+    assert!(matches!(
+        EmptyTrieAttestation::verify(&snapshot, root),
+        Err(VerifyError::OldRootMismatch { .. })
+    ));
+
+    // A witness of a non-empty root is rejected, even if the host never loaded the root.
+    let builder = SnapshotBuilder::<_, u64>::new(db, root);
+    let unloaded = Transaction::from_snapshot_builder(builder).build_initial_snapshot();
+    assert!(matches!(
+        EmptyTrieAttestation::verify(&unloaded, TrieRoot::Empty),
+        Err(VerifyError::Trie(_))
+    ));
+}
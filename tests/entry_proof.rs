@@ -0,0 +1,80 @@
+mod utils;
+
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_structured_key_hash;
+
+proptest! {
+    #[test]
+    fn prop_entry_path_proves_the_leaf_after_commit(
+        stored_keys in prop::collection::btree_set(arb_structured_key_hash(), 0..50),
+        modified_keys in prop::collection::btree_set(arb_structured_key_hash(), 0..50),
+        key in arb_structured_key_hash(),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for (i, key) in stored_keys.iter().enumerate() {
+            txn.insert(key, i as u64).unwrap();
+        }
+        let root = txn.commit(hasher).unwrap().root;
+
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for (i, key) in modified_keys.iter().enumerate() {
+            txn.insert(key, i as u64).unwrap();
+        }
+
+        let (value, path) = txn.entry(&key).unwrap().or_insert_with_proof(|| 1000);
+        *value += 1;
+        prop_assert_eq!(path.key_hash, key);
+
+        let new_root = txn.commit(hasher).unwrap().root;
+        let proof = txn.prove_leaf_path(&path, hasher).unwrap();
+        proof.verify(hasher, new_root).unwrap();
+
+        prop_assert_eq!(Some(proof), txn.prove_inclusion(&key, hasher).unwrap());
+    }
+}
+
+#[test]
+fn entry_path_goes_stale_when_the_trie_changes() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::<u64>::empty()),
+        TrieRoot::Empty,
+    ));
+
+    let (_, path) = txn
+        .entry(&KeyHash::from_u64(0))
+        .unwrap()
+        .or_insert_with_proof(|| 0);
+    assert!(path.turns.is_empty());
+    let root = txn.calc_root_hash(hasher).unwrap();
+    txn.prove_leaf_path(&path, hasher)
+        .unwrap()
+        .verify(hasher, root)
+        .unwrap();
+
+    // A sibling inserted at the root pushes the leaf one branch down.
+    txn.insert(&KeyHash::from_u64(1), 1).unwrap();
+    assert!(txn.prove_leaf_path(&path, hasher).is_err());
+
+    let (value, path) = txn
+        .entry(&KeyHash::from_u64(0))
+        .unwrap()
+        .or_insert_with_proof(|| 2);
+    assert_eq!(*value, 0);
+    assert_eq!(path.turns, [false]);
+
+    txn.remove(&KeyHash::from_u64(0)).unwrap();
+    assert!(txn.prove_leaf_path(&path, hasher).is_err());
+}
@@ -0,0 +1,83 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, Entry, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+type Db = Rc<MemoryDb<u64>>;
+
+fn committed() -> (Db, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..64 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (db, root)
+}
+
+#[test]
+fn reading_an_entry_leaves_the_stored_leaf_in_place() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (db, root) = committed();
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+
+    let key = KeyHash::from_u64(7);
+    let entry = txn.entry(&key).unwrap();
+    assert_eq!(entry.key(), &key);
+    assert_eq!(entry.get(), Some(&7));
+    let Entry::Occupied(o) = entry else {
+        panic!("The key is in the trie");
+    };
+    assert_eq!(o.get(), &7);
+    assert_eq!(o.path().key_hash, key);
+
+    // Only the branches on the path were copied into the transaction.
+    let shape = txn.modified_shape();
+    assert_eq!(shape.mod_leaves, 0);
+    assert_eq!(shape.mod_branches, 6);
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), root);
+
+    // `or_insert` on a present key hands out its value, which copies the leaf.
+    assert_eq!(*txn.entry(&key).unwrap().or_insert(0), 7);
+    assert_eq!(txn.modified_shape().mod_leaves, 1);
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), root);
+}
+
+#[test]
+fn modifying_an_entry_copies_the_stored_leaf() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (db, root) = committed();
+
+    let mut expected = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    for i in 0..4 {
+        expected.insert(&KeyHash::from_u64(i), i + 100).unwrap();
+    }
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    *txn.entry(&KeyHash::from_u64(0)).unwrap().get_mut().unwrap() += 100;
+    txn.entry(&KeyHash::from_u64(1))
+        .unwrap()
+        .and_modify(|value| *value += 100);
+    assert_eq!(txn.entry(&KeyHash::from_u64(2)).unwrap().insert(102), &102);
+
+    let Entry::Occupied(mut o) = txn.entry(&KeyHash::from_u64(3)).unwrap() else {
+        panic!("The key is in the trie");
+    };
+    assert_eq!(o.insert(103), 3);
+    assert_eq!(o.get(), &103);
+    assert_eq!(o.insert(203), 103);
+    *o.into_mut() -= 100;
+
+    assert_eq!(txn.modified_shape().mod_leaves, 4);
+    assert_eq!(
+        txn.calc_root_hash(hasher).unwrap(),
+        expected.calc_root_hash(hasher).unwrap()
+    );
+}
@@ -0,0 +1,114 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    export::{export, ExportFormat},
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// A committed trie over `keys`, each mapped to its first word.
+fn committed(keys: &[KeyHash]) -> (Rc<MemoryDb<u32>>, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u32>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys {
+        txn.insert(key, key.0[0]).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (db, root)
+}
+
+fn export_to_string(
+    db: &Rc<MemoryDb<u32>>,
+    root: TrieRoot<NodeHash>,
+    format: ExportFormat,
+) -> (String, Vec<u64>) {
+    let builder = SnapshotBuilder::new(db.clone(), root);
+    let mut out = Vec::new();
+    let mut progress = Vec::new();
+    let written = export(
+        &builder,
+        match root {
+            TrieRoot::Node(_) => TrieRoot::Node(0),
+            TrieRoot::Empty => TrieRoot::Empty,
+        },
+        format,
+        &mut out,
+        |value: &u32| Ok(value.to_be_bytes().to_vec()),
+        |leaves| progress.push(leaves),
+    )
+    .unwrap();
+    assert_eq!(Some(&written), progress.last().or(Some(&0)));
+    (String::from_utf8(out).unwrap(), progress)
+}
+
+#[test]
+fn export_csv_and_jsonl() {
+    let mut keys: Vec<KeyHash> = [3, 1, 2, 0xdead_beef]
+        .into_iter()
+        .map(|word| KeyHash([word, 0, 0, 0, 0, 0, 0, 1]))
+        .collect();
+    let (db, root) = committed(&keys);
+    keys.sort_by(KeyHash::cmp_trie_order);
+
+    let (csv, progress) = export_to_string(&db, root, ExportFormat::Csv);
+    assert_eq!(progress, [1, 2, 3, 4]);
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("key,value"));
+    for (line, key) in lines.zip(&keys) {
+        let expected = format!("{},{}", hex(&key.to_bytes()), hex(&key.0[0].to_be_bytes()));
+        assert_eq!(line, expected);
+    }
+    assert_eq!(csv.lines().count(), keys.len() + 1);
+
+    let (jsonl, _) = export_to_string(&db, root, ExportFormat::Jsonl);
+    assert_eq!(jsonl.lines().count(), keys.len());
+    assert_eq!(
+        jsonl.lines().next().unwrap(),
+        format!(
+            r#"{{"key":"{}","value":"{}"}}"#,
+            hex(&keys[0].to_bytes()),
+            hex(&keys[0].0[0].to_be_bytes())
+        )
+    );
+}
+
+#[test]
+fn export_empty_trie() {
+    let db = Rc::new(MemoryDb::<u32>::empty());
+    let (csv, progress) = export_to_string(&db, TrieRoot::Empty, ExportFormat::Csv);
+    assert_eq!(csv, "key,value\n");
+    assert!(progress.is_empty());
+
+    let (jsonl, _) = export_to_string(&db, TrieRoot::Empty, ExportFormat::Jsonl);
+    assert_eq!(jsonl, "");
+}
+
+#[test]
+fn export_partial_snapshot_is_an_error() {
+    let keys = [KeyHash([0; 8]), KeyHash([1, 0, 0, 0, 0, 0, 0, 0])];
+    let (db, root) = committed(&keys);
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&keys[0]).unwrap();
+    let snapshot = txn.build_initial_snapshot();
+
+    let err = export(
+        &snapshot,
+        snapshot.root_node_idx().unwrap(),
+        ExportFormat::Csv,
+        &mut Vec::new(),
+        |value: &u32| Ok(value.to_le_bytes().to_vec()),
+        |_| {},
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("unvisited"));
+}
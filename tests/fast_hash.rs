@@ -0,0 +1,56 @@
+#![cfg(feature = "fast-hash")]
+
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, FastHash, KeyHash, PortableHasher, PortableUpdate, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[test]
+fn fast_hash_builds_and_reloads_tries() {
+    let hasher = &mut FastHash::new();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..256 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+    assert_ne!(
+        root,
+        txn.calc_root_hash(&mut DigestHasher::<Sha256>::default())
+            .unwrap()
+    );
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    assert_eq!(txn.get(&KeyHash::from_u64(7)).unwrap(), Some(&7));
+    txn.insert(&KeyHash::from_u64(7), 70).unwrap();
+    let new_root = txn.commit(hasher).unwrap().root;
+
+    let snapshot = txn.build_initial_snapshot();
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+    let mut replay = Transaction::from_snapshot(&snapshot).unwrap();
+    replay.insert(&KeyHash::from_u64(7), 70).unwrap();
+    assert_eq!(replay.calc_root_hash(hasher).unwrap(), new_root);
+}
+
+#[test]
+fn fast_hash_resets_and_is_stable() {
+    let mut hasher = FastHash::default();
+    hasher.portable_update(b"kairos");
+    let first = hasher.finalize_reset();
+    hasher.portable_update(b"kairos");
+    assert_eq!(hasher.finalize_reset(), first);
+
+    // The output must not change between versions, local indexes persist it.
+    assert_eq!(
+        first,
+        [
+            169, 20, 169, 225, 29, 154, 83, 168, 112, 4, 81, 134, 54, 182, 139, 216, 226, 124, 175,
+            204, 239, 236, 194, 29, 25, 20, 16, 94, 184, 196, 33, 20
+        ]
+    );
+}
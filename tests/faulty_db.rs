@@ -0,0 +1,273 @@
+#![cfg(feature = "test-utils")]
+
+mod utils;
+
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{
+        faulty::{truncate_snapshot, Fault, FaultyDb, InjectedFault},
+        memory_db::{MemoryDb, MemoryDbError},
+        merkle::{MissingNode, SnapshotBuilder},
+    },
+    verify_batch, DigestHasher, Journal, KeyHash, NodeHash, Op, ReplicationMode, Transaction,
+    TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_structured_key_hash;
+
+fn arb_fault() -> impl Strategy<Value = Fault> {
+    prop_oneof![Just(Fault::Error), Just(Fault::WrongNode)]
+}
+
+fn committed_root(db: &Rc<MemoryDb<u64>>, keys: &[KeyHash]) -> TrieRoot<NodeHash> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for (i, key) in keys.iter().enumerate() {
+        txn.insert(key, i as u64).unwrap();
+    }
+    txn.commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root
+}
+
+fn to_ops(keys: &[KeyHash], ops: &[(prop::sample::Index, Option<u64>)]) -> Vec<Op<u64>> {
+    ops.iter()
+        .map(|(idx, value)| {
+            let key = keys[idx.index(keys.len())];
+            match value {
+                Some(value) => Op::Insert(key, *value),
+                None => Op::Get(key),
+            }
+        })
+        .collect()
+}
+
+proptest! {
+    /// Whatever the database does, an audited commit either fails with an error
+    /// or produces the same root as a fault free run.
+    #[test]
+    fn prop_faults_never_produce_wrong_roots(
+        keys in prop::collection::vec(arb_structured_key_hash(), 1..100),
+        ops in prop::collection::vec((any::<prop::sample::Index>(), any::<Option<u64>>()), 1..50),
+        schedule in prop::collection::vec((0..60u64, arb_fault()), 0..4),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+        let old_root = committed_root(&db, &keys);
+        let ops = to_ops(&keys, &ops);
+
+        let mut clean = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+        for op in ops.iter() {
+            op.apply(&mut clean).unwrap();
+        }
+        let expected_root = clean.calc_root_hash(hasher).unwrap();
+
+        let faulty = FaultyDb::new(db, schedule);
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(faulty, old_root));
+        let mut journal = Journal::new();
+        for op in ops {
+            if journal.apply(&mut txn, op).is_err() {
+                return Ok(());
+            }
+        }
+
+        if let Ok(batch) = txn.commit_audited(hasher, &journal) {
+            prop_assert_eq!(batch.old_root, old_root);
+            prop_assert_eq!(batch.new_root, expected_root);
+            batch.verify(hasher).unwrap();
+        }
+    }
+
+    /// Removals and entries against a faulty database return errors rather than panicking.
+    #[test]
+    fn prop_faults_never_panic(
+        keys in prop::collection::vec(arb_structured_key_hash(), 1..100),
+        ops in prop::collection::vec((any::<prop::sample::Index>(), 0..3u8), 1..50),
+        period in 1..20u64,
+        offset in 0..20u64,
+        fault in arb_fault(),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+        let old_root = committed_root(&db, &keys);
+
+        let faulty = FaultyDb::every(db, period, offset, 1000, fault);
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(faulty, old_root));
+        for (idx, kind) in ops {
+            let key = keys[idx.index(keys.len())];
+            let _ = match kind {
+                0 => txn.remove(&key).map(drop),
+                1 => txn.entry(&key).map(|entry| {
+                    entry.or_default();
+                }),
+                _ => txn.remove_with_proof(&key, hasher).map(drop),
+            };
+        }
+        let _ = txn.commit(hasher);
+    }
+
+    /// A truncated snapshot never verifies.
+    #[test]
+    fn prop_truncated_snapshots_are_rejected(
+        keys in prop::collection::vec(arb_structured_key_hash(), 2..100),
+        ops in prop::collection::vec((any::<prop::sample::Index>(), any::<Option<u64>>()), 1..50),
+        cut in (0..3usize, 0..3usize, 0..3usize).prop_filter("must cut something", |c| *c != (0, 0, 0)),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+        let old_root = committed_root(&db, &keys);
+        let ops = to_ops(&keys, &ops);
+
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, old_root));
+        for op in ops.iter() {
+            op.apply(&mut txn).unwrap();
+        }
+        let new_root = txn.commit(hasher).unwrap().root;
+        let snapshot = txn.build_initial_snapshot();
+        verify_batch(old_root, new_root, &snapshot, &ops, hasher).unwrap();
+
+        let truncated = truncate_snapshot(&snapshot, cut.0, cut.1, cut.2);
+        prop_assume!(truncated != snapshot);
+        prop_assert!(verify_batch(old_root, new_root, &truncated, &ops, hasher).is_err());
+
+        // Reading a truncated snapshot directly must not panic either.
+        if let Ok(mut txn) = Transaction::from_snapshot(&truncated) {
+            for op in ops.iter() {
+                let _ = op.apply(&mut txn);
+            }
+            let _ = txn.calc_root_hash(hasher);
+        }
+    }
+}
+
+#[test]
+fn injected_errors_surface_from_get_and_commit() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let keys = [KeyHash([0; 8]), KeyHash([1, 0, 0, 0, 0, 0, 0, 0])];
+    let old_root = committed_root(&db, &keys);
+
+    // The first call loads the root.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        FaultyDb::new(db.clone(), [(0, Fault::Error)]),
+        old_root,
+    ));
+    let err = txn.get(&keys[0]).unwrap_err();
+    assert!(err.to_string().contains("Injected fault"));
+    assert_eq!(
+        err.downcast_source::<InjectedFault>(),
+        Some(&InjectedFault {
+            call_idx: 0,
+            hash: Option::from(old_root).unwrap(),
+        })
+    );
+    assert_eq!(txn.data_store.db().calls(), 1);
+
+    // Loading the root and a leaf take two calls, writing the modified leaf and root the next two.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        FaultyDb::new(db, [(3, Fault::Error)]),
+        old_root,
+    ));
+    txn.insert(&keys[0], 10).unwrap();
+    let err = txn.commit(hasher).unwrap_err();
+    assert_eq!(
+        err.downcast_source::<InjectedFault>()
+            .map(|fault| fault.call_idx),
+        Some(3)
+    );
+}
+
+#[test]
+fn keys_with_prefix_reads_nodes_as_it_goes() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let keys = [KeyHash([0; 8]), KeyHash([1, 0, 0, 0, 0, 0, 0, 0])];
+    let old_root = committed_root(&db, &keys);
+
+    // The root and the first leaf take two calls, the second leaf the third.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        FaultyDb::new(db, [(2, Fault::Error)]),
+        old_root,
+    ));
+    let mut found = txn.keys_with_prefix(&[], 0).unwrap();
+    assert_eq!(found.next().unwrap().unwrap(), keys[0]);
+    assert_eq!(txn.data_store.db().calls(), 2);
+
+    let err = found.next().unwrap().unwrap_err();
+    assert_eq!(
+        err.downcast_source::<InjectedFault>()
+            .map(|fault| fault.call_idx),
+        Some(2)
+    );
+    assert!(found.next().is_none());
+}
+
+#[test]
+fn all_or_nothing_replication_keeps_the_secondary_error() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    txn.insert(&KeyHash([0; 8]), 0).unwrap();
+
+    let secondary = FaultyDb::new(MemoryDb::<u64>::empty(), [(0, Fault::Error)]);
+    let err = txn
+        .commit_replicated(hasher, &[&secondary], ReplicationMode::AllOrNothing)
+        .unwrap_err();
+    assert!(err.to_string().contains("secondary database 0"));
+    assert_eq!(
+        err.downcast_source::<InjectedFault>()
+            .map(|fault| fault.call_idx),
+        Some(0)
+    );
+}
+
+#[test]
+fn database_errors_keep_their_source() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let missing = NodeHash::new([7; 32]);
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        FaultyDb::new(db, []),
+        TrieRoot::Node(missing),
+    ));
+    let err = txn.get(&KeyHash([0; 8])).unwrap_err();
+    assert_eq!(
+        err.downcast_source::<MemoryDbError>(),
+        Some(&MemoryDbError::NotFound(missing))
+    );
+    assert!(err.downcast_source::<InjectedFault>().is_none());
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[test]
+fn missing_nodes_report_their_position() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let keys: Vec<_> = (0..16).map(KeyHash::from_u64).collect();
+    let root = committed_root(&db, &keys);
+    let root_hash: NodeHash = Option::from(root).unwrap();
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        FaultyDb::new(db.clone(), [(0, Fault::Error)]),
+        root,
+    ));
+    let err = txn.get(&keys[3]).unwrap_err();
+    let missing = err.downcast_source::<MissingNode>().unwrap();
+    assert!(missing.is_root());
+    assert_eq!((missing.hash, missing.depth), (root_hash, 0));
+    assert_eq!(missing.key.as_deref(), Some(&keys[3].0[..]));
+    assert!(err.downcast_source::<InjectedFault>().is_some());
+
+    // The second call loads the root's child on the path to the key.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        FaultyDb::new(db, [(1, Fault::Error)]),
+        root,
+    ));
+    let err = txn.insert(&keys[3], 30).unwrap_err();
+    let missing = err.downcast_source::<MissingNode>().unwrap();
+    assert_eq!((missing.parent, missing.depth), (Some(root_hash), 1));
+    assert_eq!(missing.key.as_deref(), Some(&keys[3].0[..]));
+    assert!(err.to_string().contains("at depth 1"), "{err}");
+}
@@ -0,0 +1,64 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    fixture::hex32,
+    key_hash, node_hash,
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+/// The root of `from_u64(i) => i` for `i` in `0..16`, hashed with SHA-256.
+const GOLDEN_ROOT: TrieRoot<NodeHash> = TrieRoot::Node(node_hash!(
+    "05b2ea64498acb5d7f173c8b32b3a4aca5822931a96f6676d125f17f863e2a21"
+));
+
+const KEY: KeyHash =
+    key_hash!("0x0100000002000000030000000400000005000000060000000700000008000000");
+
+#[test]
+fn golden_root() {
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::<u64>::empty()),
+        TrieRoot::Empty,
+    ));
+    for i in 0..16 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn
+        .calc_root_hash(&mut DigestHasher::<Sha256>::default())
+        .unwrap();
+    assert_eq!(root, GOLDEN_ROOT);
+}
+
+#[test]
+fn const_keys() {
+    assert_eq!(KEY, KeyHash::from_words([1, 2, 3, 4, 5, 6, 7, 8]));
+    assert_eq!(
+        KEY.to_bytes(),
+        hex32("0100000002000000030000000400000005000000060000000700000008000000")
+    );
+    assert_eq!(KeyHash::from_bytes(&KEY.to_bytes()), KEY);
+
+    const FROM_U64: KeyHash = KeyHash::from_u64(0x0807_0605_0403_0201);
+    assert_eq!(
+        FROM_U64,
+        key_hash!("0102030405060708000000000000000000000000000000000000000000000000")
+    );
+    assert_eq!(
+        node_hash!("00ff10Ab00000000000000000000000000000000000000000000000000000000").bytes[..4],
+        [0x00, 0xff, 0x10, 0xab]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Expected 64 hex digits")]
+fn short_hex_is_rejected() {
+    hex32("00ff");
+}
+
+#[test]
+#[should_panic(expected = "Invalid hex digit")]
+fn invalid_digits_are_rejected() {
+    hex32("0g00000000000000000000000000000000000000000000000000000000000000");
+}
@@ -0,0 +1,169 @@
+mod utils;
+
+use std::{collections::BTreeMap, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{
+        flat::{FixedFlatSnapshot, FlatSnapshot},
+        memory_db::MemoryDb,
+        merkle::SnapshotBuilder,
+    },
+    DigestHasher, FlatError, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+fn flat_snapshot_end_to_end(map: BTreeMap<KeyHash, [u8; 8]>, reads: Vec<KeyHash>) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<[u8; 8]>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for (k, v) in map.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    let keys: Vec<KeyHash> = map.keys().step_by(3).chain(reads.iter()).copied().collect();
+    for key in keys.iter() {
+        txn.get(key).unwrap();
+    }
+    let snapshot = txn.build_initial_snapshot();
+    let buf = snapshot.to_flat();
+    let flat = buf.as_flat();
+
+    assert_eq!(flat.calc_root_hash(hasher).unwrap(), root);
+    for key in keys.iter() {
+        assert_eq!(flat.get(key).unwrap(), map.get(key));
+    }
+
+    let fixed_buf = snapshot.to_fixed_flat();
+    assert_eq!(fixed_buf.leaves.len(), buf.leaves.len() * (32 + 8));
+    let fixed = fixed_buf.as_flat();
+
+    assert_eq!(fixed.calc_root_hash(hasher).unwrap(), root);
+    for key in keys.iter() {
+        assert_eq!(fixed.get(key).unwrap(), map.get(key));
+    }
+}
+
+proptest! {
+    #[test]
+    fn prop_flat_snapshot_matches_snapshot(
+        map in prop::collection::btree_map(arb_key_hash(), any::<[u8; 8]>(), 0..200),
+        reads in prop::collection::vec(arb_key_hash(), 0..20),
+    ) {
+        flat_snapshot_end_to_end(map, reads);
+    }
+
+    #[test]
+    fn prop_flat_snapshot_matches_snapshot_structured_keys(
+        map in prop::collection::btree_map(arb_structured_key_hash(), any::<[u8; 8]>(), 0..200),
+        reads in prop::collection::vec(arb_structured_key_hash(), 0..20),
+    ) {
+        flat_snapshot_end_to_end(map, reads);
+    }
+}
+
+#[test]
+fn flat_snapshot_errors() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<[u8; 8]>::empty());
+    let keys = [KeyHash([0; 8]), KeyHash([0, 0, 1, 0, 0, 0, 0, 0])];
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys.iter() {
+        txn.insert(key, [1; 8]).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // Only the first key is read, so the second leaf is unvisited.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&keys[0]).unwrap();
+    let buf = txn.build_initial_snapshot().to_flat();
+    let flat = buf.as_flat();
+
+    assert!(matches!(flat.get(&keys[1]), Err(FlatError::Unvisited(_))));
+
+    // The keys first differ in their third word, so the root branch has a one word prefix.
+    let broken = FlatSnapshot::new(&buf.branches, &[], &buf.leaves, &buf.unvisited_nodes);
+    assert!(matches!(
+        broken.calc_root_hash(hasher),
+        Err(FlatError::PrefixOutOfRange(_))
+    ));
+
+    let broken =
+        FlatSnapshot::<[u8; 8]>::new(&buf.branches, &buf.prefixes, &[], &buf.unvisited_nodes);
+    assert!(matches!(
+        broken.calc_root_hash(hasher),
+        Err(FlatError::NodeNotFound(_))
+    ));
+
+    let empty = FlatSnapshot::<[u8; 8]>::new(&[], &[], &[], &[]);
+    assert_eq!(empty.calc_root_hash(hasher).unwrap(), TrieRoot::Empty);
+    assert_eq!(empty.get(&keys[0]).unwrap(), None);
+
+    // A leaf is 40 bytes, cut the last one short.
+    let fixed_buf = txn.build_initial_snapshot().to_fixed_flat();
+    let leaves = &fixed_buf.leaves[..fixed_buf.leaves.len() - 1];
+    let broken = FixedFlatSnapshot::<8>::new(
+        &fixed_buf.branches,
+        &fixed_buf.prefixes,
+        leaves,
+        &fixed_buf.unvisited_nodes,
+    );
+    assert_eq!(
+        broken.calc_root_hash(hasher),
+        Err(FlatError::MisalignedLeaves {
+            bytes: 39,
+            stride: 40
+        })
+    );
+    assert!(broken.get(&keys[0]).is_err());
+}
+
+#[test]
+fn corrupt_flat_snapshot_is_an_error() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<[u8; 8]>::empty());
+    let keys = [KeyHash([0; 8]), KeyHash([0, 0, 1, 0, 0, 0, 0, 0])];
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys.iter() {
+        txn.insert(key, [1; 8]).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&keys[0]).unwrap();
+    txn.get(&keys[1]).unwrap();
+    let buf = txn.build_initial_snapshot().to_flat();
+
+    // A root branch that is its own left child would loop forever.
+    let mut branches = buf.branches.to_vec();
+    branches[0].left = 0;
+    let cyclic = FlatSnapshot::new(&branches, &buf.prefixes, &buf.leaves, &buf.unvisited_nodes);
+    assert!(matches!(
+        cyclic.calc_root_hash(hasher),
+        Err(FlatError::BranchOutOfOrder(0))
+    ));
+    assert!(matches!(
+        cyclic.get(&keys[0]),
+        Err(FlatError::BranchOutOfOrder(0))
+    ));
+
+    // The root branch discriminates on the third word, so it has room for a two word prefix at most.
+    let mut branches = buf.branches.to_vec();
+    branches[0].prefix_len = 3;
+    let long_prefix =
+        FlatSnapshot::new(&branches, &buf.prefixes, &buf.leaves, &buf.unvisited_nodes);
+    assert!(matches!(
+        long_prefix.get(&keys[0]),
+        Err(FlatError::PrefixTooLong(0))
+    ));
+}
@@ -0,0 +1,85 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, HashCounts, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[test]
+fn counts_modified_nodes() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..100 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let (root, counts) = txn.calc_root_hash_counted(hasher).unwrap();
+    assert_eq!(root, txn.calc_root_hash(hasher).unwrap());
+    assert_eq!(
+        counts,
+        HashCounts {
+            branches: 99,
+            leaves: 100,
+            stored: 0,
+        }
+    );
+
+    // Updating one key of a balanced trie of 32 keys rehashes its leaf and the 5 branches above it.
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..32 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.insert(&KeyHash::from_u64(3), 0).unwrap();
+    let (_, counts) = txn.calc_root_hash_counted(hasher).unwrap();
+    assert_eq!(
+        counts,
+        HashCounts {
+            branches: 5,
+            leaves: 1,
+            stored: 0,
+        }
+    );
+}
+
+#[test]
+fn counts_stored_hashes_of_uncached_snapshots() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..32 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&KeyHash::from_u64(5)).unwrap();
+    txn.insert(&KeyHash::from_u64(3), 0).unwrap();
+    let expected = txn.calc_root_hash(hasher).unwrap();
+    let snapshot = txn.build_initial_snapshot();
+
+    let mut txn = Transaction::from_snapshot(&snapshot).unwrap();
+    txn.insert(&KeyHash::from_u64(3), 0).unwrap();
+    let (root, uncached) = txn.calc_root_hash_counted(hasher).unwrap();
+    assert_eq!(root, expected);
+    assert!(uncached.stored > 0);
+
+    // Once the snapshot's hashes are cached, only the modified nodes are hashed.
+    snapshot.calc_root_hash(hasher).unwrap();
+    let (root, cached) = txn.calc_root_hash_counted(hasher).unwrap();
+    assert_eq!(root, expected);
+    assert_eq!(cached.stored, 0);
+    assert_eq!(
+        (cached.branches, cached.leaves),
+        (uncached.branches, uncached.leaves)
+    );
+}
@@ -0,0 +1,117 @@
+mod utils;
+
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    KeyHash, Transaction, TrieRoot,
+};
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+fn arb_key() -> impl Strategy<Value = KeyHash> {
+    prop_oneof![arb_key_hash(), arb_structured_key_hash()]
+}
+
+fn agree_on(a: &KeyHash, b: &KeyHash, bits: std::ops::Range<u32>) -> bool {
+    bits.into_iter().all(|bit| a.bit(bit) == b.bit(bit))
+}
+
+proptest! {
+    #[test]
+    fn prop_matches_prefix(key in arb_key(), prefix in arb_key(), len in 0u32..=256) {
+        prop_assert_eq!(key.matches_prefix(&prefix, len), agree_on(&key, &prefix, 0..len));
+        prop_assert!(key.matches_prefix(&key, len));
+    }
+
+    #[test]
+    fn prop_with_prefix_bits(key in arb_key(), prefix in arb_key(), len in 0u32..=256) {
+        let routed = key.with_prefix_bits(&prefix, len);
+        prop_assert!(routed.matches_prefix(&prefix, len));
+        prop_assert!(agree_on(&routed, &key, len..256));
+    }
+
+    #[test]
+    fn prop_prefix_range(key in arb_key(), prefix in arb_key(), len in 0u32..=256) {
+        let range = prefix.prefix_range(len);
+        prop_assert!(range.start().matches_prefix(&prefix, len));
+        prop_assert!(range.end().matches_prefix(&prefix, len));
+        prop_assert!(range.start().cmp_trie_order(&prefix).is_le());
+        prop_assert!(prefix.cmp_trie_order(range.end()).is_le());
+
+        let in_range = range.start().cmp_trie_order(&key).is_le()
+            && key.cmp_trie_order(range.end()).is_le();
+        prop_assert_eq!(in_range, key.matches_prefix(&prefix, len));
+    }
+
+    #[test]
+    fn prop_prefix_range_selects_keys_with_prefix(
+        keys in prop::collection::btree_set(arb_key(), 0..50),
+        prefix in arb_key(),
+        len in 0u32..=256,
+    ) {
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+            Rc::new(MemoryDb::<u64>::empty()),
+            TrieRoot::Empty,
+        ));
+        for (i, key) in keys.iter().enumerate() {
+            txn.insert(key, i as u64).unwrap();
+        }
+
+        let in_range: Vec<KeyHash> = txn
+            .range_get(prefix.prefix_range(len))
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        let with_prefix: Vec<KeyHash> = txn
+            .keys_with_prefix(&prefix.to_bytes(), len as usize)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        prop_assert_eq!(&in_range, &with_prefix);
+        prop_assert!(in_range.iter().all(|key| key.matches_prefix(&prefix, len)));
+        prop_assert_eq!(
+            in_range.len(),
+            keys.iter().filter(|key| key.matches_prefix(&prefix, len)).count()
+        );
+    }
+}
+
+#[test]
+fn prefixes_at_word_edges() {
+    let key = KeyHash([0x5555_5555; 8]);
+
+    assert_eq!(key.prefix_min(0), KeyHash([0; 8]));
+    assert_eq!(key.prefix_max(0), KeyHash([u32::MAX; 8]));
+    assert_eq!(key.prefix_min(256), key);
+    assert_eq!(key.prefix_max(256), key);
+
+    // Bits 0..32 are the whole first word.
+    let mut max = [u32::MAX; 8];
+    max[0] = 0x5555_5555;
+    assert_eq!(key.prefix_max(32), KeyHash(max));
+
+    // The lowest bit of the second word.
+    let mut min = [0; 8];
+    min[0] = 0x5555_5555;
+    min[1] = 1;
+    assert_eq!(key.prefix_min(33), KeyHash(min));
+
+    // Sharding on the first 4 bits puts `from_u64(n)` in shard `n % 16`.
+    let shard = KeyHash::from_u64(5);
+    for n in 0..64 {
+        assert_eq!(KeyHash::from_u64(n).matches_prefix(&shard, 4), n % 16 == 5);
+    }
+    assert_eq!(
+        KeyHash::from_u64(0x1230).with_prefix_bits(&shard, 4),
+        KeyHash::from_u64(0x1235)
+    );
+}
+
+#[test]
+#[should_panic]
+fn prefixes_longer_than_a_key_panic() {
+    KeyHash::from_u64(1).prefix_min(257);
+}
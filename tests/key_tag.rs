@@ -0,0 +1,62 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, KeyTag, KeyTagged, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+type Value = KeyTagged<u64>;
+
+fn txn() -> Transaction<SnapshotBuilder<Rc<MemoryDb<Value>>, Value>, Value> {
+    Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::empty()),
+        TrieRoot::Empty,
+    ))
+}
+
+#[test]
+fn insert_tagged_detects_collisions() {
+    let account = KeyTag::from_preimage(b"account/alice");
+    let contract = KeyTag::from_preimage(b"contract/alice");
+    let key = KeyHash::from_u64(7);
+
+    let mut txn = txn();
+    txn.insert_tagged(&key, account, 1).unwrap();
+    txn.insert_tagged(&key, account, 2).unwrap();
+    assert_eq!(txn.get_tagged(&key, account).unwrap(), Some(&2));
+
+    let err = txn.insert_tagged(&key, contract, 3).unwrap_err();
+    assert!(err.to_string().contains("collision"), "{err}");
+    assert!(txn.get_tagged(&key, contract).is_err());
+    assert_eq!(txn.get_tagged(&key, account).unwrap(), Some(&2));
+
+    assert_eq!(
+        txn.get_tagged(&KeyHash::from_u64(8), contract).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn tags_are_part_of_the_leaf_hash() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let key = KeyHash::from_u64(7);
+
+    let mut a = txn();
+    a.insert_tagged(&key, KeyTag::from_preimage(b"a"), 1)
+        .unwrap();
+    let mut b = txn();
+    b.insert_tagged(&key, KeyTag::from_preimage(b"b"), 1)
+        .unwrap();
+
+    assert_ne!(
+        a.calc_root_hash(hasher).unwrap(),
+        b.calc_root_hash(hasher).unwrap()
+    );
+}
+
+#[test]
+fn from_preimage_pads_short_keys() {
+    assert_eq!(KeyTag::from_preimage(b"abc").0, *b"abc\0\0\0\0\0");
+    assert_eq!(KeyTag::from_preimage(b"0123456789").0, *b"01234567");
+}
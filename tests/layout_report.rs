@@ -0,0 +1,69 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    layout_report,
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, PortableHash, PortableHasher, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn commit(db: &Rc<MemoryDb<u64>>, keys: impl IntoIterator<Item = KeyHash>) -> TrieRoot<NodeHash> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys {
+        txn.insert(&key, 0).unwrap();
+    }
+    txn.commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root
+}
+
+#[test]
+fn dense_indices_make_a_balanced_trie() {
+    let db = Rc::new(MemoryDb::empty());
+    let root = commit(&db, (0..256).map(KeyHash::from_u64));
+    let report = layout_report(&db, root).unwrap();
+
+    assert_eq!(report.leaf_count(), 256);
+    assert_eq!(report.branch_count(), 255);
+    assert_eq!(report.proof_lengths().collect::<Vec<_>>(), [(8, 256)]);
+    assert_eq!(report.mean_proof_len(), Some(8.0));
+    assert_eq!(report.balanced_proof_len(), 8);
+    for bit in 0..8 {
+        assert_eq!(report.bit_branches[bit], 1 << bit);
+    }
+    assert!(report.bit_branches[8..].iter().all(|&count| count == 0));
+    assert!(report.depths.iter().all(|depth| depth.skipped_bits == 0));
+}
+
+#[test]
+fn sparse_keys_skip_bits() {
+    // Indices that are all multiples of 16 agree on their 4 lowest bits.
+    let db = Rc::new(MemoryDb::empty());
+    let root = commit(&db, (0..64).map(|i| KeyHash::from_u64(i * 16)));
+    let report = layout_report(&db, root).unwrap();
+
+    assert_eq!(report.depths[0].skipped_bits, 4);
+    assert_eq!(report.max_proof_len(), Some(6));
+    assert!(report.bit_branches[..4].iter().all(|&count| count == 0));
+}
+
+#[test]
+fn hashed_keys_are_near_balanced() {
+    let db = Rc::new(MemoryDb::empty());
+    let keys = (0..1000u64).map(|i| {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        i.portable_hash(hasher);
+        KeyHash::from_bytes(&hasher.finalize_reset())
+    });
+    let root = commit(&db, keys);
+    let report = layout_report(&db, root).unwrap();
+
+    assert_eq!(report.leaf_count(), 1000);
+    let mean = report.mean_proof_len().unwrap();
+    assert!((9.0..13.0).contains(&mean), "{mean}");
+
+    let empty = layout_report(&db, TrieRoot::Empty).unwrap();
+    assert_eq!(empty.leaf_count(), 0);
+    assert_eq!(empty.mean_proof_len(), None);
+}
@@ -0,0 +1,85 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, Store},
+    DigestHasher, KeyHash, Leaf, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+/// Two roots over 64 consecutive keys, the second with a different value at key 5.
+fn roots(db: &Rc<MemoryDb<u64>>) -> (TrieRoot<NodeHash>, TrieRoot<NodeHash>) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..64 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let old_root = txn.commit(hasher).unwrap().root;
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+    txn.insert(&KeyHash::from_u64(5), 500).unwrap();
+    let new_root = txn.commit(hasher).unwrap().root;
+
+    (old_root, new_root)
+}
+
+#[test]
+fn locate_mismatch_finds_the_changed_leaf() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let (old_root, new_root) = roots(&db);
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+    for i in 0..64 {
+        txn.get(&KeyHash::from_u64(i)).unwrap();
+    }
+    let snapshot = txn.build_initial_snapshot();
+
+    assert_eq!(
+        snapshot.locate_mismatch(&db, old_root, hasher).unwrap(),
+        None
+    );
+
+    let mismatch = snapshot
+        .locate_mismatch(&db, new_root, hasher)
+        .unwrap()
+        .unwrap();
+    // The trie branches on the least significant bit first, 5 is 0b101.
+    assert_eq!(*mismatch.path, [true, false, true, false, false, false]);
+    let leaf = Leaf {
+        key_hash: KeyHash::from_u64(5),
+        value: 500u64,
+    };
+    assert_eq!(mismatch.expected, leaf.hash_leaf(hasher));
+    assert_eq!(
+        mismatch.actual,
+        snapshot.calc_subtree_hash(hasher, mismatch.idx).unwrap()
+    );
+}
+
+#[test]
+fn locate_mismatch_stops_at_unvisited_nodes() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let (old_root, new_root) = roots(&db);
+
+    // Key 0 is under the left child of the root, key 5 under the right.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+    txn.get(&KeyHash::from_u64(0)).unwrap();
+    let snapshot = txn.build_initial_snapshot();
+
+    let mismatch = snapshot
+        .locate_mismatch(&db, new_root, hasher)
+        .unwrap()
+        .unwrap();
+    assert_eq!(*mismatch.path, [true]);
+    assert_eq!(
+        snapshot.get_unvisited_hash(mismatch.idx).unwrap(),
+        Some(mismatch.actual)
+    );
+
+    assert!(snapshot
+        .locate_mismatch(&db, TrieRoot::Empty, hasher)
+        .is_err());
+}
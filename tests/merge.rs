@@ -0,0 +1,101 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    merge_disjoint,
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, PortableHash, PortableHasher, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn commit(db: &Rc<MemoryDb<u64>>, keys: impl IntoIterator<Item = KeyHash>) -> TrieRoot<NodeHash> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys {
+        txn.insert(&key, key.0[0] as u64).unwrap();
+    }
+    txn.commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root
+}
+
+fn hashed_key(i: u64) -> KeyHash {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    i.portable_hash(hasher);
+    KeyHash::from_bytes(&hasher.finalize_reset())
+}
+
+/// Merge the shards into a fresh database, and check the result is the trie of all their keys.
+fn check_merge(shard_a: &[KeyHash], shard_b: &[KeyHash]) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (db_a, db_b) = (Rc::new(MemoryDb::empty()), Rc::new(MemoryDb::empty()));
+    let root_a = commit(&db_a, shard_a.iter().copied());
+    let root_b = commit(&db_b, shard_b.iter().copied());
+
+    let db_out = Rc::new(MemoryDb::empty());
+    let merged = merge_disjoint(&db_a, root_a, &db_b, root_b, &db_out, hasher).unwrap();
+
+    let expected = commit(
+        &Rc::new(MemoryDb::empty()),
+        shard_a.iter().chain(shard_b).copied(),
+    );
+    assert_eq!(merged, expected);
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db_out, merged));
+    for key in shard_a.iter().chain(shard_b) {
+        assert_eq!(txn.get(key).unwrap(), Some(&(key.0[0] as u64)));
+    }
+    assert_eq!(txn.commit(hasher).unwrap().root, merged);
+}
+
+#[test]
+fn merge_shards_of_a_key_range() {
+    // Sharded on the lowest bit, the first bit the trie branches on.
+    let (even, odd): (Vec<_>, Vec<_>) = (0..200)
+        .map(KeyHash::from_u64)
+        .partition(|k| k.0[0] % 2 == 0);
+    check_merge(&even, &odd);
+
+    // Sharded on a high bit, so the shards interleave below many branches.
+    let (low, high): (Vec<_>, Vec<_>) = (0..200).map(KeyHash::from_u64).partition(|k| k.0[0] < 128);
+    check_merge(&low, &high);
+}
+
+#[test]
+fn merge_interleaved_hashed_keys() {
+    let keys: Vec<_> = (0..300).map(hashed_key).collect();
+    check_merge(&keys[..150], &keys[150..]);
+    check_merge(&keys[..1], &keys[1..]);
+
+    // Keys sharing whole words, so branches carry prefix words that change when re-parented.
+    let deep: Vec<_> = (0..40)
+        .map(|i| KeyHash([7, 7, 7, i % 3, 9, 9, i, 0]))
+        .chain((0..40).map(|i| KeyHash([7, 7, i % 5, 7, 7, 7, 7, i])))
+        .collect();
+    check_merge(&deep[..40], &deep[40..]);
+    check_merge(
+        &deep.iter().step_by(2).copied().collect::<Vec<_>>(),
+        &deep.iter().skip(1).step_by(2).copied().collect::<Vec<_>>(),
+    );
+}
+
+#[test]
+fn merge_with_an_empty_trie() {
+    let keys: Vec<_> = (0..20).map(hashed_key).collect();
+    check_merge(&keys, &[]);
+    check_merge(&[], &keys);
+    check_merge(&[], &[]);
+}
+
+#[test]
+fn merge_rejects_shared_keys() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::empty());
+    let root_a = commit(&db, (0..50).map(hashed_key));
+    let root_b = commit(&db, (49..100).map(hashed_key));
+
+    let err = merge_disjoint(&db, root_a, &db, root_b, &db, hasher).unwrap_err();
+    assert!(
+        err.to_string().contains(&format!("{:?}", hashed_key(49))),
+        "{err}"
+    );
+}
@@ -0,0 +1,55 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, ModifiedShape, NodeRef, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[test]
+fn modified_shape_tracks_the_modified_spine() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    assert_eq!(txn.modified_shape(), ModifiedShape::default());
+    assert_eq!(txn.current_root(), &TrieRoot::Empty);
+
+    // 64 consecutive keys make a balanced trie.
+    for i in 0..64 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    assert_eq!(
+        txn.modified_shape(),
+        ModifiedShape {
+            mod_branches: 63,
+            mod_leaves: 64,
+            stored: 0,
+            depth: 6,
+        }
+    );
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+
+    // Reads leave the stored trie untouched.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&KeyHash::from_u64(0)).unwrap();
+    assert!(matches!(
+        txn.current_root(),
+        TrieRoot::Node(NodeRef::Stored(0))
+    ));
+    assert_eq!(txn.modified_shape().stored, 1);
+
+    // A write only loads its path, the sibling subtrees stay stored.
+    txn.insert(&KeyHash::from_u64(0), 1).unwrap();
+    assert_eq!(
+        txn.modified_shape(),
+        ModifiedShape {
+            mod_branches: 6,
+            mod_leaves: 1,
+            stored: 6,
+            depth: 6,
+        }
+    );
+}
@@ -0,0 +1,140 @@
+#![cfg(feature = "test-utils")]
+
+mod utils;
+
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    naive::NaiveMerkleMap,
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, PortableHasher, TaggedHasher, Transaction, TrieParams,
+    TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+struct Tagged;
+
+impl TrieParams for Tagged {
+    const LEAF_TAG: &'static [u8] = b"naive/leaf";
+    const BRANCH_TAG: &'static [u8] = b"naive/branch";
+}
+
+struct Counted;
+
+impl TrieParams for Counted {
+    const LEAF_TAG: &'static [u8] = &[];
+    const BRANCH_TAG: &'static [u8] = &[];
+    const COUNTED: bool = true;
+}
+
+/// Apply `ops` to both a trie, committing after every batch, and a `NaiveMerkleMap`,
+/// checking their roots agree after each batch.
+fn check_against_trie<H: PortableHasher<32>>(
+    batches: &[Vec<(KeyHash, Option<u64>)>],
+) -> Result<(), TestCaseError> {
+    let hasher = &mut H::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut naive = NaiveMerkleMap::new();
+    let mut root = TrieRoot::Empty;
+
+    for ops in batches {
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+        for (key, op) in ops {
+            match op {
+                Some(value) => {
+                    txn.insert(key, *value).unwrap();
+                    naive.insert(*key, *value);
+                }
+                None => {
+                    prop_assert_eq!(txn.remove(key).unwrap(), naive.remove(key));
+                }
+            }
+        }
+
+        root = txn.commit(hasher).unwrap().root;
+        prop_assert_eq!(root, naive.root_hash(hasher));
+    }
+
+    Ok(())
+}
+
+fn arb_batches(
+    key: impl Strategy<Value = KeyHash>,
+) -> impl Strategy<Value = Vec<Vec<(KeyHash, Option<u64>)>>> {
+    let op = (key, prop::option::weighted(0.8, 0u64..1000));
+    prop::collection::vec(prop::collection::vec(op, 0..50), 1..5)
+}
+
+proptest! {
+    #[test]
+    fn prop_naive_root_matches_trie(batches in arb_batches(arb_key_hash())) {
+        check_against_trie::<DigestHasher<Sha256>>(&batches)?;
+    }
+
+    #[test]
+    fn prop_naive_root_matches_trie_with_shared_prefixes(
+        batches in arb_batches(arb_structured_key_hash()),
+    ) {
+        check_against_trie::<DigestHasher<Sha256>>(&batches)?;
+        check_against_trie::<TaggedHasher<DigestHasher<Sha256>, Tagged>>(&batches)?;
+        check_against_trie::<TaggedHasher<DigestHasher<Sha256>, Counted>>(&batches)?;
+    }
+}
+
+/// Keys that differ only at the edges of words, where off by one errors in masks and prefixes hide.
+fn adversarial_keys() -> Vec<KeyHash> {
+    let mut keys = vec![KeyHash([0; 8]), KeyHash([u32::MAX; 8])];
+    for word in 0..8 {
+        for bit in [0, 1, 30, 31] {
+            let mut key = [0; 8];
+            key[word] = 1 << bit;
+            keys.push(KeyHash(key));
+
+            let mut key = [u32::MAX; 8];
+            key[word] ^= 1 << bit;
+            keys.push(KeyHash(key));
+        }
+    }
+    keys
+}
+
+#[test]
+fn naive_root_matches_trie_on_adversarial_keys() {
+    let keys = adversarial_keys();
+    let inserts: Vec<_> = keys.iter().zip(0..).map(|(k, v)| (*k, Some(v))).collect();
+    // Remove every other key, leaving branches whose parents were removed.
+    let removes: Vec<_> = keys.iter().step_by(2).map(|k| (*k, None)).collect();
+
+    check_against_trie::<DigestHasher<Sha256>>(&[inserts.clone(), removes.clone()]).unwrap();
+    // One insert per transaction, so every key is added to a committed trie.
+    let single: Vec<_> = inserts.iter().map(|op| vec![*op]).collect();
+    check_against_trie::<DigestHasher<Sha256>>(&single).unwrap();
+}
+
+#[test]
+fn naive_map_basics() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut map: NaiveMerkleMap<u64> = [(KeyHash::from_u64(1), 1), (KeyHash::from_u64(2), 2)]
+        .into_iter()
+        .collect();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.insert(KeyHash::from_u64(1), 10), Some(1));
+    assert_eq!(map.get(&KeyHash::from_u64(1)), Some(&10));
+
+    // Iteration is in trie order, least significant bit first, so 2 comes before 1.
+    let keys: Vec<_> = map.iter().map(|(k, _)| *k).collect();
+    assert_eq!(keys, vec![KeyHash::from_u64(2), KeyHash::from_u64(1)]);
+
+    assert_eq!(map.remove(&KeyHash::from_u64(2)), Some(2));
+    assert_eq!(map.remove(&KeyHash::from_u64(2)), None);
+    let TrieRoot::Node(_) = map.root_hash(hasher) else {
+        panic!("A non-empty map has a root");
+    };
+
+    map.remove(&KeyHash::from_u64(1));
+    assert!(map.is_empty());
+    assert_eq!(map.root_hash(hasher), TrieRoot::<NodeHash>::Empty);
+}
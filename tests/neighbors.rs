@@ -0,0 +1,112 @@
+mod utils;
+
+use std::{collections::BTreeMap, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, Store},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_structured_key_hash;
+
+type Neighbors = Vec<(Option<(KeyHash, u64)>, Option<(KeyHash, u64)>)>;
+
+fn expected_neighbors(map: &BTreeMap<KeyHash, u64>, queries: &[KeyHash]) -> Neighbors {
+    let mut sorted: Vec<_> = map.iter().map(|(k, v)| (*k, *v)).collect();
+    sorted.sort_by(|(a, _), (b, _)| a.cmp_trie_order(b));
+
+    queries
+        .iter()
+        .map(|query| {
+            let next = sorted
+                .iter()
+                .find(|(k, _)| k.cmp_trie_order(query).is_gt())
+                .copied();
+            let prev = sorted
+                .iter()
+                .rev()
+                .find(|(k, _)| k.cmp_trie_order(query).is_lt())
+                .copied();
+            (next, prev)
+        })
+        .collect()
+}
+
+fn neighbors<S: Store<u64>>(txn: &Transaction<S, u64>, queries: &[KeyHash]) -> Neighbors {
+    queries
+        .iter()
+        .map(|query| {
+            let next = txn.next_key_after(query).unwrap().map(|(k, v)| (k, *v));
+            let prev = txn.prev_key_before(query).unwrap().map(|(k, v)| (k, *v));
+            (next, prev)
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn prop_neighbors_match_sorted_keys(
+        committed in prop::collection::btree_map(arb_structured_key_hash(), 0u64..1000, 0..100),
+        pending in prop::collection::btree_map(arb_structured_key_hash(), 0u64..1000, 0..20),
+        queries in prop::collection::vec(arb_structured_key_hash(), 1..20),
+    ) {
+        let db = Rc::new(MemoryDb::<u64>::empty());
+        let hasher = &mut DigestHasher::<Sha256>::default();
+
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for (k, v) in committed.iter() {
+            txn.insert(k, *v).unwrap();
+        }
+        let root = txn.commit(hasher).unwrap().root;
+
+        // Mix stored and modified nodes in the trie we query.
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for (k, v) in pending.iter() {
+            txn.insert(k, *v).unwrap();
+        }
+        // Query the keys present as well as the random ones.
+        let queries: Vec<KeyHash> = queries
+            .into_iter()
+            .chain(committed.keys().take(5).copied())
+            .collect();
+
+        let mut map = committed;
+        map.extend(pending.iter().map(|(k, v)| (*k, *v)));
+        let expected = expected_neighbors(&map, &queries);
+        prop_assert_eq!(neighbors(&txn, &queries), expected.clone());
+
+        // The snapshot must be enough to replay the same queries.
+        let snapshot = txn.build_initial_snapshot();
+        prop_assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+
+        let mut txn = Transaction::from_snapshot(&snapshot).unwrap();
+        for (k, v) in pending.iter() {
+            txn.insert(k, *v).unwrap();
+        }
+        prop_assert_eq!(neighbors(&txn, &queries), expected);
+    }
+}
+
+#[test]
+fn neighbors_of_consecutive_keys() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, TrieRoot::Empty));
+    assert_eq!(txn.next_key_after(&KeyHash::from_u64(0)).unwrap(), None);
+
+    // Even keys only. In trie order the least significant bit comes first,
+    // so 2 is followed by 18, then 10.
+    for i in (0..32).step_by(2) {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+
+    let key = |i| KeyHash::from_u64(i);
+    assert_eq!(txn.next_key_after(&key(2)).unwrap(), Some((key(18), &18)));
+    assert_eq!(txn.prev_key_before(&key(18)).unwrap(), Some((key(2), &2)));
+    assert_eq!(txn.prev_key_before(&key(0)).unwrap(), None);
+    // Every odd key comes after every even key.
+    assert_eq!(txn.next_key_after(&key(1)).unwrap(), None);
+    assert_eq!(txn.prev_key_before(&key(1)).unwrap(), Some((key(30), &30)));
+}
@@ -0,0 +1,105 @@
+use std::{rc::Rc, sync::Arc, thread};
+
+use kairos_trie::{
+    stored::{
+        memory_db::{MemoryDb, SyncMemoryDb},
+        merkle::SnapshotBuilder,
+        node_cache::{CachedDb, NodeCache},
+    },
+    DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+/// Build `blocks` consecutive blocks of 100 updates each, with a new builder per block.
+fn build_blocks(
+    blocks: u64,
+    mut builder: impl FnMut(
+        TrieRoot<NodeHash>,
+    ) -> SnapshotBuilder<CachedDb<Rc<MemoryDb<u64>>, u64>, u64>,
+) -> TrieRoot<NodeHash> {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut root = TrieRoot::Empty;
+    for block in 0..blocks {
+        let mut txn = Transaction::from_snapshot_builder(builder(root));
+        for i in 0..100 {
+            txn.insert(&KeyHash::from_u64(i * 7 % 1000 + block), block)
+                .unwrap();
+        }
+        root = txn.commit(hasher).unwrap().root;
+    }
+    root
+}
+
+#[test]
+fn cached_builders_reach_the_same_roots() {
+    let uncached_db = Rc::new(MemoryDb::<u64>::empty());
+    let uncached_root = build_blocks(5, |root| {
+        // A cache too small to ever hit.
+        let cache = Arc::new(NodeCache::new(1));
+        SnapshotBuilder::new(CachedDb::new(uncached_db.clone(), cache), root)
+    });
+
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let cache = Arc::new(NodeCache::new(10_000));
+    let root = build_blocks(5, |root| {
+        SnapshotBuilder::new(CachedDb::new(db.clone(), cache.clone()), root)
+    });
+
+    assert_eq!(root, uncached_root);
+    // Every node read was written by an earlier block, and so is cached.
+    assert_eq!(cache.misses(), 0);
+    assert!(cache.hits() > 0);
+}
+
+#[test]
+fn node_cache_is_bounded() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let cache = Arc::new(NodeCache::new(50));
+    let root = build_blocks(5, |root| {
+        SnapshotBuilder::new(CachedDb::new(db.clone(), cache.clone()), root)
+    });
+    assert!(cache.len() <= 100);
+
+    // Evicted nodes are read from the database again.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        CachedDb::new(db, cache.clone()),
+        root,
+    ));
+    for i in 0..1000 {
+        txn.get(&KeyHash::from_u64(i)).unwrap();
+    }
+    assert!(cache.misses() > 0);
+    assert!(cache.len() <= 100);
+
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn threads_share_one_node_cache() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Arc::new(SyncMemoryDb::<u64>::empty());
+    let cache = Arc::new(NodeCache::new(10_000));
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        CachedDb::new(db.clone(), cache.clone()),
+        TrieRoot::Empty,
+    ));
+    for i in 0..256 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    thread::scope(|scope| {
+        for t in 0..4u64 {
+            let db = CachedDb::new(db.clone(), cache.clone());
+            scope.spawn(move || {
+                let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+                for i in (t * 64)..(t + 1) * 64 {
+                    assert_eq!(txn.get(&KeyHash::from_u64(i)).unwrap(), Some(&i));
+                }
+            });
+        }
+    });
+    assert_eq!(cache.misses(), 0);
+}
@@ -0,0 +1,131 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    spec::{
+        leaf_preimage, value_len, NodeLimits, DEFAULT_MAX_VALUE_BYTES, MAX_BRANCH_PREFIX_WORDS,
+    },
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    verify_batch, Branch, BranchMask, DigestHasher, KeyHash, NodeSizeError, Op, Transaction,
+    TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+
+fn branch(prefix_words: usize) -> Branch<()> {
+    Branch {
+        left: (),
+        right: (),
+        mask: BranchMask::new(7, 0, 1),
+        prior_word: 0,
+        prefix: vec![0; prefix_words].into(),
+    }
+}
+
+#[test]
+fn limits_bound_prefixes_and_values() {
+    let limits = NodeLimits::default();
+    limits
+        .check_branch(&branch(MAX_BRANCH_PREFIX_WORDS))
+        .unwrap();
+    assert_eq!(
+        limits.check_branch(&branch(MAX_BRANCH_PREFIX_WORDS + 1)),
+        Err(NodeSizeError::PrefixTooLong {
+            words: MAX_BRANCH_PREFIX_WORDS + 1,
+            max: MAX_BRANCH_PREFIX_WORDS
+        })
+    );
+
+    // A looser prefix limit cannot admit a non-canonical branch.
+    let loose = NodeLimits {
+        max_prefix_words: 100,
+        ..limits
+    };
+    assert!(loose
+        .check_branch(&branch(MAX_BRANCH_PREFIX_WORDS + 1))
+        .is_err());
+
+    // The value's size is its share of the leaf preimage.
+    let value = vec![7u8; 100];
+    let preimage = leaf_preimage::<DigestHasher<Sha256>, _>(&KeyHash::from_u64(0), &value);
+    assert_eq!(value_len(&value), preimage.len() - 32);
+
+    let strict = NodeLimits {
+        max_value_bytes: value_len(&value) - 1,
+        ..limits
+    };
+    limits.check_value(&value).unwrap();
+    assert_eq!(
+        strict.check_value(&value),
+        Err(NodeSizeError::ValueTooLarge {
+            bytes: value_len(&value),
+            max: value_len(&value) - 1
+        })
+    );
+}
+
+#[test]
+fn verify_batch_rejects_oversized_values() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<Vec<u8>>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    txn.insert(&KeyHash::from_u64(0), vec![0; 16]).unwrap();
+    txn.insert(&KeyHash::from_u64(1), vec![1; DEFAULT_MAX_VALUE_BYTES])
+        .unwrap();
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&KeyHash::from_u64(0)).unwrap();
+    txn.get(&KeyHash::from_u64(1)).unwrap();
+    let snapshot = txn.build_initial_snapshot();
+
+    snapshot.check_limits(&NodeLimits::default()).unwrap();
+    let ops = [Op::Get(KeyHash::from_u64(0))];
+    verify_batch(root, root, &snapshot, &ops, hasher).unwrap();
+
+    let strict = NodeLimits {
+        max_value_bytes: 1024,
+        ..NodeLimits::default()
+    };
+    let error = snapshot.check_limits(&strict).unwrap_err();
+    assert!(matches!(
+        error.downcast_source::<NodeSizeError>(),
+        Some(NodeSizeError::ValueTooLarge { max: 1024, .. })
+    ));
+
+    // One byte over the default limit.
+    let db = Rc::new(MemoryDb::<Vec<u8>>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    txn.insert(&KeyHash::from_u64(1), vec![1; DEFAULT_MAX_VALUE_BYTES + 1])
+        .unwrap();
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&KeyHash::from_u64(1)).unwrap();
+    let snapshot = txn.build_initial_snapshot();
+
+    let Err(VerifyError::Trie(error)) = verify_batch(root, root, &snapshot, &[], hasher) else {
+        panic!("an oversized value must fail verification");
+    };
+    assert!(matches!(
+        error.downcast_source::<NodeSizeError>(),
+        Some(NodeSizeError::ValueTooLarge { .. })
+    ));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn decoding_rejects_long_prefixes() {
+    let bytes = bincode::serialize(&branch(MAX_BRANCH_PREFIX_WORDS)).unwrap();
+    let decoded: Branch<()> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(decoded, branch(MAX_BRANCH_PREFIX_WORDS));
+
+    let bytes = bincode::serialize(&branch(MAX_BRANCH_PREFIX_WORDS + 1)).unwrap();
+    assert!(bincode::deserialize::<Branch<()>>(&bytes).is_err());
+
+    // A length prefix claiming a huge prefix is rejected without allocating it.
+    let mut bytes = bincode::serialize(&branch(0)).unwrap();
+    let len_offset = bytes.len() - 8;
+    bytes[len_offset..].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(bincode::deserialize::<Branch<()>>(&bytes).is_err());
+}
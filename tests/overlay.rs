@@ -0,0 +1,124 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn stored_trie(
+    hasher: &mut DigestHasher<Sha256>,
+    keys: impl IntoIterator<Item = u64>,
+) -> (Rc<MemoryDb<u64>>, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in keys {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+    (db, root)
+}
+
+#[test]
+fn overlay_reads_through_and_writes_stay_in_the_child() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (db, root) = stored_trie(hasher, 0..100);
+
+    let mut base = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    base.insert(&KeyHash::from_u64(5), 500).unwrap();
+    base.insert(&KeyHash::from_u64(1000), 1000).unwrap();
+    let base_root = base.calc_root_hash(hasher).unwrap();
+
+    {
+        let mut child = base.overlay().unwrap();
+        assert_eq!(child.calc_root_hash(hasher).unwrap(), base_root);
+
+        // The parent's modified nodes, then its store.
+        assert_eq!(child.get(&KeyHash::from_u64(5)).unwrap(), Some(&500));
+        assert_eq!(child.get(&KeyHash::from_u64(1000)).unwrap(), Some(&1000));
+        assert_eq!(child.get(&KeyHash::from_u64(6)).unwrap(), Some(&6));
+        assert_eq!(child.get(&KeyHash::from_u64(2000)).unwrap(), None);
+
+        child.insert(&KeyHash::from_u64(5), 0).unwrap();
+        child.insert(&KeyHash::from_u64(2000), 2000).unwrap();
+        assert_eq!(child.remove(&KeyHash::from_u64(7)).unwrap(), Some(7));
+        assert_eq!(child.get(&KeyHash::from_u64(5)).unwrap(), Some(&0));
+        assert_ne!(child.calc_root_hash(hasher).unwrap(), base_root);
+        // Dropped without merging.
+    }
+
+    assert_eq!(base.get(&KeyHash::from_u64(5)).unwrap(), Some(&500));
+    assert_eq!(base.get(&KeyHash::from_u64(2000)).unwrap(), None);
+    assert_eq!(base.get(&KeyHash::from_u64(7)).unwrap(), Some(&7));
+    assert_eq!(base.calc_root_hash(hasher).unwrap(), base_root);
+}
+
+#[test]
+fn merged_overlay_matches_direct_writes() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (db, root) = stored_trie(hasher, 0..100);
+
+    let parent_ops = |txn: &mut Transaction<_, u64>| {
+        txn.insert(&KeyHash::from_u64(5), 500).unwrap();
+        txn.insert(&KeyHash::from_u64(1000), 1000).unwrap();
+        txn.remove(&KeyHash::from_u64(50)).unwrap();
+    };
+
+    let mut direct = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    parent_ops(&mut direct);
+
+    let mut base = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    parent_ops(&mut base);
+
+    let mut child = base.overlay().unwrap();
+    for i in (0..150).step_by(3) {
+        direct.insert(&KeyHash::from_u64(i), i * 2).unwrap();
+        child.insert(&KeyHash::from_u64(i), i * 2).unwrap();
+    }
+    for i in (1..150).step_by(7) {
+        assert_eq!(
+            direct.remove(&KeyHash::from_u64(i)).unwrap(),
+            child.remove(&KeyHash::from_u64(i)).unwrap()
+        );
+    }
+    let child_root = child.calc_root_hash(hasher).unwrap();
+    child.merge_into_parent().unwrap();
+
+    let direct_root = direct.calc_root_hash(hasher).unwrap();
+    assert_eq!(child_root, direct_root);
+    assert_eq!(base.calc_root_hash(hasher).unwrap(), direct_root);
+    assert_eq!(base.get(&KeyHash::from_u64(1000)).unwrap(), Some(&1000));
+    assert_eq!(base.get(&KeyHash::from_u64(3)).unwrap(), Some(&6));
+
+    // The merged trie keeps working as the parent's own.
+    base.insert(&KeyHash::from_u64(3000), 3).unwrap();
+    direct.insert(&KeyHash::from_u64(3000), 3).unwrap();
+    assert_eq!(
+        base.commit(hasher).unwrap().root,
+        direct.commit(hasher).unwrap().root
+    );
+}
+
+#[test]
+fn overlay_of_unmodified_and_empty_parents() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (db, root) = stored_trie(hasher, 0..10);
+
+    let mut base = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    let mut child = base.overlay().unwrap();
+    child.insert(&KeyHash::from_u64(20), 20).unwrap();
+    child.merge_into_parent().unwrap();
+    assert_eq!(base.get(&KeyHash::from_u64(20)).unwrap(), Some(&20));
+    assert_eq!(base.get(&KeyHash::from_u64(2)).unwrap(), Some(&2));
+
+    let mut empty = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::<u64>::empty()),
+        TrieRoot::Empty,
+    ));
+    let mut child = empty.overlay().unwrap();
+    assert_eq!(child.get(&KeyHash::from_u64(1)).unwrap(), None);
+    child.insert(&KeyHash::from_u64(1), 1).unwrap();
+    child.merge_into_parent().unwrap();
+    assert_eq!(empty.get(&KeyHash::from_u64(1)).unwrap(), Some(&1));
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeSet;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb, merkle::SnapshotBuilder, refcount::RefCountDb, DatabaseGet,
+        DatabasePin,
+    },
+    DigestHasher, KeyHash, Node, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+type Db = RefCountDb<MemoryDb<u64>>;
+
+/// Every node hash reachable from `root` in `db`.
+fn reachable(db: &Db, root: TrieRoot<NodeHash>) -> BTreeSet<NodeHash> {
+    let mut hashes = BTreeSet::new();
+    let mut stack: Vec<NodeHash> = match root {
+        TrieRoot::Node(hash) => vec![hash],
+        TrieRoot::Empty => vec![],
+    };
+
+    while let Some(hash) = stack.pop() {
+        hashes.insert(hash);
+        if let Node::Branch(branch) = db.get(&hash).unwrap() {
+            stack.push(branch.left);
+            stack.push(branch.right);
+        }
+    }
+
+    hashes
+}
+
+/// Apply `writes` to the trie at `root`, committing with the events fed to `db`.
+fn commit(db: &Db, root: TrieRoot<NodeHash>, writes: &[(u64, Option<u64>)]) -> TrieRoot<NodeHash> {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for (key, value) in writes {
+        let key = KeyHash::from_u64(*key);
+        match value {
+            Some(value) => txn.insert(&key, *value).unwrap(),
+            None => {
+                txn.remove(&key).unwrap();
+            }
+        }
+    }
+    txn.commit_with_events(hasher, &mut |event| db.apply(event))
+        .unwrap()
+}
+
+fn node(root: TrieRoot<NodeHash>) -> NodeHash {
+    let TrieRoot::Node(hash) = root else {
+        panic!("The trie is not empty");
+    };
+    hash
+}
+
+#[test]
+fn collect_returns_nodes_no_root_reaches() {
+    let db = RefCountDb::new(MemoryDb::empty());
+    let writes: Vec<_> = (0..32).map(|key| (key, Some(key))).collect();
+    let first = commit(&db, TrieRoot::Empty, &writes);
+    assert!(db.collect().unwrap().is_empty());
+
+    let second = commit(&db, first, &[(3, Some(100)), (7, None)]);
+    let first_nodes = reachable(&db, first);
+    let second_nodes = reachable(&db, second);
+
+    let collected: BTreeSet<_> = db.collect().unwrap().into_iter().collect();
+    let dropped: BTreeSet<_> = first_nodes.difference(&second_nodes).copied().collect();
+    assert_eq!(collected, dropped);
+    assert!(second_nodes.iter().all(|hash| db.ref_count(hash) == 1));
+
+    // Collected nodes are forgotten, so they are only returned once.
+    assert!(db.collect().unwrap().is_empty());
+}
+
+#[test]
+fn pinned_roots_survive_collection_until_unpinned() {
+    let db = RefCountDb::new(MemoryDb::empty());
+    let writes: Vec<_> = (0..32).map(|key| (key, Some(key))).collect();
+    let first = commit(&db, TrieRoot::Empty, &writes);
+
+    db.pin_root(node(first)).unwrap();
+    db.pin_root(node(first)).unwrap();
+    assert!(db.is_pinned(&node(first)).unwrap());
+
+    let second = commit(&db, first, &[(3, Some(100)), (7, None)]);
+    let third = commit(&db, second, &[(11, Some(200))]);
+    let first_nodes = reachable(&db, first);
+
+    // Nodes of the pinned root are kept, nodes only the second root reached are not.
+    let collected: BTreeSet<_> = db.collect().unwrap().into_iter().collect();
+    assert!(collected.is_disjoint(&first_nodes));
+    assert!(!collected.is_empty());
+
+    db.unpin_root(node(first)).unwrap();
+    assert!(db.is_pinned(&node(first)).unwrap());
+    assert!(db.collect().unwrap().is_empty());
+
+    db.unpin_root(node(first)).unwrap();
+    assert!(!db.is_pinned(&node(first)).unwrap());
+    let collected: BTreeSet<_> = db.collect().unwrap().into_iter().collect();
+    let third_nodes = reachable(&db, third);
+    let dropped: BTreeSet<_> = first_nodes.difference(&third_nodes).copied().collect();
+    assert_eq!(collected, dropped);
+
+    assert!(db.unpin_root(node(first)).is_err());
+    assert!(db.pin_root(node(first)).is_err());
+    assert!(db.pin_root(node(third)).is_ok());
+}
+
+#[test]
+fn orphaning_an_unreferenced_node_is_an_error() {
+    let db = RefCountDb::new(MemoryDb::empty());
+    let root = commit(&db, TrieRoot::Empty, &[(1, Some(1)), (2, Some(2))]);
+
+    let other: Db = RefCountDb::new(MemoryDb::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(&db, root));
+    txn.remove(&KeyHash::from_u64(1)).unwrap();
+    assert!(txn
+        .commit_with_events(hasher, &mut |event| other.apply(event))
+        .is_err());
+}
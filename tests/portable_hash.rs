@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use kairos_trie::{PortableHash, PortableUpdate};
+
+/// Records the bytes hashed, to check encodings byte for byte.
+#[derive(Default)]
+struct Bytes(Vec<u8>);
+
+impl PortableUpdate for Bytes {
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.extend_from_slice(data.as_ref());
+    }
+}
+
+fn hashed(value: &impl PortableHash) -> Vec<u8> {
+    let mut bytes = Bytes::default();
+    value.portable_hash(&mut bytes);
+    bytes.0
+}
+
+#[test]
+fn options_and_results_hash_like_derived_enums() {
+    assert_eq!(hashed(&None::<u16>), 0u32.to_le_bytes());
+    assert_eq!(
+        hashed(&Some(7u16)),
+        [&1u32.to_le_bytes()[..], &7u16.to_le_bytes()].concat()
+    );
+
+    assert_eq!(
+        hashed(&Ok::<u8, bool>(3)),
+        [&0u32.to_le_bytes()[..], &[3]].concat()
+    );
+    assert_eq!(
+        hashed(&Err::<u8, bool>(true)),
+        [&1u32.to_le_bytes()[..], &[1]].concat()
+    );
+}
+
+#[test]
+fn collections_hash_their_length_then_their_items_in_order() {
+    let accounts = vec![(1u64, Some(true)), (2, None)];
+    let expected = [
+        &2u64.to_le_bytes()[..],
+        &1u64.to_le_bytes(),
+        &1u32.to_le_bytes(),
+        &[1],
+        &2u64.to_le_bytes(),
+        &0u32.to_le_bytes(),
+    ]
+    .concat();
+    assert_eq!(hashed(&accounts), expected);
+    assert_eq!(hashed(&accounts.as_slice()), expected);
+
+    let map: BTreeMap<u32, Vec<u8>> = [(9, vec![1]), (2, vec![3, 4])].into();
+    assert_eq!(
+        hashed(&map),
+        [
+            &2u64.to_le_bytes()[..],
+            &2u32.to_le_bytes(),
+            &2u64.to_le_bytes(),
+            &[3, 4],
+            &9u32.to_le_bytes(),
+            &1u64.to_le_bytes(),
+            &[1],
+        ]
+        .concat()
+    );
+
+    let set: BTreeSet<i16> = [5, -1].into();
+    assert_eq!(
+        hashed(&set),
+        [
+            &2u64.to_le_bytes()[..],
+            &(-1i16).to_le_bytes(),
+            &5i16.to_le_bytes()
+        ]
+        .concat()
+    );
+}
+
+#[test]
+fn nested_byte_strings_are_delimited() {
+    let split_left: BTreeMap<u8, Vec<u8>> = [(1, vec![2]), (3, vec![])].into();
+    let split_right: BTreeMap<u8, Vec<u8>> = [(1, vec![2, 3])].into();
+    assert_ne!(hashed(&split_left), hashed(&split_right));
+
+    assert_ne!(
+        hashed(&vec![vec![1u8], vec![2, 3]]),
+        hashed(&vec![vec![1u8, 2], vec![3]])
+    );
+    assert_ne!(
+        hashed(&[String::from("a"), String::from("bc")]),
+        hashed(&[String::from("ab"), String::from("c")])
+    );
+    assert_ne!(
+        hashed(&vec![Some(vec![1u8]), Some(vec![2, 3])]),
+        hashed(&vec![Some(vec![1u8, 2]), Some(vec![3])])
+    );
+
+    assert_eq!(
+        hashed(&Some(vec![7u8])),
+        [&1u32.to_le_bytes()[..], &1u64.to_le_bytes(), &[7]].concat()
+    );
+}
+
+#[test]
+fn primitive_sequences_keep_their_encoding() {
+    assert_eq!(hashed(&vec![1u8, 2, 3]), [1, 2, 3]);
+    assert_eq!(hashed(&[true, false, true]), [1, 0, 1]);
+    assert_eq!(hashed(&vec![true; 100]), [1; 100]);
+    assert_eq!(
+        hashed(&[1u32, 2]),
+        [1u32.to_le_bytes(), 2u32.to_le_bytes()].concat()
+    );
+    assert_eq!(
+        hashed(&vec![1u32, 2]),
+        [1u32.to_le_bytes(), 2u32.to_le_bytes()].concat()
+    );
+    assert_eq!(
+        hashed(&&[-1i64, 2][..]),
+        [(-1i64).to_le_bytes(), 2i64.to_le_bytes()].concat()
+    );
+    assert_eq!(hashed(&vec![7u128]), 7u128.to_le_bytes());
+    assert_eq!(hashed(&vec!['a']), ('a' as u32).to_le_bytes());
+    assert_eq!(hashed(&&"ab"), b"ab");
+}
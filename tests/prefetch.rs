@@ -0,0 +1,170 @@
+use std::sync::{Arc, Mutex};
+
+use kairos_trie::{
+    raw::RawSnapshot,
+    stored::{
+        memory_db::SyncMemoryDb,
+        merkle::SnapshotBuilder,
+        node_cache::{CachedDb, NodeCache},
+        prefetch::{PrefetchStats, Prefetcher},
+        DatabaseGet,
+    },
+    Branch, DigestHasher, KeyHash, Leaf, Node, NodeHash, Transaction, TrieError, TrieRoot,
+};
+use sha2::Sha256;
+
+type Db = Arc<SyncMemoryDb<u64>>;
+
+fn committed(keys: u64) -> (Db, TrieRoot<NodeHash>) {
+    let db = Arc::new(SyncMemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..keys {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (db, root)
+}
+
+#[test]
+fn prefetched_builders_read_the_same_snapshot() {
+    let (db, root) = committed(1000);
+    let cache = Arc::new(NodeCache::new(10_000));
+    let prefetcher = Arc::new(Prefetcher::spawn(db.clone(), cache.clone(), 2, 1024));
+
+    let txn = Transaction::from_snapshot_builder(
+        SnapshotBuilder::new(CachedDb::new(db.clone(), cache.clone()), root)
+            .with_prefetcher(prefetcher.clone()),
+    );
+    let plain = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    for i in (0..1000).step_by(97) {
+        let key = KeyHash::from_u64(i);
+        assert_eq!(txn.get(&key).unwrap(), Some(&i));
+        assert_eq!(plain.get(&key).unwrap(), Some(&i));
+    }
+    let snapshot = txn.build_initial_snapshot();
+    assert_eq!(snapshot, plain.build_initial_snapshot());
+
+    // Both children of every loaded branch were queued, and are cached once the workers are idle.
+    prefetcher.wait_idle();
+    let stats = prefetcher.stats();
+    let branches = RawSnapshot::new(&snapshot).branches().len() as u64;
+    assert_eq!(stats.requested + stats.dropped, 2 * branches);
+    assert_eq!(stats.dropped, 0);
+    assert_eq!(stats.failed, 0);
+    assert_eq!(stats.pending(), 0);
+    for unvisited in snapshot.unvisited().unwrap() {
+        assert!(cache.contains(&unvisited.hash));
+    }
+}
+
+/// A database whose reads wait for a gate the test holds.
+#[derive(Clone)]
+struct Gated {
+    db: Db,
+    gate: Arc<Mutex<()>>,
+}
+
+impl DatabaseGet<u64> for Gated {
+    type GetError = TrieError;
+
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<u64>>, TrieError> {
+        let _open = self.gate.lock().unwrap();
+        Ok(self.db.get(hash)?)
+    }
+}
+
+#[test]
+fn full_queue_drops_requests() {
+    let (db, _) = committed(0);
+    let gate = Arc::new(Mutex::new(()));
+    let cache = Arc::new(NodeCache::<u64>::new(100));
+    let closed = gate.lock().unwrap();
+    let prefetcher = Prefetcher::spawn(
+        Gated {
+            db,
+            gate: gate.clone(),
+        },
+        cache.clone(),
+        1,
+        1,
+    );
+
+    // One hash is held by the blocked worker and one is queued, the rest are dropped.
+    for i in 0..10 {
+        prefetcher.prefetch(NodeHash::new([i; 32]));
+    }
+    let stats = prefetcher.stats();
+    assert_eq!(stats.requested + stats.dropped, 10);
+    assert!(stats.requested <= 2, "{stats:?}");
+
+    // None of the hashes are in the database.
+    drop(closed);
+    prefetcher.wait_idle();
+    let stats = prefetcher.stats();
+    assert_eq!(stats.failed, stats.requested);
+    assert_eq!(stats.pending(), 0);
+    assert!(cache.is_empty());
+    drop(prefetcher);
+}
+
+#[test]
+fn cached_nodes_are_not_fetched_again() {
+    let (db, root) = committed(10);
+    let TrieRoot::Node(root) = root else {
+        panic!("A trie of 10 keys has a root node");
+    };
+    let cache = Arc::new(NodeCache::new(100));
+    let prefetcher = Prefetcher::spawn(db, cache.clone(), 1, 4);
+
+    prefetcher.prefetch(root);
+    prefetcher.wait_idle();
+    prefetcher.prefetch(root);
+    prefetcher.wait_idle();
+    assert_eq!(
+        prefetcher.stats(),
+        PrefetchStats {
+            requested: 2,
+            dropped: 0,
+            fetched: 1,
+            cached: 1,
+            failed: 0,
+        }
+    );
+    assert!(cache.contains(&root));
+    assert_eq!(cache.hits() + cache.misses(), 0);
+}
+
+/// A database whose reads panic.
+#[derive(Clone)]
+struct Panicking;
+
+impl DatabaseGet<u64> for Panicking {
+    type GetError = TrieError;
+
+    fn get(&self, _: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<u64>>, TrieError> {
+        panic!("injected panic in a prefetch read");
+    }
+}
+
+#[test]
+fn panicking_reads_are_counted_as_failed() {
+    let cache = Arc::new(NodeCache::<u64>::new(100));
+    let prefetcher = Prefetcher::spawn(Panicking, cache.clone(), 2, 8);
+
+    for i in 0..4 {
+        prefetcher.prefetch(NodeHash::new([i; 32]));
+    }
+    // Returns although every read panicked, and the workers keep serving requests.
+    prefetcher.wait_idle();
+    prefetcher.prefetch(NodeHash::new([4; 32]));
+    prefetcher.wait_idle();
+
+    let stats = prefetcher.stats();
+    assert_eq!(stats.failed, stats.requested);
+    assert_eq!(stats.requested + stats.dropped, 5);
+    assert!(cache.is_empty());
+}
@@ -0,0 +1,180 @@
+mod utils;
+
+use std::{collections::BTreeMap, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+fn expected_range(
+    map: &BTreeMap<KeyHash, u64>,
+    start: &KeyHash,
+    end: &KeyHash,
+) -> Vec<(KeyHash, u64)> {
+    let mut entries: Vec<_> = map
+        .iter()
+        .filter(|(k, _)| start.cmp_trie_order(k).is_le() && k.cmp_trie_order(end).is_le())
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp_trie_order(b));
+    entries
+}
+
+fn range_get_end_to_end(
+    committed: BTreeMap<KeyHash, u64>,
+    pending: BTreeMap<KeyHash, u64>,
+    start: KeyHash,
+    end: KeyHash,
+) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for (k, v) in committed.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // Mix stored and modified nodes in the trie we query.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for (k, v) in pending.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+
+    let mut map = committed;
+    map.extend(pending.iter().map(|(k, v)| (*k, *v)));
+    let expected = expected_range(&map, &start, &end);
+
+    let entries: Vec<_> = txn
+        .range_get(start..=end)
+        .unwrap()
+        .into_iter()
+        .map(|(k, v)| (k, *v))
+        .collect();
+    assert_eq!(entries, expected);
+
+    // The snapshot must be enough to replay the same range read.
+    let snapshot = txn.build_initial_snapshot();
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+
+    let mut txn = Transaction::from_snapshot(&snapshot).unwrap();
+    for (k, v) in pending.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+    let entries: Vec<_> = txn
+        .range_get(start..=end)
+        .unwrap()
+        .into_iter()
+        .map(|(k, v)| (k, *v))
+        .collect();
+    assert_eq!(entries, expected);
+}
+
+proptest! {
+    #[test]
+    fn prop_range_get(
+        committed in prop::collection::btree_map(arb_key_hash(), any::<u64>(), 0..200),
+        pending in prop::collection::btree_map(arb_key_hash(), any::<u64>(), 0..20),
+        start in arb_key_hash(),
+        end in arb_key_hash(),
+    ) {
+        range_get_end_to_end(committed, pending, start, end);
+    }
+
+    #[test]
+    fn prop_range_get_structured_keys(
+        committed in prop::collection::btree_map(arb_structured_key_hash(), any::<u64>(), 0..200),
+        pending in prop::collection::btree_map(arb_structured_key_hash(), any::<u64>(), 0..20),
+        start in arb_structured_key_hash(),
+        end in arb_structured_key_hash(),
+    ) {
+        range_get_end_to_end(committed, pending, start, end);
+    }
+}
+
+#[test]
+fn range_get_full_and_empty_ranges() {
+    let map =
+        BTreeMap::from_iter((0..100u32).map(|i| (KeyHash([i, 0, i % 3, 0, 0, 0, 0, 0]), i as u64)));
+    let min = KeyHash([0; 8]);
+    let max = KeyHash([u32::MAX; 8]);
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+    assert!(txn.range_get(min..=max).unwrap().is_empty());
+
+    for (k, v) in map.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+
+    assert_eq!(txn.range_get(min..=max).unwrap().len(), map.len());
+    assert!(txn.range_get(max..=min).unwrap().is_empty());
+
+    let key = KeyHash([7, 0, 1, 0, 0, 0, 0, 0]);
+    assert_eq!(txn.range_get(key..=key).unwrap(), vec![(key, &7)]);
+}
+
+fn has_prefix(key: &KeyHash, prefix_bits: &[u8; 32], len_bits: usize) -> bool {
+    let key = key.to_bytes();
+    (0..len_bits)
+        .all(|bit| (key[bit / 8] >> (bit % 8)) & 1 == (prefix_bits[bit / 8] >> (bit % 8)) & 1)
+}
+
+proptest! {
+    #[test]
+    fn prop_keys_with_prefix(
+        keys in prop::collection::btree_set(arb_structured_key_hash(), 1..200),
+        prefix_of in any::<prop::sample::Index>(),
+        len_bits in 0..=256usize,
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+        let keys: Vec<KeyHash> = keys.into_iter().collect();
+        let prefix_bits = keys[prefix_of.index(keys.len())].to_bytes();
+
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for k in keys.iter() {
+            txn.insert(k, 0).unwrap();
+        }
+        let root = txn.commit(hasher).unwrap().root;
+
+        let mut expected: Vec<_> = keys
+            .iter()
+            .filter(|k| has_prefix(k, &prefix_bits, len_bits))
+            .copied()
+            .collect();
+        expected.sort_by(|a, b| a.cmp_trie_order(b));
+
+        let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        let found: Vec<_> = txn
+            .keys_with_prefix(&prefix_bits, len_bits)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        prop_assert_eq!(&found, &expected);
+
+        let snapshot = txn.build_initial_snapshot();
+        let txn = Transaction::from_snapshot(&snapshot).unwrap();
+        let found: Vec<_> = txn
+            .keys_with_prefix(&prefix_bits, len_bits)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        prop_assert_eq!(&found, &expected);
+    }
+}
+
+#[test]
+fn keys_with_prefix_rejects_short_prefix() {
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+    assert!(txn.keys_with_prefix(&[0; 2], 17).is_err());
+    assert!(txn.keys_with_prefix(&[0; 33], 257).is_err());
+    assert_eq!(txn.keys_with_prefix(&[0; 2], 16).unwrap().count(), 0);
+}
@@ -0,0 +1,144 @@
+mod utils;
+
+use std::{collections::BTreeMap, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    prove_range,
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, RangeProof, RangeProofNode, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+type Db = Rc<MemoryDb<u64>>;
+
+fn committed(map: &BTreeMap<KeyHash, u64>) -> (Db, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for (k, v) in map.iter() {
+        txn.insert(k, *v).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (db, root)
+}
+
+fn prove(db: &Db, root: TrieRoot<NodeHash>, start: KeyHash, end: KeyHash) -> RangeProof<u64> {
+    let builder = SnapshotBuilder::new(db.clone(), root);
+    prove_range(
+        &builder,
+        match root {
+            TrieRoot::Node(_) => TrieRoot::Node(0),
+            TrieRoot::Empty => TrieRoot::Empty,
+        },
+        start..=end,
+        &mut DigestHasher::<Sha256>::default(),
+    )
+    .unwrap()
+}
+
+fn range_proof_end_to_end(map: BTreeMap<KeyHash, u64>, start: KeyHash, end: KeyHash) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (db, root) = committed(&map);
+    let proof = prove(&db, root, start, end);
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    let expected = txn.range_get(start..=end).unwrap();
+    assert_eq!(proof.verify(hasher, root, start..=end).unwrap(), expected);
+
+    // Dropping any node, or pruning a node the range reaches, breaks the proof.
+    for i in 0..proof.nodes.len() {
+        let mut nodes = proof.nodes.to_vec();
+        nodes.remove(i);
+        let dropped = RangeProof {
+            nodes: nodes.into_boxed_slice(),
+        };
+        assert!(dropped.verify(hasher, root, start..=end).is_err());
+
+        if !matches!(proof.nodes[i], RangeProofNode::Pruned(_)) {
+            let mut nodes = proof.nodes.to_vec();
+            nodes[i] = RangeProofNode::Pruned(NodeHash::new([0; 32]));
+            let pruned = RangeProof {
+                nodes: nodes.into_boxed_slice(),
+            };
+            assert!(pruned.verify(hasher, root, start..=end).is_err());
+        }
+    }
+
+    let wrong_root = TrieRoot::Node(NodeHash::new([7; 32]));
+    assert!(proof.verify(hasher, wrong_root, start..=end).is_err());
+}
+
+proptest! {
+    #[test]
+    fn prop_range_proof(
+        map in prop::collection::btree_map(arb_key_hash(), any::<u64>(), 0..100),
+        start in arb_key_hash(),
+        end in arb_key_hash(),
+    ) {
+        range_proof_end_to_end(map, start, end);
+    }
+
+    #[test]
+    fn prop_range_proof_structured_keys(
+        map in prop::collection::btree_map(arb_structured_key_hash(), any::<u64>(), 0..100),
+        start in arb_structured_key_hash(),
+        end in arb_structured_key_hash(),
+    ) {
+        range_proof_end_to_end(map, start, end);
+    }
+}
+
+#[test]
+fn range_proof_hides_leaves_outside_the_range() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let map = BTreeMap::from_iter((0..64).map(|i| (KeyHash::from_u64(i), i)));
+    let (db, root) = committed(&map);
+
+    let (start, end) = (KeyHash::from_u64(8), KeyHash::from_u64(12));
+    let proof = prove(&db, root, start, end);
+    let entries = proof.verify(hasher, root, start..=end).unwrap();
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    assert_eq!(entries, txn.range_get(start..=end).unwrap());
+
+    // Only the leaves in the range and the two bounding it are revealed.
+    let leaves = proof
+        .nodes
+        .iter()
+        .filter(|node| matches!(node, RangeProofNode::Leaf(_)))
+        .count();
+    assert!(leaves <= entries.len() + 2);
+    assert!(leaves < map.len());
+
+    // A proof for one range does not verify a wider one.
+    let (min, max) = (KeyHash([0; 8]), KeyHash([u32::MAX; 8]));
+    assert!(proof.verify(hasher, root, min..=max).is_err());
+}
+
+#[test]
+fn range_proof_of_empty_trie_and_empty_range() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let min = KeyHash([0; 8]);
+    let max = KeyHash([u32::MAX; 8]);
+
+    let (db, root) = committed(&BTreeMap::new());
+    let proof = prove(&db, root, min, max);
+    assert!(proof.nodes.is_empty());
+    assert!(proof.verify(hasher, root, min..=max).unwrap().is_empty());
+
+    // An empty proof cannot hide a non-empty trie.
+    let map = BTreeMap::from_iter((0..10).map(|i| (KeyHash::from_u64(i), i)));
+    let (db, root) = committed(&map);
+    assert!(proof.verify(hasher, root, min..=max).is_err());
+
+    // A reversed range proves no entries, with the root alone.
+    let proof = prove(&db, root, max, min);
+    assert_eq!(proof.nodes.len(), 1);
+    assert!(proof.verify(hasher, root, max..=min).unwrap().is_empty());
+    assert!(proof.verify(hasher, root, min..=max).is_err());
+}
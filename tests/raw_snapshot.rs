@@ -0,0 +1,97 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    raw::{RawNode, RawSnapshot},
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+        Idx,
+    },
+    DigestHasher, KeyHash, NodeHash, PortableHasher, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn witness(reads: &[u64]) -> (Snapshot<u64>, TrieRoot<NodeHash>) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..64 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for i in reads {
+        txn.get(&KeyHash::from_u64(*i)).unwrap();
+    }
+    (txn.build_initial_snapshot(), root)
+}
+
+/// Hash the subtree at `idx` from the raw arrays alone, as an external verifier would.
+fn hash_raw(raw: RawSnapshot<u64>, idx: Idx, hasher: &mut impl PortableHasher<32>) -> NodeHash {
+    match raw.node(idx).unwrap() {
+        RawNode::Branch(branch) => {
+            let left = hash_raw(raw, branch.left, hasher);
+            let right = hash_raw(raw, branch.right, hasher);
+            branch.hash_branch(hasher, &left, &right)
+        }
+        RawNode::Leaf(leaf) => leaf.hash_leaf(hasher),
+        RawNode::Unvisited(hash) => *hash,
+    }
+}
+
+#[test]
+fn raw_arrays_hash_to_the_root() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    for reads in [&[][..], &[5], &[3, 17, 40], &(0..64).collect::<Vec<_>>()] {
+        let (snapshot, root) = witness(reads);
+        let raw = RawSnapshot::from(&snapshot);
+
+        let TrieRoot::Node(root_idx) = raw.root().unwrap() else {
+            panic!("the trie is not empty");
+        };
+        assert_eq!(TrieRoot::Node(hash_raw(raw, root_idx, hasher)), root);
+
+        let total = raw.unvisited_offset() + raw.unvisited_nodes().len() as Idx;
+        assert_eq!(raw.leaf_offset(), raw.branches().len() as Idx);
+        assert!(raw.node(total).is_none());
+
+        // Leaves are in trie order, and branches follow the branches below them.
+        assert_eq!(raw.leaves().len(), reads.len());
+        assert!(raw
+            .leaves()
+            .windows(2)
+            .all(|w| w[0].key_hash.cmp_trie_order(&w[1].key_hash).is_lt()));
+        for (idx, branch) in raw.branches().iter().enumerate() {
+            for child in [branch.left, branch.right] {
+                assert!(child >= raw.leaf_offset() || (child as usize) < idx);
+            }
+        }
+    }
+}
+
+#[test]
+fn raw_view_of_small_snapshots() {
+    let empty = SnapshotBuilder::<_, u64>::empty(MemoryDb::<u64>::empty()).build_initial_snapshot();
+    let raw = RawSnapshot::new(&empty);
+    assert_eq!(raw.root().unwrap(), TrieRoot::Empty);
+    assert!(raw.node(0).is_none());
+
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    txn.insert(&KeyHash::from_u64(1), 1).unwrap();
+    let root = txn.commit(hasher).unwrap().root;
+
+    let unread: Snapshot<u64> =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root)).build_initial_snapshot();
+    let raw = RawSnapshot::new(&unread);
+    assert_eq!(raw.root().unwrap(), TrieRoot::Node(0));
+    let Some(RawNode::Unvisited(hash)) = raw.node(0) else {
+        panic!("the root was not read");
+    };
+    assert_eq!(TrieRoot::Node(*hash), root);
+}
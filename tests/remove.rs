@@ -0,0 +1,155 @@
+mod utils;
+
+use std::{collections::BTreeSet, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+fn root_of(keys: &BTreeSet<KeyHash>) -> TrieRoot<NodeHash> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+    for key in keys {
+        txn.insert(key, key.0[0] as u64).unwrap();
+    }
+    txn.calc_root_hash(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+}
+
+/// Removing keys must leave the trie exactly as if they were never inserted,
+/// both within a transaction and across commits, with and without deletion proofs.
+fn remove_matches_never_inserted(keys: Vec<KeyHash>, removed: Vec<prop::sample::Index>) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut remaining: BTreeSet<KeyHash> = keys.iter().copied().collect();
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys.iter() {
+        txn.insert(key, key.0[0] as u64).unwrap();
+    }
+    let committed_root = txn.commit(hasher).unwrap().root;
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, committed_root));
+    let mut old_root = committed_root;
+
+    for (i, idx) in removed.iter().enumerate() {
+        let key = keys[idx.index(keys.len())];
+        let expected = remaining.remove(&key).then_some(key.0[0] as u64);
+
+        if i % 2 == 0 {
+            assert_eq!(txn.remove(&key).unwrap(), expected);
+        } else {
+            let proof = txn.remove_with_proof(&key, hasher).unwrap();
+            assert_eq!(proof.as_ref().map(|p| p.leaf.value), expected);
+
+            if let Some(proof) = proof {
+                let new_root = txn.calc_root_hash(hasher).unwrap();
+                proof.verify(hasher, old_root, new_root).unwrap();
+            }
+        }
+
+        assert_eq!(txn.get(&key).unwrap(), None);
+        old_root = txn.calc_root_hash(hasher).unwrap();
+        assert_eq!(old_root, root_of(&remaining));
+    }
+
+    // The guest replays the removals against the snapshot.
+    let snapshot = txn.build_initial_snapshot();
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), committed_root);
+
+    let mut txn = Transaction::from_snapshot(&snapshot).unwrap();
+    for idx in removed.iter() {
+        txn.remove(&keys[idx.index(keys.len())]).unwrap();
+    }
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), old_root);
+}
+
+proptest! {
+    #[test]
+    fn prop_remove_matches_never_inserted(
+        keys in prop::collection::vec(arb_key_hash(), 1..200),
+        removed in prop::collection::vec(any::<prop::sample::Index>(), 0..200),
+    ) {
+        remove_matches_never_inserted(keys, removed);
+    }
+
+    #[test]
+    fn prop_remove_matches_never_inserted_structured_keys(
+        keys in prop::collection::vec(arb_structured_key_hash(), 1..200),
+        removed in prop::collection::vec(any::<prop::sample::Index>(), 0..200),
+    ) {
+        remove_matches_never_inserted(keys, removed);
+    }
+}
+
+#[test]
+fn remove_last_key_empties_trie() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let key = KeyHash([1; 8]);
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+    assert_eq!(txn.remove(&key).unwrap(), None);
+
+    txn.insert(&key, 1).unwrap();
+    let old_root = txn.calc_root_hash(hasher).unwrap();
+
+    let proof = txn.remove_with_proof(&key, hasher).unwrap().unwrap();
+    assert!(proof.path.is_empty());
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), TrieRoot::Empty);
+    proof.verify(hasher, old_root, TrieRoot::Empty).unwrap();
+}
+
+#[test]
+fn deletion_proof_rejects_tampering() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let keys = [
+        KeyHash([0, 0, 0, 0, 0, 0, 0, 0]),
+        KeyHash([0, 0, 1, 0, 0, 0, 0, 0]),
+        KeyHash([0, 0, 1, 0, 0, 0, 0, 1]),
+        KeyHash([0, 0, 1, 0, 0, 0, 0, 3]),
+    ];
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+    for (i, key) in keys.iter().enumerate() {
+        txn.insert(key, i as u64).unwrap();
+    }
+    let old_root = txn.calc_root_hash(hasher).unwrap();
+
+    // The sibling of the removed leaf is a branch that absorbs the parent's prefix.
+    let proof = txn.remove_with_proof(&keys[0], hasher).unwrap().unwrap();
+    let new_root = txn.calc_root_hash(hasher).unwrap();
+    assert!(proof.sibling.is_some());
+    proof.verify(hasher, old_root, new_root).unwrap();
+
+    assert!(matches!(
+        proof.verify(hasher, new_root, new_root),
+        Err(VerifyError::OldRootMismatch { .. })
+    ));
+    assert!(matches!(
+        proof.verify(hasher, old_root, old_root),
+        Err(VerifyError::NewRootMismatch { .. })
+    ));
+
+    let mut wrong_value = proof.clone();
+    wrong_value.leaf.value += 1;
+    assert!(wrong_value.verify(hasher, old_root, new_root).is_err());
+
+    let mut wrong_key = proof.clone();
+    wrong_key.leaf.key_hash = keys[1];
+    assert!(wrong_key.verify(hasher, old_root, new_root).is_err());
+
+    let mut missing_sibling = proof.clone();
+    missing_sibling.sibling = None;
+    assert!(matches!(
+        missing_sibling.verify(hasher, old_root, new_root),
+        Err(VerifyError::NewRootMismatch { .. })
+    ));
+}
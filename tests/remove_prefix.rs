@@ -0,0 +1,195 @@
+mod utils;
+
+use std::{collections::BTreeSet, rc::Rc};
+
+use proptest::{prelude::*, sample::Index};
+
+use kairos_trie::{
+    raw::RawSnapshot,
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, NodeHash, RemovedSubtree, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+type Db = Rc<MemoryDb<u64>>;
+
+fn root_of(keys: &BTreeSet<KeyHash>) -> TrieRoot<NodeHash> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::empty(MemoryDb::<u64>::empty()));
+    for key in keys {
+        txn.insert(key, key.0[0] as u64).unwrap();
+    }
+    txn.calc_root_hash(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+}
+
+fn committed(keys: &BTreeSet<KeyHash>) -> (Db, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys {
+        txn.insert(key, key.0[0] as u64).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (db, root)
+}
+
+/// Removing a prefix must leave the trie exactly as if its keys were never inserted,
+/// with a proof relating the two roots, and a witness that replays the removal.
+fn remove_prefix_matches_never_inserted(
+    keys: BTreeSet<KeyHash>,
+    prefix: KeyHash,
+    len_bits: usize,
+) -> Result<(), TestCaseError> {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let prefix_bits = prefix.to_bytes();
+    let (db, old_root) = committed(&keys);
+    let remaining: BTreeSet<KeyHash> = keys
+        .iter()
+        .filter(|key| !key.matches_prefix(&prefix, len_bits as u32))
+        .copied()
+        .collect();
+    let removed_any = remaining.len() != keys.len();
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+    prop_assert_eq!(
+        txn.remove_prefix(&prefix_bits, len_bits).unwrap(),
+        removed_any
+    );
+    let new_root = txn.calc_root_hash(hasher).unwrap();
+    prop_assert_eq!(new_root, root_of(&remaining));
+
+    // Replaying the removal over the witness reaches the same root.
+    let snapshot = txn.build_initial_snapshot();
+    prop_assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), old_root);
+    let mut replay = Transaction::from_snapshot(&snapshot).unwrap();
+    prop_assert_eq!(
+        replay.remove_prefix(&prefix_bits, len_bits).unwrap(),
+        removed_any
+    );
+    prop_assert_eq!(replay.calc_root_hash(hasher).unwrap(), new_root);
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, old_root));
+    let proof = txn
+        .remove_prefix_with_proof(&prefix_bits, len_bits, hasher)
+        .unwrap();
+    prop_assert_eq!(proof.is_some(), removed_any);
+    prop_assert_eq!(txn.calc_root_hash(hasher).unwrap(), new_root);
+
+    if let Some(proof) = proof {
+        proof
+            .verify(hasher, old_root, new_root, &prefix_bits, len_bits)
+            .unwrap();
+
+        prop_assert!(proof
+            .verify(hasher, new_root, new_root, &prefix_bits, len_bits)
+            .is_err());
+        prop_assert!(proof
+            .verify(hasher, old_root, old_root, &prefix_bits, len_bits)
+            .is_err());
+
+        // A shorter prefix covers keys outside the removed subtree,
+        // unless they share the same subtree.
+        if len_bits > 0 {
+            let shorter: BTreeSet<KeyHash> = keys
+                .iter()
+                .filter(|key| key.matches_prefix(&prefix, len_bits as u32 - 1))
+                .copied()
+                .collect();
+            let same_keys = shorter.len() == keys.len() - remaining.len();
+            prop_assert_eq!(
+                proof
+                    .verify(hasher, old_root, new_root, &prefix_bits, len_bits - 1)
+                    .is_ok(),
+                same_keys
+            );
+        }
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn prop_remove_prefix(
+        keys in prop::collection::btree_set(arb_key_hash(), 0..100),
+        random_prefix in arb_key_hash(),
+        key_prefix: Option<Index>,
+        len_bits in 0usize..=256,
+    ) {
+        let prefix = match key_prefix {
+            Some(idx) if !keys.is_empty() => **idx.get(&keys.iter().collect::<Vec<_>>()),
+            _ => random_prefix,
+        };
+        remove_prefix_matches_never_inserted(keys, prefix, len_bits)?;
+    }
+
+    #[test]
+    fn prop_remove_prefix_structured_keys(
+        keys in prop::collection::btree_set(arb_structured_key_hash(), 0..100),
+        prefix in arb_structured_key_hash(),
+        len_bits in 0usize..=256,
+    ) {
+        remove_prefix_matches_never_inserted(keys, prefix, len_bits)?;
+    }
+}
+
+#[test]
+fn remove_prefix_detaches_the_subtree_without_visiting_it() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let keys: BTreeSet<KeyHash> = (0..1000).map(KeyHash::from_u64).collect();
+    let (db, root) = committed(&keys);
+
+    // The odd keys are the right child of the root.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    assert!(txn.remove_prefix(&[1], 1).unwrap());
+    let even = keys
+        .iter()
+        .filter(|key| key.0[0] % 2 == 0)
+        .copied()
+        .collect();
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), root_of(&even));
+
+    // The witness holds the root and the removed subtree's root, not one of its 500 leaves.
+    let snapshot = txn.build_initial_snapshot();
+    let raw = RawSnapshot::new(&snapshot);
+    assert_eq!(raw.branches().len(), 2);
+    assert_eq!(raw.leaves().len(), 0);
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    let proof = txn
+        .remove_prefix_with_proof(&[1], 1, hasher)
+        .unwrap()
+        .unwrap();
+    assert_eq!(proof.path.len(), 1);
+    assert!(proof.sibling.is_none());
+    assert!(matches!(proof.removed, RemovedSubtree::Branch(_)));
+
+    // A whole key is a prefix of one leaf.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    let key = KeyHash::from_u64(7).to_bytes();
+    let proof = txn.remove_prefix_with_proof(&key, 256, hasher).unwrap();
+    assert!(matches!(
+        proof.map(|p| p.removed),
+        Some(RemovedSubtree::Leaf(leaf)) if leaf.value == 7
+    ));
+    assert_eq!(txn.get(&KeyHash::from_u64(7)).unwrap(), None);
+
+    // The empty prefix removes everything.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    assert!(txn.remove_prefix(&[], 0).unwrap());
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), TrieRoot::Empty);
+    assert!(!txn.remove_prefix(&[], 0).unwrap());
+}
+
+#[test]
+fn remove_prefix_rejects_short_prefix() {
+    let (db, root) = committed(&BTreeSet::from([KeyHash::from_u64(1)]));
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    assert!(txn.remove_prefix(&[1], 9).is_err());
+    assert!(txn.remove_prefix(&[0; 33], 257).is_err());
+}
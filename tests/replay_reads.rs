@@ -0,0 +1,60 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+    },
+    DigestHasher, KeyHash, Transaction, TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+
+#[test]
+fn replay_reads_checks_the_root_then_reads() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..100 {
+        txn.insert(&KeyHash::from_u64(i), i * 10).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // The prover reads some present keys and one absent key.
+    let keys = [3, 42, 99, 1000].map(KeyHash::from_u64);
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for key in &keys {
+        txn.get(key).unwrap();
+    }
+    let snapshot = txn.build_initial_snapshot();
+
+    let values = snapshot.replay_reads(hasher, root, &keys).unwrap();
+    assert_eq!(values, [Some(&30), Some(&420), Some(&990), None]);
+    assert_eq!(snapshot.get(&KeyHash::from_u64(42)).unwrap(), Some(&420));
+
+    // A key the prover did not read is behind an unvisited node.
+    assert!(snapshot.get(&KeyHash::from_u64(7)).is_err());
+    assert!(matches!(
+        snapshot.replay_reads(hasher, root, &[KeyHash::from_u64(7)]),
+        Err(VerifyError::Trie(_))
+    ));
+
+    assert!(matches!(
+        snapshot.replay_reads(hasher, TrieRoot::Empty, &keys),
+        Err(VerifyError::OldRootMismatch { .. })
+    ));
+}
+
+#[test]
+fn replay_reads_of_an_empty_trie() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let snapshot: Snapshot<u64> =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db, TrieRoot::Empty))
+            .build_initial_snapshot();
+
+    let values = snapshot
+        .replay_reads(hasher, TrieRoot::Empty, &[KeyHash::from_u64(1)])
+        .unwrap();
+    assert_eq!(values, [None]);
+}
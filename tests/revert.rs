@@ -0,0 +1,155 @@
+mod utils;
+
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    AuditedBatch, DigestHasher, Journal, KeyHash, NodeHash, Op, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_structured_key_hash;
+
+fn commit_block(
+    db: &Rc<MemoryDb<u64>>,
+    root: TrieRoot<NodeHash>,
+    ops: Vec<Op<u64>>,
+) -> AuditedBatch<u64> {
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    let mut journal = Journal::new();
+    for op in ops {
+        journal.apply(&mut txn, op).unwrap();
+    }
+    txn.commit_audited(&mut DigestHasher::<Sha256>::default(), &journal)
+        .unwrap()
+}
+
+fn to_ops(block: &[(KeyHash, u64, u8)]) -> Vec<Op<u64>> {
+    block
+        .iter()
+        .map(|&(key, value, kind)| match kind {
+            0 => Op::Get(key),
+            1 => Op::Insert(key, value),
+            _ => Op::Remove(key),
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn prop_revert_restores_each_parent(
+        blocks in prop::collection::vec(
+            prop::collection::vec((arb_structured_key_hash(), any::<u64>(), 0..3u8), 0..40),
+            1..5,
+        ),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+
+        let mut batches = Vec::new();
+        let mut root = TrieRoot::Empty;
+        for block in blocks.iter() {
+            let batch = commit_block(&db, root, to_ops(block));
+            root = batch.new_root;
+            batches.push(batch);
+        }
+
+        // Unwind the chain one block at a time, as a reorg would.
+        for batch in batches.iter().rev() {
+            let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+            let reverted = batch.revert(&mut txn, hasher).unwrap();
+            prop_assert_eq!(reverted, batch.old_root);
+            root = txn.commit(hasher).unwrap().root;
+            prop_assert_eq!(root, batch.old_root);
+        }
+        prop_assert_eq!(root, TrieRoot::Empty);
+    }
+}
+
+#[test]
+fn inverse_restores_old_values_and_removes_new_keys() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let parent = commit_block(
+        &db,
+        TrieRoot::Empty,
+        vec![
+            Op::Insert(KeyHash::from_u64(1), 10),
+            Op::Insert(KeyHash::from_u64(2), 20),
+        ],
+    );
+    let batch = commit_block(
+        &db,
+        parent.new_root,
+        vec![
+            Op::Insert(KeyHash::from_u64(3), 30),
+            Op::Get(KeyHash::from_u64(2)),
+            Op::Insert(KeyHash::from_u64(1), 11),
+            Op::Insert(KeyHash::from_u64(3), 31),
+        ],
+    );
+
+    assert_eq!(
+        batch.inverse().unwrap(),
+        vec![
+            (KeyHash::from_u64(3), None),
+            (KeyHash::from_u64(1), Some(10)),
+        ]
+    );
+}
+
+#[test]
+fn revert_checks_the_starting_root() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let parent = commit_block(
+        &db,
+        TrieRoot::Empty,
+        vec![Op::Insert(KeyHash::from_u64(1), 10)],
+    );
+    let batch = commit_block(
+        &db,
+        parent.new_root,
+        vec![Op::Insert(KeyHash::from_u64(2), 20)],
+    );
+
+    // The parent is not the trie the batch produced.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, parent.new_root));
+    assert!(batch.revert(&mut txn, hasher).is_err());
+}
+
+#[test]
+fn revert_restores_removed_leaves() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let parent = commit_block(
+        &db,
+        TrieRoot::Empty,
+        vec![
+            Op::Insert(KeyHash::from_u64(1), 10),
+            Op::Insert(KeyHash::from_u64(2), 20),
+        ],
+    );
+    let batch = commit_block(
+        &db,
+        parent.new_root,
+        vec![
+            Op::Remove(KeyHash::from_u64(1)),
+            Op::Insert(KeyHash::from_u64(2), 21),
+            Op::Remove(KeyHash::from_u64(3)),
+        ],
+    );
+    assert_eq!(
+        batch.inverse().unwrap(),
+        vec![
+            (KeyHash::from_u64(1), Some(10)),
+            (KeyHash::from_u64(2), Some(20)),
+            (KeyHash::from_u64(3), None),
+        ]
+    );
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), batch.new_root));
+    assert_eq!(batch.revert(&mut txn, hasher).unwrap(), parent.new_root);
+    assert_eq!(txn.get(&KeyHash::from_u64(1)).unwrap(), Some(&10));
+}
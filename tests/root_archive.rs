@@ -0,0 +1,65 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{archive::RootArchive, memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+
+#[test]
+fn find_roots_containing_an_entry() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let key = KeyHash::from_u64(42);
+
+    // Block 2 adds the key, block 4 changes its value, block 6 removes it.
+    let mut roots = vec![TrieRoot::Empty];
+    for block in 0..8u64 {
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+            db.clone(),
+            *roots.last().unwrap(),
+        ));
+        for i in 0..20 {
+            txn.insert(&KeyHash::from_u64(block * 100 + i), i).unwrap();
+        }
+        match block {
+            2 => txn.insert(&key, 1).unwrap(),
+            4 => txn.insert(&key, 2).unwrap(),
+            6 => assert_eq!(txn.remove(&key).unwrap(), Some(2)),
+            _ => {}
+        }
+        roots.push(txn.commit(hasher).unwrap().root);
+    }
+
+    let archive = RootArchive::new(db);
+    let mut found = |value| -> Vec<usize> {
+        archive
+            .prove_entry_in_roots(&roots, &key, &value)
+            .unwrap()
+            .into_iter()
+            .map(|(idx, proof)| {
+                proof.verify(hasher, roots[idx]).unwrap();
+                idx
+            })
+            .collect()
+    };
+    // `roots[b + 1]` is the root after block `b`.
+    assert_eq!(found(1), vec![3, 4]);
+    assert_eq!(found(2), vec![5, 6]);
+    assert_eq!(found(3), Vec::<usize>::new());
+
+    assert!(archive.prove(TrieRoot::Empty, &key).unwrap().is_none());
+    assert!(archive.prove(roots[1], &key).unwrap().is_none());
+
+    // A proof only holds for the roots it was made from.
+    let proof = archive.prove(roots[3], &key).unwrap().unwrap();
+    assert_eq!(proof.leaf.value, 1);
+    assert!(matches!(
+        proof.verify(hasher, roots[5]),
+        Err(VerifyError::OldRootMismatch { .. })
+    ));
+
+    let mut forged = proof.clone();
+    forged.leaf.value = 2;
+    assert!(forged.verify(hasher, roots[3]).is_err());
+}
@@ -0,0 +1,54 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[test]
+fn root_hash_is_cached_until_modified() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    assert!(!txn.is_dirty());
+    assert_eq!(txn.root_hash_cached(), Some(TrieRoot::Empty));
+
+    for i in 0..100 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    assert!(txn.is_dirty());
+    assert_eq!(txn.root_hash_cached(), None);
+
+    let root = txn.calc_root_hash(hasher).unwrap();
+    assert!(!txn.is_dirty());
+    assert_eq!(txn.root_hash_cached(), Some(root));
+
+    // Reads and a failed remove leave the cached root in place.
+    assert_eq!(txn.get(&KeyHash::from_u64(7)).unwrap(), Some(&7));
+    assert_eq!(txn.remove(&KeyHash::from_u64(1000)).unwrap(), None);
+    assert_eq!(txn.root_hash_cached(), Some(root));
+
+    assert_eq!(txn.remove(&KeyHash::from_u64(7)).unwrap(), Some(7));
+    assert_eq!(txn.root_hash_cached(), None);
+    *txn.entry(&KeyHash::from_u64(7)).unwrap().or_default() = 7;
+    assert!(txn.is_dirty());
+
+    // Committing records the root too.
+    let committed = txn.commit(hasher).unwrap().root;
+    assert_eq!(committed, root);
+    assert_eq!(txn.root_hash_cached(), Some(root));
+
+    // A transaction over the committed trie starts with its root.
+    let txn = Transaction::<_, u64>::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    assert_eq!(txn.root_hash_cached(), Some(root));
+
+    // A transaction over a snapshot computes its root first.
+    let snapshot = txn.build_initial_snapshot();
+    let txn = Transaction::from_snapshot(&snapshot).unwrap();
+    assert!(txn.is_dirty());
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), root);
+    assert_eq!(txn.root_hash_cached(), Some(root));
+}
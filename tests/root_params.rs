@@ -0,0 +1,111 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    verify_batch_with_params, DigestHasher, KeyHash, Op, ParamsRoot, RootParams, Transaction,
+    TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+
+const SHA256: RootParams = RootParams::new(1, 256);
+const BLAKE3: RootParams = RootParams::new(2, 256);
+
+fn batch() -> (
+    ParamsRoot,
+    ParamsRoot,
+    kairos_trie::stored::merkle::Snapshot<u64>,
+    Vec<Op<u64>>,
+) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..16 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let old_root = txn.commit(hasher).unwrap().root;
+
+    let ops = vec![
+        Op::Get(KeyHash::from_u64(3)),
+        Op::Insert(KeyHash::from_u64(40), 40),
+    ];
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, old_root));
+    txn.get(&KeyHash::from_u64(3)).unwrap();
+    txn.insert(&KeyHash::from_u64(40), 40).unwrap();
+    let new_root = txn.commit(hasher).unwrap().root;
+
+    (
+        ParamsRoot::new(SHA256, old_root),
+        ParamsRoot::new(SHA256, new_root),
+        txn.build_initial_snapshot(),
+        ops,
+    )
+}
+
+#[test]
+fn verify_batch_with_params_checks_both_roots() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (old_root, new_root, snapshot, ops) = batch();
+
+    verify_batch_with_params(&SHA256, old_root, new_root, &snapshot, &ops, hasher).unwrap();
+
+    assert_eq!(
+        verify_batch_with_params(&BLAKE3, old_root, new_root, &snapshot, &ops, hasher),
+        Err(VerifyError::ParamsMismatch {
+            expected: BLAKE3,
+            actual: SHA256
+        })
+    );
+
+    let old_version = ParamsRoot::new(
+        RootParams {
+            version: 0,
+            ..SHA256
+        },
+        new_root.root,
+    );
+    assert!(matches!(
+        verify_batch_with_params(&SHA256, old_root, old_version, &snapshot, &ops, hasher),
+        Err(VerifyError::ParamsMismatch { .. })
+    ));
+}
+
+#[test]
+fn unsupported_params_are_rejected() {
+    SHA256.check_supported().unwrap();
+    RootParams::new(1, 160).check_supported().unwrap();
+
+    for params in [
+        RootParams::new(1, 0),
+        RootParams::new(1, 255),
+        RootParams::new(1, 512),
+        RootParams {
+            arity: 16,
+            ..SHA256
+        },
+        RootParams {
+            version: 2,
+            ..SHA256
+        },
+    ] {
+        assert!(params.check_supported().is_err(), "{params:?}");
+    }
+}
+
+#[test]
+fn commitment_binds_the_params() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (old_root, new_root, ..) = batch();
+
+    let commitment = old_root.commitment(hasher);
+    assert_eq!(commitment, old_root.commitment(hasher));
+    assert_ne!(commitment, new_root.commitment(hasher));
+    assert_ne!(
+        commitment,
+        ParamsRoot::new(BLAKE3, old_root.root).commitment(hasher)
+    );
+    assert_ne!(
+        ParamsRoot::new(SHA256, TrieRoot::Empty).commitment(hasher),
+        ParamsRoot::new(BLAKE3, TrieRoot::Empty).commitment(hasher)
+    );
+}
@@ -0,0 +1,129 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    verify_indexed_batch, DigestHasher, IndexedRoots, KeyHash, Op, SecondaryIndex, Transaction,
+    TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+
+/// An account's `[owner, balance]`, indexed by owner, with owner 0 left out of the index.
+type Account = [u64; 2];
+
+fn owner(account: &Account) -> Option<KeyHash> {
+    (account[0] != 0).then(|| KeyHash::from_u64(account[0]))
+}
+
+fn expected_keys(model: &BTreeMap<u64, Account>, owner: u64) -> Vec<KeyHash> {
+    let mut keys: Vec<_> = model
+        .iter()
+        .filter(|(_, account)| account[0] == owner)
+        .map(|(key, _)| KeyHash::from_u64(*key))
+        .collect();
+    keys.sort_by(KeyHash::cmp_trie_order);
+    keys
+}
+
+proptest! {
+    #[test]
+    fn prop_index_follows_the_primary_trie(
+        setup in prop::collection::vec((0..20u64, 0..4u64, any::<u64>()), 0..30),
+        batch in prop::collection::vec((0..30u64, 0..4u64, any::<u64>()), 1..30),
+        removes in prop::collection::vec(0..30u64, 0..10),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let primary_db = Rc::new(MemoryDb::<Account>::empty());
+        let index_db = Rc::new(MemoryDb::empty());
+        let mut model = BTreeMap::new();
+
+        let mut txn = SecondaryIndex::new(
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(primary_db.clone(), TrieRoot::Empty)),
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(index_db.clone(), TrieRoot::Empty)),
+            owner,
+        );
+        for &(key, owner, balance) in &setup {
+            txn.insert(&KeyHash::from_u64(key), [owner, balance]).unwrap();
+            model.insert(key, [owner, balance]);
+        }
+        for key in &removes {
+            prop_assert_eq!(txn.remove(&KeyHash::from_u64(*key)).unwrap(), model.remove(key));
+        }
+        let old_roots = txn.commit(hasher).unwrap();
+
+        // The witness of a batch of inserts.
+        let mut txn = SecondaryIndex::new(
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(primary_db, old_roots.primary)),
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(index_db, old_roots.index)),
+            owner,
+        );
+        let ops: Vec<_> = batch
+            .iter()
+            .map(|&(key, owner, balance)| Op::Insert(KeyHash::from_u64(key), [owner, balance]))
+            .collect();
+        for &(key, owner, balance) in &batch {
+            txn.insert(&KeyHash::from_u64(key), [owner, balance]).unwrap();
+            model.insert(key, [owner, balance]);
+        }
+        for owner in 1..4 {
+            prop_assert_eq!(
+                txn.keys_with_attribute(&KeyHash::from_u64(owner)).unwrap(),
+                expected_keys(&model, owner)
+            );
+        }
+
+        let new_roots = txn.commit(hasher).unwrap();
+        let (primary, index) = txn.build_initial_snapshots();
+        verify_indexed_batch(old_roots, new_roots, &primary, &index, &ops, owner, hasher).unwrap();
+
+        // A prover that writes the primary trie alone leaves the index root behind.
+        let mut primary_txn = Transaction::from_snapshot(&primary).unwrap();
+        for op in &ops {
+            op.apply(&mut primary_txn).unwrap();
+        }
+        let stale_roots = IndexedRoots {
+            primary: primary_txn.calc_root_hash(hasher).unwrap(),
+            index: old_roots.index,
+        };
+        prop_assert_eq!(stale_roots.primary, new_roots.primary);
+        if stale_roots.index != new_roots.index {
+            let result =
+                verify_indexed_batch(old_roots, stale_roots, &primary, &index, &ops, owner, hasher);
+            let mismatch = matches!(result, Err(VerifyError::NewRootMismatch { .. }));
+            prop_assert!(mismatch);
+        }
+    }
+}
+
+#[test]
+fn index_collisions_are_rejected() {
+    let mut txn = SecondaryIndex::new(
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(
+            Rc::new(MemoryDb::<Account>::empty()),
+            TrieRoot::Empty,
+        )),
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(
+            Rc::new(MemoryDb::empty()),
+            TrieRoot::Empty,
+        )),
+        owner,
+    );
+
+    // Keys and owners agreeing on their first 128 bits share an index key.
+    let key = KeyHash([1, 0, 0, 0, 0, 0, 0, 0]);
+    let twin = KeyHash([1, 0, 0, 0, 5, 0, 0, 0]);
+    txn.insert(&key, [1, 0]).unwrap();
+    assert!(txn.insert(&twin, [1, 0]).is_err());
+
+    // Moving a key between owners moves its entry.
+    txn.insert(&key, [2, 0]).unwrap();
+    assert!(txn
+        .keys_with_attribute(&KeyHash::from_u64(1))
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        txn.keys_with_attribute(&KeyHash::from_u64(2)).unwrap(),
+        [key]
+    );
+}
@@ -0,0 +1,81 @@
+mod utils;
+
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, SeekNode, SeekResult, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_structured_key_hash;
+
+fn common_bits(a: &KeyHash, b: &KeyHash) -> u32 {
+    (0..256).take_while(|&i| a.bit(i) == b.bit(i)).count() as u32
+}
+
+proptest! {
+    #[test]
+    fn prop_seek_matches_the_closest_key(
+        keys in prop::collection::btree_set(arb_structured_key_hash(), 1..50),
+        probe in arb_structured_key_hash(),
+    ) {
+        let db = Rc::new(MemoryDb::<u64>::empty());
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for (i, key) in keys.iter().enumerate() {
+            txn.insert(key, i as u64).unwrap();
+        }
+        let in_memory = txn.seek(&probe).unwrap();
+
+        let root = txn.commit(&mut DigestHasher::<Sha256>::default()).unwrap().root;
+        let stored = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root))
+            .seek(&probe)
+            .unwrap();
+        prop_assert_eq!(in_memory, stored);
+
+        let closest = keys.iter().map(|key| common_bits(&probe, key)).max().unwrap();
+        prop_assert_eq!(stored.matched_bits, closest);
+        prop_assert_eq!(stored.exact, keys.contains(&probe));
+        if let SeekNode::Branch(mask) = stored.node {
+            prop_assert!(stored.matched_bits < mask.bit_idx());
+        }
+    }
+}
+
+#[test]
+fn seek_reports_where_the_path_stops() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, TrieRoot::Empty));
+    assert_eq!(
+        txn.seek(&KeyHash::from_u64(0)).unwrap(),
+        SeekResult {
+            matched_bits: 0,
+            depth: 0,
+            node: SeekNode::Empty,
+            exact: false,
+        }
+    );
+
+    // 0b000 and 0b100 share two bits, and branch on bit 2.
+    txn.insert(&KeyHash::from_u64(0b000), 0).unwrap();
+    txn.insert(&KeyHash::from_u64(0b100), 1).unwrap();
+
+    let found = txn.seek(&KeyHash::from_u64(0b100)).unwrap();
+    assert_eq!(found.node, SeekNode::Leaf(KeyHash::from_u64(0b100)));
+    assert_eq!(
+        (found.matched_bits, found.depth, found.exact),
+        (256, 1, true)
+    );
+
+    // 0b1100 takes the right side, then parts from 0b100 at bit 3.
+    let near = txn.seek(&KeyHash::from_u64(0b1100)).unwrap();
+    assert_eq!(near.node, SeekNode::Leaf(KeyHash::from_u64(0b100)));
+    assert_eq!((near.matched_bits, near.depth, near.exact), (3, 1, false));
+
+    // 0b010 parts from both keys at bit 1, before the root's discriminant bit.
+    let far = txn.seek(&KeyHash::from_u64(0b010)).unwrap();
+    assert!(matches!(far.node, SeekNode::Branch(mask) if mask.bit_idx() == 2));
+    assert_eq!((far.matched_bits, far.depth, far.exact), (1, 0, false));
+}
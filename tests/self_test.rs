@@ -0,0 +1,34 @@
+use kairos_trie::{
+    node_hash, self_test::portable_hash_consistency, DigestHasher, NodeHash, TaggedHasher,
+    TrieParams,
+};
+use sha2::Sha256;
+
+struct Tagged;
+
+impl TrieParams for Tagged {
+    const LEAF_TAG: &'static [u8] = b"self-test/leaf";
+    const BRANCH_TAG: &'static [u8] = b"self-test/branch";
+}
+
+/// The digest every target must compute with SHA-256.
+///
+/// If this changes, so did the hash of some value, key or node, and every root computed before the change.
+const SHA256_DIGEST: NodeHash =
+    node_hash!("c5337fc4e09c0d1f8ecdae5e3f1bad6edcdec3b514c757e57e23aae296d0f481");
+
+#[test]
+fn portable_hash_consistency_matches_known_answer() {
+    let digest = portable_hash_consistency::<DigestHasher<Sha256>>().unwrap();
+    assert_eq!(digest, SHA256_DIGEST);
+    assert_eq!(
+        portable_hash_consistency::<DigestHasher<Sha256>>().unwrap(),
+        digest
+    );
+}
+
+#[test]
+fn portable_hash_consistency_depends_on_the_hasher() {
+    let tagged = portable_hash_consistency::<TaggedHasher<DigestHasher<Sha256>, Tagged>>().unwrap();
+    assert_ne!(tagged, SHA256_DIGEST);
+}
@@ -0,0 +1,45 @@
+use std::{rc::Rc, sync::Arc, thread};
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[test]
+fn threads_share_one_snapshot() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..32 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for i in 0..4 {
+        txn.get(&KeyHash::from_u64(i)).unwrap();
+    }
+    let snapshot = Arc::new(txn.build_initial_snapshot());
+
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let snapshot = snapshot.clone();
+            thread::spawn(move || {
+                let mut txn = Transaction::from_snapshot_shared(snapshot).unwrap();
+                assert_eq!(txn.get(&KeyHash::from_u64(i)).unwrap(), Some(&i));
+                txn.insert(&KeyHash::from_u64(i), i + 100).unwrap();
+                txn.calc_root_hash(&mut DigestHasher::<Sha256>::default())
+                    .unwrap()
+            })
+        })
+        .collect();
+    let roots: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    assert!(roots.iter().all(|new_root| *new_root != root));
+    assert_ne!(roots[0], roots[1]);
+
+    let txn: Transaction<_, u64> = snapshot.try_into().unwrap();
+    assert_eq!(txn.calc_root_hash(hasher).unwrap(), root);
+}
@@ -0,0 +1,126 @@
+mod utils;
+
+use std::{collections::BTreeSet, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+        DatabaseGet,
+    },
+    DigestHasher, KeyHash, Node, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_key_hash;
+
+fn committed(keys: &BTreeSet<KeyHash>) -> (Rc<MemoryDb<u64>>, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys {
+        txn.insert(key, key.0[0] as u64).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (db, root)
+}
+
+fn snapshot_of_gets<'k>(
+    db: &Rc<MemoryDb<u64>>,
+    root: TrieRoot<NodeHash>,
+    keys: impl IntoIterator<Item = &'k KeyHash>,
+) -> Snapshot<u64> {
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    for key in keys {
+        txn.get(key).unwrap();
+    }
+    txn.build_initial_snapshot()
+}
+
+proptest! {
+    #[test]
+    fn prop_difference(
+        stored in prop::collection::btree_set(arb_key_hash(), 1..200),
+        reads_a in prop::collection::vec(arb_key_hash(), 0..10),
+        reads_b in prop::collection::vec(arb_key_hash(), 0..10),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let (db, root) = committed(&stored);
+
+        // Read a mix of present and absent keys.
+        let keys_a: Vec<KeyHash> = stored.iter().step_by(7).chain(&reads_a).copied().collect();
+        let keys_b: Vec<KeyHash> = stored.iter().step_by(5).chain(&reads_b).copied().collect();
+        let a = snapshot_of_gets(&db, root, &keys_a);
+        let b = snapshot_of_gets(&db, root, &keys_b);
+        let both = snapshot_of_gets(&db, root, keys_a.iter().chain(&keys_b));
+
+        let diff = Snapshot::difference(&a, &b, hasher).unwrap();
+        let reversed = Snapshot::difference(&b, &a, hasher).unwrap();
+        prop_assert_eq!(&diff.only_in_a, &reversed.only_in_b);
+        prop_assert_eq!(&diff.only_in_b, &reversed.only_in_a);
+        prop_assert_eq!(diff.shared, reversed.shared);
+
+        prop_assert_eq!(diff.only_in_a.is_empty(), a.is_sub_snapshot_of(&b, hasher).unwrap());
+        prop_assert_eq!(diff.is_empty(), a.is_equivalent_to(&b, hasher).unwrap());
+
+        // A transaction making both sets of reads visits every node of either, and no others.
+        let with_both = Snapshot::difference(&a, &both, hasher).unwrap();
+        prop_assert!(with_both.only_in_a.is_empty());
+        prop_assert_eq!(&with_both.only_in_b, &diff.only_in_b);
+        prop_assert_eq!(with_both.shared, diff.shared + diff.only_in_a.len());
+
+        // The nodes are keyed by their hashes, and only one side has each of them.
+        let (db_b, _) = b.to_memory_db(hasher).unwrap();
+        let (db_a, _) = a.to_memory_db(hasher).unwrap();
+        for (hash, node) in &diff.only_in_a {
+            let hash_of = match node {
+                Node::Branch(branch) => {
+                    branch.hash_branch(hasher, &branch.left, &branch.right)
+                }
+                Node::Leaf(leaf) => leaf.hash_leaf(hasher),
+            };
+            prop_assert_eq!(&hash_of, hash);
+            prop_assert!(db_b.get(hash).is_err());
+            prop_assert!(db_a.get(hash).is_ok());
+        }
+    }
+}
+
+#[test]
+fn difference_of_disjoint_reads() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let stored: BTreeSet<KeyHash> = (0..1000).map(KeyHash::from_u64).collect();
+    let (db, root) = committed(&stored);
+
+    let even = KeyHash::from_u64(0);
+    let odd = KeyHash::from_u64(1);
+    let a = snapshot_of_gets(&db, root, [&even]);
+    let b = snapshot_of_gets(&db, root, [&odd]);
+
+    // The keys split at the root, so only the root is shared.
+    let diff = Snapshot::difference(&a, &b, hasher).unwrap();
+    assert_eq!(diff.shared, 1);
+    assert!(!diff.only_in_a.is_empty());
+    assert_eq!(diff.only_in_a.len(), diff.only_in_b.len());
+    assert!(diff
+        .only_in_a
+        .values()
+        .any(|node| matches!(node, Node::Leaf(leaf) if leaf.key_hash == even)));
+
+    let same = Snapshot::difference(&a, &a, hasher).unwrap();
+    assert!(same.is_empty());
+    assert_eq!(same.shared, diff.shared + diff.only_in_a.len());
+
+    let empty = Snapshot::difference(
+        &snapshot_of_gets(&db, root, []),
+        &snapshot_of_gets(&db, root, []),
+        hasher,
+    )
+    .unwrap();
+    assert!(empty.is_empty());
+    assert_eq!(empty.shared, 0);
+}
@@ -0,0 +1,177 @@
+#![cfg(feature = "serde")]
+
+use std::rc::Rc;
+
+use kairos_trie::{
+    snapshot::{self, SnapshotCounts, HEADER_LEN, HEADER_LEN_V1, MIN_VERSION, VERSION},
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+    },
+    DigestHasher, KeyHash, Transaction, TrieKey, TrieRoot,
+};
+use sha2::Sha256;
+
+fn witness() -> Snapshot<u64> {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..50 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for i in [3, 17, 40] {
+        txn.get(&KeyHash::from_u64(i)).unwrap();
+    }
+    txn.build_initial_snapshot()
+}
+
+fn decode_bincode(body: &[u8]) -> Result<Snapshot<u64>, bincode::Error> {
+    bincode::deserialize(body)
+}
+
+#[test]
+fn header_describes_the_framed_snapshot() {
+    let snapshot = witness();
+    let body = bincode::serialize(&snapshot).unwrap();
+    let bytes = snapshot::encode(&snapshot, &body);
+
+    let header = snapshot::peek_header(&bytes).unwrap();
+    assert_eq!(header.version, VERSION);
+    assert_eq!(header.body_len, body.len() as u64);
+    assert_eq!(header.counts.leaves, 3);
+    assert_eq!(
+        header.counts.branches + 1,
+        header.counts.leaves + header.counts.unvisited_nodes
+    );
+    assert!(header.estimated_memory >= body.len() as u64);
+
+    let decoded = snapshot::decode(&bytes, header.estimated_memory, decode_bincode).unwrap();
+    assert_eq!(decoded, snapshot);
+
+    // The guest's memory budget is checked before the body is touched.
+    let error = snapshot::decode(&bytes, header.estimated_memory - 1, |_| {
+        Err::<Snapshot<u64>, _>("must not decode")
+    })
+    .unwrap_err();
+    assert!(error.display().starts_with("Snapshot too large"));
+}
+
+#[test]
+fn header_rejects_malformed_frames() {
+    let snapshot = witness();
+    let body = bincode::serialize(&snapshot).unwrap();
+    let bytes = snapshot::encode(&snapshot, &body);
+
+    assert!(snapshot::peek_header(&bytes[..HEADER_LEN - 1]).is_err());
+    assert!(snapshot::peek_header(&bytes[..bytes.len() - 1]).is_err());
+    assert!(snapshot::peek_header(&body).is_err());
+
+    let mut future = bytes.clone();
+    future[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    let error = snapshot::peek_header(&future).unwrap_err();
+    assert!(error.display().starts_with("Unsupported snapshot version"));
+    let mut ancient = bytes.clone();
+    ancient[4..8].copy_from_slice(&(MIN_VERSION - 1).to_le_bytes());
+    assert!(snapshot::peek_header(&ancient).is_err());
+
+    // Counts no index can address, or more prefix words than the branches hold.
+    let mut huge = bytes.clone();
+    huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(snapshot::peek_header(&huge).is_err());
+    let mut prefixes = bytes.clone();
+    prefixes[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(snapshot::peek_header(&prefixes).is_err());
+    // With `idx-u64` these branches are addressable, but the most prefix words they could hold overflows.
+    let mut overflowing = bytes.clone();
+    overflowing[8..16].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+    overflowing[16..32].fill(0);
+    overflowing[32..40].copy_from_slice(&0u64.to_le_bytes());
+    assert!(snapshot::peek_header(&overflowing).is_err());
+
+    // A header understating the body is caught after decoding it.
+    let empty = SnapshotBuilder::<_, u64>::empty(MemoryDb::<u64>::empty()).build_initial_snapshot();
+    let lying = snapshot::encode(&empty, &body);
+    let header = snapshot::peek_header(&lying).unwrap();
+    assert_eq!(
+        header.counts,
+        SnapshotCounts {
+            branches: 0,
+            leaves: 0,
+            unvisited_nodes: 0
+        }
+    );
+    assert!(snapshot::decode(&lying, u64::MAX, decode_bincode).is_err());
+}
+
+/// Frame `body` as version 1 did, with a header ending at the body length.
+fn encode_v1(snapshot: &Snapshot<u64>, body: &[u8]) -> Vec<u8> {
+    let framed = snapshot::encode(snapshot, body);
+    let mut bytes = framed[..HEADER_LEN_V1].to_vec();
+    bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+#[test]
+fn version_1_frames_are_read_and_upgraded() {
+    let snapshot = witness();
+    let body = bincode::serialize(&snapshot).unwrap();
+    let current = snapshot::encode(&snapshot, &body);
+    let old = encode_v1(&snapshot, &body);
+    assert_eq!(MIN_VERSION, 1);
+
+    let header = snapshot::peek_header(&old).unwrap();
+    assert_eq!(header.version, 1);
+    assert_eq!(header.key_bytes, size_of::<KeyHash>() as u64);
+    assert_eq!(header.body_len, body.len() as u64);
+    assert_eq!(
+        snapshot::decode(&old, u64::MAX, decode_bincode).unwrap(),
+        snapshot
+    );
+
+    // Upgrading gives the frame this version writes, and leaves a current frame as it is.
+    assert_eq!(snapshot::upgrade::<KeyHash>(&old).unwrap(), current);
+    assert_eq!(snapshot::upgrade::<KeyHash>(&current).unwrap(), current);
+
+    // A version 2 header cut to the length of a version 1 header is incomplete.
+    let mut truncated = current[..HEADER_LEN_V1].to_vec();
+    truncated[40..48].copy_from_slice(&0u64.to_le_bytes());
+    assert!(snapshot::peek_header(&truncated).is_err());
+}
+
+/// A key half the size of a `KeyHash`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct ShortKey([u32; 4]);
+
+impl TrieKey for ShortKey {
+    fn words(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+#[test]
+fn frames_record_their_key_size() {
+    let snapshot = witness();
+    let body = bincode::serialize(&snapshot).unwrap();
+    let bytes = snapshot::encode(&snapshot, &body);
+    assert_eq!(
+        snapshot::peek_header(&bytes).unwrap().key_bytes,
+        size_of::<KeyHash>() as u64
+    );
+
+    let error = snapshot::decode(&bytes, u64::MAX, |_| {
+        Err::<Snapshot<u64, ShortKey>, _>("must not decode")
+    })
+    .unwrap_err();
+    assert!(error.display().contains("keys of 32 bytes"));
+    assert!(snapshot::upgrade::<ShortKey>(&bytes).is_err());
+
+    // Version 1 did not record the key size, so its frames are upgraded with the caller's.
+    let old = encode_v1(&snapshot, &body);
+    let upgraded = snapshot::upgrade::<ShortKey>(&old).unwrap();
+    assert_eq!(snapshot::peek_header(&upgraded).unwrap().key_bytes, 16);
+}
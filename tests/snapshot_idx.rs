@@ -0,0 +1,70 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{BranchIdx, LeafIdx, NodeIdx, SnapshotBuilder, UnvisitedIdx},
+        Idx,
+    },
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[test]
+fn typed_indexes_address_every_node() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..64 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // Read a few keys, leaving the rest of the trie unvisited.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for i in [3, 17, 40] {
+        assert_eq!(txn.get(&KeyHash::from_u64(i)).unwrap(), Some(&i));
+    }
+    let snapshot = txn.build_initial_snapshot();
+
+    let mut counts = [0; 3];
+    let mut idx: Idx = 0;
+    while let Ok(node) = snapshot.node_idx(idx) {
+        assert_eq!(snapshot.encode_idx(node), idx);
+        match node {
+            NodeIdx::Branch(branch) => {
+                assert_eq!(branch, BranchIdx(counts[0]));
+                assert!(snapshot.branch(branch).is_some());
+                counts[0] += 1;
+            }
+            NodeIdx::Leaf(leaf) => {
+                assert_eq!(leaf, LeafIdx(counts[1]));
+                assert!(snapshot.leaf(leaf).is_some());
+                counts[1] += 1;
+            }
+            NodeIdx::Unvisited(unvisited) => {
+                assert_eq!(unvisited, UnvisitedIdx(counts[2]));
+                assert!(snapshot.unvisited_node(unvisited).is_some());
+                counts[2] += 1;
+            }
+        }
+        idx += 1;
+    }
+    assert_eq!(counts[1], 3);
+    assert!(counts[0] > 0 && counts[2] > 0);
+
+    // The root is the last branch, and the children of a branch decode to the nodes under it.
+    let TrieRoot::Node(NodeIdx::Branch(root_branch)) = snapshot.root_node().unwrap() else {
+        panic!("The root of a multi-leaf trie is a branch");
+    };
+    assert_eq!(root_branch, BranchIdx(counts[0] - 1));
+    let branch = snapshot.branch(root_branch).unwrap();
+    assert!(snapshot.node_idx(branch.left).is_ok());
+    assert!(snapshot.node_idx(branch.right).is_ok());
+
+    assert!(snapshot.branch(BranchIdx(counts[0])).is_none());
+    assert!(snapshot.leaf(LeafIdx(3)).is_none());
+    assert!(snapshot.unvisited_node(UnvisitedIdx(counts[2])).is_none());
+}
@@ -0,0 +1,56 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{MissingNode, SnapshotBuilder},
+    },
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+#[test]
+fn snapshot_replays_against_its_memory_db() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..100 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // The witness of a block that reads, updates and removes a few keys.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&KeyHash::from_u64(3)).unwrap();
+    txn.insert(&KeyHash::from_u64(42), 0).unwrap();
+    txn.remove(&KeyHash::from_u64(77)).unwrap();
+    let new_root = txn.commit(hasher).unwrap().root;
+    let snapshot = txn.build_initial_snapshot();
+
+    let (local_db, local_root) = snapshot.to_memory_db(hasher).unwrap();
+    assert_eq!(local_root, root);
+
+    let mut local =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(Rc::new(local_db), local_root));
+    assert_eq!(local.get(&KeyHash::from_u64(3)).unwrap(), Some(&3));
+    local.insert(&KeyHash::from_u64(42), 0).unwrap();
+    assert_eq!(local.remove(&KeyHash::from_u64(77)).unwrap(), Some(77));
+    assert_eq!(local.commit(hasher).unwrap().root, new_root);
+    assert_eq!(local.build_initial_snapshot(), snapshot);
+
+    // Keys the witness does not cover are behind unvisited nodes.
+    let error = local.get(&KeyHash::from_u64(8)).unwrap_err();
+    assert!(error.downcast_source::<MissingNode>().is_some());
+}
+
+#[test]
+fn empty_snapshot_makes_an_empty_memory_db() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let snapshot =
+        SnapshotBuilder::<_, u64>::empty(MemoryDb::<u64>::empty()).build_initial_snapshot();
+
+    let (db, root) = snapshot.to_memory_db(hasher).unwrap();
+    assert_eq!(root, TrieRoot::Empty);
+    assert_eq!(db, MemoryDb::empty());
+}
@@ -0,0 +1,90 @@
+mod utils;
+
+use std::{collections::BTreeSet, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+    },
+    DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_key_hash;
+
+fn snapshot_of_gets(
+    db: Rc<MemoryDb<u64>>,
+    root: TrieRoot<NodeHash>,
+    keys: impl IntoIterator<Item = KeyHash>,
+) -> Snapshot<u64> {
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for key in keys {
+        txn.get(&key).unwrap();
+    }
+    txn.build_initial_snapshot()
+}
+
+proptest! {
+    #[test]
+    fn prop_sub_snapshot(
+        stored in prop::collection::btree_set(arb_key_hash(), 1..200),
+        reads in prop::collection::vec(arb_key_hash(), 0..20),
+        split in any::<prop::sample::Index>(),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for key in stored.iter() {
+            txn.insert(key, key.0[0] as u64).unwrap();
+        }
+        let root = txn.commit(hasher).unwrap().root;
+
+        // Read a mix of present and absent keys.
+        let keys: Vec<KeyHash> = stored.iter().step_by(7).chain(reads.iter()).copied().collect();
+        let (part, _) = keys.split_at(split.index(keys.len() + 1));
+
+        let whole = snapshot_of_gets(db.clone(), root, keys.iter().copied());
+        let part = snapshot_of_gets(db.clone(), root, part.iter().copied());
+        let reversed = snapshot_of_gets(db.clone(), root, keys.iter().rev().copied());
+
+        prop_assert!(part.is_sub_snapshot_of(&whole, hasher).unwrap());
+        prop_assert!(whole.is_sub_snapshot_of(&whole, hasher).unwrap());
+        prop_assert!(whole.is_equivalent_to(&reversed, hasher).unwrap());
+        prop_assert!(reversed.is_sub_snapshot_of(&whole, hasher).unwrap());
+
+        // Containment both ways is equivalence.
+        prop_assert_eq!(
+            whole.is_sub_snapshot_of(&part, hasher).unwrap(),
+            part.is_equivalent_to(&whole, hasher).unwrap()
+        );
+    }
+}
+
+#[test]
+fn snapshots_of_different_tries_are_unrelated() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let keys: BTreeSet<KeyHash> = (0..20u32).map(|i| KeyHash([i; 8])).collect();
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys.iter() {
+        txn.insert(key, 0).unwrap();
+    }
+    let root_a = txn.commit(hasher).unwrap().root;
+    txn.insert(&KeyHash([100; 8]), 0).unwrap();
+    let root_b = txn.commit(hasher).unwrap().root;
+
+    let a = snapshot_of_gets(db.clone(), root_a, keys.iter().copied());
+    let b = snapshot_of_gets(db.clone(), root_b, keys.iter().copied());
+    let empty = snapshot_of_gets(db, TrieRoot::Empty, []);
+
+    assert!(!a.is_sub_snapshot_of(&b, hasher).unwrap());
+    assert!(!a.is_equivalent_to(&b, hasher).unwrap());
+    assert!(!empty.is_sub_snapshot_of(&a, hasher).unwrap());
+    assert!(empty.is_equivalent_to(&empty, hasher).unwrap());
+}
@@ -0,0 +1,109 @@
+mod utils;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    spec::{branch_preimage, hash_preimage, leaf_preimage, BRANCH_BIT_IDX, BRANCH_PREFIX},
+    Branch, BranchMask, DigestHasher, KeyHash, Leaf, NodeHash, PortableHasher, TaggedHasher,
+    TrieParams,
+};
+use sha2::Sha256;
+use utils::arb_key_hash;
+
+struct Tagged;
+
+impl TrieParams for Tagged {
+    const LEAF_TAG: &'static [u8] = b"spec/leaf";
+    const BRANCH_TAG: &'static [u8] = b"spec/branch";
+}
+
+fn check_leaf<H: PortableHasher<32>>(key: KeyHash, value: Vec<u8>) -> Result<(), TestCaseError> {
+    let hasher = &mut H::default();
+    let preimage = leaf_preimage::<H, _>(&key, &value);
+    let leaf = Leaf {
+        key_hash: key,
+        value,
+    };
+    prop_assert_eq!(hash_preimage(hasher, &preimage), leaf.hash_leaf(hasher));
+    Ok(())
+}
+
+fn check_branch<H: PortableHasher<32>>(
+    branch: &Branch<()>,
+    left: NodeHash,
+    right: NodeHash,
+) -> Result<(), TestCaseError> {
+    let hasher = &mut H::default();
+    let preimage = branch_preimage::<H, _>(branch, &left, &right);
+    prop_assert_eq!(
+        preimage.len(),
+        H::BRANCH_TAG.len() + BRANCH_PREFIX + branch.prefix.len() * 4
+    );
+    prop_assert_eq!(
+        hash_preimage(hasher, &preimage),
+        branch.hash_branch(hasher, &left, &right)
+    );
+    Ok(())
+}
+
+prop_compose! {
+    /// A branch with arbitrary fields, including prefixes longer than any canonical branch.
+    fn arb_branch()(
+        word_idx in 0..8u32,
+        a in any::<u32>(),
+        diff in 1..=u32::MAX,
+        prior_word in any::<u32>(),
+        prefix in prop::collection::vec(any::<u32>(), 0..10),
+    ) -> Branch<()> {
+        Branch {
+            left: (),
+            right: (),
+            mask: BranchMask::new(word_idx, a, a ^ diff),
+            prior_word,
+            prefix: prefix.into(),
+        }
+    }
+}
+
+#[test]
+fn branch_layout() {
+    let branch = Branch {
+        left: (),
+        right: (),
+        mask: BranchMask::new(1, 0b0100, 0b1100),
+        prior_word: 7,
+        prefix: [9].into(),
+    };
+    let preimage = branch_preimage::<DigestHasher<Sha256>, _>(
+        &branch,
+        &NodeHash::new([1; 32]),
+        &NodeHash::new([2; 32]),
+    );
+
+    assert_eq!(&preimage[..32], &[1; 32]);
+    assert_eq!(&preimage[32..64], &[2; 32]);
+    // Bit 3 of word 1, below which both keys share 0b100.
+    assert_eq!(
+        &preimage[BRANCH_BIT_IDX..],
+        &[35, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0, 9, 0, 0, 0]
+    );
+}
+
+proptest! {
+    #[test]
+    fn leaf_preimage_matches_hash_leaf(key in arb_key_hash(), value in prop::collection::vec(any::<u8>(), 0..100)) {
+        check_leaf::<DigestHasher<Sha256>>(key, value.clone())?;
+        check_leaf::<TaggedHasher<DigestHasher<Sha256>, Tagged>>(key, value)?;
+    }
+
+    #[test]
+    fn branch_preimage_matches_hash_branch(
+        branch in arb_branch(),
+        left in any::<[u8; 32]>(),
+        right in any::<[u8; 32]>(),
+    ) {
+        let (left, right) = (NodeHash::new(left), NodeHash::new(right));
+        check_branch::<DigestHasher<Sha256>>(&branch, left, right)?;
+        check_branch::<TaggedHasher<DigestHasher<Sha256>, Tagged>>(&branch, left, right)?;
+    }
+}
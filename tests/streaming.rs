@@ -0,0 +1,160 @@
+mod utils;
+
+use std::{collections::BTreeSet, io::Cursor, rc::Rc};
+
+use proptest::{prelude::*, sample::Index};
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+    },
+    stream::STREAM_MAGIC,
+    DigestHasher, KeyHash, NodeHash, PortableHash, TaggedHasher, Transaction, TrieParams, TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+/// A witness reading `reads` of `keys`, and its root.
+fn witness<V: PortableHash + Clone>(
+    keys: &BTreeSet<KeyHash>,
+    value: impl Fn(&KeyHash) -> V,
+    reads: &[usize],
+    hasher: &mut DigestHasher<Sha256>,
+) -> (Snapshot<V>, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<V>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys {
+        txn.insert(key, value(key)).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let keys: Vec<_> = keys.iter().collect();
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for &idx in reads {
+        let key = keys[idx % keys.len()];
+        txn.get(key).unwrap();
+        txn.insert(&KeyHash([key.0[0]; 8]), value(key)).unwrap();
+    }
+    (txn.build_initial_snapshot(), root)
+}
+
+fn streams_like_calc_root_hash(
+    keys: BTreeSet<KeyHash>,
+    reads: Vec<usize>,
+) -> Result<(), TestCaseError> {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (snapshot, root) = witness(&keys, |key| key.0[0] as u64, &reads, hasher);
+    prop_assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+
+    let bytes = snapshot.encode_streaming().unwrap();
+    Snapshot::<u64>::verify_streaming(&bytes[..], root, hasher).unwrap();
+    Snapshot::<u64>::verify_streaming(Cursor::new(&bytes), root, hasher).unwrap();
+
+    let wrong_root = TrieRoot::Node(NodeHash::new([7; 32]));
+    prop_assert!(Snapshot::<u64>::verify_streaming(&bytes[..], wrong_root, hasher).is_err());
+    if bytes.len() > STREAM_MAGIC.len() + 8 {
+        prop_assert!(
+            Snapshot::<u64>::verify_streaming(&bytes[..bytes.len() - 1], root, hasher).is_err()
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn prop_streams_like_calc_root_hash(
+        keys in prop::collection::btree_set(arb_key_hash(), 1..100),
+        reads: Vec<usize>,
+    ) {
+        streams_like_calc_root_hash(keys, reads)?;
+    }
+
+    #[test]
+    fn prop_streams_like_calc_root_hash_structured_keys(
+        keys in prop::collection::btree_set(arb_structured_key_hash(), 1..100),
+        reads: Vec<usize>,
+    ) {
+        streams_like_calc_root_hash(keys, reads)?;
+    }
+
+    #[test]
+    fn prop_changing_any_byte_fails(
+        keys in prop::collection::btree_set(arb_structured_key_hash(), 1..20),
+        reads: Vec<usize>,
+        byte: Index,
+        flip in 1u8..,
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let (snapshot, root) = witness(&keys, |key| key.0[0] as u64, &reads, hasher);
+        let mut bytes = snapshot.encode_streaming().unwrap();
+        let byte = byte.index(bytes.len());
+        bytes[byte] ^= flip;
+        prop_assert!(Snapshot::<u64>::verify_streaming(&bytes[..], root, hasher).is_err());
+    }
+}
+
+#[test]
+fn streams_long_values_and_tagged_hashers() {
+    struct Tagged;
+    impl TrieParams for Tagged {
+        const LEAF_TAG: &'static [u8] = b"stream/leaf";
+        const BRANCH_TAG: &'static [u8] = b"stream/branch";
+    }
+
+    let keys: BTreeSet<KeyHash> = (0..50).map(KeyHash::from_u64).collect();
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    // Values longer than a chunk of the streaming hasher.
+    let value = |key: &KeyHash| vec![key.0[0] as u8; 300 + key.0[0] as usize * 7];
+    let (snapshot, root) = witness(&keys, value, &[0, 9], hasher);
+    let bytes = snapshot.encode_streaming().unwrap();
+    Snapshot::<Vec<u8>>::verify_streaming(&bytes[..], root, hasher).unwrap();
+
+    let tagged = &mut TaggedHasher::<DigestHasher<Sha256>, Tagged>::default();
+    let tagged_root = snapshot.calc_root_hash(tagged).unwrap();
+    assert_ne!(tagged_root, root);
+    Snapshot::<Vec<u8>>::verify_streaming(&bytes[..], tagged_root, tagged).unwrap();
+}
+
+#[test]
+fn streams_the_empty_trie() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let (snapshot, root) = witness(&BTreeSet::new(), |_| 0u64, &[], hasher);
+    assert_eq!(root, TrieRoot::Empty);
+
+    let bytes = snapshot.encode_streaming().unwrap();
+    assert_eq!(bytes.len(), STREAM_MAGIC.len() + 8);
+    Snapshot::<u64>::verify_streaming(&bytes[..], TrieRoot::Empty, hasher).unwrap();
+    assert!(Snapshot::<u64>::verify_streaming(
+        &bytes[..],
+        TrieRoot::Node(NodeHash::new([0; 32])),
+        hasher
+    )
+    .is_err());
+}
+
+#[test]
+fn rejects_malformed_streams() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut bytes = STREAM_MAGIC.to_vec();
+    bytes.extend_from_slice(&2u64.to_le_bytes());
+    for _ in 0..2 {
+        bytes.push(2);
+        bytes.extend_from_slice(&[1; 32]);
+    }
+    // Two unvisited nodes without a branch above them.
+    let err = Snapshot::<u64>::verify_streaming(&bytes[..], TrieRoot::Empty, hasher).unwrap_err();
+    assert!(err.to_string().contains("single trie"), "{err}");
+
+    // A branch alone has no children.
+    let mut bytes = STREAM_MAGIC.to_vec();
+    bytes.extend_from_slice(&1u64.to_le_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&[0; 13]);
+    let err = Snapshot::<u64>::verify_streaming(&bytes[..], TrieRoot::Empty, hasher).unwrap_err();
+    assert!(err.to_string().contains("before its children"), "{err}");
+
+    let err = Snapshot::<u64>::verify_streaming(&b"KTSN"[..], TrieRoot::Empty, hasher).unwrap_err();
+    assert!(err.to_string().contains("STREAM_MAGIC"), "{err}");
+}
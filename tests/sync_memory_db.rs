@@ -0,0 +1,101 @@
+use std::{sync::Arc, thread};
+
+use kairos_trie::{
+    stored::{
+        memory_db::SyncMemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+    },
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn assert_send_sync<T: Send + Sync>() {}
+fn assert_send<T: Send>() {}
+
+#[test]
+fn provided_stores_are_send_sync() {
+    assert_send_sync::<SyncMemoryDb<u64>>();
+    assert_send_sync::<Arc<SyncMemoryDb<u64>>>();
+    assert_send_sync::<Snapshot<u64>>();
+    assert_send::<SnapshotBuilder<Arc<SyncMemoryDb<u64>>, u64>>();
+    #[cfg(feature = "test-utils")]
+    assert_send_sync::<kairos_trie::stored::faulty::FaultyDb<Arc<SyncMemoryDb<u64>>>>();
+}
+
+#[test]
+fn threads_share_one_sync_memory_db() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Arc::new(SyncMemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..256 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // Each thread reads and updates its own part of the trie from the shared store.
+    let snapshots: Vec<Snapshot<u64>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..4u64)
+            .map(|t| {
+                let db = db.clone();
+                scope.spawn(move || {
+                    let mut txn =
+                        Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+                    for i in (t * 64)..(t + 1) * 64 {
+                        assert_eq!(txn.get(&KeyHash::from_u64(i)).unwrap(), Some(&i));
+                        txn.insert(&KeyHash::from_u64(i), i + 1).unwrap();
+                    }
+                    txn.build_initial_snapshot()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    // A snapshot can be verified from several threads at once.
+    let snapshot = Arc::new(snapshots.into_iter().next().unwrap());
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            let snapshot = snapshot.clone();
+            scope.spawn(move || {
+                let hasher = &mut DigestHasher::<Sha256>::default();
+                assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+                let txn = Transaction::from_snapshot(&*snapshot).unwrap();
+                assert_eq!(txn.get(&KeyHash::from_u64(3)).unwrap(), Some(&3));
+            });
+        }
+    });
+}
+
+#[test]
+fn loaded_builder_moves_between_threads() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Arc::new(SyncMemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..1000 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // Load every node on one thread, then keep using the same builder on another.
+    let builder = SnapshotBuilder::new(db, root);
+    let txn = thread::spawn(move || {
+        let txn = Transaction::from_snapshot_builder(builder);
+        for i in 0..1000 {
+            assert_eq!(txn.get(&KeyHash::from_u64(i)).unwrap(), Some(&i));
+        }
+        txn
+    })
+    .join()
+    .unwrap();
+
+    let snapshot = thread::spawn(move || txn.build_initial_snapshot())
+        .join()
+        .unwrap();
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+    let txn = Transaction::from_snapshot(&snapshot).unwrap();
+    assert_eq!(txn.get(&KeyHash::from_u64(999)).unwrap(), Some(&999));
+}
@@ -0,0 +1,127 @@
+//! Index boundaries that only bite on 32-bit targets, such as wasm32 and riscv32 zkVM guests.
+//!
+//! On a 64-bit host these indices are rejected because they are past the end of the snapshot.
+//! Run on a 32-bit target with the `idx-u64` feature, they check that an `Idx` past `usize::MAX`
+//! is rejected rather than truncated onto a node that exists.
+
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, Idx, MAX_NODES},
+    DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn committed(keys: u64) -> (Rc<MemoryDb<u64>>, TrieRoot<NodeHash>) {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..keys {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (db, root)
+}
+
+/// Indices past the end of any snapshot: the largest, and with the `idx-u64` feature,
+/// those a truncating cast to 32 bits would read as `aliased`.
+fn boundary_indices(aliased: Idx) -> Vec<Idx> {
+    let high = [1u64 << 32, 1 << 33, u64::MAX << 32];
+    [Idx::MAX, Idx::MAX - 1]
+        .into_iter()
+        .chain(
+            high.into_iter()
+                .filter_map(|high| Idx::try_from(u128::from(high) | aliased as u128).ok()),
+        )
+        .collect()
+}
+
+#[test]
+fn max_nodes_fits_idx_and_usize() {
+    assert_eq!(
+        MAX_NODES as u128,
+        (Idx::MAX as u128).min(usize::MAX as u128)
+    );
+    assert!(Idx::try_from(MAX_NODES).is_ok());
+
+    if cfg!(target_pointer_width = "32") {
+        assert_eq!(MAX_NODES, usize::MAX);
+    } else {
+        assert_eq!(MAX_NODES as u128, Idx::MAX as u128);
+    }
+}
+
+#[test]
+fn builder_indices_are_not_truncated() {
+    let (db, root) = committed(16);
+    let builder = SnapshotBuilder::<_, u64>::new(db, root);
+    let TrieRoot::Node(root_hash) = root else {
+        panic!("A trie of 16 keys has a root node");
+    };
+    assert_eq!(builder.get_node_hash(0).unwrap(), root_hash);
+    for idx in boundary_indices(0) {
+        assert!(builder.get_node_hash(idx).is_err(), "{idx}");
+    }
+}
+
+#[cfg(feature = "serde")]
+mod corrupt_indices {
+    use kairos_trie::{
+        stored::{
+            merkle::{Snapshot, SnapshotBuilder},
+            Idx,
+        },
+        Branch, DigestHasher, FlatError, KeyHash, Leaf, NodeHash, Transaction, TrieRoot,
+    };
+    use sha2::Sha256;
+
+    use super::{boundary_indices, committed};
+
+    type Parts = (Vec<Branch<Idx>>, Vec<Leaf<u64>>, Vec<NodeHash>);
+
+    fn witness() -> (Parts, TrieRoot<NodeHash>) {
+        let (db, root) = committed(64);
+        let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for i in [2, 7, 19, 40, 63] {
+            txn.get(&KeyHash::from_u64(i)).unwrap();
+        }
+        let snapshot = txn.build_initial_snapshot();
+        let parts = bincode::deserialize(&bincode::serialize(&snapshot).unwrap()).unwrap();
+        (parts, root)
+    }
+
+    fn from_parts(parts: &Parts) -> Snapshot<u64> {
+        bincode::deserialize(&bincode::serialize(parts).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn out_of_range_children_are_errors() {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let (parts, root) = witness();
+        let root_idx = parts.0.len() - 1;
+        let left = parts.0[root_idx].left;
+
+        // A truncating cast would read the right child as the left child, which is already hashed and cached.
+        for idx in boundary_indices(left) {
+            let (mut branches, leaves, unvisited) = parts.clone();
+            branches[root_idx].right = idx;
+            let corrupt = from_parts(&(branches, leaves, unvisited));
+
+            assert!(corrupt.calc_root_hash(hasher).is_err(), "{idx}");
+            assert!(corrupt.replay_reads(hasher, root, &[]).is_err(), "{idx}");
+            assert!(corrupt.check_canonical().is_err(), "{idx}");
+
+            let buf = corrupt.to_flat();
+            assert!(
+                matches!(
+                    buf.as_flat().calc_root_hash(hasher),
+                    Err(FlatError::NodeNotFound(_))
+                ),
+                "{idx}"
+            );
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Timestamped, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+type Value = Timestamped<u64>;
+
+fn txn() -> Transaction<SnapshotBuilder<Rc<MemoryDb<Value>>, Value>, Value> {
+    Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::empty()),
+        TrieRoot::Empty,
+    ))
+}
+
+fn stamped(value: u64, created: u64, updated: u64) -> Value {
+    Timestamped {
+        value,
+        created,
+        updated,
+    }
+}
+
+#[test]
+fn insert_at_maintains_heights() {
+    let key = KeyHash::from_u64(7);
+    let mut txn = txn();
+
+    txn.insert_at(&key, 1, 10).unwrap();
+    assert_eq!(txn.get(&key).unwrap(), Some(&stamped(1, 10, 10)));
+
+    txn.insert_at(&key, 2, 12).unwrap();
+    assert_eq!(txn.get(&key).unwrap(), Some(&stamped(2, 10, 12)));
+
+    // The same value is not a change.
+    txn.insert_at(&key, 2, 15).unwrap();
+    assert_eq!(txn.get(&key).unwrap(), Some(&stamped(2, 10, 12)));
+
+    let err = txn.insert_at(&key, 3, 11).unwrap_err();
+    assert!(err.to_string().contains("before"), "{err}");
+    assert_eq!(txn.get(&key).unwrap(), Some(&stamped(2, 10, 12)));
+
+    // A removed key is created again.
+    txn.remove(&key).unwrap();
+    txn.insert_at(&key, 3, 20).unwrap();
+    assert_eq!(txn.get(&key).unwrap(), Some(&stamped(3, 20, 20)));
+}
+
+#[test]
+fn update_at_maintains_heights() {
+    let key = KeyHash::from_u64(7);
+    let mut txn = txn();
+
+    txn.update_at(&key, 5, |value| *value += 4).unwrap();
+    assert_eq!(txn.get(&key).unwrap(), Some(&stamped(4, 5, 5)));
+
+    txn.update_at(&key, 6, |value| *value += 1).unwrap();
+    assert_eq!(txn.get(&key).unwrap(), Some(&stamped(5, 5, 6)));
+
+    txn.update_at(&key, 9, |_| {}).unwrap();
+    assert_eq!(txn.get(&key).unwrap(), Some(&stamped(5, 5, 6)));
+    assert!(txn.update_at(&key, 1, |value| *value += 1).is_err());
+}
+
+#[test]
+fn heights_are_part_of_the_root() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let key = KeyHash::from_u64(7);
+
+    let mut early = txn();
+    early.insert_at(&key, 1, 10).unwrap();
+    let mut late = txn();
+    late.insert_at(&key, 1, 11).unwrap();
+    assert_ne!(
+        early.calc_root_hash(hasher).unwrap(),
+        late.calc_root_hash(hasher).unwrap()
+    );
+
+    // An inclusion proof shows when the value last changed.
+    early.insert_at(&key, 2, 30).unwrap();
+    let root = early.calc_root_hash(hasher).unwrap();
+    let proof = early.prove_inclusion(&key, hasher).unwrap().unwrap();
+    proof.verify(hasher, root).unwrap();
+    assert_eq!(proof.leaf.value, stamped(2, 10, 30));
+}
@@ -0,0 +1,135 @@
+mod utils;
+
+use std::{cell::Cell, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, DatabaseGet, DatabaseSet},
+    Branch, DigestHasher, KeyHash, Leaf, Node, NodeHash, Transaction, TrieAccumulator, TrieError,
+    TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+/// A `MemoryDb` counting its writes.
+struct CountingDb {
+    db: MemoryDb<u64>,
+    writes: Cell<usize>,
+}
+
+impl DatabaseGet<u64> for CountingDb {
+    type GetError = TrieError;
+
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<u64>>, TrieError> {
+        Ok(self.db.get(hash)?)
+    }
+}
+
+impl DatabaseSet<u64> for CountingDb {
+    type SetError = TrieError;
+
+    fn set(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<u64>>,
+    ) -> Result<(), TrieError> {
+        self.writes.set(self.writes.get() + 1);
+        Ok(self.db.set(hash, node)?)
+    }
+}
+
+fn sorted(mut entries: Vec<(KeyHash, u64)>) -> Vec<(KeyHash, u64)> {
+    // Stable, so the pushes of a key stay in order and the last one wins.
+    entries.sort_by(|(a, _), (b, _)| a.cmp_trie_order(b));
+    entries
+}
+
+/// Push `entries` one at a time, checking the rolling root against a `Transaction` after each,
+/// then check the finished trie reads back every key.
+fn check_against_transaction(entries: &[(KeyHash, u64)]) -> Result<(), TestCaseError> {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = CountingDb {
+        db: MemoryDb::empty(),
+        writes: Cell::new(0),
+    };
+    let mut acc = TrieAccumulator::new(&db);
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::<u64>::empty()),
+        TrieRoot::Empty,
+    ));
+    prop_assert_eq!(acc.root(hasher), TrieRoot::Empty);
+
+    for (key, value) in entries {
+        acc.push(hasher, *key, *value).unwrap();
+        txn.insert(key, *value).unwrap();
+
+        // Hashing the rolling root writes nothing.
+        let writes = db.writes.get();
+        prop_assert_eq!(acc.root(hasher), txn.calc_root_hash(hasher).unwrap());
+        prop_assert_eq!(db.writes.get(), writes);
+    }
+
+    // Every pushed leaf is already written.
+    prop_assert!(db.writes.get() >= entries.len());
+
+    let len = acc.len();
+    let root = acc.finish(hasher).unwrap();
+    prop_assert_eq!(root, txn.calc_root_hash(hasher).unwrap());
+
+    let built = Transaction::from_snapshot_builder(SnapshotBuilder::new(&db, root));
+    for (key, _) in entries {
+        prop_assert_eq!(built.get(key).unwrap(), txn.get(key).unwrap());
+    }
+    let distinct = entries
+        .windows(2)
+        .filter(|pair| pair[0].0 != pair[1].0)
+        .count() as u64
+        + !entries.is_empty() as u64;
+    prop_assert_eq!(len, distinct);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn prop_accumulator_matches_transaction(
+        entries in prop::collection::vec((arb_key_hash(), any::<u64>()), 0..64),
+    ) {
+        check_against_transaction(&sorted(entries))?;
+    }
+
+    #[test]
+    fn prop_accumulator_matches_transaction_structured_keys(
+        entries in prop::collection::vec((arb_structured_key_hash(), 0u64..4), 0..64),
+    ) {
+        check_against_transaction(&sorted(entries))?;
+    }
+}
+
+#[test]
+fn accumulator_rejects_out_of_order_keys() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = MemoryDb::<u64>::empty();
+    let mut acc = TrieAccumulator::new(&db);
+
+    let keys = sorted((0..3).map(|i| (KeyHash::from_u64(i), i)).collect());
+    acc.push(hasher, keys[0].0, 0).unwrap();
+    acc.push(hasher, keys[2].0, 2).unwrap();
+    let root = acc.root(hasher);
+
+    let err = acc.push(hasher, keys[1].0, 1).unwrap_err();
+    assert!(err.to_string().contains("trie order"), "{err}");
+    assert_eq!(acc.len(), 2);
+    assert_eq!(acc.last_key(), Some(&keys[2].0));
+    assert_eq!(acc.root(hasher), root);
+
+    // The last key can be pushed again, replacing its value.
+    acc.push(hasher, keys[2].0, 20).unwrap();
+    assert_eq!(acc.len(), 2);
+    assert_ne!(acc.root(hasher), root);
+    let root = acc.finish(hasher).unwrap();
+
+    let built = Transaction::from_snapshot_builder(SnapshotBuilder::new(&db, root));
+    assert_eq!(built.get(&keys[2].0).unwrap(), Some(&20));
+    assert_eq!(built.get(&keys[1].0).unwrap(), None);
+}
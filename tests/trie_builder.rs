@@ -0,0 +1,163 @@
+mod utils;
+
+use std::{num::NonZeroUsize, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, FileRuns, KeyHash, MemoryRuns, RunStorage, Transaction, TrieBuilder, TrieRoot,
+};
+use sha2::Sha256;
+use utils::{arb_key_hash, arb_structured_key_hash};
+
+/// A `RunStorage` that fails to spill more than `capacity` runs.
+struct LimitedRuns {
+    capacity: usize,
+}
+
+impl RunStorage<u64> for LimitedRuns {
+    type Error = String;
+    type Run = std::iter::Map<
+        std::vec::IntoIter<(KeyHash, u64)>,
+        fn((KeyHash, u64)) -> Result<(KeyHash, u64), String>,
+    >;
+
+    fn spill(&mut self, run: Vec<(KeyHash, u64)>) -> Result<Self::Run, String> {
+        if self.capacity == 0 {
+            return Err("out of disk".to_owned());
+        }
+        self.capacity -= 1;
+        Ok(run.into_iter().map(Ok))
+    }
+}
+
+/// A `RunStorage` that reads each run back in reverse, as a corrupt temporary file might.
+struct ReversedRuns;
+
+impl RunStorage<u64> for ReversedRuns {
+    type Error = String;
+    type Run = std::iter::Map<
+        std::iter::Rev<std::vec::IntoIter<(KeyHash, u64)>>,
+        fn((KeyHash, u64)) -> Result<(KeyHash, u64), String>,
+    >;
+
+    fn spill(&mut self, run: Vec<(KeyHash, u64)>) -> Result<Self::Run, String> {
+        Ok(run.into_iter().rev().map(Ok))
+    }
+}
+
+fn run_len(len: usize) -> NonZeroUsize {
+    NonZeroUsize::new(len).unwrap()
+}
+
+/// Build `entries` with both a `TrieBuilder` spilling every `run_len` entries to `storage` and a `Transaction`,
+/// checking the roots agree and the built trie reads back the last value of every key.
+fn check_against_transaction(
+    storage: impl RunStorage<u64>,
+    entries: &[(KeyHash, u64)],
+    run_len: NonZeroUsize,
+) -> Result<(), TestCaseError> {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let db = MemoryDb::<u64>::empty();
+    let mut builder = TrieBuilder::new(storage, run_len);
+    builder.extend(entries.iter().copied()).unwrap();
+    prop_assert_eq!(
+        builder.spilled_runs(),
+        entries.len().saturating_sub(1) / run_len.get()
+    );
+    let root = builder.build(hasher, &db).unwrap();
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::<u64>::empty()),
+        TrieRoot::Empty,
+    ));
+    for (key, value) in entries {
+        txn.insert(key, *value).unwrap();
+    }
+    prop_assert_eq!(root, txn.calc_root_hash(hasher).unwrap());
+
+    let built = Transaction::from_snapshot_builder(SnapshotBuilder::new(&db, root));
+    for (key, _) in entries {
+        prop_assert_eq!(built.get(key).unwrap(), txn.get(key).unwrap());
+    }
+    Ok(())
+}
+
+#[test]
+fn empty_builder_builds_an_empty_trie() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let builder = TrieBuilder::<_, u64>::new(MemoryRuns, run_len(4));
+    assert_eq!(
+        builder.build(hasher, &MemoryDb::<u64>::empty()).unwrap(),
+        TrieRoot::Empty
+    );
+}
+
+#[test]
+fn spill_errors_are_reported() {
+    let mut builder = TrieBuilder::new(LimitedRuns { capacity: 2 }, run_len(10));
+    let err = builder
+        .extend((0..100).map(|i| (KeyHash::from_u64(i), i)))
+        .unwrap_err();
+    assert!(format!("{err}").contains("out of disk"));
+    assert_eq!(builder.spilled_runs(), 2);
+}
+
+#[test]
+fn unsorted_runs_are_errors() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut builder = TrieBuilder::new(ReversedRuns, run_len(10));
+    builder
+        .extend((0..100).map(|i| (KeyHash::from_u64(i), i)))
+        .unwrap();
+    let err = builder
+        .build(hasher, &MemoryDb::<u64>::empty())
+        .unwrap_err();
+    assert!(err.to_string().contains("trie order"), "{err}");
+}
+
+#[test]
+fn later_runs_override_earlier_ones() {
+    let entries: Vec<_> = (0..1000).map(|i| (KeyHash::from_u64(i % 300), i)).collect();
+    check_against_transaction(MemoryRuns, &entries, run_len(64)).unwrap();
+}
+
+#[test]
+fn file_runs_are_read_back_and_deleted() {
+    let dir = std::env::temp_dir().join(format!("kairos-trie-builder-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let entries: Vec<_> = (0..1000).map(|i| (KeyHash::from_u64(i % 300), i)).collect();
+    check_against_transaction(FileRuns::new(&dir, 8), &entries, run_len(64)).unwrap();
+
+    // Values are limited to `max_value_bytes`.
+    let mut builder = TrieBuilder::new(FileRuns::new(&dir, 4), run_len(1));
+    let err = builder
+        .extend((0..2).map(|i| (KeyHash::from_u64(i), i)))
+        .unwrap_err();
+    assert!(err.to_string().contains("run limit"), "{err}");
+
+    drop(builder);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+proptest! {
+    #[test]
+    fn matches_transaction(
+        entries in prop::collection::vec((arb_key_hash(), any::<u64>()), 0..300),
+        len in 1..50usize,
+    ) {
+        check_against_transaction(MemoryRuns, &entries, run_len(len))?;
+    }
+
+    #[test]
+    fn matches_transaction_structured_keys(
+        entries in prop::collection::vec((arb_structured_key_hash(), any::<u64>()), 0..300),
+        len in 1..50usize,
+    ) {
+        check_against_transaction(MemoryRuns, &entries, run_len(len))?;
+    }
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "test-utils")]
+
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+};
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    testing::{assert_trie_equals, diff_trie, TrieMismatch},
+    DigestHasher, KeyHash, NodeHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+
+fn commit(db: &Rc<MemoryDb<u64>>, entries: &HashMap<KeyHash, u64>) -> TrieRoot<NodeHash> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for (key, value) in entries {
+        txn.insert(key, *value).unwrap();
+    }
+    txn.commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root
+}
+
+#[test]
+fn diff_trie_reports_each_mismatch() {
+    let db = Rc::new(MemoryDb::empty());
+    let entries: HashMap<_, _> = (0..64).map(|i| (KeyHash::from_u64(i), i)).collect();
+    let root = commit(&db, &entries);
+
+    let builder = SnapshotBuilder::new(db.clone(), root);
+    assert_trie_equals(&builder, TrieRoot::Node(0), &entries);
+
+    let snapshot = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root))
+        .build_initial_snapshot();
+    let mut expected = entries.clone();
+    expected.remove(&KeyHash::from_u64(3));
+    expected.insert(KeyHash::from_u64(5), 500);
+    expected.insert(KeyHash::from_u64(100), 100);
+
+    // Without reading any key, the snapshot only holds the root's hash.
+    assert!(diff_trie(&snapshot, snapshot.root_node_idx().unwrap(), &expected).is_err());
+
+    let mismatches = diff_trie(&builder, TrieRoot::Node(0), &expected).unwrap();
+    let mut want = vec![
+        TrieMismatch::Extra {
+            key: KeyHash::from_u64(3),
+            actual: 3,
+        },
+        TrieMismatch::Different {
+            key: KeyHash::from_u64(5),
+            expected: 500,
+            actual: 5,
+        },
+        TrieMismatch::Missing {
+            key: KeyHash::from_u64(100),
+            expected: 100,
+        },
+    ];
+    want.sort_by(|a, b| a.key().cmp(b.key()));
+    assert_eq!(mismatches, want);
+
+    let message = *panic::catch_unwind(AssertUnwindSafe(|| {
+        assert_trie_equals(&builder, TrieRoot::Node(0), &expected);
+    }))
+    .unwrap_err()
+    .downcast::<String>()
+    .unwrap();
+    assert!(message.contains("in 3 places"), "{message}");
+    assert!(message.contains("expected 500"), "{message}");
+}
+
+#[test]
+fn empty_trie_equals_empty_map() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let builder = SnapshotBuilder::new(db, TrieRoot::Empty);
+    assert_trie_equals(&builder, TrieRoot::Empty, &HashMap::new());
+
+    let expected = HashMap::from([(KeyHash::from_u64(1), 1)]);
+    assert_eq!(
+        diff_trie(&builder, TrieRoot::Empty, &expected).unwrap(),
+        vec![TrieMismatch::Missing {
+            key: KeyHash::from_u64(1),
+            expected: 1
+        }]
+    );
+}
@@ -0,0 +1,56 @@
+use std::rc::Rc;
+
+use kairos_trie::prelude::*;
+use sha2::Sha256;
+
+#[test]
+fn open_transact_commit() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut trie = Trie::open(Rc::new(MemoryDb::<u64>::empty()), TrieRoot::Empty);
+    assert_eq!(trie.get(&KeyHash::from_u64(1)).unwrap(), None);
+
+    let mut txn = trie.transaction();
+    for i in 0..10 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let (receipt, snapshot) = trie.commit(&txn, hasher).unwrap();
+    assert_eq!(receipt.leaves_inserted, 10);
+    assert_eq!(trie.root(), receipt.root);
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), TrieRoot::Empty);
+    assert_eq!(trie.get(&KeyHash::from_u64(3)).unwrap(), Some(3));
+
+    // The witness replays the next transaction from the previous root.
+    let old_root = trie.root();
+    let mut txn = trie.transaction();
+    txn.insert(&KeyHash::from_u64(3), 30).unwrap();
+    assert_eq!(txn.remove(&KeyHash::from_u64(4)).unwrap(), Some(4));
+    let (receipt, snapshot) = trie.commit(&txn, hasher).unwrap();
+
+    let mut replay = Transaction::from_snapshot(&snapshot).unwrap();
+    assert_eq!(replay.calc_root_hash(hasher).unwrap(), old_root);
+    replay.insert(&KeyHash::from_u64(3), 30).unwrap();
+    replay.remove(&KeyHash::from_u64(4)).unwrap();
+    assert_eq!(replay.calc_root_hash(hasher).unwrap(), receipt.root);
+
+    // Reopening the database at the committed root sees the same trie.
+    let reopened = Trie::<_, u64>::open(trie.db().clone(), trie.root());
+    assert_eq!(reopened.get(&KeyHash::from_u64(3)).unwrap(), Some(30));
+    assert_eq!(reopened.get(&KeyHash::from_u64(4)).unwrap(), None);
+}
+
+#[test]
+fn commit_rejects_stale_transactions() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut trie = Trie::open(Rc::new(MemoryDb::<u64>::empty()), TrieRoot::Empty);
+
+    let mut first = trie.transaction();
+    let mut second = trie.transaction();
+    first.insert(&KeyHash::from_u64(1), 1).unwrap();
+    second.insert(&KeyHash::from_u64(2), 2).unwrap();
+
+    trie.commit(&first, hasher).unwrap();
+    let root = trie.root();
+    assert!(trie.commit(&second, hasher).is_err());
+    assert_eq!(trie.root(), root);
+    assert_eq!(trie.get(&KeyHash::from_u64(2)).unwrap(), None);
+}
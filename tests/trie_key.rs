@@ -0,0 +1,89 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, Transaction, TrieKey, TrieRoot,
+};
+use sha2::Sha256;
+
+/// A 20 byte address used as a key directly, without hashing it into a `KeyHash`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct Address([u32; 5]);
+
+impl TrieKey for Address {
+    fn words(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+prop_compose! {
+    /// Addresses sharing long runs of words, so branches need prefixes.
+    fn arb_address()(words in prop::array::uniform5(prop_oneof![4 => 0u32..4, 1 => any::<u32>()])) -> Address {
+        Address(words)
+    }
+}
+
+proptest! {
+    #[test]
+    fn prop_address_keys_match_btree_map(
+        ops in prop::collection::vec((arb_address(), any::<bool>(), any::<u64>()), 1..200),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64, Address>::empty());
+        let mut expected = BTreeMap::new();
+
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for &(address, remove, value) in ops.iter() {
+            if remove {
+                prop_assert_eq!(txn.remove(&address).unwrap(), expected.remove(&address));
+            } else {
+                txn.insert(&address, value).unwrap();
+                expected.insert(address, value);
+            }
+        }
+        for (address, value) in expected.iter() {
+            prop_assert_eq!(txn.get(address).unwrap(), Some(value));
+        }
+        let root = txn.commit(hasher).unwrap().root;
+
+        // Replay reads and an update against a fresh builder, then verify them from the snapshot.
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for &(address, _, _) in ops.iter() {
+            prop_assert_eq!(txn.get(&address).unwrap(), expected.get(&address));
+        }
+        for &(address, _, value) in ops.iter() {
+            txn.insert(&address, value.wrapping_add(1)).unwrap();
+        }
+        let new_root = txn.calc_root_hash(hasher).unwrap();
+        let snapshot = txn.build_initial_snapshot();
+
+        prop_assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+        let mut replay = Transaction::from_snapshot(&snapshot).unwrap();
+        for &(address, _, _) in ops.iter() {
+            prop_assert_eq!(replay.get(&address).unwrap(), expected.get(&address));
+        }
+        for &(address, _, value) in ops.iter() {
+            replay.insert(&address, value.wrapping_add(1)).unwrap();
+        }
+        prop_assert_eq!(replay.calc_root_hash(hasher).unwrap(), new_root);
+    }
+}
+
+#[test]
+fn range_get_over_address_keys() {
+    let db = Rc::new(MemoryDb::<u64, Address>::empty());
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, TrieRoot::Empty));
+
+    let mut addresses: Vec<Address> = (0..16u32).map(|i| Address([i, 0, 0, 0, 7])).collect();
+    for (i, address) in addresses.iter().enumerate() {
+        txn.insert(address, i as u64).unwrap();
+    }
+
+    addresses.sort_by(TrieKey::cmp_trie_order);
+    let range = txn.range_get(addresses[3]..=addresses[10]).unwrap();
+    let keys: Vec<Address> = range.iter().map(|(address, _)| *address).collect();
+    assert_eq!(keys, addresses[3..=10]);
+}
@@ -0,0 +1,115 @@
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, LogLenProof, Transaction, TrieLog, TrieRoot,
+};
+use sha2::Sha256;
+
+fn empty_log() -> TrieLog<SnapshotBuilder<Rc<MemoryDb<u64>>, u64>, u64> {
+    TrieLog::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::empty()),
+        TrieRoot::Empty,
+    )))
+}
+
+proptest! {
+    #[test]
+    fn prop_log_index_round_trips(index in any::<u64>()) {
+        let key_hash = KeyHash::from_log_index(index);
+        prop_assert_eq!(key_hash.to_log_index(), Some(index));
+        prop_assert!(key_hash.cmp_trie_order(&KeyHash::from_log_index(index.wrapping_add(1))).is_lt() || index == u64::MAX);
+    }
+
+    #[test]
+    fn prop_log_proves_entries_and_len(len in 0..300u64) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let mut log = empty_log();
+        for i in 0..len {
+            prop_assert_eq!(log.append(i * 10).unwrap(), KeyHash::from_log_index(i));
+        }
+        prop_assert_eq!(log.len().unwrap(), len);
+        let root = log.commit(hasher).unwrap();
+
+        prop_assert_eq!(log.prove_len(hasher).unwrap().verify(hasher, root).unwrap(), len);
+
+        // Paths stay within ceil(log2(len)) branches.
+        let max_depth = len.next_power_of_two().trailing_zeros() as usize;
+        for i in 0..len {
+            prop_assert_eq!(log.get(i).unwrap(), Some(&(i * 10)));
+            let proof = log.prove_get(i, hasher).unwrap().unwrap();
+            proof.verify(hasher, root).unwrap();
+            prop_assert_eq!(proof.leaf.key_hash, KeyHash::from_log_index(i));
+            prop_assert!(proof.path.len() <= max_depth);
+
+            // Only the last entry proves the length.
+            if i + 1 < len {
+                let forged = LogLenProof { last: Some(proof) };
+                prop_assert!(forged.verify(hasher, root).is_err());
+            }
+        }
+        prop_assert_eq!(log.get(len).unwrap(), None);
+    }
+}
+
+#[test]
+fn newest_entries_past_a_power_of_two_are_shallow() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut log = empty_log();
+    for i in 0..=64 {
+        log.append(i).unwrap();
+    }
+
+    let newest = log.prove_get(64, hasher).unwrap().unwrap();
+    assert_eq!(newest.path.len(), 1);
+    let oldest = log.prove_get(0, hasher).unwrap().unwrap();
+    assert_eq!(oldest.path.len(), 7);
+}
+
+#[test]
+fn appends_replay_against_the_witness() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let log = TrieLog::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        db.clone(),
+        TrieRoot::Empty,
+    )));
+    assert!(log.is_empty().unwrap());
+    assert_eq!(
+        log.prove_len(hasher)
+            .unwrap()
+            .verify(hasher, TrieRoot::Empty)
+            .unwrap(),
+        0
+    );
+
+    let mut log = log;
+    for i in 0..40 {
+        log.append(i).unwrap();
+    }
+    let root = log.commit(hasher).unwrap();
+
+    let mut log = TrieLog::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        db, root,
+    )));
+    log.append(40).unwrap();
+    log.append(41).unwrap();
+    let new_root = log.commit(hasher).unwrap();
+    let snapshot = log.transaction().build_initial_snapshot();
+
+    let mut replay = TrieLog::new(Transaction::from_snapshot(&snapshot).unwrap());
+    assert_eq!(replay.len().unwrap(), 40);
+    replay.append(40).unwrap();
+    replay.append(41).unwrap();
+    assert_eq!(replay.calc_root_hash(hasher).unwrap(), new_root);
+    assert_eq!(
+        replay
+            .prove_len(hasher)
+            .unwrap()
+            .verify(hasher, new_root)
+            .unwrap(),
+        42
+    );
+}
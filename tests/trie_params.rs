@@ -0,0 +1,87 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Leaf, NodeHash, PortableHasher, PortableUpdate, TaggedHasher,
+    Transaction, TrieParams, TrieRoot,
+};
+use sha2::Sha256;
+
+struct Untagged;
+
+impl TrieParams for Untagged {
+    const LEAF_TAG: &'static [u8] = b"";
+    const BRANCH_TAG: &'static [u8] = b"";
+}
+
+struct AppA;
+
+impl TrieParams for AppA {
+    const LEAF_TAG: &'static [u8] = b"app-a/leaf";
+    const BRANCH_TAG: &'static [u8] = b"app-a/branch";
+}
+
+struct AppB;
+
+impl TrieParams for AppB {
+    const LEAF_TAG: &'static [u8] = b"app-b/leaf";
+    const BRANCH_TAG: &'static [u8] = b"app-b/branch";
+}
+
+/// Commit 32 keys with `H`, then check a snapshot of a few reads replays to the same roots.
+fn root_with<H: PortableHasher<32>>() -> TrieRoot<NodeHash> {
+    let hasher = &mut H::default();
+    let db = Rc::new(MemoryDb::<[u8; 8]>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..32 {
+        txn.insert(&KeyHash::from_u64(i), i.to_le_bytes()).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&KeyHash::from_u64(3)).unwrap();
+    txn.insert(&KeyHash::from_u64(100), [1; 8]).unwrap();
+    let new_root = txn.calc_root_hash(hasher).unwrap();
+
+    let snapshot = txn.build_initial_snapshot();
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+    let flat = snapshot.to_fixed_flat();
+    assert_eq!(flat.as_flat().calc_root_hash(hasher).unwrap(), root);
+
+    let mut replay = Transaction::from_snapshot(&snapshot).unwrap();
+    replay.insert(&KeyHash::from_u64(100), [1; 8]).unwrap();
+    assert_eq!(replay.calc_root_hash(hasher).unwrap(), new_root);
+
+    root
+}
+
+#[test]
+fn tags_separate_tries() {
+    let untagged = root_with::<DigestHasher<Sha256>>();
+    assert_eq!(
+        root_with::<TaggedHasher<DigestHasher<Sha256>, Untagged>>(),
+        untagged
+    );
+
+    let a = root_with::<TaggedHasher<DigestHasher<Sha256>, AppA>>();
+    let b = root_with::<TaggedHasher<DigestHasher<Sha256>, AppB>>();
+    assert_ne!(a, untagged);
+    assert_ne!(a, b);
+}
+
+#[test]
+fn leaf_tag_is_hashed_first() {
+    let leaf = Leaf {
+        key_hash: KeyHash::from_u64(7),
+        value: 9u64,
+    };
+    let hash = leaf.hash_leaf(&mut TaggedHasher::<DigestHasher<Sha256>, AppA>::default());
+
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    hasher.portable_update(AppA::LEAF_TAG);
+    hasher.portable_update(KeyHash::from_u64(7).to_bytes());
+    hasher.portable_update(9u64.to_le_bytes());
+    assert_eq!(hash, NodeHash::new(hasher.finalize_reset()));
+}
@@ -0,0 +1,99 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, TrieRoot, TrieSet,
+};
+use sha2::Sha256;
+
+#[test]
+fn set_operations() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<()>::empty());
+    let mut set = TrieSet::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        db.clone(),
+        TrieRoot::Empty,
+    )));
+
+    for i in 0..50 {
+        assert!(set.insert(&KeyHash::from_u64(i)).unwrap());
+    }
+    assert!(!set.insert(&KeyHash::from_u64(7)).unwrap());
+    assert!(set.contains(&KeyHash::from_u64(7)).unwrap());
+    assert!(!set.contains(&KeyHash::from_u64(50)).unwrap());
+
+    assert!(set.remove(&KeyHash::from_u64(7)).unwrap());
+    assert!(!set.remove(&KeyHash::from_u64(7)).unwrap());
+    assert!(!set.contains(&KeyHash::from_u64(7)).unwrap());
+
+    let root = set.commit(hasher).unwrap();
+    let set = TrieSet::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        db, root,
+    )));
+    assert!(set.contains(&KeyHash::from_u64(8)).unwrap());
+    assert!(!set.contains(&KeyHash::from_u64(7)).unwrap());
+}
+
+#[test]
+fn set_proofs() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<()>::empty());
+    let mut set = TrieSet::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        db.clone(),
+        TrieRoot::Empty,
+    )));
+    for i in 0..50 {
+        set.insert(&KeyHash::from_u64(i)).unwrap();
+    }
+    let root = set.commit(hasher).unwrap();
+
+    let mut set = TrieSet::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        db, root,
+    )));
+    let proof = set
+        .prove_contains(&KeyHash::from_u64(3), hasher)
+        .unwrap()
+        .unwrap();
+    proof.verify(hasher, root).unwrap();
+    assert_eq!(proof.leaf.key_hash, KeyHash::from_u64(3));
+    assert!(set
+        .prove_contains(&KeyHash::from_u64(60), hasher)
+        .unwrap()
+        .is_none());
+
+    let proof = set
+        .remove_with_proof(&KeyHash::from_u64(3), hasher)
+        .unwrap()
+        .unwrap();
+    let new_root = set.calc_root_hash(hasher).unwrap();
+    proof.verify(hasher, root, new_root).unwrap();
+}
+
+#[test]
+fn set_snapshot_replays() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<()>::empty());
+    let mut set = TrieSet::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        db.clone(),
+        TrieRoot::Empty,
+    )));
+    for i in 0..50 {
+        set.insert(&KeyHash::from_u64(i)).unwrap();
+    }
+    let root = set.commit(hasher).unwrap();
+
+    let mut set = TrieSet::new(Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        db, root,
+    )));
+    set.insert(&KeyHash::from_u64(100)).unwrap();
+    set.remove(&KeyHash::from_u64(10)).unwrap();
+    let new_root = set.calc_root_hash(hasher).unwrap();
+
+    let snapshot = set.transaction().build_initial_snapshot();
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+
+    let mut replay = TrieSet::new(Transaction::from_snapshot(&snapshot).unwrap());
+    assert!(replay.insert(&KeyHash::from_u64(100)).unwrap());
+    assert!(replay.remove(&KeyHash::from_u64(10)).unwrap());
+    assert_eq!(replay.calc_root_hash(hasher).unwrap(), new_root);
+}
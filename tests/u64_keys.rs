@@ -0,0 +1,91 @@
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, Idx},
+    walk, Branch, DigestHasher, KeyHash, Leaf, Transaction, TrieError, TrieRoot, VisitControl,
+    Visitor,
+};
+use sha2::Sha256;
+
+/// Records the deepest leaf, counting the branches above it.
+#[derive(Default)]
+struct MaxDepth {
+    depth: usize,
+    max: usize,
+}
+
+impl Visitor<u64> for MaxDepth {
+    fn pre_branch(&mut self, _: Idx, _: &Branch<Idx>) -> Result<VisitControl, TrieError> {
+        self.depth += 1;
+        Ok(VisitControl::Continue)
+    }
+
+    fn post_branch(&mut self, _: Idx, _: &Branch<Idx>) -> Result<(), TrieError> {
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn leaf(&mut self, _: Idx, leaf: &Leaf<u64>) -> Result<(), TrieError> {
+        assert_eq!(leaf.key_hash.to_u64(), Some(leaf.value));
+        self.max = self.max.max(self.depth);
+        Ok(())
+    }
+}
+
+/// Insert the `from_u64` keys of `indexes`, check they read back, and return the trie's depth.
+fn depth_of(indexes: impl IntoIterator<Item = u64>) -> usize {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let indexes: Vec<u64> = indexes.into_iter().collect();
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for &index in indexes.iter() {
+        txn.insert(&KeyHash::from_u64(index), index).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for &index in indexes.iter() {
+        assert_eq!(txn.get(&KeyHash::from_u64(index)).unwrap(), Some(&index));
+    }
+
+    let mut depth = MaxDepth::default();
+    walk(&txn.data_store, TrieRoot::Node(0), &mut depth).unwrap();
+    depth.max
+}
+
+#[test]
+fn dense_ranges_are_balanced() {
+    assert_eq!(depth_of(0..1), 0);
+    assert_eq!(depth_of(0..2), 1);
+    assert_eq!(depth_of(0..1024), 10);
+    assert_eq!(depth_of(0..1000), 10);
+    assert_eq!(depth_of(1 << 40..(1 << 40) + 4096), 12);
+}
+
+#[test]
+fn adversarial_keys_are_bounded_by_the_key_width() {
+    let indexes = std::iter::once(0).chain((0..64).map(|i| 1 << i));
+    assert_eq!(depth_of(indexes), 64);
+}
+
+#[test]
+fn u64_round_trip() {
+    for index in [0, 1, u32::MAX as u64, 1 << 32, u64::MAX] {
+        assert_eq!(KeyHash::from_u64(index).to_u64(), Some(index));
+    }
+    assert_eq!(KeyHash([0, 0, 1, 0, 0, 0, 0, 0]).to_u64(), None);
+}
+
+proptest! {
+    #[test]
+    fn prop_u64_keys_depth_is_bounded(indexes in prop::collection::btree_set(any::<u64>(), 1..500)) {
+        let len = indexes.len();
+        let depth = depth_of(indexes);
+        prop_assert!(depth <= 64);
+        prop_assert!(depth < len);
+    }
+}
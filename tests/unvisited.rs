@@ -0,0 +1,106 @@
+mod utils;
+
+use std::{collections::BTreeSet, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, Store},
+    DigestHasher, KeyHash, Transaction, TrieRoot,
+};
+use sha2::Sha256;
+use utils::arb_structured_key_hash;
+
+proptest! {
+    #[test]
+    fn prop_unvisited_ranges_cover_the_unread_keys(
+        keys in prop::collection::btree_set(arb_structured_key_hash(), 1..100),
+        reads in prop::collection::vec(any::<prop::sample::Index>(), 0..10),
+    ) {
+        let db = Rc::new(MemoryDb::<u64>::empty());
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for key in keys.iter() {
+            txn.insert(key, 0).unwrap();
+        }
+        let root = txn.commit(&mut DigestHasher::<Sha256>::default()).unwrap().root;
+
+        let keys: Vec<KeyHash> = keys.into_iter().collect();
+        let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for idx in reads.iter() {
+            txn.get(&keys[idx.index(keys.len())]).unwrap();
+        }
+        let snapshot = txn.build_initial_snapshot();
+        let unvisited = snapshot.unvisited().unwrap();
+
+        for node in unvisited.iter() {
+            prop_assert_eq!(snapshot.get_unvisited_hash(node.idx).unwrap(), Some(node.hash));
+        }
+        // In trie order, without overlapping.
+        for pair in unvisited.windows(2) {
+            prop_assert!(pair[0].key_range().end().cmp_trie_order(pair[1].key_range().start()).is_lt());
+        }
+
+        // A key is either readable from the snapshot or under exactly one unvisited node.
+        let replay = Transaction::from_snapshot(&snapshot).unwrap();
+        let mut covering_nodes = BTreeSet::new();
+        for key in keys.iter() {
+            let covering: Vec<_> = unvisited
+                .iter()
+                .filter(|node| {
+                    let range = node.key_range();
+                    range.start().cmp_trie_order(key).is_le() && key.cmp_trie_order(range.end()).is_le()
+                })
+                .collect();
+
+            if replay.get(key).is_ok() {
+                prop_assert!(covering.is_empty());
+            } else {
+                prop_assert_eq!(covering.len(), 1);
+                covering_nodes.insert(covering[0].idx);
+            }
+        }
+        // Every unvisited subtree holds a key.
+        prop_assert_eq!(covering_nodes.len(), unvisited.len());
+    }
+}
+
+#[test]
+fn unvisited_prefix_bits() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    // 0b00, 0b10, 0b01, 0b11: the root splits on bit 0, both children on bit 1.
+    for i in 0..4 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+
+    // An untouched trie is one unvisited node covering every key.
+    let txn = Transaction::<_, u64>::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    let unvisited = txn.build_initial_snapshot().unvisited().unwrap();
+    assert_eq!(unvisited.len(), 1);
+    assert_eq!(unvisited[0].len_bits, 0);
+    assert_eq!(
+        unvisited[0].key_range(),
+        KeyHash([0; 8])..=KeyHash([u32::MAX; 8])
+    );
+
+    // Reading key 0 leaves key 2, and the subtree of the keys ending in 1, unvisited.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    txn.get(&KeyHash::from_u64(0)).unwrap();
+    let unvisited = txn.build_initial_snapshot().unvisited().unwrap();
+    let prefixes: Vec<_> = unvisited
+        .iter()
+        .map(|node| (node.prefix.to_vec(), node.len_bits))
+        .collect();
+    assert_eq!(prefixes, [(vec![0b10], 2), (vec![0b1], 1)]);
+
+    assert_eq!(
+        unvisited[1].key_range(),
+        KeyHash([1, 0, 0, 0, 0, 0, 0, 0])..=KeyHash([u32::MAX; 8])
+    );
+}
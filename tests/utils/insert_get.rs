@@ -24,7 +24,10 @@ pub fn run_against_snapshot_builder<Db: 'static + DatabaseSet<[u8; 8]>>(
         txn.insert(key, value.to_le_bytes()).unwrap();
     }
 
-    let new_root_hash = txn.commit(&mut DigestHasher::<Sha256>::default()).unwrap();
+    let new_root_hash = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
     let snapshot = txn.build_initial_snapshot();
 
     (new_root_hash, snapshot)
@@ -9,3 +9,12 @@ prop_compose! {
         KeyHash::from(&data)
     }
 }
+
+prop_compose! {
+    /// Keys sharing long runs of words, which exercise branch prefixes much harder than uniform keys.
+    pub fn arb_structured_key_hash()(
+        words in prop::array::uniform8(prop_oneof![4 => 0u32..4, 1 => any::<u32>()])
+    ) -> KeyHash {
+        KeyHash(words)
+    }
+}
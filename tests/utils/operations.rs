@@ -17,9 +17,10 @@ use kairos_trie::{
 };
 use sha2::Sha256;
 
-use super::arb_key_hash;
+use super::{arb_key_hash, arb_structured_key_hash};
 
 pub type Value = [u8; 8];
+pub type Builder = SnapshotBuilder<Rc<MemoryDb<Value>>, Value>;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Operation {
@@ -29,6 +30,7 @@ pub enum Operation {
     EntryInsert(KeyHash, Value),
     EntryAndModifyOrInsert(KeyHash, Value),
     EntryOrInsert(KeyHash, Value),
+    Remove(KeyHash),
 }
 
 prop_compose! {
@@ -39,9 +41,16 @@ prop_compose! {
 
 prop_compose! {
     pub fn arb_operations(key_count: impl Into<SizeRange>, op_count: impl Into<SizeRange>)
-                         (keys in prop::collection::vec(arb_key_hash(), key_count),
+                         (ops in arb_operations_with_keys(arb_key_hash(), key_count, op_count)) -> Vec<Operation> {
+        ops
+    }
+}
+
+prop_compose! {
+    pub fn arb_operations_with_keys(key: impl Strategy<Value = KeyHash>, key_count: impl Into<SizeRange>, op_count: impl Into<SizeRange>)
+                         (keys in prop::collection::vec(key, key_count),
                           ops in prop::collection::vec(
-                              (0..5u8,
+                              (0..7u8,
                                any::<prop::sample::Index>(),
                                arb_value()
                               ),
@@ -57,6 +66,7 @@ prop_compose! {
             3 => Operation::EntryInsert(key, value),
             4 => Operation::EntryAndModifyOrInsert(key, value),
             5 => Operation::EntryOrInsert(key, value),
+            6 => Operation::Remove(key),
             _ => unreachable!(),
         }}).collect()
     }
@@ -65,7 +75,16 @@ prop_compose! {
 prop_compose! {
     pub fn arb_batches(key_count: impl Into<SizeRange>, op_count: impl Into<SizeRange>, max_batch_count: usize, max_batch_size: usize)
                       (
-                          ops in arb_operations(key_count, op_count),
+                          batches in arb_batches_with_keys(arb_key_hash(), key_count, op_count, max_batch_count, max_batch_size)
+                      ) -> Vec<Vec<Operation>> {
+                          batches
+    }
+}
+
+prop_compose! {
+    pub fn arb_batches_with_keys(key: impl Strategy<Value = KeyHash>, key_count: impl Into<SizeRange>, op_count: impl Into<SizeRange>, max_batch_count: usize, max_batch_size: usize)
+                      (
+                          ops in arb_operations_with_keys(key, key_count, op_count),
                           windows in prop::collection::vec(0..max_batch_size, max_batch_count - 1)
                       ) -> Vec<Vec<Operation>> {
                           arb_batches_inner(ops, windows)
@@ -101,8 +120,17 @@ pub fn run_against_snapshot_builder(
     db: Rc<MemoryDb<Value>>,
     hash_map: &mut HashMap<KeyHash, Value>,
 ) -> (TrieRoot<NodeHash>, Snapshot<Value>) {
-    let bump = bumpalo::Bump::new();
     let builder = SnapshotBuilder::empty(db).with_trie_root_hash(old_root_hash);
+    let (new_root_hash, snapshot, _) = run_against_builder(batch, builder, hash_map);
+    (new_root_hash, snapshot)
+}
+
+/// Like `run_against_snapshot_builder`, but hands the builder back so it can be reused.
+pub fn run_against_builder(
+    batch: &[Operation],
+    builder: Builder,
+    hash_map: &mut HashMap<KeyHash, Value>,
+) -> (TrieRoot<NodeHash>, Snapshot<Value>, Builder) {
     let mut txn = Transaction::from_snapshot_builder(builder);
 
     for op in batch {
@@ -112,9 +140,12 @@ pub fn run_against_snapshot_builder(
         assert_eq!(new, new_hm);
     }
 
-    let new_root_hash = txn.commit(&mut DigestHasher::<Sha256>::default()).unwrap();
+    let new_root_hash = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
     let snapshot = txn.build_initial_snapshot();
-    (new_root_hash, snapshot)
+    (new_root_hash, snapshot, txn.data_store)
 }
 
 /// Code like this would run in a zkVM
@@ -206,6 +237,7 @@ fn trie_op<S: Store<Value>>(
             let old = txn.entry(key).unwrap().get().copied();
             (old, old)
         }
+        Operation::Remove(key) => (txn.remove(key).unwrap(), None),
     }
 }
 
@@ -254,5 +286,6 @@ fn hashmap_op(op: &Operation, map: &mut HashMap<KeyHash, Value>) -> (Option<Valu
             let old = map.get(key).copied();
             (old, old)
         }
+        Operation::Remove(key) => (map.remove(key), None),
     }
 }
@@ -0,0 +1,90 @@
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    DigestHasher, KeyHash, Transaction, Transformed, TrieError, TrieRoot, ValueTransform,
+};
+use sha2::Sha256;
+
+/// A toy cipher, XORing each value with a pad derived from its key and a secret.
+/// The stored form carries a check byte, so decoding with the wrong secret fails.
+struct XorCipher {
+    secret: u64,
+}
+
+impl XorCipher {
+    fn pad(&self, key_hash: &KeyHash) -> u64 {
+        self.secret ^ key_hash.0[0] as u64
+    }
+}
+
+impl ValueTransform for XorCipher {
+    type Value = u64;
+    type Stored = [u8; 9];
+
+    fn encode(&self, key_hash: &KeyHash, value: &u64) -> Result<[u8; 9], TrieError> {
+        let mut stored = [0; 9];
+        stored[..8].copy_from_slice(&(value ^ self.pad(key_hash)).to_le_bytes());
+        stored[8] = self.secret as u8;
+        Ok(stored)
+    }
+
+    fn decode(&self, key_hash: &KeyHash, stored: &[u8; 9]) -> Result<u64, TrieError> {
+        if stored[8] != self.secret as u8 {
+            return Err("wrong secret".into());
+        }
+        let (bytes, _) = stored.split_first_chunk().unwrap();
+        Ok(u64::from_le_bytes(*bytes) ^ self.pad(key_hash))
+    }
+}
+
+#[test]
+fn values_are_hashed_as_stored() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let cipher = XorCipher {
+        secret: 0xdead_beef,
+    };
+    let db = Rc::new(MemoryDb::empty());
+
+    let mut trie = Transformed::new(
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty)),
+        &cipher,
+    );
+    for i in 0..50 {
+        trie.insert(&KeyHash::from_u64(i), &(i * 3)).unwrap();
+    }
+    assert_eq!(trie.remove(&KeyHash::from_u64(7)).unwrap(), Some(21));
+    let root = trie.commit(hasher).unwrap();
+
+    // The same trie, built from the encoded values by hand.
+    let mut plain = Transaction::from_snapshot_builder(SnapshotBuilder::new(
+        Rc::new(MemoryDb::empty()),
+        TrieRoot::Empty,
+    ));
+    for i in (0..50).filter(|&i| i != 7) {
+        let key = KeyHash::from_u64(i);
+        plain
+            .insert(&key, cipher.encode(&key, &(i * 3)).unwrap())
+            .unwrap();
+    }
+    assert_eq!(plain.calc_root_hash(hasher).unwrap(), root);
+
+    let trie = Transformed::new(
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root)),
+        &cipher,
+    );
+    let key = KeyHash::from_u64(10);
+    assert_eq!(trie.get(&key).unwrap(), Some(30));
+    assert_ne!(
+        trie.get_stored(&key).unwrap().unwrap()[..8],
+        30u64.to_le_bytes()
+    );
+    assert_eq!(trie.get(&KeyHash::from_u64(7)).unwrap(), None);
+
+    let wrong = Transformed::new(
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root)),
+        XorCipher { secret: 1 },
+    );
+    let err = wrong.get(&key).unwrap_err();
+    assert!(err.to_string().contains("wrong secret"), "{err}");
+}
@@ -0,0 +1,337 @@
+mod utils;
+
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+    },
+    verify_batch, verify_batch_truncated, AuditedBatch, DigestHasher, Journal, KeyHash, NodeHash,
+    Op, PortableHasher, PortableUpdate, SnapshotChain, Transaction, TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+use utils::arb_key_hash;
+
+fn prove(
+    db: Rc<MemoryDb<u64>>,
+    old_root: TrieRoot<NodeHash>,
+    ops: &[Op<u64>],
+) -> (TrieRoot<NodeHash>, Snapshot<u64>) {
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, old_root));
+
+    for op in ops {
+        op.apply(&mut txn).unwrap();
+    }
+
+    let new_root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+    (new_root, txn.build_initial_snapshot())
+}
+
+prop_compose! {
+    fn arb_ops()(ops in prop::collection::vec((arb_key_hash(), any::<u64>(), 0..3u8), 0..200)) -> Vec<Op<u64>> {
+        ops.into_iter()
+            .map(|(key, value, kind)| match kind {
+                0 => Op::Get(key),
+                1 => Op::Insert(key, value),
+                _ => Op::Remove(key),
+            })
+            .collect()
+    }
+}
+
+proptest! {
+    #[test]
+    fn prop_verify_batch(batches in prop::collection::vec(arb_ops(), 1..10)) {
+        let db = Rc::new(MemoryDb::empty());
+        let mut old_root = TrieRoot::Empty;
+
+        for ops in batches.iter() {
+            let (new_root, snapshot) = prove(db.clone(), old_root, ops);
+
+            verify_batch(old_root, new_root, &snapshot, ops, &mut DigestHasher::<Sha256>::default())
+                .unwrap();
+
+            old_root = new_root;
+        }
+    }
+}
+
+#[test]
+fn verify_batch_rejects_wrong_roots() {
+    let db = Rc::new(MemoryDb::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let first = [
+        Op::Insert(KeyHash([1; 8]), 1),
+        Op::Insert(KeyHash([2; 8]), 2),
+    ];
+    let (root_1, _) = prove(db.clone(), TrieRoot::Empty, &first);
+
+    let second = [Op::Insert(KeyHash([1; 8]), 10), Op::Get(KeyHash([3; 8]))];
+    let (root_2, snapshot) = prove(db, root_1, &second);
+
+    verify_batch(root_1, root_2, &snapshot, &second, hasher).unwrap();
+
+    assert!(matches!(
+        verify_batch(TrieRoot::Empty, root_2, &snapshot, &second, hasher),
+        Err(VerifyError::OldRootMismatch { .. })
+    ));
+
+    assert!(matches!(
+        verify_batch(root_1, root_1, &snapshot, &second, hasher),
+        Err(VerifyError::NewRootMismatch { .. })
+    ));
+
+    // Dropping an insert from the replay must not go unnoticed.
+    assert!(matches!(
+        verify_batch(root_1, root_2, &snapshot, &second[1..], hasher),
+        Err(VerifyError::NewRootMismatch { .. })
+    ));
+
+    // Touching a key outside the witness is an error, not a silent miss.
+    let extra = [Op::Insert(KeyHash([1; 8]), 10), Op::Get(KeyHash([2; 8]))];
+    assert!(matches!(
+        verify_batch(root_1, root_2, &snapshot, &extra, hasher),
+        Err(VerifyError::Trie(_))
+    ));
+}
+
+#[test]
+fn verify_batch_replays_removals() {
+    let db = Rc::new(MemoryDb::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let first = [
+        Op::Insert(KeyHash([1; 8]), 1),
+        Op::Insert(KeyHash([2; 8]), 2),
+    ];
+    let (root_1, _) = prove(db.clone(), TrieRoot::Empty, &first);
+    let second = [Op::Remove(KeyHash([1; 8])), Op::Get(KeyHash([2; 8]))];
+    let (root_2, snapshot) = prove(db, root_1, &second);
+    assert_ne!(root_1, root_2);
+
+    verify_batch(root_1, root_2, &snapshot, &second, hasher).unwrap();
+
+    // Dropping the removal from the replay must not go unnoticed.
+    assert!(matches!(
+        verify_batch(root_1, root_2, &snapshot, &second[1..], hasher),
+        Err(VerifyError::NewRootMismatch { .. })
+    ));
+}
+
+#[test]
+fn verify_batch_against_truncated_roots() {
+    let db = Rc::new(MemoryDb::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let first = [Op::Insert(KeyHash([1; 8]), 1)];
+    let (root_1, _) = prove(db.clone(), TrieRoot::Empty, &first);
+    let second = [Op::Insert(KeyHash([2; 8]), 2), Op::Get(KeyHash([1; 8]))];
+    let (root_2, snapshot) = prove(db, root_1, &second);
+
+    let TrieRoot::Node(hash_1) = root_1 else {
+        panic!("root_1 is not empty")
+    };
+    let commitment_1 = root_1.truncated::<20>();
+    assert_eq!(
+        commitment_1,
+        TrieRoot::Node(hash_1.bytes[..20].try_into().unwrap())
+    );
+    assert_eq!(
+        TrieRoot::<NodeHash>::Empty.truncated::<20>(),
+        TrieRoot::Empty
+    );
+    assert!(root_1.matches_truncated(&commitment_1));
+    assert!(!root_2.matches_truncated(&commitment_1));
+    assert!(!TrieRoot::Empty.matches_truncated(&commitment_1));
+
+    let commitment_2 = root_2.truncated::<20>();
+    verify_batch_truncated(commitment_1, commitment_2, &snapshot, &second, hasher).unwrap();
+
+    assert!(matches!(
+        verify_batch_truncated(commitment_2, commitment_2, &snapshot, &second, hasher),
+        Err(VerifyError::OldTruncatedRootMismatch { .. })
+    ));
+    assert!(matches!(
+        verify_batch_truncated(commitment_1, commitment_1, &snapshot, &second, hasher),
+        Err(VerifyError::NewTruncatedRootMismatch { .. })
+    ));
+}
+
+#[test]
+fn commit_audited_round_trip() {
+    let db = Rc::new(MemoryDb::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut old_root = TrieRoot::Empty;
+
+    for batch in 0..5u64 {
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+        let mut journal = Journal::new();
+
+        for i in 0..50u64 {
+            let key = KeyHash([(i * 7 + batch) as u32 % 64, i as u32 % 3, 0, 0, 0, 0, 0, 0]);
+            let read = journal.apply(&mut txn, Op::Get(key)).unwrap().copied();
+            journal
+                .apply(&mut txn, Op::Insert(key, read.unwrap_or(0) + i))
+                .unwrap();
+        }
+
+        let batch = txn.commit_audited(hasher, &journal).unwrap();
+        assert_eq!(batch.old_root, old_root);
+        assert_eq!(batch.ops, journal.into_ops());
+        batch.verify(hasher).unwrap();
+
+        old_root = batch.new_root;
+    }
+}
+
+fn audited_batches(count: u64) -> Vec<AuditedBatch<u64>> {
+    let db = Rc::new(MemoryDb::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let mut old_root = TrieRoot::Empty;
+
+    (0..count)
+        .map(|batch| {
+            let mut txn =
+                Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), old_root));
+            let mut journal = Journal::new();
+            for i in 0..20u64 {
+                let key = KeyHash([(i * 5 + batch) as u32 % 32, 0, 0, 0, 0, 0, 0, 0]);
+                journal.apply(&mut txn, Op::Get(key)).unwrap();
+                journal.apply(&mut txn, Op::Insert(key, batch)).unwrap();
+            }
+
+            let batch = txn.commit_audited(hasher, &journal).unwrap();
+            old_root = batch.new_root;
+            batch
+        })
+        .collect()
+}
+
+#[test]
+fn snapshot_chain_verifies_consecutive_batches() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let batches = audited_batches(4);
+
+    let mut chain = SnapshotChain::new();
+    assert!(chain.verify(hasher).is_err());
+
+    for batch in batches.iter().cloned() {
+        chain.push(batch).unwrap();
+    }
+    assert_eq!(
+        chain.verify(hasher).unwrap(),
+        (TrieRoot::Empty, batches[3].new_root)
+    );
+
+    // Skipping a batch breaks the chain.
+    let mut skipping = SnapshotChain::new();
+    skipping.push(batches[0].clone()).unwrap();
+    assert!(matches!(
+        skipping.push(batches[2].clone()),
+        Err(VerifyError::ChainBroken { batch_idx: 1, .. })
+    ));
+
+    // Every batch is verified, not just the links.
+    let mut tampered = chain.into_batches();
+    tampered[2].ops[1] = Op::Insert(*tampered[2].ops[1].key_hash(), 1000);
+    let mut chain = SnapshotChain::new();
+    for batch in tampered {
+        chain.push(batch).unwrap();
+    }
+    assert!(matches!(
+        chain.verify(hasher),
+        Err(VerifyError::NewRootMismatch { .. })
+    ));
+}
+
+#[test]
+fn commit_audited_rejects_unjournaled_writes() {
+    let db = Rc::new(MemoryDb::empty());
+    let hasher = &mut DigestHasher::<Sha256>::default();
+
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, TrieRoot::Empty));
+    let mut journal = Journal::new();
+
+    journal
+        .apply(&mut txn, Op::Insert(KeyHash([1; 8]), 1))
+        .unwrap();
+    txn.insert(&KeyHash([2; 8]), 2).unwrap();
+
+    assert!(matches!(
+        txn.commit_audited(hasher, &journal),
+        Err(VerifyError::NewRootMismatch { .. })
+    ));
+}
+
+#[cfg(feature = "subtle")]
+#[test]
+fn trie_root_ct_eq_matches_eq() {
+    let a = TrieRoot::Node(NodeHash::new([1; 32]));
+    let b = TrieRoot::Node(NodeHash::new([2; 32]));
+    let zero = TrieRoot::Node(NodeHash::new([0; 32]));
+
+    for x in [TrieRoot::Empty, a, b, zero] {
+        for y in [TrieRoot::Empty, a, b, zero] {
+            assert_eq!(bool::from(x.ct_eq(&y)), x == y);
+        }
+    }
+}
+
+/// Counts the hashes finalized by a `DigestHasher`.
+#[derive(Default)]
+struct CountingHasher {
+    hasher: DigestHasher<Sha256>,
+    hashes: usize,
+}
+
+impl PortableUpdate for CountingHasher {
+    fn portable_update(&mut self, data: impl AsRef<[u8]>) {
+        self.hasher.portable_update(data);
+    }
+}
+
+impl PortableHasher<32> for CountingHasher {
+    fn finalize_reset(&mut self) -> [u8; 32] {
+        self.hashes += 1;
+        self.hasher.finalize_reset()
+    }
+}
+
+#[test]
+fn verify_batch_reuses_hashes_of_unmodified_subtrees() {
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let keys: Vec<KeyHash> = (0..64).map(KeyHash::from_u64).collect();
+    let inserts: Vec<Op<u64>> = keys.iter().map(|key| Op::Insert(*key, 0)).collect();
+    let (old_root, _) = prove(db.clone(), TrieRoot::Empty, &inserts);
+
+    // Read every key, but only modify one.
+    let mut ops: Vec<Op<u64>> = keys.iter().map(|key| Op::Get(*key)).collect();
+    ops.push(Op::Insert(keys[0], 1));
+    let (new_root, snapshot) = prove(db, old_root, &ops);
+
+    // Checking the old root hashes the 64 leaves and 63 branches,
+    // the new root only rehashes the modified leaf and the 6 branches above it.
+    let hasher = &mut CountingHasher::default();
+    verify_batch(old_root, new_root, &snapshot, &ops, hasher).unwrap();
+    assert_eq!(hasher.hashes, 127 + 7);
+
+    // The snapshot keeps its hashes across verifications.
+    hasher.hashes = 0;
+    assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), old_root);
+    verify_batch(old_root, new_root, &snapshot, &ops, hasher).unwrap();
+    assert_eq!(hasher.hashes, 7);
+
+    // The cache does not affect equality.
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let unhashed = snapshot.clone();
+    assert_eq!(unhashed.calc_root_hash(hasher).unwrap(), old_root);
+    assert_eq!(unhashed, snapshot);
+}
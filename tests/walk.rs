@@ -0,0 +1,213 @@
+mod utils;
+
+use std::{collections::BTreeSet, ops::ControlFlow, rc::Rc};
+
+use proptest::prelude::*;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, DatabaseSet, Idx},
+    walk, walk_nodes, Branch, DigestHasher, KeyHash, Leaf, NodeHash, Transaction, TrieError,
+    TrieRoot, VisitControl, Visitor,
+};
+use sha2::Sha256;
+use utils::arb_key_hash;
+
+/// Records what `walk` reports, optionally pruning branches deeper than `max_depth`.
+#[derive(Default)]
+struct Recorder {
+    max_depth: Option<usize>,
+    depth: usize,
+    branches: usize,
+    leaves: Vec<KeyHash>,
+    unvisited: Vec<NodeHash>,
+}
+
+impl Visitor<u64> for Recorder {
+    fn pre_branch(&mut self, _: Idx, _: &Branch<Idx>) -> Result<VisitControl, TrieError> {
+        self.branches += 1;
+        self.depth += 1;
+        if self.max_depth.is_some_and(|max| self.depth > max) {
+            Ok(VisitControl::SkipSubtree)
+        } else {
+            Ok(VisitControl::Continue)
+        }
+    }
+
+    fn post_branch(&mut self, _: Idx, _: &Branch<Idx>) -> Result<(), TrieError> {
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn leaf(&mut self, _: Idx, leaf: &Leaf<u64>) -> Result<(), TrieError> {
+        assert_eq!(leaf.value, leaf.key_hash.0[0] as u64);
+        self.leaves.push(leaf.key_hash);
+        Ok(())
+    }
+
+    fn unvisited(&mut self, _: Idx, hash: &NodeHash) -> Result<(), TrieError> {
+        self.unvisited.push(*hash);
+        Ok(())
+    }
+}
+
+proptest! {
+    #[test]
+    fn prop_walk_snapshot(
+        keys in prop::collection::btree_set(arb_key_hash(), 1..200),
+        reads in prop::collection::vec(arb_key_hash(), 0..20),
+    ) {
+        let hasher = &mut DigestHasher::<Sha256>::default();
+        let db = Rc::new(MemoryDb::<u64>::empty());
+
+        let mut txn =
+            Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+        for key in keys.iter() {
+            txn.insert(key, key.0[0] as u64).unwrap();
+        }
+        let root = txn.commit(hasher).unwrap().root;
+
+        let mut in_trie_order: Vec<_> = keys.iter().copied().collect();
+        in_trie_order.sort_by(|a, b| a.cmp_trie_order(b));
+
+        // Walking every node of the builder visits every leaf in trie order.
+        let builder = SnapshotBuilder::new(db.clone(), root);
+        let mut recorder = Recorder::default();
+        walk(&builder, TrieRoot::Node(0), &mut recorder).unwrap();
+        prop_assert_eq!(&recorder.leaves, &in_trie_order);
+        prop_assert_eq!(recorder.branches, keys.len() - 1);
+        prop_assert!(recorder.unvisited.is_empty());
+        prop_assert_eq!(recorder.depth, 0);
+
+        // A snapshot of a few reads reports the leaves read, the rest are unvisited.
+        let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+        for key in reads.iter().chain(keys.iter().step_by(11)) {
+            txn.get(key).unwrap();
+        }
+        let snapshot = txn.build_initial_snapshot();
+
+        let mut recorder = Recorder::default();
+        walk(&snapshot, snapshot.root_node_idx().unwrap(), &mut recorder).unwrap();
+        let read: BTreeSet<_> = keys.iter().step_by(11).copied().collect();
+        prop_assert!(recorder.leaves.iter().all(|k| keys.contains(k)));
+        prop_assert!(read.iter().all(|k| recorder.leaves.contains(k)));
+        prop_assert!(recorder.leaves.is_sorted_by(|a, b| a.cmp_trie_order(b).is_lt()));
+        prop_assert_eq!(
+            recorder.branches + 1,
+            recorder.leaves.len() + recorder.unvisited.len()
+        );
+        prop_assert_eq!(snapshot.calc_root_hash(hasher).unwrap(), root);
+    }
+}
+
+#[test]
+fn walk_skips_pruned_subtrees() {
+    let keys: Vec<KeyHash> = (0..64u32)
+        .map(|i| KeyHash([i, 0, 0, 0, 0, 0, 0, 0]))
+        .collect();
+
+    let db = Rc::new(MemoryDb::<u64>::empty());
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for key in keys.iter() {
+        txn.insert(key, key.0[0] as u64).unwrap();
+    }
+    let root = txn
+        .commit(&mut DigestHasher::<Sha256>::default())
+        .unwrap()
+        .root;
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db, root));
+    for key in keys.iter() {
+        txn.get(key).unwrap();
+    }
+    let snapshot = txn.build_initial_snapshot();
+    let root = snapshot.root_node_idx().unwrap();
+
+    let mut recorder = Recorder {
+        max_depth: Some(0),
+        ..Recorder::default()
+    };
+    walk(&snapshot, root, &mut recorder).unwrap();
+    assert_eq!(recorder.branches, 1);
+    assert_eq!(recorder.depth, 0);
+    assert!(recorder.leaves.is_empty());
+
+    // Keys 0..64 form a complete trie 6 levels deep.
+    let mut recorder = Recorder {
+        max_depth: Some(3),
+        ..Recorder::default()
+    };
+    walk(&snapshot, root, &mut recorder).unwrap();
+    assert_eq!(recorder.branches, 1 + 2 + 4 + 8);
+    assert!(recorder.leaves.is_empty());
+
+    let mut recorder = Recorder::default();
+    walk(&snapshot, root, &mut recorder).unwrap();
+    let mut in_trie_order = keys.clone();
+    in_trie_order.sort_by(|a, b| a.cmp_trie_order(b));
+    assert_eq!(recorder.leaves, in_trie_order);
+
+    let mut recorder = Recorder::default();
+    walk(&snapshot, TrieRoot::Empty, &mut recorder).unwrap();
+    assert_eq!(recorder.branches, 0);
+    assert!(recorder.leaves.is_empty());
+}
+
+#[test]
+fn walk_nodes_backs_up_one_root() {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<u64>::empty());
+
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..100 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    let root = txn.commit(hasher).unwrap().root;
+
+    // A later root shares most nodes with `root`, the backup only holds those of `root`.
+    let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    txn.insert(&KeyHash::from_u64(1000), 1000).unwrap();
+    txn.commit(hasher).unwrap();
+
+    let backup = Rc::new(MemoryDb::<u64>::empty());
+    let mut hashes = Vec::new();
+    let flow = walk_nodes(&db, root, |hash, node| {
+        hashes.push(hash);
+        backup.set(hash, node.clone()).unwrap();
+        ControlFlow::Continue(())
+    })
+    .unwrap();
+    assert_eq!(flow, ControlFlow::Continue(()));
+    assert_eq!(hashes.len(), 199);
+    assert_eq!(TrieRoot::Node(hashes[0]), root);
+    assert_eq!(hashes.iter().collect::<BTreeSet<_>>().len(), hashes.len());
+
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(backup, root));
+    for i in 0..100 {
+        assert_eq!(txn.get(&KeyHash::from_u64(i)).unwrap(), Some(&i));
+    }
+    assert_eq!(txn.get(&KeyHash::from_u64(1000)).unwrap(), None);
+    assert_eq!(
+        txn.build_initial_snapshot().calc_root_hash(hasher).unwrap(),
+        root
+    );
+
+    let mut visited = 0;
+    let flow = walk_nodes(&db, root, |_, _| {
+        visited += 1;
+        if visited == 10 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .unwrap();
+    assert_eq!(flow, ControlFlow::Break(()));
+    assert_eq!(visited, 10);
+
+    assert_eq!(
+        walk_nodes(&db, TrieRoot::Empty, |_, _| unreachable!()).unwrap(),
+        ControlFlow::Continue(())
+    );
+}
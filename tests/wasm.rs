@@ -0,0 +1,76 @@
+#![cfg(feature = "wasm")]
+
+use std::rc::Rc;
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
+    wasm::verify_encoded_batch,
+    DigestHasher, Journal, KeyHash, NodeHash, Op, Transaction, TrieRoot, VerifyError,
+};
+use sha2::Sha256;
+
+fn root_bytes(root: TrieRoot<NodeHash>) -> Vec<u8> {
+    match root {
+        TrieRoot::Node(hash) => hash.bytes.to_vec(),
+        TrieRoot::Empty => Vec::new(),
+    }
+}
+
+/// The encoded batches of two consecutive transactions, and the roots between them.
+fn encoded_batches() -> ([TrieRoot<NodeHash>; 3], [Vec<u8>; 2]) {
+    let hasher = &mut DigestHasher::<Sha256>::default();
+    let db = Rc::new(MemoryDb::<Vec<u8>>::empty());
+
+    let mut root = TrieRoot::Empty;
+    let mut roots = [root; 3];
+    let mut batches: [Vec<u8>; 2] = Default::default();
+    for block in 0..2u64 {
+        let mut txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+        let mut journal = Journal::new();
+        for i in 0..20 {
+            let key = KeyHash::from_u64(block * 10 + i);
+            journal.apply(&mut txn, Op::Get(key)).unwrap();
+            journal
+                .apply(&mut txn, Op::Insert(key, i.to_le_bytes().to_vec()))
+                .unwrap();
+        }
+
+        let batch = txn.commit_audited(hasher, &journal).unwrap();
+        root = batch.new_root;
+        roots[block as usize + 1] = root;
+        batches[block as usize] = bincode::serialize(&batch).unwrap();
+    }
+
+    (roots, batches)
+}
+
+#[test]
+fn verify_encoded_batches_in_order() {
+    let (roots, batches) = encoded_batches();
+
+    let mut trusted = root_bytes(roots[0]);
+    for (batch, expected) in batches.iter().zip(&roots[1..]) {
+        let new_root = verify_encoded_batch(&trusted, batch).unwrap();
+        assert_eq!(new_root, *expected);
+        trusted = root_bytes(new_root);
+    }
+}
+
+#[test]
+fn verify_encoded_batch_rejects_bad_input() {
+    let (roots, batches) = encoded_batches();
+
+    // The second batch does not start from the empty trie.
+    assert_eq!(
+        verify_encoded_batch(&[], &batches[1]),
+        Err(VerifyError::OldRootMismatch {
+            expected: TrieRoot::Empty,
+            actual: roots[1],
+        })
+    );
+
+    assert!(verify_encoded_batch(&[0; 31], &batches[0]).is_err());
+
+    let truncated = &batches[0][..batches[0].len() - 1];
+    assert!(verify_encoded_batch(&[], truncated).is_err());
+}
@@ -0,0 +1,128 @@
+use std::{cell::RefCell, rc::Rc};
+
+use kairos_trie::{
+    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder, DatabaseGet, DatabaseSet},
+    Branch, DigestHasher, KeyHash, Leaf, Node, NodeHash, Transaction, TrieError, TrieRoot,
+};
+use sha2::Sha256;
+
+type DbNode = Node<Branch<NodeHash>, Leaf<u64>>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Event {
+    Begin,
+    Set,
+    End(bool),
+}
+
+/// Buffers the writes of a batch, writing them on `end_batch`.
+struct BatchingDb {
+    db: MemoryDb<u64>,
+    pending: RefCell<Vec<(NodeHash, DbNode)>>,
+    events: RefCell<Vec<Event>>,
+    fail_set: Option<usize>,
+    fail_end: bool,
+}
+
+impl BatchingDb {
+    fn new(fail_set: Option<usize>, fail_end: bool) -> Rc<Self> {
+        Rc::new(BatchingDb {
+            db: MemoryDb::empty(),
+            pending: RefCell::default(),
+            events: RefCell::default(),
+            fail_set,
+            fail_end,
+        })
+    }
+}
+
+impl DatabaseGet<u64> for BatchingDb {
+    type GetError = String;
+
+    fn get(&self, hash: &NodeHash) -> Result<DbNode, String> {
+        self.db.get(hash).map_err(|e| e.to_string())
+    }
+}
+
+impl DatabaseSet<u64> for BatchingDb {
+    // Write errors have their own type, distinct from `GetError`.
+    type SetError = &'static str;
+
+    fn set(&self, hash: NodeHash, node: DbNode) -> Result<(), &'static str> {
+        let mut pending = self.pending.borrow_mut();
+        if Some(pending.len()) == self.fail_set {
+            return Err("disk full");
+        }
+        self.events.borrow_mut().push(Event::Set);
+        pending.push((hash, node));
+        Ok(())
+    }
+
+    fn begin_batch(&self) -> Result<(), &'static str> {
+        self.events.borrow_mut().push(Event::Begin);
+        Ok(())
+    }
+
+    fn end_batch(&self, completed: bool) -> Result<(), &'static str> {
+        self.events.borrow_mut().push(Event::End(completed));
+        let pending = self.pending.take();
+        if self.fail_end {
+            return Err("fsync failed");
+        }
+        if completed {
+            for (hash, node) in pending {
+                self.db
+                    .set(hash, node)
+                    .map_err(|_| "memory db write failed")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn commit(db: &Rc<BatchingDb>) -> Result<TrieRoot<NodeHash>, TrieError> {
+    let mut txn =
+        Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), TrieRoot::Empty));
+    for i in 0..10 {
+        txn.insert(&KeyHash::from_u64(i), i).unwrap();
+    }
+    txn.commit(&mut DigestHasher::<Sha256>::default())
+        .map(|receipt| receipt.root)
+}
+
+#[test]
+fn commit_writes_one_batch() {
+    let db = BatchingDb::new(None, false);
+    let root = commit(&db).unwrap();
+
+    let events = db.events.take();
+    assert_eq!(events.first(), Some(&Event::Begin));
+    assert_eq!(events.last(), Some(&Event::End(true)));
+    assert_eq!(events.len(), 2 + 19);
+    assert!(events[1..events.len() - 1].iter().all(|e| *e == Event::Set));
+
+    // The batch was written when it ended.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    assert_eq!(txn.get(&KeyHash::from_u64(3)).unwrap(), Some(&3));
+
+    // A commit with nothing to write is still one batch.
+    let txn = Transaction::from_snapshot_builder(SnapshotBuilder::new(db.clone(), root));
+    txn.commit(&mut DigestHasher::<Sha256>::default()).unwrap();
+    assert_eq!(db.events.take(), [Event::Begin, Event::End(true)]);
+}
+
+#[test]
+fn failed_commits_end_their_batch() {
+    // A failed write ends the batch incomplete, and is the error the commit returns.
+    let db = BatchingDb::new(Some(4), false);
+    let err = commit(&db).unwrap_err();
+    assert!(err.to_string().contains("disk full"), "{err}");
+    let events = db.events.take();
+    assert_eq!(events.last(), Some(&Event::End(false)));
+    assert_eq!(events.len(), 2 + 4);
+
+    let db = BatchingDb::new(None, true);
+    let err = commit(&db).unwrap_err();
+    assert!(err.to_string().contains("fsync failed"), "{err}");
+    assert_eq!(db.events.take().last(), Some(&Event::End(true)));
+}